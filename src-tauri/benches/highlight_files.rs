@@ -0,0 +1,91 @@
+//! `highlight_file` on the two shapes that froze the preview: a large Rust
+//! file and minified JS. The Rust file is this crate's own source repeated to
+//! 20k lines; the JS is generated. The first run also loads the syntax set,
+//! which the warm-up absorbs.
+
+mod support;
+
+use bups_engine::highlight::{highlight_file, HighlightOptions, LineRange};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const RUST_LINES: usize = 20_000;
+
+fn large_rust() -> String {
+    let mut sources: Vec<PathBuf> = fs::read_dir(Path::new(env!("CARGO_MANIFEST_DIR")).join("src"))
+        .unwrap()
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|e| e == "rs"))
+        .collect();
+    sources.sort();
+    let mut lines = Vec::new();
+    for path in sources.iter().cycle() {
+        lines.extend(
+            fs::read_to_string(path)
+                .unwrap()
+                .lines()
+                .map(str::to_string),
+        );
+        if lines.len() >= RUST_LINES {
+            break;
+        }
+    }
+    lines.truncate(RUST_LINES);
+    lines.join("\n")
+}
+
+/// `lines` lines of roughly `width` bytes of bundler output each
+fn minified_js(lines: usize, width: usize) -> String {
+    let statement = "function a(b,c){return b+c}var d=[1,2,3].map(function(e){return e*2}),\
+                     f={g:\"h\",i:/j+k/g,l:null};if(d.length>2&&f.g){console.log(`m${d[0]}`)}";
+    let line = statement.repeat(width / statement.len());
+    vec![line; lines].join("\n")
+}
+
+fn options(range: Option<(usize, usize)>, theme: Option<&str>) -> HighlightOptions {
+    HighlightOptions {
+        theme: theme.map(str::to_string),
+        range: range.map(|(start, end)| LineRange { start, end }),
+        language: None,
+    }
+}
+
+fn main() {
+    let dir = std::env::temp_dir().join(format!("bups-highlight-bench-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let rust = dir.join("large.rs");
+    let js = dir.join("bundle.min.js");
+    let one_line_js = dir.join("one-line.min.js");
+    fs::write(&rust, large_rust()).unwrap();
+    fs::write(&js, minified_js(20, 9_000)).unwrap();
+    fs::write(&one_line_js, minified_js(1, 1024 * 1024)).unwrap();
+
+    let cases = [
+        (
+            "large.rs, first 5000 lines (the line cap)",
+            &rust,
+            options(None, None),
+        ),
+        (
+            "large.rs, first 5000 lines, themed",
+            &rust,
+            options(None, Some("base16-ocean.dark")),
+        ),
+        (
+            "large.rs, lines 15000-15200 after a lead-in parse",
+            &rust,
+            options(Some((15_000, 15_200)), None),
+        ),
+        ("bundle.min.js, 20 lines of 9 kB", &js, options(None, None)),
+        (
+            "one-line.min.js, 1 MB line (past the line cap)",
+            &one_line_js,
+            options(None, None),
+        ),
+    ];
+    for (name, path, options) in &cases {
+        support::bench(name, 1, || highlight_file(path, options).unwrap());
+    }
+
+    let _ = fs::remove_dir_all(&dir);
+}
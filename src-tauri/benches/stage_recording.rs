@@ -0,0 +1,35 @@
+//! What one stage recording costs: a bare `record`, a scoped `timer` (which
+//! adds two clock reads), and `Trace::record`, which also keeps the sample for
+//! the request's breakdown. All of it should stay in the nanoseconds.
+
+mod support;
+
+use bups_engine::metrics::{record, timer, Stage, Trace};
+use std::hint::black_box;
+use std::time::Duration;
+
+fn main() {
+    let mut i = 0u64;
+    support::bench("metrics::record", 1_000_000, || {
+        i += 1;
+        // Spread over the buckets, from nanoseconds to minutes
+        record(Stage::PostProcess, Duration::from_nanos(i << (i % 20)));
+    });
+    support::bench("metrics::timer, created and dropped", 1_000_000, || {
+        let _timer = timer(black_box(Stage::PostProcess));
+    });
+    support::bench("Trace::record, six stages per trace", 100_000, || {
+        let mut trace = Trace::new("bench");
+        for stage in [
+            Stage::Discovery,
+            Stage::Spawn,
+            Stage::FirstByte,
+            Stage::Stream,
+            Stage::Persist,
+            Stage::PostProcess,
+        ] {
+            trace.record(stage, Duration::from_micros(250));
+        }
+        trace
+    });
+}
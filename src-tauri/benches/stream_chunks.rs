@@ -0,0 +1,76 @@
+//! The per-chunk work of `claude::stream_reply`: check for cancellation, decode
+//! the bytes, emit the text. "before" reads the flag the way the engine did
+//! before synth-235, through two async mutexes; "after" is the `CancelToken`
+//! atomic the loop checks now.
+
+mod support;
+
+use bups_engine::cancel::CancelToken;
+use bups_engine::claude::{emit_text, NullSink, Utf8Decoder};
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+const CHUNKS: usize = 1_000;
+
+/// The old shape: `Arc<Mutex<CancelState>>` around an `Arc<Mutex<bool>>`
+struct CancelState {
+    flag: Arc<Mutex<bool>>,
+}
+
+/// Text deltas the size the CLI sends, with a multi-byte character split
+/// across every other chunk boundary
+fn chunks() -> Vec<Vec<u8>> {
+    let text = "Here is the function you asked for — it returns early ".repeat(CHUNKS);
+    text.as_bytes()
+        .chunks(text.len() / CHUNKS)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+fn main() {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let chunks = chunks();
+    let token = CancelToken::default();
+    let state = Arc::new(Mutex::new(CancelState {
+        flag: Arc::new(Mutex::new(false)),
+    }));
+
+    let before = support::bench(
+        "chunk loop, 1000 chunks, two async mutexes (before)",
+        200,
+        || {
+            runtime.block_on(async {
+                let mut decoder = Utf8Decoder::default();
+                for chunk in &chunks {
+                    if *state.lock().await.flag.lock().await {
+                        break;
+                    }
+                    emit_text(&NullSink, &decoder.push(chunk)).unwrap();
+                }
+            })
+        },
+    );
+    let after = support::bench("chunk loop, 1000 chunks, CancelToken (after)", 200, || {
+        runtime.block_on(async {
+            let mut decoder = Utf8Decoder::default();
+            for chunk in &chunks {
+                if token.is_cancelled() {
+                    break;
+                }
+                emit_text(&NullSink, &decoder.push(chunk)).unwrap();
+            }
+        })
+    });
+    support::bench("cancel check alone, two async mutexes", 100_000, || {
+        runtime.block_on(async { *state.lock().await.flag.lock().await })
+    });
+    support::bench("cancel check alone, CancelToken", 100_000, || {
+        token.is_cancelled()
+    });
+    println!(
+        "locking cost per chunk: {:.1} ns",
+        (before.as_nanos() as f64 - after.as_nanos() as f64) / CHUNKS as f64
+    );
+}
@@ -0,0 +1,44 @@
+//! A small timing harness for `harness = false` benches: each case runs in
+//! batches, and the median time per iteration across the batches is printed.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+const SAMPLES: usize = 15;
+
+/// Time `f`, `iterations` calls per sample, and print the median per call
+pub fn bench<T>(name: &str, iterations: u64, mut f: impl FnMut() -> T) -> Duration {
+    for _ in 0..iterations.min(1_000) {
+        black_box(f());
+    }
+    let mut samples: Vec<Duration> = (0..SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            for _ in 0..iterations {
+                black_box(f());
+            }
+            started.elapsed().div_f64(iterations.max(1) as f64)
+        })
+        .collect();
+    samples.sort();
+    let median = samples[SAMPLES / 2];
+    println!(
+        "{:<56} {:>12} /iter  (min {}, max {})",
+        name,
+        show(median),
+        show(samples[0]),
+        show(samples[SAMPLES - 1])
+    );
+    median
+}
+
+fn show(duration: Duration) -> String {
+    let nanos = duration.as_nanos();
+    if nanos < 10_000 {
+        format!("{} ns", nanos)
+    } else if nanos < 10_000_000 {
+        format!("{:.1} µs", nanos as f64 / 1e3)
+    } else {
+        format!("{:.1} ms", nanos as f64 / 1e6)
+    }
+}
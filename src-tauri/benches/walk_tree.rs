@@ -0,0 +1,67 @@
+//! The shared walker on a generated 100k-file tree: one worker, which is how
+//! the scanners walked before synth-228, against the default thread budget.
+//! The tree sits in the OS temp dir, so after the warm-up this measures a warm
+//! page cache; cold-disk and network-share runs gain more.
+
+mod support;
+
+use bups_engine::walker::{walk_files, WalkConfig};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const TOP: usize = 10;
+const DIRS: usize = 100;
+const FILES: usize = 100;
+
+/// `TOP` × `DIRS` directories of `FILES` files each, plus a gitignored
+/// `target/` the walker has to skip
+fn generate(root: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(root.join("target"))?;
+    fs::write(root.join(".gitignore"), "target/\n")?;
+    fs::write(root.join("target/ignored.o"), "x")?;
+    for top in 0..TOP {
+        for dir in 0..DIRS {
+            let dir = root.join(format!("pkg{}/mod{}", top, dir));
+            fs::create_dir_all(&dir)?;
+            for file in 0..FILES {
+                fs::write(dir.join(format!("file{}.rs", file)), "fn main() {}\n")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn walk(root: &Path, threads: usize) -> usize {
+    let config = WalkConfig {
+        threads,
+        ..WalkConfig::default()
+    };
+    let found = walk_files(
+        root,
+        config,
+        |_| true,
+        |entry| entry.metadata().ok().map(|m| m.len()),
+    )
+    .unwrap();
+    assert_eq!(found.len(), TOP * DIRS * FILES);
+    found.len()
+}
+
+fn main() {
+    let root: PathBuf =
+        std::env::temp_dir().join(format!("bups-walk-bench-{}", std::process::id()));
+    generate(&root).unwrap();
+
+    let sequential = support::bench("walk 100k files, 1 thread (sequential)", 1, || {
+        walk(&root, 1)
+    });
+    let parallel = support::bench("walk 100k files, default threads (parallel)", 1, || {
+        walk(&root, 0)
+    });
+    println!(
+        "speedup: {:.2}x",
+        sequential.as_secs_f64() / parallel.as_secs_f64()
+    );
+
+    let _ = fs::remove_dir_all(&root);
+}
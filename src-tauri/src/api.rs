@@ -0,0 +1,445 @@
+use crate::cancel::CancelToken;
+use crate::claude::{
+    self, emit_started, emit_stats, emit_text, finish, first_token, CliSession, Reply,
+    ReplyMetrics, SpawnOptions, StreamSink, UsageInfo, Watchdog,
+};
+use crate::error::EngineError;
+use crate::fetch::request_error;
+use crate::history::{self, Message};
+use crate::metrics::Trace;
+use crate::secrets;
+use crate::settings::{Backend, EngineConfig};
+use crate::spill::{Overflow, Spilled};
+use crate::stop::{Advance, StopScanner};
+use serde_json::{json, Value};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+
+const MESSAGES_URL: &str = "https://api.anthropic.com/v1/messages";
+const API_VERSION: &str = "2023-06-01";
+const DEFAULT_MODEL: &str = "claude-sonnet-4-5";
+const MAX_TOKENS: u32 = 8192;
+const CHUNK_POLL: Duration = Duration::from_millis(500);
+
+/// The CLI's model aliases, as the API knows them
+const MODEL_ALIASES: &[(&str, &str)] = &[
+    ("sonnet", "claude-sonnet-4-5"),
+    ("opus", "claude-opus-4-5"),
+    ("haiku", "claude-haiku-4-5"),
+];
+
+/// Kept out of debug output, so it can't end up in an error or a panic
+pub struct ApiKey(String);
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiKey(********)")
+    }
+}
+
+/// Where a request goes
+pub enum Route {
+    Cli,
+    Api(ApiKey),
+}
+
+/// The key in the keychain, or `ANTHROPIC_API_KEY`. A keychain that can't
+/// be read is only an error when the environment has no key either.
+fn api_key() -> Result<Option<ApiKey>, EngineError> {
+    let from_env = || {
+        std::env::var("ANTHROPIC_API_KEY")
+            .ok()
+            .filter(|key| !key.trim().is_empty())
+    };
+    let key = match secrets::read(secrets::ANTHROPIC_API_KEY) {
+        Ok(stored) => stored
+            .filter(|key| !key.trim().is_empty())
+            .or_else(from_env),
+        Err(e) => Some(from_env().ok_or(e)?),
+    };
+    Ok(key.map(|key| ApiKey(key.trim().to_string())))
+}
+
+fn pick_route(config: &EngineConfig) -> Result<Route, EngineError> {
+    match config.backend {
+        Backend::Cli => Ok(Route::Cli),
+        Backend::Api => api_key()?.map(Route::Api).ok_or(EngineError::ApiKeyMissing),
+        Backend::Auto => {
+            if claude::locate_cli(config).0.is_some() {
+                return Ok(Route::Cli);
+            }
+            Ok(api_key()?.map_or(Route::Cli, Route::Api))
+        }
+    }
+}
+
+/// The configured backend; on auto the CLI when it's installed, otherwise the
+/// API when there's a key. With neither, the CLI's not-found error stands.
+/// Off the async runtime, since the keychain may wait to be unlocked.
+pub async fn route(config: &EngineConfig) -> Result<Route, EngineError> {
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || pick_route(&config))
+        .await
+        .map_err(|e| EngineError::internal(format!("Backend selection failed: {}", e)))?
+}
+
+fn api_model(model: &str) -> &str {
+    MODEL_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == model)
+        .map_or(model, |(_, id)| id)
+}
+
+/// A conversation as API messages, ending with `message`. The API wants
+/// turns to alternate starting with the user, so runs of one role are joined.
+pub fn turns(history: &[Message], message: &str) -> Vec<Value> {
+    let mut turns: Vec<(&str, String)> = Vec::new();
+    let said = history
+        .iter()
+        .map(|m| (m.role.as_str(), m.content.as_str()))
+        .chain([("user", message)]);
+    for (role, content) in said {
+        let role = if role == "assistant" {
+            "assistant"
+        } else {
+            "user"
+        };
+        if content.trim().is_empty() || (turns.is_empty() && role == "assistant") {
+            continue;
+        }
+        match turns.last_mut() {
+            Some((last, text)) if *last == role => {
+                text.push_str("\n\n");
+                text.push_str(content);
+            }
+            _ => turns.push((role, content.to_string())),
+        }
+    }
+    turns
+        .into_iter()
+        .map(|(role, content)| json!({ "role": role, "content": content }))
+        .collect()
+}
+
+/// [`turns`] for a message in a saved conversation, or on its own
+pub fn conversation_turns(
+    app: &AppHandle,
+    conversation_id: Option<&str>,
+    message: &str,
+) -> Result<Vec<Value>, EngineError> {
+    let history = match conversation_id {
+        Some(id) if history::exists(app, id)? => history::load(app, id)?.messages,
+        _ => Vec::new(),
+    };
+    Ok(turns(&history, message))
+}
+
+fn request_body(
+    spawn: &SpawnOptions,
+    turns: Vec<Value>,
+    stream: bool,
+) -> Result<Value, EngineError> {
+    if !matches!(spawn.session, CliSession::Fresh) {
+        return Err(EngineError::invalid(
+            "Resuming a CLI session needs the Claude CLI",
+        ));
+    }
+    let model = spawn.model.as_deref().map_or(DEFAULT_MODEL, api_model);
+    let mut body = json!({
+        "model": model,
+        "max_tokens": MAX_TOKENS,
+        "messages": turns,
+        "stream": stream,
+    });
+    // There's no built-in prompt to append to, so both make up the system prompt
+    let appended = spawn.appended_prompt();
+    let system: Vec<&str> = [&spawn.system_prompt, &appended]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .filter(|p| !p.trim().is_empty())
+        .collect();
+    if !system.is_empty() {
+        body["system"] = system.join("\n\n").into();
+    }
+    Ok(body)
+}
+
+/// The API's own message for a failed request, rather than its raw body
+fn api_failure(status: Option<u16>, body: &Value) -> EngineError {
+    let detail = body
+        .pointer("/error/message")
+        .and_then(|m| m.as_str())
+        .unwrap_or("Request failed with no error message");
+    EngineError::ApiFailed {
+        status,
+        detail: detail.to_string(),
+        retry_after_secs: None,
+    }
+}
+
+async fn post(
+    key: &ApiKey,
+    body: &Value,
+    timeout: Option<Duration>,
+) -> Result<reqwest::Response, EngineError> {
+    let mut builder = reqwest::Client::builder()
+        .connect_timeout(Duration::from_secs(10))
+        .user_agent(concat!("bups-engine/", env!("CARGO_PKG_VERSION")));
+    if let Some(timeout) = timeout {
+        builder = builder.timeout(timeout);
+    }
+    let client = builder
+        .build()
+        .map_err(|e| EngineError::internal(format!("Failed to build HTTP client: {}", e)))?;
+    let limit = timeout.unwrap_or_default();
+    let response = client
+        .post(MESSAGES_URL)
+        .header("x-api-key", &key.0)
+        .header("anthropic-version", API_VERSION)
+        .header("Content-Type", "application/json")
+        .body(body.to_string())
+        .send()
+        .await
+        .map_err(|e| request_error(MESSAGES_URL, e, limit))?;
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let retry_after_secs = response
+        .headers()
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    let body = response
+        .bytes()
+        .await
+        .ok()
+        .and_then(|raw| serde_json::from_slice(&raw).ok())
+        .unwrap_or(Value::Null);
+    let mut error = api_failure(Some(status.as_u16()), &body);
+    if let EngineError::ApiFailed {
+        retry_after_secs: slot,
+        ..
+    } = &mut error
+    {
+        *slot = retry_after_secs;
+    }
+    Err(error)
+}
+
+/// One reply over the Messages API, the same shape as the CLI's
+pub async fn send(
+    key: &ApiKey,
+    turns: Vec<Value>,
+    spawn: &SpawnOptions,
+) -> Result<Reply, EngineError> {
+    let started = Instant::now();
+    let body = request_body(spawn, turns, false)?;
+    let limit = spawn.limits.send_timeout;
+    let response = post(key, &body, Some(limit)).await?;
+    let raw = response
+        .bytes()
+        .await
+        .map_err(|e| request_error(MESSAGES_URL, e, limit))?;
+    let message: Value = serde_json::from_slice(&raw)
+        .map_err(|e| EngineError::internal(format!("Unreadable API response: {}", e)))?;
+    let text: String = message["content"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+        .collect();
+    let usage = UsageInfo::from_result(&message).map(|usage| UsageInfo {
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+        ..usage
+    });
+    let metrics = ReplyMetrics::measure(&text, None, started);
+    Ok(Reply {
+        text,
+        session_id: None,
+        usage,
+        metrics: Some(metrics),
+        spilled: Spilled::default(),
+    })
+}
+
+/// Server-sent events split into their JSON data, however the bytes arrive
+#[derive(Default)]
+struct SseLines {
+    pending: Vec<u8>,
+}
+
+impl SseLines {
+    fn push(&mut self, bytes: &[u8]) -> Vec<Value> {
+        self.pending.extend_from_slice(bytes);
+        let mut events = Vec::new();
+        while let Some(end) = self.pending.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line);
+            if let Some(data) = line.trim_end().strip_prefix("data:") {
+                if let Ok(event) = serde_json::from_str(data.trim()) {
+                    events.push(event);
+                }
+            }
+        }
+        events
+    }
+}
+
+/// [`claude::stream_reply`] over the Messages API: the same text, stats,
+/// completion and error events, the same stop rules, watchdog and cancellation
+pub async fn stream(
+    sink: Arc<dyn StreamSink>,
+    key: &ApiKey,
+    turns: Vec<Value>,
+    cancel: CancelToken,
+    spawn: SpawnOptions,
+) -> Result<Reply, EngineError> {
+    let result = stream_turn(sink.as_ref(), key, turns, &cancel, &spawn).await;
+    if let Err(error) = &result {
+        if !matches!(error, EngineError::Cancelled) {
+            let _ = sink.emit("claude-stream-error", error.to_string().into());
+        }
+    }
+    result
+}
+
+async fn stream_turn(
+    sink: &dyn StreamSink,
+    key: &ApiKey,
+    turns: Vec<Value>,
+    cancel: &CancelToken,
+    spawn: &SpawnOptions,
+) -> Result<Reply, EngineError> {
+    let started = Instant::now();
+    let mut trace = Trace::new("api");
+    let body = request_body(spawn, turns, true)?;
+    let mut response = post(key, &body, None).await?;
+    emit_started(sink, "api", started);
+
+    let mut lines = SseLines::default();
+    let mut full_response = String::new();
+    let mut scanner = spawn.stop.as_ref().map(StopScanner::new);
+    let mut emitted = 0;
+    let mut stop_reason = None;
+    let mut first_chunk_ms = None;
+    let mut watchdog = Watchdog::new(spawn.limits);
+    let mut overflow = Overflow::new(&spawn.spill);
+    let (mut input_tokens, mut output_tokens, mut cache_read_tokens) = (0, 0, 0);
+
+    'read: loop {
+        let received = tokio::select! {
+            biased;
+            _ = cancel.cancelled() => {
+                // Dropping the response closes the connection, which ends generation
+                sink.emit("claude-stream-cancelled", Value::Null)
+                    .map_err(|e| format!("Failed to emit cancel event: {}", e))?;
+                return Err(EngineError::Cancelled);
+            }
+            received = tokio::time::timeout(CHUNK_POLL, response.chunk()) => received,
+        };
+        let bytes = match received {
+            Ok(Ok(Some(bytes))) => bytes,
+            Ok(Ok(None)) => break,
+            Ok(Err(e)) => return Err(request_error(MESSAGES_URL, e, Duration::ZERO)),
+            Err(_) => {
+                watchdog.check(sink)?;
+                continue;
+            }
+        };
+        watchdog.fed();
+        for event in lines.push(&bytes) {
+            let tokens = |pointer: &str| event.pointer(pointer).and_then(|v| v.as_u64());
+            match event["type"].as_str() {
+                Some("message_start") => {
+                    input_tokens = tokens("/message/usage/input_tokens").unwrap_or(0);
+                    cache_read_tokens =
+                        tokens("/message/usage/cache_read_input_tokens").unwrap_or(0);
+                }
+                Some("message_delta") => {
+                    output_tokens = tokens("/usage/output_tokens").unwrap_or(output_tokens);
+                }
+                Some("message_stop") => break 'read,
+                Some("error") => return Err(api_failure(None, &event)),
+                Some("content_block_delta") => {
+                    let Some(text) = event.pointer("/delta/text").and_then(|t| t.as_str()) else {
+                        continue;
+                    };
+                    first_token(sink, &mut first_chunk_ms, started);
+                    if overflow.active() {
+                        overflow
+                            .take(sink, text)
+                            .map_err(|e| format!("Failed to emit stream chunk: {}", e))?;
+                        continue;
+                    }
+                    full_response.push_str(text);
+                    let advance = match scanner.as_mut() {
+                        Some(scanner) => scanner.advance(&full_response),
+                        None => Advance {
+                            release: full_response.len(),
+                            stop: None,
+                        },
+                    };
+                    let release = advance.release.max(emitted);
+                    if release > emitted {
+                        emit_text(sink, &full_response[emitted..release])
+                            .map_err(|e| format!("Failed to emit stream chunk: {}", e))?;
+                        emitted = release;
+                    }
+                    if let Some(reason) = advance.stop {
+                        full_response.truncate(release);
+                        stop_reason = Some(reason);
+                        break 'read;
+                    }
+                    overflow
+                        .check(sink, &mut full_response, &mut emitted)
+                        .map_err(|e| format!("Failed to emit stream chunk: {}", e))?;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // Text held back for a stop sequence that never came
+    if emitted < full_response.len() {
+        emit_text(sink, &full_response[emitted..])
+            .map_err(|e| format!("Failed to emit stream chunk: {}", e))?;
+    }
+    let metrics = emit_stats(
+        sink,
+        "api",
+        first_chunk_ms,
+        started,
+        &full_response,
+        &mut trace,
+    );
+    let usage = UsageInfo {
+        input_tokens,
+        output_tokens,
+        cache_read_tokens,
+        cost_usd: None,
+        duration_ms: Some(started.elapsed().as_millis() as u64),
+    };
+    let (text, mut payload) = finish(
+        spawn,
+        full_response,
+        stop_reason,
+        Some(&usage),
+        &metrics,
+        &mut trace,
+    );
+    let spilled = overflow.finish();
+    spilled.mark(&mut payload);
+    sink.emit("claude-stream-complete", payload)
+        .map_err(|e| format!("Failed to emit completion event: {}", e))?;
+    Ok(Reply {
+        text,
+        session_id: None,
+        usage: Some(usage),
+        metrics: Some(metrics),
+        spilled,
+    })
+}
@@ -0,0 +1,286 @@
+use crate::cancel::CancelRegistry;
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::settings;
+use serde::Serialize;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+#[cfg(feature = "updater")]
+use {
+    crate::fetch::guarded_client,
+    std::sync::atomic::{AtomicU64, Ordering},
+    std::sync::{Arc, Mutex},
+    tauri::updater::{Error as UpdaterError, UpdateResponse},
+    tauri::Wry,
+};
+
+#[cfg(feature = "updater")]
+const CHECK_TIMEOUT: Duration = Duration::from_secs(20);
+const CHECK_INTERVAL_MS: u64 = 24 * 60 * 60 * 1000;
+const CHECK_POLL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AppUpdateInfo {
+    pub current: String,
+    pub latest: String,
+    pub available: bool,
+    pub notes: Option<String>,
+    pub date: Option<String>,
+    pub download_size: Option<u64>,
+}
+
+#[cfg(feature = "updater")]
+#[derive(Debug, Clone, Serialize)]
+struct UpdateProgress {
+    request_id: String,
+    downloaded: u64,
+    total: Option<u64>,
+}
+
+/// Last found update plus the download in progress, if any
+#[derive(Default)]
+pub struct AppUpdateState {
+    #[cfg(feature = "updater")]
+    pending: tokio::sync::Mutex<Option<UpdateResponse<Wry>>>,
+    #[cfg(feature = "updater")]
+    active_download: Mutex<Option<String>>,
+    #[cfg(feature = "updater")]
+    downloaded: AtomicU64,
+}
+
+/// A build can only install updates it can verify: built with the `updater`
+/// feature, and the updater switched on with the release signing key. Until
+/// a key ships it's off, and checks say so rather than fail at install.
+fn configured(app: &AppHandle) -> bool {
+    let updater = &app.config().tauri.updater;
+    cfg!(feature = "updater") && updater.active && !updater.pubkey.trim().is_empty()
+}
+
+fn not_enabled() -> EngineError {
+    EngineError::UpdateFailed {
+        detail: "Updates aren't enabled in this build".to_string(),
+    }
+}
+
+#[cfg(feature = "updater")]
+fn update_error(err: UpdaterError) -> EngineError {
+    match err {
+        UpdaterError::Network(detail) => EngineError::Network { detail },
+        UpdaterError::Minisign(_) | UpdaterError::Base64(_) | UpdaterError::SignatureUtf8(_) => {
+            EngineError::UpdateSignatureInvalid {
+                detail: err.to_string(),
+            }
+        }
+        UpdaterError::Io(e) if e.kind() == std::io::ErrorKind::StorageFull => {
+            EngineError::InsufficientDisk {
+                detail: e.to_string(),
+            }
+        }
+        other => EngineError::UpdateFailed {
+            detail: other.to_string(),
+        },
+    }
+}
+
+/// Installer size from a HEAD request; best effort
+#[cfg(feature = "updater")]
+async fn download_size(url: &str) -> Option<u64> {
+    let client = guarded_client(false, CHECK_TIMEOUT).ok()?;
+    let response = client.head(url).send().await.ok()?;
+    response
+        .headers()
+        .get(reqwest::header::CONTENT_LENGTH)?
+        .to_str()
+        .ok()?
+        .parse()
+        .ok()
+}
+
+#[cfg(not(feature = "updater"))]
+async fn check(_app: &AppHandle) -> Result<AppUpdateInfo, EngineError> {
+    Err(not_enabled())
+}
+
+#[cfg(feature = "updater")]
+async fn check(app: &AppHandle) -> Result<AppUpdateInfo, EngineError> {
+    if !configured(app) {
+        return Err(not_enabled());
+    }
+    let current = app.package_info().version.to_string();
+    let download_url = Arc::new(Mutex::new(None::<String>));
+    let builder = {
+        let download_url = download_url.clone();
+        let target = tauri::updater::target().unwrap_or_default();
+        tauri::updater::builder(app.clone())
+            .skip_events()
+            .timeout(CHECK_TIMEOUT)
+            .should_install(move |current, release| {
+                if let (Ok(mut slot), Ok(url)) =
+                    (download_url.lock(), release.download_url(&target))
+                {
+                    *slot = Some(url.to_string());
+                }
+                release.version() > current
+            })
+    };
+
+    let state = app.state::<AppUpdateState>();
+    let response = match builder.check().await {
+        Ok(response) => response,
+        Err(UpdaterError::UpToDate) => {
+            *state.pending.lock().await = None;
+            return Ok(AppUpdateInfo {
+                latest: current.clone(),
+                current,
+                available: false,
+                notes: None,
+                date: None,
+                download_size: None,
+            });
+        }
+        Err(e) => return Err(update_error(e)),
+    };
+
+    let url = download_url.lock().ok().and_then(|u| u.clone());
+    let size = match (&url, response.is_update_available()) {
+        (Some(url), true) => download_size(url).await,
+        _ => None,
+    };
+    let info = AppUpdateInfo {
+        current,
+        latest: response.latest_version().to_string(),
+        available: response.is_update_available(),
+        notes: response.body().cloned(),
+        date: response.date().map(|d| d.to_string()),
+        download_size: size,
+    };
+    *state.pending.lock().await = info.available.then_some(response);
+    Ok(info)
+}
+
+/// Called from the run loop for every updater progress event
+#[cfg(feature = "updater")]
+pub fn on_download_progress(app: &AppHandle, chunk_length: usize, total: Option<u64>) {
+    let state = app.state::<AppUpdateState>();
+    let Some(request_id) = state.active_download.lock().ok().and_then(|id| id.clone()) else {
+        return;
+    };
+    let downloaded = state
+        .downloaded
+        .fetch_add(chunk_length as u64, Ordering::Relaxed)
+        + chunk_length as u64;
+    let _ = app.emit_all(
+        "app-update-progress",
+        UpdateProgress {
+            request_id,
+            downloaded,
+            total,
+        },
+    );
+}
+
+fn ensure_idle(app: &AppHandle) -> Result<(), EngineError> {
+    let request_ids = app.state::<CancelRegistry>().active_ids();
+    if request_ids.is_empty() {
+        Ok(())
+    } else {
+        Err(EngineError::RequestsInFlight { request_ids })
+    }
+}
+
+/// Background check, at most once a day and only when enabled in settings
+/// and in the build
+pub fn spawn_daily_check(app: AppHandle) {
+    if !configured(&app) {
+        return;
+    }
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let config = settings::load(&app);
+            let due = config.updates.auto_check
+                && config
+                    .updates
+                    .last_checked_at
+                    .is_none_or(|at| now_millis().saturating_sub(at) >= CHECK_INTERVAL_MS);
+            if due {
+                if let Ok(info) = check(&app).await {
+                    if info.available {
+                        let _ = app.emit_all("app-update-available", info);
+                    }
+                }
+                // The check takes a while; anything changed meanwhile stays
+                let _ = settings::patch(
+                    &app,
+                    serde_json::json!({"updates": {"last_checked_at": now_millis()}}),
+                );
+            }
+            tokio::time::sleep(CHECK_POLL).await;
+        }
+    });
+}
+
+#[tauri::command]
+pub async fn check_app_update(app: AppHandle) -> Result<AppUpdateInfo, EngineError> {
+    check(&app).await
+}
+
+/// Download, verify and install the update found by the last check, emitting
+/// `app-update-progress`. On Windows and Linux the installer takes over and the
+/// app exits, so running requests block this unless `force` is set; on macOS the
+/// new bundle is staged and takes effect on relaunch.
+#[cfg(not(feature = "updater"))]
+#[tauri::command]
+pub async fn download_and_install_update(
+    _request_id: String,
+    _force: Option<bool>,
+) -> Result<(), EngineError> {
+    Err(not_enabled())
+}
+
+#[cfg(feature = "updater")]
+#[tauri::command]
+pub async fn download_and_install_update(
+    app: AppHandle,
+    request_id: String,
+    force: Option<bool>,
+    state: tauri::State<'_, AppUpdateState>,
+) -> Result<(), EngineError> {
+    if cfg!(not(target_os = "macos")) && !force.unwrap_or(false) {
+        ensure_idle(&app)?;
+    }
+    if state.pending.lock().await.is_none() {
+        check(&app).await?;
+    }
+    let Some(update) = state.pending.lock().await.take() else {
+        return Err(EngineError::UpdateFailed {
+            detail: "No update available".to_string(),
+        });
+    };
+
+    if let Ok(mut active) = state.active_download.lock() {
+        *active = Some(request_id);
+    }
+    state.downloaded.store(0, Ordering::Relaxed);
+    let result = update.download_and_install().await.map_err(update_error);
+    if let Ok(mut active) = state.active_download.lock() {
+        *active = None;
+    }
+    result
+}
+
+/// Restart into the installed update; refuses while generations are running unless forced
+#[tauri::command]
+pub async fn relaunch_app(app: AppHandle, force: Option<bool>) -> Result<(), EngineError> {
+    if !force.unwrap_or(false) {
+        ensure_idle(&app)?;
+    }
+    app.restart();
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn set_update_auto_check(app: AppHandle, enabled: bool) -> Result<(), EngineError> {
+    let mut config = settings::load(&app);
+    config.updates.auto_check = enabled;
+    settings::save(&app, &config)
+}
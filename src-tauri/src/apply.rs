@@ -0,0 +1,257 @@
+use crate::diff::{apply_unified, diff_text, TextDiff, DEFAULT_CONTEXT_LINES};
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::formatter::format_for_path;
+use crate::hash::sha256_hex;
+use crate::sandbox;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+/// How a suggested code block is applied; line numbers are 1-based
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ApplyMode {
+    ReplaceFile,
+    /// Insert before `line`; `line == line_count + 1` appends
+    InsertAtLine {
+        line: usize,
+    },
+    /// Replace the inclusive range `start_line..=end_line`
+    ReplaceRange {
+        start_line: usize,
+        end_line: usize,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplyPreview {
+    pub path: String,
+    pub exists: bool,
+    /// Hash of the current content; pass back to `confirm_apply_block`
+    pub base_hash: Option<String>,
+    pub diff: TextDiff,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ApplyResult {
+    pub path: String,
+    pub hash: String,
+    pub additions: usize,
+    pub deletions: usize,
+    pub hunks: usize,
+    pub backup_path: Option<String>,
+}
+
+fn read_current(path: &Path) -> Result<Option<String>, EngineError> {
+    match std::fs::read_to_string(path) {
+        Ok(content) => Ok(Some(content)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(EngineError::io(path, e)),
+    }
+}
+
+/// Re-terminate the snippet's lines with the file's line ending
+fn normalize_snippet(snippet: &str, eol: &str) -> String {
+    let unix = snippet.replace("\r\n", "\n");
+    if eol == "\n" {
+        unix
+    } else {
+        unix.replace('\n', eol)
+    }
+}
+
+fn with_trailing_eol(mut text: String, eol: &str) -> String {
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push_str(eol);
+    }
+    text
+}
+
+/// Build the new file content without touching disk
+pub fn compose(current: &str, new_content: &str, mode: &ApplyMode) -> Result<String, EngineError> {
+    let eol = if current.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let snippet = normalize_snippet(new_content, eol);
+    let mut lines: Vec<String> = current.split_inclusive('\n').map(str::to_string).collect();
+    let count = lines.len();
+
+    match *mode {
+        ApplyMode::ReplaceFile => return Ok(new_content.to_string()),
+        ApplyMode::InsertAtLine { line } => {
+            if line == 0 || line > count + 1 {
+                return Err(EngineError::invalid(format!(
+                    "Line {} is out of range (file has {} lines, valid insert points are 1..={})",
+                    line,
+                    count,
+                    count + 1
+                )));
+            }
+            if line == count + 1 {
+                if let Some(last) = lines.last_mut() {
+                    if !last.ends_with('\n') {
+                        last.push_str(eol);
+                    }
+                }
+                lines.push(snippet);
+            } else {
+                lines.insert(line - 1, with_trailing_eol(snippet, eol));
+            }
+        }
+        ApplyMode::ReplaceRange {
+            start_line,
+            end_line,
+        } => {
+            if start_line == 0 || start_line > end_line || end_line > count {
+                return Err(EngineError::invalid(format!(
+                    "Range {}..={} is out of bounds (file has {} lines)",
+                    start_line, end_line, count
+                )));
+            }
+            let replaced_had_eol = lines[end_line - 1].ends_with('\n');
+            let snippet = if end_line < count || replaced_had_eol {
+                with_trailing_eol(snippet, eol)
+            } else {
+                snippet
+            };
+            lines.splice(start_line - 1..end_line, std::iter::once(snippet));
+        }
+    }
+
+    Ok(lines.concat())
+}
+
+async fn load_and_compose(
+    path: PathBuf,
+    new_content: String,
+    mode: ApplyMode,
+) -> Result<(Option<String>, String), EngineError> {
+    tokio::task::spawn_blocking(move || {
+        let current = read_current(&path)?;
+        let updated = compose(current.as_deref().unwrap_or(""), &new_content, &mode)?;
+        Ok((current, updated))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Show what applying a code block would change, without writing anything.
+/// With `format` the composed file is run through the project's formatter first.
+#[tauri::command]
+pub async fn preview_apply_block(
+    app: AppHandle,
+    path: String,
+    new_content: String,
+    mode: ApplyMode,
+    format: Option<bool>,
+) -> Result<ApplyPreview, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let (current, mut updated) = load_and_compose(target.clone(), new_content, mode).await?;
+    if format.unwrap_or(false) {
+        updated = format_for_path(&app, &target, updated).await?.content;
+    }
+    let base = current.as_deref().unwrap_or("");
+
+    Ok(ApplyPreview {
+        exists: current.is_some(),
+        base_hash: current.as_deref().map(|c| sha256_hex(c.as_bytes())),
+        diff: diff_text(base, &updated, DEFAULT_CONTEXT_LINES, &path),
+        path,
+    })
+}
+
+/// Apply a previewed code block; refuses if the file changed since the preview.
+/// `format` must match what was passed to the preview for the diff to hold.
+#[tauri::command]
+pub async fn confirm_apply_block(
+    app: AppHandle,
+    path: String,
+    new_content: String,
+    mode: ApplyMode,
+    expected_hash: Option<String>,
+    format: Option<bool>,
+) -> Result<ApplyResult, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let (current, mut updated) = load_and_compose(target.clone(), new_content, mode).await?;
+    if format.unwrap_or(false) {
+        updated = format_for_path(&app, &target, updated).await?.content;
+    }
+
+    tokio::task::spawn_blocking(move || {
+        // Re-read right before writing; formatting may have taken a while
+        let latest = read_current(&target)?;
+        let actual_hash = latest.as_deref().map(|c| sha256_hex(c.as_bytes()));
+        if actual_hash != expected_hash || latest != current {
+            return Err(EngineError::Conflict {
+                path,
+                expected: expected_hash,
+                actual: actual_hash,
+            });
+        }
+
+        let base = current.as_deref().unwrap_or("");
+        let diff = diff_text(base, &updated, DEFAULT_CONTEXT_LINES, &path);
+        let backup = atomic_write(&target, updated.as_bytes(), true)
+            .map_err(|e| EngineError::io(&target, e))?;
+
+        Ok(ApplyResult {
+            hash: sha256_hex(updated.as_bytes()),
+            additions: diff.additions,
+            deletions: diff.deletions,
+            hunks: diff.hunks.len(),
+            backup_path: backup.map(|p| p.display().to_string()),
+            path,
+        })
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Apply a unified diff, such as one from `compute_diff`, to `path`. Nothing
+/// is written unless every hunk fits; `PatchFailed` lists the ones that
+/// don't. `expected_hash` refuses the patch if the file changed since.
+#[tauri::command]
+pub async fn apply_patch(
+    app: AppHandle,
+    path: String,
+    unified_diff: String,
+    expected_hash: Option<String>,
+) -> Result<ApplyResult, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || {
+        let current = read_current(&target)?;
+        let actual_hash = current.as_deref().map(|c| sha256_hex(c.as_bytes()));
+        if expected_hash.is_some() && actual_hash != expected_hash {
+            return Err(EngineError::Conflict {
+                path,
+                expected: expected_hash,
+                actual: actual_hash,
+            });
+        }
+
+        let base = current.as_deref().unwrap_or("");
+        let updated = apply_unified(base, &unified_diff).map_err(|e| EngineError::PatchFailed {
+            path: path.clone(),
+            failed_hunks: e.failed_hunks,
+            total_hunks: e.total_hunks,
+            detail: e.detail,
+        })?;
+        let diff = diff_text(base, &updated, DEFAULT_CONTEXT_LINES, &path);
+        let backup = atomic_write(&target, updated.as_bytes(), true)
+            .map_err(|e| EngineError::fs(&path, e))?;
+
+        Ok(ApplyResult {
+            hash: sha256_hex(updated.as_bytes()),
+            additions: diff.additions,
+            deletions: diff.deletions,
+            hunks: diff.hunks.len(),
+            backup_path: backup.map(|p| p.display().to_string()),
+            path,
+        })
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
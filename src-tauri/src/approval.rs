@@ -0,0 +1,323 @@
+use crate::error::EngineError;
+use crate::hash::random_hex;
+use crate::settings;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, Window};
+use tokio::sync::oneshot;
+
+/// The MCP server's name in `--mcp-config`, and its one tool
+const SERVER_NAME: &str = "bups";
+const TOOL_NAME: &str = "approve";
+// Spoken when the client doesn't say which version it wants
+const PROTOCOL_VERSION: &str = "2025-03-26";
+
+/// Where a window's CLI processes send their tool approvals
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ApprovalRoute {
+    url: String,
+}
+
+impl ApprovalRoute {
+    /// A route of the usual shape that nothing listens at, for showing the
+    /// arguments a stream would get without starting the server
+    pub fn placeholder() -> Self {
+        Self {
+            url: "http://127.0.0.1:0/mcp/preview".to_string(),
+        }
+    }
+
+    /// `--mcp-config` with the approval server and `--permission-prompt-tool`
+    /// pointing at it
+    pub fn args(&self) -> Vec<String> {
+        let config = json!({
+            "mcpServers": { SERVER_NAME: { "type": "http", "url": self.url } }
+        });
+        vec![
+            "--mcp-config".to_string(),
+            config.to_string(),
+            "--permission-prompt-tool".to_string(),
+            format!("mcp__{}__{}", SERVER_NAME, TOOL_NAME),
+        ]
+    }
+}
+
+struct Pending {
+    window: String,
+    tool: String,
+    reply: oneshot::Sender<bool>,
+}
+
+/// Managed approval server: a loopback MCP endpoint per window, started the
+/// first time a stream asks for one, and the tool calls waiting on an answer
+#[derive(Default)]
+pub struct Approvals {
+    port: tokio::sync::Mutex<Option<u16>>,
+    /// URL token to window label
+    windows: Mutex<HashMap<String, String>>,
+    pending: Mutex<HashMap<String, Pending>>,
+}
+
+impl Approvals {
+    fn window_for(&self, token: &str) -> Option<String> {
+        self.windows.lock().ok()?.get(token).cloned()
+    }
+
+    /// Forget the window's endpoint; its waiting tool calls are denied
+    pub fn close_window(&self, label: &str) {
+        if let Ok(mut windows) = self.windows.lock() {
+            windows.retain(|_, window| window != label);
+        }
+        if let Ok(mut pending) = self.pending.lock() {
+            pending.retain(|_, p| p.window != label);
+        }
+    }
+
+    fn answer(&self, window: &str, request_id: &str, allow: bool) -> Option<String> {
+        let mut pending = self.pending.lock().ok()?;
+        if pending.get(request_id)?.window != window {
+            return None;
+        }
+        let waiting = pending.remove(request_id)?;
+        waiting.reply.send(allow).ok()?;
+        Some(waiting.tool)
+    }
+}
+
+/// Removes a tool call from the pending set once it's answered or abandoned,
+/// e.g. when the CLI is killed mid-prompt and drops the connection
+struct PendingGuard<'a> {
+    approvals: &'a Approvals,
+    request_id: String,
+}
+
+impl Drop for PendingGuard<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut pending) = self.approvals.pending.lock() {
+            pending.remove(&self.request_id);
+        }
+    }
+}
+
+fn allow(input: Value) -> Value {
+    json!({ "behavior": "allow", "updatedInput": input })
+}
+
+fn deny(message: &str) -> Value {
+    json!({ "behavior": "deny", "message": message })
+}
+
+/// Ask the window about one tool call: `claude-permission-request`
+/// `{ request_id, tool, input }`, then wait for `respond_permission`. No
+/// answer in time is a denial, announced as `claude-permission-timeout`.
+async fn decide(app: &AppHandle, window: &str, arguments: &Value) -> Value {
+    let tool = arguments["tool_name"]
+        .as_str()
+        .unwrap_or_default()
+        .to_string();
+    let input = arguments.get("input").cloned().unwrap_or_else(|| json!({}));
+    let engine = settings::load(app).engine;
+    if engine.always_allowed_tools.contains(&tool) {
+        return allow(input);
+    }
+    let Some(target) = app.get_window(window) else {
+        return deny("The window that sent this request is closed");
+    };
+    let approvals = app.state::<Approvals>();
+    let request_id = format!("perm-{}", random_hex(8));
+    let (reply, answer) = oneshot::channel();
+    let registered = approvals
+        .pending
+        .lock()
+        .map(|mut pending| {
+            pending.insert(
+                request_id.clone(),
+                Pending {
+                    window: window.to_string(),
+                    tool: tool.clone(),
+                    reply,
+                },
+            )
+        })
+        .is_ok();
+    if !registered {
+        return deny("Approvals are unavailable");
+    }
+    let _guard = PendingGuard {
+        approvals: &approvals,
+        request_id: request_id.clone(),
+    };
+    let _ = target.emit(
+        "claude-permission-request",
+        json!({
+            "request_id": request_id,
+            "tool": tool,
+            "input": input,
+            "tool_use_id": arguments.get("tool_use_id"),
+        }),
+    );
+    let timeout = Duration::from_secs(engine.permission_timeout_secs);
+    match tokio::time::timeout(timeout, answer).await {
+        Ok(Ok(true)) => allow(input),
+        Ok(Ok(false)) => deny("The user denied this tool call"),
+        Ok(Err(_)) => deny("The window that sent this request is closed"),
+        Err(_) => {
+            tracing::info!(tool = %tool, "Tool approval timed out");
+            let _ = target.emit(
+                "claude-permission-timeout",
+                json!({ "request_id": request_id }),
+            );
+            deny("Nobody answered the approval request in time")
+        }
+    }
+}
+
+fn tool_list() -> Value {
+    json!({
+        "tools": [{
+            "name": TOOL_NAME,
+            "description": "Ask the user whether a tool call may run",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "tool_name": { "type": "string" },
+                    "input": { "type": "object" },
+                    "tool_use_id": { "type": "string" }
+                },
+                "required": ["tool_name", "input"]
+            }
+        }]
+    })
+}
+
+/// JSON-RPC over MCP's streamable HTTP transport, answered with plain JSON
+async fn handle(
+    State(app): State<AppHandle>,
+    Path(token): Path<String>,
+    headers: HeaderMap,
+    Json(message): Json<Value>,
+) -> Response {
+    // Only the CLI talks to this; a browser page never gets to
+    if headers.contains_key(header::ORIGIN) {
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let Some(window) = app.state::<Approvals>().window_for(&token) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+    // Notifications get no reply
+    let Some(id) = message.get("id").cloned() else {
+        return StatusCode::ACCEPTED.into_response();
+    };
+    let result = match message["method"].as_str().unwrap_or_default() {
+        "initialize" => json!({
+            "protocolVersion": message
+                .pointer("/params/protocolVersion")
+                .cloned()
+                .unwrap_or_else(|| PROTOCOL_VERSION.into()),
+            "capabilities": { "tools": {} },
+            "serverInfo": { "name": "bups-engine", "version": env!("CARGO_PKG_VERSION") },
+        }),
+        "ping" => json!({}),
+        "tools/list" => tool_list(),
+        "tools/call" if message.pointer("/params/name") == Some(&json!(TOOL_NAME)) => {
+            let arguments = message
+                .pointer("/params/arguments")
+                .cloned()
+                .unwrap_or_default();
+            let decision = decide(&app, &window, &arguments).await;
+            json!({ "content": [{ "type": "text", "text": decision.to_string() }] })
+        }
+        method => {
+            return Json(json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": -32601, "message": format!("Unknown method '{}'", method) },
+            }))
+            .into_response();
+        }
+    };
+    Json(json!({ "jsonrpc": "2.0", "id": id, "result": result })).into_response()
+}
+
+async fn start(app: &AppHandle) -> Result<u16, EngineError> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, 0));
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .map_err(|e| EngineError::Network {
+            detail: format!("Failed to bind the approval server: {}", e),
+        })?;
+    let port = listener
+        .local_addr()
+        .map_err(|e| EngineError::internal(format!("Approval server has no address: {}", e)))?
+        .port();
+    let router = Router::new()
+        .route("/mcp/{token}", post(handle))
+        .with_state(app.clone());
+    tauri::async_runtime::spawn(async move {
+        let _ = axum::serve(listener, router).await;
+    });
+    Ok(port)
+}
+
+/// The endpoint for `window`'s CLI processes, starting the server if need be
+pub async fn route_for(app: &AppHandle, window: &Window) -> Result<ApprovalRoute, EngineError> {
+    let approvals = app.state::<Approvals>();
+    let port = {
+        let mut port = approvals.port.lock().await;
+        match *port {
+            Some(port) => port,
+            None => *port.insert(start(app).await?),
+        }
+    };
+    let mut windows = approvals
+        .windows
+        .lock()
+        .map_err(|_| EngineError::internal("Approval lock is poisoned"))?;
+    let label = window.label();
+    let token = match windows.iter().find(|(_, owner)| *owner == label) {
+        Some((token, _)) => token.clone(),
+        None => {
+            let token = random_hex(16);
+            windows.insert(token.clone(), label.to_string());
+            token
+        }
+    };
+    Ok(ApprovalRoute {
+        url: format!("http://127.0.0.1:{}/mcp/{}", port, token),
+    })
+}
+
+/// Answer a `claude-permission-request`. With `always`, an allowed tool is
+/// added to the engine's always-allowed tools and isn't asked about again.
+/// False if the request already timed out or was never this window's.
+#[tauri::command]
+pub async fn respond_permission(
+    app: AppHandle,
+    window: Window,
+    request_id: String,
+    allow: bool,
+    always: Option<bool>,
+) -> Result<bool, EngineError> {
+    let Some(tool) = app
+        .state::<Approvals>()
+        .answer(window.label(), &request_id, allow)
+    else {
+        return Ok(false);
+    };
+    if allow && always.unwrap_or(false) {
+        let mut current = settings::load(&app);
+        if !current.engine.always_allowed_tools.contains(&tool) {
+            current.engine.always_allowed_tools.push(tool);
+            settings::save(&app, &current)?;
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,372 @@
+use crate::context::{build_globset, relative};
+use crate::error::EngineError;
+use crate::sandbox;
+use ignore::WalkBuilder;
+use serde::Serialize;
+use serde_json::json;
+use std::fs;
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Window};
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Serialize)]
+pub struct ArchiveSummary {
+    pub entries: usize,
+    /// Uncompressed
+    pub bytes: u64,
+}
+
+/// `archive-progress` for one archive, at most every [`PROGRESS_INTERVAL`]
+/// and always for the last entry
+struct Progress<'a> {
+    window: &'a Window,
+    request_id: Option<&'a str>,
+    total: usize,
+    last_emit: Option<Instant>,
+}
+
+impl Progress<'_> {
+    fn report(&mut self, done: usize, bytes: u64) {
+        let due = self
+            .last_emit
+            .is_none_or(|at| at.elapsed() >= PROGRESS_INTERVAL);
+        if !due && done < self.total {
+            return;
+        }
+        self.last_emit = Some(Instant::now());
+        let _ = self.window.emit(
+            "archive-progress",
+            json!({
+                "request_id": self.request_id,
+                "done": done,
+                "total": self.total,
+                "bytes": bytes,
+            }),
+        );
+    }
+}
+
+#[cfg(unix)]
+fn permissions(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o777)
+}
+
+#[cfg(not(unix))]
+fn permissions(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: Option<u32>) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    match mode {
+        Some(mode) => fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777)),
+        None => Ok(()),
+    }
+}
+
+#[cfg(not(unix))]
+fn set_permissions(_path: &Path, _mode: Option<u32>) -> io::Result<()> {
+    Ok(())
+}
+
+fn zip_error(path: &Path, err: zip::result::ZipError) -> EngineError {
+    match err {
+        zip::result::ZipError::Io(e) => EngineError::fs(path, e),
+        other => EngineError::io(path, format!("Invalid zip: {}", other)),
+    }
+}
+
+/// Everything under `src` except excluded paths and the archive being
+/// written, directories before their contents. Symlinks are left out.
+fn collect_entries(
+    src: &Path,
+    dest: &Path,
+    exclude_globs: &[String],
+) -> Result<Vec<(PathBuf, bool)>, EngineError> {
+    let exclude = build_globset(exclude_globs)?;
+    let root = src.to_path_buf();
+    let walk = WalkBuilder::new(src)
+        .standard_filters(false)
+        .follow_links(false)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| {
+            entry.depth() == 0
+                || !exclude
+                    .as_ref()
+                    .is_some_and(|set| set.is_match(relative(&root, entry.path())))
+        })
+        .build();
+    let mut entries = Vec::new();
+    for entry in walk {
+        let entry = entry.map_err(|e| EngineError::io(src, e))?;
+        let Some(file_type) = entry.file_type() else {
+            continue;
+        };
+        if entry.depth() == 0 || entry.path() == dest || file_type.is_symlink() {
+            continue;
+        }
+        entries.push((entry.into_path(), file_type.is_dir()));
+    }
+    Ok(entries)
+}
+
+fn write_zip(
+    src: &Path,
+    dest: &Path,
+    entries: &[(PathBuf, bool)],
+    progress: &mut Progress,
+) -> Result<ArchiveSummary, EngineError> {
+    let file = fs::File::create(dest).map_err(|e| EngineError::fs(dest, e))?;
+    let mut zip = ZipWriter::new(io::BufWriter::new(file));
+    let mut bytes = 0;
+    for (done, (path, is_dir)) in entries.iter().enumerate() {
+        let name = relative(src, path);
+        let meta = fs::metadata(path).map_err(|e| EngineError::fs(path, e))?;
+        let mut options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(meta.len() >= u32::MAX as u64);
+        if let Some(mode) = permissions(&meta) {
+            options = options.unix_permissions(mode);
+        }
+        if *is_dir {
+            zip.add_directory(name, options)
+                .map_err(|e| zip_error(dest, e))?;
+        } else {
+            zip.start_file(name, options)
+                .map_err(|e| zip_error(dest, e))?;
+            let mut source = fs::File::open(path).map_err(|e| EngineError::fs(path, e))?;
+            bytes += io::copy(&mut source, &mut zip).map_err(|e| EngineError::fs(path, e))?;
+        }
+        progress.report(done + 1, bytes);
+    }
+    zip.finish().map_err(|e| zip_error(dest, e))?;
+    Ok(ArchiveSummary {
+        entries: entries.len(),
+        bytes,
+    })
+}
+
+/// Zip `src_dir` into `dest_zip`, one file at a time so memory stays flat.
+/// `exclude_globs` match paths relative to `src_dir`; symlinks are skipped.
+/// Progress arrives as `archive-progress` `{ request_id, done, total, bytes }`.
+#[tauri::command]
+pub async fn zip_directory(
+    app: AppHandle,
+    window: Window,
+    src_dir: String,
+    dest_zip: String,
+    exclude_globs: Option<Vec<String>>,
+    request_id: Option<String>,
+) -> Result<ArchiveSummary, EngineError> {
+    let src = sandbox::allow(&app, &src_dir)?;
+    let dest = sandbox::allow(&app, &dest_zip)?;
+    if !src.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            src_dir
+        )));
+    }
+    tokio::task::spawn_blocking(move || {
+        let entries = collect_entries(&src, &dest, &exclude_globs.unwrap_or_default())?;
+        let mut progress = Progress {
+            window: &window,
+            request_id: request_id.as_deref(),
+            total: entries.len(),
+            last_emit: None,
+        };
+        let written = write_zip(&src, &dest, &entries, &mut progress);
+        // Half an archive is worse than none
+        if written.is_err() {
+            let _ = fs::remove_file(&dest);
+        }
+        written
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Where an entry extracts to under `dest`, if its name stays inside it. Any
+/// `..`, root or drive prefix refuses the entry, whichever slash it uses.
+fn entry_target(dest: &Path, name: &str) -> Option<PathBuf> {
+    let name = name.replace('\\', "/");
+    let mut target = dest.to_path_buf();
+    for part in Path::new(&name).components() {
+        match part {
+            Component::Normal(part) => target.push(part),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    (target != dest).then_some(target)
+}
+
+/// Whether anything already on disk between `dest` and `target` is a
+/// symlink, which would carry the write somewhere else
+fn through_link(dest: &Path, target: &Path) -> bool {
+    let Ok(rel) = target.strip_prefix(dest) else {
+        return true;
+    };
+    let mut at = dest.to_path_buf();
+    for part in rel.components() {
+        at.push(part);
+        match fs::symlink_metadata(&at) {
+            Ok(meta) if meta.file_type().is_symlink() => return true,
+            Ok(_) => {}
+            // Nothing below a missing directory exists yet either
+            Err(_) => return false,
+        }
+    }
+    false
+}
+
+fn outside(name: &str, dest: &Path) -> EngineError {
+    EngineError::invalid(format!(
+        "Archive entry '{}' would extract outside {}",
+        name,
+        dest.display()
+    ))
+}
+
+/// Re-check a directory just created for an entry against the real
+/// location of `dest`, in case a link or junction slipped in since vetting
+fn ensure_inside(root: &Path, dir: &Path, name: &str, dest: &Path) -> Result<(), EngineError> {
+    let real = fs::canonicalize(dir).map_err(|e| EngineError::fs(dir, e))?;
+    if real.starts_with(root) {
+        Ok(())
+    } else {
+        Err(outside(name, dest))
+    }
+}
+
+fn extract(
+    archive: &mut ZipArchive<fs::File>,
+    src: &Path,
+    dest: &Path,
+    overwrite: bool,
+    progress: &mut Progress,
+) -> Result<ArchiveSummary, EngineError> {
+    // Vet every name before anything is written, so a bad archive leaves no trace
+    let mut targets = Vec::with_capacity(archive.len());
+    for index in 0..archive.len() {
+        let entry = archive.by_index_raw(index).map_err(|e| zip_error(src, e))?;
+        let name = entry.name();
+        let target = entry_target(dest, name)
+            .filter(|target| !through_link(dest, target))
+            .ok_or_else(|| outside(name, dest))?;
+        if !overwrite && !entry.is_dir() && fs::symlink_metadata(&target).is_ok() {
+            return Err(EngineError::AlreadyExists {
+                path: target.display().to_string(),
+            });
+        }
+        targets.push(target);
+    }
+
+    fs::create_dir_all(dest).map_err(|e| EngineError::fs(dest, e))?;
+    let root = fs::canonicalize(dest).map_err(|e| EngineError::fs(dest, e))?;
+    let mut bytes = 0;
+    for (index, target) in targets.iter().enumerate() {
+        let mut entry = archive.by_index(index).map_err(|e| zip_error(src, e))?;
+        let name = entry.name().to_string();
+        if entry.is_dir() {
+            fs::create_dir_all(target).map_err(|e| EngineError::fs(target, e))?;
+            ensure_inside(&root, target, &name, dest)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent).map_err(|e| EngineError::fs(parent, e))?;
+                ensure_inside(&root, parent, &name, dest)?;
+            }
+            let mut out = fs::File::create(target).map_err(|e| EngineError::fs(target, e))?;
+            bytes += io::copy(&mut entry, &mut out).map_err(|e| EngineError::fs(target, e))?;
+            set_permissions(target, entry.unix_mode()).map_err(|e| EngineError::fs(target, e))?;
+        }
+        progress.report(index + 1, bytes);
+    }
+    Ok(ArchiveSummary {
+        entries: targets.len(),
+        bytes,
+    })
+}
+
+/// Extract `src_zip` into `dest_dir`, streaming each entry to disk. Entries
+/// that would land outside `dest_dir`, by name or through a symlink already
+/// in it, refuse the whole archive, as do
+/// existing files unless `overwrite`. Unix permission bits are kept.
+#[tauri::command]
+pub async fn unzip_archive(
+    app: AppHandle,
+    window: Window,
+    src_zip: String,
+    dest_dir: String,
+    overwrite: Option<bool>,
+    request_id: Option<String>,
+) -> Result<ArchiveSummary, EngineError> {
+    let src = sandbox::allow(&app, &src_zip)?;
+    let dest = sandbox::allow(&app, &dest_dir)?;
+    tokio::task::spawn_blocking(move || {
+        let file = fs::File::open(&src).map_err(|e| EngineError::fs(&src, e))?;
+        let mut archive = ZipArchive::new(file).map_err(|e| zip_error(&src, e))?;
+        let mut progress = Progress {
+            window: &window,
+            request_id: request_id.as_deref(),
+            total: archive.len(),
+            last_emit: None,
+        };
+        extract(
+            &mut archive,
+            &src,
+            &dest,
+            overwrite.unwrap_or(false),
+            &mut progress,
+        )
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_that_climb_out_are_refused() {
+        let dest = Path::new("/work/out");
+        for name in ["../x", "a/../../x", "/etc/passwd", "..\\x", "", "./"] {
+            assert_eq!(entry_target(dest, name), None, "{}", name);
+        }
+        // Only a drive on Windows; elsewhere `C:` is an ordinary name
+        if cfg!(windows) {
+            assert_eq!(entry_target(dest, "C:\\x"), None);
+        }
+        assert_eq!(entry_target(dest, "a/./b"), Some(dest.join("a").join("b")));
+        assert_eq!(entry_target(dest, "a\\b"), Some(dest.join("a").join("b")));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_already_in_the_destination_are_refused() {
+        let base = std::env::temp_dir().join(format!("bups-archive-{}", std::process::id()));
+        let dest = base.join("dest");
+        let elsewhere = base.join("elsewhere");
+        fs::create_dir_all(dest.join("real")).unwrap();
+        fs::create_dir_all(&elsewhere).unwrap();
+        std::os::unix::fs::symlink(&elsewhere, dest.join("link")).unwrap();
+        std::os::unix::fs::symlink(elsewhere.join("f"), dest.join("file-link")).unwrap();
+
+        assert!(through_link(&dest, &dest.join("link").join("a").join("b")));
+        assert!(through_link(&dest, &dest.join("file-link")));
+        assert!(!through_link(&dest, &dest.join("real").join("a")));
+        assert!(!through_link(&dest, &dest.join("new").join("a")));
+
+        let root = fs::canonicalize(&dest).unwrap();
+        assert!(ensure_inside(&root, &dest.join("real"), "real/", &dest).is_ok());
+        assert!(ensure_inside(&root, &dest.join("link"), "link/", &dest).is_err());
+        let _ = fs::remove_dir_all(base);
+    }
+}
@@ -0,0 +1,481 @@
+use crate::error::EngineError;
+use crate::files::{looks_binary, sniff_mime};
+use crate::hash::sha256_hex;
+use crate::images::{self, ImageOptions, PassthroughReason};
+use crate::paths::app_cache_subdir;
+use crate::pdf::{self, PdfOptions};
+use crate::sandbox;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+pub const MAX_INLINE_TEXT_BYTES: usize = 512 * 1024;
+// What one send or stream takes, before images are downscaled
+pub const MAX_ATTACHMENT_BYTES: u64 = 20 * 1024 * 1024;
+pub const MAX_TOTAL_ATTACHMENT_BYTES: u64 = 50 * 1024 * 1024;
+// All the Messages API takes as image blocks
+const API_IMAGE_MIMES: &[&str] = &["image/jpeg", "image/png", "image/gif", "image/webp"];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentMode {
+    /// Inline text-like files, reference everything else
+    #[default]
+    Auto,
+    InlineText,
+    Reference,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AttachmentRequest {
+    pub path: String,
+    #[serde(default)]
+    pub mode: AttachmentMode,
+    /// Send images byte-for-byte, e.g. for pixel-exact comparisons
+    #[serde(default)]
+    pub skip_processing: bool,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct AttachmentOptions {
+    pub image: ImageOptions,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AttachmentKind {
+    Text,
+    Pdf,
+    Image,
+    Binary,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PdfSummary {
+    pub title: Option<String>,
+    pub page_count: u32,
+    pub pages_included: usize,
+    pub pages_skipped: usize,
+    pub likely_scanned: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ProcessedImageFile {
+    pub path: String,
+    pub mime: &'static str,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageSummary {
+    pub original_width: Option<u32>,
+    pub original_height: Option<u32>,
+    pub original_bytes: u64,
+    /// Downscaled, metadata-free copy in the temp store; send this instead of the original
+    pub processed: Option<ProcessedImageFile>,
+    pub passthrough: Option<PassthroughReason>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PreparedAttachment {
+    pub name: String,
+    pub path: String,
+    pub kind: AttachmentKind,
+    pub mime: String,
+    pub bytes: u64,
+    /// Text to place in the prompt; `None` means the file is passed by path
+    pub inline_text: Option<String>,
+    pub pdf: Option<PdfSummary>,
+    pub image: Option<ImageSummary>,
+    pub warnings: Vec<String>,
+}
+
+fn classify(bytes: &[u8]) -> (AttachmentKind, &'static str) {
+    match sniff_mime(bytes) {
+        Some("application/pdf") => (AttachmentKind::Pdf, "application/pdf"),
+        Some(mime) if mime.starts_with("image/") => (AttachmentKind::Image, mime),
+        Some(mime) => (AttachmentKind::Binary, mime),
+        None if looks_binary(bytes) => (AttachmentKind::Binary, "application/octet-stream"),
+        None => (AttachmentKind::Text, "text/plain"),
+    }
+}
+
+fn inline_text(bytes: &[u8], warnings: &mut Vec<String>) -> String {
+    let mut text = String::from_utf8_lossy(bytes).into_owned();
+    if text.len() > MAX_INLINE_TEXT_BYTES {
+        let mut cut = MAX_INLINE_TEXT_BYTES;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        warnings.push(format!(
+            "Truncated to the first {} bytes",
+            MAX_INLINE_TEXT_BYTES
+        ));
+    }
+    text
+}
+
+fn prepare_image(
+    bytes: &[u8],
+    request: &AttachmentRequest,
+    options: &ImageOptions,
+    temp_dir: &Path,
+    warnings: &mut Vec<String>,
+) -> Result<ImageSummary, EngineError> {
+    let outcome = if request.skip_processing {
+        Err(PassthroughReason::Skipped)
+    } else {
+        images::process(bytes, options)
+    };
+    match outcome {
+        Ok(processed) => {
+            let name = format!(
+                "{}-{}.{}",
+                &sha256_hex(bytes)[..16],
+                options.max_edge,
+                processed.format.extension()
+            );
+            let out = temp_dir.join(name);
+            std::fs::write(&out, &processed.data).map_err(|e| EngineError::io(&out, e))?;
+            Ok(ImageSummary {
+                original_width: Some(processed.original_width),
+                original_height: Some(processed.original_height),
+                original_bytes: bytes.len() as u64,
+                processed: Some(ProcessedImageFile {
+                    path: out.display().to_string(),
+                    mime: processed.format.mime(),
+                    width: processed.width,
+                    height: processed.height,
+                    bytes: processed.data.len() as u64,
+                }),
+                passthrough: None,
+            })
+        }
+        Err(reason) => {
+            if reason == PassthroughReason::Unsupported {
+                warnings.push("Image format not supported for resizing; sent as-is".to_string());
+            }
+            let dimensions = image::ImageReader::new(std::io::Cursor::new(bytes))
+                .with_guessed_format()
+                .ok()
+                .and_then(|r| r.into_dimensions().ok());
+            Ok(ImageSummary {
+                original_width: dimensions.map(|d| d.0),
+                original_height: dimensions.map(|d| d.1),
+                original_bytes: bytes.len() as u64,
+                processed: None,
+                passthrough: Some(reason),
+            })
+        }
+    }
+}
+
+pub fn prepare(
+    request: &AttachmentRequest,
+    options: &AttachmentOptions,
+    temp_dir: &Path,
+) -> Result<PreparedAttachment, EngineError> {
+    let path = Path::new(&request.path);
+    let bytes = std::fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    let (kind, mime) = classify(&bytes);
+    let mut warnings = Vec::new();
+    let mut pdf_summary = None;
+    let image_summary = match kind {
+        AttachmentKind::Image => Some(prepare_image(
+            &bytes,
+            request,
+            &options.image,
+            temp_dir,
+            &mut warnings,
+        )?),
+        _ => None,
+    };
+
+    let inline = match (kind, request.mode) {
+        (_, AttachmentMode::Reference) => None,
+        (AttachmentKind::Text, _) => Some(inline_text(&bytes, &mut warnings)),
+        (AttachmentKind::Pdf, _) => {
+            let extracted = pdf::extract(path, &PdfOptions::default())?;
+            if extracted.likely_scanned {
+                warnings.push(
+                    "PDF appears to be scanned images; little or no text could be extracted"
+                        .to_string(),
+                );
+            }
+            if !extracted.pages_skipped.is_empty() {
+                warnings.push(format!(
+                    "{} of {} pages were not included",
+                    extracted.pages_skipped.len(),
+                    extracted.page_count
+                ));
+            }
+            let text = extracted.joined();
+            pdf_summary = Some(PdfSummary {
+                title: extracted.title,
+                page_count: extracted.page_count,
+                pages_included: extracted.pages.len(),
+                pages_skipped: extracted.pages_skipped.len(),
+                likely_scanned: extracted.likely_scanned,
+            });
+            (!text.trim().is_empty()).then_some(text)
+        }
+        (_, AttachmentMode::InlineText) => {
+            warnings.push("Binary file can't be inlined; passing by reference".to_string());
+            None
+        }
+        (_, AttachmentMode::Auto) => None,
+    };
+
+    Ok(PreparedAttachment {
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| request.path.clone()),
+        path: request.path.clone(),
+        kind,
+        mime: mime.to_string(),
+        bytes: bytes.len() as u64,
+        inline_text: inline,
+        pdf: pdf_summary,
+        image: image_summary,
+        warnings,
+    })
+}
+
+/// A file attached to a send or stream: a path, or the bytes themselves
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Attachment {
+    Path {
+        path: String,
+    },
+    Inline {
+        /// Standard base64
+        data: String,
+        mime: String,
+        name: Option<String>,
+    },
+}
+
+/// An image as the turn sends it: the processed copy when there is one
+struct AttachedImage {
+    path: PathBuf,
+    mime: String,
+}
+
+/// A message with its attachments worked in: text inlined between markers,
+/// everything else listed by path
+pub struct AttachedTurn {
+    text: String,
+    images: Vec<AttachedImage>,
+}
+
+fn extension(mime: &str) -> &str {
+    match mime {
+        "image/jpeg" => "jpg",
+        "text/plain" => "txt",
+        other => other.rsplit('/').next().unwrap_or("bin"),
+    }
+}
+
+fn too_large(name: &str, size: u64) -> Result<(), EngineError> {
+    if size > MAX_ATTACHMENT_BYTES {
+        return Err(EngineError::FileTooLarge {
+            path: name.to_string(),
+            size,
+            limit: MAX_ATTACHMENT_BYTES,
+        });
+    }
+    Ok(())
+}
+
+/// Check each attachment against the workspace and the size limits, write
+/// inline ones to the temp store, and prepare them all the way
+/// `prepare_attachments` does
+fn gather(
+    app: &AppHandle,
+    attachments: &[Attachment],
+    temp_dir: &Path,
+) -> Result<Vec<PreparedAttachment>, EngineError> {
+    let mut total = 0;
+    let mut prepared = Vec::with_capacity(attachments.len());
+    for attachment in attachments {
+        let (path, name) = match attachment {
+            Attachment::Path { path } => {
+                let resolved = sandbox::allow(app, path)?;
+                let size = std::fs::metadata(&resolved)
+                    .map_err(|e| EngineError::fs(path, e))?
+                    .len();
+                too_large(path, size)?;
+                total += size;
+                (resolved, None)
+            }
+            Attachment::Inline { data, mime, name } => {
+                let label = name.as_deref().unwrap_or("attachment");
+                too_large(label, data.len() as u64 / 4 * 3)?;
+                let bytes = base64::engine::general_purpose::STANDARD
+                    .decode(data.trim())
+                    .map_err(|e| {
+                        EngineError::invalid(format!("{} isn't valid base64: {}", label, e))
+                    })?;
+                total += bytes.len() as u64;
+                let file =
+                    temp_dir.join(format!("{}.{}", &sha256_hex(&bytes)[..16], extension(mime)));
+                std::fs::write(&file, &bytes).map_err(|e| EngineError::io(&file, e))?;
+                (file, name.clone())
+            }
+        };
+        if total > MAX_TOTAL_ATTACHMENT_BYTES {
+            return Err(EngineError::AttachmentsTooLarge {
+                total,
+                limit: MAX_TOTAL_ATTACHMENT_BYTES,
+            });
+        }
+        let request = AttachmentRequest {
+            path: path.display().to_string(),
+            mode: AttachmentMode::Auto,
+            skip_processing: false,
+        };
+        let mut attached = prepare(&request, &AttachmentOptions::default(), temp_dir)?;
+        if let Some(name) = name {
+            attached.name = name;
+        }
+        prepared.push(attached);
+    }
+    Ok(prepared)
+}
+
+impl AttachedTurn {
+    fn build(message: &str, prepared: Vec<PreparedAttachment>) -> Self {
+        let mut text = message.to_string();
+        let mut referenced = Vec::new();
+        let mut images = Vec::new();
+        for attachment in prepared {
+            if let Some(inline) = &attachment.inline_text {
+                text.push_str(&format!(
+                    "\n\n<attachment name=\"{}\">\n{}\n</attachment>",
+                    attachment.name, inline
+                ));
+                continue;
+            }
+            match attachment.image.and_then(|image| image.processed) {
+                Some(processed) => images.push(AttachedImage {
+                    path: PathBuf::from(processed.path),
+                    mime: processed.mime.to_string(),
+                }),
+                None if attachment.kind == AttachmentKind::Image => images.push(AttachedImage {
+                    path: PathBuf::from(&attachment.path),
+                    mime: attachment.mime.clone(),
+                }),
+                None => referenced.push(format!("{} ({})", attachment.path, attachment.name)),
+            }
+        }
+        if !referenced.is_empty() {
+            text.push_str("\n\nAttached files:");
+            for file in referenced {
+                text.push_str(&format!("\n- {}", file));
+            }
+        }
+        Self { text, images }
+    }
+
+    /// The prompt for the CLI, which reads images itself with its Read tool
+    pub fn cli_prompt(&self) -> String {
+        if self.images.is_empty() {
+            return self.text.clone();
+        }
+        let mut prompt = format!("{}\n\nAttached images:", self.text);
+        for image in &self.images {
+            prompt.push_str(&format!("\n- {}", image.path.display()));
+        }
+        prompt
+    }
+
+    /// The text half of the API turn; the images go in with [`add_images`](Self::add_images)
+    pub fn api_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Turn the last user turn's content into blocks with the images after
+    /// the text. Formats the API doesn't take are named by path instead.
+    pub fn add_images(&self, turns: &mut [Value]) -> Result<(), EngineError> {
+        if self.images.is_empty() {
+            return Ok(());
+        }
+        let Some(last) = turns.last_mut() else {
+            return Ok(());
+        };
+        let text = last["content"].as_str().unwrap_or_default().to_string();
+        let mut blocks = vec![json!({ "type": "text", "text": text })];
+        for image in &self.images {
+            if !API_IMAGE_MIMES.contains(&image.mime.as_str()) {
+                blocks[0]["text"] = json!(format!(
+                    "{}\n\nAttached image, in a format that can't be shown: {}",
+                    blocks[0]["text"].as_str().unwrap_or_default(),
+                    image.path.display()
+                ));
+                continue;
+            }
+            let bytes = std::fs::read(&image.path).map_err(|e| EngineError::fs(&image.path, e))?;
+            blocks.push(json!({
+                "type": "image",
+                "source": {
+                    "type": "base64",
+                    "media_type": image.mime,
+                    "data": base64::engine::general_purpose::STANDARD.encode(bytes),
+                },
+            }));
+        }
+        last["content"] = Value::Array(blocks);
+        Ok(())
+    }
+}
+
+/// `message` with `attachments` worked in. Paths must be inside the
+/// workspace unless the filesystem is unrestricted; each attachment is held
+/// to 20 MiB and the turn to 50 MiB.
+pub async fn attach(
+    app: &AppHandle,
+    message: &str,
+    attachments: Vec<Attachment>,
+) -> Result<AttachedTurn, EngineError> {
+    if attachments.is_empty() {
+        return Ok(AttachedTurn {
+            text: message.to_string(),
+            images: Vec::new(),
+        });
+    }
+    let temp_dir = app_cache_subdir(app, "attachments")?;
+    let app = app.clone();
+    let message = message.to_string();
+    tokio::task::spawn_blocking(move || {
+        let prepared = gather(&app, &attachments, &temp_dir)?;
+        Ok(AttachedTurn::build(&message, prepared))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+#[tauri::command]
+pub async fn prepare_attachments(
+    app: AppHandle,
+    attachments: Vec<AttachmentRequest>,
+    options: Option<AttachmentOptions>,
+) -> Result<Vec<PreparedAttachment>, EngineError> {
+    let options = options.unwrap_or_default();
+    let temp_dir = app_cache_subdir(&app, "attachments")?;
+    tokio::task::spawn_blocking(move || {
+        attachments
+            .iter()
+            .map(|a| prepare(a, &options, &temp_dir))
+            .collect()
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
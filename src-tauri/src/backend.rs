@@ -0,0 +1,602 @@
+use crate::cancel::CancelToken;
+use crate::claude::{
+    send_message_to_claude, stream_reply, CliSession, Reply, SpawnOptions, StreamSink,
+};
+use crate::cli_slots::{CliSlots, Slot};
+use crate::error::EngineError;
+use crate::hash;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, EngineError>> + Send + 'a>>;
+
+/// What runs a prompt on the CLI: the real one, or with the `mock-backend`
+/// feature a scripted stand-in the frontend can be developed against. A
+/// prompt routed to the API skips it, mock included. Cancelling is through
+/// the [`CancelToken`] a stream is given, as everywhere else.
+pub trait ClaudeBackend: Send + Sync {
+    /// One whole reply, with progress but not text going to `progress`
+    fn send<'a>(
+        &'a self,
+        message: &'a str,
+        progress: Arc<dyn StreamSink>,
+        spawn: SpawnOptions,
+    ) -> BackendFuture<'a, Reply>;
+
+    /// A reply streamed to `sink`, with the same events and ordering as
+    /// [`stream_reply`]: text, then exactly one of complete, error or cancelled
+    fn stream<'a>(
+        &'a self,
+        sink: Arc<dyn StreamSink>,
+        message: String,
+        cancel: CancelToken,
+        spawn: SpawnOptions,
+    ) -> BackendFuture<'a, Reply>;
+
+    /// Whether a conversation may keep a CLI process running between turns;
+    /// see [`crate::session`]. A stand-in has none to keep.
+    fn warm_sessions(&self) -> bool {
+        false
+    }
+}
+
+/// The Claude CLI, found and spawned per message
+pub struct CliBackend;
+
+impl ClaudeBackend for CliBackend {
+    fn send<'a>(
+        &'a self,
+        message: &'a str,
+        progress: Arc<dyn StreamSink>,
+        spawn: SpawnOptions,
+    ) -> BackendFuture<'a, Reply> {
+        Box::pin(send_message_to_claude(message, progress, spawn))
+    }
+
+    fn stream<'a>(
+        &'a self,
+        sink: Arc<dyn StreamSink>,
+        message: String,
+        cancel: CancelToken,
+        spawn: SpawnOptions,
+    ) -> BackendFuture<'a, Reply> {
+        Box::pin(stream_reply(sink, message, cancel, spawn))
+    }
+
+    fn warm_sessions(&self) -> bool {
+        true
+    }
+}
+
+/// Managed backend every prompt the app runs on the CLI goes through: the
+/// chat commands, the local HTTP API, pipelines, batches, schedules and the
+/// rest. Each run holds one of its [`CliSlots`] for as long as it takes.
+/// Prompts [`crate::api::route`] sends to the API don't come here; they go to
+/// [`crate::api::send`] and [`crate::api::stream`] directly and take no slot.
+pub struct ActiveBackend {
+    backend: Box<dyn ClaudeBackend>,
+    slots: CliSlots,
+}
+
+impl Default for ActiveBackend {
+    fn default() -> Self {
+        #[cfg(feature = "mock-backend")]
+        if let Some(mock) = mock::MockBackend::from_env() {
+            return Self::new(Box::new(mock));
+        }
+        Self::new(Box::new(CliBackend))
+    }
+}
+
+impl ActiveBackend {
+    fn new(backend: Box<dyn ClaudeBackend>) -> Self {
+        Self {
+            backend,
+            slots: CliSlots::default(),
+        }
+    }
+
+    pub fn slots(&self) -> &CliSlots {
+        &self.slots
+    }
+
+    /// Wait for a CLI slot for the run `spawn` describes, as the sends and
+    /// streams here do; for a caller running the CLI itself, like a warm session
+    pub async fn slot(
+        &self,
+        spawn: &SpawnOptions,
+        sink: &dyn StreamSink,
+        cancel: &CancelToken,
+    ) -> Result<Slot<'_>, EngineError> {
+        let request_id = spawn
+            .request_id
+            .clone()
+            .unwrap_or_else(|| format!("run-{}", hash::random_hex(6)));
+        let session_id = match &spawn.session {
+            CliSession::Resume(id) => Some(id.clone()),
+            _ => None,
+        };
+        self.slots
+            .acquire(
+                spawn.engine.max_concurrent_cli,
+                &request_id,
+                session_id,
+                spawn.reject_if_busy,
+                sink,
+                cancel,
+            )
+            .await
+    }
+
+    pub async fn send(
+        &self,
+        message: &str,
+        progress: Arc<dyn StreamSink>,
+        spawn: SpawnOptions,
+    ) -> Result<Reply, EngineError> {
+        let slot = self
+            .slot(&spawn, progress.as_ref(), &CancelToken::default())
+            .await?;
+        let progress = slot.meter(progress);
+        self.backend.send(message, progress, spawn).await
+    }
+
+    pub async fn stream(
+        &self,
+        sink: Arc<dyn StreamSink>,
+        message: String,
+        cancel: CancelToken,
+        spawn: SpawnOptions,
+    ) -> Result<Reply, EngineError> {
+        let slot = self.slot(&spawn, sink.as_ref(), &cancel).await?;
+        let sink = slot.meter(sink);
+        self.stream_in_slot(&slot, sink, message, cancel, spawn)
+            .await
+    }
+
+    /// [`Self::stream`] under a slot the caller already holds, with `sink`
+    /// metered by it
+    pub async fn stream_in_slot(
+        &self,
+        _slot: &Slot<'_>,
+        sink: Arc<dyn StreamSink>,
+        message: String,
+        cancel: CancelToken,
+        spawn: SpawnOptions,
+    ) -> Result<Reply, EngineError> {
+        self.backend.stream(sink, message, cancel, spawn).await
+    }
+
+    pub fn warm_sessions(&self) -> bool {
+        self.backend.warm_sessions()
+    }
+}
+
+#[cfg(any(test, feature = "mock-backend"))]
+mod mock {
+    use super::{BackendFuture, ClaudeBackend};
+    use crate::cancel::CancelToken;
+    use crate::claude::{
+        emit_started, emit_stats, emit_text, finish, first_token, Reply, SpawnOptions, StreamSink,
+        Utf8Decoder,
+    };
+    use crate::error::EngineError;
+    use crate::metrics::Trace;
+    use crate::spill::Spilled;
+    use serde::Deserialize;
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    /// What the mock says and how, read from the JSON file named by
+    /// `BUPS_MOCK_SCRIPT`. A file that can't be read or parsed gets a short
+    /// canned reply instead.
+    #[derive(Debug, Clone, Deserialize)]
+    #[serde(default)]
+    pub struct MockBackend {
+        pub chunks: Vec<String>,
+        /// Between chunks
+        pub delay_ms: u64,
+        /// Fail with this once this many chunks are out
+        pub fail_after: Option<usize>,
+        pub error: String,
+        /// Send the reply as reads of this many bytes, cutting through
+        /// characters the way pipe reads do, instead of chunk by chunk
+        pub split_bytes: Option<usize>,
+    }
+
+    impl Default for MockBackend {
+        fn default() -> Self {
+            Self {
+                chunks: [
+                    "This is ",
+                    "a scripted ",
+                    "reply from the ",
+                    "mock backend — ",
+                    "no CLI was run.",
+                ]
+                .map(String::from)
+                .to_vec(),
+                delay_ms: 80,
+                fail_after: None,
+                error: "Mock backend failure".to_string(),
+                split_bytes: None,
+            }
+        }
+    }
+
+    impl MockBackend {
+        /// None without `BUPS_MOCK_SCRIPT`, so the CLI still runs
+        #[cfg(feature = "mock-backend")]
+        pub fn from_env() -> Option<Self> {
+            let path = std::env::var_os("BUPS_MOCK_SCRIPT")?;
+            let script = std::fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| serde_json::from_slice(&data).map_err(|e| e.to_string()));
+            Some(script.unwrap_or_else(|e| {
+                tracing::warn!(error = %e, "Mock script unusable; using the canned reply");
+                Self::default()
+            }))
+        }
+
+        fn reads(&self) -> Vec<Vec<u8>> {
+            match self.split_bytes {
+                Some(size) => self
+                    .chunks
+                    .concat()
+                    .into_bytes()
+                    .chunks(size.max(1))
+                    .map(<[u8]>::to_vec)
+                    .collect(),
+                None => self.chunks.iter().map(|c| c.clone().into_bytes()).collect(),
+            }
+        }
+
+        fn failure(&self) -> EngineError {
+            EngineError::CliExited {
+                code: Some(1),
+                stderr: self.error.clone(),
+            }
+        }
+
+        async fn run(
+            &self,
+            sink: Arc<dyn StreamSink>,
+            cancel: CancelToken,
+            spawn: SpawnOptions,
+        ) -> Result<Reply, EngineError> {
+            let started = Instant::now();
+            let mut trace = Trace::new("mock");
+            let mut first_chunk_ms = None;
+            let mut text = String::new();
+            let mut decoder = Utf8Decoder::default();
+            emit_started(sink.as_ref(), "mock", started);
+            for (sent, read) in self.reads().iter().enumerate() {
+                if self.fail_after == Some(sent) {
+                    let _ = sink.emit("claude-stream-error", self.error.as_str().into());
+                    return Err(self.failure());
+                }
+                tokio::time::sleep(Duration::from_millis(self.delay_ms)).await;
+                if cancel.is_cancelled() {
+                    let _ = sink.emit("claude-stream-cancelled", serde_json::Value::Null);
+                    return Err(EngineError::Cancelled);
+                }
+                let chunk = decoder.push(read);
+                if chunk.is_empty() {
+                    continue;
+                }
+                first_token(sink.as_ref(), &mut first_chunk_ms, started);
+                emit_text(sink.as_ref(), &chunk)?;
+                text.push_str(&chunk);
+            }
+            let rest = decoder.finish();
+            if !rest.is_empty() {
+                emit_text(sink.as_ref(), &rest)?;
+                text.push_str(&rest);
+            }
+            let metrics = emit_stats(
+                sink.as_ref(),
+                "mock",
+                first_chunk_ms,
+                started,
+                &text,
+                &mut trace,
+            );
+            let (text, payload) = finish(&spawn, text, None, None, &metrics, &mut trace);
+            sink.emit("claude-stream-complete", payload)?;
+            Ok(Reply {
+                text,
+                session_id: None,
+                usage: None,
+                metrics: Some(metrics),
+                spilled: Spilled::default(),
+            })
+        }
+    }
+
+    impl ClaudeBackend for MockBackend {
+        fn send<'a>(
+            &'a self,
+            _message: &'a str,
+            _progress: Arc<dyn StreamSink>,
+            _spawn: SpawnOptions,
+        ) -> BackendFuture<'a, Reply> {
+            Box::pin(async move {
+                if self.fail_after.is_some() {
+                    return Err(self.failure());
+                }
+                Ok(Reply {
+                    text: self.chunks.concat(),
+                    session_id: None,
+                    usage: None,
+                    metrics: None,
+                    spilled: Spilled::default(),
+                })
+            })
+        }
+
+        fn stream<'a>(
+            &'a self,
+            sink: Arc<dyn StreamSink>,
+            _message: String,
+            cancel: CancelToken,
+            spawn: SpawnOptions,
+        ) -> BackendFuture<'a, Reply> {
+            Box::pin(self.run(sink, cancel, spawn))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::MockBackend;
+    use super::*;
+    use crate::cancel::CancelRegistry;
+    use serde_json::Value;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    /// Every event a stream emits, in order; optionally cancels `request_id`
+    /// once `cancel_at` chunks are out
+    #[derive(Default)]
+    struct Recorder {
+        events: Mutex<Vec<(String, Value)>>,
+        cancel_at: Option<(usize, CancelRegistry, String)>,
+    }
+
+    impl Recorder {
+        fn names(&self) -> Vec<String> {
+            let events = self.events.lock().unwrap();
+            events.iter().map(|(name, _)| name.clone()).collect()
+        }
+
+        fn chunks(&self) -> Vec<String> {
+            let events = self.events.lock().unwrap();
+            events
+                .iter()
+                .filter(|(name, _)| name == "claude-stream-chunk")
+                .map(|(_, payload)| payload.as_str().unwrap().to_string())
+                .collect()
+        }
+
+        fn count(&self, name: &str) -> usize {
+            self.names().iter().filter(|n| *n == name).count()
+        }
+    }
+
+    impl StreamSink for Recorder {
+        fn emit(&self, event: &str, payload: Value) -> Result<(), String> {
+            let mut events = self.events.lock().unwrap();
+            events.push((event.to_string(), payload));
+            if let Some((at, registry, request_id)) = &self.cancel_at {
+                let chunks = events
+                    .iter()
+                    .filter(|(name, _)| name == "claude-stream-chunk")
+                    .count();
+                if chunks == *at {
+                    registry.cancel(request_id);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn backend(chunks: &[&str]) -> MockBackend {
+        MockBackend {
+            chunks: chunks.iter().map(|c| c.to_string()).collect(),
+            delay_ms: 0,
+            ..MockBackend::default()
+        }
+    }
+
+    async fn stream(
+        mock: MockBackend,
+        sink: Arc<Recorder>,
+        cancel: CancelToken,
+    ) -> Result<Reply, EngineError> {
+        ActiveBackend::new(Box::new(mock))
+            .stream(sink, "hi".to_string(), cancel, SpawnOptions::default())
+            .await
+    }
+
+    #[tokio::test]
+    async fn complete_comes_last_with_the_streamed_text() {
+        let sink = Arc::new(Recorder::default());
+        let reply = stream(
+            backend(&["one ", "two ", "three"]),
+            sink.clone(),
+            CancelToken::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reply.text, "one two three");
+        assert_eq!(sink.chunks().concat(), reply.text);
+        let names = sink.names();
+        assert_eq!(names.first().unwrap(), "claude-stream-started");
+        assert_eq!(names.last().unwrap(), "claude-stream-complete");
+        assert_eq!(sink.count("claude-stream-complete"), 1);
+        assert_eq!(sink.count("claude-stream-error"), 0);
+        assert_eq!(sink.count("claude-stream-cancelled"), 0);
+        let events = sink.events.lock().unwrap();
+        assert_eq!(events.last().unwrap().1["text"], "one two three");
+    }
+
+    #[tokio::test]
+    async fn an_error_mid_stream_ends_it_without_completing() {
+        let mock = MockBackend {
+            fail_after: Some(2),
+            error: "overloaded".to_string(),
+            ..backend(&["a", "b", "c", "d"])
+        };
+        let sink = Arc::new(Recorder::default());
+        let error = stream(mock, sink.clone(), CancelToken::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EngineError::CliExited { ref stderr, .. } if stderr == "overloaded"
+        ));
+        assert_eq!(sink.chunks(), ["a", "b"]);
+        assert_eq!(sink.names().last().unwrap(), "claude-stream-error");
+        assert_eq!(sink.count("claude-stream-error"), 1);
+        assert_eq!(sink.count("claude-stream-complete"), 0);
+    }
+
+    #[tokio::test]
+    async fn cancelling_mid_stream_stops_the_chunks() {
+        let registry = CancelRegistry::default();
+        let guard = registry.register("r1");
+        let sink = Arc::new(Recorder {
+            cancel_at: Some((1, registry.clone(), "r1".to_string())),
+            ..Recorder::default()
+        });
+        let error = stream(backend(&["a", "b", "c"]), sink.clone(), guard.token())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EngineError::Cancelled));
+        assert_eq!(sink.chunks(), ["a"]);
+        assert_eq!(sink.names().last().unwrap(), "claude-stream-cancelled");
+        assert_eq!(sink.count("claude-stream-cancelled"), 1);
+        assert_eq!(sink.count("claude-stream-complete"), 0);
+        assert_eq!(sink.count("claude-stream-error"), 0);
+    }
+
+    #[tokio::test]
+    async fn reads_cut_through_characters_still_decode_whole() {
+        let text = "héllo 👋🏽 世界 🎉 done";
+        for size in 1..=8 {
+            let mock = MockBackend {
+                split_bytes: Some(size),
+                ..backend(&[text])
+            };
+            let sink = Arc::new(Recorder::default());
+            let reply = stream(mock, sink.clone(), CancelToken::default())
+                .await
+                .unwrap();
+            assert_eq!(reply.text, text, "reads of {} bytes", size);
+            assert_eq!(sink.chunks().concat(), text, "reads of {} bytes", size);
+            assert!(sink.chunks().iter().all(|c| !c.contains('\u{FFFD}')));
+        }
+    }
+
+    #[tokio::test]
+    async fn every_run_holds_a_slot_until_it_ends() {
+        let active = Arc::new(ActiveBackend::new(Box::new(MockBackend {
+            delay_ms: 50,
+            ..backend(&["a", "b", "c"])
+        })));
+        let mut spawn = SpawnOptions::default();
+        spawn.engine.max_concurrent_cli = 1;
+        spawn.request_id = Some("first".to_string());
+        let running = tokio::spawn({
+            let active = active.clone();
+            let spawn = spawn.clone();
+            async move {
+                let sink = Arc::new(Recorder::default());
+                active
+                    .stream(sink, "hi".to_string(), CancelToken::default(), spawn)
+                    .await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let listed = active.slots().list();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].request_id, "first");
+
+        spawn.request_id = Some("second".to_string());
+        spawn.reject_if_busy = true;
+        let error = active
+            .send("hi", Arc::new(Recorder::default()), spawn)
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error,
+            EngineError::TooManyRequests { limit: 1, .. }
+        ));
+
+        running.await.unwrap().unwrap();
+        assert!(active.slots().list().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send_returns_the_reply_or_the_error() {
+        let active = ActiveBackend::new(Box::new(backend(&["whole ", "reply"])));
+        let reply = active
+            .send("hi", Arc::new(Recorder::default()), SpawnOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(reply.text, "whole reply");
+
+        let failing = ActiveBackend::new(Box::new(MockBackend {
+            fail_after: Some(0),
+            ..backend(&["x"])
+        }));
+        let error = failing
+            .send("hi", Arc::new(Recorder::default()), SpawnOptions::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EngineError::CliExited { .. }));
+    }
+
+    #[tokio::test]
+    async fn a_cancelled_stream_leaves_the_next_one_alone() {
+        let active = ActiveBackend::new(Box::new(backend(&["a", "b", "c"])));
+        let registry = CancelRegistry::default();
+
+        let guard = registry.register("r1");
+        let sink = Arc::new(Recorder {
+            cancel_at: Some((1, registry.clone(), "r1".to_string())),
+            ..Recorder::default()
+        });
+        let error = active
+            .stream(
+                sink,
+                "hi".to_string(),
+                guard.token(),
+                SpawnOptions::default(),
+            )
+            .await
+            .unwrap_err();
+        assert!(matches!(error, EngineError::Cancelled));
+        drop(guard);
+        // A second click on Stop lands after the stream is over
+        assert!(!registry.cancel("r1"));
+
+        let guard = registry.register("r1");
+        let sink = Arc::new(Recorder::default());
+        let reply = active
+            .stream(
+                sink.clone(),
+                "hi".to_string(),
+                guard.token(),
+                SpawnOptions::default(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(reply.text, "abc");
+        assert_eq!(sink.chunks(), ["a", "b", "c"]);
+        assert_eq!(sink.names().last().unwrap(), "claude-stream-complete");
+        assert_eq!(sink.count("claude-stream-cancelled"), 0);
+        assert!(active.slots().list().is_empty());
+    }
+}
@@ -0,0 +1,422 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::claude::NullSink;
+use crate::context::glob_files;
+use crate::diff::{apply_unified, diff_text, TextDiff, DEFAULT_CONTEXT_LINES};
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::{atomic_write, looks_binary};
+use crate::hash::sha256_hex;
+use crate::postprocess::first_code_block;
+use crate::sandbox::Workspace;
+use crate::template_vars::{self, ExpandOptions};
+use crate::templates;
+use crate::tokens::count_tokens;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tauri::{AppHandle, Manager, State, Window};
+use tokio::task::JoinSet;
+
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+const MAX_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WriteMode {
+    /// Only collect the responses
+    #[default]
+    None,
+    /// The response (its first code block, if any) becomes the file
+    ReplaceFile,
+    /// The response is a unified diff against the file
+    Patch,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct BatchOptions {
+    /// Relative to `workspace`
+    pub file_glob: Option<String>,
+    pub paths: Vec<String>,
+    /// Root for the glob and template providers; the first workspace root otherwise
+    pub workspace: Option<String>,
+    /// Name of a saved template; `{{file}}` and `{{path}}` are filled per file
+    pub template: String,
+    pub vars: BTreeMap<String, String>,
+    pub model: Option<String>,
+    pub concurrency: usize,
+    /// Compute diffs without writing
+    pub dry_run: bool,
+    pub write_mode: WriteMode,
+    pub max_files: usize,
+    /// Stop scheduling once reported cost passes this
+    pub budget_usd: Option<f64>,
+    /// Stop scheduling once prompt plus response tokens pass this
+    pub max_total_tokens: Option<usize>,
+    /// On cancel, kill requests already running instead of letting them finish
+    pub kill_in_flight: bool,
+}
+
+impl Default for BatchOptions {
+    fn default() -> Self {
+        Self {
+            file_glob: None,
+            paths: Vec::new(),
+            workspace: None,
+            template: String::new(),
+            vars: BTreeMap::new(),
+            model: None,
+            concurrency: 2,
+            dry_run: false,
+            write_mode: WriteMode::None,
+            max_files: 200,
+            budget_usd: None,
+            max_total_tokens: None,
+            kill_in_flight: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FileStatus {
+    Succeeded,
+    Failed,
+    Skipped,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchFileResult {
+    pub path: String,
+    pub status: FileStatus,
+    pub output: Option<String>,
+    /// What was (or, in a dry run, would be) written
+    pub diff: Option<TextDiff>,
+    pub backup_path: Option<String>,
+    pub error: Option<String>,
+    pub tokens: usize,
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct BatchSummary {
+    pub request_id: String,
+    pub succeeded: usize,
+    pub failed: usize,
+    pub skipped: usize,
+    pub total_tokens: usize,
+    pub total_cost_usd: Option<f64>,
+    /// Why the batch ended early: "cancelled", "budget" or "token_limit"
+    pub stopped: Option<&'static str>,
+    pub files: Vec<BatchFileResult>,
+}
+
+struct Job {
+    app: AppHandle,
+    root: PathBuf,
+    rel: String,
+    path: PathBuf,
+    body: Arc<String>,
+    options: Arc<BatchOptions>,
+    token: CancelToken,
+}
+
+impl Job {
+    fn result(&self, status: FileStatus) -> BatchFileResult {
+        BatchFileResult {
+            path: self.rel.clone(),
+            status,
+            output: None,
+            diff: None,
+            backup_path: None,
+            error: None,
+            tokens: 0,
+            cost_usd: None,
+        }
+    }
+}
+
+fn read_text(path: &Path) -> Result<String, &'static str> {
+    let size = std::fs::metadata(path).map(|m| m.len()).unwrap_or(0);
+    if size > MAX_FILE_BYTES {
+        return Err("larger than 1 MB");
+    }
+    let bytes = std::fs::read(path).map_err(|_| "unreadable")?;
+    if looks_binary(&bytes) {
+        return Err("binary");
+    }
+    String::from_utf8(bytes).map_err(|_| "not UTF-8")
+}
+
+async fn run_job(job: Job) -> BatchFileResult {
+    let current = match read_text(&job.path) {
+        Ok(text) => text,
+        Err(reason) => {
+            let mut result = job.result(FileStatus::Skipped);
+            result.error = Some(format!("Skipped: {}", reason));
+            return result;
+        }
+    };
+    let mut result = job.result(FileStatus::Failed);
+    match process(&job, &current, &mut result).await {
+        Ok(()) => result.status = FileStatus::Succeeded,
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
+
+async fn process(
+    job: &Job,
+    current: &str,
+    result: &mut BatchFileResult,
+) -> Result<(), EngineError> {
+    let mut vars = job.options.vars.clone();
+    vars.insert("file".to_string(), current.to_string());
+    vars.insert("path".to_string(), job.rel.clone());
+    let expand_options = ExpandOptions {
+        workspace: Some(job.root.display().to_string()),
+        ..ExpandOptions::default()
+    };
+    let prompt = template_vars::expand(&job.app, &job.body, &vars, &expand_options)
+        .await?
+        .prompt;
+
+    let mut spawn = env_profile::spawn_options(&job.app, Some(job.root.clone()))?;
+    spawn.model = job.options.model.clone();
+    result.tokens = count_tokens(&prompt);
+    let output = match job
+        .app
+        .state::<ActiveBackend>()
+        .stream(Arc::new(NullSink), prompt, job.token.clone(), spawn)
+        .await
+    {
+        Ok(reply) => reply.text,
+        Err(_) if job.token.is_cancelled() => return Err(EngineError::Cancelled),
+        Err(e) => return Err(e),
+    };
+    result.tokens += count_tokens(&output);
+
+    let updated = match job.options.write_mode {
+        WriteMode::None => None,
+        WriteMode::ReplaceFile => Some(first_code_block(&output).unwrap_or_else(|| output.clone())),
+        WriteMode::Patch => Some(
+            apply_unified(
+                current,
+                &first_code_block(&output).unwrap_or_else(|| output.clone()),
+            )
+            .map_err(|e| EngineError::invalid(e.to_string()))?,
+        ),
+    };
+    result.output = Some(output);
+    let Some(updated) = updated else {
+        return Ok(());
+    };
+
+    let diff = diff_text(current, &updated, DEFAULT_CONTEXT_LINES, &job.rel);
+    if !job.options.dry_run && diff.additions + diff.deletions > 0 {
+        let path = job.path.clone();
+        let base_hash = sha256_hex(current.as_bytes());
+        let backup = tokio::task::spawn_blocking(move || {
+            // Same rule as confirm_apply_block: never overwrite edits made meanwhile
+            let latest = std::fs::read(&path).map_err(|e| EngineError::io(&path, e))?;
+            let actual = sha256_hex(&latest);
+            if actual != base_hash {
+                return Err(EngineError::Conflict {
+                    path: path.display().to_string(),
+                    expected: Some(base_hash),
+                    actual: Some(actual),
+                });
+            }
+            atomic_write(&path, updated.as_bytes(), true).map_err(|e| EngineError::io(&path, e))
+        })
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+        result.backup_path = backup.map(|p| p.display().to_string());
+    }
+    result.diff = Some(diff);
+    Ok(())
+}
+
+fn select_files(
+    root: &Path,
+    options: &BatchOptions,
+    workspace: &Workspace,
+) -> Result<Vec<(String, PathBuf)>, EngineError> {
+    let mut files = match &options.file_glob {
+        Some(pattern) => glob_files(root, pattern)?,
+        None => Vec::new(),
+    };
+    for raw in &options.paths {
+        let path = workspace.check(&root.join(raw))?;
+        let rel = path
+            .strip_prefix(root)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .replace('\\', "/");
+        if !files.iter().any(|(_, p)| *p == path) {
+            files.push((rel, path));
+        }
+    }
+    if files.is_empty() {
+        return Err(EngineError::invalid("No files selected for the batch"));
+    }
+    if files.len() > options.max_files {
+        return Err(EngineError::invalid(format!(
+            "{} files selected; the limit is {}",
+            files.len(),
+            options.max_files
+        )));
+    }
+    Ok(files)
+}
+
+/// Run one template over many files with bounded concurrency, optionally
+/// writing the results back with backups
+#[tauri::command]
+pub async fn run_batch(
+    window: Window,
+    request_id: String,
+    options: BatchOptions,
+    registry: State<'_, CancelRegistry>,
+    workspace: State<'_, Workspace>,
+) -> Result<BatchSummary, EngineError> {
+    let app = window.app_handle();
+    let root = match &options.workspace {
+        Some(root) => workspace.check(Path::new(root))?,
+        None => workspace
+            .roots()
+            .into_iter()
+            .next()
+            .ok_or_else(|| EngineError::invalid("No workspace is open"))?,
+    };
+    let body = Arc::new(templates::load(&app, &options.template)?);
+    let files = {
+        let root = root.clone();
+        let options = options.clone();
+        let app = app.clone();
+        tokio::task::spawn_blocking(move || {
+            select_files(&root, &options, &app.state::<Workspace>())
+        })
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??
+    };
+
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+    // In-flight requests only see the batch token when they should die with it
+    let job_token = if options.kill_in_flight {
+        token.clone()
+    } else {
+        CancelToken::default()
+    };
+    let concurrency = options.concurrency.clamp(1, MAX_CONCURRENCY);
+    let options = Arc::new(options);
+    let total = files.len();
+    let progress = |path: &str, index: usize, status: &str, error: Option<&str>| {
+        let _ = window.emit(
+            "batch-progress",
+            json!({
+                "request_id": request_id,
+                "path": path,
+                "index": index,
+                "total": total,
+                "status": status,
+                "error": error,
+            }),
+        );
+    };
+
+    let mut summary = BatchSummary {
+        request_id: request_id.clone(),
+        succeeded: 0,
+        failed: 0,
+        skipped: 0,
+        total_tokens: 0,
+        total_cost_usd: None,
+        stopped: None,
+        files: Vec::with_capacity(total),
+    };
+    let mut pending = files.into_iter().enumerate();
+    let mut running: JoinSet<(usize, BatchFileResult)> = JoinSet::new();
+    loop {
+        if summary.stopped.is_none() {
+            if token.is_cancelled() {
+                summary.stopped = Some("cancelled");
+            } else if options
+                .budget_usd
+                .zip(summary.total_cost_usd)
+                .is_some_and(|(cap, spent)| spent >= cap)
+            {
+                summary.stopped = Some("budget");
+            } else if options
+                .max_total_tokens
+                .is_some_and(|cap| summary.total_tokens >= cap)
+            {
+                summary.stopped = Some("token_limit");
+            }
+        }
+        while summary.stopped.is_none() && running.len() < concurrency {
+            let Some((index, (rel, path))) = pending.next() else {
+                break;
+            };
+            progress(&rel, index, "started", None);
+            let job = Job {
+                app: app.clone(),
+                root: root.clone(),
+                rel,
+                path,
+                body: Arc::clone(&body),
+                options: Arc::clone(&options),
+                token: job_token.clone(),
+            };
+            running.spawn(async move { (index, run_job(job).await) });
+        }
+
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (index, result) =
+            joined.map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+        let status = match result.status {
+            FileStatus::Succeeded => {
+                summary.succeeded += 1;
+                "succeeded"
+            }
+            FileStatus::Failed => {
+                summary.failed += 1;
+                "failed"
+            }
+            FileStatus::Skipped => {
+                summary.skipped += 1;
+                "skipped"
+            }
+        };
+        progress(&result.path, index, status, result.error.as_deref());
+        summary.total_tokens += result.tokens;
+        if let Some(cost) = result.cost_usd {
+            *summary.total_cost_usd.get_or_insert(0.0) += cost;
+        }
+        summary.files.push(result);
+    }
+
+    // Files never started because the batch stopped early
+    for (_, (rel, _)) in pending {
+        summary.skipped += 1;
+        summary.files.push(BatchFileResult {
+            path: rel,
+            status: FileStatus::Skipped,
+            output: None,
+            diff: None,
+            backup_path: None,
+            error: summary
+                .stopped
+                .map(|reason| format!("Not started: {}", reason)),
+            tokens: 0,
+            cost_usd: None,
+        });
+    }
+    Ok(summary)
+}
@@ -0,0 +1,360 @@
+use crate::clock::{millis, now_millis};
+use crate::context::SNAPSHOT_DIR;
+use crate::error::EngineError;
+use crate::history::{Conversation, HISTORY_DIR};
+use crate::index::INDEX_DIR;
+use crate::pipelines::PIPELINES_DIR;
+use crate::project_context::CONTEXT_DIR;
+use crate::settings::{AppSettings, SETTINGS_FILE};
+use crate::spill::SPILL_DIR;
+use crate::templates::TEMPLATES_DIR;
+use crate::usage_ledger::LEDGER_DIR;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use tauri::api::path::{app_cache_dir, app_config_dir, app_data_dir, app_log_dir};
+use tauri::{Config, State};
+
+/// Bumped with each change to where things are kept, with a step in
+/// [`MIGRATIONS`] that moves an older layout over
+const LAYOUT_VERSION: u32 = 1;
+const LAYOUT_MARKER: &str = "layout-version";
+// Younger temp files may belong to a write still in progress
+const STALE_TEMP_MS: u64 = 60 * 60 * 1000;
+
+const CONFIG_DIRS: &[&str] = &[TEMPLATES_DIR, PIPELINES_DIR];
+const DATA_DIRS: &[&str] = &[
+    HISTORY_DIR,
+    SNAPSHOT_DIR,
+    CONTEXT_DIR,
+    LEDGER_DIR,
+    "scheduler",
+    "workspaces",
+];
+const CACHE_DIRS: &[&str] = &[INDEX_DIR, "attachments"];
+
+type Migration = fn(&Dirs) -> io::Result<()>;
+
+/// `(version it brings the layout to, step)`, oldest first
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+/// The app's base directories, each None when the platform has no such place
+struct Dirs {
+    config: Option<PathBuf>,
+    data: Option<PathBuf>,
+    cache: Option<PathBuf>,
+    logs: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueKind {
+    /// A directory couldn't be created; what's kept there won't be saved
+    Unwritable,
+    /// A file couldn't be parsed and was set aside
+    Corrupt,
+    Migration,
+    Cleanup,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BootstrapIssue {
+    pub kind: IssueKind,
+    pub path: String,
+    /// A sentence the UI can show as is
+    pub message: String,
+}
+
+/// What startup found and fixed in the app's directories
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BootstrapReport {
+    pub layout_version: u32,
+    /// Set when an older layout was moved over this run
+    pub migrated_from: Option<u32>,
+    /// Stale temp and spill files removed
+    pub cleaned: usize,
+    pub issues: Vec<BootstrapIssue>,
+    pub duration_ms: u64,
+}
+
+impl BootstrapReport {
+    fn issue(&mut self, kind: IssueKind, path: &Path, message: String) {
+        self.issues.push(BootstrapIssue {
+            kind,
+            path: path.display().to_string(),
+            message,
+        });
+    }
+
+    /// Issues only reach the log once logging is up, after `run`
+    pub fn log(&self) {
+        for issue in &self.issues {
+            tracing::warn!(kind = ?issue.kind, path = %issue.path, "{}", issue.message);
+        }
+    }
+}
+
+fn ensure(report: &mut BootstrapReport, base: Option<&Path>, subs: &[&str], what: &str) {
+    let Some(base) = base else {
+        report.issues.push(BootstrapIssue {
+            kind: IssueKind::Unwritable,
+            path: String::new(),
+            message: format!("This system has no app {} directory", what),
+        });
+        return;
+    };
+    if let Err(e) = fs::create_dir_all(base) {
+        report.issue(
+            IssueKind::Unwritable,
+            base,
+            format!("The app {} directory can't be created: {}", what, e),
+        );
+        return;
+    }
+    for sub in subs {
+        let dir = base.join(sub);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            report.issue(
+                IssueKind::Unwritable,
+                &dir,
+                format!(
+                    "{} can't be created, so nothing kept there will be saved: {}",
+                    dir.display(),
+                    e
+                ),
+            );
+        }
+    }
+}
+
+fn read_marker(path: &Path) -> io::Result<Option<u32>> {
+    match fs::read_to_string(path) {
+        Ok(text) => text
+            .trim()
+            .parse()
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Bring an older layout up to [`LAYOUT_VERSION`], one step at a time. A
+/// step that fails leaves the marker where it got to, so the rest are tried
+/// again next start.
+fn migrate(report: &mut BootstrapReport, dirs: &Dirs) {
+    let Some(data) = &dirs.data else {
+        return;
+    };
+    let marker = data.join(LAYOUT_MARKER);
+    let (found, marked) = match read_marker(&marker) {
+        Ok(Some(version)) => (version, true),
+        // A new install, or one from before the marker, which used the
+        // layout the marker started at
+        Ok(None) => (1, false),
+        Err(e) => {
+            report.issue(
+                IssueKind::Migration,
+                &marker,
+                format!(
+                    "The layout marker can't be read ({}); no migration was attempted",
+                    e
+                ),
+            );
+            return;
+        }
+    };
+    report.layout_version = found;
+    if found > LAYOUT_VERSION {
+        report.issue(
+            IssueKind::Migration,
+            &marker,
+            format!(
+                "The app data was written by a newer version (layout {}); it's left as it is",
+                found
+            ),
+        );
+        return;
+    }
+    for (to, step) in MIGRATIONS.iter().filter(|(to, _)| *to > found) {
+        if let Err(e) = step(dirs) {
+            report.issue(
+                IssueKind::Migration,
+                data,
+                format!("Moving the app data to layout {} failed: {}", to, e),
+            );
+            break;
+        }
+        report.layout_version = *to;
+    }
+    if report.layout_version != found {
+        report.migrated_from = Some(found);
+    } else if marked {
+        return;
+    }
+    if let Err(e) = fs::write(&marker, report.layout_version.to_string()) {
+        report.issue(
+            IssueKind::Migration,
+            &marker,
+            format!("The layout marker can't be written: {}", e),
+        );
+    }
+}
+
+/// Where a corrupt file is moved: `X.corrupt`, or with a timestamp when an
+/// earlier one is already there
+fn corrupt_path(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let moved = path.with_file_name(format!("{}.corrupt", name));
+    if moved.exists() {
+        path.with_file_name(format!("{}.{}.corrupt", name, now_millis()))
+    } else {
+        moved
+    }
+}
+
+/// Set `path` aside if it doesn't parse as `T`, so it's neither lost when
+/// the defaults are saved over it nor failing every read
+fn quarantine<T: DeserializeOwned>(report: &mut BootstrapReport, path: &Path) {
+    let Ok(data) = fs::read(path) else {
+        return;
+    };
+    let Err(e) = serde_json::from_slice::<T>(&data) else {
+        return;
+    };
+    let moved = corrupt_path(path);
+    let message = match fs::rename(path, &moved) {
+        Ok(()) => format!(
+            "{} was corrupt ({}) and has been moved to {}",
+            path.display(),
+            e,
+            moved.display()
+        ),
+        Err(move_error) => format!(
+            "{} is corrupt ({}) and couldn't be moved aside: {}",
+            path.display(),
+            e,
+            move_error
+        ),
+    };
+    report.issue(IssueKind::Corrupt, path, message);
+}
+
+fn check_files(report: &mut BootstrapReport, dirs: &Dirs) {
+    if let Some(config) = &dirs.config {
+        let settings = config.join(SETTINGS_FILE);
+        if settings.exists() {
+            quarantine::<AppSettings>(report, &settings);
+        }
+    }
+    let Some(history) = dirs.data.as_ref().map(|d| d.join(HISTORY_DIR)) else {
+        return;
+    };
+    let Ok(entries) = fs::read_dir(&history) else {
+        return;
+    };
+    for path in entries.flatten().map(|e| e.path()) {
+        if path.extension().is_some_and(|ext| ext == "json") {
+            quarantine::<Conversation>(report, &path);
+        }
+    }
+}
+
+fn is_stale_temp(path: &Path, now: u64) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    if !(name.starts_with('.') && name.ends_with(".tmp")) {
+        return false;
+    }
+    fs::metadata(path)
+        .ok()
+        .and_then(|m| m.modified().ok())
+        .and_then(millis)
+        .is_some_and(|at| now.saturating_sub(at) > STALE_TEMP_MS)
+}
+
+/// Temp files left by writes a crash interrupted, in `dir` and the
+/// directories directly under it
+fn clean_temps(report: &mut BootstrapReport, dir: &Path, now: u64) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let paths: Vec<PathBuf> = entries.flatten().map(|e| e.path()).collect();
+    let nested = paths.iter().filter(|p| p.is_dir()).flat_map(|p| {
+        fs::read_dir(p)
+            .into_iter()
+            .flat_map(|entries| entries.flatten().map(|e| e.path()))
+    });
+    let stale: Vec<PathBuf> = paths
+        .iter()
+        .cloned()
+        .chain(nested)
+        .filter(|p| is_stale_temp(p, now))
+        .collect();
+    for path in stale {
+        match fs::remove_file(&path) {
+            Ok(()) => report.cleaned += 1,
+            Err(e) => report.issue(
+                IssueKind::Cleanup,
+                &path,
+                format!("A leftover temp file couldn't be removed: {}", e),
+            ),
+        }
+    }
+}
+
+fn clean(report: &mut BootstrapReport, dirs: &Dirs) {
+    let now = now_millis();
+    for dir in [&dirs.config, &dirs.data].into_iter().flatten() {
+        clean_temps(report, dir, now);
+    }
+    // Spill files only live as long as the session that wrote them
+    let Some(spill) = dirs.cache.as_ref().map(|c| c.join(SPILL_DIR)) else {
+        return;
+    };
+    let count = fs::read_dir(&spill).map_or(0, |entries| entries.count());
+    match fs::remove_dir_all(&spill) {
+        Ok(()) => report.cleaned += count,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => report.issue(
+            IssueKind::Cleanup,
+            &spill,
+            format!("Last session's spill files couldn't be removed: {}", e),
+        ),
+    }
+}
+
+/// Check the app's directories before anything uses them: create the
+/// layout, move an older one over, set corrupt files aside and clear stale
+/// temp and spill files. Nothing here stops the app starting; each problem
+/// goes in the report instead, so chat still works with, say, an unwritable
+/// history directory.
+pub fn run(config: &Config) -> BootstrapReport {
+    let started = Instant::now();
+    let dirs = Dirs {
+        config: app_config_dir(config),
+        data: app_data_dir(config),
+        cache: app_cache_dir(config),
+        logs: app_log_dir(config),
+    };
+    let mut report = BootstrapReport::default();
+    ensure(&mut report, dirs.config.as_deref(), CONFIG_DIRS, "config");
+    ensure(&mut report, dirs.data.as_deref(), DATA_DIRS, "data");
+    ensure(&mut report, dirs.cache.as_deref(), CACHE_DIRS, "cache");
+    ensure(&mut report, dirs.logs.as_deref(), &[], "log");
+    migrate(&mut report, &dirs);
+    check_files(&mut report, &dirs);
+    clean(&mut report, &dirs);
+    report.duration_ms = started.elapsed().as_millis() as u64;
+    report
+}
+
+/// What startup found wrong with the app's files and did about it
+#[tauri::command]
+pub async fn get_bootstrap_report(
+    report: State<'_, BootstrapReport>,
+) -> Result<BootstrapReport, EngineError> {
+    Ok(report.inner().clone())
+}
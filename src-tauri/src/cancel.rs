@@ -0,0 +1,286 @@
+use crate::error::EngineError;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Notify;
+
+/// Cheap, cloneable flag checked by long-running work
+#[derive(Clone, Default)]
+pub struct CancelToken(Arc<TokenState>);
+
+#[derive(Default)]
+struct TokenState {
+    flag: AtomicBool,
+    notify: Notify,
+}
+
+impl CancelToken {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.flag.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled, for `select!` against other work
+    pub async fn cancelled(&self) {
+        let notified = self.0.notify.notified();
+        tokio::pin!(notified);
+        // Registered before the check, so a cancel in between still wakes us
+        notified.as_mut().enable();
+        if !self.is_cancelled() {
+            notified.await;
+        }
+    }
+
+    fn cancel(&self) {
+        self.0.flag.store(true, Ordering::SeqCst);
+        self.0.notify.notify_waiters();
+    }
+}
+
+struct Entry {
+    token: CancelToken,
+    /// Label of the window the request belongs to, if it came from one
+    owner: Option<String>,
+}
+
+/// Managed registry of in-flight requests keyed by their frontend request id
+#[derive(Clone, Default)]
+pub struct CancelRegistry {
+    tokens: Arc<Mutex<HashMap<String, Entry>>>,
+}
+
+impl CancelRegistry {
+    /// Register a request; the entry is removed when the guard drops
+    pub fn register(&self, request_id: &str) -> CancelGuard {
+        self.insert(request_id, None)
+    }
+
+    /// [`register`](Self::register) a request made by a window, so it can
+    /// only be cancelled from there and ends when the window closes
+    pub fn register_for(&self, owner: &str, request_id: &str) -> CancelGuard {
+        self.insert(request_id, Some(owner.to_string()))
+    }
+
+    fn insert(&self, request_id: &str, owner: Option<String>) -> CancelGuard {
+        let token = CancelToken::default();
+        if let Ok(mut tokens) = self.tokens.lock() {
+            tokens.insert(
+                request_id.to_string(),
+                Entry {
+                    token: token.clone(),
+                    owner,
+                },
+            );
+        }
+        CancelGuard {
+            request_id: request_id.to_string(),
+            token,
+            registry: self.clone(),
+        }
+    }
+
+    /// Ids of everything currently registered
+    pub fn active_ids(&self) -> Vec<String> {
+        match self.tokens.lock() {
+            Ok(tokens) => tokens.keys().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Flag a request as cancelled; returns false if it isn't running
+    pub fn cancel(&self, request_id: &str) -> bool {
+        match self.tokens.lock() {
+            Ok(tokens) => match tokens.get(request_id) {
+                Some(entry) => {
+                    entry.token.cancel();
+                    true
+                }
+                None => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// [`cancel`](Self::cancel), but only a request `owner` registered;
+    /// another window's request with the same id is left running
+    pub fn cancel_owned(&self, owner: &str, request_id: &str) -> bool {
+        match self.tokens.lock() {
+            Ok(tokens) => match tokens.get(request_id) {
+                Some(entry) if entry.owner.as_deref() == Some(owner) => {
+                    entry.token.cancel();
+                    true
+                }
+                _ => false,
+            },
+            Err(_) => false,
+        }
+    }
+
+    /// Cancel everything a window registered; called when it's destroyed
+    pub fn cancel_window(&self, owner: &str) {
+        if let Ok(tokens) = self.tokens.lock() {
+            for entry in tokens.values() {
+                if entry.owner.as_deref() == Some(owner) {
+                    entry.token.cancel();
+                }
+            }
+        }
+    }
+}
+
+pub struct CancelGuard {
+    request_id: String,
+    token: CancelToken,
+    registry: CancelRegistry,
+}
+
+impl CancelGuard {
+    pub fn token(&self) -> CancelToken {
+        self.token.clone()
+    }
+}
+
+impl Drop for CancelGuard {
+    fn drop(&mut self) {
+        if let Ok(mut tokens) = self.registry.tokens.lock() {
+            // Only remove our own entry; the id may have been re-registered
+            if tokens
+                .get(&self.request_id)
+                .is_some_and(|e| Arc::ptr_eq(&e.token.0, &self.token.0))
+            {
+                tokens.remove(&self.request_id);
+            }
+        }
+    }
+}
+
+#[tauri::command]
+pub async fn cancel_request(
+    request_id: String,
+    registry: tauri::State<'_, CancelRegistry>,
+) -> Result<bool, EngineError> {
+    Ok(registry.cancel(&request_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn a_cancel_after_the_request_ended_is_a_no_op() {
+        let registry = CancelRegistry::default();
+        let first = registry.register("r1");
+        let token = first.token();
+        drop(first);
+        assert!(!registry.cancel("r1"));
+        assert!(!token.is_cancelled());
+
+        // The late cancel mustn't carry over to the next request with the id
+        let next = registry.register("r1");
+        assert!(!next.token().is_cancelled());
+        assert!(registry.cancel("r1"));
+        assert!(next.token().is_cancelled());
+    }
+
+    #[test]
+    fn a_stale_guard_leaves_the_newer_registration() {
+        let registry = CancelRegistry::default();
+        let stale = registry.register("r1");
+        let current = registry.register("r1");
+        drop(stale);
+        assert_eq!(registry.active_ids(), ["r1"]);
+        assert!(registry.cancel("r1"));
+        assert!(current.token().is_cancelled());
+        drop(current);
+        assert!(registry.active_ids().is_empty());
+    }
+
+    #[test]
+    fn owned_requests_are_cancelled_only_by_their_window() {
+        let registry = CancelRegistry::default();
+        let mine = registry.register_for("main", "r1");
+        let other = registry.register_for("other", "r2");
+        assert!(!registry.cancel_owned("other", "r1"));
+        assert!(!mine.token().is_cancelled());
+        registry.cancel_window("main");
+        assert!(mine.token().is_cancelled());
+        assert!(!other.token().is_cancelled());
+    }
+
+    /// Readers spin on their token the way a stream's read loop does, each
+    /// round re-registering its id behind a stale guard, while another thread
+    /// cancels every id nonstop. A cancel landing on the stale entry, or a
+    /// stale drop taking out the live one, leaves a reader spinning until the
+    /// deadline.
+    #[test]
+    fn cancels_racing_reads_and_re_registration() {
+        const READERS: usize = 8;
+        const ROUNDS: usize = 200;
+        const DEADLINE: Duration = Duration::from_secs(5);
+        let registry = CancelRegistry::default();
+        let done = Arc::new(AtomicBool::new(false));
+        let canceller = std::thread::spawn({
+            let registry = registry.clone();
+            let done = done.clone();
+            move || {
+                let mut cancels = 0usize;
+                while !done.load(Ordering::SeqCst) {
+                    for reader in 0..READERS {
+                        cancels += registry.cancel(&format!("r{}", reader)) as usize;
+                    }
+                    std::thread::yield_now();
+                }
+                cancels
+            }
+        });
+        let readers: Vec<_> = (0..READERS)
+            .map(|reader| {
+                let registry = registry.clone();
+                std::thread::spawn(move || {
+                    let id = format!("r{}", reader);
+                    for round in 0..ROUNDS {
+                        let stale = registry.register(&id);
+                        let guard = registry.register(&id);
+                        drop(stale);
+                        let token = guard.token();
+                        let started = Instant::now();
+                        while !token.is_cancelled() {
+                            assert!(
+                                started.elapsed() < DEADLINE,
+                                "{} round {} was never cancelled",
+                                id,
+                                round
+                            );
+                            std::thread::yield_now();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        done.store(true, Ordering::SeqCst);
+        assert!(canceller.join().unwrap() >= READERS * ROUNDS);
+        assert!(registry.active_ids().is_empty());
+    }
+
+    #[tokio::test]
+    async fn cancelled_wakes_a_waiter_and_resolves_after_the_fact() {
+        let registry = CancelRegistry::default();
+        let guard = registry.register("r1");
+        let token = guard.token();
+        let waiter = tokio::spawn(async move { token.cancelled().await });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished());
+        registry.cancel("r1");
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .expect("the waiter wasn't woken")
+            .unwrap();
+        // Already cancelled: no notification to wait for
+        tokio::time::timeout(Duration::from_secs(1), guard.token().cancelled())
+            .await
+            .unwrap();
+    }
+}
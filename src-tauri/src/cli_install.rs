@@ -0,0 +1,160 @@
+use crate::cancel::CancelRegistry;
+use crate::claude::{self, StreamSink};
+use crate::cli_status::{self, CliStatus};
+use crate::env_profile::{child_env, EnvProfile};
+use crate::error::EngineError;
+use crate::runner::{find_program, run_streamed, RunSpec};
+use crate::settings;
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::{AppHandle, State, Window};
+
+const PACKAGE: &str = "@anthropic-ai/claude-code";
+// npm on a slow connection can take minutes, but not forever
+const INSTALL_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+const ERROR_LINES: usize = 20;
+
+/// Runner output as `claude-install-progress`; how the install ended is
+/// reported separately, once the CLI has been checked
+struct ProgressSink<'a>(&'a Window);
+
+impl StreamSink for ProgressSink<'_> {
+    fn emit(&self, event: &str, payload: Value) -> Result<(), String> {
+        match event {
+            "command-output" => StreamSink::emit(self.0, "claude-install-progress", payload),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// npm where node installers put it, then PATH, which for a GUI app often
+/// lacks it
+fn find_npm() -> Option<PathBuf> {
+    let mut dirs = Vec::new();
+    if cfg!(windows) {
+        if let Some(files) = std::env::var_os("ProgramFiles") {
+            dirs.push(PathBuf::from(files).join("nodejs"));
+        }
+        if let Some(appdata) = std::env::var_os("APPDATA") {
+            dirs.push(PathBuf::from(appdata).join("npm"));
+        }
+    } else {
+        if let Some(home) = std::env::var_os("HOME").map(PathBuf::from) {
+            dirs.extend(
+                claude::nvm_versions(&home)
+                    .iter()
+                    .map(|dir| dir.join("bin")),
+            );
+            dirs.push(home.join(".npm-global").join("bin"));
+        }
+        dirs.push(PathBuf::from("/usr/local/bin"));
+        dirs.push(PathBuf::from("/opt/homebrew/bin"));
+    }
+    find_program("npm", &dirs)
+}
+
+async fn run_install(
+    window: &Window,
+    app: &AppHandle,
+    request_id: &str,
+    registry: &CancelRegistry,
+    package: &str,
+) -> Result<CliStatus, EngineError> {
+    let npm = tokio::task::spawn_blocking(find_npm)
+        .await
+        .ok()
+        .flatten()
+        .ok_or(EngineError::NpmNotFound)?;
+    // npm is a node script, so node has to be on the child's PATH too
+    let profile = EnvProfile {
+        path_prepend: npm
+            .parent()
+            .map(|dir| dir.display().to_string())
+            .into_iter()
+            .collect(),
+        ..EnvProfile::default()
+    };
+    let spec = RunSpec {
+        program: npm,
+        args: vec!["install".to_string(), "-g".to_string(), package.to_string()],
+        cwd: std::env::temp_dir(),
+        env: child_env(&profile)?,
+        timeout: Some(INSTALL_TIMEOUT),
+    };
+
+    let guard = registry.register(request_id);
+    let output = run_streamed(
+        spec,
+        request_id,
+        Some(&ProgressSink(window)),
+        &guard.token(),
+        None,
+    )
+    .await?;
+    if output.exit_code != Some(0) {
+        let from = output.tail.len().saturating_sub(ERROR_LINES);
+        return Err(EngineError::InstallFailed {
+            code: output.exit_code,
+            detail: output.tail[from..].join("\n"),
+        });
+    }
+    // The next message should find the fresh install, not a remembered miss
+    claude::invalidate_cli();
+    Ok(cli_status::probe(settings::load(app).engine).await)
+}
+
+/// Run `npm install -g` for `package`, then `claude-install-complete` with the
+/// CLI's status or `claude-install-error` with the error
+async fn install(
+    window: Window,
+    app: AppHandle,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+    package: &str,
+) -> Result<CliStatus, EngineError> {
+    let result = run_install(&window, &app, &request_id, &registry, package).await;
+    let _ = match &result {
+        Ok(status) => StreamSink::emit(
+            &window,
+            "claude-install-complete",
+            json!({ "id": request_id, "status": status }),
+        ),
+        Err(error) => StreamSink::emit(
+            &window,
+            "claude-install-error",
+            json!({ "id": request_id, "error": error }),
+        ),
+    };
+    result
+}
+
+/// Install the CLI with npm, streaming npm's output as `claude-install-progress`
+/// events tagged with `request_id`; cancel with `cancel_request`
+#[tauri::command]
+pub async fn install_claude_cli(
+    window: Window,
+    app: AppHandle,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+) -> Result<CliStatus, EngineError> {
+    install(window, app, request_id, registry, PACKAGE).await
+}
+
+/// Reinstall the CLI at its latest version, the same way as `install_claude_cli`
+#[tauri::command]
+pub async fn update_claude_cli(
+    window: Window,
+    app: AppHandle,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+) -> Result<CliStatus, EngineError> {
+    install(
+        window,
+        app,
+        request_id,
+        registry,
+        &format!("{}@latest", PACKAGE),
+    )
+    .await
+}
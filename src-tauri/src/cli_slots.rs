@@ -0,0 +1,237 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelToken;
+use crate::claude::StreamSink;
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::State;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+/// One request holding a slot, as `get_active_requests` reports it
+#[derive(Debug, Clone, Serialize)]
+pub struct ActiveRequest {
+    pub request_id: String,
+    /// The session resumed, or the one the CLI reported once it did
+    pub session_id: Option<String>,
+    /// Unix millis
+    pub started_at: u64,
+    /// Reply text streamed so far; a request that isn't streamed stays at 0
+    pub bytes_streamed: u64,
+}
+
+struct Activity {
+    request_id: String,
+    session_id: Mutex<Option<String>>,
+    started_at: u64,
+    bytes: AtomicU64,
+}
+
+#[derive(Default)]
+struct Size {
+    /// Permits the semaphore was given
+    permits: usize,
+    /// What settings last asked for; permits coming back past it are dropped
+    limit: usize,
+}
+
+/// Cap on Claude CLI processes running at once, held by [`ActiveBackend`]
+/// so every prompt run on the CLI takes a slot; one sent to the API takes
+/// none, having no process to count. The size follows `engine.max_concurrent_cli`:
+/// a raise applies at once, and a cut as running requests finish.
+///
+/// [`ActiveBackend`]: crate::backend::ActiveBackend
+pub struct CliSlots {
+    semaphore: Arc<Semaphore>,
+    size: Mutex<Size>,
+    waiting: AtomicUsize,
+    /// Keyed by a serial, since a comparison runs several under one request id
+    active: Mutex<BTreeMap<u64, Arc<Activity>>>,
+    next: AtomicU64,
+}
+
+impl Default for CliSlots {
+    // Sized on first use, once settings can be read
+    fn default() -> Self {
+        Self {
+            semaphore: Arc::new(Semaphore::new(0)),
+            size: Mutex::new(Size::default()),
+            waiting: AtomicUsize::new(0),
+            active: Mutex::new(BTreeMap::new()),
+            next: AtomicU64::new(0),
+        }
+    }
+}
+
+/// A held slot: dropping it (on return, cancellation or a panic unwinding
+/// through the command) hands it to the next request
+pub struct Slot<'a> {
+    slots: &'a CliSlots,
+    key: u64,
+    activity: Arc<Activity>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut active) = self.slots.active.lock() {
+            active.remove(&self.key);
+        }
+        let Some(permit) = self.permit.take() else {
+            return;
+        };
+        if let Ok(mut size) = self.slots.size.lock() {
+            if size.permits > size.limit {
+                size.permits -= 1;
+                permit.forget();
+            }
+        }
+    }
+}
+
+impl Slot<'_> {
+    /// `sink` with the reply's bytes and session id noted for this request
+    pub fn meter(&self, sink: Arc<dyn StreamSink>) -> Arc<dyn StreamSink> {
+        Arc::new(Meter {
+            inner: sink,
+            activity: self.activity.clone(),
+        })
+    }
+}
+
+struct Meter {
+    inner: Arc<dyn StreamSink>,
+    activity: Arc<Activity>,
+}
+
+impl StreamSink for Meter {
+    fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String> {
+        match event {
+            "claude-stream-chunk" => {
+                let bytes = payload.as_str().map_or(0, str::len) as u64;
+                self.activity.bytes.fetch_add(bytes, Ordering::Relaxed);
+            }
+            "claude-session-started" => {
+                let id = payload["session_id"].as_str().map(str::to_string);
+                if let (Ok(mut session), Some(id)) = (self.activity.session_id.lock(), id) {
+                    *session = Some(id);
+                }
+            }
+            _ => {}
+        }
+        self.inner.emit(event, payload)
+    }
+}
+
+impl CliSlots {
+    fn resize(&self, limit: usize) {
+        let Ok(mut size) = self.size.lock() else {
+            return;
+        };
+        let limit = limit.max(1);
+        size.limit = limit;
+        if size.permits < limit {
+            self.semaphore.add_permits(limit - size.permits);
+            size.permits = limit;
+        } else if size.permits > limit {
+            size.permits -= self.semaphore.forget_permits(size.permits - limit);
+        }
+    }
+
+    /// Take a slot for `request_id`, waiting for one if they're all in use
+    /// (announced as `claude-queued` `{ position, waiting_for: "process" }`),
+    /// or failing with `TooManyRequests` when `reject` is set
+    pub async fn acquire(
+        &self,
+        limit: usize,
+        request_id: &str,
+        session_id: Option<String>,
+        reject: bool,
+        sink: &dyn StreamSink,
+        cancel: &CancelToken,
+    ) -> Result<Slot<'_>, EngineError> {
+        self.resize(limit);
+        let permit = match self.semaphore.clone().try_acquire_owned() {
+            Ok(permit) => permit,
+            Err(_) if reject => {
+                return Err(EngineError::TooManyRequests {
+                    running: self.active.lock().map(|a| a.len()).unwrap_or(0),
+                    limit: limit.max(1),
+                });
+            }
+            Err(_) => {
+                let position = self.waiting.fetch_add(1, Ordering::SeqCst) + 1;
+                let _ = sink.emit(
+                    "claude-queued",
+                    json!({ "position": position, "waiting_for": "process" }),
+                );
+                let acquired = tokio::select! {
+                    permit = self.semaphore.clone().acquire_owned() => permit.ok(),
+                    _ = cancelled(cancel) => None,
+                };
+                self.waiting.fetch_sub(1, Ordering::SeqCst);
+                match acquired {
+                    Some(permit) => permit,
+                    None => {
+                        let _ = sink.emit("claude-queue-dropped", serde_json::Value::Null);
+                        return Err(EngineError::Cancelled);
+                    }
+                }
+            }
+        };
+        let activity = Arc::new(Activity {
+            request_id: request_id.to_string(),
+            session_id: Mutex::new(session_id),
+            started_at: now_millis(),
+            bytes: AtomicU64::new(0),
+        });
+        let key = self.next.fetch_add(1, Ordering::Relaxed);
+        if let Ok(mut active) = self.active.lock() {
+            active.insert(key, activity.clone());
+        }
+        Ok(Slot {
+            slots: self,
+            key,
+            activity,
+            permit: Some(permit),
+        })
+    }
+
+    pub fn list(&self) -> Vec<ActiveRequest> {
+        let Ok(active) = self.active.lock() else {
+            return Vec::new();
+        };
+        let mut requests: Vec<ActiveRequest> = active
+            .values()
+            .map(|activity| ActiveRequest {
+                request_id: activity.request_id.clone(),
+                session_id: activity.session_id.lock().ok().and_then(|s| s.clone()),
+                started_at: activity.started_at,
+                bytes_streamed: activity.bytes.load(Ordering::Relaxed),
+            })
+            .collect();
+        requests.sort_by_key(|r| r.started_at);
+        requests
+    }
+}
+
+async fn cancelled(cancel: &CancelToken) {
+    while !cancel.is_cancelled() {
+        tokio::time::sleep(CANCEL_POLL).await;
+    }
+}
+
+/// Requests holding a CLI slot, oldest first, for an activity panel; those
+/// sent to the API aren't listed
+#[tauri::command]
+pub async fn get_active_requests(
+    backend: State<'_, ActiveBackend>,
+) -> Result<Vec<ActiveRequest>, EngineError> {
+    Ok(backend.slots().list())
+}
@@ -0,0 +1,339 @@
+use crate::claude;
+use crate::env_profile::{self, ChildEnv};
+use crate::error::EngineError;
+#[cfg(all(unix, not(target_os = "macos")))]
+use crate::runner::find_program;
+use crate::runner::{run_captured, RunSpec};
+use crate::settings::{self, EngineConfig};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::AppHandle;
+
+// A CLI stuck on an update check or a prompt must not hold up the UI
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CliStatus {
+    pub found: bool,
+    /// `native` for the standalone binary, `npm` for the package run with node
+    pub flavor: Option<&'static str>,
+    pub cli_path: Option<String>,
+    /// Only for the npm flavor
+    pub node_path: Option<String>,
+    pub version: Option<String>,
+    /// None when it can't be told from here, e.g. a login kept in the macOS keychain
+    pub authenticated: Option<bool>,
+    pub error: Option<String>,
+    /// Configured paths that were ignored because they don't exist
+    pub warnings: Vec<String>,
+    /// What discovery has remembered, which requests use unless overridden
+    pub cached_cli_path: Option<String>,
+    pub cached_node_path: Option<String>,
+}
+
+fn home_dir() -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    std::env::var_os(var).map(PathBuf::from)
+}
+
+// Variables that stand in for a login: a key or token, or a cloud provider
+const KEY_VARS: &[(&str, AuthMethod)] = &[
+    ("ANTHROPIC_API_KEY", AuthMethod::ApiKey),
+    ("ANTHROPIC_AUTH_TOKEN", AuthMethod::ApiKey),
+    ("CLAUDE_CODE_OAUTH_TOKEN", AuthMethod::OauthToken),
+    ("CLAUDE_CODE_USE_BEDROCK", AuthMethod::Cloud),
+    ("CLAUDE_CODE_USE_VERTEX", AuthMethod::Cloud),
+];
+
+// What the CLI says, on stderr or in its result, when it has no usable login
+const AUTH_SIGNS: &[&str] = &[
+    "api error: 401",
+    "authentication_error",
+    "invalid api key",
+    "invalid x-api-key",
+    "run /login",
+    "not logged in",
+    "oauth token has expired",
+    "oauth token revoked",
+];
+
+/// What the CLI would log in with
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    ApiKey,
+    OauthToken,
+    /// Bedrock or Vertex, with that provider's own credentials
+    Cloud,
+    /// The login `claude` stores after signing in
+    Credentials,
+    /// A key helper in the CLI's settings
+    KeyHelper,
+}
+
+fn config_dir(home: Option<&Path>) -> Option<PathBuf> {
+    std::env::var_os("CLAUDE_CONFIG_DIR")
+        .map(PathBuf::from)
+        .or_else(|| home.map(|h| h.join(".claude")))
+}
+
+fn read_json(path: &Path) -> Option<serde_json::Value> {
+    serde_json::from_slice(&std::fs::read(path).ok()?).ok()
+}
+
+/// What a CLI run with `env` would authenticate with, where that can be
+/// found: a key in its environment, stored credentials, an OAuth account in
+/// its config, or a key helper in its settings
+pub fn auth_method(env: &ChildEnv) -> Option<AuthMethod> {
+    let found = KEY_VARS
+        .iter()
+        .find(|(name, _)| env.value(name).is_some_and(|v| !v.is_empty()));
+    if let Some((_, method)) = found {
+        return Some(*method);
+    }
+    let home = home_dir();
+    let config = config_dir(home.as_deref());
+    if config
+        .as_ref()
+        .is_some_and(|dir| dir.join(".credentials.json").is_file())
+    {
+        return Some(AuthMethod::Credentials);
+    }
+    let has_account = home
+        .and_then(|h| read_json(&h.join(".claude.json")))
+        .is_some_and(|config| config.get("oauthAccount").is_some_and(|a| !a.is_null()));
+    if has_account {
+        return Some(AuthMethod::Credentials);
+    }
+    let has_helper = config
+        .and_then(|dir| read_json(&dir.join("settings.json")))
+        .is_some_and(|settings| settings.get("apiKeyHelper").is_some_and(|h| h.is_string()));
+    has_helper.then_some(AuthMethod::KeyHelper)
+}
+
+/// Whether the CLI has something to log in with; None when it can't be told
+pub fn authenticated(env: &ChildEnv) -> Option<bool> {
+    if auth_method(env).is_some() {
+        return Some(true);
+    }
+    // The macOS CLI keeps its login in the keychain, out of sight
+    if cfg!(target_os = "macos") {
+        None
+    } else {
+        Some(false)
+    }
+}
+
+/// Whether CLI output says it isn't logged in
+pub fn is_auth_error(detail: &str) -> bool {
+    let detail = detail.to_ascii_lowercase();
+    AUTH_SIGNS.iter().any(|sign| detail.contains(sign))
+}
+
+/// Locate the CLI and run `--version` through it; never fails, and gives up
+/// on a CLI that doesn't answer within a few seconds
+pub async fn probe(config: EngineConfig) -> CliStatus {
+    let (discovered, warnings) = tokio::task::spawn_blocking(move || claude::locate_cli(&config))
+        .await
+        .unwrap_or_default();
+    let Some(install) = discovered else {
+        return CliStatus {
+            found: false,
+            flavor: None,
+            cli_path: None,
+            node_path: None,
+            version: None,
+            authenticated: None,
+            error: Some(EngineError::CliNotFound.to_string()),
+            warnings,
+            cached_cli_path: None,
+            cached_node_path: None,
+        };
+    };
+    let (program, prefix) = install.program();
+    let mut args: Vec<String> = prefix.iter().map(|p| p.display().to_string()).collect();
+    args.push("--version".to_string());
+    let cli = install.cli_path();
+    let spec = RunSpec {
+        program: program.to_path_buf(),
+        args,
+        cwd: cli.parent().map(Path::to_path_buf).unwrap_or_default(),
+        env: ChildEnv::default(),
+        timeout: Some(PROBE_TIMEOUT),
+    };
+    let (version, error) = match run_captured(spec, Vec::new()).await {
+        Ok(output) if output.exit_code == Some(0) => (
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string()),
+            None,
+        ),
+        Ok(output) => {
+            let stderr = output.stderr.trim();
+            let detail = if stderr.is_empty() {
+                format!("--version exited with {:?}", output.exit_code)
+            } else {
+                stderr.to_string()
+            };
+            (None, Some(detail))
+        }
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let cached = claude::cached_cli();
+    CliStatus {
+        found: true,
+        flavor: Some(install.flavor()),
+        cli_path: Some(cli.display().to_string()),
+        node_path: install.node_path().map(|node| node.display().to_string()),
+        version,
+        authenticated: authenticated(&ChildEnv::default()),
+        error,
+        warnings,
+        cached_cli_path: cached.as_ref().map(|c| c.cli_path().display().to_string()),
+        cached_node_path: cached
+            .as_ref()
+            .and_then(|c| c.node_path())
+            .map(|node| node.display().to_string()),
+    }
+}
+
+/// Whether Claude is usable, checked before sending anything
+#[tauri::command]
+pub async fn claude_cli_status(app: AppHandle) -> Result<CliStatus, EngineError> {
+    Ok(probe(settings::load(&app).engine).await)
+}
+
+/// Forget the remembered CLI and look again, e.g. right after installing it
+#[tauri::command]
+pub async fn rediscover_claude_cli(app: AppHandle) -> Result<CliStatus, EngineError> {
+    claude::invalidate_cli();
+    Ok(probe(settings::load(&app).engine).await)
+}
+
+#[derive(Debug, Serialize)]
+pub struct AuthStatus {
+    /// None when it can't be told from here
+    pub authenticated: Option<bool>,
+    pub method: Option<AuthMethod>,
+}
+
+/// Whether the CLI is logged in, looked at afresh; call after the login
+/// `start_claude_login` opened has finished
+#[tauri::command]
+pub async fn get_auth_status(app: AppHandle) -> Result<AuthStatus, EngineError> {
+    tokio::task::spawn_blocking(move || {
+        let env = env_profile::for_claude(&app).env;
+        AuthStatus {
+            authenticated: authenticated(&env),
+            method: auth_method(&env),
+        }
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
+
+/// `part` quoted for a POSIX shell
+#[cfg(target_os = "macos")]
+fn shell_quote(part: &str) -> String {
+    format!("'{}'", part.replace('\'', "'\\''"))
+}
+
+/// `command` run in a new terminal window, where the user can answer it
+#[cfg(windows)]
+fn terminal_command(command: &[String]) -> Option<std::process::Command> {
+    use std::os::windows::process::CommandExt;
+
+    let mut cmd = std::process::Command::new("cmd");
+    cmd.args(["/C", "start", "Claude login"]).args(command);
+    // Only the console `start` opens shows, not cmd's own
+    cmd.creation_flags(CREATE_NO_WINDOW);
+    Some(cmd)
+}
+
+#[cfg(target_os = "macos")]
+fn terminal_command(command: &[String]) -> Option<std::process::Command> {
+    let line: Vec<String> = command.iter().map(|part| shell_quote(part)).collect();
+    let script = line.join(" ").replace('\\', "\\\\").replace('"', "\\\"");
+    let mut cmd = std::process::Command::new("osascript");
+    cmd.arg("-e")
+        .arg(format!(
+            "tell application \"Terminal\" to do script \"{}\"",
+            script
+        ))
+        .arg("-e")
+        .arg("tell application \"Terminal\" to activate");
+    Some(cmd)
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn terminal_command(command: &[String]) -> Option<std::process::Command> {
+    // The first one installed, with the flag it takes the command after
+    let terminals = [
+        ("x-terminal-emulator", "-e"),
+        ("gnome-terminal", "--"),
+        ("konsole", "-e"),
+        ("xfce4-terminal", "-x"),
+        ("xterm", "-e"),
+    ];
+    let (terminal, flag) = terminals
+        .iter()
+        .find_map(|(name, flag)| find_program(name, &[]).map(|path| (path, *flag)))?;
+    let mut cmd = std::process::Command::new(terminal);
+    cmd.arg(flag).args(command);
+    Some(cmd)
+}
+
+/// Open a terminal running the CLI interactively, which walks through
+/// logging in when it has no login (or with `/login` when it has a stale
+/// one). Returns once the window is open; `get_auth_status` tells when it's
+/// done. Turns that find no login fail with `AuthRequired` meanwhile.
+#[tauri::command]
+pub async fn start_claude_login(app: AppHandle) -> Result<(), EngineError> {
+    let spawn = env_profile::for_claude(&app);
+    let (install, _) = tokio::task::spawn_blocking({
+        let engine = spawn.engine.clone();
+        move || claude::locate_cli(&engine)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+    let install = install.ok_or(EngineError::CliNotFound)?;
+    let (program, prefix) = install.program();
+    let command: Vec<String> = std::iter::once(program)
+        .chain(prefix)
+        .map(|part| part.display().to_string())
+        .collect();
+    let mut cmd = terminal_command(&command).ok_or_else(|| {
+        EngineError::invalid("No terminal was found to log in from; run `claude` in one to log in")
+    })?;
+    spawn.env.apply_std(&mut cmd);
+    let mut child = cmd.spawn().map_err(|e| EngineError::SpawnFailed {
+        detail: e.to_string(),
+    })?;
+    tracing::info!(cli = %install.cli_path().display(), "Opened a terminal for the CLI login");
+    // Reaped in the background; the window outlives this command
+    std::thread::spawn(move || child.wait());
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn get_engine_config(app: AppHandle) -> Result<EngineConfig, EngineError> {
+    Ok(settings::load(&app).engine)
+}
+
+/// Save CLI and node path overrides and the backend; empty paths clear them.
+/// Used from the next request on; `claude_cli_status` reports any that don't
+/// exist.
+#[tauri::command]
+pub async fn set_engine_config(app: AppHandle, config: EngineConfig) -> Result<(), EngineError> {
+    let given = |path: Option<PathBuf>| path.filter(|p| !p.as_os_str().is_empty());
+    let mut current = settings::load(&app);
+    current.engine = EngineConfig {
+        claude_cli_path: given(config.claude_cli_path),
+        node_path: given(config.node_path),
+        ..config
+    };
+    settings::save(&app, &current)?;
+    claude::invalidate_cli();
+    Ok(())
+}
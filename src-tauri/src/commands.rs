@@ -0,0 +1,112 @@
+use crate::cancel::CancelRegistry;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::runner::{find_program, run_streamed, RunSpec};
+use crate::sandbox;
+use crate::settings;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use tauri::{Manager, State, Window};
+
+#[derive(Debug, Serialize)]
+pub struct CommandResult {
+    pub exit_code: Option<i32>,
+    /// Last lines of output, stdout and stderr interleaved
+    pub tail: Vec<String>,
+    pub duration_ms: u64,
+    /// Output passed `commands.max_output_bytes` and stopped being emitted
+    pub truncated: bool,
+}
+
+/// Whether `program` is on the allowlist. Only bare names count, so a
+/// `./cargo` in the workspace doesn't pass for the real one.
+fn allowed(program: &str, allowlist: &[String]) -> bool {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return false;
+    }
+    let name = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy())
+        .unwrap_or_default();
+    allowlist.iter().any(|entry| {
+        if cfg!(windows) {
+            entry.eq_ignore_ascii_case(&name)
+        } else {
+            *entry == name
+        }
+    })
+}
+
+/// Run `program` in `cwd`, streaming its lines as `command-output
+/// { id, stream, line }` and ending with `command-exit { id, code, truncated }`.
+/// While the workspace sandbox is on, programs outside
+/// `commands.allowed_programs` need `confirmed: true`. `env` goes on top of
+/// the workspace's environment profile.
+#[tauri::command]
+pub async fn run_command(
+    window: Window,
+    id: String,
+    program: String,
+    args: Option<Vec<String>>,
+    cwd: String,
+    env: Option<HashMap<String, String>>,
+    confirmed: Option<bool>,
+) -> Result<CommandResult, EngineError> {
+    let app = window.app_handle();
+    let cwd = sandbox::allow(&app, &cwd)?;
+    if !cwd.is_dir() {
+        return Err(EngineError::InvalidWorkingDirectory {
+            path: cwd.display().to_string(),
+            detail: "Not a directory".to_string(),
+        });
+    }
+    let settings = settings::load(&app);
+    if !settings.filesystem.unrestricted
+        && !confirmed.unwrap_or(false)
+        && !allowed(&program, &settings.commands.allowed_programs)
+    {
+        return Err(EngineError::CommandNotAllowed { program });
+    }
+
+    let mut child_env = env_profile::for_root(&app, &cwd)?;
+    for (name, value) in env.unwrap_or_default() {
+        child_env.set(&name, &value);
+    }
+    let program_path = find_program(&program, &child_env.path_prepend)
+        .ok_or_else(|| EngineError::invalid(format!("{} was not found on PATH", program)))?;
+    let spec = RunSpec {
+        program: program_path,
+        args: args.unwrap_or_default(),
+        cwd,
+        env: child_env,
+        timeout: None,
+    };
+
+    let guard = app.state::<CancelRegistry>().register(&id);
+    let output = run_streamed(
+        spec,
+        &id,
+        Some(&window),
+        &guard.token(),
+        Some(settings.commands.max_output_bytes),
+    )
+    .await?;
+    Ok(CommandResult {
+        exit_code: output.exit_code,
+        tail: output.tail,
+        duration_ms: output.duration_ms,
+        truncated: output.truncated,
+    })
+}
+
+/// Kill a `run_command` process and everything it started; false if `id`
+/// isn't running. The run itself then fails with `Cancelled`.
+#[tauri::command]
+pub async fn kill_command(
+    id: String,
+    registry: State<'_, CancelRegistry>,
+) -> Result<bool, EngineError> {
+    Ok(registry.cancel(&id))
+}
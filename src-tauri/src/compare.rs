@@ -0,0 +1,182 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::{error_text, StreamSink};
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::history::{self, Variant};
+use crate::models::validate_model;
+use crate::sandbox::Workspace;
+use crate::usage_ledger;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tauri::{Manager, State, Window};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CompareOptions {
+    /// Run in this workspace, with its environment profile
+    pub workspace: Option<String>,
+    pub store_in_history: bool,
+    /// Append to an existing conversation instead of starting one
+    pub conversation_id: Option<String>,
+}
+
+impl Default for CompareOptions {
+    fn default() -> Self {
+        Self {
+            workspace: None,
+            store_in_history: true,
+            conversation_id: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct VariantResult {
+    pub model: String,
+    pub text: Option<String>,
+    pub error: Option<String>,
+    pub latency_ms: u64,
+    /// The CLI's usage report, when it sent one
+    pub usage: Option<serde_json::Value>,
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ComparisonResult {
+    pub request_id: String,
+    pub variants: Vec<VariantResult>,
+    pub conversation_id: Option<String>,
+}
+
+/// Forwards the usual stream events, wrapped so the UI knows which column they belong to
+struct VariantSink {
+    window: Window,
+    request_id: String,
+    variant: String,
+}
+
+impl StreamSink for VariantSink {
+    fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String> {
+        let tagged = serde_json::json!({
+            "request_id": self.request_id,
+            "variant": self.variant,
+            "payload": payload,
+        });
+        StreamSink::emit(&self.window, event, tagged)
+    }
+}
+
+fn validate_models(models: Vec<String>) -> Result<Vec<String>, EngineError> {
+    let mut unique: Vec<String> = Vec::new();
+    for model in models {
+        let model = validate_model(&model)?;
+        if !unique.contains(&model) {
+            unique.push(model);
+        }
+    }
+    if unique.is_empty() {
+        return Err(EngineError::invalid("At least one model is required"));
+    }
+    Ok(unique)
+}
+
+/// Send one prompt to several models at once. A failing variant doesn't stop the
+/// others; cancelling `request_id` stops all of them.
+#[tauri::command]
+pub async fn compare_models(
+    window: Window,
+    message: String,
+    options: Option<CompareOptions>,
+    models: Vec<String>,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+) -> Result<ComparisonResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let models = validate_models(models)?;
+    let app = window.app_handle();
+    let root = match &options.workspace {
+        Some(root) => Some(app.state::<Workspace>().check(Path::new(root))?),
+        None => None,
+    };
+
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+    let mut tasks = Vec::new();
+    for model in &models {
+        let mut spawn = env_profile::spawn_options(&app, root.clone())?;
+        spawn.model = Some(model.clone());
+        spawn.request_id = Some(request_id.clone());
+        let sink = Arc::new(VariantSink {
+            window: window.clone(),
+            request_id: request_id.clone(),
+            variant: model.clone(),
+        });
+        let message = message.clone();
+        let token = token.clone();
+        let app = app.clone();
+        let model = model.clone();
+        tasks.push(tauri::async_runtime::spawn(async move {
+            let started = Instant::now();
+            let result = app
+                .state::<ActiveBackend>()
+                .stream(sink, message, token, spawn)
+                .await;
+            if let Ok(reply) = &result {
+                usage_ledger::record(&app, Some(&model), reply);
+            }
+            let result = result.map_err(error_text);
+            (result, started.elapsed().as_millis() as u64)
+        }));
+    }
+
+    let mut variants = Vec::with_capacity(models.len());
+    for (model, task) in models.into_iter().zip(tasks) {
+        let (result, latency_ms) = match task.await {
+            Ok(done) => done,
+            Err(e) => (Err(format!("Task error: {}", e)), 0),
+        };
+        let (text, usage, error) = match result {
+            Ok(reply) => (Some(reply.text), reply.usage, None),
+            Err(e) => (None, None, Some(e)),
+        };
+        variants.push(VariantResult {
+            model,
+            text,
+            error,
+            latency_ms,
+            cost_usd: usage.as_ref().and_then(|u| u.cost_usd),
+            usage: usage.and_then(|u| serde_json::to_value(u).ok()),
+        });
+    }
+    if token.is_cancelled() {
+        return Err(EngineError::Cancelled);
+    }
+
+    let conversation_id = if options.store_in_history {
+        let id = options
+            .conversation_id
+            .clone()
+            .unwrap_or_else(history::new_conversation_id);
+        let stored = variants
+            .iter()
+            .map(|v| Variant {
+                model: v.model.clone(),
+                content: v.text.clone().unwrap_or_default(),
+                error: v.error.clone(),
+            })
+            .collect();
+        history::record_comparison(&app, &id, "comparison", &message, stored)?;
+        Some(id)
+    } else {
+        None
+    };
+
+    Ok(ComparisonResult {
+        request_id,
+        variants,
+        conversation_id,
+    })
+}
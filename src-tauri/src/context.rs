@@ -0,0 +1,523 @@
+use crate::clock::{millis, now_millis};
+use crate::error::EngineError;
+use crate::files::{atomic_write, looks_binary};
+use crate::index::{FileIndex, IndexRegistry};
+use crate::languages;
+use crate::paths::{app_data_subdir, validate_name};
+use crate::sandbox;
+use crate::tokens::count_tokens;
+use crate::walker::{walk_files, WalkConfig};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+
+pub const SNAPSHOT_DIR: &str = "context_snapshots";
+
+// Skipped unless the caller explicitly includes them
+pub const DEFAULT_SKIP_DIRS: &[&str] = &[
+    ".git",
+    "node_modules",
+    "target",
+    "dist",
+    "build",
+    "out",
+    ".next",
+    "__pycache__",
+    ".venv",
+    "venv",
+    ".idea",
+    ".vscode",
+];
+const DEFAULT_SKIP_FILES: &[&str] = &[
+    "Cargo.lock",
+    "package-lock.json",
+    "yarn.lock",
+    "pnpm-lock.yaml",
+    "poetry.lock",
+    "Gemfile.lock",
+    "composer.lock",
+    "go.sum",
+    "bun.lockb",
+    "*.min.js",
+    "*.min.css",
+    "*.map",
+    "*.snap",
+    "*.generated.*",
+    "*.pb.go",
+    "*_pb2.py",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ContextOrder {
+    #[default]
+    Path,
+    Recent,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ContextOptions {
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub respect_gitignore: bool,
+    pub max_total_tokens: usize,
+    pub max_file_tokens: usize,
+    pub order: ContextOrder,
+}
+
+impl Default for ContextOptions {
+    fn default() -> Self {
+        Self {
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            respect_gitignore: true,
+            max_total_tokens: 100_000,
+            max_file_tokens: 20_000,
+            order: ContextOrder::Path,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExclusionReason {
+    NotIncluded,
+    ExcludedByGlob,
+    DefaultPattern,
+    Binary,
+    NotUtf8,
+    Minified,
+    FileTooLarge,
+    OverBudget,
+    Unreadable,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IncludedFile {
+    pub path: String,
+    pub bytes: u64,
+    pub tokens: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExcludedFile {
+    pub path: String,
+    pub reason: ExclusionReason,
+    pub tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProjectContext {
+    pub root: String,
+    pub content: String,
+    pub total_tokens: usize,
+    pub included: Vec<IncludedFile>,
+    pub excluded: Vec<ExcludedFile>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ContextSnapshot {
+    pub name: String,
+    pub created_at: u64,
+    pub context: ProjectContext,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContextSnapshotInfo {
+    pub name: String,
+    pub root: String,
+    pub created_at: u64,
+    pub total_tokens: usize,
+    pub file_count: usize,
+}
+
+struct Candidate {
+    rel: String,
+    abs: PathBuf,
+    size: u64,
+    modified: u64,
+}
+
+pub fn build_globset(patterns: &[String]) -> Result<Option<GlobSet>, EngineError> {
+    if patterns.is_empty() {
+        return Ok(None);
+    }
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern)
+            .map_err(|e| EngineError::invalid(format!("Bad glob '{}': {}", pattern, e)))?;
+        builder.add(glob);
+    }
+    builder
+        .build()
+        .map(Some)
+        .map_err(|e| EngineError::invalid(format!("Bad glob set: {}", e)))
+}
+
+fn default_skip_files() -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in DEFAULT_SKIP_FILES {
+        if let Ok(glob) = Glob::new(pattern) {
+            builder.add(glob);
+        }
+    }
+    builder.build().unwrap_or_else(|_| GlobSet::empty())
+}
+
+pub fn relative(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .replace('\\', "/")
+}
+
+/// Files under `root` whose root-relative path matches `pattern`, sorted.
+/// Hidden and gitignored files are skipped.
+pub fn glob_files(root: &Path, pattern: &str) -> Result<Vec<(String, PathBuf)>, EngineError> {
+    let matcher = Glob::new(pattern)
+        .map_err(|e| EngineError::invalid(format!("Bad glob '{}': {}", pattern, e)))?
+        .compile_matcher();
+    let files = walk_files(
+        root,
+        WalkConfig::default(),
+        |_| true,
+        |entry| Some(relative(root, entry.path())).filter(|rel| matcher.is_match(rel)),
+    )?;
+    Ok(files.into_iter().map(|(path, rel)| (rel, path)).collect())
+}
+
+/// Very long average line length is the telltale of minified/bundled output
+fn looks_minified(text: &str) -> bool {
+    let lines = text.lines().count().max(1);
+    text.len() > 2048 && text.len() / lines > 500
+}
+
+/// `### path` followed by a fence longer than any backtick run in the content
+pub fn render_section(path: &str, content: &str) -> String {
+    let longest_run = content.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    let mut section = format!("### {}\n{}{}\n", path, fence, languages::fence_for(path));
+    section.push_str(content);
+    if !content.ends_with('\n') {
+        section.push('\n');
+    }
+    section.push_str(&fence);
+    section.push_str("\n\n");
+    section
+}
+
+fn collect_candidates(
+    root: &Path,
+    options: &ContextOptions,
+    index: Option<&FileIndex>,
+    excluded: &mut Vec<ExcludedFile>,
+) -> Result<Vec<Candidate>, EngineError> {
+    let include = build_globset(&options.include_globs)?;
+    let exclude = build_globset(&options.exclude_globs)?;
+    let skip_files = default_skip_files();
+    let classify = |rel: String, abs: PathBuf, size: u64, modified: u64| {
+        let explicitly_included = include.as_ref().is_some_and(|set| set.is_match(&rel));
+        let name = abs.file_name().unwrap_or_default();
+        let reason = if exclude.as_ref().is_some_and(|set| set.is_match(&rel)) {
+            Some(ExclusionReason::ExcludedByGlob)
+        } else if include.is_some() && !explicitly_included {
+            Some(ExclusionReason::NotIncluded)
+        } else if !explicitly_included && skip_files.is_match(name) {
+            Some(ExclusionReason::DefaultPattern)
+        } else {
+            None
+        };
+        match reason {
+            Some(reason) => Err(ExcludedFile {
+                path: rel,
+                reason,
+                tokens: None,
+            }),
+            None => Ok(Candidate {
+                rel,
+                abs,
+                size,
+                modified,
+            }),
+        }
+    };
+
+    // The index prunes the same directories and honours gitignore, so it can
+    // stand in for the walk unless includes might reach into pruned directories
+    let index = index.filter(|_| include.is_none() && options.respect_gitignore);
+    let mut candidates = Vec::new();
+    if let Some(index) = index {
+        for file in &index.files {
+            match classify(
+                file.path.clone(),
+                root.join(&file.path),
+                file.size,
+                file.modified,
+            ) {
+                Ok(candidate) => candidates.push(candidate),
+                Err(file) => excluded.push(file),
+            }
+        }
+        excluded.extend(index.skipped_dirs.iter().map(|path| ExcludedFile {
+            path: path.clone(),
+            reason: ExclusionReason::DefaultPattern,
+            tokens: None,
+        }));
+    } else {
+        // Default-skipped directories are pruned during the walk and reported afterwards
+        let skipped_dirs = Arc::new(Mutex::new(Vec::new()));
+        let descend = {
+            let root = root.to_path_buf();
+            let include = include.clone();
+            let skipped_dirs = Arc::clone(&skipped_dirs);
+            move |entry: &ignore::DirEntry| {
+                let name = entry.file_name().to_string_lossy();
+                if !DEFAULT_SKIP_DIRS.contains(&name.as_ref()) {
+                    return true;
+                }
+                let rel = relative(&root, entry.path());
+                if include.as_ref().is_some_and(|set| set.is_match(&rel)) {
+                    return true;
+                }
+                if let Ok(mut dirs) = skipped_dirs.lock() {
+                    dirs.push(rel);
+                }
+                false
+            }
+        };
+        let config = WalkConfig {
+            respect_gitignore: options.respect_gitignore,
+            ..WalkConfig::default()
+        };
+        let visited = walk_files(root, config, descend, |entry| {
+            let (size, modified) = match entry.metadata() {
+                Ok(meta) => (
+                    meta.len(),
+                    meta.modified().ok().and_then(millis).unwrap_or(0),
+                ),
+                Err(_) => (0, 0),
+            };
+            let rel = relative(root, entry.path());
+            Some(classify(rel, entry.path().to_path_buf(), size, modified))
+        })?;
+        for (_, visited) in visited {
+            match visited {
+                Ok(candidate) => candidates.push(candidate),
+                Err(file) => excluded.push(file),
+            }
+        }
+        let dirs = skipped_dirs
+            .lock()
+            .map(|dirs| dirs.clone())
+            .unwrap_or_default();
+        excluded.extend(dirs.into_iter().map(|path| ExcludedFile {
+            path,
+            reason: ExclusionReason::DefaultPattern,
+            tokens: None,
+        }));
+    }
+
+    match options.order {
+        ContextOrder::Path => candidates.sort_by(|a, b| a.rel.cmp(&b.rel)),
+        ContextOrder::Recent => {
+            candidates.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| a.rel.cmp(&b.rel)))
+        }
+    }
+    Ok(candidates)
+}
+
+/// Walk a workspace (or read its file index, when fresh) and pack its text
+/// files into one prompt-ready string
+pub fn build_context(
+    root: &Path,
+    options: &ContextOptions,
+    index: Option<&FileIndex>,
+) -> Result<ProjectContext, EngineError> {
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            root.display()
+        )));
+    }
+
+    let mut excluded = Vec::new();
+    let candidates = collect_candidates(root, options, index, &mut excluded)?;
+
+    // A token is at least one byte, so anything this large can't fit
+    let max_file_bytes = (options.max_file_tokens as u64).saturating_mul(8);
+    let mut content = String::new();
+    let mut included = Vec::new();
+    let mut total_tokens = 0;
+
+    for candidate in candidates {
+        let mut exclude = |reason, tokens| {
+            excluded.push(ExcludedFile {
+                path: candidate.rel.clone(),
+                reason,
+                tokens,
+            })
+        };
+
+        if candidate.size > max_file_bytes {
+            exclude(ExclusionReason::FileTooLarge, None);
+            continue;
+        }
+        let bytes = match std::fs::read(&candidate.abs) {
+            Ok(bytes) => bytes,
+            Err(_) => {
+                exclude(ExclusionReason::Unreadable, None);
+                continue;
+            }
+        };
+        if looks_binary(&bytes) {
+            exclude(ExclusionReason::Binary, None);
+            continue;
+        }
+        let text = match String::from_utf8(bytes) {
+            Ok(text) => text,
+            Err(_) => {
+                exclude(ExclusionReason::NotUtf8, None);
+                continue;
+            }
+        };
+        if looks_minified(&text) {
+            exclude(ExclusionReason::Minified, None);
+            continue;
+        }
+
+        let section = render_section(&candidate.rel, &text);
+        let tokens = count_tokens(&section);
+        if tokens > options.max_file_tokens {
+            exclude(ExclusionReason::FileTooLarge, Some(tokens));
+            continue;
+        }
+        if total_tokens + tokens > options.max_total_tokens {
+            exclude(ExclusionReason::OverBudget, Some(tokens));
+            continue;
+        }
+
+        total_tokens += tokens;
+        content.push_str(&section);
+        included.push(IncludedFile {
+            path: candidate.rel,
+            bytes: text.len() as u64,
+            tokens,
+        });
+    }
+
+    Ok(ProjectContext {
+        root: root.display().to_string(),
+        content,
+        total_tokens,
+        included,
+        excluded,
+    })
+}
+
+#[tauri::command]
+pub async fn build_project_context(
+    app: AppHandle,
+    root: String,
+    options: Option<ContextOptions>,
+) -> Result<ProjectContext, EngineError> {
+    let options = options.unwrap_or_default();
+    let root = sandbox::allow(&app, &root)?;
+    let index = app.state::<IndexRegistry>().fresh(&app, &root);
+    tokio::task::spawn_blocking(move || build_context(&root, &options, index.as_deref()))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+fn snapshot_path(app: &AppHandle, name: &str) -> Result<PathBuf, EngineError> {
+    let name = validate_name(name)?;
+    Ok(app_data_subdir(app, SNAPSHOT_DIR)?.join(format!("{}.json", name)))
+}
+
+#[tauri::command]
+pub async fn save_context_snapshot(
+    app: AppHandle,
+    name: String,
+    context: ProjectContext,
+) -> Result<ContextSnapshotInfo, EngineError> {
+    let path = snapshot_path(&app, &name)?;
+    let snapshot = ContextSnapshot {
+        name: validate_name(&name)?.to_string(),
+        created_at: now_millis(),
+        context,
+    };
+    let json = serde_json::to_vec_pretty(&snapshot)
+        .map_err(|e| EngineError::internal(format!("Failed to serialize snapshot: {}", e)))?;
+    tokio::task::spawn_blocking({
+        let path = path.clone();
+        move || atomic_write(&path, &json, false)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+    .map_err(|e| EngineError::io(&path, e))?;
+
+    Ok(ContextSnapshotInfo {
+        root: snapshot.context.root,
+        created_at: snapshot.created_at,
+        total_tokens: snapshot.context.total_tokens,
+        file_count: snapshot.context.included.len(),
+        name: snapshot.name,
+    })
+}
+
+#[tauri::command]
+pub async fn load_context_snapshot(
+    app: AppHandle,
+    name: String,
+) -> Result<ContextSnapshot, EngineError> {
+    let path = snapshot_path(&app, &name)?;
+    let data = tokio::fs::read(&path)
+        .await
+        .map_err(|e| EngineError::io(&path, e))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| EngineError::io(&path, format!("Corrupt snapshot: {}", e)))
+}
+
+#[tauri::command]
+pub async fn list_context_snapshots(
+    app: AppHandle,
+) -> Result<Vec<ContextSnapshotInfo>, EngineError> {
+    let dir = app_data_subdir(&app, SNAPSHOT_DIR)?;
+    let mut entries = tokio::fs::read_dir(&dir)
+        .await
+        .map_err(|e| EngineError::io(&dir, e))?;
+
+    let mut snapshots = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let path = entry.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+        // Skip unreadable snapshots rather than failing the whole listing
+        let Ok(data) = tokio::fs::read(&path).await else {
+            continue;
+        };
+        let Ok(snapshot) = serde_json::from_slice::<ContextSnapshot>(&data) else {
+            continue;
+        };
+        snapshots.push(ContextSnapshotInfo {
+            root: snapshot.context.root,
+            created_at: snapshot.created_at,
+            total_tokens: snapshot.context.total_tokens,
+            file_count: snapshot.context.included.len(),
+            name: snapshot.name,
+        });
+    }
+    snapshots.sort_by_key(|s| std::cmp::Reverse(s.created_at));
+    Ok(snapshots)
+}
+
+#[tauri::command]
+pub async fn delete_context_snapshot(app: AppHandle, name: String) -> Result<(), EngineError> {
+    let path = snapshot_path(&app, &name)?;
+    tokio::fs::remove_file(&path)
+        .await
+        .map_err(|e| EngineError::io(&path, e))
+}
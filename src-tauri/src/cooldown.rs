@@ -0,0 +1,218 @@
+use crate::cancel::CancelToken;
+use crate::claude::StreamSink;
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use serde::Serialize;
+use serde_json::json;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+// Without a retry-after, the first cooldown; it doubles each time the limit
+// is hit again before a request gets through
+const BASE_COOLDOWN: Duration = Duration::from_secs(30);
+const MAX_COOLDOWN: Duration = Duration::from_secs(15 * 60);
+
+// As the CLI and the API word it; auth failures never say these
+const RATE_LIMIT_SIGNS: &[&str] = &[
+    "rate_limit",
+    "rate limit",
+    "overloaded",
+    "too many requests",
+    "api error: 429",
+    "api error: 529",
+    "usage limit reached",
+];
+
+// Followed by a number of seconds, or a unit
+const RETRY_AFTER_SIGNS: &[&str] = &["retry-after", "retry after", "try again in"];
+
+/// How long the text says to wait: a retry-after, or the reset time the CLI
+/// puts after `usage limit reached|` as Unix seconds
+fn retry_after_text(text: &str) -> Option<Duration> {
+    if let Some((_, rest)) = text.split_once("usage limit reached|") {
+        let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+        let reset_ms = digits.parse::<u64>().ok()?.saturating_mul(1000);
+        return Some(Duration::from_millis(reset_ms.saturating_sub(now_millis())));
+    }
+    let lower = text.to_ascii_lowercase();
+    let at = RETRY_AFTER_SIGNS
+        .iter()
+        .find_map(|sign| lower.find(sign).map(|at| at + sign.len()))?;
+    let rest = lower[at..].trim_start_matches([':', '=', ' ', '"']);
+    let digits: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    let amount: u64 = digits.parse().ok()?;
+    let unit = rest[digits.len()..].trim_start();
+    Some(if unit.starts_with("ms") || unit.starts_with("milli") {
+        Duration::from_millis(amount)
+    } else if unit.starts_with('m') {
+        Duration::from_secs(amount.saturating_mul(60))
+    } else {
+        Duration::from_secs(amount)
+    })
+}
+
+fn rate_limited_text(text: &str) -> bool {
+    let lower = text.to_ascii_lowercase();
+    RATE_LIMIT_SIGNS.iter().any(|sign| lower.contains(sign))
+}
+
+/// Some when `error` is a rate limit, with the wait it asked for if it said
+fn rate_limit(error: &EngineError) -> Option<Option<Duration>> {
+    match error {
+        EngineError::ApiFailed {
+            status,
+            detail,
+            retry_after_secs,
+        } => (matches!(status, Some(429 | 529)) || rate_limited_text(detail)).then(|| {
+            retry_after_secs
+                .map(Duration::from_secs)
+                .or_else(|| retry_after_text(detail))
+        }),
+        EngineError::CliExited { stderr: text, .. } | EngineError::Internal { detail: text } => {
+            rate_limited_text(text).then(|| retry_after_text(text))
+        }
+        EngineError::StreamInterrupted { error, .. } => rate_limit(error),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineStatus {
+    pub cooling_down: bool,
+    /// Unix millis
+    pub resume_at: Option<u64>,
+    /// Rate limits hit in a row; the next cooldown without a retry-after
+    /// is longer for each
+    pub strikes: u32,
+}
+
+#[derive(Default)]
+struct Hold {
+    until: Option<(Instant, u64)>,
+    strikes: u32,
+}
+
+/// Managed engine-wide cooldown after a rate limit. Turns wait it out
+/// before they're sent, so a queue held behind one isn't failed turn by turn.
+#[derive(Default)]
+pub struct Cooldown {
+    state: Mutex<Hold>,
+}
+
+impl Cooldown {
+    /// Note how a request went: a rate limit starts the cooldown, anything
+    /// that got through resets the backoff
+    pub fn record<T>(&self, app: &AppHandle, result: &Result<T, EngineError>) {
+        match result {
+            Ok(_) => self.succeeded(),
+            Err(error) => {
+                if let Some(retry_after) = rate_limit(error) {
+                    self.trip(app, retry_after);
+                }
+            }
+        }
+    }
+
+    /// Start (or lengthen) the cooldown and announce `claude-rate-limited`
+    /// `{ resume_at }` to every window
+    fn trip(&self, app: &AppHandle, retry_after: Option<Duration>) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        let delay = retry_after.unwrap_or_else(|| {
+            BASE_COOLDOWN
+                .saturating_mul(2u32.saturating_pow(state.strikes))
+                .min(MAX_COOLDOWN)
+        });
+        state.strikes = state.strikes.saturating_add(1);
+        let until = Instant::now() + delay;
+        if state.until.is_some_and(|(current, _)| current >= until) {
+            return;
+        }
+        let resume_at = now_millis() + delay.as_millis() as u64;
+        state.until = Some((until, resume_at));
+        tracing::warn!(
+            delay_ms = delay.as_millis() as u64,
+            strikes = state.strikes,
+            "Rate limited; cooling down"
+        );
+        let _ = app.emit_all("claude-rate-limited", json!({ "resume_at": resume_at }));
+    }
+
+    fn succeeded(&self) {
+        if let Ok(mut state) = self.state.lock() {
+            state.strikes = 0;
+        }
+    }
+
+    /// The Unix-millis resume time, while there's a cooldown to wait out
+    fn resume_at(&self) -> Option<u64> {
+        let mut state = self.state.lock().ok()?;
+        match state.until {
+            Some((until, resume_at)) if Instant::now() < until => Some(resume_at),
+            Some(_) => {
+                state.until = None;
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn status(&self) -> EngineStatus {
+        let resume_at = self.resume_at();
+        EngineStatus {
+            cooling_down: resume_at.is_some(),
+            resume_at,
+            strikes: self.state.lock().map(|s| s.strikes).unwrap_or(0),
+        }
+    }
+
+    /// Hold until the cooldown is over or forced to end. A stream that has
+    /// to wait gets `claude-rate-limited` `{ resume_at }` on its sink first;
+    /// cancelling it ends the wait with `claude-stream-cancelled`.
+    pub async fn wait(
+        &self,
+        sink: Option<&dyn StreamSink>,
+        cancel: &CancelToken,
+    ) -> Result<(), EngineError> {
+        let mut announced = None;
+        while let Some(resume_at) = self.resume_at() {
+            if cancel.is_cancelled() {
+                if let Some(sink) = sink {
+                    let _ = sink.emit("claude-stream-cancelled", serde_json::Value::Null);
+                }
+                return Err(EngineError::Cancelled);
+            }
+            if let Some(sink) = sink.filter(|_| announced != Some(resume_at)) {
+                let _ = sink.emit("claude-rate-limited", json!({ "resume_at": resume_at }));
+            }
+            announced = Some(resume_at);
+            tokio::time::sleep(CANCEL_POLL).await;
+        }
+        Ok(())
+    }
+
+    /// End the cooldown now; false if there wasn't one
+    fn resume(&self) -> bool {
+        let was = self.resume_at().is_some();
+        if let Ok(mut state) = self.state.lock() {
+            state.until = None;
+        }
+        was
+    }
+}
+
+/// Whether the engine is cooling down after a rate limit, and until when
+#[tauri::command]
+pub async fn get_engine_status(cooldown: State<'_, Cooldown>) -> Result<EngineStatus, EngineError> {
+    Ok(cooldown.status())
+}
+
+/// Send held turns now instead of waiting out the cooldown; false if there
+/// wasn't one
+#[tauri::command]
+pub async fn force_resume(cooldown: State<'_, Cooldown>) -> Result<bool, EngineError> {
+    Ok(cooldown.resume())
+}
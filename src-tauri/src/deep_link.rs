@@ -0,0 +1,141 @@
+use crate::error::EngineError;
+use crate::paths::{canonicalize, validate_name};
+use serde::Serialize;
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+use url::Url;
+
+pub const SCHEME: &str = "bups";
+const MAX_PROMPT_CHARS: usize = 8000;
+
+/// `deep-link` event payload: `{ action, params }`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "action", content = "params", rename_all = "snake_case")]
+pub enum DeepLinkAction {
+    Prompt { text: String, truncated: bool },
+    OpenWorkspace { path: String },
+    OpenConversation { id: String },
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DeepLinkWarning {
+    pub url: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PendingLinks {
+    pub links: Vec<DeepLinkAction>,
+    pub warnings: Vec<DeepLinkWarning>,
+}
+
+/// Links that arrived before the webview was listening (cold start)
+#[derive(Default)]
+pub struct PendingDeepLinks(Mutex<PendingLinks>);
+
+fn param(url: &Url, name: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| k == name)
+        .map(|(_, v)| v.into_owned())
+        .filter(|v| !v.trim().is_empty())
+}
+
+fn sanitize_text(text: &str) -> (String, bool) {
+    let clean: String = text
+        .chars()
+        .filter(|c| !c.is_control() || matches!(c, '\n' | '\t'))
+        .collect();
+    let truncated = clean.chars().count() > MAX_PROMPT_CHARS;
+    (clean.chars().take(MAX_PROMPT_CHARS).collect(), truncated)
+}
+
+/// Only existing absolute directories; the workspace flow applies its own checks on open
+fn sanitize_workspace(path: &str) -> Result<String, String> {
+    let path = Path::new(path);
+    if !path.is_absolute() {
+        return Err("workspace path must be absolute".to_string());
+    }
+    let canonical =
+        canonicalize(path).map_err(|e| format!("workspace path is not accessible: {}", e))?;
+    if !canonical.is_dir() {
+        return Err("workspace path is not a directory".to_string());
+    }
+    Ok(canonical.display().to_string())
+}
+
+pub fn parse(raw: &str) -> Result<DeepLinkAction, String> {
+    let url = Url::parse(raw.trim()).map_err(|e| format!("invalid URL: {}", e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("unexpected scheme '{}'", url.scheme()));
+    }
+    let action = url.host_str().unwrap_or_default().to_ascii_lowercase();
+    let required = |name: &str| param(&url, name).ok_or_else(|| format!("missing '{}'", name));
+
+    match action.as_str() {
+        "prompt" => {
+            let (text, truncated) = sanitize_text(&required("text")?);
+            Ok(DeepLinkAction::Prompt { text, truncated })
+        }
+        "open" | "open-workspace" => Ok(DeepLinkAction::OpenWorkspace {
+            path: sanitize_workspace(&required("path")?)?,
+        }),
+        "open-conversation" => {
+            let id = required("id")?;
+            let id = validate_name(&id).map_err(|e| e.to_string())?;
+            Ok(DeepLinkAction::OpenConversation { id: id.to_string() })
+        }
+        other => Err(format!("unsupported action '{}'", other)),
+    }
+}
+
+/// Emit a link to the webview, or queue it when the UI isn't up yet
+pub fn dispatch(app: &AppHandle, raw: &str, queue: bool) {
+    let parsed = parse(raw).map_err(|reason| DeepLinkWarning {
+        url: raw.to_string(),
+        reason,
+    });
+    if queue {
+        if let Ok(mut pending) = app.state::<PendingDeepLinks>().0.lock() {
+            match parsed {
+                Ok(action) => pending.links.push(action),
+                Err(warning) => pending.warnings.push(warning),
+            }
+        }
+        return;
+    }
+    let _ = match parsed {
+        Ok(action) => app.emit_all("deep-link", action),
+        Err(warning) => app.emit_all("deep-link-warning", warning),
+    };
+}
+
+/// Register the scheme and forward links from later instances to this one
+pub fn setup(app: &AppHandle) {
+    let handle = app.clone();
+    let _ = tauri_plugin_deep_link::register(SCHEME, move |url| {
+        if let Some(window) = handle.get_window("main") {
+            let _ = window.set_focus();
+        }
+        dispatch(&handle, &url, false);
+    });
+
+    // Cold start: the link is the process argument
+    if let Some(url) = std::env::args()
+        .skip(1)
+        .find(|a| a.starts_with(&format!("{}:", SCHEME)))
+    {
+        dispatch(app, &url, true);
+    }
+}
+
+#[tauri::command]
+pub async fn take_pending_deep_links(
+    pending: tauri::State<'_, PendingDeepLinks>,
+) -> Result<PendingLinks, EngineError> {
+    let mut pending = pending
+        .0
+        .lock()
+        .map_err(|_| EngineError::internal("Deep link queue is poisoned"))?;
+    Ok(std::mem::take(&mut *pending))
+}
@@ -0,0 +1,395 @@
+use crate::error::EngineError;
+use crate::sandbox;
+use serde::{Deserialize, Serialize};
+use similar::{Algorithm, ChangeTag, DiffTag, TextDiff as SimilarDiff};
+use std::fmt::{self, Write};
+use tauri::AppHandle;
+
+pub const DEFAULT_CONTEXT_LINES: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DiffLineKind {
+    Ctx,
+    Add,
+    Del,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffLine {
+    pub kind: DiffLineKind,
+    pub text: String,
+}
+
+/// One hunk; starts are 1-based like unified diff headers
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffHunk {
+    pub old_start: usize,
+    pub old_lines: usize,
+    pub new_start: usize,
+    pub new_lines: usize,
+    pub lines: Vec<DiffLine>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TextDiff {
+    pub unified: String,
+    pub hunks: Vec<DiffHunk>,
+    pub additions: usize,
+    pub deletions: usize,
+}
+
+/// The line without its line ending
+fn line_text(line: &str) -> String {
+    let text = line.strip_suffix('\n').unwrap_or(line);
+    text.strip_suffix('\r').unwrap_or(text).to_string()
+}
+
+fn hunk_start(range: &std::ops::Range<usize>) -> usize {
+    // Empty ranges point at the line before the change, as in `diff -u`
+    if range.is_empty() {
+        range.start
+    } else {
+        range.start + 1
+    }
+}
+
+/// Line diff of two texts with both a unified rendering and structured hunks
+pub fn diff_text(old: &str, new: &str, context: usize, label: &str) -> TextDiff {
+    let diff = SimilarDiff::from_lines(old, new);
+
+    let mut hunks = Vec::new();
+    let mut additions = 0;
+    let mut deletions = 0;
+
+    for group in diff.grouped_ops(context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+
+        let mut lines = Vec::new();
+        for op in &group {
+            for change in diff.iter_changes(op) {
+                let kind = match change.tag() {
+                    ChangeTag::Equal => DiffLineKind::Ctx,
+                    ChangeTag::Insert => {
+                        additions += 1;
+                        DiffLineKind::Add
+                    }
+                    ChangeTag::Delete => {
+                        deletions += 1;
+                        DiffLineKind::Del
+                    }
+                };
+                lines.push(DiffLine {
+                    kind,
+                    text: line_text(change.value()),
+                });
+            }
+        }
+
+        hunks.push(DiffHunk {
+            old_start: hunk_start(&old_range),
+            old_lines: old_range.len(),
+            new_start: hunk_start(&new_range),
+            new_lines: new_range.len(),
+            lines,
+        });
+    }
+
+    let unified = if hunks.is_empty() {
+        String::new()
+    } else {
+        diff.unified_diff()
+            .context_radius(context)
+            .header(&format!("a/{}", label), &format!("b/{}", label))
+            .to_string()
+    };
+
+    TextDiff {
+        unified,
+        hunks,
+        additions,
+        deletions,
+    }
+}
+
+/// Runs of whitespace collapsed and the ends trimmed
+fn whitespace_key(line: &str) -> String {
+    line.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+fn render_unified(hunks: &[DiffHunk], label: &str) -> String {
+    if hunks.is_empty() {
+        return String::new();
+    }
+    let mut out = format!("--- a/{}\n+++ b/{}\n", label, label);
+    for hunk in hunks {
+        let _ = writeln!(
+            out,
+            "@@ -{},{} +{},{} @@",
+            hunk.old_start, hunk.old_lines, hunk.new_start, hunk.new_lines
+        );
+        for line in &hunk.lines {
+            let sign = match line.kind {
+                DiffLineKind::Ctx => ' ',
+                DiffLineKind::Add => '+',
+                DiffLineKind::Del => '-',
+            };
+            let _ = writeln!(out, "{}{}", sign, line.text);
+        }
+    }
+    out
+}
+
+/// [`diff_text`], except that lines differing only in whitespace count as
+/// unchanged. Unchanged lines are shown as they are in `old`, so the patch
+/// still applies to it.
+pub fn diff_text_ignoring_whitespace(
+    old: &str,
+    new: &str,
+    context: usize,
+    label: &str,
+) -> TextDiff {
+    let old_lines: Vec<&str> = old.split_inclusive('\n').collect();
+    let new_lines: Vec<&str> = new.split_inclusive('\n').collect();
+    let old_keys: Vec<String> = old_lines.iter().map(|l| whitespace_key(l)).collect();
+    let new_keys: Vec<String> = new_lines.iter().map(|l| whitespace_key(l)).collect();
+    let ops = similar::capture_diff_slices(Algorithm::Myers, &old_keys, &new_keys);
+
+    let mut hunks = Vec::new();
+    let mut additions = 0;
+    let mut deletions = 0;
+    for group in similar::group_diff_ops(ops, context) {
+        let (first, last) = match (group.first(), group.last()) {
+            (Some(first), Some(last)) => (first, last),
+            _ => continue,
+        };
+        let old_range = first.old_range().start..last.old_range().end;
+        let new_range = first.new_range().start..last.new_range().end;
+
+        let mut lines = Vec::new();
+        let mut push = |kind, from: &[&str]| {
+            lines.extend(from.iter().map(|l| DiffLine {
+                kind,
+                text: line_text(l),
+            }));
+        };
+        for op in &group {
+            let (tag, old_part, new_part) = op.as_tag_tuple();
+            if tag == DiffTag::Equal {
+                push(DiffLineKind::Ctx, &old_lines[old_part]);
+                continue;
+            }
+            deletions += old_part.len();
+            additions += new_part.len();
+            push(DiffLineKind::Del, &old_lines[old_part]);
+            push(DiffLineKind::Add, &new_lines[new_part]);
+        }
+
+        hunks.push(DiffHunk {
+            old_start: hunk_start(&old_range),
+            old_lines: old_range.len(),
+            new_start: hunk_start(&new_range),
+            new_lines: new_range.len(),
+            lines,
+        });
+    }
+
+    TextDiff {
+        unified: render_unified(&hunks, label),
+        hunks,
+        additions,
+        deletions,
+    }
+}
+
+/// Why [`apply_unified`] refused a patch
+#[derive(Debug, Clone)]
+pub struct PatchError {
+    /// 1-based; empty when the patch itself couldn't be read
+    pub failed_hunks: Vec<usize>,
+    pub total_hunks: usize,
+    pub detail: String,
+}
+
+impl PatchError {
+    fn malformed(detail: impl Into<String>) -> Self {
+        Self {
+            failed_hunks: Vec::new(),
+            total_hunks: 0,
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for PatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.detail)
+    }
+}
+
+fn hunk_old_start(header: &str) -> Option<usize> {
+    let old = header.strip_prefix("@@ -")?.split([' ', ',']).next()?;
+    old.parse().ok()
+}
+
+/// Apply a unified diff to `original`. Hunks are matched on their context and
+/// removed lines, searching from the header position outwards so a patch made
+/// against slightly shifted line numbers still applies.
+/// Every hunk must apply or nothing does; the error lists each one that didn't.
+pub fn apply_unified(original: &str, patch: &str) -> Result<String, PatchError> {
+    let eol = if original.contains("\r\n") {
+        "\r\n"
+    } else {
+        "\n"
+    };
+    let strip = |line: &str| line.trim_end_matches(['\r', '\n']).to_string();
+    let mut lines: Vec<String> = original.split_inclusive('\n').map(strip).collect();
+    let had_trailing_eol = original.is_empty() || original.ends_with('\n');
+
+    // (old_start, old lines, new lines) per hunk
+    let mut hunks: Vec<(usize, Vec<String>, Vec<String>)> = Vec::new();
+    for line in patch.lines() {
+        if line.starts_with("@@") {
+            let start = hunk_old_start(line)
+                .ok_or_else(|| PatchError::malformed(format!("Bad hunk header: {}", line)))?;
+            hunks.push((start, Vec::new(), Vec::new()));
+            continue;
+        }
+        let Some((_, old, new)) = hunks.last_mut() else {
+            // File headers and any prose before the first hunk
+            continue;
+        };
+        if let Some(rest) = line.strip_prefix(' ') {
+            old.push(strip(rest));
+            new.push(strip(rest));
+        } else if let Some(rest) = line.strip_prefix('-') {
+            old.push(strip(rest));
+        } else if let Some(rest) = line.strip_prefix('+') {
+            new.push(strip(rest));
+        } else if line.is_empty() {
+            // Some tools drop the space on blank context lines
+            old.push(String::new());
+            new.push(String::new());
+        }
+    }
+    if hunks.is_empty() {
+        return Err(PatchError::malformed("The patch has no hunks"));
+    }
+
+    // Line counts shift as earlier hunks apply
+    let mut shift: isize = 0;
+    let mut failed_hunks = Vec::new();
+    for (index, (start, old, new)) in hunks.iter().enumerate() {
+        let expected = (*start as isize - 1 + shift).max(0) as usize;
+        let fits =
+            |at: usize| at + old.len() <= lines.len() && lines[at..at + old.len()] == old[..];
+        let found = (0..=lines.len())
+            .flat_map(|distance| {
+                let before = expected.checked_sub(distance);
+                let after = Some(expected + distance).filter(|_| distance > 0);
+                before.into_iter().chain(after)
+            })
+            .find(|&at| at <= lines.len() && fits(at));
+        // Keep going, so the error names every hunk that needs another look
+        let Some(found) = found else {
+            failed_hunks.push(index + 1);
+            continue;
+        };
+        lines.splice(found..found + old.len(), new.iter().cloned());
+        shift += new.len() as isize - old.len() as isize;
+    }
+
+    if !failed_hunks.is_empty() {
+        let names: Vec<String> = failed_hunks.iter().map(|n| n.to_string()).collect();
+        return Err(PatchError {
+            detail: format!(
+                "Hunk {} of {} doesn't match the file",
+                names.join(", "),
+                hunks.len()
+            ),
+            failed_hunks,
+            total_hunks: hunks.len(),
+        });
+    }
+
+    let mut out = lines.join(eol);
+    if had_trailing_eol && !out.is_empty() {
+        out.push_str(eol);
+    }
+    Ok(out)
+}
+
+/// One side of a `compute_diff`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DiffSource {
+    Text {
+        text: String,
+    },
+    /// A missing file diffs as empty, so creating one shows as all additions
+    File {
+        path: String,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct DiffOptions {
+    pub context: usize,
+    pub ignore_whitespace: bool,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            context: DEFAULT_CONTEXT_LINES,
+            ignore_whitespace: false,
+        }
+    }
+}
+
+async fn source_text(app: &AppHandle, source: DiffSource) -> Result<String, EngineError> {
+    let path = match source {
+        DiffSource::Text { text } => return Ok(text),
+        DiffSource::File { path } => path,
+    };
+    let target = sandbox::allow(app, &path)?;
+    match tokio::fs::read_to_string(&target).await {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(EngineError::fs(&path, e)),
+    }
+}
+
+/// Unified diff and structured hunks between two texts or files, for
+/// reviewing a change before writing it. `apply_patch` applies the result.
+#[tauri::command]
+pub async fn compute_diff(
+    app: AppHandle,
+    old: DiffSource,
+    new: DiffSource,
+    label: Option<String>,
+    options: Option<DiffOptions>,
+) -> Result<TextDiff, EngineError> {
+    let options = options.unwrap_or_default();
+    let label = label.unwrap_or_else(|| match (&new, &old) {
+        (DiffSource::File { path }, _) | (_, DiffSource::File { path }) => path.clone(),
+        _ => "text".to_string(),
+    });
+    let old = source_text(&app, old).await?;
+    let new = source_text(&app, new).await?;
+    tokio::task::spawn_blocking(move || {
+        if options.ignore_whitespace {
+            diff_text_ignoring_whitespace(&old, &new, options.context, &label)
+        } else {
+            diff_text(&old, &new, options.context, &label)
+        }
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
@@ -0,0 +1,264 @@
+use crate::api::{self, Route};
+use crate::approval;
+use crate::attachments;
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::{
+    self, cli_error, error_text, CliSession, FailedTurns, Reply, RequestSink, StreamOptions,
+    StreamReply, StreamSink,
+};
+use crate::cooldown::Cooldown;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::estimate;
+use crate::hash;
+use crate::history;
+use crate::mentions;
+use crate::models;
+use crate::postprocess;
+use crate::queue::{self, TurnQueue};
+use crate::retry::{self, RetryPolicy};
+use crate::session;
+use crate::settings;
+use crate::spill;
+use crate::stream_input::StreamInputs;
+use crate::usage_ledger;
+use crate::webhooks;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, Window};
+
+/// Who a turn is for, which decides who may cancel it, whose queue it waits
+/// in and whether tools can be approved
+pub enum Origin {
+    /// A chat command; events go to the window, which can approve tools
+    Window(Box<Window>),
+    /// A program calling the local HTTP API, with nobody to approve a tool
+    Http(Arc<dyn StreamSink>),
+}
+
+impl Origin {
+    fn owner(&self) -> &str {
+        match self {
+            Self::Window(window) => window.label(),
+            Self::Http(_) => "http",
+        }
+    }
+
+    fn sink(&self) -> Arc<dyn StreamSink> {
+        match self {
+            Self::Window(window) => Arc::new(window.as_ref().clone()),
+            Self::Http(sink) => sink.clone(),
+        }
+    }
+}
+
+/// The message with its `@path` mentions inlined, when asked for, and the
+/// ones that couldn't be
+pub async fn expand_mentions(
+    app: &AppHandle,
+    message: String,
+    expand: bool,
+    cwd: Option<&std::path::Path>,
+) -> Result<(String, Vec<String>), EngineError> {
+    if !expand {
+        return Ok((message, Vec::new()));
+    }
+    let mentioned = mentions::expand(app, &message, cwd).await?;
+    Ok((mentioned.text, mentioned.unresolved))
+}
+
+/// One streamed turn as `stream_to_claude` runs it, for a window or a local
+/// program: mentions, confirmation, the conversation's turn, the cooldown, a
+/// CLI slot, retries and webhooks, with typed errors and the partial reply
+/// kept when it's cancelled
+pub async fn stream(
+    app: &AppHandle,
+    origin: Origin,
+    message: String,
+    webhook_url: Option<String>,
+    conversation_id: Option<String>,
+    confirmed: Option<bool>,
+    options: StreamOptions,
+) -> Result<StreamReply, EngineError> {
+    // Kept to be sent again by retry_last_turn if the stream breaks
+    let failed_turn = (message.clone(), options.clone());
+    let app = app.clone();
+    let model = models::requested_model(options.model)?;
+    options.tools.validate()?;
+    let cwd = claude::working_dir(options.cwd)?;
+    let (message, unresolved_mentions) =
+        expand_mentions(&app, message, options.expand_mentions, cwd.as_deref()).await?;
+    estimate::confirm_send(&app, &message, model.as_deref(), confirmed)?;
+    let request_id = options
+        .request_id
+        .unwrap_or_else(|| format!("stream-{}", hash::random_hex(6)));
+    // A token per request, so cancelling one stream leaves the others running
+    let registry = app.state::<CancelRegistry>();
+    let guard = match &origin {
+        Origin::Window(window) => registry.register_for(window.label(), &request_id),
+        Origin::Http(_) => registry.register(&request_id),
+    };
+    let events = Arc::new(RequestSink::new(
+        origin.sink(),
+        request_id.clone(),
+        options.session_id.clone(),
+    ));
+    // Messages in the same conversation take turns, in the order they were sent
+    let lane = queue::lane_key(
+        origin.owner(),
+        conversation_id.as_deref().or(options.session_id.as_deref()),
+    );
+    let _turn = app
+        .state::<TurnQueue>()
+        .wait_turn(&lane, &request_id, events.as_ref(), &guard.token())
+        .await?;
+    // After a rate limit the queue holds here rather than failing each turn
+    let cooldown = app.state::<Cooldown>();
+    cooldown.wait(Some(events.as_ref()), &guard.token()).await?;
+    let mut spawn = env_profile::spawn_options(&app, cwd)?;
+    spawn.model = model.clone();
+    spawn.stop = options.stop;
+    spawn.transforms = postprocess::resolve(&app, &options.transforms)?;
+    spawn.session = CliSession::from_request(options.session_id, options.continue_last);
+    spawn.tools = options.tools;
+    spawn.system_prompt = options.system_prompt;
+    spawn.append_system_prompt = options.append_system_prompt;
+    spawn.request_id = Some(request_id.clone());
+    spawn.reject_if_busy = options.reject_if_busy;
+    if let Some(id) = &conversation_id {
+        spawn.spill.for_conversation(id);
+    }
+    if let (true, Origin::Window(window)) = (spawn.engine.permission_prompts, &origin) {
+        spawn.approval = Some(approval::route_for(&app, window).await?);
+    }
+    let inputs = app.state::<StreamInputs>();
+    let _input = options.stdin_input.map(|format| {
+        let (guard, stdin) = inputs.register(&request_id, format);
+        spawn.stdin = Some(stdin);
+        guard
+    });
+    if let Some(secs) = options.stall_warning_secs {
+        spawn.limits.stall_warning = Duration::from_secs(secs);
+    }
+    if let Some(secs) = options.stall_timeout_secs {
+        spawn.limits.stall_timeout = Duration::from_secs(secs);
+    }
+    let session = spawn.session.clone();
+    let cwd = spawn.effective_cwd();
+    let started = Instant::now();
+    let token = guard.token();
+    let route = api::route(&spawn.engine).await?;
+    let attached = attachments::attach(&app, &message, options.attachments).await?;
+    let message = match &route {
+        Route::Api(_) => attached.api_text().to_string(),
+        Route::Cli => attached.cli_prompt(),
+    };
+    // A fork's first message brings the copied conversation along
+    let replayed = match (&route, &conversation_id) {
+        (Route::Cli, Some(id)) => history::replay_prompt(&app, id, &message)?,
+        _ => None,
+    };
+    let replaying = replayed.is_some();
+    let message = replayed.unwrap_or(message);
+    let sink = claude::window_sink(events.clone(), &settings::load(&app).streaming);
+    let policy = RetryPolicy::from_config(&spawn.engine);
+    let backend = app.state::<ActiveBackend>();
+    let result = match (route, &conversation_id) {
+        // The API is sent the whole conversation from history every turn.
+        // With no CLI process it skips the backend and takes no slot.
+        (Route::Api(key), id) => match api::conversation_turns(&app, id.as_deref(), &message)
+            .and_then(|mut turns| attached.add_images(&mut turns).map(|_| turns))
+        {
+            Ok(turns) => {
+                retry::retry_stream(&policy, &token, sink, |sink| {
+                    api::stream(sink, &key, turns.clone(), token.clone(), spawn.clone())
+                })
+                .await
+            }
+            Err(e) => Err(e),
+        },
+        // The warm process holds the session itself
+        (Route::Cli, Some(id)) => {
+            retry::retry_stream(&policy, &token, sink, |sink| {
+                session::stream_in_session(
+                    &app,
+                    id,
+                    sink,
+                    message.clone(),
+                    token.clone(),
+                    spawn.clone(),
+                )
+            })
+            .await
+        }
+        (Route::Cli, None) => {
+            retry::retry_stream(&policy, &token, sink, |sink| {
+                backend.stream(sink, message.clone(), token.clone(), spawn.clone())
+            })
+            .await
+        }
+    };
+    cooldown.record(&app, &result);
+    if let (Ok(_), Some(id), true) = (&result, &conversation_id, replaying) {
+        let _ = history::mark_replayed(&app, id);
+    }
+    let outcome = result
+        .as_ref()
+        .map(|r| r.text.clone())
+        .map_err(|e| error_text(e.clone()));
+    webhooks::notify(
+        &app,
+        &request_id,
+        started,
+        &outcome,
+        token.is_cancelled(),
+        webhook_url,
+    );
+    if let Ok(reply) = &result {
+        usage_ledger::record(&app, model.as_deref(), reply);
+    }
+    match result {
+        Ok(reply) => Ok(StreamReply {
+            request_id,
+            cwd,
+            reply,
+            cancelled: false,
+            unresolved_mentions,
+        }),
+        // Not a failure: the text so far comes back like a finished reply
+        Err(_) if token.is_cancelled() => Ok(StreamReply {
+            request_id,
+            cwd,
+            reply: Reply {
+                text: events.partial(),
+                session_id: events.session_id(),
+                usage: None,
+                metrics: None,
+                spilled: spill::Spilled::default(),
+            },
+            cancelled: true,
+            unresolved_mentions,
+        }),
+        Err(detail) => {
+            let error = cli_error(detail, &session, model.as_deref());
+            let session_id = events.session_id();
+            if let Some(id) = &session_id {
+                let (message, mut options) = failed_turn;
+                options.request_id = None;
+                options.session_id = Some(id.clone());
+                options.continue_last = false;
+                app.state::<FailedTurns>().record(id, message, options);
+            }
+            let partial = events.partial();
+            if partial.is_empty() {
+                return Err(error);
+            }
+            Err(EngineError::StreamInterrupted {
+                partial,
+                session_id,
+                error: Box::new(error),
+            })
+        }
+    }
+}
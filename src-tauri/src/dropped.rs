@@ -0,0 +1,315 @@
+use crate::attachments::{MAX_ATTACHMENT_BYTES, MAX_INLINE_TEXT_BYTES, MAX_TOTAL_ATTACHMENT_BYTES};
+use crate::encoding;
+use crate::error::EngineError;
+use crate::files::{looks_binary, sniff_mime};
+use crate::listing;
+use crate::sandbox;
+use encoding_rs::Encoding;
+use serde::Serialize;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::Path;
+use tauri::AppHandle;
+
+// Enough to sniff the format and run the binary check
+const HEAD_BYTES: u64 = 8192;
+const MAX_DIR_ENTRIES: usize = 200;
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DroppedKind {
+    Text,
+    Binary,
+    Image,
+    Directory,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DropStatus {
+    Ready,
+    /// Over the per-file limit, so it wasn't read
+    TooLarge,
+    /// Would have taken the drop past `max_total_bytes`, so it wasn't read
+    OverTotal,
+    /// Outside the workspace, missing or unreadable; see `error`
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirSummaryEntry {
+    pub name: String,
+    pub is_dir: bool,
+    /// Files only
+    pub size: Option<u64>,
+    /// Entries directly inside, for directories that could be read
+    pub children: Option<usize>,
+}
+
+/// A dropped directory's top level
+#[derive(Debug, Clone, Serialize)]
+pub struct DirSummary {
+    /// Directories first, then by name
+    pub entries: Vec<DirSummaryEntry>,
+    pub files: usize,
+    pub dirs: usize,
+    /// Entries past the first 200, counted but not listed
+    pub omitted: usize,
+    /// The listing as indented text, to put in the prompt
+    pub tree: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DroppedItem {
+    /// As dropped
+    pub path: String,
+    /// Where it resolved to; what goes in the next turn's `attachments`,
+    /// except for a directory, whose `tree` goes in the message instead
+    pub resolved: Option<String>,
+    pub name: String,
+    pub kind: Option<DroppedKind>,
+    pub status: DropStatus,
+    pub bytes: u64,
+    pub mime: Option<String>,
+    /// Decoded text files, cut at 512 KiB
+    pub content: Option<String>,
+    /// WHATWG name of what the text was decoded from
+    pub encoding: Option<&'static str>,
+    pub truncated: bool,
+    pub directory: Option<DirSummary>,
+    pub warnings: Vec<String>,
+    pub error: Option<EngineError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DropIngest {
+    /// In the order dropped, repeats left out
+    pub items: Vec<DroppedItem>,
+    /// Bytes of the files marked ready
+    pub total_bytes: u64,
+    pub max_total_bytes: u64,
+    /// Paths left out for resolving to one already in the drop
+    pub duplicates: usize,
+}
+
+impl DroppedItem {
+    fn new(path: &str) -> Self {
+        let name = Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string());
+        Self {
+            path: path.to_string(),
+            resolved: None,
+            name,
+            kind: None,
+            status: DropStatus::Failed,
+            bytes: 0,
+            mime: None,
+            content: None,
+            encoding: None,
+            truncated: false,
+            directory: None,
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    fn failed(mut self, error: EngineError) -> Self {
+        self.status = DropStatus::Failed;
+        self.error = Some(error);
+        self
+    }
+}
+
+fn read_head(path: &Path) -> std::io::Result<Vec<u8>> {
+    let mut head = Vec::with_capacity(HEAD_BYTES as usize);
+    std::fs::File::open(path)?
+        .take(HEAD_BYTES)
+        .read_to_end(&mut head)?;
+    Ok(head)
+}
+
+/// UTF-16 text is full of zero bytes, so a BOM settles it before the binary check
+fn classify(head: &[u8]) -> (DroppedKind, &'static str) {
+    match sniff_mime(head) {
+        Some(mime) if mime.starts_with("image/") => (DroppedKind::Image, mime),
+        Some(mime) => (DroppedKind::Binary, mime),
+        None if Encoding::for_bom(head).is_some() => (DroppedKind::Text, "text/plain"),
+        None if looks_binary(head) => (DroppedKind::Binary, "application/octet-stream"),
+        None => (DroppedKind::Text, "text/plain"),
+    }
+}
+
+fn summarize_dir(dir: &Path) -> Result<DirSummary, EngineError> {
+    let mut listed = listing::list(dir, false)?;
+    listed.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+    let dirs = listed.iter().filter(|e| e.is_dir).count();
+    let files = listed.len() - dirs;
+    let omitted = listed.len().saturating_sub(MAX_DIR_ENTRIES);
+    let entries: Vec<DirSummaryEntry> = listed
+        .into_iter()
+        .take(MAX_DIR_ENTRIES)
+        .map(|e| DirSummaryEntry {
+            children: e
+                .is_dir
+                .then(|| std::fs::read_dir(&e.path).ok().map(|d| d.count()))
+                .flatten(),
+            name: e.name,
+            is_dir: e.is_dir,
+            size: e.size,
+        })
+        .collect();
+    let root = dir.file_name().map_or_else(
+        || dir.display().to_string(),
+        |n| n.to_string_lossy().into_owned(),
+    );
+    let mut tree = format!("{}/ ({} directories, {} files)", root, dirs, files);
+    for entry in &entries {
+        tree.push_str("\n  ");
+        tree.push_str(&entry.name);
+        match (entry.is_dir, entry.children, entry.size) {
+            (true, Some(children), _) => tree.push_str(&format!("/ ({} entries)", children)),
+            (true, None, _) => tree.push('/'),
+            (false, _, Some(size)) => tree.push_str(&format!(" ({} bytes)", size)),
+            (false, _, None) => {}
+        }
+    }
+    if omitted > 0 {
+        tree.push_str(&format!("\n  … and {} more", omitted));
+    }
+    Ok(DirSummary {
+        entries,
+        files,
+        dirs,
+        omitted,
+        tree,
+    })
+}
+
+fn read_text(path: &Path, item: &mut DroppedItem) -> Result<(), EngineError> {
+    let bytes = std::fs::read(path).map_err(|e| EngineError::fs(path, e))?;
+    let decoded = encoding::decode(&bytes, true).map_err(|name| {
+        EngineError::invalid(format!("{} can't be decoded as {}", path.display(), name))
+    })?;
+    if decoded.lossy {
+        item.warnings.push(format!(
+            "Some bytes weren't valid {} and were replaced",
+            decoded.encoding
+        ));
+    }
+    let mut content = decoded.content;
+    if content.len() > MAX_INLINE_TEXT_BYTES {
+        let mut cut = MAX_INLINE_TEXT_BYTES;
+        while !content.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        content.truncate(cut);
+        item.truncated = true;
+        item.warnings.push(format!(
+            "Truncated to the first {} bytes",
+            MAX_INLINE_TEXT_BYTES
+        ));
+    }
+    item.content = Some(content);
+    item.encoding = Some(decoded.encoding);
+    Ok(())
+}
+
+/// One path of the drop; `total` is what the ready files so far add up to
+fn ingest(path: &str, resolved: &Path, total: &mut u64, limit: u64) -> DroppedItem {
+    let mut item = DroppedItem::new(path);
+    item.resolved = Some(resolved.display().to_string());
+    let meta = match std::fs::metadata(resolved) {
+        Ok(meta) => meta,
+        Err(e) => return item.failed(EngineError::fs(path, e)),
+    };
+    if meta.is_dir() {
+        item.kind = Some(DroppedKind::Directory);
+        return match summarize_dir(resolved) {
+            Ok(summary) => {
+                item.directory = Some(summary);
+                item.status = DropStatus::Ready;
+                item
+            }
+            Err(e) => item.failed(e),
+        };
+    }
+    item.bytes = meta.len();
+    let head = match read_head(resolved) {
+        Ok(head) => head,
+        Err(e) => return item.failed(EngineError::fs(path, e)),
+    };
+    let (kind, mime) = classify(&head);
+    item.kind = Some(kind);
+    item.mime = Some(mime.to_string());
+    if item.bytes > MAX_ATTACHMENT_BYTES {
+        item.status = DropStatus::TooLarge;
+        item.error = Some(EngineError::FileTooLarge {
+            path: path.to_string(),
+            size: item.bytes,
+            limit: MAX_ATTACHMENT_BYTES,
+        });
+        return item;
+    }
+    if *total + item.bytes > limit {
+        item.status = DropStatus::OverTotal;
+        item.error = Some(EngineError::AttachmentsTooLarge {
+            total: *total + item.bytes,
+            limit,
+        });
+        return item;
+    }
+    if kind == DroppedKind::Text {
+        if let Err(e) = read_text(resolved, &mut item) {
+            return item.failed(e);
+        }
+    }
+    *total += item.bytes;
+    item.status = DropStatus::Ready;
+    item
+}
+
+/// Turn paths dropped on the window into attachment chips: each is checked
+/// against the workspace, then text files are read and decoded, directories
+/// get a summary of their top level, and images and other files are sized
+/// and typed for sending by path. Files over 20 MiB, or past
+/// `max_total_bytes` (50 MiB by default) for the whole drop, are listed but
+/// not read. A path that fails doesn't fail the rest, and one dropped twice
+/// is kept once.
+#[tauri::command]
+pub async fn ingest_dropped_paths(
+    app: AppHandle,
+    paths: Vec<String>,
+    max_total_bytes: Option<u64>,
+) -> Result<DropIngest, EngineError> {
+    let limit = max_total_bytes.unwrap_or(MAX_TOTAL_ATTACHMENT_BYTES);
+    tokio::task::spawn_blocking(move || {
+        let mut seen = HashSet::new();
+        let mut items = Vec::with_capacity(paths.len());
+        let mut total = 0;
+        let mut duplicates = 0;
+        for path in &paths {
+            let resolved = match sandbox::allow(&app, path) {
+                Ok(resolved) => resolved,
+                Err(e) => {
+                    items.push(DroppedItem::new(path).failed(e));
+                    continue;
+                }
+            };
+            if !seen.insert(resolved.clone()) {
+                duplicates += 1;
+                continue;
+            }
+            items.push(ingest(path, &resolved, &mut total, limit));
+        }
+        DropIngest {
+            items,
+            total_bytes: total,
+            max_total_bytes: limit,
+            duplicates,
+        }
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
@@ -0,0 +1,83 @@
+use crate::error::EngineError;
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use serde::Serialize;
+
+/// Text read from a file, with what it was decoded from so a save can
+/// write it back the same way
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedText {
+    pub content: String,
+    /// WHATWG name, e.g. `UTF-8`, `UTF-16LE`, `windows-1252`
+    pub encoding: &'static str,
+    pub bom: bool,
+    /// Bytes that weren't valid in the encoding were replaced with U+FFFD
+    pub lossy: bool,
+}
+
+/// The BOM's encoding if there is one, then UTF-8, then chardetng's guess.
+/// Without `lossy` a file that doesn't decode cleanly fails with the name of
+/// the encoding it was tried as.
+pub fn decode(bytes: &[u8], lossy: bool) -> Result<DecodedText, &'static str> {
+    let (encoding, bom_len) = match Encoding::for_bom(bytes) {
+        Some(found) => found,
+        None if std::str::from_utf8(bytes).is_ok() => (UTF_8, 0),
+        None => {
+            let mut detector = EncodingDetector::new();
+            detector.feed(bytes, true);
+            (detector.guess(None, true), 0)
+        }
+    };
+    let (content, had_errors) = encoding.decode_without_bom_handling(&bytes[bom_len..]);
+    if had_errors && !lossy {
+        return Err(encoding.name());
+    }
+    Ok(DecodedText {
+        content: content.into_owned(),
+        encoding: encoding.name(),
+        bom: bom_len > 0,
+        lossy: had_errors,
+    })
+}
+
+/// `text` in the encoding named by `label`, with a BOM if asked for. Text the
+/// encoding can't represent is refused rather than written as `&#...;`.
+pub fn encode(text: &str, label: &str, bom: bool) -> Result<Vec<u8>, EngineError> {
+    let encoding = Encoding::for_label(label.trim().as_bytes())
+        .ok_or_else(|| EngineError::invalid(format!("Unknown encoding '{}'", label)))?;
+    // encoding_rs only decodes UTF-16, so it's written out here
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let little = encoding == UTF_16LE;
+        let mut bytes = Vec::with_capacity(text.len() * 2 + 2);
+        for unit in std::iter::once(0xFEFF)
+            .filter(|_| bom)
+            .chain(text.encode_utf16())
+        {
+            bytes.extend(if little {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            });
+        }
+        return Ok(bytes);
+    }
+    if encoding.output_encoding() != encoding {
+        return Err(EngineError::invalid(format!(
+            "Files can't be written as {}",
+            encoding.name()
+        )));
+    }
+    let (encoded, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(EngineError::invalid(format!(
+            "The text has characters {} can't represent",
+            encoding.name()
+        )));
+    }
+    let mut bytes = Vec::with_capacity(encoded.len() + 3);
+    if bom && encoding == UTF_8 {
+        bytes.extend_from_slice(b"\xEF\xBB\xBF");
+    }
+    bytes.extend_from_slice(&encoded);
+    Ok(bytes)
+}
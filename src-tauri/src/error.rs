@@ -0,0 +1,456 @@
+use crate::estimate::Estimate;
+use serde::Serialize;
+use std::fmt;
+
+/// Structured error returned to the frontend as `{ kind: "...", ...fields }`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "kind")]
+pub enum EngineError {
+    Io {
+        path: String,
+        detail: String,
+    },
+    /// The path doesn't exist; from [`EngineError::fs`]
+    NotFound {
+        path: String,
+    },
+    /// The OS refused access to the path; from [`EngineError::fs`]
+    PermissionDenied {
+        path: String,
+    },
+    /// The OS couldn't move this path to the trash, e.g. on a network drive;
+    /// deleting it permanently is the fallback
+    TrashUnavailable {
+        path: String,
+        detail: String,
+    },
+    InvalidInput {
+        detail: String,
+    },
+    Internal {
+        detail: String,
+    },
+    /// Neither the CLI script nor a node to run it with could be found
+    CliNotFound,
+    SpawnFailed {
+        detail: String,
+    },
+    /// A requested working directory that doesn't exist or isn't a directory
+    InvalidWorkingDirectory {
+        path: String,
+        detail: String,
+    },
+    /// The OS keychain is locked, denied access or isn't running
+    SecretStoreUnavailable {
+        detail: String,
+    },
+    /// The API backend was chosen but no key is stored or in the environment
+    ApiKeyMissing,
+    /// The Messages API refused or failed a request; `status` is None for an
+    /// error reported mid-stream
+    ApiFailed {
+        status: Option<u16>,
+        detail: String,
+        /// From the response's `retry-after` header
+        retry_after_secs: Option<u64>,
+    },
+    /// No npm to install the CLI with; Node.js has to be installed first
+    NpmNotFound,
+    /// `npm install` for the CLI failed; `detail` is the end of its output
+    InstallFailed {
+        code: Option<i32>,
+        detail: String,
+    },
+    /// The CLI has no login or key to use; `start_claude_login` fixes that
+    AuthRequired {
+        detail: String,
+    },
+    /// The CLI ran but failed; `code` is None when it didn't exit on its own
+    CliExited {
+        code: Option<i32>,
+        stderr: String,
+    },
+    Cancelled,
+    /// Every CLI slot is taken and the request asked not to wait for one;
+    /// see `engine.max_concurrent_cli`
+    TooManyRequests {
+        running: usize,
+        limit: usize,
+    },
+    /// The request has no stdin to write to: it never kept one open, or the
+    /// CLI has exited or had its input ended
+    StreamClosed {
+        request_id: String,
+    },
+    /// A stream failed after some of the reply was shown; `partial` is that
+    /// text, and `retry_last_turn` picks the turn up again in `session_id`
+    StreamInterrupted {
+        partial: String,
+        session_id: Option<String>,
+        error: Box<EngineError>,
+    },
+    Timeout {
+        after_ms: u64,
+    },
+    Network {
+        detail: String,
+    },
+    UrlNotAllowed {
+        url: String,
+        reason: String,
+    },
+    UnsupportedContentType {
+        content_type: String,
+    },
+    PdfEncrypted {
+        path: String,
+    },
+    PdfInvalid {
+        path: String,
+        detail: String,
+    },
+    // Only the updater raises these two
+    #[cfg_attr(not(feature = "updater"), allow(dead_code))]
+    UpdateSignatureInvalid {
+        detail: String,
+    },
+    #[cfg_attr(not(feature = "updater"), allow(dead_code))]
+    InsufficientDisk {
+        detail: String,
+    },
+    UpdateFailed {
+        detail: String,
+    },
+    /// Relaunching now would interrupt these requests
+    RequestsInFlight {
+        request_ids: Vec<String>,
+    },
+    AlreadyExists {
+        path: String,
+    },
+    PathOutsideWorkspace {
+        path: String,
+    },
+    /// A unified diff that doesn't fit the file; nothing was written
+    PatchFailed {
+        path: String,
+        /// 1-based; empty when the patch itself is malformed
+        failed_hunks: Vec<usize>,
+        total_hunks: usize,
+        detail: String,
+    },
+    /// Not valid text in the encoding it was detected as; reading with
+    /// `lossy` replaces the bad bytes instead
+    DecodeFailed {
+        path: String,
+        encoding: String,
+    },
+    /// Over the caller's `max_bytes`; page through it with `read_file_range`
+    FileTooLarge {
+        path: String,
+        size: u64,
+        limit: u64,
+    },
+    /// A turn's attachments add up to more than the total allowed; each one
+    /// over its own limit is a `FileTooLarge`
+    AttachmentsTooLarge {
+        total: u64,
+        limit: u64,
+    },
+    Conflict {
+        path: String,
+        expected: Option<String>,
+        actual: Option<String>,
+    },
+    /// Nothing recognizable to run tests with; the UI should ask for a command
+    NoTestCommand {
+        root: String,
+    },
+    FormatterNotFound {
+        language: String,
+    },
+    FormatFailed {
+        stderr: String,
+    },
+    /// No `git` binary on PATH or in the workspace's environment profile
+    GitNotFound,
+    NotAGitRepository {
+        path: String,
+    },
+    /// git ran but failed; `detail` is its stderr
+    GitFailed {
+        detail: String,
+    },
+    /// Placeholders left without a value, in order of first use
+    TemplateVariablesMissing {
+        names: Vec<String>,
+    },
+    /// A `file:`, `git:` etc. placeholder couldn't be filled
+    TemplateExpansionFailed {
+        variable: String,
+        detail: String,
+    },
+    /// `variable` is None when the total across all placeholders is over
+    TemplateBudgetExceeded {
+        variable: Option<String>,
+        bytes: usize,
+        limit: usize,
+    },
+    /// The OS hasn't granted screen recording; `guidance` says where to allow it
+    ScreenCapturePermissionDenied {
+        guidance: String,
+    },
+    ScreenCaptureUnsupported {
+        detail: String,
+    },
+    /// A pipeline stopped at this step; later steps didn't run
+    PipelineStepFailed {
+        step_index: usize,
+        name: Option<String>,
+        detail: String,
+    },
+    /// Over a confirmation threshold; repeat the call with `confirmed: true`
+    ConfirmationRequired {
+        estimate: Box<Estimate>,
+    },
+    /// The workspace sandbox is on and the program isn't in
+    /// `commands.allowed_programs`; repeat the call with `confirmed: true`
+    CommandNotAllowed {
+        program: String,
+    },
+    /// The CLI has no session by this id any more; send without it to start anew
+    SessionNotFound {
+        session_id: String,
+    },
+    /// The CLI didn't recognize the model; `detail` is what it said
+    UnknownModel {
+        model: String,
+        detail: String,
+    },
+}
+
+impl EngineError {
+    pub fn io(path: impl AsRef<std::path::Path>, err: impl fmt::Display) -> Self {
+        EngineError::Io {
+            path: path.as_ref().display().to_string(),
+            detail: err.to_string(),
+        }
+    }
+
+    /// [`EngineError::io`], except that a missing path and a refused one get
+    /// their own kinds, for callers where the UI reacts to those differently
+    pub fn fs(path: impl AsRef<std::path::Path>, err: std::io::Error) -> Self {
+        let path = path.as_ref().display().to_string();
+        // Missing files are routine: existence checks, optional config
+        if err.kind() == std::io::ErrorKind::NotFound {
+            tracing::debug!(path = %path, error = %err, "Filesystem operation failed");
+        } else {
+            tracing::warn!(path = %path, error = %err, "Filesystem operation failed");
+        }
+        match err.kind() {
+            std::io::ErrorKind::NotFound => EngineError::NotFound { path },
+            std::io::ErrorKind::PermissionDenied => EngineError::PermissionDenied { path },
+            _ => EngineError::Io {
+                path,
+                detail: err.to_string(),
+            },
+        }
+    }
+
+    pub fn invalid(detail: impl Into<String>) -> Self {
+        EngineError::InvalidInput {
+            detail: detail.into(),
+        }
+    }
+
+    pub fn internal(detail: impl Into<String>) -> Self {
+        EngineError::Internal {
+            detail: detail.into(),
+        }
+    }
+}
+
+impl fmt::Display for EngineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EngineError::Io { path, detail } if path.is_empty() => write!(f, "I/O error: {}", detail),
+            EngineError::Io { path, detail } => write!(f, "I/O error on {}: {}", path, detail),
+            EngineError::NotFound { path } => write!(f, "{} does not exist", path),
+            EngineError::PermissionDenied { path } => write!(f, "Permission denied: {}", path),
+            EngineError::TrashUnavailable { path, detail } => {
+                write!(f, "Couldn't move {} to the trash: {}", path, detail)
+            }
+            EngineError::InvalidInput { detail } => write!(f, "Invalid input: {}", detail),
+            EngineError::Internal { detail } => write!(f, "Internal error: {}", detail),
+            EngineError::CliNotFound => write!(
+                f,
+                "Claude CLI not found. Please install it with: npm install -g @anthropic-ai/claude-code"
+            ),
+            EngineError::SpawnFailed { detail } => {
+                write!(f, "Failed to spawn Claude CLI: {}. Make sure node is installed.", detail)
+            }
+            EngineError::CliExited { stderr, .. } => write!(f, "Claude CLI error: {}", stderr),
+            EngineError::InvalidWorkingDirectory { path, detail } => {
+                write!(f, "Can't run in {}: {}", path, detail)
+            }
+            EngineError::SecretStoreUnavailable { detail } => {
+                write!(f, "The system keychain is unavailable: {}", detail)
+            }
+            EngineError::ApiKeyMissing => write!(
+                f,
+                "No Anthropic API key. Save one in settings or set ANTHROPIC_API_KEY"
+            ),
+            EngineError::ApiFailed { detail, .. } => write!(f, "Anthropic API error: {}", detail),
+            EngineError::NpmNotFound => write!(
+                f,
+                "npm not found. Install Node.js from https://nodejs.org, then try again"
+            ),
+            EngineError::InstallFailed { detail, .. } => {
+                write!(f, "Installing the Claude CLI failed: {}", detail)
+            }
+            EngineError::AuthRequired { detail } => {
+                write!(f, "The Claude CLI needs you to log in: {}", detail)
+            }
+            EngineError::Cancelled => write!(f, "Cancelled by user"),
+            EngineError::TooManyRequests { running, limit } => write!(
+                f,
+                "{} requests are already running, the most allowed at once is {}",
+                running, limit
+            ),
+            EngineError::StreamClosed { request_id } => {
+                write!(f, "Request {} is no longer taking input", request_id)
+            }
+            EngineError::StreamInterrupted { error, .. } => write!(f, "{}", error),
+            EngineError::Timeout { after_ms } => write!(f, "Timed out after {}ms", after_ms),
+            EngineError::Network { detail } => write!(f, "Network error: {}", detail),
+            EngineError::UrlNotAllowed { url, reason } => {
+                write!(f, "Refusing to fetch {}: {}", url, reason)
+            }
+            EngineError::UnsupportedContentType { content_type } => {
+                write!(f, "Unsupported content type: {}", content_type)
+            }
+            EngineError::PdfEncrypted { path } => {
+                write!(f, "{} is password-protected", path)
+            }
+            EngineError::PdfInvalid { path, detail } => {
+                write!(f, "{} is not a readable PDF: {}", path, detail)
+            }
+            EngineError::UpdateSignatureInvalid { detail } => {
+                write!(f, "Update signature check failed: {}", detail)
+            }
+            EngineError::InsufficientDisk { detail } => {
+                write!(f, "Not enough disk space: {}", detail)
+            }
+            EngineError::UpdateFailed { detail } => write!(f, "Update failed: {}", detail),
+            EngineError::RequestsInFlight { request_ids } => {
+                write!(f, "{} request(s) still running", request_ids.len())
+            }
+            EngineError::AlreadyExists { path } => write!(f, "{} already exists", path),
+            EngineError::PathOutsideWorkspace { path } => {
+                write!(f, "{} is outside the workspace", path)
+            }
+            EngineError::PatchFailed { path, detail, .. } => {
+                write!(f, "Patch doesn't apply to {}: {}", path, detail)
+            }
+            EngineError::DecodeFailed { path, encoding } => {
+                write!(f, "{} is not valid {} text", path, encoding)
+            }
+            EngineError::FileTooLarge { path, size, limit } => write!(
+                f,
+                "{} is {} bytes, over the {} byte limit",
+                path, size, limit
+            ),
+            EngineError::AttachmentsTooLarge { total, limit } => write!(
+                f,
+                "Attachments come to {} bytes, over the {} byte limit",
+                total, limit
+            ),
+            EngineError::Conflict { path, .. } => {
+                write!(f, "{} was modified since it was last read", path)
+            }
+            EngineError::NoTestCommand { root } => {
+                write!(f, "No test command found for {}", root)
+            }
+            EngineError::GitNotFound => write!(f, "git was not found on PATH"),
+            EngineError::NotAGitRepository { path } => {
+                write!(f, "{} is not in a git repository", path)
+            }
+            EngineError::GitFailed { detail } => write!(f, "git failed: {}", detail),
+            EngineError::FormatterNotFound { language } => {
+                write!(f, "No formatter found for {}", language)
+            }
+            EngineError::FormatFailed { stderr } => write!(f, "Formatter failed: {}", stderr),
+            EngineError::TemplateVariablesMissing { names } => {
+                write!(f, "Missing template variables: {}", names.join(", "))
+            }
+            EngineError::TemplateExpansionFailed { variable, detail } => {
+                write!(f, "Could not expand {{{{{}}}}}: {}", variable, detail)
+            }
+            EngineError::TemplateBudgetExceeded {
+                variable,
+                bytes,
+                limit,
+            } => match variable {
+                Some(variable) => write!(
+                    f,
+                    "{{{{{}}}}} is {} bytes, over the {} byte limit",
+                    variable, bytes, limit
+                ),
+                None => write!(
+                    f,
+                    "Expanded variables total {} bytes, over the {} byte limit",
+                    bytes, limit
+                ),
+            },
+            EngineError::ScreenCapturePermissionDenied { guidance } => {
+                write!(f, "Screen recording permission is required. {}", guidance)
+            }
+            EngineError::ScreenCaptureUnsupported { detail } => write!(f, "{}", detail),
+            EngineError::PipelineStepFailed {
+                step_index,
+                name,
+                detail,
+            } => match name {
+                Some(name) => write!(
+                    f,
+                    "Pipeline step {} ({}) failed: {}",
+                    step_index + 1,
+                    name,
+                    detail
+                ),
+                None => write!(f, "Pipeline step {} failed: {}", step_index + 1, detail),
+            },
+            EngineError::ConfirmationRequired { estimate } => write!(
+                f,
+                "About {} input tokens; confirm to send",
+                estimate.input_tokens
+            ),
+            EngineError::CommandNotAllowed { program } => {
+                write!(f, "{} isn't an allowed program; confirm to run it", program)
+            }
+            EngineError::SessionNotFound { session_id } => {
+                write!(f, "Claude CLI session {} no longer exists", session_id)
+            }
+            EngineError::UnknownModel { model, .. } => write!(f, "Unknown model {}", model),
+        }
+    }
+}
+
+impl std::error::Error for EngineError {}
+
+// `?` on I/O where there's no path worth naming
+impl From<std::io::Error> for EngineError {
+    fn from(err: std::io::Error) -> Self {
+        EngineError::Io {
+            path: String::new(),
+            detail: err.to_string(),
+        }
+    }
+}
+
+// Lower layers that only have a message to give
+impl From<String> for EngineError {
+    fn from(detail: String) -> Self {
+        EngineError::Internal { detail }
+    }
+}
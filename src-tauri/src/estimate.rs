@@ -0,0 +1,374 @@
+use crate::attachments::{self, AttachmentKind, AttachmentOptions, AttachmentRequest};
+use crate::context::{build_context, ContextOptions, ExclusionReason};
+use crate::error::EngineError;
+use crate::index::IndexRegistry;
+use crate::models;
+use crate::paths::app_cache_subdir;
+use crate::project_context;
+use crate::sandbox::Workspace;
+use crate::settings::{self, EstimateSettings, ModelPrice};
+use crate::template_vars::{self, ExpandOptions};
+use crate::templates;
+use crate::tokens::{self, count_tokens};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::Path;
+use tauri::{AppHandle, Manager};
+
+// What the CLI runs when no model is given, for pricing purposes
+const DEFAULT_MODEL: &str = "sonnet";
+// Anthropic's published rule of thumb for image input
+const IMAGE_PIXELS_PER_TOKEN: u64 = 750;
+
+/// USD per million tokens, matched as a substring of the model id; the
+/// longest match wins, and `estimates.pricing` in settings overrides these
+const BUILT_IN_PRICING: &[(&str, f64, f64)] = &[
+    ("opus-4-5", 5.0, 25.0),
+    ("opus", 15.0, 75.0),
+    ("sonnet", 3.0, 15.0),
+    ("haiku-4-5", 1.0, 5.0),
+    ("haiku", 0.8, 4.0),
+];
+
+/// A single prompt, or a conversation sent as a whole
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PromptInput {
+    Text(String),
+    Messages(Vec<PromptMessage>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PromptMessage {
+    pub role: String,
+    pub content: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct EstimateOptions {
+    pub model: Option<String>,
+    /// Saved template rendered ahead of the message, with `vars` and providers
+    pub template: Option<String>,
+    pub vars: BTreeMap<String, String>,
+    pub expand: ExpandOptions,
+    /// Pack this workspace's files in as project context
+    pub context_root: Option<String>,
+    pub context: ContextOptions,
+    /// Where the turn would run, for the project context it picks up
+    pub cwd: Option<String>,
+    pub attachments: Vec<AttachmentRequest>,
+    pub attachment_options: AttachmentOptions,
+    /// Reply length to price in; only input is priced otherwise
+    pub expected_output_tokens: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ComponentKind {
+    Message,
+    Template,
+    Expansion,
+    Context,
+    ProjectContext,
+    Attachment,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct EstimateComponent {
+    pub kind: ComponentKind,
+    pub label: String,
+    pub tokens: usize,
+    /// E.g. how many files were trimmed to fit the context budget
+    pub note: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Estimate {
+    pub model: String,
+    pub input_tokens: usize,
+    pub output_tokens: Option<usize>,
+    /// `None` when no price is known for the model
+    pub cost_usd: Option<f64>,
+    pub pricing: Option<ModelPrice>,
+    pub components: Vec<EstimateComponent>,
+    pub warnings: Vec<String>,
+    /// Over a confirmation threshold; send commands refuse until confirmed
+    pub needs_confirmation: bool,
+}
+
+fn price_for(model: &str, overrides: &BTreeMap<String, ModelPrice>) -> Option<ModelPrice> {
+    let model = model.to_ascii_lowercase();
+    let mut table: BTreeMap<String, ModelPrice> = BUILT_IN_PRICING
+        .iter()
+        .map(|&(key, input, output)| {
+            (
+                key.to_string(),
+                ModelPrice {
+                    input_per_mtok: input,
+                    output_per_mtok: output,
+                },
+            )
+        })
+        .collect();
+    for (key, price) in overrides {
+        table.insert(key.to_ascii_lowercase(), price.clone());
+    }
+    table
+        .into_iter()
+        .filter(|(key, _)| model.contains(key.as_str()))
+        .max_by_key(|(key, _)| key.len())
+        .map(|(_, price)| price)
+}
+
+fn finish(
+    model: Option<&str>,
+    components: Vec<EstimateComponent>,
+    output_tokens: Option<usize>,
+    config: &EstimateSettings,
+) -> Estimate {
+    let model = model.unwrap_or(DEFAULT_MODEL).to_string();
+    let input_tokens = components.iter().map(|c| c.tokens).sum();
+    let pricing = price_for(&model, &config.pricing);
+    let cost_usd = pricing.as_ref().map(|price| {
+        (input_tokens as f64 * price.input_per_mtok
+            + output_tokens.unwrap_or(0) as f64 * price.output_per_mtok)
+            / 1_000_000.0
+    });
+
+    let mut warnings = Vec::new();
+    if input_tokens >= config.warn_tokens {
+        warnings.push(format!(
+            "About {} input tokens, over the {} token warning threshold",
+            input_tokens, config.warn_tokens
+        ));
+    }
+    if let (Some(cost), Some(limit)) = (cost_usd, config.warn_cost_usd) {
+        if cost >= limit {
+            warnings.push(format!(
+                "Estimated cost ${:.2} is over the ${:.2} warning threshold",
+                cost, limit
+            ));
+        }
+    }
+    if pricing.is_none() {
+        warnings.push(format!("No price known for {}; cost not estimated", model));
+    }
+    let needs_confirmation = input_tokens >= config.confirm_tokens
+        || matches!((cost_usd, config.confirm_cost_usd), (Some(cost), Some(limit)) if cost >= limit);
+
+    Estimate {
+        model,
+        input_tokens,
+        output_tokens,
+        cost_usd,
+        pricing,
+        components,
+        warnings,
+        needs_confirmation,
+    }
+}
+
+fn message_components(input: &PromptInput) -> Vec<EstimateComponent> {
+    let component = |label: String, text: &str| EstimateComponent {
+        kind: ComponentKind::Message,
+        label,
+        tokens: count_tokens(text),
+        note: None,
+    };
+    match input {
+        PromptInput::Text(text) => vec![component("message".to_string(), text)],
+        PromptInput::Messages(messages) => messages
+            .iter()
+            .enumerate()
+            .map(|(i, m)| component(format!("{} #{}", m.role, i + 1), &m.content))
+            .collect(),
+    }
+}
+
+fn attachment_component(
+    request: &AttachmentRequest,
+    options: &AttachmentOptions,
+    temp_dir: &Path,
+) -> Result<EstimateComponent, EngineError> {
+    let prepared = attachments::prepare(request, options, temp_dir)?;
+    let (tokens, note) = match (&prepared.inline_text, prepared.kind, &prepared.image) {
+        (Some(text), _, _) => (count_tokens(text), None),
+        (None, AttachmentKind::Image, Some(image)) => {
+            let size = match &image.processed {
+                Some(processed) => Some((processed.width, processed.height)),
+                None => image.original_width.zip(image.original_height),
+            };
+            match size {
+                Some((w, h)) => (
+                    (w as u64 * h as u64).div_ceil(IMAGE_PIXELS_PER_TOKEN) as usize,
+                    Some(format!("{}x{} image", w, h)),
+                ),
+                None => (0, Some("Image size unknown".to_string())),
+            }
+        }
+        // Passed by path: only the path itself is in the prompt
+        (None, _, _) => (
+            count_tokens(&prepared.path),
+            Some("Passed by reference".to_string()),
+        ),
+    };
+    Ok(EstimateComponent {
+        kind: ComponentKind::Attachment,
+        label: prepared.name,
+        tokens,
+        note,
+    })
+}
+
+/// Assemble the prompt the way a send would, without sending it, and price it
+pub async fn estimate(
+    app: &AppHandle,
+    input: &PromptInput,
+    options: EstimateOptions,
+) -> Result<Estimate, EngineError> {
+    let config = settings::load(app).estimates;
+    let mut components = Vec::new();
+
+    if let Some(name) = &options.template {
+        let body = templates::load(app, name)?;
+        let expanded = template_vars::expand(app, &body, &options.vars, &options.expand).await?;
+        let expansion_tokens: usize = expanded.report.iter().map(|item| item.tokens).sum();
+        components.push(EstimateComponent {
+            kind: ComponentKind::Template,
+            label: name.clone(),
+            tokens: count_tokens(&expanded.prompt).saturating_sub(expansion_tokens),
+            note: None,
+        });
+        components.extend(expanded.report.into_iter().map(|item| EstimateComponent {
+            kind: ComponentKind::Expansion,
+            label: item.variable,
+            tokens: item.tokens,
+            note: item.truncated.then(|| "Truncated".to_string()),
+        }));
+    }
+
+    components.extend(message_components(input));
+
+    if let Some(root) = &options.context_root {
+        let root = app.state::<Workspace>().check(Path::new(root))?;
+        let index = app.state::<IndexRegistry>().fresh(app, &root);
+        let context_options = options.context;
+        let context = tokio::task::spawn_blocking(move || {
+            build_context(&root, &context_options, index.as_deref())
+        })
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+        let trimmed = context
+            .excluded
+            .iter()
+            .filter(|f| matches!(f.reason, ExclusionReason::OverBudget))
+            .count();
+        components.push(EstimateComponent {
+            kind: ComponentKind::Context,
+            label: context.root,
+            tokens: context.total_tokens,
+            note: Some(format!(
+                "{} files included, {} trimmed to fit the budget",
+                context.included.len(),
+                trimmed
+            )),
+        });
+    }
+
+    if let Some(cwd) = options.cwd.as_deref().filter(|c| !c.trim().is_empty()) {
+        let read = app.clone();
+        let cwd = Path::new(cwd).to_path_buf();
+        let context = tokio::task::spawn_blocking(move || project_context::for_cwd(&read, &cwd))
+            .await
+            .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+        if let Some(context) = context {
+            components.push(EstimateComponent {
+                kind: ComponentKind::ProjectContext,
+                label: "project context".to_string(),
+                tokens: count_tokens(&context),
+                note: None,
+            });
+        }
+    }
+
+    if !options.attachments.is_empty() {
+        let temp_dir = app_cache_subdir(app, "attachments")?;
+        let requests = options.attachments;
+        let attachment_options = options.attachment_options;
+        let attached = tokio::task::spawn_blocking(move || {
+            requests
+                .iter()
+                .map(|request| attachment_component(request, &attachment_options, &temp_dir))
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+        components.extend(attached);
+    }
+
+    Ok(finish(
+        options.model.as_deref(),
+        components,
+        options.expected_output_tokens,
+        &config,
+    ))
+}
+
+/// Refuse a send that crosses a confirmation threshold, when the setting is
+/// on, until the caller repeats it with `confirmed`
+pub fn confirm_send(
+    app: &AppHandle,
+    message: &str,
+    model: Option<&str>,
+    confirmed: Option<bool>,
+) -> Result<(), EngineError> {
+    let config = settings::load(app).estimates;
+    if !config.confirm_before_send || confirmed == Some(true) {
+        return Ok(());
+    }
+    let input = PromptInput::Text(message.to_string());
+    let estimate = finish(model, message_components(&input), None, &config);
+    if estimate.needs_confirmation {
+        return Err(EngineError::ConfirmationRequired {
+            estimate: Box::new(estimate),
+        });
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TokenEstimate {
+    pub tokens: usize,
+    /// "cl100k_base", or "chars/4" when the tokenizer couldn't load
+    pub method: &'static str,
+    pub context_window: usize,
+}
+
+/// Token count for text as typed, cheap enough to run on every debounced
+/// keystroke; `estimate_request` covers templates, context and attachments
+#[tauri::command]
+pub async fn estimate_tokens(
+    text: String,
+    model: Option<String>,
+) -> Result<TokenEstimate, EngineError> {
+    let model = models::requested_model(model)?;
+    Ok(TokenEstimate {
+        tokens: count_tokens(&text),
+        method: if tokens::has_tokenizer() {
+            "cl100k_base"
+        } else {
+            "chars/4"
+        },
+        context_window: models::context_window(model.as_deref()),
+    })
+}
+
+#[tauri::command]
+pub async fn estimate_request(
+    app: AppHandle,
+    message: PromptInput,
+    options: Option<EstimateOptions>,
+) -> Result<Estimate, EngineError> {
+    estimate(&app, &message, options.unwrap_or_default()).await
+}
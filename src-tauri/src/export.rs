@@ -0,0 +1,750 @@
+use crate::clock::{now_millis, utc_date};
+use crate::env_profile::ChildEnv;
+use crate::error::EngineError;
+use crate::files::{atomic_write, resolve_target, sniff_mime, IfExists};
+use crate::hash::{random_hex, sha256_hex};
+use crate::history::{self, Conversation};
+use crate::markdown::{escape_html, render_markdown_offline, standalone_html, DOCUMENT_STYLE};
+use crate::paths::{app_cache_subdir, canonicalize};
+use crate::postprocess::unclosed_fence;
+use crate::runner::{find_program, run_captured, RunSpec};
+use crate::sandbox::Workspace;
+use crate::settings;
+use base64::Engine;
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, State};
+
+const SLUG_CHARS: usize = 60;
+const MAX_EMBEDDED_IMAGE_BYTES: u64 = 5 * 1024 * 1024;
+const PDF_TIMEOUT: Duration = Duration::from_secs(90);
+
+// Print rules so long transcripts paginate at message boundaries where they can
+const TRANSCRIPT_STYLE: &str = ".meta{color:#59636e;font-size:.9em}\
+.message{margin:1.5rem 0;padding-top:.5rem;border-top:1px solid #d0d7de}\
+.message h2{font-size:.95em;margin:0 0 .5rem;text-transform:capitalize}\
+.message.user h2{color:#0969da}.message.thinking,.message.tool{color:#59636e;font-size:.9em}\
+.message time{font-weight:normal;color:#59636e;margin-left:.5rem}\
+img{max-width:100%}\
+@media print{body{max-width:none;margin:0}\
+pre{white-space:pre-wrap;word-break:break-word;overflow:visible}\
+.message h2{break-after:avoid}pre,table,img,blockquote{break-inside:avoid}\
+@page{margin:18mm 16mm}}";
+
+// Chromium-based browsers that can print a page to PDF headlessly
+#[cfg(windows)]
+const PDF_BROWSERS: &[&str] = &[
+    r"Microsoft\Edge\Application\msedge.exe",
+    r"Google\Chrome\Application\chrome.exe",
+];
+#[cfg(target_os = "macos")]
+const PDF_BROWSERS: &[&str] = &[
+    "/Applications/Google Chrome.app/Contents/MacOS/Google Chrome",
+    "/Applications/Microsoft Edge.app/Contents/MacOS/Microsoft Edge",
+    "/Applications/Chromium.app/Contents/MacOS/Chromium",
+];
+#[cfg(not(any(windows, target_os = "macos")))]
+const PDF_BROWSERS: &[&str] = &[
+    "chromium",
+    "chromium-browser",
+    "google-chrome",
+    "google-chrome-stable",
+    "microsoft-edge",
+];
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Md,
+    Txt,
+    Html,
+}
+
+impl ExportFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Md => "md",
+            ExportFormat::Txt => "txt",
+            ExportFormat::Html => "html",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    pub format: ExportFormat,
+    /// Prepended as YAML front matter in markdown exports
+    pub front_matter: Option<Map<String, Value>>,
+    pub if_exists: IfExists,
+    /// HTML document title; defaults to the file name
+    pub title: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportResult {
+    pub path: String,
+    pub bytes: usize,
+    pub hash: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportSuggestion {
+    pub path: String,
+    pub file_name: String,
+}
+
+fn yaml_key(key: &str) -> String {
+    let plain = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-'));
+    if plain {
+        key.to_string()
+    } else {
+        Value::String(key.to_string()).to_string()
+    }
+}
+
+/// JSON scalars and flow collections are valid YAML, so values are emitted as JSON
+fn front_matter(map: &Map<String, Value>) -> String {
+    let mut out = String::from("---\n");
+    for (key, value) in map {
+        out.push_str(&format!("{}: {}\n", yaml_key(key), value));
+    }
+    out.push_str("---\n\n");
+    out
+}
+
+pub fn render_export(text: &str, options: &ExportOptions, fallback_title: &str) -> String {
+    match options.format {
+        ExportFormat::Md => match &options.front_matter {
+            Some(map) if !map.is_empty() => format!("{}{}", front_matter(map), text),
+            _ => text.to_string(),
+        },
+        ExportFormat::Txt => text.to_string(),
+        ExportFormat::Html => {
+            standalone_html(options.title.as_deref().unwrap_or(fallback_title), text)
+        }
+    }
+}
+
+/// Formats a stored conversation can be exported as by `export_conversation`
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TranscriptFormat {
+    #[serde(alias = "md")]
+    Markdown,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RichFormat {
+    Html,
+    Pdf,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RichExportOptions {
+    pub include_thinking: bool,
+    pub include_tool_activity: bool,
+    pub if_exists: IfExists,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportCapabilities {
+    pub html: bool,
+    pub pdf: bool,
+    /// Browser used to print PDFs, when one was found
+    pub pdf_engine: Option<String>,
+    pub pdf_note: String,
+}
+
+fn message_shown(role: &str, options: &RichExportOptions) -> bool {
+    match role {
+        "thinking" => options.include_thinking,
+        "tool" => options.include_tool_activity,
+        _ => true,
+    }
+}
+
+/// Images from the attachment store as data URIs; anything else is dropped so
+/// the document never loads an external resource
+fn embed_image(attachments: Option<&Path>, url: &str) -> Option<String> {
+    let attachments = attachments?;
+    let path = match url::Url::parse(url) {
+        Ok(parsed) if parsed.scheme() == "file" => parsed.to_file_path().ok()?,
+        Ok(_) => return None,
+        Err(_) => PathBuf::from(url),
+    };
+    let path = canonicalize(&path).ok()?;
+    if !path.starts_with(attachments)
+        || std::fs::metadata(&path).ok()?.len() > MAX_EMBEDDED_IMAGE_BYTES
+    {
+        return None;
+    }
+    let bytes = std::fs::read(&path).ok()?;
+    let mime = sniff_mime(&bytes).filter(|m| m.starts_with("image/"))?;
+    Some(format!(
+        "data:{};base64,{}",
+        mime,
+        base64::engine::general_purpose::STANDARD.encode(bytes)
+    ))
+}
+
+fn local_time(millis: u64) -> String {
+    Local
+        .timestamp_millis_opt(millis as i64)
+        .single()
+        .map(|t| t.format("%Y-%m-%d %H:%M").to_string())
+        .unwrap_or_default()
+}
+
+fn role_heading(role: &str) -> String {
+    let mut chars = role.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => "Message".to_string(),
+    }
+}
+
+/// Token totals from the usage saved with each reply's active revision
+fn token_usage(conversation: &Conversation) -> Option<(u64, u64)> {
+    let usages: Vec<&Value> = conversation
+        .messages
+        .iter()
+        .filter_map(|m| {
+            m.revisions
+                .iter()
+                .find(|r| Some(&r.id) == m.active_revision.as_ref())
+        })
+        .filter_map(|r| r.usage.as_ref())
+        .collect();
+    if usages.is_empty() {
+        return None;
+    }
+    let total = |key: &str| usages.iter().filter_map(|u| u[key].as_u64()).sum();
+    Some((total("input_tokens"), total("output_tokens")))
+}
+
+/// The conversation as markdown: a metadata header, then a `## Role` section
+/// per message with its content as written, code fences and all
+fn conversation_markdown(conversation: &Conversation) -> String {
+    let mut models: Vec<&str> = Vec::new();
+    for model in conversation
+        .messages
+        .iter()
+        .filter_map(|m| m.model.as_deref())
+    {
+        if !models.contains(&model) {
+            models.push(model);
+        }
+    }
+    let mut out = format!("# {}\n\n", conversation.title);
+    out.push_str(&format!(
+        "- Date: {}\n",
+        local_time(conversation.created_at)
+    ));
+    if !models.is_empty() {
+        out.push_str(&format!("- Model: {}\n", models.join(", ")));
+    }
+    if let Some((input, output)) = token_usage(conversation) {
+        out.push_str(&format!("- Tokens: {} in, {} out\n", input, output));
+    }
+    out.push_str(&format!("- Messages: {}\n", conversation.messages.len()));
+    for message in &conversation.messages {
+        out.push_str(&format!("\n## {}\n\n", role_heading(&message.role)));
+        out.push_str(message.content.trim_end());
+        // A reply cut off inside a code block would swallow every section after it
+        if let Some(marker) = unclosed_fence(&message.content) {
+            out.push('\n');
+            out.push_str(marker);
+        }
+        if message.cancelled {
+            out.push_str("\n\n*[cancelled]*");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// A self-contained transcript page; markdown goes through the same renderer as the app
+fn conversation_html(
+    conversation: &Conversation,
+    attachments: Option<&Path>,
+    options: &RichExportOptions,
+) -> String {
+    let mut body = format!(
+        "<h1>{}</h1>\n<p class=\"meta\">{} · {} messages</p>\n",
+        escape_html(&conversation.title),
+        local_time(conversation.created_at),
+        conversation.messages.len()
+    );
+    for message in conversation
+        .messages
+        .iter()
+        .filter(|m| message_shown(&m.role, options))
+    {
+        let role = escape_html(&message.role);
+        body.push_str(&format!(
+            "<section class=\"message {}\">\n<h2>{}<time>{}</time></h2>\n{}</section>\n",
+            role,
+            role,
+            local_time(message.timestamp),
+            render_markdown_offline(&message.content, |url| embed_image(attachments, url))
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(&conversation.title),
+        DOCUMENT_STYLE,
+        TRANSCRIPT_STYLE,
+        body
+    )
+}
+
+fn pdf_browser() -> Option<PathBuf> {
+    if cfg!(windows) {
+        ["ProgramFiles(x86)", "ProgramFiles"]
+            .iter()
+            .filter_map(std::env::var_os)
+            .flat_map(|base| {
+                PDF_BROWSERS
+                    .iter()
+                    .map(move |rel| Path::new(&base).join(rel))
+            })
+            .find(|path| path.is_file())
+    } else {
+        PDF_BROWSERS.iter().find_map(|name| find_program(name, &[]))
+    }
+}
+
+/// Print `html_path` to `dest` with a headless browser
+async fn print_pdf(browser: PathBuf, html_path: &Path, dest: &Path) -> Result<(), EngineError> {
+    let page = url::Url::from_file_path(html_path)
+        .map_err(|_| EngineError::internal("Export page path is not absolute"))?;
+    let spec = RunSpec {
+        program: browser,
+        args: vec![
+            "--headless".to_string(),
+            "--disable-gpu".to_string(),
+            "--no-pdf-header-footer".to_string(),
+            format!("--print-to-pdf={}", dest.display()),
+            page.to_string(),
+        ],
+        cwd: html_path.parent().unwrap_or(Path::new(".")).to_path_buf(),
+        env: ChildEnv::default(),
+        timeout: Some(PDF_TIMEOUT),
+    };
+    let output = run_captured(spec, Vec::new()).await?;
+    let written = std::fs::metadata(dest).is_ok_and(|m| m.len() > 0);
+    if output.exit_code != Some(0) || !written {
+        return Err(EngineError::internal(format!(
+            "PDF printing failed: {}",
+            output.stderr.trim()
+        )));
+    }
+    Ok(())
+}
+
+fn slug(title: &str) -> String {
+    let mut slug = String::new();
+    for c in title.chars().flat_map(char::to_lowercase) {
+        if c.is_alphanumeric() {
+            slug.push(c);
+        } else if !slug.ends_with('-') && !slug.is_empty() {
+            slug.push('-');
+        }
+        if slug.chars().count() >= SLUG_CHARS {
+            break;
+        }
+    }
+    let slug = slug.trim_end_matches('-').to_string();
+    if slug.is_empty() {
+        "conversation".to_string()
+    } else {
+        slug
+    }
+}
+
+#[tauri::command]
+pub async fn export_text(
+    dest_path: String,
+    text: String,
+    options: Option<ExportOptions>,
+    workspace: State<'_, Workspace>,
+) -> Result<ExportResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let mut dest = workspace.check(Path::new(&dest_path))?;
+    if dest.extension().is_none() {
+        dest.set_extension(options.format.extension());
+    }
+    let target = resolve_target(&dest, options.if_exists).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => EngineError::AlreadyExists {
+            path: dest.display().to_string(),
+        },
+        _ => EngineError::io(&dest, e),
+    })?;
+    let title = target
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let content = render_export(&text, &options, &title);
+
+    tokio::task::spawn_blocking({
+        let target = target.clone();
+        let content = content.clone();
+        move || atomic_write(&target, content.as_bytes(), false)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+    .map_err(|e| EngineError::io(&target, e))?;
+
+    Ok(ExportResult {
+        path: target.display().to_string(),
+        bytes: content.len(),
+        hash: sha256_hex(content.as_bytes()),
+    })
+}
+
+/// Write a stored conversation to `dest_path` as markdown or pretty JSON,
+/// returning how many messages went in. An existing file is only replaced
+/// with `overwrite`.
+#[tauri::command]
+pub async fn export_conversation(
+    app: AppHandle,
+    id: String,
+    format: TranscriptFormat,
+    dest_path: String,
+    overwrite: Option<bool>,
+    workspace: State<'_, Workspace>,
+) -> Result<usize, EngineError> {
+    let conversation = history::load(&app, &id)?;
+    let mut dest = workspace.check(Path::new(&dest_path))?;
+    if dest.extension().is_none() {
+        dest.set_extension(match format {
+            TranscriptFormat::Markdown => "md",
+            TranscriptFormat::Json => "json",
+        });
+    }
+    let policy = match overwrite {
+        Some(true) => IfExists::Overwrite,
+        _ => IfExists::Fail,
+    };
+    let target = resolve_target(&dest, policy).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => EngineError::AlreadyExists {
+            path: dest.display().to_string(),
+        },
+        _ => EngineError::io(&dest, e),
+    })?;
+    let content = match format {
+        TranscriptFormat::Markdown => conversation_markdown(&conversation).into_bytes(),
+        TranscriptFormat::Json => serde_json::to_vec_pretty(&conversation).map_err(|e| {
+            EngineError::internal(format!("Failed to serialize conversation: {}", e))
+        })?,
+    };
+    let count = conversation.messages.len();
+    // Parent directories are created along with the file
+    tokio::task::spawn_blocking(move || {
+        atomic_write(&target, &content, false).map_err(|e| EngineError::io(&target, e))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+    Ok(count)
+}
+
+/// Export a conversation as a standalone HTML page, or a PDF printed from it
+#[tauri::command]
+pub async fn export_conversation_rich(
+    app: AppHandle,
+    conversation_id: String,
+    format: RichFormat,
+    dest_path: String,
+    options: Option<RichExportOptions>,
+    workspace: State<'_, Workspace>,
+) -> Result<ExportResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let conversation = history::load(&app, &conversation_id)?;
+    let mut dest = workspace.check(Path::new(&dest_path))?;
+    if dest.extension().is_none() {
+        dest.set_extension(match format {
+            RichFormat::Html => "html",
+            RichFormat::Pdf => "pdf",
+        });
+    }
+    let target = resolve_target(&dest, options.if_exists).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => EngineError::AlreadyExists {
+            path: dest.display().to_string(),
+        },
+        _ => EngineError::io(&dest, e),
+    })?;
+
+    let attachments = app_cache_subdir(&app, "attachments")
+        .ok()
+        .and_then(|dir| canonicalize(&dir).ok());
+    let html = tokio::task::spawn_blocking(move || {
+        conversation_html(&conversation, attachments.as_deref(), &options)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+
+    let bytes = match format {
+        RichFormat::Html => {
+            atomic_write(&target, html.as_bytes(), false)
+                .map_err(|e| EngineError::io(&target, e))?;
+            html.into_bytes()
+        }
+        RichFormat::Pdf => {
+            let browser = pdf_browser().ok_or_else(|| {
+                EngineError::invalid(
+                    "PDF export needs Microsoft Edge, Chrome or Chromium installed",
+                )
+            })?;
+            let page = app_cache_subdir(&app, "export")?.join(format!("{}.html", random_hex(8)));
+            std::fs::write(&page, &html).map_err(|e| EngineError::io(&page, e))?;
+            let printed = print_pdf(browser, &page, &target).await;
+            let _ = std::fs::remove_file(&page);
+            printed?;
+            std::fs::read(&target).map_err(|e| EngineError::io(&target, e))?
+        }
+    };
+
+    Ok(ExportResult {
+        path: target.display().to_string(),
+        bytes: bytes.len(),
+        hash: sha256_hex(&bytes),
+    })
+}
+
+/// What export_conversation_rich can produce on this machine
+#[tauri::command]
+pub async fn get_export_capabilities() -> Result<ExportCapabilities, EngineError> {
+    let browser = tokio::task::spawn_blocking(pdf_browser)
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+    let pdf_note = match &browser {
+        Some(_) => "PDFs are printed from the HTML export by a headless browser".to_string(),
+        None => "PDF export needs Microsoft Edge, Chrome or Chromium; HTML export works without it"
+            .to_string(),
+    };
+    Ok(ExportCapabilities {
+        html: true,
+        pdf: browser.is_some(),
+        pdf_engine: browser.map(|path| path.display().to_string()),
+        pdf_note,
+    })
+}
+
+/// `<notes dir or workspace root>/<title-slug>-<date>.md`
+#[tauri::command]
+pub async fn suggest_export_path(
+    app: AppHandle,
+    conversation_id: String,
+    workspace: State<'_, Workspace>,
+) -> Result<ExportSuggestion, EngineError> {
+    let conversation = history::load(&app, &conversation_id)?;
+    let file_name = format!(
+        "{}-{}.md",
+        slug(&conversation.title),
+        utc_date(now_millis())
+    );
+
+    let root = workspace.roots().into_iter().next();
+    let notes_dir = settings::load(&app).export.notes_dir.map(PathBuf::from);
+    let dir = match (notes_dir, root) {
+        (Some(notes), _) if notes.is_absolute() => notes,
+        (Some(notes), Some(root)) => root.join(notes),
+        (None, Some(root)) => root,
+        (_, None) => {
+            return Err(EngineError::invalid(
+                "No workspace is open to suggest an export location in",
+            ))
+        }
+    };
+
+    Ok(ExportSuggestion {
+        path: dir.join(&file_name).display().to_string(),
+        file_name,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pulldown_cmark::{CodeBlockKind, Event, HeadingLevel, MetadataBlockKind, Parser, Tag};
+    use serde_json::json;
+
+    const CODE_REPLY: &str = concat!(
+        "Two ways:\n\n",
+        "```rust\nlet s = \"`tick`\";\n```\n\n",
+        "````markdown\nA fence inside a fence:\n```python\nprint(\"hi\")\n```\n````\n\n",
+        "~~~\n``` not a closing fence\n~~~\n\n",
+        "    indented code stays as is\n",
+    );
+
+    #[derive(Debug, Default, PartialEq)]
+    struct Parsed {
+        metadata: Option<String>,
+        /// (info string, body)
+        code: Vec<(String, String)>,
+        h2: Vec<String>,
+    }
+
+    fn parse(markdown: &str) -> Parsed {
+        let options = pulldown_cmark::Options::ENABLE_YAML_STYLE_METADATA_BLOCKS;
+        let mut parsed = Parsed::default();
+        let mut in_metadata = false;
+        let mut in_h2 = false;
+        let mut in_code = false;
+        for event in Parser::new_ext(markdown, options) {
+            match event {
+                Event::Start(Tag::MetadataBlock(MetadataBlockKind::YamlStyle)) => {
+                    in_metadata = true;
+                    parsed.metadata = Some(String::new());
+                }
+                Event::Start(Tag::CodeBlock(kind)) => {
+                    let info = match kind {
+                        CodeBlockKind::Fenced(info) => info.to_string(),
+                        CodeBlockKind::Indented => "(indented)".to_string(),
+                    };
+                    parsed.code.push((info, String::new()));
+                    in_code = true;
+                }
+                Event::Start(Tag::Heading {
+                    level: HeadingLevel::H2,
+                    ..
+                }) => {
+                    in_h2 = true;
+                    parsed.h2.push(String::new());
+                }
+                Event::End(_) => {
+                    in_metadata = false;
+                    in_h2 = false;
+                    in_code = false;
+                }
+                Event::Text(text) if in_metadata => {
+                    parsed.metadata.as_mut().unwrap().push_str(&text)
+                }
+                Event::Text(text) if in_h2 => parsed.h2.last_mut().unwrap().push_str(&text),
+                Event::Text(text) if in_code => parsed.code.last_mut().unwrap().1.push_str(&text),
+                _ => {}
+            }
+        }
+        parsed
+    }
+
+    fn code_blocks(markdown: &str) -> Vec<(String, String)> {
+        parse(markdown).code
+    }
+
+    #[test]
+    fn markdown_export_keeps_code_blocks_byte_for_byte() {
+        let mut front = Map::new();
+        front.insert("conversation_id".into(), json!("conv-1"));
+        front.insert("date".into(), json!("2024-06-01"));
+        front.insert("model".into(), json!("claude-sonnet: latest"));
+        front.insert("cost_usd".into(), json!(0.0123));
+        front.insert("odd key: \"quoted\"".into(), json!(["a", "b"]));
+        let options = ExportOptions {
+            front_matter: Some(front.clone()),
+            ..ExportOptions::default()
+        };
+        let exported = render_export(CODE_REPLY, &options, "untitled");
+
+        // The body after the front matter is exactly the text given
+        let (head, body) = exported.split_once("---\n\n").unwrap();
+        assert_eq!(body, CODE_REPLY);
+        assert!(head.starts_with("---\n"));
+
+        // Front matter values read back as the JSON they were written from
+        let parsed = parse(&exported);
+        let metadata = parsed.metadata.expect("front matter block");
+        let read: Map<String, Value> = metadata
+            .lines()
+            .map(|line| {
+                let (key, value) = if let Some(rest) = line.strip_prefix('"') {
+                    let end = rest.find("\": ").unwrap();
+                    let key: String = serde_json::from_str(&line[..end + 2]).unwrap();
+                    (key, &rest[end + 3..])
+                } else {
+                    let (key, value) = line.split_once(": ").unwrap();
+                    (key.to_string(), value)
+                };
+                (key, serde_json::from_str(value).unwrap())
+            })
+            .collect();
+        assert_eq!(read, front);
+
+        assert_eq!(code_blocks(&exported), code_blocks(CODE_REPLY));
+        assert_eq!(
+            code_blocks(CODE_REPLY),
+            [
+                ("rust".to_string(), "let s = \"`tick`\";\n".to_string()),
+                (
+                    "markdown".to_string(),
+                    "A fence inside a fence:\n```python\nprint(\"hi\")\n```\n".to_string()
+                ),
+                (String::new(), "``` not a closing fence\n".to_string()),
+                (
+                    "(indented)".to_string(),
+                    "indented code stays as is\n".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn txt_and_empty_front_matter_leave_the_text_alone() {
+        let txt = ExportOptions {
+            format: ExportFormat::Txt,
+            front_matter: Some(Map::from_iter([("a".to_string(), json!(1))])),
+            ..ExportOptions::default()
+        };
+        assert_eq!(render_export(CODE_REPLY, &txt, "x"), CODE_REPLY);
+        let empty = ExportOptions {
+            front_matter: Some(Map::new()),
+            ..ExportOptions::default()
+        };
+        assert_eq!(render_export(CODE_REPLY, &empty, "x"), CODE_REPLY);
+    }
+
+    #[test]
+    fn transcripts_close_fences_a_cancelled_reply_left_open() {
+        let conversation: Conversation = serde_json::from_value(json!({
+            "id": "conv-1",
+            "title": "Fences",
+            "created_at": 0,
+            "updated_at": 0,
+            "messages": [
+                {"role": "user", "content": "Show me", "timestamp": 0},
+                {"role": "assistant", "content": CODE_REPLY, "timestamp": 0},
+                {
+                    "role": "assistant",
+                    "content": "````markdown\n```python\nprint(1)\n```\nstill inside",
+                    "timestamp": 0,
+                    "cancelled": true
+                },
+                {"role": "user", "content": "Thanks", "timestamp": 0},
+            ]
+        }))
+        .unwrap();
+        let markdown = conversation_markdown(&conversation);
+        let parsed = parse(&markdown);
+        assert_eq!(parsed.h2, ["User", "Assistant", "Assistant", "User"]);
+        let mut expected = code_blocks(CODE_REPLY);
+        expected.push((
+            "markdown".to_string(),
+            "```python\nprint(1)\n```\nstill inside\n".to_string(),
+        ));
+        assert_eq!(code_blocks(&markdown), expected);
+        assert!(markdown.contains("still inside\n````\n\n*[cancelled]*"));
+    }
+}
@@ -0,0 +1,329 @@
+use crate::error::EngineError;
+use crate::settings;
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use reqwest::redirect::Policy;
+use reqwest::Url;
+use scraper::{Html, Selector};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::AppHandle;
+
+const DEFAULT_MAX_BYTES: usize = 2 * 1024 * 1024;
+const FETCH_TIMEOUT: Duration = Duration::from_secs(15);
+const MAX_REDIRECTS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderMode {
+    Text,
+    #[default]
+    Markdown,
+    Raw,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FetchOptions {
+    pub max_bytes: usize,
+    pub render_mode: RenderMode,
+}
+
+impl Default for FetchOptions {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            render_mode: RenderMode::Markdown,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FetchedPage {
+    pub final_url: String,
+    pub status: u16,
+    pub content: String,
+    pub content_type: String,
+    pub truncated: bool,
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    let [a, b, ..] = ip.octets();
+    ip.is_loopback()
+        || ip.is_private()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        || ip.is_multicast()
+        || ip.is_documentation()
+        // Carrier-grade NAT 100.64.0.0/10
+        || (a == 100 && (64..128).contains(&b))
+        || a == 0
+}
+
+fn is_private_v6(ip: Ipv6Addr) -> bool {
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_private_v4(v4);
+    }
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        // Unique local fc00::/7 and link-local fe80::/10
+        || (first & 0xfe00) == 0xfc00
+        || (first & 0xffc0) == 0xfe80
+}
+
+/// Loopback, RFC1918, link-local and other non-public destinations
+pub fn is_private_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_private_v4(v4),
+        IpAddr::V6(v6) => is_private_v6(v6),
+    }
+}
+
+#[derive(Debug)]
+struct BlockedAddress(String);
+
+impl std::fmt::Display for BlockedAddress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} resolves to a private or local address", self.0)
+    }
+}
+
+impl std::error::Error for BlockedAddress {}
+
+/// Resolver that refuses hostnames pointing at private addresses, so the check
+/// also covers redirects and can't be bypassed by DNS rebinding between check and connect
+struct GuardedResolver;
+
+impl Resolve for GuardedResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let host = name.as_str().to_string();
+        Box::pin(async move {
+            let addrs: Vec<_> = tokio::net::lookup_host((host.as_str(), 0)).await?.collect();
+            if addrs.is_empty() || addrs.iter().any(|a| is_private_address(a.ip())) {
+                return Err(Box::new(BlockedAddress(host)) as _);
+            }
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// Why a URL may not be fetched, or `None` if it passes the static checks
+pub fn url_rejection(url: &Url, allow_private: bool) -> Option<String> {
+    if !matches!(url.scheme(), "http" | "https") {
+        return Some(format!("scheme '{}' is not allowed", url.scheme()));
+    }
+    if allow_private {
+        return None;
+    }
+    match url.host() {
+        None => Some("URL has no host".to_string()),
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            (domain == "localhost" || domain.ends_with(".localhost"))
+                .then(|| "localhost is not allowed".to_string())
+        }
+        Some(url::Host::Ipv4(ip)) => {
+            is_private_v4(ip).then(|| format!("{} is a private address", ip))
+        }
+        Some(url::Host::Ipv6(ip)) => {
+            is_private_v6(ip).then(|| format!("{} is a private address", ip))
+        }
+    }
+}
+
+/// HTTP client with timeouts, a redirect cap and, unless `allow_private`, the SSRF guard
+pub fn guarded_client(
+    allow_private: bool,
+    timeout: Duration,
+) -> Result<reqwest::Client, EngineError> {
+    let policy = Policy::custom(move |attempt| {
+        if attempt.previous().len() >= MAX_REDIRECTS {
+            return attempt.error(format!("more than {} redirects", MAX_REDIRECTS));
+        }
+        match url_rejection(attempt.url(), allow_private) {
+            Some(reason) => attempt.error(reason),
+            None => attempt.follow(),
+        }
+    });
+
+    let mut builder = reqwest::Client::builder()
+        .timeout(timeout)
+        .connect_timeout(Duration::from_secs(10))
+        .redirect(policy)
+        .user_agent(concat!("bups-engine/", env!("CARGO_PKG_VERSION")));
+    if !allow_private {
+        builder = builder.dns_resolver(Arc::new(GuardedResolver));
+    }
+    builder
+        .build()
+        .map_err(|e| EngineError::internal(format!("Failed to build HTTP client: {}", e)))
+}
+
+/// Map a reqwest failure onto the structured error kinds
+pub fn request_error(url: &str, err: reqwest::Error, timeout: Duration) -> EngineError {
+    if err.is_timeout() {
+        return EngineError::Timeout {
+            after_ms: timeout.as_millis() as u64,
+        };
+    }
+    let mut source: Option<&dyn std::error::Error> = Some(&err);
+    while let Some(e) = source {
+        if let Some(blocked) = e.downcast_ref::<BlockedAddress>() {
+            return EngineError::UrlNotAllowed {
+                url: url.to_string(),
+                reason: blocked.to_string(),
+            };
+        }
+        source = e.source();
+    }
+    EngineError::Network {
+        detail: err.to_string(),
+    }
+}
+
+/// Strip page chrome and keep the main content, readability-style
+fn readable_html(html: &str) -> (Option<String>, String) {
+    let mut doc = Html::parse_document(html);
+
+    let title = Selector::parse("title").ok().and_then(|sel| {
+        doc.select(&sel)
+            .next()
+            .map(|t| t.text().collect::<String>().trim().to_string())
+            .filter(|t| !t.is_empty())
+    });
+
+    if let Ok(noise) = Selector::parse(
+        "script, style, noscript, template, nav, header, footer, aside, form, iframe, svg, button",
+    ) {
+        let ids: Vec<_> = doc.select(&noise).map(|el| el.id()).collect();
+        for id in ids {
+            if let Some(mut node) = doc.tree.get_mut(id) {
+                node.detach();
+            }
+        }
+    }
+
+    let main = Selector::parse("article, main, [role=main]")
+        .ok()
+        .and_then(|sel| doc.select(&sel).next().map(|el| el.html()));
+    let body = Selector::parse("body")
+        .ok()
+        .and_then(|sel| doc.select(&sel).next().map(|el| el.html()));
+    let content = main.or(body).unwrap_or_else(|| doc.root_element().html());
+    (title, content)
+}
+
+fn render_html(html: &str, mode: RenderMode) -> String {
+    if mode == RenderMode::Raw {
+        return html.to_string();
+    }
+    let (title, content) = readable_html(html);
+    let rendered = match mode {
+        RenderMode::Text => html2text::from_read(content.as_bytes(), 100).unwrap_or_default(),
+        _ => htmd::convert(&content).unwrap_or_default(),
+    };
+    match title {
+        Some(title) if mode == RenderMode::Markdown => {
+            format!("# {}\n\n{}", title, rendered.trim())
+        }
+        Some(title) => format!("{}\n\n{}", title, rendered.trim()),
+        None => rendered.trim().to_string(),
+    }
+}
+
+fn is_html(mime: &str) -> bool {
+    matches!(mime, "text/html" | "application/xhtml+xml")
+}
+
+fn is_passthrough(mime: &str) -> bool {
+    mime.starts_with("text/")
+        || matches!(
+            mime,
+            "application/json"
+                | "application/xml"
+                | "application/javascript"
+                | "application/x-ndjson"
+        )
+        || mime.ends_with("+json")
+        || mime.ends_with("+xml")
+}
+
+/// Fetch `url` for use as prompt context. Localhost and private addresses
+/// are refused unless `fetch.allow_private` is set.
+#[tauri::command]
+pub async fn fetch_url(
+    app: AppHandle,
+    url: String,
+    options: Option<FetchOptions>,
+) -> Result<FetchedPage, EngineError> {
+    let options = options.unwrap_or_default();
+    let allow_private = settings::load(&app).fetch.allow_private;
+    let parsed = Url::parse(&url).map_err(|e| EngineError::UrlNotAllowed {
+        url: url.clone(),
+        reason: format!("invalid URL: {}", e),
+    })?;
+    if let Some(reason) = url_rejection(&parsed, allow_private) {
+        return Err(EngineError::UrlNotAllowed { url, reason });
+    }
+
+    let client = guarded_client(allow_private, FETCH_TIMEOUT)?;
+    let mut response = client
+        .get(parsed)
+        .send()
+        .await
+        .map_err(|e| request_error(&url, e, FETCH_TIMEOUT))?;
+
+    let status = response.status().as_u16();
+    let final_url = response.url().to_string();
+    let content_type = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or("text/plain")
+        .to_string();
+    let mime = content_type
+        .split(';')
+        .next()
+        .unwrap_or("")
+        .trim()
+        .to_ascii_lowercase();
+
+    if !is_html(&mime) && !is_passthrough(&mime) {
+        return Err(EngineError::UnsupportedContentType { content_type });
+    }
+
+    let mut body = Vec::new();
+    let mut truncated = false;
+    while let Some(chunk) = response
+        .chunk()
+        .await
+        .map_err(|e| request_error(&url, e, FETCH_TIMEOUT))?
+    {
+        let room = options.max_bytes.saturating_sub(body.len());
+        if chunk.len() > room {
+            body.extend_from_slice(&chunk[..room]);
+            truncated = true;
+            break;
+        }
+        body.extend_from_slice(&chunk);
+    }
+
+    let text = String::from_utf8_lossy(&body);
+    let content = if is_html(&mime) {
+        render_html(&text, options.render_mode)
+    } else {
+        text.into_owned()
+    };
+
+    Ok(FetchedPage {
+        final_url,
+        status,
+        content,
+        content_type,
+        truncated,
+    })
+}
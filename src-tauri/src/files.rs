@@ -0,0 +1,904 @@
+use crate::clock::millis;
+use crate::error::EngineError;
+use crate::metrics::{self, Stage};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// `<name>.bak` next to the given file
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".bak");
+    path.with_file_name(name)
+}
+
+fn temp_path_for(path: &Path) -> io::Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "path has no file name"))?;
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    Ok(path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name.to_string_lossy(),
+        std::process::id(),
+        n
+    )))
+}
+
+#[cfg(windows)]
+fn rename_replace(from: &Path, to: &Path) -> io::Result<()> {
+    // MoveFileEx fails with sharing violations / access denied while another
+    // process (editor, indexer, antivirus) briefly holds the target open
+    const ERROR_ACCESS_DENIED: i32 = 5;
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    let mut attempt = 0;
+    loop {
+        match fs::rename(from, to) {
+            Err(e)
+                if attempt < 5
+                    && matches!(
+                        e.raw_os_error(),
+                        Some(ERROR_ACCESS_DENIED) | Some(ERROR_SHARING_VIOLATION)
+                    ) =>
+            {
+                attempt += 1;
+                std::thread::sleep(std::time::Duration::from_millis(20 * attempt));
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(not(windows))]
+fn rename_replace(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to)
+}
+
+/// A file written in pieces to a temp sibling and renamed over the target on
+/// [`commit`](AtomicFile::commit); dropping it uncommitted deletes the temp file
+pub struct AtomicFile {
+    path: PathBuf,
+    tmp: PathBuf,
+    file: Option<fs::File>,
+    existing: Option<fs::Metadata>,
+}
+
+impl AtomicFile {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(parent)?;
+        }
+
+        let existing = fs::metadata(path).ok();
+        if let Some(meta) = &existing {
+            if meta.is_dir() {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "path is a directory",
+                ));
+            }
+            if meta.permissions().readonly() {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "file is read-only",
+                ));
+            }
+        }
+
+        let tmp = temp_path_for(path)?;
+        let file = fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&tmp)?;
+        Ok(Self {
+            path: path.to_path_buf(),
+            tmp,
+            file: Some(file),
+            existing,
+        })
+    }
+
+    pub fn write_all(&mut self, data: &[u8]) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.write_all(data),
+            None => Err(io::Error::other("file was already committed")),
+        }
+    }
+
+    /// fsync and rename into place. Returns the backup path when `backup` is
+    /// set and a previous version existed.
+    pub fn commit(mut self, backup: bool) -> io::Result<Option<PathBuf>> {
+        let file = self
+            .file
+            .take()
+            .ok_or_else(|| io::Error::other("file was already committed"))?;
+        file.sync_all()?;
+        drop(file);
+
+        if let Some(meta) = &self.existing {
+            fs::set_permissions(&self.tmp, meta.permissions())?;
+        }
+
+        let backup = match (&self.existing, backup) {
+            (Some(_), true) => {
+                let dest = backup_path(&self.path);
+                fs::copy(&self.path, &dest)?;
+                Some(dest)
+            }
+            _ => None,
+        };
+
+        rename_replace(&self.tmp, &self.path)?;
+        // Renamed away; nothing left for drop to clean up
+        self.tmp = PathBuf::new();
+        Ok(backup)
+    }
+}
+
+impl Drop for AtomicFile {
+    fn drop(&mut self) {
+        self.file.take();
+        if !self.tmp.as_os_str().is_empty() {
+            let _ = fs::remove_file(&self.tmp);
+        }
+    }
+}
+
+/// Write via temp file + fsync + rename so a crash never leaves a half-written file.
+/// Returns the backup path when `backup` is set and a previous version existed.
+pub fn atomic_write(path: &Path, data: &[u8], backup: bool) -> io::Result<Option<PathBuf>> {
+    let _timer = metrics::timer(Stage::Io);
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(data)?;
+    file.commit(backup)
+}
+
+/// How `write_file` treats what's already at the path
+#[derive(Debug, Clone, Default, serde::Deserialize)]
+#[serde(default)]
+pub struct WriteOptions {
+    /// Keep the previous content as `<name>.bak`
+    pub backup: bool,
+    /// Fail with `AlreadyExists` instead of replacing an existing file
+    pub create_new: bool,
+    /// Add to the end of the file instead of replacing it
+    pub append: bool,
+    /// sha256 the file must still have; anything else is a `Conflict`
+    pub expected_hash: Option<String>,
+}
+
+/// Open for append and write directly; going through a temp file would mean
+/// rewriting everything already there
+fn append_to(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let mut file = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+    file.write_all(data)?;
+    file.sync_all()
+}
+
+/// [`atomic_write`], or an append, per [`WriteOptions`]. `create_new` is
+/// checked before the write, so a file created meanwhile by another process
+/// can still be replaced.
+pub fn write_with(path: &Path, data: &[u8], options: &WriteOptions) -> io::Result<Option<PathBuf>> {
+    let existing = fs::symlink_metadata(path).is_ok();
+    if options.create_new && existing {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "file already exists",
+        ));
+    }
+    if !options.append {
+        return atomic_write(path, data, options.backup);
+    }
+    let _timer = metrics::timer(Stage::Io);
+    let backup = if options.backup && existing {
+        let dest = backup_path(path);
+        fs::copy(path, &dest)?;
+        Some(dest)
+    } else {
+        None
+    };
+    append_to(path, data)?;
+    Ok(backup)
+}
+
+/// Replace several files as one change. Everything is staged to temp
+/// siblings before anything is renamed into place; if a rename fails, files
+/// already replaced are put back from their backups. Previous versions are
+/// kept as `.bak`, in the same order as `writes`.
+pub fn write_files_atomic(writes: &[(PathBuf, Vec<u8>)]) -> io::Result<Vec<Option<PathBuf>>> {
+    let _timer = metrics::timer(Stage::Io);
+    let mut staged = Vec::with_capacity(writes.len());
+    for (path, data) in writes {
+        let mut file = AtomicFile::create(path)?;
+        file.write_all(data)?;
+        staged.push(file);
+    }
+
+    let mut committed: Vec<(&Path, Option<PathBuf>)> = Vec::with_capacity(writes.len());
+    for (file, (path, _)) in staged.into_iter().zip(writes) {
+        match file.commit(true) {
+            Ok(backup) => committed.push((path, backup)),
+            Err(e) => {
+                for (path, backup) in committed.iter().rev() {
+                    let _ = match backup {
+                        Some(backup) => rename_replace(backup, path),
+                        None => fs::remove_file(path),
+                    };
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(committed.into_iter().map(|(_, backup)| backup).collect())
+}
+
+/// What `stat_file` reports about a path. Timestamps are millis since the
+/// Unix epoch, None where the platform or filesystem doesn't record them.
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FileStat {
+    pub path: String,
+    pub size: u64,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Where a symlink points, as stored in the link
+    pub symlink_target: Option<String>,
+    /// A symlink whose target is missing
+    pub broken: bool,
+    /// The read-only attribute on Windows, no write permission for anyone on Unix
+    pub readonly: bool,
+    pub modified: Option<u64>,
+    pub created: Option<u64>,
+    pub accessed: Option<u64>,
+    /// Permission bits, Unix only
+    pub mode: Option<u32>,
+}
+
+#[cfg(unix)]
+fn mode_bits(meta: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(meta.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+fn mode_bits(_meta: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Where the symlink at `path` points and whether that exists; None if it
+/// isn't a link
+pub fn link_target(path: &Path) -> Option<(PathBuf, bool)> {
+    let target = fs::read_link(path).ok()?;
+    Some((target, fs::metadata(path).is_err()))
+}
+
+/// Metadata for `path`. With `follow` a symlink describes its target, and a
+/// dangling link is described as the link itself.
+pub fn stat(path: &Path, follow: bool) -> io::Result<FileStat> {
+    let link = fs::symlink_metadata(path)?;
+    let is_symlink = link.file_type().is_symlink();
+    let target = is_symlink.then(|| link_target(path)).flatten();
+    let meta = if is_symlink && follow {
+        fs::metadata(path).unwrap_or(link)
+    } else {
+        link
+    };
+    Ok(FileStat {
+        path: path.display().to_string(),
+        size: meta.len(),
+        is_dir: meta.is_dir(),
+        is_symlink,
+        broken: target.as_ref().is_some_and(|(_, broken)| *broken),
+        symlink_target: target.map(|(to, _)| to.display().to_string()),
+        readonly: meta.permissions().readonly(),
+        modified: meta.modified().ok().and_then(millis),
+        created: meta.created().ok().and_then(millis),
+        accessed: meta.accessed().ok().and_then(millis),
+        mode: mode_bits(&meta),
+    })
+}
+
+/// What `resolve_path` reports: where a path ends up once every link on
+/// the way is followed
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct ResolvedPath {
+    pub path: String,
+    /// None when a link on the way is broken
+    pub resolved: Option<String>,
+    pub is_symlink: bool,
+    /// The link's own target, as stored
+    pub symlink_target: Option<String>,
+    pub broken: bool,
+    /// Whether `resolved` is under a workspace root
+    pub inside_workspace: bool,
+}
+
+/// [`ResolvedPath`] for `path`, with `inside` deciding whether the
+/// resolved path is in the workspace
+pub fn resolve(path: &Path, inside: impl FnOnce(&Path) -> bool) -> io::Result<ResolvedPath> {
+    let link = fs::symlink_metadata(path)?;
+    let target = link
+        .file_type()
+        .is_symlink()
+        .then(|| link_target(path))
+        .flatten();
+    let resolved = crate::paths::canonicalize(path).ok();
+    Ok(ResolvedPath {
+        path: path.display().to_string(),
+        inside_workspace: resolved.as_deref().is_some_and(inside),
+        resolved: resolved.map(|p| p.display().to_string()),
+        is_symlink: link.file_type().is_symlink(),
+        broken: target.as_ref().is_some_and(|(_, broken)| *broken),
+        symlink_target: target.map(|(to, _)| to.display().to_string()),
+    })
+}
+
+// One IPC payload; larger windows are clamped to this
+const MAX_RANGE_BYTES: u64 = 16 * 1024 * 1024;
+
+/// One window of a file, from `read_file_range`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FileRange {
+    pub text: String,
+    /// Where the text starts; past the requested offset when a partial line
+    /// or character at the start was skipped
+    pub offset: u64,
+    /// Bytes of the file the text covers; the next window starts at `offset + length`
+    pub length: u64,
+    pub total_size: u64,
+    /// The window ends early to keep its last line or character whole
+    pub truncated: bool,
+    pub eof: bool,
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xC0 == 0x80
+}
+
+/// How many bytes at the end of `bytes` are an unfinished UTF-8 character
+fn partial_char_tail(bytes: &[u8]) -> usize {
+    let Some(back) = bytes
+        .iter()
+        .rev()
+        .take(4)
+        .position(|b| !is_continuation(*b))
+    else {
+        return 0;
+    };
+    let width = match bytes[bytes.len() - 1 - back] {
+        0xC0..=0xDF => 2,
+        0xE0..=0xEF => 3,
+        0xF0..=0xF7 => 4,
+        _ => 1,
+    };
+    if back + 1 < width {
+        back + 1
+    } else {
+        0
+    }
+}
+
+/// Read up to `length` bytes from `offset` without loading the rest of the
+/// file. Windows start and end on character boundaries, and on line
+/// boundaries with `snap_to_lines` unless one line fills the whole window.
+/// Invalid UTF-8 inside the window is replaced rather than failing the read.
+pub fn read_range(
+    path: &Path,
+    offset: u64,
+    length: u64,
+    snap_to_lines: bool,
+) -> io::Result<FileRange> {
+    use std::io::{Read, Seek, SeekFrom};
+
+    let mut file = fs::File::open(path)?;
+    let total_size = file.metadata()?.len();
+    let start = offset.min(total_size);
+    let end = start
+        .saturating_add(length.min(MAX_RANGE_BYTES))
+        .min(total_size);
+    // The byte before the window tells whether it starts mid-line
+    let lead = u64::from(start > 0);
+    let base = start - lead;
+    file.seek(SeekFrom::Start(base))?;
+    let mut bytes = Vec::with_capacity((end - base) as usize);
+    file.take(end - base).read_to_end(&mut bytes)?;
+    // The file may have shrunk since its size was read
+    let at_end = base + bytes.len() as u64 >= total_size;
+
+    let mut from = (lead as usize).min(bytes.len());
+    let mut to = bytes.len();
+    if snap_to_lines {
+        if from == 1 && bytes[0] != b'\n' {
+            if let Some(newline) = bytes[1..].iter().position(|b| *b == b'\n') {
+                from = newline + 2;
+            }
+        }
+        if !at_end {
+            if let Some(newline) = bytes[from..].iter().rposition(|b| *b == b'\n') {
+                to = from + newline + 1;
+            }
+        }
+    }
+    if start > 0 {
+        from += bytes[from..to]
+            .iter()
+            .take(3)
+            .take_while(|b| is_continuation(**b))
+            .count();
+    }
+    if !at_end {
+        let cut = partial_char_tail(&bytes[from..to]);
+        // A window too small for one character is returned as is, so paging moves on
+        if cut < to - from {
+            to -= cut;
+        }
+    }
+
+    let offset = base + from as u64;
+    let length = (to - from) as u64;
+    Ok(FileRange {
+        text: String::from_utf8_lossy(&bytes[from..to]).into_owned(),
+        offset,
+        length,
+        total_size,
+        truncated: base + (to as u64) < end,
+        eof: offset + length >= total_size,
+    })
+}
+
+/// A file's bytes for `read_file_binary`
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BinaryFile {
+    /// Standard base64
+    pub data: String,
+    /// From magic bytes; None when the format isn't one the app recognizes
+    pub mime: Option<&'static str>,
+    pub size: u64,
+}
+
+/// Whether anything is at `path`, counting a dangling symlink
+fn occupied(path: &Path) -> bool {
+    fs::symlink_metadata(path).is_ok()
+}
+
+/// Delete a file, a link or a directory. Without `recursive` only an empty
+/// directory can go, and a full one fails with `DirectoryNotEmpty`. A link is
+/// removed itself, never what it points to.
+pub fn remove_path(path: &Path, recursive: bool) -> io::Result<()> {
+    let meta = fs::symlink_metadata(path)?;
+    if meta.is_dir() {
+        return if recursive {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_dir(path)
+        };
+    }
+    match fs::remove_file(path) {
+        // A directory symlink on Windows is removed as a directory
+        Err(_) if meta.file_type().is_symlink() => fs::remove_dir(path),
+        result => result,
+    }
+}
+
+/// How one path of a `trash_path` call went
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct TrashOutcome {
+    pub path: String,
+    pub trashed: bool,
+    pub error: Option<EngineError>,
+}
+
+/// Move `path` to the Recycle Bin or Trash. A missing path is `NotFound`;
+/// anything the platform's trash refuses is `TrashUnavailable`.
+pub fn trash(path: &Path) -> Result<(), EngineError> {
+    fs::symlink_metadata(path).map_err(|e| EngineError::fs(path, e))?;
+    trash::delete(path).map_err(|e| EngineError::TrashUnavailable {
+        path: path.display().to_string(),
+        detail: e.to_string(),
+    })
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CopySummary {
+    /// Files and directories created, counting the top one
+    pub copied: usize,
+    /// Links to directories aren't followed, so a cycle can't recurse
+    pub skipped_links: usize,
+}
+
+fn copy_into(from: &Path, to: &Path, summary: &mut CopySummary) -> io::Result<()> {
+    let meta = fs::symlink_metadata(from)?;
+    if meta.file_type().is_symlink() && !fs::metadata(from).is_ok_and(|m| m.is_file()) {
+        summary.skipped_links += 1;
+        return Ok(());
+    }
+    if meta.is_dir() {
+        fs::create_dir(to)?;
+        summary.copied += 1;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_into(&entry.path(), &to.join(entry.file_name()), summary)?;
+        }
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    summary.copied += 1;
+    Ok(())
+}
+
+/// Refuse to replace `to` when `from` is inside it, where deleting the old
+/// `to` would take the source with it
+fn check_not_ancestor(from: &Path, to: &Path) -> io::Result<()> {
+    if let (Ok(from), Ok(to)) = (fs::canonicalize(from), fs::canonicalize(to)) {
+        if from != to && from.starts_with(&to) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't replace a directory with something inside it",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Rename `from` to `to`. A directory already at `to` is renamed aside first
+/// and only deleted once `from` is in its place, so a failed rename puts it back.
+fn rename_over(from: &Path, to: &Path) -> io::Result<()> {
+    // Renaming replaces a file, but not a directory
+    if !fs::symlink_metadata(to).is_ok_and(|m| m.is_dir()) {
+        return fs::rename(from, to);
+    }
+    let aside = temp_path_for(to)?;
+    fs::rename(to, &aside)?;
+    if let Err(e) = fs::rename(from, to) {
+        let _ = fs::rename(&aside, to);
+        return Err(e);
+    }
+    // Already out of the way; a failure here only leaves a stray temp entry
+    let _ = remove_path(&aside, true);
+    Ok(())
+}
+
+/// Copy a file or a whole directory. The copy is staged beside `to` and only
+/// then moved into place, so a copy that fails partway leaves any existing `to` as it
+/// was; without `overwrite` an existing `to` fails with `AlreadyExists`.
+pub fn copy_path(from: &Path, to: &Path, overwrite: bool) -> io::Result<CopySummary> {
+    let meta = fs::metadata(from)?;
+    if occupied(to) {
+        if !overwrite {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "destination already exists",
+            ));
+        }
+        check_not_ancestor(from, to)?;
+    }
+    if let Some(parent) = to.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    if meta.is_dir() {
+        let source = fs::canonicalize(from)?;
+        let parent = to.parent().map(fs::canonicalize).transpose()?;
+        if parent.is_some_and(|parent| parent.starts_with(&source)) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "can't copy a directory into itself",
+            ));
+        }
+    }
+
+    let staged = temp_path_for(to)?;
+    let mut summary = CopySummary::default();
+    if let Err(e) = copy_into(from, &staged, &mut summary) {
+        let _ = remove_path(&staged, true);
+        return Err(e);
+    }
+    if let Err(e) = rename_over(&staged, to) {
+        let _ = remove_path(&staged, true);
+        return Err(e);
+    }
+    Ok(summary)
+}
+
+/// The cross-device half of [`move_path`]: copy, then delete the original
+fn move_by_copy(from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+    copy_path(from, to, overwrite)?;
+    remove_path(from, true)
+}
+
+/// Rename or move `from` to `to`. Across filesystems or drives, where a plain
+/// rename can't work, it's copied and then the original deleted; `to` is
+/// never removed before what replaces it is in place.
+pub fn move_path(from: &Path, to: &Path, overwrite: bool) -> io::Result<()> {
+    fs::symlink_metadata(from)?;
+    // The same file under another spelling, e.g. a change of case on Windows
+    let same = match (fs::canonicalize(from), fs::canonicalize(to)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    };
+    if occupied(to) && !same {
+        if !overwrite {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                "destination already exists",
+            ));
+        }
+        check_not_ancestor(from, to)?;
+    }
+    if let Some(parent) = to.parent().filter(|p| !p.as_os_str().is_empty()) {
+        fs::create_dir_all(parent)?;
+    }
+    let renamed = if same {
+        fs::rename(from, to)
+    } else {
+        rename_over(from, to)
+    };
+    match renamed {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => move_by_copy(from, to, overwrite),
+        result => result,
+    }
+}
+
+/// [`looks_binary`] for a file, reading only the bytes it looks at
+pub fn file_looks_binary(path: &Path) -> io::Result<bool> {
+    use std::io::Read;
+
+    let mut head = Vec::with_capacity(8192);
+    fs::File::open(path)?.take(8192).read_to_end(&mut head)?;
+    Ok(looks_binary(&head))
+}
+
+/// Null-byte heuristic over the first 8KB, like git's binary detection
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    bytes.iter().take(8192).any(|b| *b == 0)
+}
+
+/// Magic-byte sniffing for the formats the app treats specially
+pub fn sniff_mime(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"SQLite format 3\0", "application/vnd.sqlite3"),
+    ];
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    SIGNATURES
+        .iter()
+        .find(|(magic, _)| bytes.starts_with(magic))
+        .map(|(_, mime)| *mime)
+}
+
+/// What to do when a write target already exists
+#[derive(Debug, Clone, Copy, Default, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IfExists {
+    #[default]
+    Fail,
+    Overwrite,
+    /// Pick `name (1).ext`, `name (2).ext`, ... instead
+    Rename,
+}
+
+/// Apply an [`IfExists`] policy, returning the path to actually write to
+pub fn resolve_target(path: &Path, policy: IfExists) -> io::Result<PathBuf> {
+    if !path.exists() || policy == IfExists::Overwrite {
+        return Ok(path.to_path_buf());
+    }
+    if policy == IfExists::Fail {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            "file already exists",
+        ));
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    (1..1000)
+        .map(|n| path.with_file_name(format!("{} ({}){}", stem, n, ext)))
+        .find(|candidate| !candidate.exists())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::AlreadyExists, "no free file name"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bups-files-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// `root/a/x`, `root/a/sub/y` and `root/a/sub/z`
+    fn tree(root: &Path) -> PathBuf {
+        let a = root.join("a");
+        fs::create_dir_all(a.join("sub")).unwrap();
+        fs::write(a.join("x"), "x").unwrap();
+        fs::write(a.join("sub").join("y"), "y").unwrap();
+        fs::write(a.join("sub").join("z"), "z").unwrap();
+        a
+    }
+
+    fn kind<T: std::fmt::Debug>(result: io::Result<T>) -> io::ErrorKind {
+        result.unwrap_err().kind()
+    }
+
+    #[test]
+    fn a_full_directory_only_goes_with_recursive() {
+        let dir = scratch("remove");
+        let a = tree(&dir);
+        assert_eq!(
+            kind(remove_path(&a, false)),
+            io::ErrorKind::DirectoryNotEmpty
+        );
+        assert!(a.join("sub").join("y").is_file());
+        remove_path(&a.join("x"), false).unwrap();
+        remove_path(&a, true).unwrap();
+        assert!(!occupied(&a));
+        assert_eq!(kind(remove_path(&a, true)), io::ErrorKind::NotFound);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn removing_a_link_leaves_what_it_points_to() {
+        let dir = scratch("remove-link");
+        let a = tree(&dir);
+        std::os::unix::fs::symlink(&a, dir.join("link")).unwrap();
+        remove_path(&dir.join("link"), true).unwrap();
+        assert!(a.join("sub").join("y").is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copies_count_every_entry_created() {
+        let dir = scratch("copy-count");
+        let a = tree(&dir);
+        let summary = copy_path(&a, &dir.join("b"), false).unwrap();
+        // a, x, sub, y and z
+        assert_eq!(summary.copied, 5);
+        assert_eq!(
+            fs::read_to_string(dir.join("b").join("sub").join("z")).unwrap(),
+            "z"
+        );
+        let summary = copy_path(&a.join("x"), &dir.join("x"), false).unwrap();
+        assert_eq!(summary.copied, 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn copies_skip_links_to_directories() {
+        let dir = scratch("copy-links");
+        let a = tree(&dir);
+        std::os::unix::fs::symlink(&a, a.join("sub").join("back")).unwrap();
+        std::os::unix::fs::symlink(a.join("x"), a.join("x-link")).unwrap();
+        let summary = copy_path(&a, &dir.join("b"), false).unwrap();
+        assert_eq!(summary.copied, 6);
+        assert_eq!(summary.skipped_links, 1);
+        assert_eq!(
+            fs::read_to_string(dir.join("b").join("x-link")).unwrap(),
+            "x"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn copies_only_replace_with_overwrite() {
+        let dir = scratch("copy-overwrite");
+        let a = tree(&dir);
+        fs::write(dir.join("old"), "old").unwrap();
+        assert_eq!(
+            kind(copy_path(&a.join("x"), &dir.join("old"), false)),
+            io::ErrorKind::AlreadyExists
+        );
+        assert_eq!(fs::read_to_string(dir.join("old")).unwrap(), "old");
+        copy_path(&a.join("x"), &dir.join("old"), true).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("old")).unwrap(), "x");
+
+        // A directory over a directory replaces it whole
+        fs::create_dir(dir.join("b")).unwrap();
+        fs::write(dir.join("b").join("stale"), "").unwrap();
+        copy_path(&a, &dir.join("b"), true).unwrap();
+        assert!(!dir.join("b").join("stale").exists());
+        assert!(dir.join("b").join("sub").join("y").is_file());
+
+        assert_eq!(
+            kind(copy_path(&a, &a.join("sub").join("inner"), false)),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(copy_path(&a.join("sub"), &a, true)),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(a.join("x").is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moves_only_replace_with_overwrite() {
+        let dir = scratch("move-overwrite");
+        let a = tree(&dir);
+        fs::write(dir.join("old"), "old").unwrap();
+        assert_eq!(
+            kind(move_path(&a.join("x"), &dir.join("old"), false)),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(a.join("x").is_file());
+        move_path(&a.join("x"), &dir.join("old"), true).unwrap();
+        assert_eq!(fs::read_to_string(dir.join("old")).unwrap(), "x");
+        assert!(!a.join("x").exists());
+
+        fs::create_dir(dir.join("b")).unwrap();
+        fs::write(dir.join("b").join("stale"), "").unwrap();
+        move_path(&a, &dir.join("b"), true).unwrap();
+        assert!(!a.exists());
+        assert!(!dir.join("b").join("stale").exists());
+        assert!(dir.join("b").join("sub").join("y").is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_move_never_deletes_its_own_source() {
+        let dir = scratch("move-ancestor");
+        let a = tree(&dir);
+        assert_eq!(
+            kind(move_path(&a.join("sub"), &a, true)),
+            io::ErrorKind::InvalidInput
+        );
+        assert_eq!(
+            kind(move_path(&a.join("sub").join("y"), &a, true)),
+            io::ErrorKind::InvalidInput
+        );
+        assert!(a.join("sub").join("y").is_file());
+        assert!(a.join("x").is_file());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn a_failed_rename_puts_the_old_directory_back() {
+        let dir = scratch("rename-over");
+        let a = tree(&dir);
+        assert_eq!(
+            kind(rename_over(&dir.join("missing"), &a)),
+            io::ErrorKind::NotFound
+        );
+        assert!(a.join("sub").join("y").is_file());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn moves_across_devices_copy_then_delete() {
+        let dir = scratch("move-copy");
+        let a = tree(&dir);
+        fs::create_dir(dir.join("b")).unwrap();
+        assert_eq!(
+            kind(move_by_copy(&a, &dir.join("b"), false)),
+            io::ErrorKind::AlreadyExists
+        );
+        assert!(a.is_dir());
+        move_by_copy(&a, &dir.join("b"), true).unwrap();
+        assert!(!a.exists());
+        assert_eq!(
+            fs::read_to_string(dir.join("b").join("sub").join("z")).unwrap(),
+            "z"
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
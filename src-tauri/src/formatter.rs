@@ -0,0 +1,244 @@
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::languages::{self, Language};
+use crate::runner::{find_program, run_captured, RunSpec};
+use crate::sandbox::Workspace;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+const FORMAT_TIMEOUT: Duration = Duration::from_secs(20);
+
+struct Formatter {
+    name: &'static str,
+    /// Language ids from the shared table
+    languages: &'static [&'static str],
+    /// Look in `<root>/node_modules/.bin` before PATH
+    node_local: bool,
+}
+
+const FORMATTERS: &[Formatter] = &[
+    Formatter {
+        name: "rustfmt",
+        languages: &["rust"],
+        node_local: false,
+    },
+    Formatter {
+        name: "prettier",
+        languages: &[
+            "typescript",
+            "javascript",
+            "json",
+            "markdown",
+            "css",
+            "html",
+            "yaml",
+        ],
+        node_local: true,
+    },
+    Formatter {
+        name: "gofmt",
+        languages: &["go"],
+        node_local: false,
+    },
+    Formatter {
+        name: "black",
+        languages: &["python"],
+        node_local: false,
+    },
+];
+
+/// Formatter executables found per workspace root; only hits are cached so a
+/// formatter installed later is picked up without a restart
+#[derive(Default)]
+pub struct FormatterCache {
+    found: Mutex<HashMap<(PathBuf, &'static str), PathBuf>>,
+}
+
+impl FormatterCache {
+    fn locate(&self, root: &Path, formatter: &Formatter, extra: &[PathBuf]) -> Option<PathBuf> {
+        let key = (root.to_path_buf(), formatter.name);
+        if let Some(path) = self.found.lock().ok()?.get(&key) {
+            return Some(path.clone());
+        }
+        let mut local = Vec::new();
+        if formatter.node_local {
+            local.push(root.join("node_modules").join(".bin"));
+        }
+        local.extend(extra.iter().cloned());
+        let path = find_program(formatter.name, &local)?;
+        if let Ok(mut found) = self.found.lock() {
+            found.insert(key, path.clone());
+        }
+        Some(path)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FormatResult {
+    pub content: String,
+    pub changed: bool,
+    pub formatter: String,
+}
+
+/// `edition = "2021"` from the workspace's Cargo.toml; rustfmt defaults to 2015 otherwise
+fn rust_edition(root: &Path) -> String {
+    std::fs::read_to_string(root.join("Cargo.toml"))
+        .ok()
+        .and_then(|toml| {
+            toml.lines().find_map(|line| {
+                let (key, value) = line.split_once('=')?;
+                (key.trim() == "edition").then(|| value.trim().trim_matches('"').to_string())
+            })
+        })
+        .unwrap_or_else(|| "2021".to_string())
+}
+
+fn args(formatter: &Formatter, root: &Path, file_name: &Path) -> Vec<String> {
+    let file_name = file_name.display().to_string();
+    match formatter.name {
+        "rustfmt" => vec![
+            "--edition".to_string(),
+            rust_edition(root),
+            "--emit".to_string(),
+            "stdout".to_string(),
+        ],
+        // The file name picks the parser and resolves the project's prettier config
+        "prettier" => vec!["--stdin-filepath".to_string(), file_name],
+        "black" => vec![
+            "-q".to_string(),
+            "--stdin-filename".to_string(),
+            file_name,
+            "-".to_string(),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+/// Format `content` as if it were `file_name` inside `root`; nothing is written
+pub async fn format_content(
+    app: &AppHandle,
+    cache: &FormatterCache,
+    language: &Language,
+    root: &Path,
+    file_name: &Path,
+    content: String,
+) -> Result<FormatResult, EngineError> {
+    let formatter = FORMATTERS
+        .iter()
+        .find(|f| f.languages.contains(&language.id))
+        .ok_or_else(|| EngineError::FormatterNotFound {
+            language: language.id.to_string(),
+        })?;
+    let env = env_profile::for_root(app, root)?;
+    let program = cache
+        .locate(root, formatter, &env.path_prepend)
+        .ok_or_else(|| EngineError::FormatterNotFound {
+            language: language.id.to_string(),
+        })?;
+
+    let spec = RunSpec {
+        program,
+        args: args(formatter, root, file_name),
+        cwd: root.to_path_buf(),
+        env,
+        timeout: Some(FORMAT_TIMEOUT),
+    };
+    let output = run_captured(spec, content.clone().into_bytes()).await?;
+    if output.exit_code != Some(0) {
+        return Err(EngineError::FormatFailed {
+            stderr: output.stderr,
+        });
+    }
+    let formatted = String::from_utf8(output.stdout).map_err(|_| EngineError::FormatFailed {
+        stderr: "Formatter produced invalid UTF-8".to_string(),
+    })?;
+    Ok(FormatResult {
+        changed: formatted != content,
+        content: formatted,
+        formatter: formatter.name.to_string(),
+    })
+}
+
+/// Workspace root containing `path`, falling back to its directory
+fn root_for(workspace: &Workspace, path: &Path) -> PathBuf {
+    workspace
+        .roots()
+        .into_iter()
+        .filter(|root| path.starts_with(root))
+        .max_by_key(|root| root.components().count())
+        .or_else(|| path.parent().map(Path::to_path_buf))
+        .unwrap_or_default()
+}
+
+/// Format a file's would-be content using the formatter for its language
+pub async fn format_for_path(
+    app: &AppHandle,
+    path: &Path,
+    content: String,
+) -> Result<FormatResult, EngineError> {
+    let cache = app.state::<FormatterCache>();
+    let workspace = app.state::<Workspace>();
+    let path = workspace.check(path)?;
+    let language = languages::detect(&path, content.as_bytes())
+        .and_then(|d| languages::by_id(d.language))
+        .ok_or_else(|| EngineError::FormatterNotFound {
+            language: path.display().to_string(),
+        })?;
+    let root = root_for(&workspace, &path);
+    format_content(app, &cache, language, &root, &path, content).await
+}
+
+/// Format a buffer; `language` is an id, fence tag or extension
+#[tauri::command]
+pub async fn format_text(
+    app: AppHandle,
+    language: String,
+    content: String,
+    root: String,
+    cache: State<'_, FormatterCache>,
+    workspace: State<'_, Workspace>,
+) -> Result<FormatResult, EngineError> {
+    let lang = languages::lookup(&language).ok_or_else(|| EngineError::FormatterNotFound {
+        language: language.clone(),
+    })?;
+    let root = workspace.check(Path::new(&root))?;
+    // Keep a given extension like `tsx` so prettier picks the right parser
+    let ext = if lang
+        .extensions
+        .contains(&language.to_ascii_lowercase().as_str())
+    {
+        language.to_ascii_lowercase()
+    } else {
+        lang.extensions
+            .first()
+            .copied()
+            .unwrap_or("txt")
+            .to_string()
+    };
+    let file_name = root.join(format!("stdin.{}", ext));
+    format_content(&app, &cache, lang, &root, &file_name, content).await
+}
+
+/// Format a file in place; untouched when the formatter changes nothing
+#[tauri::command]
+pub async fn format_file(
+    app: AppHandle,
+    path: String,
+    workspace: State<'_, Workspace>,
+) -> Result<FormatResult, EngineError> {
+    let target = workspace.check(Path::new(&path))?;
+    let content = tokio::fs::read_to_string(&target)
+        .await
+        .map_err(|e| EngineError::io(&target, e))?;
+    let result = format_for_path(&app, &target, content).await?;
+    if result.changed {
+        atomic_write(&target, result.content.as_bytes(), false)
+            .map_err(|e| EngineError::io(&target, e))?;
+    }
+    Ok(result)
+}
@@ -0,0 +1,226 @@
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::runner::{find_program, run_captured, CapturedOutput, RunSpec};
+use crate::sandbox;
+use crate::settings;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tauri::AppHandle;
+
+const GIT_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Serialize)]
+pub struct GitStatusEntry {
+    /// Relative to the repository root
+    pub path: String,
+    /// Where a rename or copy came from
+    pub orig_path: Option<String>,
+    /// Porcelain letters: `M`, `A`, `D`, `R`, `C`, `U`, `?` or `.` for none
+    pub index_status: char,
+    pub worktree_status: char,
+    pub staged: bool,
+    pub unstaged: bool,
+    pub untracked: bool,
+    /// Unmerged, i.e. mid-merge with conflicts
+    pub conflicted: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GitStatus {
+    /// None when HEAD is detached
+    pub branch: Option<String>,
+    pub entries: Vec<GitStatusEntry>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct GitDiff {
+    pub diff: String,
+    /// The diff went past `git.max_diff_bytes` and was cut at a line break
+    pub truncated: bool,
+    /// Of the whole diff
+    pub total_bytes: usize,
+}
+
+/// Run git in `repo` and hand back its output, telling a missing git and a
+/// directory outside any repository apart from other failures
+async fn git(app: &AppHandle, repo: &Path, args: &[&str]) -> Result<CapturedOutput, EngineError> {
+    if !repo.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            repo.display()
+        )));
+    }
+    let mut env = env_profile::for_root(app, repo)?;
+    // Untranslated messages, so "not a git repository" can be recognized
+    env.set("LC_ALL", "C");
+    // Status refreshes the index otherwise, racing the user's own git
+    env.set("GIT_OPTIONAL_LOCKS", "0");
+    let program = find_program("git", &env.path_prepend).ok_or(EngineError::GitNotFound)?;
+    let mut full_args = vec!["--no-pager".to_string()];
+    full_args.extend(args.iter().map(|a| a.to_string()));
+    let spec = RunSpec {
+        program,
+        args: full_args,
+        cwd: repo.to_path_buf(),
+        env,
+        timeout: Some(GIT_TIMEOUT),
+    };
+    let output = run_captured(spec, Vec::new()).await?;
+    if output.exit_code != Some(0) {
+        if output.stderr.contains("not a git repository") {
+            return Err(EngineError::NotAGitRepository {
+                path: repo.display().to_string(),
+            });
+        }
+        return Err(EngineError::GitFailed {
+            detail: output.stderr.trim().to_string(),
+        });
+    }
+    Ok(output)
+}
+
+fn entry(xy: &str, path: &str, orig_path: Option<&str>, conflicted: bool) -> GitStatusEntry {
+    let mut letters = xy.chars();
+    let index_status = letters.next().unwrap_or('.');
+    let worktree_status = letters.next().unwrap_or('.');
+    GitStatusEntry {
+        path: path.to_string(),
+        orig_path: orig_path.map(str::to_string),
+        index_status,
+        worktree_status,
+        staged: !conflicted && index_status != '.',
+        unstaged: !conflicted && worktree_status != '.',
+        untracked: false,
+        conflicted,
+    }
+}
+
+/// `git status --porcelain=v2 --branch -z`: NUL-separated records, with a
+/// rename's original path in the record after it
+fn parse_status(output: &[u8]) -> GitStatus {
+    let text = String::from_utf8_lossy(output);
+    let mut records = text.split('\0').filter(|r| !r.is_empty());
+    let mut status = GitStatus {
+        branch: None,
+        entries: Vec::new(),
+    };
+    while let Some(record) = records.next() {
+        let fields = |n: usize| record.splitn(n, ' ').collect::<Vec<_>>();
+        match record.as_bytes()[0] {
+            b'#' => {
+                if let Some(head) = record.strip_prefix("# branch.head ") {
+                    status.branch = (head != "(detached)").then(|| head.to_string());
+                }
+            }
+            // 1 XY sub mH mI mW hH hI path
+            b'1' => {
+                let f = fields(9);
+                if let [_, xy, .., path] = f[..] {
+                    status.entries.push(entry(xy, path, None, false));
+                }
+            }
+            // 2 XY sub mH mI mW hH hI Xscore path, then origPath
+            b'2' => {
+                let f = fields(10);
+                if let [_, xy, .., path] = f[..] {
+                    let orig = records.next();
+                    status.entries.push(entry(xy, path, orig, false));
+                }
+            }
+            // u XY sub m1 m2 m3 mW h1 h2 h3 path
+            b'u' => {
+                let f = fields(11);
+                if let [_, xy, .., path] = f[..] {
+                    status.entries.push(entry(xy, path, None, true));
+                }
+            }
+            b'?' => {
+                let path = record.get(2..).unwrap_or_default();
+                let mut untracked = entry("??", path, None, false);
+                untracked.staged = false;
+                untracked.untracked = true;
+                status.entries.push(untracked);
+            }
+            _ => {}
+        }
+    }
+    status
+}
+
+/// Changed, staged, untracked and conflicted files in the repository
+/// holding `repo_path`, from `git status --porcelain=v2`
+#[tauri::command]
+pub async fn git_status(app: AppHandle, repo_path: String) -> Result<GitStatus, EngineError> {
+    let repo = sandbox::allow(&app, &repo_path)?;
+    let output = git(
+        &app,
+        &repo,
+        &[
+            "status",
+            "--porcelain=v2",
+            "--branch",
+            "-z",
+            "--untracked-files=all",
+        ],
+    )
+    .await?;
+    Ok(parse_status(&output.stdout))
+}
+
+/// Unified diff of the working tree against the index, or of the index
+/// against HEAD with `staged`, optionally for one `path`. Cut at a line break
+/// past `git.max_diff_bytes`.
+#[tauri::command]
+pub async fn git_diff(
+    app: AppHandle,
+    repo_path: String,
+    path: Option<String>,
+    staged: Option<bool>,
+) -> Result<GitDiff, EngineError> {
+    let repo = sandbox::allow(&app, &repo_path)?;
+    let mut args = vec!["diff", "--no-color", "--no-ext-diff"];
+    if staged.unwrap_or(false) {
+        args.push("--cached");
+    }
+    if let Some(path) = path.as_deref() {
+        args.extend(["--", path]);
+    }
+    let output = git(&app, &repo, &args).await?;
+    let limit = settings::load(&app).git.max_diff_bytes;
+    let total_bytes = output.stdout.len();
+    let truncated = total_bytes > limit;
+    let mut kept = &output.stdout[..];
+    if truncated {
+        let cut = kept[..limit]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        kept = &kept[..cut];
+    }
+    Ok(GitDiff {
+        diff: String::from_utf8_lossy(kept).into_owned(),
+        truncated,
+        total_bytes,
+    })
+}
+
+/// The checked-out branch; None when HEAD is detached
+#[tauri::command]
+pub async fn git_current_branch(
+    app: AppHandle,
+    repo_path: String,
+) -> Result<Option<String>, EngineError> {
+    let repo = sandbox::allow(&app, &repo_path)?;
+    let output = git(&app, &repo, &["rev-parse", "--abbrev-ref", "HEAD"]).await;
+    let output = match output {
+        Ok(output) => output,
+        // No commits yet, so HEAD names a branch that doesn't exist
+        Err(EngineError::GitFailed { .. }) => {
+            git(&app, &repo, &["symbolic-ref", "--short", "HEAD"]).await?
+        }
+        Err(e) => return Err(e),
+    };
+    let branch = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok((branch != "HEAD" && !branch.is_empty()).then_some(branch))
+}
@@ -0,0 +1,81 @@
+use crate::clock::millis;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::io::{self, Read};
+use std::path::Path;
+
+/// Hex-encoded sha256 of a byte slice
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// [`sha256_hex`] of a file's contents, read in chunks rather than all at once
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    Ok(hash_file(path, HashAlgorithm::Sha256)?.hash)
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    /// Several times faster on big files
+    Blake3,
+}
+
+/// A file's digest with the size and mtime it had while being read
+#[derive(Debug, Clone, Serialize)]
+pub struct FileHash {
+    /// Lowercase hex
+    pub hash: String,
+    pub size: u64,
+    /// Millis since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+/// Hash a file in fixed-size chunks, so even a huge one costs no memory
+pub fn hash_file(path: &Path, algorithm: HashAlgorithm) -> io::Result<FileHash> {
+    let mut file = std::fs::File::open(path)?;
+    let meta = file.metadata()?;
+    let mut sha256 = Sha256::new();
+    let mut blake3 = blake3::Hasher::new();
+    let mut chunk = vec![0u8; 64 * 1024];
+    loop {
+        let read = match file.read(&mut chunk) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        match algorithm {
+            HashAlgorithm::Sha256 => sha256.update(&chunk[..read]),
+            HashAlgorithm::Blake3 => {
+                blake3.update(&chunk[..read]);
+            }
+        }
+    }
+    let hash = match algorithm {
+        HashAlgorithm::Sha256 => format!("{:x}", sha256.finalize()),
+        HashAlgorithm::Blake3 => blake3.finalize().to_hex().to_string(),
+    };
+    Ok(FileHash {
+        hash,
+        size: meta.len(),
+        modified: meta.modified().ok().and_then(millis),
+    })
+}
+
+/// Hex string of `bytes` random bytes from the OS generator
+pub fn random_hex(bytes: usize) -> String {
+    let mut buf = vec![0u8; bytes];
+    // The OS generator only fails on misconfigured systems; fall back to the clock
+    if getrandom::fill(&mut buf).is_err() {
+        let seed = Sha256::digest(
+            format!("{:?}-{}", std::time::SystemTime::now(), std::process::id()).as_bytes(),
+        );
+        for (b, s) in buf.iter_mut().zip(seed.iter().cycle()) {
+            *b = *s;
+        }
+    }
+    buf.iter().map(|b| format!("{:02x}", b)).collect()
+}
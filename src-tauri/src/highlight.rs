@@ -0,0 +1,261 @@
+use crate::error::EngineError;
+use crate::languages::{self, detect_file};
+use crate::sandbox;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::OnceLock;
+use syntect::highlighting::{Highlighter, Theme, ThemeSet};
+use syntect::parsing::{ParseState, ScopeStack, SyntaxReference, SyntaxSet};
+use tauri::AppHandle;
+
+// Per-call caps; the frontend asks for the next range when it scrolls
+const MAX_LINES: usize = 5_000;
+const MAX_BYTES: usize = 1024 * 1024;
+// Minified lines are slow to parse and unreadable anyway; they come back plain
+const MAX_LINE_BYTES: usize = 10_000;
+// Lines above the range are parsed for state (open comments, strings) up to this
+const MAX_LEAD_IN_BYTES: usize = 2 * 1024 * 1024;
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+
+// Both sets take a while to deserialize, so they're built on first use and shared
+static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+static THEMES: OnceLock<ThemeSet> = OnceLock::new();
+
+fn syntaxes() -> &'static SyntaxSet {
+    SYNTAXES.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme(name: &str) -> Result<&'static Theme, EngineError> {
+    let themes = THEMES.get_or_init(ThemeSet::load_defaults);
+    themes.themes.get(name).ok_or_else(|| {
+        EngineError::invalid(format!(
+            "Unknown theme {}; available: {}",
+            name,
+            themes.themes.keys().cloned().collect::<Vec<_>>().join(", ")
+        ))
+    })
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct LineRange {
+    /// 1-based, inclusive
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct HighlightOptions {
+    /// Also resolve each span's foreground color, e.g. `base16-ocean.dark`
+    pub theme: Option<String>,
+    pub range: Option<LineRange>,
+    /// Language id, fence tag or extension; detected from the file otherwise
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenSpan {
+    /// Offsets in UTF-16 code units, the way JS indexes strings
+    pub start: usize,
+    pub end: usize,
+    /// Innermost scope as space-separated atoms, e.g. `keyword control rust`
+    pub scope_class: String,
+    /// `#rrggbb`, only when a theme was asked for
+    pub color: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct HighlightedFile {
+    pub path: String,
+    pub language: Option<String>,
+    /// Grammar used; `Plain Text` when none matched
+    pub syntax: String,
+    pub start_line: usize,
+    pub end_line: usize,
+    pub total_lines: usize,
+    /// The range's lines, joined with `\n`
+    pub text: String,
+    /// One entry per line in the range; unscoped text has no span
+    pub lines: Vec<Vec<TokenSpan>>,
+    /// The range was cut short by the per-call caps
+    pub truncated: bool,
+}
+
+fn find_syntax(
+    path: &Path,
+    language: Option<&str>,
+) -> Result<(Option<String>, &'static SyntaxReference), EngineError> {
+    let set = syntaxes();
+    let language = match language {
+        Some(name) => Some(
+            languages::lookup(name)
+                .map(|l| l.id.to_string())
+                .unwrap_or_else(|| name.to_string()),
+        ),
+        None => detect_file(path)?.map(|d| d.language.to_string()),
+    };
+    let by_language = language.as_deref().and_then(|id| {
+        let lang = languages::by_id(id);
+        set.find_syntax_by_token(lang.map_or(id, |l| l.fence))
+            .or_else(|| {
+                lang.into_iter()
+                    .flat_map(|l| l.extensions)
+                    .find_map(|ext| set.find_syntax_by_extension(ext))
+            })
+    });
+    let syntax = by_language
+        .or_else(|| {
+            path.extension()
+                .and_then(|e| e.to_str())
+                .and_then(|ext| set.find_syntax_by_extension(ext))
+        })
+        .unwrap_or_else(|| set.find_syntax_plain_text());
+    Ok((language, syntax))
+}
+
+fn utf16_offsets(line: &str) -> Vec<usize> {
+    // offsets[byte] for every char boundary, plus one past the end
+    let mut offsets = vec![0; line.len() + 1];
+    let mut units = 0;
+    for (i, c) in line.char_indices() {
+        offsets[i] = units;
+        units += c.len_utf16();
+    }
+    offsets[line.len()] = units;
+    offsets
+}
+
+fn scope_class(stack: &ScopeStack) -> Option<String> {
+    // The bottom scope is the grammar itself, e.g. `source.rust`
+    let slice = stack.as_slice();
+    if slice.len() < 2 {
+        return None;
+    }
+    Some(slice[slice.len() - 1].build_string().replace('.', " "))
+}
+
+fn highlight_line(
+    line: &str,
+    state: &mut ParseState,
+    stack: &mut ScopeStack,
+    highlighter: Option<&Highlighter>,
+) -> Result<Vec<TokenSpan>, EngineError> {
+    let failed =
+        |e: &dyn std::fmt::Display| EngineError::internal(format!("Highlighting failed: {}", e));
+    let with_newline = format!("{}\n", line);
+    let ops = state
+        .parse_line(&with_newline, syntaxes())
+        .map_err(|e| failed(&e))?;
+    let offsets = utf16_offsets(line);
+    let mut spans = Vec::new();
+    let mut push = |from: usize, to: usize, stack: &ScopeStack| {
+        let to = to.min(line.len());
+        if from >= to {
+            return;
+        }
+        if let Some(class) = scope_class(stack) {
+            spans.push(TokenSpan {
+                start: offsets[from],
+                end: offsets[to],
+                scope_class: class,
+                color: highlighter.map(|h| {
+                    let fg = h.style_for_stack(stack.as_slice()).foreground;
+                    format!("#{:02x}{:02x}{:02x}", fg.r, fg.g, fg.b)
+                }),
+            });
+        }
+    };
+    let mut last = 0;
+    for (pos, op) in ops {
+        push(last, pos, stack);
+        stack.apply(&op).map_err(|e| failed(&e))?;
+        last = pos;
+    }
+    push(last, line.len(), stack);
+    Ok(spans)
+}
+
+pub fn highlight_file(
+    path: &Path,
+    options: &HighlightOptions,
+) -> Result<HighlightedFile, EngineError> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| EngineError::io(path, e))?
+        .len();
+    if size > MAX_FILE_BYTES {
+        return Err(EngineError::invalid(format!(
+            "{} is {} bytes, over the {} byte limit for highlighting",
+            path.display(),
+            size,
+            MAX_FILE_BYTES
+        )));
+    }
+    let bytes = std::fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    let content = String::from_utf8_lossy(&bytes);
+    let all: Vec<&str> = content.lines().collect();
+    let (language, syntax) = find_syntax(path, options.language.as_deref())?;
+    let theme = options.theme.as_deref().map(theme).transpose()?;
+    let highlighter = theme.map(Highlighter::new);
+
+    let start = options.range.map_or(1, |r| r.start.max(1));
+    let end = options.range.map_or(all.len(), |r| r.end).min(all.len());
+    let mut state = ParseState::new(syntax);
+    let mut stack = ScopeStack::new();
+
+    // Parse from the top so a range inside a block comment still looks right,
+    // unless that's too much work; then the range starts fresh
+    let lead_in = &all[..(start - 1).min(all.len())];
+    if lead_in.iter().map(|l| l.len() + 1).sum::<usize>() <= MAX_LEAD_IN_BYTES {
+        for line in lead_in.iter().filter(|l| l.len() <= MAX_LINE_BYTES) {
+            highlight_line(line, &mut state, &mut stack, None)?;
+        }
+    }
+
+    let mut text = String::new();
+    let mut lines = Vec::new();
+    let mut truncated = false;
+    let mut last_line = start.saturating_sub(1);
+    for (index, line) in all.iter().enumerate().take(end).skip(start - 1) {
+        if lines.len() >= MAX_LINES || text.len() + line.len() > MAX_BYTES {
+            truncated = true;
+            break;
+        }
+        if !lines.is_empty() {
+            text.push('\n');
+        }
+        text.push_str(line);
+        lines.push(if line.len() > MAX_LINE_BYTES {
+            Vec::new()
+        } else {
+            highlight_line(line, &mut state, &mut stack, highlighter.as_ref())?
+        });
+        last_line = index + 1;
+    }
+
+    Ok(HighlightedFile {
+        path: path.display().to_string(),
+        language,
+        syntax: syntax.name.clone(),
+        start_line: start,
+        end_line: last_line,
+        total_lines: all.len(),
+        text,
+        lines,
+        truncated,
+    })
+}
+
+/// Read a file (or a line range of it) with syntax token spans, so large
+/// files don't have to be highlighted in the webview
+#[tauri::command]
+pub async fn read_file_highlighted(
+    app: AppHandle,
+    path: String,
+    options: Option<HighlightOptions>,
+) -> Result<HighlightedFile, EngineError> {
+    let options = options.unwrap_or_default();
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || highlight_file(&path, &options))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
@@ -0,0 +1,504 @@
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::hash::random_hex;
+use crate::history_search::HistorySearch;
+use crate::metrics::{self, Stage};
+use crate::paths::{app_data_subdir, validate_name};
+use crate::session::SessionPool;
+use crate::spill;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+pub const HISTORY_DIR: &str = "history";
+const TITLE_CHARS: usize = 60;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Message {
+    /// Filled in on load for messages saved before ids existed
+    #[serde(default)]
+    pub id: String,
+    pub role: String,
+    pub content: String,
+    pub timestamp: u64,
+    /// Per-model answers when the message is a side-by-side comparison
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<Variant>,
+    /// Model the reply came from, when one was picked; reused on regenerate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Alternative replies; `content` always holds the active one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub revisions: Vec<Revision>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub active_revision: Option<String>,
+    /// The stream was cancelled, so `content` is only what arrived before that
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Revision {
+    pub id: String,
+    pub content: String,
+    pub model: Option<String>,
+    pub created_at: u64,
+    /// The CLI's usage report, when it sent one
+    pub usage: Option<serde_json::Value>,
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Variant {
+    pub model: String,
+    pub content: String,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: String,
+    pub title: String,
+    /// Where the conversation was started from, e.g. "app" or "http"
+    #[serde(default)]
+    pub source: String,
+    /// CLI session id, for resuming with `--resume`
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// Service an imported conversation came from, e.g. "claude_ai"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub imported_from: Option<String>,
+    pub created_at: u64,
+    pub updated_at: u64,
+    pub messages: Vec<Message>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forked_from: Option<ForkOrigin>,
+    /// Conversations forked from this one
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forks: Vec<ForkRef>,
+    /// The next send carries the copied messages as context, since there's
+    /// no CLI session holding them
+    #[serde(default)]
+    pub replay_context: bool,
+    /// The title came from `generate_conversation_title`, or its fallback,
+    /// and isn't generated again unless forced
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub title_generated: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ForkMode {
+    /// Forked at the tip of a CLI session, which can be resumed as a fork
+    ResumeSession,
+    /// The CLI can't fork a session mid-way; context is rebuilt from messages
+    ContextReplay,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkOrigin {
+    pub conversation_id: String,
+    pub message_id: String,
+    /// Kept so the fork still reads sensibly once the parent is deleted
+    pub title: String,
+    pub mode: ForkMode,
+    pub forked_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForkRef {
+    pub conversation_id: String,
+    pub message_id: String,
+    pub forked_at: u64,
+}
+
+pub fn new_conversation_id() -> String {
+    format!("conv-{}", random_hex(8))
+}
+
+pub fn new_message_id() -> String {
+    format!("msg-{}", random_hex(8))
+}
+
+fn conversation_path(app: &AppHandle, id: &str) -> Result<PathBuf, EngineError> {
+    let id = validate_name(id)?;
+    Ok(app_data_subdir(app, HISTORY_DIR)?.join(format!("{}.json", id)))
+}
+
+pub fn title_from(prompt: &str) -> String {
+    let line = prompt.lines().find(|l| !l.trim().is_empty()).unwrap_or("");
+    let mut title: String = line.trim().chars().take(TITLE_CHARS).collect();
+    if line.trim().chars().count() > TITLE_CHARS {
+        title.push('…');
+    }
+    title
+}
+
+pub fn exists(app: &AppHandle, id: &str) -> Result<bool, EngineError> {
+    Ok(conversation_path(app, id)?.exists())
+}
+
+pub fn load(app: &AppHandle, id: &str) -> Result<Conversation, EngineError> {
+    let path = conversation_path(app, id)?;
+    let data = std::fs::read(&path).map_err(|e| EngineError::io(&path, e))?;
+    let mut conversation: Conversation = serde_json::from_slice(&data)
+        .map_err(|e| EngineError::io(&path, format!("Corrupt conversation: {}", e)))?;
+    // Messages only ever get appended, so the position is a stable id
+    for (i, message) in conversation.messages.iter_mut().enumerate() {
+        if message.id.is_empty() {
+            message.id = format!("msg-{}", i);
+        }
+    }
+    Ok(conversation)
+}
+
+pub fn save(app: &AppHandle, conversation: &Conversation) -> Result<(), EngineError> {
+    let _timer = metrics::timer(Stage::Persist);
+    let path = conversation_path(app, &conversation.id)?;
+    let json = {
+        let _timer = metrics::timer(Stage::Serialize);
+        serde_json::to_vec_pretty(conversation)
+    }
+    .map_err(|e| EngineError::internal(format!("Failed to serialize conversation: {}", e)))?;
+    atomic_write(&path, &json, false).map_err(|e| EngineError::io(&path, e))?;
+    app.state::<HistorySearch>().update(conversation);
+    Ok(())
+}
+
+fn append(
+    app: &AppHandle,
+    conversation_id: &str,
+    source: &str,
+    prompt: &str,
+    response: &str,
+    variants: Vec<Variant>,
+) -> Result<(), EngineError> {
+    let now = now_millis();
+    let mut conversation = load(app, conversation_id).unwrap_or_else(|_| Conversation {
+        id: conversation_id.to_string(),
+        title: title_from(prompt),
+        source: source.to_string(),
+        session_id: None,
+        imported_from: None,
+        created_at: now,
+        updated_at: now,
+        messages: Vec::new(),
+        forked_from: None,
+        forks: Vec::new(),
+        replay_context: false,
+        title_generated: false,
+    });
+    conversation.messages.push(Message {
+        id: new_message_id(),
+        role: "user".to_string(),
+        content: prompt.to_string(),
+        timestamp: now,
+        variants: Vec::new(),
+        model: None,
+        revisions: Vec::new(),
+        active_revision: None,
+        cancelled: false,
+    });
+    conversation.messages.push(Message {
+        id: new_message_id(),
+        role: "assistant".to_string(),
+        content: response.to_string(),
+        timestamp: now,
+        variants,
+        model: None,
+        revisions: Vec::new(),
+        active_revision: None,
+        cancelled: false,
+    });
+    conversation.updated_at = now;
+    save(app, &conversation)
+}
+
+/// Append a prompt/response pair, creating the conversation if it doesn't exist yet
+pub fn record_exchange(
+    app: &AppHandle,
+    conversation_id: &str,
+    source: &str,
+    prompt: &str,
+    response: &str,
+) -> Result<(), EngineError> {
+    append(app, conversation_id, source, prompt, response, Vec::new())
+}
+
+/// Append a prompt and one assistant entry holding every model's answer. The
+/// content is a readable fallback with a section per model.
+pub fn record_comparison(
+    app: &AppHandle,
+    conversation_id: &str,
+    source: &str,
+    prompt: &str,
+    variants: Vec<Variant>,
+) -> Result<(), EngineError> {
+    let content = variants
+        .iter()
+        .map(|v| match &v.error {
+            Some(error) => format!("### {}\n\n_Failed: {}_", v.model, error),
+            None => format!("### {}\n\n{}", v.model, v.content),
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    append(app, conversation_id, source, prompt, &content, variants)
+}
+
+/// Copy a conversation up to and including `from_message_id` into a new one,
+/// linking the two. The fork holds its own copy, so deleting the parent
+/// leaves it intact.
+pub fn fork(
+    app: &AppHandle,
+    conversation_id: &str,
+    from_message_id: &str,
+) -> Result<Conversation, EngineError> {
+    let mut parent = load(app, conversation_id)?;
+    let at = parent
+        .messages
+        .iter()
+        .position(|m| m.id == from_message_id)
+        .ok_or_else(|| {
+            EngineError::invalid(format!(
+                "No message {} in {}",
+                from_message_id, conversation_id
+            ))
+        })?;
+    let at_tip = at + 1 == parent.messages.len();
+    // `--fork-session` branches from the end of a session only
+    let (mode, session_id) = match &parent.session_id {
+        Some(session) if at_tip => (ForkMode::ResumeSession, Some(session.clone())),
+        _ => (ForkMode::ContextReplay, None),
+    };
+
+    let now = now_millis();
+    let child = Conversation {
+        id: new_conversation_id(),
+        title: parent.title.clone(),
+        source: parent.source.clone(),
+        session_id,
+        imported_from: None,
+        created_at: now,
+        updated_at: now,
+        messages: parent.messages[..=at].to_vec(),
+        forked_from: Some(ForkOrigin {
+            conversation_id: parent.id.clone(),
+            message_id: from_message_id.to_string(),
+            title: parent.title.clone(),
+            mode,
+            forked_at: now,
+        }),
+        forks: Vec::new(),
+        replay_context: mode == ForkMode::ContextReplay,
+        title_generated: parent.title_generated,
+    };
+    save(app, &child)?;
+    parent.forks.push(ForkRef {
+        conversation_id: child.id.clone(),
+        message_id: from_message_id.to_string(),
+        forked_at: now,
+    });
+    save(app, &parent)?;
+    Ok(child)
+}
+
+/// `message` preceded by `messages` as a plain transcript, for a CLI process
+/// that has none of the conversation
+pub fn with_transcript(messages: &[Message], message: &str) -> String {
+    if messages.is_empty() {
+        return message.to_string();
+    }
+    let mut prompt = String::from("Conversation so far:\n\n");
+    for m in messages {
+        let speaker = if m.role == "assistant" {
+            "Assistant"
+        } else {
+            "User"
+        };
+        prompt.push_str(&format!("{}: {}\n\n", speaker, m.content));
+    }
+    prompt.push_str("---\n\n");
+    prompt.push_str(message);
+    prompt
+}
+
+/// `message` with the conversation so far in front of it, when the
+/// conversation is waiting for its context to be replayed
+pub fn replay_prompt(
+    app: &AppHandle,
+    conversation_id: &str,
+    message: &str,
+) -> Result<Option<String>, EngineError> {
+    if !exists(app, conversation_id)? {
+        return Ok(None);
+    }
+    let conversation = load(app, conversation_id)?;
+    if !conversation.replay_context {
+        return Ok(None);
+    }
+    Ok(Some(with_transcript(&conversation.messages, message)))
+}
+
+/// Call once a replayed send succeeded, so later sends don't repeat the transcript
+pub fn mark_replayed(app: &AppHandle, conversation_id: &str) -> Result<(), EngineError> {
+    let mut conversation = load(app, conversation_id)?;
+    conversation.replay_context = false;
+    save(app, &conversation)
+}
+
+/// What the conversation list shows, without reading every message into it
+#[derive(Debug, Clone, Serialize)]
+pub struct ConversationSummary {
+    pub id: String,
+    pub title: String,
+    pub updated_at: u64,
+    pub message_count: usize,
+}
+
+/// Every stored conversation, most recently updated first. A file that can't
+/// be read or parsed is skipped rather than hiding all the others.
+pub fn list(app: &AppHandle) -> Result<Vec<ConversationSummary>, EngineError> {
+    let dir = app_data_subdir(app, HISTORY_DIR)?;
+    let entries = std::fs::read_dir(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    let mut summaries: Vec<ConversationSummary> = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|path| std::fs::read(path).ok())
+        .filter_map(|data| serde_json::from_slice::<Conversation>(&data).ok())
+        .map(|conversation| ConversationSummary {
+            id: conversation.id,
+            title: conversation.title,
+            updated_at: conversation.updated_at,
+            message_count: conversation.messages.len(),
+        })
+        .collect();
+    summaries.sort_by_key(|s| std::cmp::Reverse(s.updated_at));
+    Ok(summaries)
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, EngineError> + Send + 'static,
+) -> Result<T, EngineError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Store a conversation the frontend built, replacing any earlier copy. One
+/// without an id gets a new one; the saved conversation is returned.
+#[tauri::command]
+pub async fn save_conversation(
+    app: AppHandle,
+    mut conversation: Conversation,
+) -> Result<Conversation, EngineError> {
+    if conversation.id.is_empty() {
+        conversation.id = new_conversation_id();
+    }
+    blocking(move || save(&app, &conversation).map(|()| conversation)).await
+}
+
+#[tauri::command]
+pub async fn load_conversation(app: AppHandle, id: String) -> Result<Conversation, EngineError> {
+    blocking(move || load(&app, &id)).await
+}
+
+#[tauri::command]
+pub async fn list_conversations(app: AppHandle) -> Result<Vec<ConversationSummary>, EngineError> {
+    blocking(move || list(&app)).await
+}
+
+/// Delete a conversation and end its warm process; false if there was none
+#[tauri::command]
+pub async fn delete_conversation(app: AppHandle, id: String) -> Result<bool, EngineError> {
+    let path = conversation_path(&app, &id)?;
+    app.state::<SessionPool>().close(&id);
+    spill::forget(&app, &id);
+    app.state::<HistorySearch>().remove(&id);
+    match tokio::fs::remove_file(&path).await {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(EngineError::io(&path, e)),
+    }
+}
+
+/// Branch a conversation at an earlier message without touching the original
+#[tauri::command]
+pub async fn fork_conversation(
+    app: AppHandle,
+    conversation_id: String,
+    from_message_id: String,
+) -> Result<Conversation, EngineError> {
+    blocking(move || fork(&app, &conversation_id, &from_message_id)).await
+}
+
+fn revision_of(message: &Message) -> Revision {
+    Revision {
+        id: format!("rev-{}", random_hex(8)),
+        content: message.content.clone(),
+        model: message.model.clone(),
+        created_at: message.timestamp,
+        usage: None,
+        cost_usd: None,
+    }
+}
+
+/// Store `revision` as another reply to the same turn and make it the active
+/// one. The original reply becomes the first revision. Later sends replay the
+/// conversation, so a CLI session can't keep the superseded reply in context.
+pub fn add_revision(
+    app: &AppHandle,
+    conversation_id: &str,
+    message_id: &str,
+    revision: Revision,
+) -> Result<(), EngineError> {
+    let mut conversation = load(app, conversation_id)?;
+    let message = conversation
+        .messages
+        .iter_mut()
+        .find(|m| m.id == message_id)
+        .ok_or_else(|| EngineError::invalid(format!("No message {}", message_id)))?;
+    if message.revisions.is_empty() {
+        let original = revision_of(message);
+        message.active_revision = Some(original.id.clone());
+        message.revisions.push(original);
+    }
+    message.content = revision.content.clone();
+    message.model = revision.model.clone();
+    message.active_revision = Some(revision.id.clone());
+    message.revisions.push(revision);
+    conversation.replay_context = true;
+    conversation.updated_at = now_millis();
+    save(app, &conversation)
+}
+
+/// Switch which revision of a reply counts as the conversation's answer
+#[tauri::command]
+pub async fn set_active_revision(
+    app: AppHandle,
+    conversation_id: String,
+    message_id: String,
+    revision_id: String,
+) -> Result<Message, EngineError> {
+    let mut conversation = load(&app, &conversation_id)?;
+    let message = conversation
+        .messages
+        .iter_mut()
+        .find(|m| m.id == message_id)
+        .ok_or_else(|| EngineError::invalid(format!("No message {}", message_id)))?;
+    let revision = message
+        .revisions
+        .iter()
+        .find(|r| r.id == revision_id)
+        .ok_or_else(|| EngineError::invalid(format!("No revision {}", revision_id)))?;
+    message.content = revision.content.clone();
+    message.model = revision.model.clone();
+    message.active_revision = Some(revision_id);
+    let message = message.clone();
+    conversation.replay_context = true;
+    conversation.updated_at = now_millis();
+    save(&app, &conversation)?;
+    app.state::<SessionPool>().close(&conversation_id);
+    Ok(message)
+}
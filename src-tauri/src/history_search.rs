@@ -0,0 +1,376 @@
+use crate::error::EngineError;
+use crate::history::{Conversation, HISTORY_DIR};
+use crate::paths::app_data_subdir;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager};
+
+const DEFAULT_LIMIT: usize = 100;
+// Snippet context around the hit, in chars
+const BEFORE_CHARS: usize = 60;
+const AFTER_CHARS: usize = 120;
+
+/// What the index keeps of a stored conversation. Every field has a
+/// default, so files from older versions without some of them still load.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StoredConversation {
+    id: String,
+    title: String,
+    updated_at: u64,
+    messages: Vec<StoredMessage>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct StoredMessage {
+    id: String,
+    role: String,
+    content: String,
+    timestamp: u64,
+}
+
+impl From<&Conversation> for StoredConversation {
+    fn from(conversation: &Conversation) -> Self {
+        Self {
+            id: conversation.id.clone(),
+            title: conversation.title.clone(),
+            updated_at: conversation.updated_at,
+            messages: conversation
+                .messages
+                .iter()
+                .map(|m| StoredMessage {
+                    id: m.id.clone(),
+                    role: m.role.clone(),
+                    content: m.content.clone(),
+                    timestamp: m.timestamp,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// The words of `text`, lowercased, once each
+fn terms(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+#[derive(Default)]
+struct Index {
+    docs: HashMap<String, StoredConversation>,
+    /// Word to the conversations with a message containing it
+    postings: BTreeMap<String, HashSet<String>>,
+}
+
+impl Index {
+    fn remove(&mut self, id: &str) {
+        let Some(doc) = self.docs.remove(id) else {
+            return;
+        };
+        for term in doc.messages.iter().flat_map(|m| terms(&m.content)) {
+            if let Some(ids) = self.postings.get_mut(&term) {
+                ids.remove(id);
+                if ids.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    fn insert(&mut self, doc: StoredConversation) {
+        self.remove(&doc.id);
+        for term in doc.messages.iter().flat_map(|m| terms(&m.content)) {
+            self.postings
+                .entry(term)
+                .or_default()
+                .insert(doc.id.clone());
+        }
+        self.docs.insert(doc.id.clone(), doc);
+    }
+
+    /// Conversations with a word starting with each of `query`'s words
+    fn candidates(&self, query: &HashSet<String>) -> HashSet<&str> {
+        let mut found: Option<HashSet<&str>> = None;
+        for term in query {
+            let with_prefix: HashSet<&str> = self
+                .postings
+                .range(term.clone()..)
+                .take_while(|(key, _)| key.starts_with(term.as_str()))
+                .flat_map(|(_, ids)| ids.iter().map(String::as_str))
+                .collect();
+            found = Some(match found {
+                Some(found) => found.intersection(&with_prefix).copied().collect(),
+                None => with_prefix,
+            });
+        }
+        found.unwrap_or_default()
+    }
+}
+
+fn load_all(dir: &Path) -> Index {
+    let mut index = Index::default();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return index;
+    };
+    let paths = entries
+        .flatten()
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"));
+    for path in paths {
+        let Some(mut doc) = std::fs::read(&path)
+            .ok()
+            .and_then(|data| serde_json::from_slice::<StoredConversation>(&data).ok())
+        else {
+            continue;
+        };
+        // The file name is the id the rest of history goes by
+        if let Some(stem) = path.file_stem() {
+            doc.id = stem.to_string_lossy().into_owned();
+        }
+        index.insert(doc);
+    }
+    index
+}
+
+/// Managed word index over stored conversations, built on first use (or
+/// while starting up) and kept current as conversations are saved and
+/// deleted
+#[derive(Default)]
+pub struct HistorySearch {
+    index: Mutex<Option<Index>>,
+}
+
+impl HistorySearch {
+    /// Read every stored conversation into the index, unless that's done
+    pub fn build(&self, app: &AppHandle) -> Result<(), EngineError> {
+        let dir = app_data_subdir(app, HISTORY_DIR)?;
+        let mut index = self
+            .index
+            .lock()
+            .map_err(|_| EngineError::internal("History index lock is poisoned"))?;
+        if index.is_none() {
+            *index = Some(load_all(&dir));
+        }
+        Ok(())
+    }
+
+    /// Reindex a conversation that was just saved; before the index is built
+    /// there's nothing to do, since building reads the file
+    pub fn update(&self, conversation: &Conversation) {
+        if let Ok(mut index) = self.index.lock() {
+            if let Some(index) = index.as_mut() {
+                index.insert(conversation.into());
+            }
+        }
+    }
+
+    pub fn remove(&self, id: &str) {
+        if let Ok(mut index) = self.index.lock() {
+            if let Some(index) = index.as_mut() {
+                index.remove(id);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub case_sensitive: bool,
+    /// `user` or `assistant`; both by default
+    pub role: Option<String>,
+    /// Unix millis, inclusive, against each message's timestamp
+    pub since: Option<u64>,
+    /// Unix millis, exclusive
+    pub until: Option<u64>,
+    /// Most hits returned; 100 by default
+    pub limit: Option<usize>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchHit {
+    pub conversation_id: String,
+    pub title: String,
+    pub message_index: usize,
+    pub message_id: String,
+    pub role: String,
+    pub timestamp: u64,
+    /// Text around the first hit, with `…` where it was cut
+    pub snippet: String,
+    /// `[start, end]` byte ranges of the query's words within `snippet`
+    pub highlights: Vec<[usize; 2]>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchResults {
+    /// Most recently updated conversation first, then in message order
+    pub hits: Vec<SearchHit>,
+    /// More messages matched than `limit`
+    pub truncated: bool,
+}
+
+/// `text` lowercased, with the byte in `text` each lowered byte came from,
+/// so hits found in the lowered copy map back onto the original
+fn fold(text: &str) -> (String, Vec<usize>) {
+    let mut folded = String::with_capacity(text.len());
+    let mut origin = Vec::with_capacity(text.len());
+    for (at, c) in text.char_indices() {
+        for lower in c.to_lowercase() {
+            folded.push(lower);
+            origin.extend(std::iter::repeat_n(at, lower.len_utf8()));
+        }
+    }
+    origin.push(text.len());
+    (folded, origin)
+}
+
+/// Byte ranges in `text` of every occurrence of each word, or None when one
+/// of them isn't there
+fn find_all(text: &str, words: &[String], case_sensitive: bool) -> Option<Vec<(usize, usize)>> {
+    let (haystack, origin) = if case_sensitive {
+        (text.to_string(), Vec::new())
+    } else {
+        fold(text)
+    };
+    let to_original = |at: usize| if case_sensitive { at } else { origin[at] };
+    let mut ranges = Vec::new();
+    for word in words {
+        let before = ranges.len();
+        for (at, found) in haystack.match_indices(word.as_str()) {
+            let start = to_original(at);
+            let mut end = to_original(at + found.len());
+            // Part of what one char lowered to still covers that whole char
+            if end <= start {
+                end = start + text[start..].chars().next().map_or(0, char::len_utf8);
+            }
+            ranges.push((start, end));
+        }
+        if ranges.len() == before {
+            return None;
+        }
+    }
+    ranges.sort_unstable();
+    Some(ranges)
+}
+
+fn snippet(text: &str, ranges: &[(usize, usize)]) -> (String, Vec<[usize; 2]>) {
+    let (first, first_end) = ranges[0];
+    let start = text[..first]
+        .char_indices()
+        .rev()
+        .nth(BEFORE_CHARS.saturating_sub(1))
+        .map_or(0, |(at, _)| at);
+    let end = text[first_end..]
+        .char_indices()
+        .nth(AFTER_CHARS)
+        .map_or(text.len(), |(at, _)| first_end + at);
+    let lead = if start > 0 { "…" } else { "" };
+    let mut out = format!("{}{}", lead, &text[start..end]);
+    if end < text.len() {
+        out.push('…');
+    }
+    let highlights = ranges
+        .iter()
+        .filter(|(s, e)| *s >= start && *e <= end)
+        .map(|(s, e)| [s - start + lead.len(), e - start + lead.len()])
+        .collect();
+    (out, highlights)
+}
+
+fn search(index: &Index, query: &str, options: &SearchOptions) -> SearchResults {
+    let words: Vec<String> = query
+        .split_whitespace()
+        .map(|w| {
+            if options.case_sensitive {
+                w.to_string()
+            } else {
+                w.to_lowercase()
+            }
+        })
+        .collect();
+    let limit = options.limit.unwrap_or(DEFAULT_LIMIT).max(1);
+    let since = options.since.unwrap_or(0);
+    let until = options.until.unwrap_or(u64::MAX);
+    let mut docs: Vec<&StoredConversation> = index
+        .candidates(&terms(query))
+        .into_iter()
+        .filter_map(|id| index.docs.get(id))
+        .collect();
+    docs.sort_by(|a, b| {
+        b.updated_at
+            .cmp(&a.updated_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+    let mut hits = Vec::new();
+    let mut truncated = false;
+    'docs: for doc in docs {
+        for (message_index, message) in doc.messages.iter().enumerate() {
+            if options
+                .role
+                .as_ref()
+                .is_some_and(|role| *role != message.role)
+            {
+                continue;
+            }
+            if message.timestamp < since || message.timestamp >= until {
+                continue;
+            }
+            let Some(ranges) = find_all(&message.content, &words, options.case_sensitive) else {
+                continue;
+            };
+            if hits.len() == limit {
+                truncated = true;
+                break 'docs;
+            }
+            let (snippet, highlights) = snippet(&message.content, &ranges);
+            hits.push(SearchHit {
+                conversation_id: doc.id.clone(),
+                title: doc.title.clone(),
+                message_index,
+                message_id: message.id.clone(),
+                role: message.role.clone(),
+                timestamp: message.timestamp,
+                snippet,
+                highlights,
+            });
+        }
+    }
+    SearchResults { hits, truncated }
+}
+
+/// Messages in stored conversations containing every word of `query`, as
+/// written or with a longer word starting with it. The index is built the
+/// first time and kept current from then on.
+#[tauri::command]
+pub async fn search_conversations(
+    app: AppHandle,
+    query: String,
+    options: Option<SearchOptions>,
+) -> Result<SearchResults, EngineError> {
+    if terms(&query).is_empty() {
+        return Err(EngineError::invalid("The search has no words to look for"));
+    }
+    let options = options.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        let state = app.state::<HistorySearch>();
+        state.build(&app)?;
+        let index = state
+            .index
+            .lock()
+            .map_err(|_| EngineError::internal("History index lock is poisoned"))?;
+        Ok(index
+            .as_ref()
+            .map(|index| search(index, &query, &options))
+            .unwrap_or(SearchResults {
+                hits: Vec::new(),
+                truncated: false,
+            }))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
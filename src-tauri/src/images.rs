@@ -0,0 +1,166 @@
+use image::codecs::gif::GifDecoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::imageops::FilterType;
+use image::{AnimationDecoder, DynamicImage, GenericImageView, ImageDecoder, ImageReader};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+const JPEG_QUALITIES: &[u8] = &[85, 75, 65, 55, 45];
+// Never shrink below this to hit the byte target; past it the image stops being useful
+const MIN_EDGE: u32 = 256;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ImageOptions {
+    /// Longest edge after resizing, in pixels
+    pub max_edge: u32,
+    pub target_bytes: usize,
+}
+
+impl Default for ImageOptions {
+    fn default() -> Self {
+        Self {
+            max_edge: 1568,
+            target_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PassthroughReason {
+    Skipped,
+    Animated,
+    Unsupported,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Jpeg,
+    Png,
+}
+
+impl OutputFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::Png => "png",
+        }
+    }
+
+    pub fn mime(self) -> &'static str {
+        match self {
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::Png => "image/png",
+        }
+    }
+}
+
+pub struct ProcessedImage {
+    pub width: u32,
+    pub height: u32,
+    pub original_width: u32,
+    pub original_height: u32,
+    pub format: OutputFormat,
+    pub data: Vec<u8>,
+}
+
+fn is_animated_gif(bytes: &[u8]) -> bool {
+    GifDecoder::new(Cursor::new(bytes))
+        .map(|decoder| decoder.into_frames().take(2).count() > 1)
+        .unwrap_or(false)
+}
+
+/// Decode and apply the EXIF orientation so the pixels are upright
+fn decode_upright(bytes: &[u8]) -> Option<DynamicImage> {
+    let mut decoder = ImageReader::new(Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?
+        .into_decoder()
+        .ok()?;
+    let orientation = decoder.orientation().ok();
+    let mut image = DynamicImage::from_decoder(decoder).ok()?;
+    if let Some(orientation) = orientation {
+        image.apply_orientation(orientation);
+    }
+    Some(image)
+}
+
+fn encode(image: &DynamicImage, format: OutputFormat, quality: u8) -> Option<Vec<u8>> {
+    let mut out = Vec::new();
+    match format {
+        OutputFormat::Jpeg => {
+            let rgb = DynamicImage::ImageRgb8(image.to_rgb8());
+            rgb.write_with_encoder(JpegEncoder::new_with_quality(&mut out, quality))
+                .ok()?
+        }
+        OutputFormat::Png => image.write_with_encoder(PngEncoder::new(&mut out)).ok()?,
+    }
+    Some(out)
+}
+
+/// Smallest acceptable encoding: step JPEG quality down, then shrink, until under target
+fn encode_under(
+    mut image: DynamicImage,
+    format: OutputFormat,
+    target: usize,
+) -> Option<(DynamicImage, Vec<u8>)> {
+    let qualities: &[u8] = match format {
+        OutputFormat::Jpeg => JPEG_QUALITIES,
+        OutputFormat::Png => &[0],
+    };
+    loop {
+        let mut last = None;
+        for &quality in qualities {
+            let data = encode(&image, format, quality)?;
+            let fits = data.len() <= target;
+            last = Some(data);
+            if fits {
+                break;
+            }
+        }
+        let data = last?;
+        let (w, h) = image.dimensions();
+        if data.len() <= target || w.max(h) * 3 / 4 < MIN_EDGE {
+            return Some((image, data));
+        }
+        image = image.resize(w * 3 / 4, h * 3 / 4, FilterType::Lanczos3);
+    }
+}
+
+/// Re-encode an image for upload; the output carries no EXIF or other metadata.
+/// `Err` explains why the original should be passed through unchanged.
+pub fn process(bytes: &[u8], options: &ImageOptions) -> Result<ProcessedImage, PassthroughReason> {
+    if bytes.starts_with(b"GIF8") && is_animated_gif(bytes) {
+        return Err(PassthroughReason::Animated);
+    }
+    let image = decode_upright(bytes).ok_or(PassthroughReason::Unsupported)?;
+    let (original_width, original_height) = image.dimensions();
+
+    let max_edge = options.max_edge.max(1);
+    let image = if original_width.max(original_height) > max_edge {
+        image.resize(max_edge, max_edge, FilterType::Lanczos3)
+    } else {
+        image
+    };
+    // Keep transparency; everything else is a photo as far as we're concerned
+    let format = if image.color().has_alpha() {
+        OutputFormat::Png
+    } else {
+        OutputFormat::Jpeg
+    };
+    let (image, data) =
+        encode_under(image, format, options.target_bytes).ok_or(PassthroughReason::Unsupported)?;
+    let (width, height) = image.dimensions();
+
+    Ok(ProcessedImage {
+        width,
+        height,
+        original_width,
+        original_height,
+        format,
+        data,
+    })
+}
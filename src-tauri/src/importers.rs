@@ -0,0 +1,591 @@
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::hash::sha256_hex;
+use crate::history::{self, Conversation, Message};
+use crate::sandbox;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::Read;
+use std::path::Path;
+use tauri::AppHandle;
+
+const CONVERSATIONS_FILE: &str = "conversations.json";
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportSource {
+    ClaudeAi,
+    Chatgpt,
+    #[default]
+    Auto,
+}
+
+/// A conversation mapped into app terms, before it gets an id
+struct ParsedConversation {
+    title: String,
+    created_at: u64,
+    updated_at: u64,
+    /// (role, content, timestamp)
+    messages: Vec<(String, String, u64)>,
+    /// Anything dropped on the way, e.g. alternate branches
+    notes: Vec<String>,
+}
+
+/// One service's export format. Adding a source is one more implementation.
+trait ExportParser {
+    fn id(&self) -> &'static str;
+    fn matches(&self, source: ImportSource) -> bool;
+    /// Whether a conversation entry looks like this service's format
+    fn recognizes(&self, entry: &Value) -> bool;
+    fn parse(&self, entry: &Value) -> Result<ParsedConversation, String>;
+}
+
+const PARSERS: &[&dyn ExportParser] = &[&ClaudeAiParser, &ChatGptParser];
+
+#[derive(Debug, Serialize)]
+pub struct ImportFailure {
+    pub index: usize,
+    pub title: Option<String>,
+    pub reason: String,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct ImportSummary {
+    pub imported: usize,
+    /// Already in history from an earlier import
+    pub duplicates: usize,
+    pub failed: Vec<ImportFailure>,
+    pub notes: Vec<String>,
+}
+
+fn iso_millis(value: &Value) -> Option<u64> {
+    let parsed = chrono::DateTime::parse_from_rfc3339(value.as_str()?).ok()?;
+    u64::try_from(parsed.timestamp_millis()).ok()
+}
+
+fn epoch_seconds_millis(value: &Value) -> Option<u64> {
+    value
+        .as_f64()
+        .filter(|s| *s >= 0.0)
+        .map(|s| (s * 1000.0) as u64)
+}
+
+struct ClaudeAiParser;
+
+impl ExportParser for ClaudeAiParser {
+    fn id(&self) -> &'static str {
+        "claude_ai"
+    }
+
+    fn matches(&self, source: ImportSource) -> bool {
+        matches!(source, ImportSource::ClaudeAi | ImportSource::Auto)
+    }
+
+    fn recognizes(&self, entry: &Value) -> bool {
+        entry.get("chat_messages").is_some_and(Value::is_array)
+    }
+
+    fn parse(&self, entry: &Value) -> Result<ParsedConversation, String> {
+        let raw = entry
+            .get("chat_messages")
+            .and_then(Value::as_array)
+            .ok_or("missing chat_messages")?;
+        let created_at = entry.get("created_at").and_then(iso_millis).unwrap_or(0);
+        let mut messages = Vec::new();
+        for message in raw {
+            let role = match message.get("sender").and_then(Value::as_str) {
+                Some("human") => "user",
+                Some("assistant") => "assistant",
+                _ => continue,
+            };
+            // Newer exports split text into content blocks; older ones only have `text`
+            let blocks: Vec<&str> = message
+                .get("content")
+                .and_then(Value::as_array)
+                .map(|blocks| {
+                    blocks
+                        .iter()
+                        .filter(|b| b.get("type").and_then(Value::as_str) == Some("text"))
+                        .filter_map(|b| b.get("text").and_then(Value::as_str))
+                        .collect()
+                })
+                .unwrap_or_default();
+            let content = if blocks.is_empty() {
+                message
+                    .get("text")
+                    .and_then(Value::as_str)
+                    .unwrap_or("")
+                    .to_string()
+            } else {
+                blocks.join("\n\n")
+            };
+            if content.trim().is_empty() {
+                continue;
+            }
+            let at = message
+                .get("created_at")
+                .and_then(iso_millis)
+                .unwrap_or(created_at);
+            messages.push((role.to_string(), content, at));
+        }
+        Ok(ParsedConversation {
+            title: entry
+                .get("name")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string(),
+            created_at,
+            updated_at: entry
+                .get("updated_at")
+                .and_then(iso_millis)
+                .unwrap_or(created_at),
+            messages,
+            notes: Vec::new(),
+        })
+    }
+}
+
+struct ChatGptParser;
+
+impl ChatGptParser {
+    fn content(message: &Value) -> Option<String> {
+        let content = message.get("content")?;
+        let parts: Vec<String> = match content.get("content_type").and_then(Value::as_str) {
+            Some("text") | Some("multimodal_text") => content
+                .get("parts")?
+                .as_array()?
+                .iter()
+                .map(|part| match part.as_str() {
+                    Some(text) => text.to_string(),
+                    // Uploaded images and other assets aren't in conversations.json
+                    None => "[attachment]".to_string(),
+                })
+                .collect(),
+            Some("code") => vec![format!(
+                "```\n{}\n```",
+                content.get("text").and_then(Value::as_str).unwrap_or("")
+            )],
+            _ => return None,
+        };
+        let text = parts.join("\n\n");
+        (!text.trim().is_empty()).then_some(text)
+    }
+}
+
+impl ExportParser for ChatGptParser {
+    fn id(&self) -> &'static str {
+        "chatgpt"
+    }
+
+    fn matches(&self, source: ImportSource) -> bool {
+        matches!(source, ImportSource::Chatgpt | ImportSource::Auto)
+    }
+
+    fn recognizes(&self, entry: &Value) -> bool {
+        entry.get("mapping").is_some_and(Value::is_object)
+    }
+
+    /// Conversations are trees; the branch ending at `current_node` is the one
+    /// the user last saw, so that's the one kept
+    fn parse(&self, entry: &Value) -> Result<ParsedConversation, String> {
+        let mapping = entry
+            .get("mapping")
+            .and_then(Value::as_object)
+            .ok_or("missing mapping")?;
+        let mut node_id = entry
+            .get("current_node")
+            .and_then(Value::as_str)
+            .ok_or("missing current_node")?
+            .to_string();
+
+        let mut branch = Vec::new();
+        while let Some(node) = mapping.get(&node_id) {
+            if branch.len() > mapping.len() {
+                return Err("message tree has a cycle".to_string());
+            }
+            branch.push(node);
+            match node.get("parent").and_then(Value::as_str) {
+                Some(parent) => node_id = parent.to_string(),
+                None => break,
+            }
+        }
+        branch.reverse();
+
+        let created_at = entry
+            .get("create_time")
+            .and_then(epoch_seconds_millis)
+            .unwrap_or(0);
+        let mut messages = Vec::new();
+        for message in branch.iter().filter_map(|node| node.get("message")) {
+            let hidden = message
+                .pointer("/metadata/is_visually_hidden_from_conversation")
+                .and_then(Value::as_bool)
+                .unwrap_or(false);
+            let role = match message.pointer("/author/role").and_then(Value::as_str) {
+                Some("user") => "user",
+                Some("assistant") => "assistant",
+                Some("tool") => "tool",
+                _ => continue,
+            };
+            let Some(content) = Self::content(message).filter(|_| !hidden) else {
+                continue;
+            };
+            let at = message
+                .get("create_time")
+                .and_then(epoch_seconds_millis)
+                .unwrap_or(created_at);
+            messages.push((role.to_string(), content, at));
+        }
+
+        let mut notes = Vec::new();
+        let alternates: usize = mapping
+            .values()
+            .filter_map(|node| node.get("children").and_then(Value::as_array))
+            .map(|children| children.len().saturating_sub(1))
+            .sum();
+        if alternates > 0 {
+            notes.push(format!(
+                "{} alternate branch(es) not imported; kept the last one viewed",
+                alternates
+            ));
+        }
+
+        Ok(ParsedConversation {
+            title: entry
+                .get("title")
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string(),
+            created_at,
+            updated_at: entry
+                .get("update_time")
+                .and_then(epoch_seconds_millis)
+                .unwrap_or(created_at),
+            messages,
+            notes,
+        })
+    }
+}
+
+/// The parser for an entry, among those allowed by `source`
+fn parser_for(entry: &Value, source: ImportSource) -> Option<&'static dyn ExportParser> {
+    PARSERS
+        .iter()
+        .copied()
+        .find(|p| p.matches(source) && p.recognizes(entry))
+}
+
+/// conversations.json, read directly or out of the export zip
+fn read_export(path: &Path) -> Result<Value, EngineError> {
+    let mut file = std::fs::File::open(path).map_err(|e| EngineError::io(path, e))?;
+    let mut magic = [0u8; 4];
+    let is_zip = file.read_exact(&mut magic).is_ok() && magic == *b"PK\x03\x04";
+    let data = if is_zip {
+        let file = std::fs::File::open(path).map_err(|e| EngineError::io(path, e))?;
+        let mut archive = zip::ZipArchive::new(file)
+            .map_err(|e| EngineError::io(path, format!("Invalid zip: {}", e)))?;
+        let name = archive
+            .file_names()
+            .find(|name| {
+                Path::new(name)
+                    .file_name()
+                    .is_some_and(|f| f == CONVERSATIONS_FILE)
+            })
+            .map(str::to_string)
+            .ok_or_else(|| {
+                EngineError::invalid(format!("The archive has no {}", CONVERSATIONS_FILE))
+            })?;
+        let mut entry = archive
+            .by_name(&name)
+            .map_err(|e| EngineError::io(path, format!("Invalid zip: {}", e)))?;
+        let mut data = Vec::new();
+        entry
+            .read_to_end(&mut data)
+            .map_err(|e| EngineError::io(path, e))?;
+        data
+    } else {
+        std::fs::read(path).map_err(|e| EngineError::io(path, e))?
+    };
+    serde_json::from_slice(&data).map_err(|e| {
+        EngineError::invalid(format!("{} is not valid JSON: {}", CONVERSATIONS_FILE, e))
+    })
+}
+
+/// Same messages give the same id, so re-importing an export is a no-op
+fn stable_id(parser: &str, parsed: &ParsedConversation) -> String {
+    let mut key = String::from(parser);
+    for (role, content, _) in &parsed.messages {
+        key.push('\0');
+        key.push_str(role);
+        key.push('\0');
+        key.push_str(content);
+    }
+    format!("imp-{}", &sha256_hex(key.as_bytes())[..16])
+}
+
+fn import(
+    app: &AppHandle,
+    path: &Path,
+    source: ImportSource,
+) -> Result<ImportSummary, EngineError> {
+    let root = read_export(path)?;
+    let entries = root.as_array().ok_or_else(|| {
+        EngineError::invalid(format!(
+            "{} should hold a list of conversations",
+            CONVERSATIONS_FILE
+        ))
+    })?;
+
+    let mut summary = ImportSummary::default();
+    for (index, entry) in entries.iter().enumerate() {
+        let title = ["name", "title"]
+            .iter()
+            .find_map(|key| entry.get(*key).and_then(Value::as_str))
+            .map(str::to_string);
+        let fail = |reason: String| ImportFailure {
+            index,
+            title: title.clone(),
+            reason,
+        };
+        let Some(parser) = parser_for(entry, source) else {
+            summary
+                .failed
+                .push(fail("Not a recognized conversation format".to_string()));
+            continue;
+        };
+        let parsed = match parser.parse(entry) {
+            Ok(parsed) if parsed.messages.is_empty() => {
+                summary.failed.push(fail("No messages".to_string()));
+                continue;
+            }
+            Ok(parsed) => parsed,
+            Err(reason) => {
+                summary.failed.push(fail(reason));
+                continue;
+            }
+        };
+
+        let id = stable_id(parser.id(), &parsed);
+        if history::exists(app, &id)? {
+            summary.duplicates += 1;
+            continue;
+        }
+        let title = if parsed.title.trim().is_empty() {
+            history::title_from(&parsed.messages[0].1)
+        } else {
+            parsed.title.clone()
+        };
+        summary.notes.extend(
+            parsed
+                .notes
+                .iter()
+                .map(|note| format!("{}: {}", title, note)),
+        );
+        let created_at = if parsed.created_at > 0 {
+            parsed.created_at
+        } else {
+            now_millis()
+        };
+        let conversation = Conversation {
+            id,
+            title,
+            source: "import".to_string(),
+            session_id: None,
+            imported_from: Some(parser.id().to_string()),
+            created_at,
+            updated_at: parsed.updated_at.max(created_at),
+            messages: parsed
+                .messages
+                .into_iter()
+                .map(|(role, content, timestamp)| Message {
+                    id: history::new_message_id(),
+                    role,
+                    content,
+                    timestamp,
+                    variants: Vec::new(),
+                    model: None,
+                    revisions: Vec::new(),
+                    active_revision: None,
+                    cancelled: false,
+                })
+                .collect(),
+            forked_from: None,
+            forks: Vec::new(),
+            replay_context: false,
+            title_generated: false,
+        };
+        history::save(app, &conversation)?;
+        summary.imported += 1;
+    }
+    Ok(summary)
+}
+
+/// Import a claude.ai or ChatGPT data export (the zip or its conversations.json)
+#[tauri::command]
+pub async fn import_external_conversations(
+    app: AppHandle,
+    path: String,
+    source: Option<ImportSource>,
+) -> Result<ImportSummary, EngineError> {
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || import(&app, &path, source.unwrap_or_default()))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("tests/fixtures/importers")
+            .join(name)
+    }
+
+    /// Each entry's parser id and parse result, as `import` sees them
+    fn parse_all(
+        path: &Path,
+        source: ImportSource,
+    ) -> Vec<Option<(&'static str, Result<ParsedConversation, String>)>> {
+        let root = read_export(path).unwrap();
+        root.as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| parser_for(entry, source).map(|p| (p.id(), p.parse(entry))))
+            .collect()
+    }
+
+    fn messages(parsed: &ParsedConversation) -> Vec<(&str, &str, u64)> {
+        parsed
+            .messages
+            .iter()
+            .map(|(role, content, at)| (role.as_str(), content.as_str(), *at))
+            .collect()
+    }
+
+    #[test]
+    fn claude_ai_export() {
+        let mut entries = parse_all(&fixture("claude_ai.json"), ImportSource::Auto).into_iter();
+
+        let (id, parsed) = entries.next().unwrap().unwrap();
+        let parsed = parsed.unwrap();
+        assert_eq!(id, "claude_ai");
+        assert_eq!(parsed.title, "Rust lifetimes");
+        assert_eq!(parsed.created_at, 1_714_557_600_000);
+        assert_eq!(parsed.updated_at, 1_714_557_900_000);
+        assert_eq!(
+            messages(&parsed),
+            [
+                ("user", "What does 'a mean?", 1_714_557_600_000),
+                (
+                    "assistant",
+                    "It names a lifetime.\n\nReferences tagged 'a live at least that long.",
+                    1_714_557_605_000
+                ),
+            ]
+        );
+        assert!(parsed.notes.is_empty());
+
+        // Text-only messages from older exports; blank ones are dropped
+        let (_, parsed) = entries.next().unwrap().unwrap();
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.title, "");
+        assert_eq!(
+            messages(&parsed),
+            [("user", "An older export with text only", 1_700_467_200_000)]
+        );
+
+        let (_, parsed) = entries.next().unwrap().unwrap();
+        assert!(parsed.unwrap().messages.is_empty());
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn chatgpt_export_keeps_the_viewed_branch() {
+        let mut entries = parse_all(&fixture("chatgpt.json"), ImportSource::Auto).into_iter();
+
+        let (id, parsed) = entries.next().unwrap().unwrap();
+        let parsed = parsed.unwrap();
+        assert_eq!(id, "chatgpt");
+        assert_eq!(parsed.title, "Sorting in Python");
+        assert_eq!(parsed.created_at, 1_714_557_600_000);
+        assert_eq!(parsed.updated_at, 1_714_557_700_250);
+        // Hidden system prompt and tool output are dropped, the image becomes a
+        // placeholder, and a missing time falls back to the conversation's
+        assert_eq!(
+            messages(&parsed),
+            [
+                ("user", "How do I sort a list?", 1_714_557_600_500),
+                (
+                    "assistant",
+                    "Use list.sort() in place, or sorted() for a copy.",
+                    1_714_557_610_000
+                ),
+                (
+                    "user",
+                    "[attachment]\n\nBy length, like in this screenshot?",
+                    1_714_557_650_000
+                ),
+                (
+                    "assistant",
+                    "```\nsorted(words, key=len)\n```",
+                    1_714_557_660_000
+                ),
+                ("assistant", "Shortest first, as shown.", 1_714_557_600_000),
+            ]
+        );
+        assert_eq!(
+            parsed.notes,
+            ["1 alternate branch(es) not imported; kept the last one viewed"]
+        );
+
+        let (_, parsed) = entries.next().unwrap().unwrap();
+        assert_eq!(parsed.err().as_deref(), Some("missing current_node"));
+        assert!(entries.next().is_none());
+    }
+
+    #[test]
+    fn an_explicit_source_ignores_the_other_format() {
+        let entries = parse_all(&fixture("chatgpt.json"), ImportSource::ClaudeAi);
+        assert!(entries.iter().all(Option::is_none));
+        let entries = parse_all(&fixture("claude_ai.json"), ImportSource::Chatgpt);
+        assert!(entries.iter().all(Option::is_none));
+    }
+
+    #[test]
+    fn conversations_json_is_found_inside_the_zip() {
+        let dir = std::env::temp_dir().join(format!("bups-importers-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let zip_path = dir.join("export.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let options = zip::write::FileOptions::default();
+        zip.start_file("user.json", options).unwrap();
+        zip.write_all(b"{}").unwrap();
+        zip.start_file("export-2024-05-01/conversations.json", options)
+            .unwrap();
+        zip.write_all(&std::fs::read(fixture("chatgpt.json")).unwrap())
+            .unwrap();
+        zip.finish().unwrap();
+
+        let entries = parse_all(&zip_path, ImportSource::Auto);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].as_ref().unwrap().0, "chatgpt");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_same_messages_get_the_same_id() {
+        let first = parse_all(&fixture("claude_ai.json"), ImportSource::Auto);
+        let again = parse_all(&fixture("claude_ai.json"), ImportSource::Auto);
+        let id = |entries: &[Option<(&str, Result<ParsedConversation, String>)>], i: usize| {
+            let (parser, parsed) = entries[i].as_ref().unwrap();
+            stable_id(parser, parsed.as_ref().unwrap())
+        };
+        assert_eq!(id(&first, 0), id(&again, 0));
+        assert_ne!(id(&first, 0), id(&first, 1));
+        assert!(id(&first, 0).starts_with("imp-"));
+    }
+}
@@ -0,0 +1,494 @@
+use crate::clock::{millis, now_millis};
+use crate::context::{relative, DEFAULT_SKIP_DIRS};
+use crate::error::EngineError;
+use crate::files::{atomic_write, looks_binary};
+use crate::hash::sha256_hex;
+use crate::languages;
+use crate::paths::{app_cache_subdir, canonicalize};
+use crate::sandbox;
+use crate::settings;
+use crate::symbols::definition_names;
+use crate::walker::{walk_files, WalkConfig};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager, State};
+
+pub const INDEX_DIR: &str = "file_index";
+/// Bumped whenever `FileIndex` changes shape, so old caches are rebuilt
+const INDEX_VERSION: u32 = 1;
+// Past this the index is incomplete and callers walk instead
+const MAX_INDEXED_FILES: usize = 200_000;
+const MAX_SYMBOLS_PER_FILE: usize = 500;
+const MAX_SYMBOL_FILE_BYTES: u64 = 512 * 1024;
+/// Without a watcher nothing reports changes, so an index is only trusted this long
+const MAX_INDEX_AGE_MS: u64 = 60_000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IndexedFile {
+    /// Relative to the root, `/`-separated
+    pub path: String,
+    pub size: u64,
+    /// Millis since the Unix epoch
+    pub modified: u64,
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub symbols: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct FileIndex {
+    pub version: u32,
+    pub root: String,
+    pub built_at: u64,
+    /// Latest mtime of `.git/HEAD` and `.git/index`; a checkout or commit moves it
+    pub git_stamp: Option<u64>,
+    pub truncated: bool,
+    pub has_symbols: bool,
+    pub files: Vec<IndexedFile>,
+    /// Default-skipped directories that were pruned, for the context report
+    pub skipped_dirs: Vec<String>,
+}
+
+impl FileIndex {
+    fn is_fresh(&self, root: &Path) -> bool {
+        !self.truncated
+            && now_millis().saturating_sub(self.built_at) <= MAX_INDEX_AGE_MS
+            && git_stamp(root) == self.git_stamp
+    }
+
+    /// Files worth scanning for `query`: source files small enough to scan
+    /// and, when names were indexed, only those defining a matching name
+    pub fn symbol_candidates(&self, root: &Path, query: &str, max_bytes: u64) -> Vec<PathBuf> {
+        let query = query.to_lowercase();
+        self.files
+            .iter()
+            .filter(|file| file.language.is_some() && file.size <= max_bytes)
+            .filter(|file| {
+                !self.has_symbols
+                    || file
+                        .symbols
+                        .iter()
+                        .any(|name| name.to_lowercase().contains(&query))
+            })
+            .map(|file| root.join(&file.path))
+            .collect()
+    }
+}
+
+fn git_stamp(root: &Path) -> Option<u64> {
+    ["HEAD", "index"]
+        .iter()
+        .filter_map(|name| std::fs::metadata(root.join(".git").join(name)).ok())
+        .filter_map(|meta| meta.modified().ok())
+        .filter_map(millis)
+        .max()
+}
+
+fn index_symbols(path: &Path, size: u64) -> Vec<String> {
+    if size > MAX_SYMBOL_FILE_BYTES {
+        return Vec::new();
+    }
+    let Ok(bytes) = std::fs::read(path) else {
+        return Vec::new();
+    };
+    if looks_binary(&bytes) {
+        return Vec::new();
+    }
+    let mut names = definition_names(path, &String::from_utf8_lossy(&bytes));
+    names.truncate(MAX_SYMBOLS_PER_FILE);
+    names
+}
+
+/// Walk `root` the way the context builder does: hidden and gitignored files
+/// and the default-skipped directories are left out
+pub fn scan(root: &Path, with_symbols: bool) -> Result<FileIndex, EngineError> {
+    // Read before walking, so a checkout midway leaves the index looking stale
+    let stamp = git_stamp(root);
+    let built_at = now_millis();
+    let skipped_dirs = Arc::new(Mutex::new(Vec::new()));
+    let descend = {
+        let root = root.to_path_buf();
+        let skipped_dirs = Arc::clone(&skipped_dirs);
+        move |entry: &ignore::DirEntry| {
+            let name = entry.file_name().to_string_lossy();
+            if !DEFAULT_SKIP_DIRS.contains(&name.as_ref()) {
+                return true;
+            }
+            if let Ok(mut dirs) = skipped_dirs.lock() {
+                dirs.push(relative(&root, entry.path()));
+            }
+            false
+        }
+    };
+    let count = AtomicUsize::new(0);
+    let truncated = AtomicBool::new(false);
+    let found = walk_files(root, WalkConfig::default(), descend, |entry| {
+        if count.fetch_add(1, Ordering::Relaxed) >= MAX_INDEXED_FILES {
+            truncated.store(true, Ordering::Relaxed);
+            return None;
+        }
+        let meta = entry.metadata().ok();
+        let size = meta.as_ref().map_or(0, |m| m.len());
+        let language = languages::detect_path(entry.path()).map(|d| d.language.to_string());
+        let symbols = if with_symbols && language.is_some() {
+            index_symbols(entry.path(), size)
+        } else {
+            Vec::new()
+        };
+        Some(IndexedFile {
+            path: relative(root, entry.path()),
+            size,
+            modified: meta
+                .and_then(|m| m.modified().ok())
+                .and_then(millis)
+                .unwrap_or(0),
+            language,
+            symbols,
+        })
+    })?;
+
+    let mut skipped_dirs = skipped_dirs
+        .lock()
+        .map(|dirs| dirs.clone())
+        .unwrap_or_default();
+    skipped_dirs.sort();
+    Ok(FileIndex {
+        version: INDEX_VERSION,
+        root: root.display().to_string(),
+        built_at,
+        git_stamp: stamp,
+        truncated: truncated.into_inner(),
+        has_symbols: with_symbols,
+        files: found.into_iter().map(|(_, file)| file).collect(),
+        skipped_dirs,
+    })
+}
+
+fn cache_path(app: &AppHandle, root: &Path) -> Result<PathBuf, EngineError> {
+    let key = sha256_hex(root.display().to_string().as_bytes());
+    Ok(app_cache_subdir(app, INDEX_DIR)?.join(format!("{}.json", &key[..16])))
+}
+
+fn load_cached(app: &AppHandle, root: &Path) -> Option<FileIndex> {
+    let data = std::fs::read(cache_path(app, root).ok()?).ok()?;
+    let index: FileIndex = serde_json::from_slice(&data).ok()?;
+    (index.version == INDEX_VERSION && Path::new(&index.root) == root).then_some(index)
+}
+
+fn persist(app: &AppHandle, root: &Path, index: &FileIndex) -> Result<(), EngineError> {
+    let path = cache_path(app, root)?;
+    let json = serde_json::to_vec(index)
+        .map_err(|e| EngineError::internal(format!("Failed to serialize file index: {}", e)))?;
+    atomic_write(&path, &json, false).map_err(|e| EngineError::io(&path, e))?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum IndexState {
+    Building,
+    Ready,
+    Stale,
+    /// Never requested for this root, or indexing is turned off
+    Missing,
+}
+
+#[derive(Default)]
+struct Slot {
+    index: Option<Arc<FileIndex>>,
+    /// Bumped per build; only the latest build may publish its result
+    generation: u64,
+    building: bool,
+    error: Option<String>,
+}
+
+/// Per-workspace file indexes, built in the background and persisted to the
+/// app cache. Only roots currently open get a slot, keeping memory bounded.
+#[derive(Default)]
+pub struct IndexRegistry {
+    slots: Mutex<HashMap<PathBuf, Slot>>,
+}
+
+impl IndexRegistry {
+    /// Start indexing `root` unless a build exists or is under way
+    pub fn ensure(&self, app: &AppHandle, root: &Path) {
+        let known = self
+            .slots
+            .lock()
+            .map(|slots| slots.contains_key(root))
+            .unwrap_or(true);
+        if !known {
+            self.rebuild(app, root);
+        }
+    }
+
+    /// Rebuild in the background, serving the previous index meanwhile
+    pub fn rebuild(&self, app: &AppHandle, root: &Path) {
+        if !settings::load(app).index.enabled {
+            return;
+        }
+        let generation = {
+            let Ok(mut slots) = self.slots.lock() else {
+                return;
+            };
+            let slot = slots.entry(root.to_path_buf()).or_default();
+            slot.generation += 1;
+            slot.building = true;
+            slot.generation
+        };
+        let app = app.clone();
+        let root = root.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let registry = app.state::<IndexRegistry>();
+            if let Some(cached) = load_cached(&app, &root) {
+                registry.publish(&root, generation, Arc::new(cached), true);
+            }
+            let with_symbols = settings::load(&app).index.symbols;
+            match scan(&root, with_symbols) {
+                Ok(index) => {
+                    let index = Arc::new(index);
+                    if registry.publish(&root, generation, Arc::clone(&index), false) {
+                        let _ = persist(&app, &root, &index);
+                    }
+                }
+                Err(e) => registry.fail(&root, generation, e.to_string()),
+            }
+        });
+    }
+
+    /// Returns whether `index` was taken; a newer build or a closed root wins
+    fn publish(&self, root: &Path, generation: u64, index: Arc<FileIndex>, cached: bool) -> bool {
+        let Ok(mut slots) = self.slots.lock() else {
+            return false;
+        };
+        let Some(slot) = slots.get_mut(root).filter(|s| s.generation == generation) else {
+            return false;
+        };
+        // The on-disk copy only fills in while nothing newer is loaded
+        if cached && slot.index.is_some() {
+            return false;
+        }
+        slot.index = Some(index);
+        if !cached {
+            slot.building = false;
+            slot.error = None;
+        }
+        true
+    }
+
+    fn fail(&self, root: &Path, generation: u64, error: String) {
+        if let Ok(mut slots) = self.slots.lock() {
+            if let Some(slot) = slots.get_mut(root).filter(|s| s.generation == generation) {
+                slot.building = false;
+                slot.error = Some(error);
+            }
+        }
+    }
+
+    /// Drop indexes for roots that are no longer open
+    pub fn retain(&self, roots: &[PathBuf]) {
+        if let Ok(mut slots) = self.slots.lock() {
+            slots.retain(|root, _| roots.contains(root));
+        }
+    }
+
+    /// The index for `root` when it can be trusted as-is. A stale one triggers
+    /// a background rebuild, and callers walk the tree live meanwhile.
+    pub fn fresh(&self, app: &AppHandle, root: &Path) -> Option<Arc<FileIndex>> {
+        let root = canonicalize(root).ok()?;
+        let (index, building) = {
+            let slots = self.slots.lock().ok()?;
+            let slot = slots.get(&root)?;
+            (slot.index.clone(), slot.building)
+        };
+        match index {
+            Some(index) if !building && index.is_fresh(&root) => Some(index),
+            _ => {
+                if !building {
+                    self.rebuild(app, &root);
+                }
+                None
+            }
+        }
+    }
+
+    fn status(&self, root: &Path) -> IndexStatus {
+        let slots = self.slots.lock().ok();
+        let Some(slot) = slots.as_ref().and_then(|s| s.get(root)) else {
+            return IndexStatus {
+                state: IndexState::Missing,
+                file_count: 0,
+                updated_at: None,
+                truncated: false,
+                error: None,
+            };
+        };
+        let index = slot.index.as_deref();
+        let state = if slot.building {
+            IndexState::Building
+        } else if index.is_some_and(|i| i.is_fresh(root)) {
+            IndexState::Ready
+        } else {
+            IndexState::Stale
+        };
+        IndexStatus {
+            state,
+            file_count: index.map_or(0, |i| i.files.len()),
+            updated_at: index.map(|i| i.built_at),
+            truncated: index.is_some_and(|i| i.truncated),
+            error: slot.error.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IndexStatus {
+    pub state: IndexState,
+    pub file_count: usize,
+    pub updated_at: Option<u64>,
+    /// The tree has more files than the index holds; searches walk instead
+    pub truncated: bool,
+    pub error: Option<String>,
+}
+
+#[tauri::command]
+pub async fn get_index_status(
+    app: AppHandle,
+    root: String,
+    index: State<'_, IndexRegistry>,
+) -> Result<IndexStatus, EngineError> {
+    let allowed = sandbox::allow(&app, &root)?;
+    let root = canonicalize(&allowed).map_err(|e| EngineError::io(&root, e))?;
+    Ok(index.status(&root))
+}
+
+#[tauri::command]
+pub async fn rebuild_index(
+    app: AppHandle,
+    root: String,
+    index: State<'_, IndexRegistry>,
+) -> Result<IndexStatus, EngineError> {
+    let allowed = sandbox::allow(&app, &root)?;
+    let root = canonicalize(&allowed).map_err(|e| EngineError::io(&root, e))?;
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            root.display()
+        )));
+    }
+    if !settings::load(&app).index.enabled {
+        return Err(EngineError::invalid("Workspace indexing is turned off"));
+    }
+    index.rebuild(&app, &root);
+    Ok(index.status(&root))
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FindFilesOptions {
+    pub max_results: usize,
+}
+
+impl Default for FindFilesOptions {
+    fn default() -> Self {
+        Self { max_results: 50 }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileMatch {
+    pub path: String,
+    pub relative: String,
+    /// Lower is better
+    pub score: u32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FindFilesResult {
+    pub files: Vec<FileMatch>,
+    pub truncated: bool,
+    /// Answered from the index rather than a live walk
+    pub from_index: bool,
+}
+
+/// Quick-open ranking: a hit in the file name beats one in the directories,
+/// which beats the query's letters merely appearing in order
+fn fuzzy_score(path: &str, query: &str) -> Option<u32> {
+    let lower = path.to_lowercase();
+    let name = lower.rsplit('/').next().unwrap_or(&lower);
+    let length = lower.len() as u32;
+    if let Some(at) = name.find(query) {
+        let extra = (name.len() - query.len()) as u32;
+        return Some(if at == 0 { extra } else { 100 + extra });
+    }
+    if lower.contains(query) {
+        return Some(1_000 + length);
+    }
+    let mut gaps = 0u32;
+    let mut chars = lower.chars();
+    for wanted in query.chars() {
+        loop {
+            let c = chars.next()?;
+            if c == wanted {
+                break;
+            }
+            gaps += 1;
+        }
+    }
+    Some(10_000 + gaps * 10 + length)
+}
+
+#[tauri::command]
+pub async fn find_files(
+    app: AppHandle,
+    root: String,
+    query: String,
+    options: Option<FindFilesOptions>,
+) -> Result<FindFilesResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let query = query.trim().to_lowercase();
+    let allowed = sandbox::allow(&app, &root)?;
+    let root_path = canonicalize(&allowed).map_err(|e| EngineError::io(&root, e))?;
+    let (index, from_index) = match app.state::<IndexRegistry>().fresh(&app, &root_path) {
+        Some(index) => (index, true),
+        None => {
+            let root = root_path.clone();
+            let index = tokio::task::spawn_blocking(move || scan(&root, false))
+                .await
+                .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+            (Arc::new(index), false)
+        }
+    };
+
+    let mut files: Vec<FileMatch> = index
+        .files
+        .iter()
+        .filter_map(|file| {
+            let score = if query.is_empty() {
+                0
+            } else {
+                fuzzy_score(&file.path, &query)?
+            };
+            Some(FileMatch {
+                path: root_path.join(&file.path).display().to_string(),
+                relative: file.path.clone(),
+                score,
+            })
+        })
+        .collect();
+    files.sort_by(|a, b| {
+        a.score
+            .cmp(&b.score)
+            .then_with(|| a.relative.cmp(&b.relative))
+    });
+    let truncated = files.len() > options.max_results || index.truncated;
+    files.truncate(options.max_results);
+    Ok(FindFilesResult {
+        files,
+        truncated,
+        from_index,
+    })
+}
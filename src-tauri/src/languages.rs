@@ -0,0 +1,563 @@
+use crate::error::EngineError;
+use crate::sandbox;
+use serde::Serialize;
+use std::io::Read;
+use std::path::Path;
+use tauri::AppHandle;
+
+// Only the head of the file is needed for shebangs, modelines and heuristics
+const SNIFF_BYTES: usize = 4096;
+
+pub struct Language {
+    pub id: &'static str,
+    /// Markdown fence tag
+    pub fence: &'static str,
+    pub extensions: &'static [&'static str],
+    /// Exact file names, matched case-sensitively
+    pub filenames: &'static [&'static str],
+    /// Shebang interpreters and modeline names besides `id`
+    pub interpreters: &'static [&'static str],
+}
+
+// Shared by symbol search, context packing and highlighting; add languages here only
+pub const LANGUAGES: &[Language] = &[
+    Language {
+        id: "rust",
+        fence: "rust",
+        extensions: &["rs"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "typescript",
+        fence: "typescript",
+        extensions: &["ts", "tsx", "mts", "cts"],
+        filenames: &[],
+        interpreters: &["ts-node", "deno", "tsx"],
+    },
+    Language {
+        id: "javascript",
+        fence: "javascript",
+        extensions: &["js", "jsx", "mjs", "cjs"],
+        filenames: &[],
+        interpreters: &["node", "js"],
+    },
+    Language {
+        id: "python",
+        fence: "python",
+        extensions: &["py", "pyi", "pyw"],
+        filenames: &["SConstruct", "SConscript"],
+        interpreters: &["python", "python2", "python3", "py"],
+    },
+    Language {
+        id: "go",
+        fence: "go",
+        extensions: &["go"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "c",
+        fence: "c",
+        extensions: &["c"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "cpp",
+        fence: "cpp",
+        extensions: &["cc", "cpp", "cxx", "c++", "hpp", "hh", "hxx", "ipp"],
+        filenames: &[],
+        interpreters: &["c++"],
+    },
+    Language {
+        id: "csharp",
+        fence: "csharp",
+        extensions: &["cs"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "java",
+        fence: "java",
+        extensions: &["java"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "kotlin",
+        fence: "kotlin",
+        extensions: &["kt", "kts"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "swift",
+        fence: "swift",
+        extensions: &["swift"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "ruby",
+        fence: "ruby",
+        extensions: &["rb", "rake", "gemspec"],
+        filenames: &["Rakefile", "Gemfile"],
+        interpreters: &["ruby"],
+    },
+    Language {
+        id: "php",
+        fence: "php",
+        extensions: &["php"],
+        filenames: &[],
+        interpreters: &["php"],
+    },
+    Language {
+        id: "bash",
+        fence: "bash",
+        extensions: &["bash"],
+        filenames: &[".bashrc", ".bash_profile"],
+        interpreters: &["bash"],
+    },
+    Language {
+        id: "shell",
+        fence: "sh",
+        extensions: &["sh"],
+        filenames: &[".profile"],
+        interpreters: &["sh", "dash", "ksh"],
+    },
+    Language {
+        id: "zsh",
+        fence: "zsh",
+        extensions: &["zsh"],
+        filenames: &[".zshrc"],
+        interpreters: &["zsh"],
+    },
+    Language {
+        id: "powershell",
+        fence: "powershell",
+        extensions: &["ps1", "psm1", "psd1"],
+        filenames: &[],
+        interpreters: &["pwsh", "powershell"],
+    },
+    Language {
+        id: "lua",
+        fence: "lua",
+        extensions: &["lua"],
+        filenames: &[],
+        interpreters: &["lua"],
+    },
+    Language {
+        id: "perl",
+        fence: "perl",
+        extensions: &["pl", "pm"],
+        filenames: &[],
+        interpreters: &["perl"],
+    },
+    Language {
+        id: "makefile",
+        fence: "makefile",
+        extensions: &["mk", "mak"],
+        filenames: &["Makefile", "makefile", "GNUmakefile"],
+        interpreters: &["make"],
+    },
+    Language {
+        id: "dockerfile",
+        fence: "dockerfile",
+        extensions: &["dockerfile"],
+        filenames: &["Dockerfile", "Containerfile"],
+        interpreters: &[],
+    },
+    Language {
+        id: "cmake",
+        fence: "cmake",
+        extensions: &["cmake"],
+        filenames: &["CMakeLists.txt"],
+        interpreters: &[],
+    },
+    Language {
+        id: "json",
+        fence: "json",
+        extensions: &["json", "jsonc"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "toml",
+        fence: "toml",
+        extensions: &["toml"],
+        filenames: &["Cargo.lock"],
+        interpreters: &[],
+    },
+    Language {
+        id: "yaml",
+        fence: "yaml",
+        extensions: &["yml", "yaml"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "markdown",
+        fence: "markdown",
+        extensions: &["md", "markdown"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "html",
+        fence: "html",
+        extensions: &["html", "htm"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "css",
+        fence: "css",
+        extensions: &["css", "scss", "less"],
+        filenames: &[],
+        interpreters: &[],
+    },
+    Language {
+        id: "sql",
+        fence: "sql",
+        extensions: &["sql"],
+        filenames: &[],
+        interpreters: &[],
+    },
+];
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum DetectionSource {
+    Modeline,
+    Filename,
+    Shebang,
+    Extension,
+    Heuristic,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Detection {
+    pub language: &'static str,
+    pub confidence: f32,
+    pub source: DetectionSource,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PathDetection {
+    pub path: String,
+    pub detection: Option<Detection>,
+}
+
+pub fn by_id(id: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|l| l.id == id)
+}
+
+fn by_name(name: &str) -> Option<&'static Language> {
+    let name = name.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.id == name || l.interpreters.contains(&name.as_str()))
+}
+
+fn by_extension(ext: &str) -> Option<&'static Language> {
+    let ext = ext.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.extensions.contains(&ext.as_str()))
+}
+
+/// A language by id, fence tag or extension, e.g. `rust`, `sh`, `tsx`
+pub fn lookup(name: &str) -> Option<&'static Language> {
+    let lower = name.to_ascii_lowercase();
+    LANGUAGES
+        .iter()
+        .find(|l| l.id == lower || l.fence == lower)
+        .or_else(|| by_extension(&lower))
+}
+
+/// Markdown fence tag for a path, empty when unknown
+pub fn fence_for(path: &str) -> &'static str {
+    detect_path(Path::new(path))
+        .and_then(|d| by_id(d.language))
+        .map(|l| l.fence)
+        .unwrap_or("")
+}
+
+fn detection(language: &'static str, confidence: f32, source: DetectionSource) -> Detection {
+    Detection {
+        language,
+        confidence,
+        source,
+    }
+}
+
+/// `#!/usr/bin/env python3`, `#!/bin/bash -e`, `#!/usr/bin/env -S deno run`
+fn shebang(head: &str) -> Option<&'static Language> {
+    let line = head.lines().next()?.strip_prefix("#!")?;
+    let mut words = line.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|w| !w.starts_with('-'))?;
+    }
+    // python3.11 -> python3 -> python
+    let trimmed = program.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    by_name(program).or_else(|| by_name(trimmed))
+}
+
+/// Emacs `-*- mode: python -*-` / `-*- python -*-` and Vim `vim: set ft=python:`
+fn modeline(head: &str) -> Option<&'static Language> {
+    let lines: Vec<&str> = head.lines().collect();
+    let candidates = lines.iter().take(5).chain(lines.iter().rev().take(5));
+    for line in candidates {
+        if let Some(start) = line.find("-*-") {
+            let rest = &line[start + 3..];
+            let body = rest.split("-*-").next().unwrap_or("");
+            let mode = body
+                .split(';')
+                .find_map(|part| {
+                    let part = part.trim();
+                    match part.split_once(':') {
+                        Some((key, value)) if key.trim().eq_ignore_ascii_case("mode") => {
+                            Some(value.trim())
+                        }
+                        Some(_) => None,
+                        None => Some(part),
+                    }
+                })
+                .unwrap_or("");
+            let mode = mode.trim_end_matches("-mode");
+            if let Some(lang) = by_name(mode).or_else(|| emacs_alias(mode)) {
+                return Some(lang);
+            }
+        }
+        for marker in ["vim:", "vi:", "ex:"] {
+            let Some(start) = line.find(marker) else {
+                continue;
+            };
+            let settings = &line[start + marker.len()..];
+            let found = settings
+                .split(|c: char| c.is_whitespace() || c == ':')
+                .find_map(|s| {
+                    s.strip_prefix("ft=")
+                        .or_else(|| s.strip_prefix("filetype="))
+                        .or_else(|| s.strip_prefix("syntax="))
+                });
+            if let Some(lang) = found.and_then(|ft| by_name(ft).or_else(|| vim_alias(ft))) {
+                return Some(lang);
+            }
+        }
+    }
+    None
+}
+
+fn emacs_alias(mode: &str) -> Option<&'static Language> {
+    match mode {
+        "c++" => by_id("cpp"),
+        "sh" | "shell-script" => by_id("shell"),
+        "js" | "js2" => by_id("javascript"),
+        _ => None,
+    }
+}
+
+fn vim_alias(ft: &str) -> Option<&'static Language> {
+    match ft {
+        "sh" => by_id("shell"),
+        "make" => by_id("makefile"),
+        "cs" => by_id("csharp"),
+        "ps1" => by_id("powershell"),
+        _ => None,
+    }
+}
+
+fn looks_like_cpp(head: &str) -> bool {
+    const MARKERS: &[&str] = &[
+        "class ",
+        "namespace ",
+        "template<",
+        "template <",
+        "std::",
+        "public:",
+        "private:",
+        "#include <iostream>",
+        "#include <string>",
+        "#include <vector>",
+        "constexpr ",
+        "nullptr",
+    ];
+    MARKERS.iter().any(|m| head.contains(m))
+}
+
+fn looks_like_bash(head: &str) -> bool {
+    const MARKERS: &[&str] = &["[[ ", "function ", "declare ", "local -a", "shopt ", "$(("];
+    MARKERS.iter().any(|m| head.contains(m))
+}
+
+/// Detection from the path alone
+pub fn detect_path(path: &Path) -> Option<Detection> {
+    let name = path.file_name()?.to_str()?;
+    if let Some(lang) = LANGUAGES.iter().find(|l| l.filenames.contains(&name)) {
+        return Some(detection(lang.id, 0.95, DetectionSource::Filename));
+    }
+    // Dockerfile.dev, Makefile.am and friends
+    if let Some((stem, _)) = name.split_once('.') {
+        if let Some(lang) = LANGUAGES
+            .iter()
+            .find(|l| matches!(l.id, "dockerfile" | "makefile") && l.filenames.contains(&stem))
+        {
+            return Some(detection(lang.id, 0.8, DetectionSource::Filename));
+        }
+    }
+    let ext = path.extension()?.to_str()?;
+    // .h is shared by C and C++; without content call it C with low confidence
+    if ext.eq_ignore_ascii_case("h") {
+        return Some(detection("c", 0.5, DetectionSource::Extension));
+    }
+    by_extension(ext).map(|lang| detection(lang.id, 0.9, DetectionSource::Extension))
+}
+
+/// Full detection: modeline, file name, shebang, extension, then content heuristics
+pub fn detect(path: &Path, head: &[u8]) -> Option<Detection> {
+    let head = String::from_utf8_lossy(&head[..head.len().min(SNIFF_BYTES)]);
+    if let Some(lang) = modeline(&head) {
+        return Some(detection(lang.id, 1.0, DetectionSource::Modeline));
+    }
+    let from_path = detect_path(path);
+    if let Some(d) = &from_path {
+        if d.source == DetectionSource::Filename {
+            return from_path;
+        }
+    }
+    if let Some(lang) = shebang(&head) {
+        let lang = match lang.id {
+            "shell" if looks_like_bash(&head) => by_id("bash").unwrap_or(lang),
+            _ => lang,
+        };
+        return Some(detection(lang.id, 0.95, DetectionSource::Shebang));
+    }
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match (ext.as_deref(), from_path) {
+        (Some("h"), _) if looks_like_cpp(&head) => {
+            Some(detection("cpp", 0.8, DetectionSource::Heuristic))
+        }
+        (Some("h"), _) => Some(detection("c", 0.7, DetectionSource::Heuristic)),
+        (Some("sh"), _) if looks_like_bash(&head) => {
+            Some(detection("bash", 0.7, DetectionSource::Heuristic))
+        }
+        (_, Some(d)) => Some(d),
+        (None, None) if head.trim_start().starts_with("<?php") => {
+            Some(detection("php", 0.9, DetectionSource::Heuristic))
+        }
+        _ => None,
+    }
+}
+
+/// Read the head of a file and detect its language
+pub fn detect_file(path: &Path) -> Result<Option<Detection>, EngineError> {
+    let mut file = std::fs::File::open(path).map_err(|e| EngineError::io(path, e))?;
+    let mut head = vec![0u8; SNIFF_BYTES];
+    let mut filled = 0;
+    while filled < head.len() {
+        match file.read(&mut head[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(e) => return Err(EngineError::io(path, e)),
+        }
+    }
+    head.truncate(filled);
+    Ok(detect(path, &head))
+}
+
+#[tauri::command]
+pub async fn detect_language(
+    app: AppHandle,
+    path: String,
+) -> Result<Option<Detection>, EngineError> {
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || detect_file(&path))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Unreadable files, and ones outside the workspace, come back with no
+/// detection rather than failing the batch
+#[tauri::command]
+pub async fn detect_languages(
+    app: AppHandle,
+    paths: Vec<String>,
+) -> Result<Vec<PathDetection>, EngineError> {
+    tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .map(|path| PathDetection {
+                detection: sandbox::allow(&app, &path)
+                    .and_then(|allowed| detect_file(&allowed))
+                    .ok()
+                    .flatten(),
+                path,
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixtures_are_detected() {
+        use DetectionSource::*;
+        let cases: &[(&str, Option<(&str, DetectionSource)>)] = &[
+            ("Makefile", Some(("makefile", Filename))),
+            ("Dockerfile", Some(("dockerfile", Filename))),
+            ("Dockerfile.dev", Some(("dockerfile", Filename))),
+            ("CMakeLists.txt", Some(("cmake", Filename))),
+            ("App.tsx", Some(("typescript", Extension))),
+            ("sync-mirrors", Some(("python", Shebang))),
+            ("deploy", Some(("bash", Shebang))),
+            ("bootstrap", Some(("shell", Shebang))),
+            ("widget.h", Some(("cpp", Heuristic))),
+            ("list.h", Some(("c", Heuristic))),
+            ("build.conf", Some(("python", Modeline))),
+            ("LICENSE", None),
+        ];
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/languages");
+        for (name, expected) in cases {
+            let detected = detect_file(&dir.join(name))
+                .unwrap()
+                .map(|d| (d.language, d.source));
+            assert_eq!(detected, *expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn path_only_detection() {
+        let cases = [
+            ("src/main.rs", Some("rust")),
+            ("web/App.TSX", Some("typescript")),
+            ("Makefile.am", Some("makefile")),
+            ("GNUmakefile", Some("makefile")),
+            ("project/CMakeLists.txt", Some("cmake")),
+            ("notes.txt", None),
+            ("cmakelists.txt", None),
+            ("scripts/sync-mirrors", None),
+        ];
+        for (path, expected) in cases {
+            let detected = detect_path(Path::new(path)).map(|d| d.language);
+            assert_eq!(detected, expected, "{}", path);
+        }
+    }
+
+    #[test]
+    fn lookups_and_fences() {
+        assert_eq!(lookup("tsx").map(|l| l.id), Some("typescript"));
+        assert_eq!(lookup("RS").map(|l| l.id), Some("rust"));
+        assert_eq!(fence_for("Dockerfile"), "dockerfile");
+        assert_eq!(fence_for("unknown.zzz"), "");
+    }
+}
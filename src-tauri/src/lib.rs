@@ -0,0 +1,965 @@
+mod api;
+mod app_update;
+mod apply;
+mod approval;
+mod archive;
+mod attachments;
+mod backend;
+mod batch;
+mod bootstrap;
+pub mod cancel;
+pub mod claude;
+mod cli_install;
+mod cli_slots;
+mod cli_status;
+mod clock;
+mod commands;
+mod compare;
+mod context;
+mod cooldown;
+mod deep_link;
+mod diff;
+mod dispatch;
+mod dropped;
+mod encoding;
+mod env_profile;
+pub mod error;
+mod estimate;
+mod export;
+mod fetch;
+mod files;
+mod formatter;
+mod git;
+mod hash;
+pub mod highlight;
+mod history;
+mod history_search;
+mod images;
+mod importers;
+mod index;
+mod languages;
+mod listing;
+mod local_api;
+mod logging;
+mod markdown;
+mod mentions;
+pub mod metrics;
+mod models;
+mod paths;
+mod pdf;
+mod permissions;
+mod pipelines;
+mod postprocess;
+mod proctree;
+mod project_context;
+mod queue;
+mod regenerate;
+mod replace;
+mod retry;
+mod runner;
+mod sandbox;
+mod scheduler;
+mod screenshot;
+mod search;
+mod secrets;
+mod session;
+mod settings;
+mod spill;
+mod startup;
+mod stop;
+mod stream_file;
+mod stream_input;
+mod structured;
+mod summarize;
+mod supervisor;
+mod symbols;
+mod template_vars;
+mod templates;
+mod test_runner;
+mod titles;
+mod tokens;
+mod tree;
+mod usage_ledger;
+pub mod walker;
+mod watcher;
+mod webhooks;
+mod workspace_config;
+
+use api::Route;
+use backend::ActiveBackend;
+use cancel::CancelRegistry;
+use cancel::CancelToken;
+use claude::{
+    cli_error, CliSession, FailedTurns, RequestProgress, SendOptions, SendReply, StreamOptions,
+    StreamReply,
+};
+use cooldown::Cooldown;
+use dispatch::{expand_mentions, Origin};
+use error::EngineError;
+use local_api::LocalApiServer;
+use queue::TurnQueue;
+use retry::RetryPolicy;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use stream_input::StreamInputs;
+#[cfg(feature = "updater")]
+use tauri::UpdaterEvent;
+use tauri::{AppHandle, Manager, RunEvent, State, Window};
+
+#[tauri::command]
+async fn send_to_claude(
+    app: AppHandle,
+    message: String,
+    confirmed: Option<bool>,
+    options: Option<SendOptions>,
+) -> Result<SendReply, EngineError> {
+    let options = options.unwrap_or_default();
+    let model = models::requested_model(options.model)?;
+    options.tools.validate()?;
+    let cwd = claude::working_dir(options.cwd)?;
+    let (message, unresolved_mentions) =
+        expand_mentions(&app, message, options.expand_mentions, cwd.as_deref()).await?;
+    estimate::confirm_send(&app, &message, model.as_deref(), confirmed)?;
+    let transforms = postprocess::resolve(&app, &options.transforms)?;
+    let mut spawn = env_profile::spawn_options(&app, cwd)?;
+    spawn.model = model.clone();
+    spawn.reject_if_busy = options.reject_if_busy;
+    spawn.session = CliSession::from_request(options.session_id, options.continue_last);
+    spawn.tools = options.tools;
+    spawn.system_prompt = options.system_prompt;
+    spawn.append_system_prompt = options.append_system_prompt;
+    if let Some(secs) = options.timeout_secs {
+        spawn.limits.send_timeout = Duration::from_secs(secs);
+    }
+    let session = spawn.session.clone();
+    let route = api::route(&spawn.engine).await?;
+    let attached = attachments::attach(&app, &message, options.attachments).await?;
+    let (prompt, turns) = match &route {
+        Route::Api(_) => {
+            let mut turns = api::turns(&[], attached.api_text());
+            attached.add_images(&mut turns)?;
+            (String::new(), turns)
+        }
+        Route::Cli => (attached.cli_prompt(), Vec::new()),
+    };
+    let announce = |retrying| {
+        let _ = app.emit_all("claude-retrying", retrying);
+    };
+    let cooldown = app.state::<Cooldown>();
+    cooldown.wait(None, &CancelToken::default()).await?;
+    let backend = app.state::<ActiveBackend>();
+    let request_id = options
+        .request_id
+        .unwrap_or_else(|| format!("send-{}", hash::random_hex(6)));
+    let progress: Arc<dyn claude::StreamSink> =
+        Arc::new(RequestProgress::new(app.clone(), request_id.clone()));
+    spawn.request_id = Some(request_id);
+    let result = retry::retry(
+        &RetryPolicy::from_config(&spawn.engine),
+        &CancelToken::default(),
+        announce,
+        || async {
+            match &route {
+                // No CLI process, so no backend or slot
+                Route::Api(key) => api::send(key, turns.clone(), &spawn).await,
+                Route::Cli => backend
+                    .send(&prompt, progress.clone(), spawn.clone())
+                    .await
+                    .map_err(|detail| cli_error(detail, &session, model.as_deref())),
+            }
+        },
+    )
+    .await;
+    cooldown.record(&app, &result);
+    let mut reply = result?;
+    usage_ledger::record(&app, model.as_deref(), &reply);
+    reply.text = postprocess::apply(&transforms, &reply.text);
+    Ok(SendReply {
+        reply,
+        unresolved_mentions,
+    })
+}
+
+#[tauri::command]
+async fn stream_to_claude(
+    window: Window,
+    message: String,
+    webhook_url: Option<String>,
+    conversation_id: Option<String>,
+    confirmed: Option<bool>,
+    options: Option<StreamOptions>,
+) -> Result<StreamReply, EngineError> {
+    dispatch::stream(
+        &window.app_handle(),
+        Origin::Window(Box::new(window)),
+        message,
+        webhook_url,
+        conversation_id,
+        confirmed,
+        options.unwrap_or_default(),
+    )
+    .await
+}
+
+/// The CLI command line `stream_to_claude` (or `send_to_claude`, without
+/// `streamed`) would run for `message` and `options`, with the program,
+/// working directory and environment it would get, secrets masked; nothing
+/// is spawned. The approval server a stream may use shows as a placeholder.
+#[tauri::command]
+async fn preview_claude_invocation(
+    app: AppHandle,
+    message: Option<String>,
+    streamed: Option<bool>,
+    options: Option<StreamOptions>,
+) -> Result<claude::Invocation, EngineError> {
+    let options = options.unwrap_or_default();
+    let streamed = streamed.unwrap_or(true);
+    let model = models::requested_model(options.model)?;
+    options.tools.validate()?;
+    let cwd = claude::working_dir(options.cwd)?;
+    let mut spawn = env_profile::spawn_options(&app, cwd)?;
+    spawn.model = model;
+    spawn.session = CliSession::from_request(options.session_id, options.continue_last);
+    spawn.tools = options.tools;
+    spawn.system_prompt = options.system_prompt;
+    spawn.append_system_prompt = options.append_system_prompt;
+    if streamed {
+        if spawn.engine.permission_prompts {
+            spawn.approval = Some(approval::ApprovalRoute::placeholder());
+        }
+        spawn.stdin = options
+            .stdin_input
+            .map(|format| Arc::new(claude::OpenStdin::new(format)));
+    }
+    let uses_api = matches!(api::route(&spawn.engine).await, Ok(Route::Api(_)));
+    let message = message.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        claude::preview_invocation(&spawn, streamed, &message, uses_api)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Send the turn that last failed in `session_id` again, resuming that
+/// session so the CLI sees what it had already said
+#[tauri::command]
+async fn retry_last_turn(window: Window, session_id: String) -> Result<StreamReply, EngineError> {
+    let (message, options) = window
+        .state::<FailedTurns>()
+        .take(&session_id)
+        .ok_or_else(|| {
+            EngineError::invalid(format!("No failed turn to retry in session {}", session_id))
+        })?;
+    stream_to_claude(window, message, None, None, Some(true), Some(options)).await
+}
+
+/// Cancel one of this window's streams; another window's stream with the
+/// same id is left alone
+#[tauri::command]
+async fn cancel_stream(
+    window: Window,
+    request_id: String,
+    flush_queue: Option<bool>,
+    registry: State<'_, CancelRegistry>,
+    queue: State<'_, TurnQueue>,
+) -> Result<(), EngineError> {
+    // A message still waiting its turn is just taken out of line
+    if flush_queue.unwrap_or(false) {
+        queue.flush_behind(window.label(), &request_id);
+    }
+    if !queue.remove(window.label(), &request_id) {
+        registry.cancel_owned(window.label(), &request_id);
+    }
+    Ok(())
+}
+
+/// `FileTooLarge` when `target` is over `max_bytes`
+async fn check_size(
+    target: &std::path::Path,
+    path: &str,
+    max_bytes: Option<u64>,
+) -> Result<(), EngineError> {
+    let Some(limit) = max_bytes else {
+        return Ok(());
+    };
+    let size = tokio::fs::metadata(target)
+        .await
+        .map_err(|e| EngineError::fs(path, e))?
+        .len();
+    if size > limit {
+        return Err(EngineError::FileTooLarge {
+            path: path.to_string(),
+            size,
+            limit,
+        });
+    }
+    Ok(())
+}
+
+async fn read_text(
+    app: &AppHandle,
+    path: &str,
+    max_bytes: Option<u64>,
+    lossy: Option<bool>,
+) -> Result<encoding::DecodedText, EngineError> {
+    let target = sandbox::allow(app, path)?;
+    let _timer = metrics::timer(metrics::Stage::Io);
+    check_size(&target, path, max_bytes).await?;
+    let bytes = tokio::fs::read(&target)
+        .await
+        .map_err(|e| EngineError::io(path, e))?;
+    encoding::decode(&bytes, lossy.unwrap_or(false)).map_err(|tried| EngineError::DecodeFailed {
+        path: path.to_string(),
+        encoding: tried.to_string(),
+    })
+}
+
+/// The whole file as text, decoded from whatever encoding it's in; with
+/// `max_bytes`, anything bigger is refused as `FileTooLarge` instead of being
+/// shipped to the webview
+#[tauri::command]
+async fn read_file(
+    app: AppHandle,
+    path: String,
+    max_bytes: Option<u64>,
+    lossy: Option<bool>,
+) -> Result<String, EngineError> {
+    Ok(read_text(&app, &path, max_bytes, lossy).await?.content)
+}
+
+/// `read_file` plus the encoding and BOM it found, to pass back to `write_file`
+#[tauri::command]
+async fn read_file_with_encoding(
+    app: AppHandle,
+    path: String,
+    max_bytes: Option<u64>,
+    lossy: Option<bool>,
+) -> Result<encoding::DecodedText, EngineError> {
+    read_text(&app, &path, max_bytes, lossy).await
+}
+
+/// `length` bytes from `offset`, for paging through files too big to read
+/// whole; `snap_to_lines` keeps lines from being cut in half
+#[tauri::command]
+async fn read_file_range(
+    app: AppHandle,
+    path: String,
+    offset: Option<u64>,
+    length: u64,
+    snap_to_lines: Option<bool>,
+) -> Result<files::FileRange, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let _timer = metrics::timer(metrics::Stage::Io);
+    let (offset, snap) = (offset.unwrap_or(0), snap_to_lines.unwrap_or(false));
+    tokio::task::spawn_blocking(move || files::read_range(&target, offset, length, snap))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        .map_err(|e| EngineError::fs(&path, e))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct WriteResult {
+    /// sha256 of the whole file as written, to pass as the next `expected_hash`
+    hash: String,
+    backup_path: Option<String>,
+}
+
+/// Write through a temp file and rename so a crash or full disk never leaves
+/// `path` half written, or append in place
+async fn write_bytes(
+    path: String,
+    target: std::path::PathBuf,
+    bytes: Vec<u8>,
+    options: Option<files::WriteOptions>,
+) -> Result<WriteResult, EngineError> {
+    let options = options.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        if let Some(expected) = &options.expected_hash {
+            let actual = match hash::sha256_file(&target) {
+                Ok(actual) => Some(actual),
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => None,
+                Err(e) => return Err(EngineError::fs(&path, e)),
+            };
+            if actual.as_deref() != Some(expected.trim()) {
+                return Err(EngineError::Conflict {
+                    path,
+                    expected: Some(expected.clone()),
+                    actual,
+                });
+            }
+        }
+        let backup = files::write_with(&target, &bytes, &options).map_err(|e| match e.kind() {
+            std::io::ErrorKind::AlreadyExists => EngineError::AlreadyExists { path: path.clone() },
+            _ => EngineError::fs(&path, e),
+        })?;
+        let hash = if options.append {
+            hash::sha256_file(&target).map_err(|e| EngineError::fs(&path, e))?
+        } else {
+            hash::sha256_hex(&bytes)
+        };
+        Ok(WriteResult {
+            hash,
+            backup_path: backup.map(|b| b.display().to_string()),
+        })
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Write text as UTF-8, or in `encoding` to round-trip a file read with
+/// `read_file_with_encoding`. Parent directories are created; a read-only
+/// target is `PermissionDenied`.
+#[tauri::command]
+async fn write_file(
+    app: AppHandle,
+    path: String,
+    content: String,
+    encoding: Option<String>,
+    bom: Option<bool>,
+    options: Option<files::WriteOptions>,
+) -> Result<WriteResult, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let bytes = encoding::encode(
+        &content,
+        encoding.as_deref().unwrap_or("utf-8"),
+        bom.unwrap_or(false),
+    )?;
+    write_bytes(path, target, bytes, options).await
+}
+
+/// Raw bytes as base64 with a sniffed MIME type, for images, PDFs and other
+/// files that aren't text; `max_bytes` works as in `read_file`
+#[tauri::command]
+async fn read_file_binary(
+    app: AppHandle,
+    path: String,
+    max_bytes: Option<u64>,
+) -> Result<files::BinaryFile, EngineError> {
+    use base64::Engine;
+
+    let target = sandbox::allow(&app, &path)?;
+    let _timer = metrics::timer(metrics::Stage::Io);
+    check_size(&target, &path, max_bytes).await?;
+    let bytes = tokio::fs::read(&target)
+        .await
+        .map_err(|e| EngineError::fs(&path, e))?;
+    Ok(files::BinaryFile {
+        mime: files::sniff_mime(&bytes),
+        size: bytes.len() as u64,
+        data: base64::engine::general_purpose::STANDARD.encode(&bytes),
+    })
+}
+
+/// Decode base64 and write the bytes, the same way `write_file` writes text
+#[tauri::command]
+async fn write_file_binary(
+    app: AppHandle,
+    path: String,
+    data: String,
+    options: Option<files::WriteOptions>,
+) -> Result<WriteResult, EngineError> {
+    use base64::Engine;
+
+    let target = sandbox::allow(&app, &path)?;
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data.trim())
+        .map_err(|e| EngineError::invalid(format!("Data is not valid base64: {}", e)))?;
+    write_bytes(path, target, bytes, options).await
+}
+
+/// Null bytes in the first 8KB, so the frontend can pick `read_file_binary`
+/// over `read_file`
+#[tauri::command]
+async fn is_probably_binary(app: AppHandle, path: String) -> Result<bool, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || files::file_looks_binary(&target))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        .map_err(|e| EngineError::fs(&path, e))
+}
+
+#[tauri::command]
+async fn list_directory(app: AppHandle, path: String) -> Result<Vec<String>, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let mut entries = tokio::fs::read_dir(&target)
+        .await
+        .map_err(|e| EngineError::io(&path, e))?;
+
+    let mut files = Vec::new();
+    while let Some(entry) = entries
+        .next_entry()
+        .await
+        .map_err(|e| EngineError::io(&path, e))?
+    {
+        files.push(entry.path().display().to_string());
+    }
+    Ok(files)
+}
+
+#[tauri::command]
+async fn create_directory(app: AppHandle, path: String) -> Result<(), EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    tokio::fs::create_dir_all(&target)
+        .await
+        .map_err(|e| EngineError::io(&path, e))
+}
+
+/// Size, kind, timestamps and permissions; a missing path is `NotFound` and a
+/// refused one `PermissionDenied`. `follow` (the default) describes what a
+/// symlink points to, which must then be inside the workspace too.
+#[tauri::command]
+async fn stat_file(
+    app: AppHandle,
+    path: String,
+    follow: Option<bool>,
+) -> Result<files::FileStat, EngineError> {
+    let follow = follow.unwrap_or(true);
+    let target = sandbox::allow_entry(&app, &path)?;
+    if follow {
+        sandbox::allow(&app, &path)?;
+    }
+    let _timer = metrics::timer(metrics::Stage::Io);
+    tokio::task::spawn_blocking(move || files::stat(&target, follow))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        .map_err(|e| EngineError::fs(&path, e))
+}
+
+/// Where `path` leads once its links are followed, so a link can be shown
+/// with its target before anything opens it. Only the link itself has to be
+/// in the workspace; `inside_workspace` says whether the target is.
+#[tauri::command]
+async fn resolve_path(app: AppHandle, path: String) -> Result<files::ResolvedPath, EngineError> {
+    let entry = sandbox::allow_entry(&app, &path)?;
+    let workspace = app.state::<sandbox::Workspace>().roots();
+    let _timer = metrics::timer(metrics::Stage::Io);
+    tokio::task::spawn_blocking(move || {
+        files::resolve(&entry, |resolved| {
+            workspace.iter().any(|root| resolved.starts_with(root))
+        })
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+    .map_err(|e| EngineError::fs(&path, e))
+}
+
+/// Run a blocking file operation, mapping a clash at `to` to `AlreadyExists`
+/// and any other failure to an error about `path`
+async fn file_op<T: Send + 'static>(
+    path: &str,
+    to: Option<&str>,
+    op: impl FnOnce() -> std::io::Result<T> + Send + 'static,
+) -> Result<T, EngineError> {
+    let _timer = metrics::timer(metrics::Stage::Io);
+    let result = tokio::task::spawn_blocking(op)
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+    result.map_err(|e| match (e.kind(), to) {
+        (std::io::ErrorKind::AlreadyExists, Some(to)) => EngineError::AlreadyExists {
+            path: to.to_string(),
+        },
+        (std::io::ErrorKind::DirectoryNotEmpty, _) => EngineError::invalid(format!(
+            "{} is not empty; delete it with recursive to remove its contents",
+            path
+        )),
+        _ => EngineError::fs(path, e),
+    })
+}
+
+/// Delete a file or directory; a directory with anything in it needs `recursive`
+#[tauri::command]
+async fn delete_path(
+    app: AppHandle,
+    path: String,
+    recursive: Option<bool>,
+) -> Result<(), EngineError> {
+    let target = sandbox::allow_entry(&app, &path)?;
+    file_op(&path, None, move || {
+        files::remove_path(&target, recursive.unwrap_or(false))
+    })
+    .await
+}
+
+/// Move each path to the OS trash, reporting every path's outcome rather
+/// than stopping at the first failure
+#[tauri::command]
+async fn trash_path(
+    app: AppHandle,
+    paths: Vec<String>,
+) -> Result<Vec<files::TrashOutcome>, EngineError> {
+    let targets: Vec<_> = paths
+        .iter()
+        .map(|path| sandbox::allow_entry(&app, path))
+        .collect();
+    let _timer = metrics::timer(metrics::Stage::Io);
+    tokio::task::spawn_blocking(move || {
+        paths
+            .into_iter()
+            .zip(targets)
+            .map(|(path, target)| {
+                let error = target.and_then(|target| files::trash(&target)).err();
+                files::TrashOutcome {
+                    trashed: error.is_none(),
+                    path,
+                    error,
+                }
+            })
+            .collect()
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
+
+/// Rename or move, copying across drives; an existing `to` is only replaced with `overwrite`
+#[tauri::command]
+async fn rename_path(
+    app: AppHandle,
+    from: String,
+    to: String,
+    overwrite: Option<bool>,
+) -> Result<(), EngineError> {
+    let (source, dest) = (
+        sandbox::allow_entry(&app, &from)?,
+        sandbox::allow_entry(&app, &to)?,
+    );
+    file_op(&from, Some(&to), move || {
+        files::move_path(&source, &dest, overwrite.unwrap_or(false))
+    })
+    .await
+}
+
+/// Copy a file or a directory tree, returning how many entries were copied
+#[tauri::command]
+async fn copy_path(
+    app: AppHandle,
+    from: String,
+    to: String,
+    overwrite: Option<bool>,
+) -> Result<files::CopySummary, EngineError> {
+    let (source, dest) = (
+        sandbox::allow_entry(&app, &from)?,
+        sandbox::allow_entry(&app, &to)?,
+    );
+    file_op(&from, Some(&to), move || {
+        files::copy_path(&source, &dest, overwrite.unwrap_or(false))
+    })
+    .await
+}
+
+// Hashing is disk bound; more at once just seeks more
+const MAX_HASH_CONCURRENCY: usize = 4;
+
+/// Digest, size and mtime of a file, for telling whether it changed since it
+/// was read. sha256 matches the hashes the write commands return.
+#[tauri::command]
+async fn hash_file(
+    app: AppHandle,
+    path: String,
+    algorithm: Option<hash::HashAlgorithm>,
+) -> Result<hash::FileHash, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    let algorithm = algorithm.unwrap_or_default();
+    let _timer = metrics::timer(metrics::Stage::Io);
+    tokio::task::spawn_blocking(move || hash::hash_file(&target, algorithm))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        .map_err(|e| EngineError::fs(&path, e))
+}
+
+#[derive(Debug, serde::Serialize)]
+struct HashOutcome {
+    path: String,
+    #[serde(flatten)]
+    file: Option<hash::FileHash>,
+    error: Option<EngineError>,
+}
+
+/// `hash_file` for many paths a few at a time, in the order given; a path
+/// that can't be read gets an error without failing the rest
+#[tauri::command]
+async fn hash_files(
+    app: AppHandle,
+    paths: Vec<String>,
+    algorithm: Option<hash::HashAlgorithm>,
+) -> Result<Vec<HashOutcome>, EngineError> {
+    let algorithm = algorithm.unwrap_or_default();
+    let _timer = metrics::timer(metrics::Stage::Io);
+    let permits = Arc::new(tokio::sync::Semaphore::new(MAX_HASH_CONCURRENCY));
+    let tasks: Vec<_> = paths
+        .into_iter()
+        .map(|path| {
+            let target = sandbox::allow(&app, &path);
+            let permits = Arc::clone(&permits);
+            tauri::async_runtime::spawn(async move {
+                let _permit = permits.acquire_owned().await;
+                let result = match target {
+                    Ok(target) => {
+                        tokio::task::spawn_blocking(move || hash::hash_file(&target, algorithm))
+                            .await
+                            .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+                            .and_then(|hashed| hashed.map_err(|e| EngineError::fs(&path, e)))
+                    }
+                    Err(e) => Err(e),
+                };
+                let (file, error) = match result {
+                    Ok(file) => (Some(file), None),
+                    Err(e) => (None, Some(e)),
+                };
+                HashOutcome { path, file, error }
+            })
+        })
+        .collect();
+    let mut outcomes = Vec::with_capacity(tasks.len());
+    for task in tasks {
+        outcomes.push(
+            task.await
+                .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?,
+        );
+    }
+    Ok(outcomes)
+}
+
+#[tauri::command]
+async fn file_exists(app: AppHandle, path: String) -> Result<bool, EngineError> {
+    let target = sandbox::allow(&app, &path)?;
+    Ok(tokio::fs::metadata(&target).await.is_ok())
+}
+
+/// Build and run the app; `main.rs` only calls this, so benches can link the crate
+pub fn run() {
+    // Must run first: a second instance hands its link to the running one and exits
+    tauri_plugin_deep_link::prepare("com.bupsengine.app");
+    startup::mark_process_start();
+    let context = tauri::generate_context!();
+    // Before anything reads or writes the app's directories
+    let report = bootstrap::run(context.config());
+
+    tauri::Builder::default()
+        .manage(report)
+        .manage(CancelRegistry::default())
+        .manage(TurnQueue::default())
+        .manage(titles::TitleJobs::default())
+        .manage(usage_ledger::UsageLedger::default())
+        .manage(project_context::ProjectContexts::default())
+        .manage(FailedTurns::default())
+        .manage(Cooldown::default())
+        .manage(StreamInputs::default())
+        .manage(history_search::HistorySearch::default())
+        .manage(ActiveBackend::default())
+        .manage(approval::Approvals::default())
+        .manage(LocalApiServer::default())
+        .manage(deep_link::PendingDeepLinks::default())
+        .manage(app_update::AppUpdateState::default())
+        .manage(sandbox::Workspace::default())
+        .manage(listing::ListingCache::default())
+        .manage(index::IndexRegistry::default())
+        .manage(formatter::FormatterCache::default())
+        .manage(scheduler::Scheduler::default())
+        .manage(webhooks::WebhookLog::default())
+        .manage(session::SessionPool::default())
+        .manage(startup::Startup::default())
+        .manage(supervisor::Supervisor::default())
+        .manage(replace::ReplacePlans::default())
+        .manage(watcher::Watchers::default())
+        .setup(|app| {
+            let started = Instant::now();
+            logging::init(&app.handle());
+            app.state::<bootstrap::BootstrapReport>().log();
+            deep_link::setup(&app.handle());
+            // Loading state and probing the CLI wait until the window is up
+            startup::spawn_deferred_init(app.handle());
+            app_update::spawn_daily_check(app.handle());
+            session::spawn_reaper(app.handle());
+            metrics::spawn_snapshots(app.handle());
+            let handle = app.handle();
+            tauri::async_runtime::spawn(async move {
+                // A taken port shouldn't stop the app from starting
+                let _ = local_api::start_if_enabled(handle).await;
+            });
+            startup::record_setup(&app.handle(), started);
+            Ok(())
+        })
+        .invoke_handler(tauri::generate_handler![
+            send_to_claude,
+            stream_to_claude,
+            preview_claude_invocation,
+            retry_last_turn,
+            cooldown::get_engine_status,
+            cooldown::force_resume,
+            approval::respond_permission,
+            cancel_stream,
+            queue::clear_queue,
+            read_file,
+            read_file_with_encoding,
+            read_file_range,
+            read_file_binary,
+            write_file_binary,
+            is_probably_binary,
+            write_file,
+            list_directory,
+            create_directory,
+            file_exists,
+            hash_file,
+            hash_files,
+            stat_file,
+            resolve_path,
+            delete_path,
+            trash_path,
+            rename_path,
+            copy_path,
+            apply::preview_apply_block,
+            apply::confirm_apply_block,
+            apply::apply_patch,
+            diff::compute_diff,
+            archive::zip_directory,
+            archive::unzip_archive,
+            context::build_project_context,
+            context::save_context_snapshot,
+            context::load_context_snapshot,
+            context::list_context_snapshots,
+            context::delete_context_snapshot,
+            cancel::cancel_request,
+            symbols::find_symbols,
+            fetch::fetch_url,
+            pdf::extract_pdf_text,
+            attachments::prepare_attachments,
+            dropped::ingest_dropped_paths,
+            local_api::get_local_api_status,
+            local_api::set_local_api_enabled,
+            local_api::get_local_api_token,
+            deep_link::take_pending_deep_links,
+            app_update::check_app_update,
+            app_update::download_and_install_update,
+            app_update::relaunch_app,
+            app_update::set_update_auto_check,
+            sandbox::set_workspace_roots,
+            export::export_text,
+            export::suggest_export_path,
+            structured::preview_structured_file,
+            structured::summarize_structured_file_for_prompt,
+            languages::detect_language,
+            languages::detect_languages,
+            test_runner::detect_test_command,
+            test_runner::run_tests,
+            commands::run_command,
+            commands::kill_command,
+            git::git_status,
+            git::git_diff,
+            git::git_current_branch,
+            settings::get_settings,
+            settings::update_settings,
+            settings::reset_settings,
+            logging::get_recent_logs,
+            logging::set_log_level,
+            formatter::format_text,
+            formatter::format_file,
+            env_profile::set_workspace_env,
+            env_profile::get_effective_env,
+            env_profile::get_spawn_environment,
+            listing::list_directory_detailed,
+            scheduler::create_schedule,
+            scheduler::list_schedules,
+            scheduler::update_schedule,
+            scheduler::delete_schedule,
+            webhooks::set_webhook,
+            webhooks::get_webhook_secret,
+            webhooks::get_webhook_deliveries,
+            compare::compare_models,
+            pipelines::run_pipeline,
+            pipelines::save_pipeline,
+            pipelines::load_pipeline,
+            pipelines::list_pipelines,
+            pipelines::delete_pipeline,
+            template_vars::expand_template,
+            templates::save_template,
+            templates::list_templates,
+            templates::delete_template,
+            templates::render_template,
+            export::export_conversation,
+            export::export_conversation_rich,
+            export::get_export_capabilities,
+            importers::import_external_conversations,
+            screenshot::capture_screenshot,
+            summarize::summarize_file,
+            batch::run_batch,
+            listing::refresh_directory,
+            tree::read_directory_tree,
+            index::get_index_status,
+            index::rebuild_index,
+            index::find_files,
+            session::close_session,
+            stream_file::stream_to_file,
+            startup::get_startup_timings,
+            cli_status::claude_cli_status,
+            cli_status::rediscover_claude_cli,
+            cli_status::get_auth_status,
+            cli_status::start_claude_login,
+            cli_install::install_claude_cli,
+            cli_install::update_claude_cli,
+            secrets::set_secret,
+            secrets::get_secret,
+            secrets::delete_secret,
+            cli_status::get_engine_config,
+            cli_status::set_engine_config,
+            estimate::estimate_request,
+            estimate::estimate_tokens,
+            supervisor::start_supervised,
+            supervisor::stop_supervised,
+            supervisor::list_supervised,
+            highlight::read_file_highlighted,
+            search::search_in_files,
+            watcher::watch_path,
+            watcher::unwatch_path,
+            replace::preview_replace,
+            replace::apply_replace,
+            history::save_conversation,
+            history::load_conversation,
+            history::list_conversations,
+            history::delete_conversation,
+            history::fork_conversation,
+            history::set_active_revision,
+            history_search::search_conversations,
+            titles::generate_conversation_title,
+            cli_slots::get_active_requests,
+            stream_input::send_stream_input,
+            stream_input::end_stream_input,
+            project_context::set_project_context,
+            project_context::get_project_context,
+            bootstrap::get_bootstrap_report,
+            usage_ledger::get_usage_summary,
+            usage_ledger::clear_usage_history,
+            regenerate::regenerate_message,
+            metrics::get_performance_profile,
+            metrics::emit_performance_snapshot,
+            models::list_models
+        ])
+        .build(context)
+        .expect("error while running tauri application")
+        .run(|app, event| match event {
+            #[cfg(feature = "updater")]
+            RunEvent::Updater(UpdaterEvent::DownloadProgress {
+                chunk_length,
+                content_length,
+            }) => app_update::on_download_progress(app, chunk_length, content_length),
+            RunEvent::WindowEvent {
+                label,
+                event: tauri::WindowEvent::Destroyed,
+                ..
+            } => {
+                app.state::<watcher::Watchers>().close_window(&label);
+                app.state::<approval::Approvals>().close_window(&label);
+                // Its streams have nobody left to stream to
+                app.state::<CancelRegistry>().cancel_window(&label);
+            }
+            // Before the runtime goes away, so no CLI, build or test run outlives the app
+            RunEvent::ExitRequested { .. } => proctree::kill_all(),
+            RunEvent::Exit => {
+                // Again, for an exit that skipped the request
+                proctree::kill_all();
+                tauri::async_runtime::block_on(app.state::<LocalApiServer>().stop());
+                app.state::<session::SessionPool>().shutdown();
+                tauri::async_runtime::block_on(app.state::<supervisor::Supervisor>().shutdown());
+            }
+            _ => {}
+        });
+}
@@ -0,0 +1,555 @@
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::clock::{millis, now_millis};
+use crate::error::EngineError;
+use crate::paths::is_network_path;
+use crate::sandbox::Workspace;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{Manager, State, Window};
+
+/// How long a listing is trusted without anything telling us it changed
+const CACHE_TTL_MS: u64 = 2_000;
+const CACHE_MAX_DIRS: usize = 256;
+const METADATA_BATCH: usize = 64;
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ListOptions {
+    /// Skip size and mtime; defaults to on for network shares, where each
+    /// metadata call is a round trip
+    pub fast: Option<bool>,
+    /// Return names and kinds right away and stat the entries in the
+    /// background, delivered as `directory-metadata` events
+    pub background_metadata: bool,
+    /// `[start, end)` of the entries on screen, statted first
+    pub visible_range: Option<(usize, usize)>,
+    /// Cancels the background phase, e.g. when the folder is collapsed
+    pub request_id: Option<String>,
+    /// Dotfiles, and on Windows files marked hidden; unset lists them all
+    pub include_hidden: Option<bool>,
+    /// Unset keeps the order the directory was read in
+    pub sort: Option<SortOrder>,
+    pub descending: bool,
+}
+
+/// Ties, and entries whose metadata isn't known yet, fall back to name order
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Name,
+    DirsFirst,
+    Modified,
+    Size,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DirEntryInfo {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Where a symlink points, as stored in the link
+    pub symlink_target: Option<String>,
+    /// A symlink whose target is missing; listed rather than failing the read
+    pub broken: bool,
+    pub is_hidden: bool,
+    pub size: Option<u64>,
+    /// Millis since the Unix epoch
+    pub modified: Option<u64>,
+}
+
+#[cfg(windows)]
+fn hidden_attribute(entry: &std::fs::DirEntry) -> bool {
+    use std::os::windows::fs::MetadataExt;
+    const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+    entry
+        .metadata()
+        .is_ok_and(|m| m.file_attributes() & FILE_ATTRIBUTE_HIDDEN != 0)
+}
+
+#[cfg(not(windows))]
+fn hidden_attribute(_entry: &std::fs::DirEntry) -> bool {
+    false
+}
+
+pub fn list(dir: &Path, fast: bool) -> Result<Vec<DirEntryInfo>, EngineError> {
+    let entries = std::fs::read_dir(dir).map_err(|e| EngineError::io(dir, e))?;
+    let mut out = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| EngineError::io(dir, e))?;
+        // The file type comes with the directory read itself, no extra stat
+        let file_type = entry.file_type().ok();
+        let is_symlink = file_type.is_some_and(|t| t.is_symlink());
+        // Windows fills DirEntry metadata from the same directory read, so this
+        // stays cheap there; elsewhere it's a stat per entry
+        let metadata = if fast { None } else { entry.metadata().ok() };
+        let target = is_symlink
+            .then(|| std::fs::read_link(entry.path()).ok())
+            .flatten();
+        let target_meta = is_symlink
+            .then(|| std::fs::metadata(entry.path()).ok())
+            .flatten();
+        let is_dir = if is_symlink {
+            target_meta.as_ref().is_some_and(|m| m.is_dir())
+        } else {
+            file_type.is_some_and(|t| t.is_dir())
+        };
+        let name = entry.file_name().to_string_lossy().into_owned();
+        out.push(DirEntryInfo {
+            is_hidden: name.starts_with('.') || hidden_attribute(&entry),
+            name,
+            path: entry.path().display().to_string(),
+            is_dir,
+            is_symlink,
+            symlink_target: target.map(|t| t.display().to_string()),
+            broken: is_symlink && target_meta.is_none(),
+            size: metadata.as_ref().filter(|m| m.is_file()).map(|m| m.len()),
+            modified: metadata
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .and_then(millis),
+        });
+    }
+    Ok(out)
+}
+
+struct CachedListing {
+    entries: Vec<DirEntryInfo>,
+    cached_at: u64,
+    last_used: u64,
+}
+
+#[derive(Default)]
+struct CacheState {
+    listings: HashMap<(PathBuf, bool), CachedListing>,
+    /// Bumped by every invalidation, so a listing read while the directory
+    /// was changing is never stored over the fresher state
+    generation: u64,
+    clock: u64,
+}
+
+/// Recent directory listings keyed by canonical path and fast mode, evicting
+/// the least recently used once full
+#[derive(Default)]
+pub struct ListingCache {
+    state: Mutex<CacheState>,
+}
+
+impl ListingCache {
+    fn get(&self, key: &(PathBuf, bool)) -> Option<(Vec<DirEntryInfo>, u64)> {
+        let mut state = self.state.lock().ok()?;
+        state.clock += 1;
+        let clock = state.clock;
+        let cached = state.listings.get_mut(key)?;
+        if now_millis().saturating_sub(cached.cached_at) > CACHE_TTL_MS {
+            state.listings.remove(key);
+            return None;
+        }
+        cached.last_used = clock;
+        Some((cached.entries.clone(), cached.cached_at))
+    }
+
+    fn generation(&self) -> u64 {
+        self.state.lock().map(|s| s.generation).unwrap_or(0)
+    }
+
+    fn insert(&self, key: (PathBuf, bool), entries: Vec<DirEntryInfo>, generation: u64) -> u64 {
+        let cached_at = now_millis();
+        let Ok(mut state) = self.state.lock() else {
+            return cached_at;
+        };
+        if state.generation != generation {
+            return cached_at;
+        }
+        if state.listings.len() >= CACHE_MAX_DIRS && !state.listings.contains_key(&key) {
+            let oldest = state
+                .listings
+                .iter()
+                .min_by_key(|(_, c)| c.last_used)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                state.listings.remove(&oldest);
+            }
+        }
+        state.clock += 1;
+        let last_used = state.clock;
+        state.listings.insert(
+            key,
+            CachedListing {
+                entries,
+                cached_at,
+                last_used,
+            },
+        );
+        cached_at
+    }
+
+    /// Forget everything a change at `path` can affect: its own listing, the
+    /// listings below it, and its parent's, which gains or loses the entry
+    pub fn invalidate(&self, path: &Path) {
+        let Ok(mut state) = self.state.lock() else {
+            return;
+        };
+        state.generation += 1;
+        let parent = path.parent();
+        state
+            .listings
+            .retain(|(dir, _), _| !dir.starts_with(path) && Some(dir.as_path()) != parent);
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Listing {
+    pub entries: Vec<DirEntryInfo>,
+    pub from_cache: bool,
+    /// Millis since the Unix epoch when the entries were read
+    pub cached_at: u64,
+    /// Sizes and mtimes are still being read and will arrive as events
+    pub metadata_pending: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct EntryMetadata {
+    name: String,
+    size: Option<u64>,
+    modified: Option<u64>,
+}
+
+fn compare(a: &DirEntryInfo, b: &DirEntryInfo, sort: SortOrder) -> Ordering {
+    let by_name = || a.name.to_lowercase().cmp(&b.name.to_lowercase());
+    match sort {
+        SortOrder::Name => by_name(),
+        SortOrder::DirsFirst => b.is_dir.cmp(&a.is_dir).then_with(by_name),
+        SortOrder::Modified => a.modified.cmp(&b.modified).then_with(by_name),
+        SortOrder::Size => a.size.cmp(&b.size).then_with(by_name),
+    }
+}
+
+/// Indices of the entries to return, in the order to return them
+fn shown(entries: &[DirEntryInfo], options: &ListOptions) -> Vec<usize> {
+    let mut shown: Vec<usize> = (0..entries.len())
+        .filter(|&i| options.include_hidden != Some(false) || !entries[i].is_hidden)
+        .collect();
+    if let Some(sort) = options.sort {
+        shown.sort_by(|&a, &b| {
+            let order = compare(&entries[a], &entries[b], sort);
+            if options.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+    }
+    shown
+}
+
+fn pick(entries: &[DirEntryInfo], shown: &[usize]) -> Vec<DirEntryInfo> {
+    shown.iter().map(|&i| entries[i].clone()).collect()
+}
+
+/// Visible entries first, then the rest of what was returned, then whatever
+/// was filtered out, so the cached listing is still complete
+fn metadata_order(len: usize, shown: &[usize], visible: Option<(usize, usize)>) -> Vec<usize> {
+    let count = shown.len();
+    let (start, end) = visible.map_or((0, 0), |(start, end)| (start.min(count), end.min(count)));
+    let end = end.max(start);
+    let mut order: Vec<usize> = shown[start..end]
+        .iter()
+        .chain(&shown[..start])
+        .chain(&shown[end..])
+        .copied()
+        .collect();
+    let mut hidden = vec![true; len];
+    for &i in shown {
+        hidden[i] = false;
+    }
+    order.extend((0..len).filter(|&i| hidden[i]));
+    order
+}
+
+/// Stat entries in batches in `order`, emitting each batch, then cache the merged listing
+fn prefetch_metadata(
+    window: &Window,
+    dir: &Path,
+    mut entries: Vec<DirEntryInfo>,
+    order: &[usize],
+    request_id: Option<&str>,
+    cancel: &CancelToken,
+    generation: u64,
+) {
+    let batches = order.chunks(METADATA_BATCH).count();
+    for (index, batch) in order.chunks(METADATA_BATCH).enumerate() {
+        if cancel.is_cancelled() {
+            return;
+        }
+        let mut delivered = Vec::with_capacity(batch.len());
+        for &i in batch {
+            let entry = &mut entries[i];
+            let metadata = std::fs::symlink_metadata(&entry.path).ok();
+            entry.size = metadata.as_ref().filter(|m| m.is_file()).map(|m| m.len());
+            entry.modified = metadata
+                .as_ref()
+                .and_then(|m| m.modified().ok())
+                .and_then(millis);
+            delivered.push(EntryMetadata {
+                name: entry.name.clone(),
+                size: entry.size,
+                modified: entry.modified,
+            });
+        }
+        let _ = window.emit(
+            "directory-metadata",
+            json!({
+                "path": dir.display().to_string(),
+                "request_id": request_id,
+                "entries": delivered,
+                "done": index + 1 == batches,
+            }),
+        );
+    }
+    window.app_handle().state::<ListingCache>().insert(
+        (dir.to_path_buf(), false),
+        entries,
+        generation,
+    );
+}
+
+#[tauri::command]
+pub async fn list_directory_detailed(
+    window: Window,
+    path: String,
+    options: Option<ListOptions>,
+    workspace: State<'_, Workspace>,
+    cache: State<'_, ListingCache>,
+) -> Result<Listing, EngineError> {
+    let options = options.unwrap_or_default();
+    let dir = workspace.check(Path::new(&path))?;
+    // Backgrounding only matters when metadata is wanted at all
+    let background = options.background_metadata && options.fast != Some(true);
+    let fast = background || options.fast.unwrap_or_else(|| is_network_path(&dir));
+    let complete = (dir.clone(), false);
+    let key = (dir.clone(), fast);
+    let cached = if background {
+        cache.get(&complete)
+    } else {
+        None
+    };
+    if let Some((entries, cached_at)) = cached.or_else(|| cache.get(&key)) {
+        return Ok(Listing {
+            entries: pick(&entries, &shown(&entries, &options)),
+            from_cache: true,
+            cached_at,
+            metadata_pending: false,
+        });
+    }
+    let generation = cache.generation();
+    let entries = {
+        let dir = dir.clone();
+        tokio::task::spawn_blocking(move || list(&dir, fast))
+            .await
+            .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??
+    };
+    let cached_at = cache.insert(key, entries.clone(), generation);
+    let order = shown(&entries, &options);
+    let listed = pick(&entries, &order);
+
+    if background {
+        let guard = options
+            .request_id
+            .as_deref()
+            .map(|id| window.state::<CancelRegistry>().register(id));
+        let order = metadata_order(entries.len(), &order, options.visible_range);
+        tokio::task::spawn_blocking(move || {
+            let cancel = guard.as_ref().map(|g| g.token()).unwrap_or_default();
+            prefetch_metadata(
+                &window,
+                &dir,
+                entries,
+                &order,
+                options.request_id.as_deref(),
+                &cancel,
+                generation,
+            );
+        });
+    }
+    Ok(Listing {
+        entries: listed,
+        from_cache: false,
+        cached_at,
+        metadata_pending: background,
+    })
+}
+
+/// Drop cached listings for `path` so the next listing reads the disk
+#[tauri::command]
+pub async fn refresh_directory(
+    path: String,
+    workspace: State<'_, Workspace>,
+    cache: State<'_, ListingCache>,
+) -> Result<(), EngineError> {
+    let dir = workspace.check(Path::new(&path))?;
+    cache.invalidate(&dir);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+    use std::sync::Arc;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bups-listing-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn key(i: usize) -> (PathBuf, bool) {
+        (PathBuf::from(format!("/dirs/{}", i)), false)
+    }
+
+    /// What `list_directory_detailed` does: the cached listing, else a fresh read
+    fn cached_names(cache: &ListingCache, dir: &Path) -> Vec<String> {
+        let key = (dir.to_path_buf(), false);
+        let entries = match cache.get(&key) {
+            Some((entries, _)) => entries,
+            None => {
+                let generation = cache.generation();
+                let entries = list(dir, false).unwrap();
+                cache.insert(key, entries.clone(), generation);
+                entries
+            }
+        };
+        let mut names: Vec<String> = entries.into_iter().map(|e| e.name).collect();
+        names.sort();
+        names
+    }
+
+    fn disk_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn the_cache_holds_at_most_max_dirs_evicting_the_least_recent() {
+        let cache = ListingCache::default();
+        for i in 0..CACHE_MAX_DIRS {
+            cache.insert(key(i), Vec::new(), 0);
+        }
+        // Touch the oldest so the second oldest goes first
+        assert!(cache.get(&key(0)).is_some());
+        for i in CACHE_MAX_DIRS..CACHE_MAX_DIRS + 10 {
+            cache.insert(key(i), Vec::new(), 0);
+            assert_eq!(cache.state.lock().unwrap().listings.len(), CACHE_MAX_DIRS);
+        }
+        assert!(cache.get(&key(0)).is_some());
+        for i in 1..=10 {
+            assert!(cache.get(&key(i)).is_none(), "{} survived", i);
+        }
+        assert!(cache.get(&key(11)).is_some());
+        assert!(cache.get(&key(CACHE_MAX_DIRS + 9)).is_some());
+    }
+
+    #[test]
+    fn replacing_a_listing_in_a_full_cache_evicts_nothing() {
+        let cache = ListingCache::default();
+        for i in 0..CACHE_MAX_DIRS {
+            cache.insert(key(i), Vec::new(), 0);
+        }
+        cache.insert(key(5), Vec::new(), 0);
+        assert_eq!(cache.state.lock().unwrap().listings.len(), CACHE_MAX_DIRS);
+        assert!((0..CACHE_MAX_DIRS).all(|i| cache.get(&key(i)).is_some()));
+    }
+
+    #[test]
+    fn invalidation_drops_the_path_its_parent_and_below_only() {
+        let cache = ListingCache::default();
+        for dir in ["/w", "/w/src", "/w/src/deep", "/w/docs", "/other"] {
+            cache.insert((PathBuf::from(dir), false), Vec::new(), 0);
+            cache.insert((PathBuf::from(dir), true), Vec::new(), 0);
+        }
+        cache.invalidate(Path::new("/w/src"));
+        for (dir, kept) in [
+            ("/w", false),
+            ("/w/src", false),
+            ("/w/src/deep", false),
+            ("/w/docs", true),
+            ("/other", true),
+        ] {
+            for fast in [false, true] {
+                let cached = cache.get(&(PathBuf::from(dir), fast)).is_some();
+                assert_eq!(cached, kept, "{} fast={}", dir, fast);
+            }
+        }
+    }
+
+    #[test]
+    fn a_listing_read_across_a_change_is_not_cached() {
+        let dir = scratch("stale");
+        let cache = ListingCache::default();
+        let generation = cache.generation();
+        let stale = list(&dir, false).unwrap();
+        std::fs::write(dir.join("new.txt"), "x").unwrap();
+        cache.invalidate(&dir.join("new.txt"));
+        cache.insert((dir.clone(), false), stale, generation);
+        assert_eq!(cached_names(&cache, &dir), ["new.txt"]);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn listings_follow_rapid_creates_and_deletes() {
+        let dir = scratch("churn");
+        let cache = ListingCache::default();
+        for i in 0..200 {
+            let path = dir.join(format!("f{}", i));
+            std::fs::write(&path, "x").unwrap();
+            cache.invalidate(&path);
+            assert_eq!(cached_names(&cache, &dir), disk_names(&dir));
+            if i % 3 != 0 {
+                std::fs::remove_file(&path).unwrap();
+                cache.invalidate(&path);
+            }
+            assert_eq!(cached_names(&cache, &dir), disk_names(&dir));
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn concurrent_churn_never_leaves_a_stale_listing() {
+        let dir = scratch("race");
+        let cache = Arc::new(ListingCache::default());
+        let done = Arc::new(AtomicBool::new(false));
+        let writer = {
+            let (dir, cache, done) = (dir.clone(), Arc::clone(&cache), Arc::clone(&done));
+            std::thread::spawn(move || {
+                for i in 0..500 {
+                    let path = dir.join(format!("f{}", i));
+                    std::fs::write(&path, "x").unwrap();
+                    cache.invalidate(&path);
+                    if i % 2 == 0 {
+                        std::fs::remove_file(&path).unwrap();
+                        cache.invalidate(&path);
+                    }
+                }
+                done.store(true, AtomicOrdering::SeqCst);
+            })
+        };
+        while !done.load(AtomicOrdering::SeqCst) {
+            cached_names(&cache, &dir);
+        }
+        writer.join().unwrap();
+        assert_eq!(cached_names(&cache, &dir), disk_names(&dir));
+        assert_eq!(disk_names(&dir).len(), 250);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
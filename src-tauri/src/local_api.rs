@@ -0,0 +1,382 @@
+use crate::claude::{NullSink, StreamOptions, StreamSink};
+use crate::dispatch::{self, Origin};
+use crate::error::EngineError;
+use crate::hash::random_hex;
+use crate::history;
+use crate::settings;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::response::{IntoResponse, Response};
+use axum::routing::post;
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+use tokio::sync::{mpsc, oneshot, Mutex};
+use tokio_stream::wrappers::UnboundedReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(2);
+
+#[derive(Deserialize)]
+struct MessageRequest {
+    message: String,
+    /// Continue an existing history entry instead of starting a new one
+    #[serde(default)]
+    conversation_id: Option<String>,
+}
+
+#[derive(Serialize)]
+struct MessageResponse {
+    request_id: String,
+    conversation_id: String,
+    response: String,
+}
+
+#[derive(Clone)]
+struct ApiState {
+    app: AppHandle,
+    token: Arc<str>,
+}
+
+struct ApiError(StatusCode, EngineError);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.0, Json(self.1)).into_response()
+    }
+}
+
+/// Forwards stream events as SSE, named without the `claude-stream-` prefix
+struct SseSink {
+    tx: mpsc::UnboundedSender<Event>,
+    finished: AtomicBool,
+}
+
+impl StreamSink for SseSink {
+    fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String> {
+        let name = event.strip_prefix("claude-stream-").unwrap_or(event);
+        if matches!(name, "complete" | "error" | "cancelled") {
+            self.finished.store(true, Ordering::SeqCst);
+        }
+        self.tx
+            .send(Event::default().event(name).data(payload.to_string()))
+            .map_err(|_| "HTTP client disconnected".to_string())
+    }
+}
+
+fn token_matches(given: &str, expected: &str) -> bool {
+    // Constant-time so the token can't be guessed byte by byte
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn authorize(state: &ApiState, headers: &HeaderMap) -> Result<(), ApiError> {
+    // Browsers always send Origin; this API is for local programs only
+    if headers.contains_key(header::ORIGIN) {
+        return Err(ApiError(
+            StatusCode::FORBIDDEN,
+            EngineError::invalid("Browser requests are not accepted"),
+        ));
+    }
+    let given = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .unwrap_or("");
+    if token_matches(given.trim(), &state.token) {
+        Ok(())
+    } else {
+        Err(ApiError(
+            StatusCode::UNAUTHORIZED,
+            EngineError::invalid("Missing or invalid bearer token"),
+        ))
+    }
+}
+
+/// Run a message the way `stream_to_claude` does, queued behind the
+/// conversation's other turns, registered for cancellation under
+/// `request_id` and recorded in history. There's no dialog to confirm a
+/// costly prompt with, so the caller sending it counts as confirming.
+async fn run(
+    app: &AppHandle,
+    request_id: &str,
+    conversation_id: &str,
+    message: String,
+    sink: Arc<dyn StreamSink>,
+) -> Result<String, EngineError> {
+    let options = StreamOptions {
+        request_id: Some(request_id.to_string()),
+        ..StreamOptions::default()
+    };
+    let reply = dispatch::stream(
+        app,
+        Origin::Http(sink),
+        message.clone(),
+        None,
+        Some(conversation_id.to_string()),
+        Some(true),
+        options,
+    )
+    .await?;
+    if reply.cancelled {
+        return Err(EngineError::Cancelled);
+    }
+    let response = reply.reply.text;
+    // History is best-effort; the response itself already succeeded
+    let _ = history::record_exchange(app, conversation_id, "http", &message, &response);
+    Ok(response)
+}
+
+fn status_for(error: &EngineError) -> StatusCode {
+    match error {
+        EngineError::Cancelled => StatusCode::CONFLICT,
+        EngineError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+        EngineError::InvalidInput { .. } => StatusCode::BAD_REQUEST,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+fn ids(request: &MessageRequest) -> (String, String) {
+    let request_id = format!("http-{}", random_hex(8));
+    let conversation_id = request
+        .conversation_id
+        .clone()
+        .unwrap_or_else(history::new_conversation_id);
+    (request_id, conversation_id)
+}
+
+async fn send_message(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(request): Json<MessageRequest>,
+) -> Result<Json<MessageResponse>, ApiError> {
+    authorize(&state, &headers)?;
+    let (request_id, conversation_id) = ids(&request);
+    let response = run(
+        &state.app,
+        &request_id,
+        &conversation_id,
+        request.message,
+        Arc::new(NullSink),
+    )
+    .await
+    .map_err(|e| ApiError(status_for(&e), e))?;
+    Ok(Json(MessageResponse {
+        request_id,
+        conversation_id,
+        response,
+    }))
+}
+
+async fn stream_message(
+    State(state): State<ApiState>,
+    headers: HeaderMap,
+    Json(request): Json<MessageRequest>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, ApiError> {
+    authorize(&state, &headers)?;
+    let (request_id, conversation_id) = ids(&request);
+    let (tx, rx) = mpsc::unbounded_channel();
+    let start = serde_json::json!({
+        "request_id": request_id,
+        "conversation_id": conversation_id,
+    });
+    let _ = tx.send(Event::default().event("start").data(start.to_string()));
+
+    let sink = Arc::new(SseSink {
+        tx,
+        finished: AtomicBool::new(false),
+    });
+    tokio::spawn(async move {
+        let result = run(
+            &state.app,
+            &request_id,
+            &conversation_id,
+            request.message,
+            sink.clone(),
+        )
+        .await;
+        // Failures before the CLI started (not installed, spawn error) emit nothing themselves
+        if let Err(e) = result {
+            if !sink.finished.load(Ordering::SeqCst) {
+                let _ = sink.emit("error", serde_json::json!(e));
+            }
+        }
+    });
+
+    Ok(Sse::new(UnboundedReceiverStream::new(rx).map(Ok)).keep_alive(KeepAlive::default()))
+}
+
+struct RunningServer {
+    port: u16,
+    shutdown: oneshot::Sender<()>,
+    task: tauri::async_runtime::JoinHandle<()>,
+}
+
+/// Managed handle to the optional localhost API server
+#[derive(Default)]
+pub struct LocalApiServer {
+    running: Mutex<Option<RunningServer>>,
+}
+
+impl LocalApiServer {
+    async fn start(&self, app: AppHandle, port: u16, token: String) -> Result<(), EngineError> {
+        self.stop().await;
+        let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+        let listener =
+            tokio::net::TcpListener::bind(addr)
+                .await
+                .map_err(|e| EngineError::Network {
+                    detail: format!("Failed to bind {}: {}", addr, e),
+                })?;
+        let router = Router::new()
+            .route("/v1/messages", post(send_message))
+            .route("/v1/messages/stream", post(stream_message))
+            .with_state(ApiState {
+                app,
+                token: token.into(),
+            });
+
+        let (shutdown, signal) = oneshot::channel::<()>();
+        let task = tauri::async_runtime::spawn(async move {
+            let _ = axum::serve(listener, router)
+                .with_graceful_shutdown(async {
+                    let _ = signal.await;
+                })
+                .await;
+        });
+        *self.running.lock().await = Some(RunningServer {
+            port,
+            shutdown,
+            task,
+        });
+        Ok(())
+    }
+
+    /// Stop accepting connections and give open streams a moment to finish
+    pub async fn stop(&self) {
+        let Some(server) = self.running.lock().await.take() else {
+            return;
+        };
+        let _ = server.shutdown.send(());
+        let abort = server.task.inner().abort_handle();
+        if tokio::time::timeout(SHUTDOWN_GRACE, server.task)
+            .await
+            .is_err()
+        {
+            abort.abort();
+        }
+    }
+
+    async fn port(&self) -> Option<u16> {
+        self.running.lock().await.as_ref().map(|s| s.port)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LocalApiStatus {
+    pub enabled: bool,
+    pub running: bool,
+    pub port: u16,
+    pub url: Option<String>,
+}
+
+async fn status(app: &AppHandle, server: &LocalApiServer) -> LocalApiStatus {
+    let config = settings::load(app).local_api;
+    let running = server.port().await;
+    LocalApiStatus {
+        enabled: config.enabled,
+        running: running.is_some(),
+        port: running.unwrap_or(config.port),
+        url: running.map(|port| format!("http://127.0.0.1:{}", port)),
+    }
+}
+
+/// Start the server at launch if it was left enabled
+pub async fn start_if_enabled(app: AppHandle) -> Result<(), EngineError> {
+    let config = settings::load(&app).local_api;
+    match (config.enabled, config.token) {
+        (true, Some(token)) => {
+            let server = app.state::<LocalApiServer>();
+            server.start(app.clone(), config.port, token).await
+        }
+        _ => Ok(()),
+    }
+}
+
+#[tauri::command]
+pub async fn get_local_api_status(
+    app: AppHandle,
+    server: tauri::State<'_, LocalApiServer>,
+) -> Result<LocalApiStatus, EngineError> {
+    Ok(status(&app, &server).await)
+}
+
+#[tauri::command]
+pub async fn set_local_api_enabled(
+    app: AppHandle,
+    enabled: bool,
+    port: Option<u16>,
+    server: tauri::State<'_, LocalApiServer>,
+) -> Result<LocalApiStatus, EngineError> {
+    let mut config = settings::load(&app);
+    let api = &mut config.local_api;
+    api.enabled = enabled;
+    if let Some(port) = port {
+        api.port = port;
+    }
+    let token = enabled.then(|| api.token.get_or_insert_with(|| random_hex(32)).clone());
+    let port = api.port;
+    settings::save(&app, &config)?;
+
+    match token {
+        Some(token) => server.start(app.clone(), port, token).await?,
+        None => server.stop().await,
+    }
+    Ok(status(&app, &server).await)
+}
+
+#[tauri::command]
+pub async fn get_local_api_token(app: AppHandle) -> Result<Option<String>, EngineError> {
+    Ok(settings::load(&app).local_api.token)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_keep_their_kind_in_the_status() {
+        let cases = [
+            (EngineError::Cancelled, StatusCode::CONFLICT),
+            (
+                EngineError::TooManyRequests {
+                    running: 3,
+                    limit: 3,
+                },
+                StatusCode::TOO_MANY_REQUESTS,
+            ),
+            (EngineError::invalid("bad"), StatusCode::BAD_REQUEST),
+            (EngineError::CliNotFound, StatusCode::BAD_GATEWAY),
+            (
+                EngineError::CliExited {
+                    code: Some(1),
+                    stderr: "boom".to_string(),
+                },
+                StatusCode::BAD_GATEWAY,
+            ),
+        ];
+        for (error, status) in cases {
+            assert_eq!(status_for(&error), status, "{:?}", error);
+        }
+    }
+}
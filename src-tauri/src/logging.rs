@@ -0,0 +1,223 @@
+use crate::error::EngineError;
+use crate::settings;
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_json::Value;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+use tracing::Level;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{fmt, reload, Registry};
+
+const LOG_FILE: &str = "engine.log";
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+// engine.log.1 is the newest of these, engine.log.4 the oldest
+const KEEP_ROTATED: usize = 4;
+const DEFAULT_LEVEL: LevelFilter = LevelFilter::INFO;
+const DEFAULT_RECENT_LINES: usize = 500;
+const MAX_RECENT_LINES: usize = 5_000;
+
+fn rotated(dir: &Path, n: usize) -> PathBuf {
+    dir.join(format!("{}.{}", LOG_FILE, n))
+}
+
+/// `engine.log`, rolled over to `engine.log.1` at the first write of each UTC
+/// day and whenever it would pass [`MAX_LOG_BYTES`]
+struct RotatingFile {
+    dir: PathBuf,
+    file: Option<File>,
+    size: u64,
+    day: NaiveDate,
+}
+
+impl RotatingFile {
+    fn open(dir: PathBuf) -> io::Result<Self> {
+        let mut log = Self {
+            dir,
+            file: None,
+            size: 0,
+            day: Utc::now().date_naive(),
+        };
+        log.reopen()?;
+        // A log left from an earlier day rolls over with the first line today
+        if let Some(modified) = log
+            .file
+            .as_ref()
+            .and_then(|f| f.metadata().ok()?.modified().ok())
+        {
+            log.day = DateTime::<Utc>::from(modified).date_naive();
+        }
+        Ok(log)
+    }
+
+    fn reopen(&mut self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(LOG_FILE))?;
+        self.size = file.metadata().map(|m| m.len()).unwrap_or(0);
+        self.file = Some(file);
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        for n in (1..KEEP_ROTATED).rev() {
+            let _ = fs::rename(rotated(&self.dir, n), rotated(&self.dir, n + 1));
+        }
+        let _ = fs::rename(self.dir.join(LOG_FILE), rotated(&self.dir, 1));
+        self.reopen()
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let today = Utc::now().date_naive();
+        if self.size > 0 && (today != self.day || self.size + buf.len() as u64 > MAX_LOG_BYTES) {
+            self.rotate()?;
+        }
+        self.day = today;
+        let Some(file) = self.file.as_mut() else {
+            return Err(io::Error::other("Log file is closed"));
+        };
+        let written = file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Managed handle on the running log; empty when there's no log directory
+/// or another subscriber got there first
+#[derive(Default)]
+pub struct Logging {
+    running: Option<(reload::Handle<LevelFilter, Registry>, PathBuf)>,
+}
+
+fn parse_level(level: &str) -> Result<LevelFilter, EngineError> {
+    level
+        .trim()
+        .parse()
+        .map_err(|_| EngineError::invalid(format!("Unknown log level '{}'", level)))
+}
+
+fn start(dir: PathBuf, level: LevelFilter) -> Option<Logging> {
+    fs::create_dir_all(&dir).ok()?;
+    let file = RotatingFile::open(dir.clone()).ok()?;
+    let (filter, handle) = reload::Layer::new(level);
+    let lines = fmt::layer()
+        .json()
+        .flatten_event(true)
+        .with_current_span(false)
+        .with_span_list(false)
+        .with_writer(Mutex::new(file));
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(lines)
+        .try_init()
+        .ok()?;
+    Some(Logging {
+        running: Some((handle, dir)),
+    })
+}
+
+/// Write JSON lines to `engine.log` in the app log directory, at the level
+/// saved in `debug.log_level`. Info, the default, carries spawns, exits and
+/// failures but never message text or secrets.
+pub fn init(app: &AppHandle) {
+    let level = settings::load(app)
+        .debug
+        .log_level
+        .and_then(|level| parse_level(&level).ok())
+        .unwrap_or(DEFAULT_LEVEL);
+    let logging = app
+        .path_resolver()
+        .app_log_dir()
+        .and_then(|dir| start(dir, level))
+        .unwrap_or_default();
+    app.manage(logging);
+}
+
+fn running(
+    logging: &Logging,
+) -> Result<&(reload::Handle<LevelFilter, Registry>, PathBuf), EngineError> {
+    logging
+        .running
+        .as_ref()
+        .ok_or_else(|| EngineError::internal("Logging isn't running"))
+}
+
+/// The last `max_lines` log lines at `min_level` or more severe, oldest
+/// first, reaching into `engine.log.1` when the current file is short
+#[tauri::command]
+pub async fn get_recent_logs(
+    max_lines: Option<usize>,
+    min_level: Option<String>,
+    logging: State<'_, Logging>,
+) -> Result<Vec<Value>, EngineError> {
+    let (_, dir) = running(&logging)?;
+    let wanted = max_lines
+        .unwrap_or(DEFAULT_RECENT_LINES)
+        .min(MAX_RECENT_LINES);
+    let min_level = match min_level {
+        Some(level) => parse_level(&level)?,
+        None => LevelFilter::TRACE,
+    };
+    let dir = dir.clone();
+    tokio::task::spawn_blocking(move || {
+        let mut lines = Vec::new();
+        for path in [dir.join(LOG_FILE), rotated(&dir, 1)] {
+            let Ok(text) = fs::read_to_string(&path) else {
+                continue;
+            };
+            for line in text.lines().rev() {
+                let Ok(entry) = serde_json::from_str::<Value>(line) else {
+                    continue;
+                };
+                let level = entry["level"]
+                    .as_str()
+                    .and_then(|l| l.parse::<Level>().ok());
+                if level.is_some_and(|level| level <= min_level) {
+                    lines.push(entry);
+                    if lines.len() == wanted {
+                        break;
+                    }
+                }
+            }
+            if lines.len() == wanted {
+                break;
+            }
+        }
+        lines.reverse();
+        lines
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
+
+/// Change the log level now and for later runs: `error`, `warn`, `info`,
+/// `debug`, `trace` or `off`
+#[tauri::command]
+pub async fn set_log_level(
+    app: AppHandle,
+    level: String,
+    logging: State<'_, Logging>,
+) -> Result<(), EngineError> {
+    let filter = parse_level(&level)?;
+    let (handle, _) = running(&logging)?;
+    handle
+        .reload(filter)
+        .map_err(|e| EngineError::internal(format!("Failed to change log level: {}", e)))?;
+    let mut current = settings::load(&app);
+    current.debug.log_level = Some(filter.to_string());
+    settings::save(&app, &current)
+}
@@ -0,0 +1,75 @@
+use pulldown_cmark::{html, CowStr, Event, Options, Parser, Tag};
+
+/// Markdown to an HTML fragment. Raw HTML in the source is escaped, not passed
+/// through, since the text usually comes from the model.
+pub fn render_markdown(text: &str) -> String {
+    render(text, |_| None)
+}
+
+/// Like [`render_markdown`], for documents that must not load anything: image
+/// sources become whatever `embed` returns (e.g. a data URI), or are dropped.
+pub fn render_markdown_offline(text: &str, embed: impl Fn(&str) -> Option<String>) -> String {
+    render(text, |url| Some(embed(url).unwrap_or_default()))
+}
+
+fn render(text: &str, image_src: impl Fn(&str) -> Option<String>) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let parser = Parser::new_ext(text, options).map(|event| match event {
+        Event::Html(raw) | Event::InlineHtml(raw) => Event::Text(raw),
+        Event::Start(Tag::Image {
+            link_type,
+            dest_url,
+            title,
+            id,
+        }) => {
+            let dest_url = image_src(&dest_url).map(CowStr::from).unwrap_or(dest_url);
+            Event::Start(Tag::Image {
+                link_type,
+                dest_url,
+                title,
+                id,
+            })
+        }
+        other => other,
+    });
+    let mut out = String::with_capacity(text.len() * 3 / 2);
+    html::push_html(&mut out, parser);
+    out
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+pub const DOCUMENT_STYLE: &str = "body{max-width:860px;margin:2rem auto;padding:0 1rem;\
+font:16px/1.6 system-ui,sans-serif;color:#1f2328}\
+pre{background:#f6f8fa;padding:1rem;overflow:auto;border-radius:6px}\
+code{font-family:ui-monospace,Consolas,monospace;font-size:.9em}\
+table{border-collapse:collapse}td,th{border:1px solid #d0d7de;padding:.3rem .6rem}\
+blockquote{margin:0;padding-left:1rem;border-left:4px solid #d0d7de;color:#59636e}";
+
+/// A self-contained HTML page around rendered markdown
+pub fn standalone_html(title: &str, text: &str) -> String {
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{}</title>\n<style>{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html(title),
+        DOCUMENT_STYLE,
+        render_markdown(text)
+    )
+}
@@ -0,0 +1,152 @@
+use crate::context::render_section;
+use crate::error::EngineError;
+use crate::files::looks_binary;
+use crate::sandbox::Workspace;
+use std::collections::HashSet;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use tauri::{AppHandle, Manager};
+
+const MAX_MENTION_BYTES: usize = 64 * 1024;
+const MAX_TOTAL_MENTION_BYTES: usize = 256 * 1024;
+// Sentence punctuation after a mention isn't part of the path
+const TRAILING: &[char] = &['.', ',', ';', ':', '!', '?', ')', ']', '}', '"', '\''];
+
+/// A message with its `@path` mentions inlined
+pub struct Mentioned {
+    pub text: String,
+    /// Mentions left as they were: missing, outside the workspace, or past
+    /// the total budget
+    pub unresolved: Vec<String>,
+}
+
+/// `(start, end)` of each `@path` in `text`, the `@` included. A mention
+/// starts a word and names something with a `.` or `/`, so addresses and
+/// handles are left alone.
+fn find(text: &str) -> Vec<(usize, usize)> {
+    let mut found = Vec::new();
+    for (at, _) in text.match_indices('@') {
+        let starts_word = text[..at]
+            .chars()
+            .next_back()
+            .is_none_or(|c| c.is_whitespace() || "([{".contains(c));
+        if !starts_word {
+            continue;
+        }
+        let rest = &text[at + 1..];
+        let len = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        let path = rest[..len].trim_end_matches(TRAILING);
+        if path.contains(['.', '/', '\\']) && path.chars().any(char::is_alphanumeric) {
+            found.push((at, at + 1 + path.len()));
+        }
+    }
+    found
+}
+
+/// At most `limit` bytes of the file, whether there was more, and None for
+/// a binary file
+fn read_capped(path: &Path, limit: usize) -> std::io::Result<Option<(String, bool)>> {
+    let mut bytes = Vec::new();
+    std::fs::File::open(path)?
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)?;
+    if looks_binary(&bytes) {
+        return Ok(None);
+    }
+    let truncated = bytes.len() > limit;
+    bytes.truncate(limit);
+    let mut text = String::from_utf8_lossy(&bytes).into_owned();
+    // A character cut at the limit decodes as a replacement character
+    if truncated && text.ends_with(char::REPLACEMENT_CHARACTER) {
+        text.pop();
+    }
+    Ok(Some((text, truncated)))
+}
+
+/// The text from `copied` to `end`, without the space left after a section
+fn after(text: &str, copied: usize, end: usize) -> &str {
+    let segment = &text[copied..end];
+    if copied > 0 {
+        segment.trim_start_matches(' ')
+    } else {
+        segment
+    }
+}
+
+fn inline(text: &str, base: &Path, workspace: &Workspace) -> Mentioned {
+    let mut out = String::with_capacity(text.len());
+    let mut unresolved = Vec::new();
+    let mut seen = HashSet::new();
+    let mut budget = MAX_TOTAL_MENTION_BYTES;
+    let mut copied = 0;
+    for (start, end) in find(text) {
+        let mention = &text[start..end];
+        let rel = mention[1..].replace('\\', "/");
+        let Ok(path) = workspace.check(&base.join(&rel)) else {
+            unresolved.push(rel);
+            continue;
+        };
+        if !path.is_file() {
+            unresolved.push(rel);
+            continue;
+        }
+        // Inlined once; later mentions of the same file stay as written
+        if !seen.insert(path.clone()) {
+            continue;
+        }
+        let section = match read_capped(&path, MAX_MENTION_BYTES.min(budget)) {
+            Ok(Some((content, truncated))) if budget > 0 => {
+                budget -= content.len();
+                let mut section = render_section(&rel, &content);
+                if truncated {
+                    section.push_str(&format!("({} was cut short here)\n\n", rel));
+                }
+                section
+            }
+            Ok(None) => format!("### {}\n(binary file, not included)\n\n", rel),
+            _ => {
+                unresolved.push(rel);
+                continue;
+            }
+        };
+        out.push_str(after(text, copied, start));
+        if !out.is_empty() && !out.ends_with('\n') {
+            out.push_str("\n\n");
+        }
+        out.push_str(&section);
+        copied = end;
+    }
+    out.push_str(after(text, copied, text.len()));
+    Mentioned {
+        text: out,
+        unresolved,
+    }
+}
+
+/// Replace each `@path` in `message` with the file's content as a fenced
+/// section. Paths are taken from `cwd`, or the first workspace root, and
+/// held to the workspace like any other read. Each file is cut at 64 KiB and
+/// all of them together at 256 KiB; binary files get a note instead.
+pub async fn expand(
+    app: &AppHandle,
+    message: &str,
+    cwd: Option<&Path>,
+) -> Result<Mentioned, EngineError> {
+    let base: Option<PathBuf> = cwd
+        .map(Path::to_path_buf)
+        .or_else(|| app.state::<Workspace>().roots().into_iter().next());
+    let Some(base) = base else {
+        return Ok(Mentioned {
+            text: message.to_string(),
+            unresolved: find(message)
+                .into_iter()
+                .map(|(start, end)| message[start + 1..end].to_string())
+                .collect(),
+        });
+    };
+    let app = app.clone();
+    let message = message.to_string();
+    tokio::task::spawn_blocking(move || inline(&message, &base, &app.state::<Workspace>()))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))
+}
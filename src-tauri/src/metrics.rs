@@ -0,0 +1,324 @@
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::settings;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+// Four linear sub-buckets per power of two of nanoseconds, so a percentile is
+// off by at most a quarter of its octave
+const SUB_BUCKETS: u32 = 4;
+const BUCKETS: usize = 64 * SUB_BUCKETS as usize;
+const RECENT_TRACES: usize = 256;
+const DEFAULT_SLOWEST: usize = 10;
+const SNAPSHOT_TICK: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub enum Stage {
+    /// Finding node and the CLI script
+    #[serde(rename = "discovery")]
+    Discovery,
+    #[serde(rename = "spawn")]
+    Spawn,
+    /// From the start of a request to its first output
+    #[serde(rename = "first_byte")]
+    FirstByte,
+    /// From the first output to the last
+    #[serde(rename = "stream")]
+    Stream,
+    /// Writing a conversation to history
+    #[serde(rename = "persist")]
+    Persist,
+    #[serde(rename = "post_process")]
+    PostProcess,
+    #[serde(rename = "fs.sandbox_check")]
+    SandboxCheck,
+    #[serde(rename = "fs.io")]
+    Io,
+    #[serde(rename = "fs.serialize")]
+    Serialize,
+}
+
+impl Stage {
+    const ALL: [Stage; 9] = [
+        Stage::Discovery,
+        Stage::Spawn,
+        Stage::FirstByte,
+        Stage::Stream,
+        Stage::Persist,
+        Stage::PostProcess,
+        Stage::SandboxCheck,
+        Stage::Io,
+        Stage::Serialize,
+    ];
+}
+
+/// Lock-free, so recording costs a few atomic adds
+struct Histogram {
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            total_ns: AtomicU64::new(0),
+            max_ns: AtomicU64::new(0),
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    fn record(&self, ns: u64) {
+        self.total_ns.fetch_add(ns, Ordering::Relaxed);
+        self.max_ns.fetch_max(ns, Ordering::Relaxed);
+        self.buckets[bucket(ns)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn bucket(ns: u64) -> usize {
+    if ns < SUB_BUCKETS as u64 {
+        return ns as usize;
+    }
+    let octave = 63 - ns.leading_zeros();
+    let sub = (ns >> (octave - 2)) & (SUB_BUCKETS as u64 - 1);
+    (octave * SUB_BUCKETS) as usize + sub as usize
+}
+
+/// Largest duration that lands in `index`, in nanoseconds
+fn bucket_upper_ns(index: usize) -> f64 {
+    let (octave, sub) = (index as u32 / SUB_BUCKETS, index as u32 % SUB_BUCKETS);
+    if octave < 2 {
+        return (index + 1) as f64;
+    }
+    (SUB_BUCKETS + sub + 1) as f64 * 2f64.powi(octave as i32 - 2)
+}
+
+static HISTOGRAMS: [Histogram; Stage::ALL.len()] = [const { Histogram::new() }; Stage::ALL.len()];
+static RECENT: Mutex<VecDeque<RequestTrace>> = Mutex::new(VecDeque::new());
+
+pub fn record(stage: Stage, elapsed: Duration) {
+    HISTOGRAMS[stage as usize].record(elapsed.as_nanos().min(u64::MAX as u128) as u64);
+}
+
+/// Records the time until it's dropped
+pub struct StageTimer {
+    stage: Stage,
+    started: Instant,
+}
+
+impl Drop for StageTimer {
+    fn drop(&mut self) {
+        record(self.stage, self.started.elapsed());
+    }
+}
+
+/// Time the rest of the enclosing scope as `stage`
+pub fn timer(stage: Stage) -> StageTimer {
+    StageTimer {
+        stage,
+        started: Instant::now(),
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageSample {
+    pub stage: Stage,
+    pub ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestTrace {
+    /// How the request ran, e.g. `spawn` or `warm`
+    pub label: String,
+    pub started_at: u64,
+    pub total_ms: f64,
+    pub stages: Vec<StageSample>,
+}
+
+/// One request's stage breakdown. Stages also go to the histograms; the
+/// trace joins the recent list when dropped, however the request ended.
+pub struct Trace {
+    label: String,
+    started: Instant,
+    started_at: u64,
+    stages: Vec<StageSample>,
+}
+
+impl Trace {
+    pub fn new(label: &str) -> Self {
+        Self {
+            label: label.to_string(),
+            started: Instant::now(),
+            started_at: now_millis(),
+            stages: Vec::new(),
+        }
+    }
+
+    pub fn set_label(&mut self, label: &str) {
+        self.label = label.to_string();
+    }
+
+    pub fn record(&mut self, stage: Stage, elapsed: Duration) {
+        record(stage, elapsed);
+        self.stages.push(StageSample {
+            stage,
+            ms: elapsed.as_secs_f64() * 1000.0,
+        });
+    }
+}
+
+impl Drop for Trace {
+    fn drop(&mut self) {
+        // e.g. a warm attempt that fell back to a process per message
+        if self.stages.is_empty() {
+            return;
+        }
+        let trace = RequestTrace {
+            label: std::mem::take(&mut self.label),
+            started_at: self.started_at,
+            total_ms: self.started.elapsed().as_secs_f64() * 1000.0,
+            stages: std::mem::take(&mut self.stages),
+        };
+        if let Ok(mut recent) = RECENT.lock() {
+            if recent.len() >= RECENT_TRACES {
+                recent.pop_front();
+            }
+            recent.push_back(trace);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StageStats {
+    pub stage: Stage,
+    pub count: u64,
+    pub mean_ms: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PerformanceProfile {
+    /// Every stage recorded since launch; percentiles are bucket upper bounds
+    pub stages: Vec<StageStats>,
+    /// Slowest of the recent requests, slowest first
+    pub slowest: Vec<RequestTrace>,
+}
+
+fn stats(stage: Stage) -> StageStats {
+    let histogram = &HISTOGRAMS[stage as usize];
+    let counts: Vec<u64> = histogram
+        .buckets
+        .iter()
+        .map(|b| b.load(Ordering::Relaxed))
+        .collect();
+    // Summed from the buckets, so it agrees with them mid-update
+    let count: u64 = counts.iter().sum();
+    let percentile = |q: f64| -> f64 {
+        if count == 0 {
+            return 0.0;
+        }
+        let target = ((q * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (index, n) in counts.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return bucket_upper_ns(index) / 1e6;
+            }
+        }
+        0.0
+    };
+    let total_ns = histogram.total_ns.load(Ordering::Relaxed);
+    StageStats {
+        stage,
+        count,
+        mean_ms: if count == 0 {
+            0.0
+        } else {
+            total_ns as f64 / count as f64 / 1e6
+        },
+        p50_ms: percentile(0.50),
+        p95_ms: percentile(0.95),
+        p99_ms: percentile(0.99),
+        max_ms: histogram.max_ns.load(Ordering::Relaxed) as f64 / 1e6,
+    }
+}
+
+pub fn profile(slowest: usize) -> PerformanceProfile {
+    let mut traces: Vec<RequestTrace> = RECENT
+        .lock()
+        .map(|recent| recent.iter().cloned().collect())
+        .unwrap_or_default();
+    traces.sort_by(|a, b| b.total_ms.total_cmp(&a.total_ms));
+    traces.truncate(slowest);
+    PerformanceProfile {
+        stages: Stage::ALL.iter().map(|&stage| stats(stage)).collect(),
+        slowest: traces,
+    }
+}
+
+/// Emit `performance-snapshot` every few minutes while the debug setting asks for it
+pub fn spawn_snapshots(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let mut last = Instant::now();
+        loop {
+            tokio::time::sleep(SNAPSHOT_TICK).await;
+            let Some(minutes) = settings::load(&app).debug.performance_snapshot_minutes else {
+                continue;
+            };
+            if last.elapsed() >= Duration::from_secs(minutes.saturating_mul(60)) {
+                last = Instant::now();
+                let _ = app.emit_all("performance-snapshot", profile(DEFAULT_SLOWEST));
+            }
+        }
+    });
+}
+
+/// Per-stage latency since launch, plus the slowest recent requests
+#[tauri::command]
+pub async fn get_performance_profile(
+    slowest: Option<usize>,
+) -> Result<PerformanceProfile, EngineError> {
+    Ok(profile(slowest.unwrap_or(DEFAULT_SLOWEST)))
+}
+
+/// Send the current profile as a `performance-snapshot` event
+#[tauri::command]
+pub async fn emit_performance_snapshot(app: AppHandle) -> Result<(), EngineError> {
+    app.emit_all("performance-snapshot", profile(DEFAULT_SLOWEST))
+        .map_err(|e| EngineError::internal(format!("Failed to emit snapshot: {}", e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The per-call cost is timed in benches/stage_recording.rs; the histograms
+    // are process-wide, so counts from other tests may land here too
+    #[test]
+    fn every_record_lands_in_its_stage() {
+        const CALLS: u32 = 100_000;
+        let counted = |stage: Stage| -> u64 {
+            HISTOGRAMS[stage as usize]
+                .buckets
+                .iter()
+                .map(|b| b.load(Ordering::Relaxed))
+                .sum()
+        };
+        let before = counted(Stage::PostProcess);
+        for i in 0..CALLS {
+            // Spread over the buckets, from nanoseconds to minutes
+            record(
+                Stage::PostProcess,
+                Duration::from_nanos((i as u64) << (i % 20)),
+            );
+        }
+        assert!(counted(Stage::PostProcess) - before >= CALLS as u64);
+    }
+}
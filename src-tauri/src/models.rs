@@ -0,0 +1,99 @@
+use crate::error::EngineError;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelInfo {
+    /// Passed to the CLI as `--model`
+    pub id: &'static str,
+    pub label: &'static str,
+    pub description: &'static str,
+    /// Input tokens the model accepts
+    pub context_window: usize,
+}
+
+const CONTEXT_WINDOW: usize = 200_000;
+// Sonnet's extended context, asked for with a `[1m]` suffix on the model
+const LONG_CONTEXT_WINDOW: usize = 1_000_000;
+
+// The CLI resolves these aliases to the latest model of each family, so the
+// list doesn't go stale with every release; full model ids work too
+const MODELS: &[ModelInfo] = &[
+    ModelInfo {
+        id: "sonnet",
+        label: "Sonnet",
+        description: "Balanced speed and capability; the CLI's usual default",
+        context_window: CONTEXT_WINDOW,
+    },
+    ModelInfo {
+        id: "opus",
+        label: "Opus",
+        description: "Most capable, for large refactors and hard problems",
+        context_window: CONTEXT_WINDOW,
+    },
+    ModelInfo {
+        id: "haiku",
+        label: "Haiku",
+        description: "Fastest and cheapest, for quick questions",
+        context_window: CONTEXT_WINDOW,
+    },
+];
+
+/// For small jobs in the background, like titling conversations
+pub const CHEAPEST_MODEL: &str = "haiku";
+
+/// A model name that's safe to pass as `--model`. Anything else the CLI is
+/// left to judge, since new model ids appear faster than a list could follow.
+pub fn validate_model(model: &str) -> Result<String, EngineError> {
+    let model = model.trim();
+    // A leading dash would be parsed as another CLI flag
+    let well_formed = !model.is_empty()
+        && !model.starts_with('-')
+        && model
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_.:[]@".contains(c));
+    if !well_formed {
+        return Err(EngineError::invalid(format!(
+            "'{}' is not a valid model name",
+            model
+        )));
+    }
+    Ok(model.to_string())
+}
+
+/// Trimmed and validated; `None` and blank both mean the CLI default
+pub fn requested_model(model: Option<String>) -> Result<Option<String>, EngineError> {
+    match model {
+        Some(model) if !model.trim().is_empty() => validate_model(&model).map(Some),
+        Some(_) => Err(EngineError::invalid("Model name is empty")),
+        None => Ok(None),
+    }
+}
+
+/// How many input tokens `model` takes; every current model has the same
+/// window unless the long-context variant is asked for
+pub fn context_window(model: Option<&str>) -> usize {
+    match model {
+        Some(model) if model.to_ascii_lowercase().ends_with("[1m]") => LONG_CONTEXT_WINDOW,
+        _ => CONTEXT_WINDOW,
+    }
+}
+
+/// Whether CLI output says the model wasn't recognized
+pub fn is_unknown_model(detail: &str) -> bool {
+    let detail = detail.to_ascii_lowercase();
+    detail.contains("model")
+        && [
+            "not found",
+            "not_found_error",
+            "invalid model",
+            "unknown model",
+        ]
+        .iter()
+        .any(|sign| detail.contains(sign))
+}
+
+/// Models for the picker, in the order to show them
+#[tauri::command]
+pub async fn list_models() -> Result<Vec<ModelInfo>, EngineError> {
+    Ok(MODELS.to_vec())
+}
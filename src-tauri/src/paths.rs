@@ -0,0 +1,209 @@
+use crate::error::EngineError;
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+/// `<app-data>/<sub>`, created on demand
+pub fn app_data_subdir(app: &AppHandle, sub: &str) -> Result<PathBuf, EngineError> {
+    let base = app
+        .path_resolver()
+        .app_data_dir()
+        .ok_or_else(|| EngineError::internal("App data directory is unavailable"))?;
+    let dir = base.join(sub);
+    std::fs::create_dir_all(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    Ok(dir)
+}
+
+/// `<app-config>/<sub>`, created on demand; for user-authored files worth syncing
+pub fn app_config_subdir(app: &AppHandle, sub: &str) -> Result<PathBuf, EngineError> {
+    let base = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or_else(|| EngineError::internal("App config directory is unavailable"))?;
+    let dir = base.join(sub);
+    std::fs::create_dir_all(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    Ok(dir)
+}
+
+/// `<app-cache>/<sub>`, created on demand; for files that are safe to lose
+pub fn app_cache_subdir(app: &AppHandle, sub: &str) -> Result<PathBuf, EngineError> {
+    let base = app
+        .path_resolver()
+        .app_cache_dir()
+        .ok_or_else(|| EngineError::internal("App cache directory is unavailable"))?;
+    let dir = base.join(sub);
+    std::fs::create_dir_all(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    Ok(dir)
+}
+
+/// Restrict user-supplied names to something safe to use as a file stem
+pub fn validate_name(name: &str) -> Result<&str, EngineError> {
+    let trimmed = name.trim();
+    let valid = !trimmed.is_empty()
+        && trimmed.len() <= 128
+        && !trimmed.starts_with('.')
+        && trimmed
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ' ' | '.'));
+    if valid {
+        Ok(trimmed)
+    } else {
+        Err(EngineError::invalid(format!(
+            "'{}' is not a valid name (use letters, digits, spaces, '-', '_' or '.')",
+            name
+        )))
+    }
+}
+
+/// Strip Windows verbatim prefixes when that's lossless: `\\?\C:\x` becomes
+/// `C:\x` and `\\?\UNC\server\share\x` becomes `\\server\share\x`. Paths
+/// that only work in verbatim form (too long, reserved names) are left alone.
+#[cfg(windows)]
+pub fn simplify(path: PathBuf) -> PathBuf {
+    const MAX_PATH: usize = 260;
+    const RESERVED: &[&str] = &[
+        "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+        "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+    ];
+    let Some(text) = path.to_str() else {
+        return path;
+    };
+    let simplified = if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", rest)
+    } else if let Some(rest) = text.strip_prefix(r"\\?\") {
+        let drive = rest.as_bytes();
+        if drive.len() < 3 || !drive[0].is_ascii_alphabetic() || &drive[1..3] != b":\\" {
+            return path;
+        }
+        rest.to_string()
+    } else {
+        return path;
+    };
+    let safe = simplified.len() < MAX_PATH
+        && simplified.split('\\').skip(1).all(|part| {
+            let stem = part.split('.').next().unwrap_or("");
+            !RESERVED.iter().any(|r| r.eq_ignore_ascii_case(stem)) && !part.ends_with(['.', ' '])
+        });
+    if safe {
+        PathBuf::from(simplified)
+    } else {
+        path
+    }
+}
+
+#[cfg(not(windows))]
+pub fn simplify(path: PathBuf) -> PathBuf {
+    path
+}
+
+/// `canonicalize` without the `\\?\` prefixes, so results compare and display
+/// the same way the user typed them
+pub fn canonicalize(path: &Path) -> std::io::Result<PathBuf> {
+    path.canonicalize().map(simplify)
+}
+
+/// UNC share paths (`\\server\share`), where every metadata call is a network round trip.
+/// Mapped drive letters aren't detected.
+pub fn is_network_path(path: &Path) -> bool {
+    if !cfg!(windows) {
+        return false;
+    }
+    let text = path.to_string_lossy();
+    text.starts_with(r"\\?\UNC\") || (text.starts_with(r"\\") && !text.starts_with(r"\\?\"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(windows)]
+    #[test]
+    fn simplify_strips_only_lossless_prefixes() {
+        let long = format!(r"\\?\C:\{}", "a".repeat(300));
+        let cases: &[(&str, &str)] = &[
+            (r"\\?\C:\Users\me\project", r"C:\Users\me\project"),
+            (r"\\?\UNC\localhost\C$\Users\me", r"\\localhost\C$\Users\me"),
+            (
+                r"\\?\UNC\server\share\dir\file.rs",
+                r"\\server\share\dir\file.rs",
+            ),
+            (r"C:\already\plain", r"C:\already\plain"),
+            (
+                r"\\localhost\C$\already\plain",
+                r"\\localhost\C$\already\plain",
+            ),
+            // Only valid verbatim: left alone
+            (r"\\?\C:\dir\CON", r"\\?\C:\dir\CON"),
+            (r"\\?\C:\dir\nul.txt", r"\\?\C:\dir\nul.txt"),
+            (r"\\?\C:\dir\trailing.", r"\\?\C:\dir\trailing."),
+            (r"\\?\C:\dir\trailing ", r"\\?\C:\dir\trailing "),
+            (r"\\?\UNC\localhost\C$\LPT1", r"\\?\UNC\localhost\C$\LPT1"),
+            (
+                r"\\?\Volume{01234567-89ab-cdef-0123-456789abcdef}\dir",
+                r"\\?\Volume{01234567-89ab-cdef-0123-456789abcdef}\dir",
+            ),
+            (&long, &long),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(
+                simplify(PathBuf::from(input)),
+                Path::new(expected),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn verbatim_and_plain_forms_compare_equal_once_simplified() {
+        let pairs = [
+            (r"\\?\C:\Users\me", r"C:\Users\me"),
+            (r"\\?\UNC\localhost\C$\Users\me", r"\\localhost\C$\Users\me"),
+        ];
+        for (verbatim, plain) in pairs {
+            assert_eq!(
+                simplify(PathBuf::from(verbatim)),
+                simplify(PathBuf::from(plain))
+            );
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn is_network_path_spots_unc_shares_in_either_form() {
+        let cases = [
+            (r"\\localhost\C$\Users\me", true),
+            (r"\\?\UNC\localhost\C$\Users\me", true),
+            (r"\\server\share", true),
+            (r"\\?\C:\Users\me", false),
+            (r"C:\Users\me", false),
+            (r"Z:\mapped\drive", false),
+            (r"relative\path", false),
+        ];
+        for (path, expected) in cases {
+            assert_eq!(is_network_path(Path::new(path)), expected, "{}", path);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn canonicalize_returns_plain_paths() {
+        let dir = std::env::temp_dir();
+        let canonical = canonicalize(&dir).unwrap();
+        assert!(
+            !canonical.to_string_lossy().starts_with(r"\\?\"),
+            "{}",
+            canonical.display()
+        );
+        assert_eq!(canonical, simplify(dir.canonicalize().unwrap()));
+    }
+
+    #[cfg(not(windows))]
+    #[test]
+    fn other_platforms_leave_paths_alone() {
+        let path = PathBuf::from(r"\\?\UNC\localhost\C$\x");
+        assert_eq!(simplify(path.clone()), path);
+        assert!(!is_network_path(Path::new(r"\\localhost\C$\x")));
+        assert!(!is_network_path(Path::new("/mnt/share")));
+    }
+}
@@ -0,0 +1,207 @@
+use crate::error::EngineError;
+use crate::sandbox;
+use pdf_extract::{Document, Object, PlainTextOutput};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use tauri::AppHandle;
+
+const MAX_PDF_BYTES: u64 = 100 * 1024 * 1024;
+// Below this many non-whitespace chars per page the PDF is almost certainly scanned images
+const SCANNED_CHARS_PER_PAGE: usize = 20;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct PageRange {
+    pub start: u32,
+    pub end: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct PdfOptions {
+    pub max_pages: u32,
+    pub page_range: Option<PageRange>,
+    pub max_total_chars: usize,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            max_pages: 200,
+            page_range: None,
+            max_total_chars: 1_000_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    OutOfRange,
+    MaxPages,
+    SizeCap,
+    ExtractionFailed,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SkippedPage {
+    pub page: u32,
+    pub reason: SkipReason,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PdfPage {
+    pub page: u32,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PdfText {
+    pub title: Option<String>,
+    pub page_count: u32,
+    pub pages: Vec<PdfPage>,
+    pub pages_skipped: Vec<SkippedPage>,
+    pub total_chars: usize,
+    /// Little or no extractable text: image-only (scanned) pages
+    pub likely_scanned: bool,
+}
+
+impl PdfText {
+    /// Pages joined with page markers, for inlining into a prompt
+    pub fn joined(&self) -> String {
+        self.pages
+            .iter()
+            .map(|p| format!("--- Page {} ---\n{}", p.page, p.text.trim()))
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+}
+
+/// PDF text strings are UTF-16BE with a BOM or (roughly) Latin-1
+fn decode_pdf_string(bytes: &[u8]) -> String {
+    if let Some(rest) = bytes.strip_prefix(&[0xFE, 0xFF]) {
+        let units: Vec<u16> = rest
+            .chunks_exact(2)
+            .map(|c| u16::from_be_bytes([c[0], c[1]]))
+            .collect();
+        String::from_utf16_lossy(&units)
+    } else {
+        bytes.iter().map(|&b| b as char).collect()
+    }
+}
+
+fn document_title(doc: &Document) -> Option<String> {
+    let info = doc
+        .trailer
+        .get(b"Info")
+        .ok()
+        .and_then(|o| o.as_reference().ok())
+        .and_then(|id| doc.get_dictionary(id).ok())?;
+    match info.get(b"Title").ok()? {
+        Object::String(bytes, _) => {
+            let title = decode_pdf_string(bytes).trim().to_string();
+            (!title.is_empty()).then_some(title)
+        }
+        _ => None,
+    }
+}
+
+fn extract_page(doc: &Document, page: u32) -> Option<String> {
+    // The extractor panics on some malformed pages; treat that as a failed page
+    std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut text = String::new();
+        let mut output = PlainTextOutput::new(&mut text);
+        pdf_extract::output_doc_page(doc, &mut output, page).ok()?;
+        Some(text)
+    }))
+    .ok()
+    .flatten()
+}
+
+pub fn extract(path: &Path, options: &PdfOptions) -> Result<PdfText, EngineError> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| EngineError::io(path, e))?
+        .len();
+    if size > MAX_PDF_BYTES {
+        return Err(EngineError::invalid(format!(
+            "PDF is {} bytes; the limit is {}",
+            size, MAX_PDF_BYTES
+        )));
+    }
+    let bytes = std::fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    let mut doc = Document::load_mem(&bytes).map_err(|e| EngineError::PdfInvalid {
+        path: path.display().to_string(),
+        detail: e.to_string(),
+    })?;
+    if doc.is_encrypted() && doc.decrypt("").is_err() {
+        return Err(EngineError::PdfEncrypted {
+            path: path.display().to_string(),
+        });
+    }
+
+    let title = document_title(&doc);
+    let page_numbers: Vec<u32> = doc.get_pages().keys().copied().collect();
+    let page_count = page_numbers.len() as u32;
+
+    let mut pages = Vec::new();
+    let mut pages_skipped = Vec::new();
+    let mut total_chars = 0;
+    let mut visible_chars = 0;
+
+    for page in page_numbers {
+        let skip = |reason| SkippedPage { page, reason };
+        if let Some(range) = options.page_range {
+            if page < range.start || page > range.end {
+                pages_skipped.push(skip(SkipReason::OutOfRange));
+                continue;
+            }
+        }
+        if pages.len() as u32 >= options.max_pages {
+            pages_skipped.push(skip(SkipReason::MaxPages));
+            continue;
+        }
+        if total_chars >= options.max_total_chars {
+            pages_skipped.push(skip(SkipReason::SizeCap));
+            continue;
+        }
+        let Some(mut text) = extract_page(&doc, page) else {
+            pages_skipped.push(skip(SkipReason::ExtractionFailed));
+            continue;
+        };
+
+        let room = options.max_total_chars - total_chars;
+        if text.len() > room {
+            let mut cut = room;
+            while !text.is_char_boundary(cut) {
+                cut -= 1;
+            }
+            text.truncate(cut);
+        }
+        total_chars += text.len();
+        visible_chars += text.chars().filter(|c| !c.is_whitespace()).count();
+        pages.push(PdfPage { page, text });
+    }
+
+    let likely_scanned = !pages.is_empty() && visible_chars < SCANNED_CHARS_PER_PAGE * pages.len();
+
+    Ok(PdfText {
+        title,
+        page_count,
+        pages,
+        pages_skipped,
+        total_chars,
+        likely_scanned,
+    })
+}
+
+#[tauri::command]
+pub async fn extract_pdf_text(
+    app: AppHandle,
+    path: String,
+    options: Option<PdfOptions>,
+) -> Result<PdfText, EngineError> {
+    let options = options.unwrap_or_default();
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || extract(&path, &options))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
@@ -0,0 +1,90 @@
+use crate::error::EngineError;
+use serde::Deserialize;
+
+const PERMISSION_MODES: &[&str] = &["default", "acceptEdits", "plan", "bypassPermissions"];
+
+/// Which tools the CLI may use for a turn, passed as `--allowedTools`,
+/// `--disallowedTools` and `--permission-mode`. With `--print` nobody is there
+/// to approve a tool, so anything not allowed is refused.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ToolPermissions {
+    /// Tool names or patterns like `Bash(git diff:*)`; None leaves the CLI's defaults
+    pub allowed_tools: Option<Vec<String>>,
+    pub disallowed_tools: Option<Vec<String>>,
+    pub permission_mode: Option<String>,
+}
+
+fn check_tools(flag: &str, tools: &[String]) -> Result<(), EngineError> {
+    if tools.is_empty() {
+        return Err(EngineError::invalid(format!(
+            "{} is empty; leave it out to keep the CLI's defaults",
+            flag
+        )));
+    }
+    for tool in tools {
+        // Commas separate the list, and a leading dash would be read as a flag
+        let well_formed = !tool.trim().is_empty() && !tool.starts_with('-') && !tool.contains(',');
+        if !well_formed {
+            return Err(EngineError::invalid(format!(
+                "'{}' is not a valid tool name for {}",
+                tool, flag
+            )));
+        }
+    }
+    Ok(())
+}
+
+impl ToolPermissions {
+    /// Reject what the CLI would misread or that contradicts itself, before
+    /// anything is spawned
+    pub fn validate(&self) -> Result<(), EngineError> {
+        if let Some(allowed) = &self.allowed_tools {
+            check_tools("allowed_tools", allowed)?;
+        }
+        if let Some(disallowed) = &self.disallowed_tools {
+            check_tools("disallowed_tools", disallowed)?;
+        }
+        if let (Some(allowed), Some(disallowed)) = (&self.allowed_tools, &self.disallowed_tools) {
+            if let Some(tool) = allowed.iter().find(|t| disallowed.contains(t)) {
+                return Err(EngineError::invalid(format!(
+                    "{} is both allowed and disallowed",
+                    tool
+                )));
+            }
+        }
+        if let Some(mode) = &self.permission_mode {
+            if !PERMISSION_MODES.contains(&mode.as_str()) {
+                return Err(EngineError::invalid(format!(
+                    "Unknown permission mode '{}'; expected one of {}",
+                    mode,
+                    PERMISSION_MODES.join(", ")
+                )));
+            }
+            // Bypassing approvals would let through what the list leaves out
+            if mode == "bypassPermissions" && self.allowed_tools.is_some() {
+                return Err(EngineError::invalid(
+                    "allowed_tools has no effect with bypassPermissions",
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(allowed) = &self.allowed_tools {
+            args.push("--allowedTools".to_string());
+            args.push(allowed.join(","));
+        }
+        if let Some(disallowed) = &self.disallowed_tools {
+            args.push("--disallowedTools".to_string());
+            args.push(disallowed.join(","));
+        }
+        if let Some(mode) = &self.permission_mode {
+            args.push("--permission-mode".to_string());
+            args.push(mode.clone());
+        }
+        args
+    }
+}
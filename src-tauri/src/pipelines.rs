@@ -0,0 +1,228 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::NullSink;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::paths::{app_config_subdir, validate_name};
+use crate::sandbox::Workspace;
+use crate::template_vars::{self, ExpandOptions};
+use crate::templates;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Instant;
+use tauri::{AppHandle, Manager, State, Window};
+
+pub const PIPELINES_DIR: &str = "pipelines";
+const PREVIOUS_OUTPUT: &str = "previous_output";
+const PREVIEW_CHARS: usize = 200;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StepOptions {
+    /// Run in this workspace, with its environment profile
+    pub workspace: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PipelineStep {
+    /// Name of a saved template
+    pub template: String,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Later steps can use this step's output as `{{name}}`
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub options: StepOptions,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StepOutput {
+    pub step_index: usize,
+    pub name: Option<String>,
+    pub output: String,
+    pub duration_ms: u64,
+    /// Filled in once the CLI reports usage
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PipelineResult {
+    pub request_id: String,
+    pub steps: Vec<StepOutput>,
+    pub total_cost_usd: Option<f64>,
+}
+
+fn pipeline_path(app: &AppHandle, name: &str) -> Result<PathBuf, EngineError> {
+    let name = validate_name(name)?;
+    Ok(app_config_subdir(app, PIPELINES_DIR)?.join(format!("{}.json", name)))
+}
+
+/// Names must be unique and can't shadow `previous_output`
+fn validate_steps(steps: &[PipelineStep]) -> Result<(), EngineError> {
+    if steps.is_empty() {
+        return Err(EngineError::invalid("A pipeline needs at least one step"));
+    }
+    let mut seen: Vec<&str> = Vec::new();
+    for name in steps.iter().filter_map(|s| s.name.as_deref()) {
+        if name.trim().is_empty() || name == PREVIOUS_OUTPUT || seen.contains(&name) {
+            return Err(EngineError::invalid(format!(
+                "'{}' can't be used as a step name",
+                name
+            )));
+        }
+        seen.push(name);
+    }
+    Ok(())
+}
+
+fn step_failed(index: usize, step: &PipelineStep, detail: impl ToString) -> EngineError {
+    EngineError::PipelineStepFailed {
+        step_index: index,
+        name: step.name.clone(),
+        detail: detail.to_string(),
+    }
+}
+
+/// Run steps in order, each seeing `{{previous_output}}` and the outputs of
+/// earlier named steps. Stops at the first failure.
+#[tauri::command]
+pub async fn run_pipeline(
+    window: Window,
+    steps: Vec<PipelineStep>,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+) -> Result<PipelineResult, EngineError> {
+    validate_steps(&steps)?;
+    let app = window.app_handle();
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+
+    let mut named: BTreeMap<String, String> = BTreeMap::new();
+    let mut outputs: Vec<StepOutput> = Vec::with_capacity(steps.len());
+    for (index, step) in steps.iter().enumerate() {
+        if token.is_cancelled() {
+            return Err(EngineError::Cancelled);
+        }
+        let _ = window.emit(
+            "pipeline-step-started",
+            json!({ "request_id": request_id, "step_index": index, "name": step.name }),
+        );
+
+        // Earlier outputs first, so a step's own vars win
+        let mut vars = named.clone();
+        if let Some(previous) = outputs.last() {
+            vars.insert(PREVIOUS_OUTPUT.to_string(), previous.output.clone());
+        }
+        vars.extend(step.vars.clone());
+        let body =
+            templates::load(&app, &step.template).map_err(|e| step_failed(index, step, e))?;
+        let expand_options = ExpandOptions {
+            workspace: step.options.workspace.clone(),
+            ..ExpandOptions::default()
+        };
+        let prompt = template_vars::expand(&app, &body, &vars, &expand_options)
+            .await
+            .map_err(|e| step_failed(index, step, e))?
+            .prompt;
+
+        let root = match &step.options.workspace {
+            Some(root) => Some(
+                app.state::<Workspace>()
+                    .check(Path::new(root))
+                    .map_err(|e| step_failed(index, step, e))?,
+            ),
+            None => None,
+        };
+        let mut spawn =
+            env_profile::spawn_options(&app, root).map_err(|e| step_failed(index, step, e))?;
+        spawn.model = step.model.clone();
+        spawn.request_id = Some(request_id.clone());
+
+        let started = Instant::now();
+        let output = match app
+            .state::<ActiveBackend>()
+            .stream(Arc::new(NullSink), prompt, token.clone(), spawn)
+            .await
+        {
+            Ok(reply) => reply.text,
+            Err(_) if token.is_cancelled() => return Err(EngineError::Cancelled),
+            Err(e) => return Err(step_failed(index, step, e)),
+        };
+
+        let preview: String = output.chars().take(PREVIEW_CHARS).collect();
+        let _ = window.emit(
+            "pipeline-step-completed",
+            json!({ "request_id": request_id, "step_index": index, "output_preview": preview }),
+        );
+        if let Some(name) = &step.name {
+            named.insert(name.clone(), output.clone());
+        }
+        outputs.push(StepOutput {
+            step_index: index,
+            name: step.name.clone(),
+            output,
+            duration_ms: started.elapsed().as_millis() as u64,
+            cost_usd: None,
+        });
+    }
+
+    Ok(PipelineResult {
+        request_id,
+        steps: outputs,
+        total_cost_usd: None,
+    })
+}
+
+#[tauri::command]
+pub async fn save_pipeline(
+    app: AppHandle,
+    name: String,
+    steps: Vec<PipelineStep>,
+) -> Result<(), EngineError> {
+    validate_steps(&steps)?;
+    let path = pipeline_path(&app, &name)?;
+    let json = serde_json::to_vec_pretty(&steps)
+        .map_err(|e| EngineError::internal(format!("Failed to serialize pipeline: {}", e)))?;
+    atomic_write(&path, &json, false).map_err(|e| EngineError::io(&path, e))?;
+    Ok(())
+}
+
+#[tauri::command]
+pub async fn load_pipeline(app: AppHandle, name: String) -> Result<Vec<PipelineStep>, EngineError> {
+    let path = pipeline_path(&app, &name)?;
+    let data = std::fs::read(&path).map_err(|e| EngineError::io(&path, e))?;
+    serde_json::from_slice(&data)
+        .map_err(|e| EngineError::io(&path, format!("Corrupt pipeline: {}", e)))
+}
+
+/// Saved pipeline names, sorted
+#[tauri::command]
+pub async fn list_pipelines(app: AppHandle) -> Result<Vec<String>, EngineError> {
+    let dir = app_config_subdir(&app, PIPELINES_DIR)?;
+    let entries = std::fs::read_dir(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let path = entry.path();
+            let is_json = path.extension().is_some_and(|ext| ext == "json");
+            is_json
+                .then(|| path.file_stem().map(|s| s.to_string_lossy().into_owned()))
+                .flatten()
+        })
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+#[tauri::command]
+pub async fn delete_pipeline(app: AppHandle, name: String) -> Result<(), EngineError> {
+    let path = pipeline_path(&app, &name)?;
+    std::fs::remove_file(&path).map_err(|e| EngineError::io(&path, e))
+}
@@ -0,0 +1,323 @@
+use crate::error::EngineError;
+use crate::settings::{self, RegexRule};
+use regex::Regex;
+use tauri::AppHandle;
+
+/// One step of reply post-processing. Steps run in the order a request lists
+/// them, on the final text only; streamed chunks are always raw.
+#[derive(Clone)]
+pub enum Transform {
+    StripOuterCodeFence,
+    TrimTrailingWhitespace,
+    CollapseBlankLines,
+    ExtractFirstCodeBlock,
+    /// A regex rule from settings
+    Replace {
+        regex: Regex,
+        replacement: String,
+    },
+}
+
+impl Transform {
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Transform::StripOuterCodeFence => strip_outer_code_fence(text),
+            Transform::TrimTrailingWhitespace => trim_trailing_whitespace(text),
+            Transform::CollapseBlankLines => collapse_blank_lines(text),
+            Transform::ExtractFirstCodeBlock => {
+                first_code_block(text).unwrap_or_else(|| text.to_string())
+            }
+            Transform::Replace { regex, replacement } => {
+                regex.replace_all(text, replacement.as_str()).into_owned()
+            }
+        }
+    }
+}
+
+/// Look up transforms by name: a built-in, or a regex rule from settings.
+/// Disabled rules are dropped, unknown names and broken patterns are errors.
+pub fn resolve(app: &AppHandle, names: &[String]) -> Result<Vec<Transform>, EngineError> {
+    if names.is_empty() {
+        return Ok(Vec::new());
+    }
+    by_name(&settings::load(app).postprocess.rules, names)
+}
+
+fn by_name(rules: &[RegexRule], names: &[String]) -> Result<Vec<Transform>, EngineError> {
+    let mut transforms = Vec::new();
+    for name in names {
+        let transform = match name.as_str() {
+            "strip_outer_code_fence" => Transform::StripOuterCodeFence,
+            "trim_trailing_whitespace" => Transform::TrimTrailingWhitespace,
+            "collapse_blank_lines" => Transform::CollapseBlankLines,
+            "extract_first_code_block" => Transform::ExtractFirstCodeBlock,
+            _ => {
+                let rule = rules
+                    .iter()
+                    .find(|r| &r.name == name)
+                    .ok_or_else(|| EngineError::invalid(format!("Unknown transform {}", name)))?;
+                if !rule.enabled {
+                    continue;
+                }
+                let regex = Regex::new(&rule.pattern).map_err(|e| {
+                    EngineError::invalid(format!("Rule {} has a bad pattern: {}", name, e))
+                })?;
+                Transform::Replace {
+                    regex,
+                    replacement: rule.replacement.clone(),
+                }
+            }
+        };
+        transforms.push(transform);
+    }
+    Ok(transforms)
+}
+
+pub fn apply(transforms: &[Transform], text: &str) -> String {
+    transforms
+        .iter()
+        .fold(text.to_string(), |text, transform| transform.apply(&text))
+}
+
+/// A run of three or more backticks or tildes opening a line
+fn fence_marker(line: &str) -> Option<&str> {
+    let first = line.chars().next().filter(|&c| c == '`' || c == '~')?;
+    let len = line.len() - line.trim_start_matches(first).len();
+    (len >= 3).then(|| &line[..len])
+}
+
+/// Whether `line` closes a block opened with `marker`
+fn closes(line: &str, marker: &str) -> bool {
+    let line = line.trim();
+    let first = marker.as_bytes()[0] as char;
+    line.len() >= marker.len() && line.chars().all(|c| c == first)
+}
+
+/// The body of a reply that is one fenced block and nothing else; anything
+/// else, including two blocks back to back, comes back unchanged
+fn strip_outer_code_fence(text: &str) -> String {
+    let trimmed = text.trim();
+    let Some((opening, rest)) = trimmed.split_once('\n') else {
+        return text.to_string();
+    };
+    let Some(marker) = fence_marker(opening.trim_end()) else {
+        return text.to_string();
+    };
+    let (body, closing) = rest.rsplit_once('\n').unwrap_or(("", rest));
+    if !closes(closing, marker) || body.lines().any(|line| closes(line, marker)) {
+        return text.to_string();
+    }
+    body.trim_end_matches('\r').to_string()
+}
+
+/// Strips the end of every line and of the text, so there's no final newline
+fn trim_trailing_whitespace(text: &str) -> String {
+    text.lines()
+        .map(str::trim_end)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .trim_end()
+        .to_string()
+}
+
+/// Several blank lines in a row become one; line endings are kept
+fn collapse_blank_lines(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut after_blank = false;
+    for line in text.split_inclusive('\n') {
+        let blank = line.trim().is_empty();
+        if !(blank && after_blank) {
+            out.push_str(line);
+        }
+        after_blank = blank;
+    }
+    out
+}
+
+/// The first fenced block's body, up to the end of the text if it's never closed
+pub fn first_code_block(text: &str) -> Option<String> {
+    let mut lines = text.split_inclusive('\n');
+    while let Some(line) = lines.next() {
+        let Some(marker) = fence_marker(line.trim()) else {
+            continue;
+        };
+        let mut body = String::new();
+        for line in lines.by_ref() {
+            if closes(line, marker) {
+                break;
+            }
+            body.push_str(line);
+        }
+        return Some(body);
+    }
+    None
+}
+
+/// The marker of a fence still open at the end of `text`, e.g. a reply cut off
+/// inside a code block
+pub fn unclosed_fence(text: &str) -> Option<&str> {
+    let mut open = None;
+    for line in text.lines() {
+        match open {
+            Some(marker) if closes(line, marker) => open = None,
+            Some(_) => {}
+            None => open = fence_marker(line.trim()),
+        }
+    }
+    open
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(transform: Transform, cases: &[(&str, &str)]) {
+        for (input, expected) in cases {
+            assert_eq!(transform.apply(input), *expected, "input {:?}", input);
+        }
+    }
+
+    fn rule(name: &str, pattern: &str, replacement: &str, enabled: bool) -> RegexRule {
+        RegexRule {
+            name: name.to_string(),
+            pattern: pattern.to_string(),
+            replacement: replacement.to_string(),
+            enabled,
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn strip_outer_code_fence() {
+        check(
+            Transform::StripOuterCodeFence,
+            &[
+                ("```rust\nfn main() {}\n```", "fn main() {}"),
+                ("  ```\na\nb\n```\n\n", "a\nb"),
+                ("~~~~\nx\n~~~~", "x"),
+                ("```\r\na\r\n```\r\n", "a"),
+                ("```\n```", ""),
+                // A shorter fence inside a longer one is part of the body
+                ("````\n```\ninner\n```\n````", "```\ninner\n```"),
+                ("```\na\n```\n```\nb\n```", "```\na\n```\n```\nb\n```"),
+                ("text first\n```\na\n```", "text first\n```\na\n```"),
+                ("```\nnever closed", "```\nnever closed"),
+                ("plain", "plain"),
+            ],
+        );
+    }
+
+    #[test]
+    fn trim_trailing_whitespace() {
+        check(
+            Transform::TrimTrailingWhitespace,
+            &[
+                ("a  \nb\t\n\n", "a\nb"),
+                ("a\r\nb \r\n", "a\nb"),
+                ("  indented  ", "  indented"),
+                ("", ""),
+            ],
+        );
+    }
+
+    #[test]
+    fn collapse_blank_lines() {
+        check(
+            Transform::CollapseBlankLines,
+            &[
+                ("a\n\n\n\nb\n", "a\n\nb\n"),
+                ("a\n \n\t\nb", "a\n \nb"),
+                ("\n\n\na", "\na"),
+                ("a\r\n\r\n\r\nb", "a\r\n\r\nb"),
+                ("a\nb", "a\nb"),
+            ],
+        );
+    }
+
+    #[test]
+    fn extract_first_code_block() {
+        check(
+            Transform::ExtractFirstCodeBlock,
+            &[
+                (
+                    "intro\n```py\nprint(1)\n```\nmore\n```\nsecond\n```",
+                    "print(1)\n",
+                ),
+                ("  ```\nindented\n  ```\n", "indented\n"),
+                ("```\nnever closed\nstill", "never closed\nstill"),
+                ("no code at all", "no code at all"),
+            ],
+        );
+    }
+
+    #[test]
+    fn unclosed_fences() {
+        let cases = [
+            ("```rust\nfn main() {}\n```\n", None),
+            ("```rust\nfn main() {", Some("```")),
+            ("````md\n```\ninner\n```\n", Some("````")),
+            ("````md\n```\ninner\n```\n````", None),
+            ("~~~\n```\n", Some("~~~")),
+            ("text with ``` in it", None),
+            ("", None),
+        ];
+        for (text, expected) in cases {
+            assert_eq!(unclosed_fence(text), expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn regex_rules_replace_every_match_with_groups() {
+        let transforms = by_name(
+            &[
+                rule("word", r"\bfoo\b", "bar", true),
+                rule("swap", r"(\w+)@(?P<host>\w+)", "$host at $1", true),
+            ],
+            &names(&["word", "swap"]),
+        )
+        .unwrap();
+        assert_eq!(
+            apply(&transforms, "foo food foo, me@host"),
+            "bar food bar, host at me"
+        );
+    }
+
+    #[test]
+    fn transforms_run_in_the_order_named() {
+        let text = "x\n```\ncode  \n\n```";
+        let extract_then_trim = by_name(
+            &[],
+            &names(&["extract_first_code_block", "trim_trailing_whitespace"]),
+        )
+        .unwrap();
+        assert_eq!(apply(&extract_then_trim, text), "code");
+        let trim_then_extract = by_name(
+            &[],
+            &names(&["trim_trailing_whitespace", "extract_first_code_block"]),
+        )
+        .unwrap();
+        assert_eq!(apply(&trim_then_extract, text), "code\n\n");
+
+        // A rule's output goes through the steps after it
+        let rules = [rule("gap", ";", "\n\n\n", true)];
+        let transforms = by_name(&rules, &names(&["gap", "collapse_blank_lines"])).unwrap();
+        assert_eq!(apply(&transforms, "a;b"), "a\n\nb");
+    }
+
+    #[test]
+    fn names_resolve_to_rules_or_fail() {
+        let rules = [rule("off", "a", "b", false), rule("broken", "(", "", true)];
+        assert!(by_name(&rules, &names(&["off"])).unwrap().is_empty());
+        assert!(matches!(
+            by_name(&rules, &names(&["missing"])),
+            Err(EngineError::InvalidInput { .. })
+        ));
+        assert!(matches!(
+            by_name(&rules, &names(&["broken"])),
+            Err(EngineError::InvalidInput { .. })
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::BTreeSet;
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, Command, Stdio};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+// How long a tree gets to exit on SIGTERM before it's killed outright
+pub const TERM_GRACE: Duration = Duration::from_millis(500);
+const TERM_POLL: Duration = Duration::from_millis(20);
+
+// Every tracked child still running, for [`kill_all`] at exit
+static LIVE: Mutex<BTreeSet<u32>> = Mutex::new(BTreeSet::new());
+
+/// Keeps a child's pid on the list [`kill_all`] works from until dropped
+pub struct Tracked(u32);
+
+impl Tracked {
+    pub fn new(pid: u32) -> Self {
+        if let Ok(mut live) = LIVE.lock() {
+            live.insert(pid);
+        }
+        Self(pid)
+    }
+}
+
+impl Drop for Tracked {
+    fn drop(&mut self) {
+        if let Ok(mut live) = LIVE.lock() {
+            live.remove(&self.0);
+        }
+    }
+}
+
+/// Kill every tracked tree, giving them [`TERM_GRACE`] together to exit on
+/// their own first. Run at exit, where dropping the guards never happens and
+/// a mid-stream CLI would otherwise be left running headless.
+pub fn kill_all() {
+    let pids: Vec<u32> = match LIVE.lock() {
+        Ok(live) => live.iter().copied().collect(),
+        Err(_) => return,
+    };
+    if pids.is_empty() {
+        return;
+    }
+    for pid in &pids {
+        terminate_tree(*pid);
+    }
+    if !cfg!(windows) {
+        std::thread::sleep(TERM_GRACE);
+    }
+    for pid in &pids {
+        force_tree(*pid);
+    }
+}
+
+/// Start the process as the leader of its own group, so [`kill_tree`] can
+/// reach everything it spawns. Windows finds the tree by parent pid instead.
+pub fn own_group(cmd: &mut Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+    #[cfg(not(unix))]
+    let _ = cmd;
+}
+
+/// Kill `child` and everything it started (tool subprocesses, ripgrep, ...),
+/// then reap it
+pub fn kill_tree(child: &mut Child) {
+    let pid = child.id();
+    terminate_tree(pid);
+    let deadline = Instant::now() + TERM_GRACE;
+    while !cfg!(windows) && Instant::now() < deadline && matches!(child.try_wait(), Ok(None)) {
+        std::thread::sleep(TERM_POLL);
+    }
+    force_tree(pid);
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+/// The first half of [`kill_tree`], for children that aren't a std [`Child`]:
+/// SIGTERM to the group on Unix, an outright kill on Windows
+pub fn terminate_tree(pid: u32) {
+    if cfg!(windows) {
+        // taskkill follows parent pids, so it has to run before node is gone
+        let mut cmd = Command::new("taskkill");
+        cmd.args(["/T", "/F", "/PID", &pid.to_string()]);
+        quiet(&mut cmd);
+        let _ = cmd.status();
+    } else {
+        signal_group(pid, "TERM");
+    }
+}
+
+/// The second half: SIGKILL for whatever ignored the TERM, including
+/// children outliving their parent. Nothing left to do on Windows.
+pub fn force_tree(pid: u32) {
+    if !cfg!(windows) {
+        signal_group(pid, "KILL");
+    }
+}
+
+fn signal_group(pid: u32, signal: &str) {
+    let mut cmd = Command::new("kill");
+    cmd.args(["-s", signal, "--", &format!("-{}", pid)]);
+    quiet(&mut cmd);
+    let _ = cmd.status();
+}
+
+fn quiet(cmd: &mut Command) {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        cmd.creation_flags(CREATE_NO_WINDOW);
+    }
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+}
+
+/// A child whose tree is killed if it's dropped still running, e.g. when the
+/// command future goes away with the window mid-stream, and by [`kill_all`]
+/// if the app exits first
+pub struct ChildGuard {
+    child: Child,
+    _tracked: Tracked,
+}
+
+impl ChildGuard {
+    pub fn new(child: Child) -> Self {
+        let _tracked = Tracked::new(child.id());
+        Self { child, _tracked }
+    }
+
+    pub fn kill_tree(&mut self) {
+        kill_tree(&mut self.child);
+    }
+}
+
+impl Deref for ChildGuard {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for ChildGuard {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if matches!(self.child.try_wait(), Ok(None)) {
+            kill_tree(&mut self.child);
+        }
+    }
+}
@@ -0,0 +1,132 @@
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::hash::sha256_hex;
+use crate::paths::{app_data_subdir, canonicalize};
+use crate::sandbox::Workspace;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use tauri::{AppHandle, Manager, State};
+
+pub const CONTEXT_DIR: &str = "project-context";
+// Sent with every turn, so it has to stay small next to the prompt
+const MAX_CONTEXT_BYTES: u64 = 64 * 1024;
+
+/// Context files read so far, by path, with the mtime they were read at
+#[derive(Default)]
+pub struct ProjectContexts {
+    cache: Mutex<HashMap<PathBuf, (SystemTime, Arc<str>)>>,
+}
+
+// Keyed like the workspace config, by a hash of the canonical root
+fn context_path(app: &AppHandle, root: &Path) -> Result<PathBuf, EngineError> {
+    let key = sha256_hex(root.to_string_lossy().as_bytes());
+    Ok(app_data_subdir(app, CONTEXT_DIR)?.join(format!("{}.md", &key[..16])))
+}
+
+fn too_large(path: &Path, size: u64) -> EngineError {
+    EngineError::FileTooLarge {
+        path: path.display().to_string(),
+        size,
+        limit: MAX_CONTEXT_BYTES,
+    }
+}
+
+impl ProjectContexts {
+    /// The root's context, read again only when the file's mtime changes.
+    /// None when there isn't one or it's blank.
+    fn read(&self, path: &Path) -> Result<Option<Arc<str>>, EngineError> {
+        let meta = match std::fs::metadata(path) {
+            Ok(meta) => meta,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                if let Ok(mut cache) = self.cache.lock() {
+                    cache.remove(path);
+                }
+                return Ok(None);
+            }
+            Err(e) => return Err(EngineError::io(path, e)),
+        };
+        if meta.len() > MAX_CONTEXT_BYTES {
+            return Err(too_large(path, meta.len()));
+        }
+        let modified = meta.modified().map_err(|e| EngineError::io(path, e))?;
+        if let Some((at, content)) = self.cache.lock().ok().and_then(|c| c.get(path).cloned()) {
+            if at == modified {
+                return Ok((!content.trim().is_empty()).then_some(content));
+            }
+        }
+        let content: Arc<str> = std::fs::read_to_string(path)
+            .map_err(|e| EngineError::io(path, e))?
+            .into();
+        if let Ok(mut cache) = self.cache.lock() {
+            cache.insert(path.to_path_buf(), (modified, content.clone()));
+        }
+        Ok((!content.trim().is_empty()).then_some(content))
+    }
+}
+
+/// The context of the innermost workspace root `cwd` is under, if any
+pub fn for_cwd(app: &AppHandle, cwd: &Path) -> Result<Option<Arc<str>>, EngineError> {
+    let Ok(cwd) = canonicalize(cwd) else {
+        return Ok(None);
+    };
+    let roots = app.state::<Workspace>().roots();
+    let Some(root) = roots
+        .iter()
+        .filter(|root| cwd.starts_with(root))
+        .max_by_key(|root| root.components().count())
+    else {
+        return Ok(None);
+    };
+    app.state::<ProjectContexts>()
+        .read(&context_path(app, root)?)
+}
+
+/// Standing instructions for a workspace root, appended to the system prompt
+/// of every turn whose `cwd` is under it. Blank content removes them.
+#[tauri::command]
+pub async fn set_project_context(
+    app: AppHandle,
+    root: String,
+    content: String,
+    workspace: State<'_, Workspace>,
+) -> Result<(), EngineError> {
+    let root = workspace.check(Path::new(&root))?;
+    let path = context_path(&app, &root)?;
+    if content.len() as u64 > MAX_CONTEXT_BYTES {
+        return Err(too_large(&path, content.len() as u64));
+    }
+    tokio::task::spawn_blocking(move || {
+        if content.trim().is_empty() {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                    Err(EngineError::io(&path, e))
+                }
+                _ => Ok(()),
+            }
+        } else {
+            atomic_write(&path, content.as_bytes(), false)
+                .map(|_| ())
+                .map_err(|e| EngineError::io(&path, e))
+        }
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// The root's standing instructions; None when it has none
+#[tauri::command]
+pub async fn get_project_context(
+    app: AppHandle,
+    root: String,
+    workspace: State<'_, Workspace>,
+) -> Result<Option<String>, EngineError> {
+    let root = workspace.check(Path::new(&root))?;
+    let path = context_path(&app, &root)?;
+    let read = app.clone();
+    let content = tokio::task::spawn_blocking(move || read.state::<ProjectContexts>().read(&path))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+    Ok(content.map(|c| c.to_string()))
+}
@@ -0,0 +1,188 @@
+use crate::cancel::CancelToken;
+use crate::claude::StreamSink;
+use crate::error::EngineError;
+use serde_json::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use tauri::{State, Window};
+use tokio::sync::oneshot;
+
+/// Messages waiting for their conversation's current turn to end
+#[derive(Default)]
+struct Lane {
+    active: Option<String>,
+    waiting: VecDeque<Waiter>,
+}
+
+struct Waiter {
+    request_id: String,
+    // true when it's this message's turn, false when it's been dropped
+    start: oneshot::Sender<bool>,
+}
+
+/// Managed per-conversation turn queue, so a message sent mid-stream waits for
+/// the stream ahead of it instead of racing it on the same events
+#[derive(Clone, Default)]
+pub struct TurnQueue {
+    lanes: Arc<Mutex<HashMap<String, Lane>>>,
+}
+
+/// One lane per conversation per window, or per other caller like the HTTP API
+pub fn lane_key(owner: &str, session: Option<&str>) -> String {
+    format!("{}:{}", owner, session.unwrap_or_default())
+}
+
+fn in_window(lane: &str, window: &str) -> bool {
+    lane.strip_prefix(window)
+        .is_some_and(|rest| rest.starts_with(':'))
+}
+
+enum Ticket {
+    Ready(TurnGuard),
+    /// 1-based place in line; the receiver says whether the turn came
+    Queued {
+        position: usize,
+        start: oneshot::Receiver<bool>,
+    },
+}
+
+impl TurnQueue {
+    /// Take the lane's turn if it's free, otherwise join the back of the line
+    fn enter(&self, lane: &str, request_id: &str) -> Ticket {
+        let Ok(mut lanes) = self.lanes.lock() else {
+            return Ticket::Ready(self.guard(lane));
+        };
+        let entry = lanes.entry(lane.to_string()).or_default();
+        if entry.active.is_none() {
+            entry.active = Some(request_id.to_string());
+            return Ticket::Ready(self.guard(lane));
+        }
+        let (start, receiver) = oneshot::channel();
+        entry.waiting.push_back(Waiter {
+            request_id: request_id.to_string(),
+            start,
+        });
+        Ticket::Queued {
+            position: entry.waiting.len(),
+            start: receiver,
+        }
+    }
+
+    fn guard(&self, lane: &str) -> TurnGuard {
+        TurnGuard {
+            lane: lane.to_string(),
+            queue: self.clone(),
+        }
+    }
+
+    /// Wait for the lane's turn, announcing `claude-queued` with the place in
+    /// line if there is one. A message dropped or cancelled while it waits gets
+    /// `claude-queue-dropped` and never runs.
+    pub async fn wait_turn(
+        &self,
+        lane: &str,
+        request_id: &str,
+        sink: &dyn StreamSink,
+        cancel: &CancelToken,
+    ) -> Result<TurnGuard, EngineError> {
+        let (position, start) = match self.enter(lane, request_id) {
+            Ticket::Ready(turn) => return Ok(turn),
+            Ticket::Queued { position, start } => (position, start),
+        };
+        let _ = sink.emit("claude-queued", json!({ "position": position }));
+        let started = start.await.unwrap_or(false);
+        // A turn that's handed over and then refused passes straight on
+        match started.then(|| self.guard(lane)) {
+            Some(turn) if !cancel.is_cancelled() => Ok(turn),
+            _ => {
+                let _ = sink.emit("claude-queue-dropped", Value::Null);
+                Err(EngineError::Cancelled)
+            }
+        }
+    }
+
+    /// Hand the lane to the next message still waiting for it
+    fn advance(&self, lane: &str) {
+        let Ok(mut lanes) = self.lanes.lock() else {
+            return;
+        };
+        let Some(entry) = lanes.get_mut(lane) else {
+            return;
+        };
+        entry.active = None;
+        while let Some(next) = entry.waiting.pop_front() {
+            // A waiter whose command has gone away can't take the turn
+            if next.start.send(true).is_ok() {
+                entry.active = Some(next.request_id);
+                return;
+            }
+        }
+        lanes.remove(lane);
+    }
+
+    /// Drop every message waiting in lanes matching `matches`; returns how many
+    fn flush(&self, matches: impl Fn(&str, &Lane) -> bool) -> usize {
+        let Ok(mut lanes) = self.lanes.lock() else {
+            return 0;
+        };
+        let mut dropped = 0;
+        for (key, lane) in lanes.iter_mut() {
+            if matches(key, lane) {
+                for waiter in lane.waiting.drain(..) {
+                    dropped += usize::from(waiter.start.send(false).is_ok());
+                }
+            }
+        }
+        lanes.retain(|_, lane| lane.active.is_some() || !lane.waiting.is_empty());
+        dropped
+    }
+
+    /// Drop one of `window`'s waiting messages; false if it isn't queued
+    pub fn remove(&self, window: &str, request_id: &str) -> bool {
+        let Ok(mut lanes) = self.lanes.lock() else {
+            return false;
+        };
+        for (_, lane) in lanes.iter_mut().filter(|(key, _)| in_window(key, window)) {
+            if let Some(at) = lane.waiting.iter().position(|w| w.request_id == request_id) {
+                if let Some(waiter) = lane.waiting.remove(at) {
+                    let _ = waiter.start.send(false);
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Drop whatever is queued behind `window`'s `request_id`, running or waiting
+    pub fn flush_behind(&self, window: &str, request_id: &str) -> usize {
+        self.flush(|key, lane| {
+            in_window(key, window)
+                && (lane.active.as_deref() == Some(request_id)
+                    || lane.waiting.iter().any(|w| w.request_id == request_id))
+        })
+    }
+}
+
+/// Holds the lane's turn; the next queued message starts when it drops
+pub struct TurnGuard {
+    lane: String,
+    queue: TurnQueue,
+}
+
+impl Drop for TurnGuard {
+    fn drop(&mut self) {
+        self.queue.advance(&self.lane);
+    }
+}
+
+/// Drop every message queued for `session_id` in this window, leaving the
+/// running turn alone; each gets `claude-queue-dropped`. Returns the count.
+#[tauri::command]
+pub async fn clear_queue(
+    window: Window,
+    session_id: Option<String>,
+    queue: State<'_, TurnQueue>,
+) -> Result<usize, EngineError> {
+    let key = lane_key(window.label(), session_id.as_deref());
+    Ok(queue.flush(|lane, _| lane == key))
+}
@@ -0,0 +1,107 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::{self, StreamSink};
+use crate::clock::now_millis;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::hash::random_hex;
+use crate::history::{self, Revision};
+use crate::session::SessionPool;
+use crate::settings;
+use crate::usage_ledger;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use tauri::{Manager, State, Window};
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct RegenerateOptions {
+    /// Use this model instead of the one the reply came from
+    pub model_override: Option<String>,
+    /// For `cancel_request`; one is generated otherwise
+    pub request_id: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RegenerateResult {
+    pub request_id: String,
+    pub message_id: String,
+    pub revision: Revision,
+}
+
+/// Answer the turn before `assistant_message_id` again and keep the new reply
+/// as a revision of the old one, rather than appending a new turn. Streams the
+/// usual `claude-stream-*` events.
+#[tauri::command]
+pub async fn regenerate_message(
+    window: Window,
+    conversation_id: String,
+    assistant_message_id: String,
+    options: Option<RegenerateOptions>,
+    registry: State<'_, CancelRegistry>,
+) -> Result<RegenerateResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let app = window.app_handle();
+    let conversation = history::load(&app, &conversation_id)?;
+    let at = conversation
+        .messages
+        .iter()
+        .position(|m| m.id == assistant_message_id && m.role == "assistant")
+        .ok_or_else(|| {
+            EngineError::invalid(format!("No assistant message {}", assistant_message_id))
+        })?;
+    let user_at = conversation.messages[..at]
+        .iter()
+        .rposition(|m| m.role == "user")
+        .ok_or_else(|| EngineError::invalid("No user message precedes this reply"))?;
+    let original = &conversation.messages[at];
+    let model = options.model_override.or_else(|| original.model.clone());
+    // Only what came before the turn; the superseded reply must not leak in
+    let prompt = history::with_transcript(
+        &conversation.messages[..user_at],
+        &conversation.messages[user_at].content,
+    );
+
+    // The warm process has the old reply in its context
+    app.state::<SessionPool>().close(&conversation_id);
+    let mut spawn = env_profile::for_claude(&app);
+    spawn.model = model.clone();
+    let sink = claude::window_sink(Arc::new(window.clone()), &settings::load(&app).streaming);
+    let request_id = options
+        .request_id
+        .unwrap_or_else(|| format!("regenerate-{}", random_hex(6)));
+    spawn.request_id = Some(request_id.clone());
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+    let result = app
+        .state::<ActiveBackend>()
+        .stream(sink as Arc<dyn StreamSink>, prompt, token.clone(), spawn)
+        .await;
+    drop(guard);
+    let reply = match result {
+        Ok(reply) => reply,
+        Err(_) if token.is_cancelled() => return Err(EngineError::Cancelled),
+        Err(e) => return Err(e),
+    };
+    usage_ledger::record(&app, model.as_deref(), &reply);
+
+    let revision = Revision {
+        id: format!("rev-{}", random_hex(8)),
+        content: reply.text,
+        model,
+        created_at: now_millis(),
+        cost_usd: reply.usage.as_ref().and_then(|u| u.cost_usd),
+        usage: reply.usage.and_then(|u| serde_json::to_value(u).ok()),
+    };
+    history::add_revision(
+        &app,
+        &conversation_id,
+        &assistant_message_id,
+        revision.clone(),
+    )?;
+    Ok(RegenerateResult {
+        request_id,
+        message_id: assistant_message_id,
+        revision,
+    })
+}
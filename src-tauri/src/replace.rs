@@ -0,0 +1,444 @@
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::context::{build_globset, relative, DEFAULT_SKIP_DIRS};
+use crate::error::EngineError;
+use crate::files::{looks_binary, write_files_atomic};
+use crate::hash::{random_hex, sha256_hex};
+use crate::sandbox::Workspace;
+use crate::walker::{walk_files, WalkConfig};
+use regex::{Captures, NoExpand, Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+
+const MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+const MAX_PREVIEW_CHARS: usize = 400;
+// Previews are cheap to recompute; only the most recent few can be applied by token
+const MAX_PLANS: usize = 16;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReplaceQuery {
+    pub query: String,
+    /// `$1` / `${name}` refer to capture groups in regex mode; literal otherwise
+    pub replacement: String,
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    /// Caps the preview, and how many edits one apply may make
+    pub max_matches: usize,
+}
+
+impl Default for ReplaceQuery {
+    fn default() -> Self {
+        Self {
+            query: String::new(),
+            replacement: String::new(),
+            regex: false,
+            case_sensitive: true,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            max_matches: 2_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct MatchPreview {
+    /// 1-based
+    pub line: usize,
+    pub column: usize,
+    pub before: String,
+    pub after: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileMatches {
+    pub path: String,
+    /// Pass back to `apply_replace`; the file is refused if it changes meanwhile
+    pub hash: String,
+    pub matches: Vec<MatchPreview>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplacePreview {
+    pub plan_token: String,
+    pub root: String,
+    pub files: Vec<FileMatches>,
+    pub total_matches: usize,
+    /// More matches exist than `max_matches`; applying would be refused
+    pub truncated: bool,
+    pub skipped_binary: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplacedFile {
+    pub path: String,
+    pub edits: usize,
+    pub backup_path: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceConflict {
+    pub path: String,
+    pub expected: String,
+    /// `None` when the file is gone or unreadable
+    pub actual: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ReplaceResult {
+    pub changed: Vec<ReplacedFile>,
+    /// Changed since the preview; left untouched
+    pub conflicts: Vec<ReplaceConflict>,
+    pub total_edits: usize,
+}
+
+struct Plan {
+    token: String,
+    root: PathBuf,
+    query: ReplaceQuery,
+    hashes: BTreeMap<String, String>,
+}
+
+/// Recent previews, so the frontend can apply one by token
+#[derive(Default)]
+pub struct ReplacePlans {
+    plans: Mutex<VecDeque<Plan>>,
+}
+
+impl ReplacePlans {
+    fn insert(&self, plan: Plan) {
+        if let Ok(mut plans) = self.plans.lock() {
+            if plans.len() >= MAX_PLANS {
+                plans.pop_front();
+            }
+            plans.push_back(plan);
+        }
+    }
+
+    fn take(&self, token: &str) -> Option<Plan> {
+        let mut plans = self.plans.lock().ok()?;
+        let at = plans.iter().position(|p| p.token == token)?;
+        plans.remove(at)
+    }
+}
+
+struct Matcher {
+    regex: Regex,
+    replacement: String,
+    expand: bool,
+}
+
+impl Matcher {
+    fn new(query: &ReplaceQuery) -> Result<Self, EngineError> {
+        if query.query.is_empty() {
+            return Err(EngineError::invalid("Search text is empty"));
+        }
+        let pattern = if query.regex {
+            query.query.clone()
+        } else {
+            regex::escape(&query.query)
+        };
+        let regex = RegexBuilder::new(&pattern)
+            .case_insensitive(!query.case_sensitive)
+            .multi_line(true)
+            .build()
+            .map_err(|e| EngineError::invalid(format!("Bad pattern: {}", e)))?;
+        Ok(Self {
+            regex,
+            replacement: query.replacement.clone(),
+            expand: query.regex,
+        })
+    }
+
+    fn replacement_for(&self, caps: &Captures) -> String {
+        if !self.expand {
+            return self.replacement.clone();
+        }
+        let mut out = String::new();
+        caps.expand(&self.replacement, &mut out);
+        out
+    }
+
+    fn replace_all(&self, text: &str) -> (String, usize) {
+        let count = self.regex.find_iter(text).count();
+        let replaced = if self.expand {
+            self.regex.replace_all(text, self.replacement.as_str())
+        } else {
+            self.regex.replace_all(text, NoExpand(&self.replacement))
+        };
+        (replaced.into_owned(), count)
+    }
+}
+
+fn clip(text: &str) -> String {
+    match text.char_indices().nth(MAX_PREVIEW_CHARS) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text.to_string(),
+    }
+}
+
+/// Each match's line before and after replacing just that match
+fn previews(text: &str, matcher: &Matcher, limit: usize) -> (Vec<MatchPreview>, usize) {
+    let mut previews = Vec::new();
+    let mut count = 0;
+    let mut line = 1;
+    let mut counted_to = 0;
+    for caps in matcher.regex.captures_iter(text) {
+        count += 1;
+        if previews.len() >= limit {
+            continue;
+        }
+        let Some(m) = caps.get(0) else {
+            continue;
+        };
+        line += text[counted_to..m.start()].matches('\n').count();
+        counted_to = m.start();
+        let line_start = text[..m.start()].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = text[m.end()..]
+            .find('\n')
+            .map_or(text.len(), |i| m.end() + i);
+        let before = &text[line_start..line_end];
+        let after = format!(
+            "{}{}{}",
+            &text[line_start..m.start()],
+            matcher.replacement_for(&caps),
+            &text[m.end()..line_end]
+        );
+        previews.push(MatchPreview {
+            line,
+            column: text[line_start..m.start()].chars().count() + 1,
+            before: clip(before.trim_end_matches('\r')),
+            after: clip(after.trim_end_matches('\r')),
+        });
+    }
+    (previews, count)
+}
+
+enum Scanned {
+    Matches {
+        hash: String,
+        matches: Vec<MatchPreview>,
+        count: usize,
+    },
+    Binary,
+}
+
+fn scan(
+    root: &Path,
+    query: &ReplaceQuery,
+    cancel: Option<&CancelToken>,
+) -> Result<Vec<(String, Scanned)>, EngineError> {
+    let matcher = Matcher::new(query)?;
+    let include = build_globset(&query.include_globs)?;
+    let exclude = build_globset(&query.exclude_globs)?;
+    let config = WalkConfig {
+        max_filesize: Some(MAX_FILE_BYTES),
+        cancel,
+        ..WalkConfig::default()
+    };
+    let descend = |entry: &ignore::DirEntry| {
+        !DEFAULT_SKIP_DIRS.contains(&entry.file_name().to_string_lossy().as_ref())
+    };
+    let found = walk_files(root, config, descend, |entry| {
+        let rel = relative(root, entry.path());
+        if include.as_ref().is_some_and(|set| !set.is_match(&rel))
+            || exclude.as_ref().is_some_and(|set| set.is_match(&rel))
+        {
+            return None;
+        }
+        let bytes = std::fs::read(entry.path()).ok()?;
+        if looks_binary(&bytes) {
+            return Some(Scanned::Binary);
+        }
+        let Ok(text) = std::str::from_utf8(&bytes) else {
+            return Some(Scanned::Binary);
+        };
+        let (matches, count) = previews(text, &matcher, query.max_matches);
+        (count > 0).then(|| Scanned::Matches {
+            hash: sha256_hex(&bytes),
+            matches,
+            count,
+        })
+    })?;
+    Ok(found
+        .into_iter()
+        .map(|(path, scanned)| (relative(root, &path), scanned))
+        .collect())
+}
+
+/// Find every match under `root` and show each line before and after, without
+/// writing anything
+#[tauri::command]
+pub async fn preview_replace(
+    app: AppHandle,
+    root: String,
+    query: ReplaceQuery,
+    request_id: Option<String>,
+    workspace: State<'_, Workspace>,
+    plans: State<'_, ReplacePlans>,
+) -> Result<ReplacePreview, EngineError> {
+    let root = workspace.check(Path::new(&root))?;
+    let guard = request_id
+        .as_deref()
+        .map(|id| app.state::<CancelRegistry>().register(id));
+    let token = guard.as_ref().map(|g| g.token());
+    let (scan_root, scan_query) = (root.clone(), query.clone());
+    let scanned =
+        tokio::task::spawn_blocking(move || scan(&scan_root, &scan_query, token.as_ref()))
+            .await
+            .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+
+    let mut files = Vec::new();
+    let mut hashes = BTreeMap::new();
+    let mut total_matches = 0;
+    let mut skipped_binary = 0;
+    let mut shown = 0;
+    for (path, scanned) in scanned {
+        let (hash, mut matches, count) = match scanned {
+            Scanned::Binary => {
+                skipped_binary += 1;
+                continue;
+            }
+            Scanned::Matches {
+                hash,
+                matches,
+                count,
+            } => (hash, matches, count),
+        };
+        total_matches += count;
+        if shown >= query.max_matches {
+            continue;
+        }
+        matches.truncate(query.max_matches - shown);
+        shown += matches.len();
+        hashes.insert(path.clone(), hash.clone());
+        files.push(FileMatches {
+            path,
+            hash,
+            matches,
+        });
+    }
+
+    let plan_token = random_hex(16);
+    plans.insert(Plan {
+        token: plan_token.clone(),
+        root: root.clone(),
+        query: query.clone(),
+        hashes,
+    });
+    Ok(ReplacePreview {
+        plan_token,
+        root: root.display().to_string(),
+        files,
+        truncated: total_matches > query.max_matches,
+        total_matches,
+        skipped_binary,
+    })
+}
+
+fn apply(
+    targets: Vec<(String, PathBuf, String)>,
+    query: &ReplaceQuery,
+    cancel: Option<&CancelToken>,
+) -> Result<ReplaceResult, EngineError> {
+    let matcher = Matcher::new(query)?;
+    let mut writes = Vec::new();
+    let mut edited = Vec::new();
+    let mut conflicts = Vec::new();
+    let mut total_edits = 0;
+    for (rel, path, expected) in targets {
+        if cancel.is_some_and(|c| c.is_cancelled()) {
+            return Err(EngineError::Cancelled);
+        }
+        let bytes = std::fs::read(&path).ok();
+        let actual = bytes.as_deref().map(sha256_hex);
+        let text = match bytes.map(String::from_utf8) {
+            Some(Ok(text)) if actual.as_deref() == Some(expected.as_str()) => text,
+            _ => {
+                conflicts.push(ReplaceConflict {
+                    path: rel,
+                    expected,
+                    actual,
+                });
+                continue;
+            }
+        };
+        let (replaced, edits) = matcher.replace_all(&text);
+        if edits == 0 || replaced == text {
+            continue;
+        }
+        total_edits += edits;
+        if total_edits > query.max_matches {
+            return Err(EngineError::invalid(format!(
+                "More than {} edits; narrow the search or raise max_matches",
+                query.max_matches
+            )));
+        }
+        writes.push((path, replaced.into_bytes()));
+        edited.push((rel, edits));
+    }
+
+    // Nothing is written until every file has been checked
+    let backups = write_files_atomic(&writes)
+        .map_err(|e| EngineError::internal(format!("Failed to apply replacements: {}", e)))?;
+    Ok(ReplaceResult {
+        changed: edited
+            .into_iter()
+            .zip(backups)
+            .map(|((path, edits), backup)| ReplacedFile {
+                path,
+                edits,
+                backup_path: backup.map(|p| p.display().to_string()),
+            })
+            .collect(),
+        conflicts,
+        total_edits,
+    })
+}
+
+/// Apply a preview, by `plan_token` or by repeating its root, query and file
+/// hashes. All files change together or none do; files edited since the
+/// preview are skipped and reported as conflicts.
+#[tauri::command]
+pub async fn apply_replace(
+    app: AppHandle,
+    plan_token: Option<String>,
+    root: Option<String>,
+    query: Option<ReplaceQuery>,
+    expected_file_hashes: Option<BTreeMap<String, String>>,
+    request_id: Option<String>,
+    workspace: State<'_, Workspace>,
+) -> Result<ReplaceResult, EngineError> {
+    let (root, query, hashes) = match (plan_token, root, query, expected_file_hashes) {
+        (Some(token), ..) => {
+            let plan = app
+                .state::<ReplacePlans>()
+                .take(&token)
+                .ok_or_else(|| EngineError::invalid("Unknown or expired plan; preview again"))?;
+            (plan.root, plan.query, plan.hashes)
+        }
+        (None, Some(root), Some(query), Some(hashes)) => (PathBuf::from(root), query, hashes),
+        _ => {
+            return Err(EngineError::invalid(
+                "Pass a plan_token, or root, query and expected_file_hashes",
+            ))
+        }
+    };
+    let root = workspace.check(&root)?;
+    let targets = hashes
+        .into_iter()
+        .map(|(rel, hash)| Ok((rel.clone(), workspace.check(&root.join(&rel))?, hash)))
+        .collect::<Result<Vec<_>, EngineError>>()?;
+
+    let guard = request_id
+        .as_deref()
+        .map(|id| app.state::<CancelRegistry>().register(id));
+    let token = guard.as_ref().map(|g| g.token());
+    tokio::task::spawn_blocking(move || apply(targets, &query, token.as_ref()))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
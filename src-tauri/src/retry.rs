@@ -0,0 +1,226 @@
+use crate::cancel::CancelToken;
+use crate::claude::StreamSink;
+use crate::error::EngineError;
+use crate::settings::EngineConfig;
+use serde_json::{json, Value};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const CANCEL_POLL: Duration = Duration::from_millis(100);
+
+// Load or a dropped connection, as the CLI and the API word it
+const TRANSIENT_SIGNS: &[&str] = &[
+    "overloaded",
+    "rate_limit",
+    "rate limit",
+    "api error: 429",
+    "api error: 500",
+    "api error: 502",
+    "api error: 503",
+    "api error: 529",
+    "econnreset",
+    "connection reset",
+    "etimedout",
+    "socket hang up",
+    "eai_again",
+    "temporarily unavailable",
+];
+
+// These don't clear up by themselves, whatever else the message says
+const PERMANENT_SIGNS: &[&str] = &[
+    "api error: 401",
+    "api error: 403",
+    "authentication",
+    "unauthorized",
+    "invalid api key",
+    "invalid x-api-key",
+    "unknown option",
+];
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    /// Retries after the first attempt
+    pub max_retries: u32,
+    pub max_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn from_config(config: &EngineConfig) -> Self {
+        Self {
+            max_retries: config.max_retries,
+            max_delay: Duration::from_secs(config.max_retry_delay_secs),
+        }
+    }
+}
+
+fn transient_text(text: &str) -> bool {
+    let text = text.to_ascii_lowercase();
+    !PERMANENT_SIGNS.iter().any(|sign| text.contains(sign))
+        && TRANSIENT_SIGNS.iter().any(|sign| text.contains(sign))
+}
+
+/// Whether trying again could go differently. Cancellation, bad input and
+/// anything that looks like an auth problem never are.
+pub fn is_transient(error: &EngineError) -> bool {
+    match error {
+        EngineError::CliExited { stderr, .. } => transient_text(stderr),
+        // EAGAIN when the system is briefly out of processes
+        EngineError::SpawnFailed { detail } | EngineError::Internal { detail } => {
+            transient_text(detail)
+        }
+        EngineError::ApiFailed {
+            status: Some(code), ..
+        } => matches!(code, 429 | 500 | 502 | 503 | 504 | 529),
+        EngineError::ApiFailed {
+            status: None,
+            detail,
+            ..
+        } => transient_text(detail),
+        EngineError::Network { .. } => true,
+        _ => false,
+    }
+}
+
+/// Doubles per retry up to the cap, then somewhere in its upper half so
+/// clients that failed together don't retry together
+fn backoff(retry: u32, max_delay: Duration) -> Duration {
+    let full = BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+        .min(max_delay);
+    let mut seed = [0u8; 8];
+    let fraction = match getrandom::fill(&mut seed) {
+        Ok(()) => u64::from_le_bytes(seed) as f64 / u64::MAX as f64,
+        Err(_) => 0.5,
+    };
+    full.mul_f64(0.5 + fraction / 2.0)
+}
+
+/// Sleep for `delay`, or until the request is cancelled
+async fn wait(delay: Duration, cancel: &CancelToken) -> Result<(), EngineError> {
+    let deadline = Instant::now() + delay;
+    while Instant::now() < deadline {
+        if cancel.is_cancelled() {
+            return Err(EngineError::Cancelled);
+        }
+        tokio::time::sleep(CANCEL_POLL.min(deadline - Instant::now())).await;
+    }
+    Ok(())
+}
+
+fn retrying(retry: u32, policy: &RetryPolicy, delay: Duration, error: &EngineError) -> Value {
+    json!({
+        "attempt": retry,
+        "max_retries": policy.max_retries,
+        "delay_ms": delay.as_millis() as u64,
+        "error": error.to_string(),
+    })
+}
+
+/// Run `attempt` until it succeeds, fails for good or runs out of retries.
+/// Each retry is passed to `announce` as a `claude-retrying` payload first.
+pub async fn retry<T, Fut>(
+    policy: &RetryPolicy,
+    cancel: &CancelToken,
+    announce: impl Fn(Value),
+    mut attempt: impl FnMut() -> Fut,
+) -> Result<T, EngineError>
+where
+    Fut: Future<Output = Result<T, EngineError>>,
+{
+    let mut retries = 0;
+    loop {
+        let error = match attempt().await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if retries >= policy.max_retries || !is_transient(&error) || cancel.is_cancelled() {
+            return Err(error);
+        }
+        retries += 1;
+        let delay = backoff(retries, policy.max_delay);
+        announce(retrying(retries, policy, delay, &error));
+        wait(delay, cancel).await?;
+    }
+}
+
+/// Notes whether a stream got as far as output, and holds back its
+/// `claude-stream-error` until it's clear there won't be a retry
+struct StartSink {
+    inner: Arc<dyn StreamSink>,
+    started: AtomicBool,
+    held_error: Mutex<Option<Value>>,
+}
+
+impl StartSink {
+    fn started(&self) -> bool {
+        self.started.load(Ordering::SeqCst)
+    }
+
+    fn release(&self) {
+        let held = self.held_error.lock().ok().and_then(|mut e| e.take());
+        if let Some(payload) = held {
+            let _ = self.inner.emit("claude-stream-error", payload);
+        }
+    }
+}
+
+impl StreamSink for StartSink {
+    fn emit(&self, event: &str, payload: Value) -> Result<(), String> {
+        match event {
+            "claude-stream-error" if !self.started() => {
+                if let Ok(mut held) = self.held_error.lock() {
+                    *held = Some(payload);
+                }
+                return Ok(());
+            }
+            "claude-stream-text" | "claude-stream-chunk" | "claude-stream-tool-use" => {
+                self.started.store(true, Ordering::SeqCst);
+            }
+            _ => {}
+        }
+        self.inner.emit(event, payload)
+    }
+}
+
+/// [`retry`] for a stream: only while nothing has reached the sink, so a
+/// retry can't repeat text the user already saw. Retries go out on the sink.
+pub async fn retry_stream<T, Fut>(
+    policy: &RetryPolicy,
+    cancel: &CancelToken,
+    sink: Arc<dyn StreamSink>,
+    mut attempt: impl FnMut(Arc<dyn StreamSink>) -> Fut,
+) -> Result<T, EngineError>
+where
+    Fut: Future<Output = Result<T, EngineError>>,
+{
+    let mut retries = 0;
+    loop {
+        let start = Arc::new(StartSink {
+            inner: sink.clone(),
+            started: AtomicBool::new(false),
+            held_error: Mutex::new(None),
+        });
+        let error = match attempt(start.clone()).await {
+            Ok(value) => return Ok(value),
+            Err(error) => error,
+        };
+        if start.started()
+            || retries >= policy.max_retries
+            || !is_transient(&error)
+            || cancel.is_cancelled()
+        {
+            start.release();
+            return Err(error);
+        }
+        retries += 1;
+        let delay = backoff(retries, policy.max_delay);
+        let _ = sink.emit("claude-retrying", retrying(retries, policy, delay, &error));
+        if let Err(cancelled) = wait(delay, cancel).await {
+            let _ = sink.emit("claude-stream-cancelled", Value::Null);
+            return Err(cancelled);
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use crate::cancel::CancelToken;
+use crate::claude::StreamSink;
+use crate::env_profile::ChildEnv;
+use crate::error::EngineError;
+use crate::proctree;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWriteExt, BufReader};
+use tokio::sync::mpsc;
+
+#[cfg(windows)]
+const CREATE_NO_WINDOW: u32 = 0x08000000;
+
+const TAIL_LINES: usize = 200;
+const CANCEL_POLL: Duration = Duration::from_millis(200);
+
+/// A process to spawn inside a workspace
+pub struct RunSpec {
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    pub cwd: PathBuf,
+    pub env: ChildEnv,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+pub struct RunOutput {
+    pub exit_code: Option<i32>,
+    /// Last lines of stdout and stderr interleaved as they arrived
+    pub tail: Vec<String>,
+    pub duration_ms: u64,
+    /// Output went past the cap and stopped being emitted
+    pub truncated: bool,
+}
+
+pub struct CapturedOutput {
+    pub exit_code: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+}
+
+/// `cargo test -- --nocapture` / `"a b" c` into words; no shell expansion
+pub fn split_command(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut current = String::new();
+    let mut quote = None;
+    let mut in_word = false;
+    for c in command.chars() {
+        match (quote, c) {
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_word = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_word {
+                    words.push(std::mem::take(&mut current));
+                    in_word = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_word = true;
+            }
+        }
+    }
+    if in_word {
+        words.push(current);
+    }
+    words
+}
+
+#[cfg(windows)]
+fn candidates(dir: &Path, name: &str) -> Vec<PathBuf> {
+    if Path::new(name).extension().is_some() {
+        return vec![dir.join(name)];
+    }
+    // npm, yarn and friends are .cmd shims that CreateProcess won't find on its own
+    let exts = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    exts.split(';')
+        .filter(|e| !e.is_empty())
+        .map(|ext| dir.join(format!("{}{}", name, ext.to_ascii_lowercase())))
+        .collect()
+}
+
+#[cfg(not(windows))]
+fn candidates(dir: &Path, name: &str) -> Vec<PathBuf> {
+    vec![dir.join(name)]
+}
+
+/// Resolve a program name against `dirs` first, then PATH
+pub fn find_program(name: &str, dirs: &[PathBuf]) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.components().count() > 1 {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+    let path_dirs = std::env::var_os("PATH")
+        .map(|p| std::env::split_paths(&p).collect::<Vec<_>>())
+        .unwrap_or_default();
+    dirs.iter()
+        .chain(path_dirs.iter())
+        .flat_map(|dir| candidates(dir, name))
+        .find(|candidate| candidate.is_file())
+}
+
+async fn forward_lines<R: AsyncRead + Unpin>(
+    reader: R,
+    stream: OutputStream,
+    tx: mpsc::Sender<(OutputStream, String)>,
+) {
+    let mut reader = BufReader::new(reader);
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        match reader.read_until(b'\n', &mut buf).await {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {
+                let line = String::from_utf8_lossy(&buf)
+                    .trim_end_matches(['\n', '\r'])
+                    .to_string();
+                if tx.send((stream, line)).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// The configured command, output piped and killed if dropped
+pub fn command(spec: &RunSpec) -> tokio::process::Command {
+    let mut cmd = tokio::process::Command::new(&spec.program);
+    cmd.args(&spec.args)
+        .current_dir(&spec.cwd)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    spec.env.apply_tokio(&mut cmd);
+    #[cfg(windows)]
+    cmd.creation_flags(CREATE_NO_WINDOW);
+    cmd
+}
+
+fn spawn_error(spec: &RunSpec, e: std::io::Error) -> EngineError {
+    EngineError::internal(format!("Failed to spawn {}: {}", spec.program.display(), e))
+}
+
+/// [`proctree::kill_tree`] for a tokio child, waiting out the grace period
+/// without blocking the runtime
+async fn kill_tree(child: &mut tokio::process::Child) {
+    if let Some(pid) = child.id() {
+        proctree::terminate_tree(pid);
+        let _ = tokio::time::timeout(proctree::TERM_GRACE, child.wait()).await;
+        proctree::force_tree(pid);
+    }
+    let _ = child.kill().await;
+}
+
+/// Spawn `spec`, streaming each output line as `command-output { id, stream, line }`
+/// and finishing with `command-exit { id, code, truncated }`. Past `max_output`
+/// bytes lines stop being emitted, though the tail still keeps up.
+/// Cancellation and timeout kill the child and everything it started.
+pub async fn run_streamed(
+    spec: RunSpec,
+    id: &str,
+    sink: Option<&dyn StreamSink>,
+    cancel: &CancelToken,
+    max_output: Option<usize>,
+) -> Result<RunOutput, EngineError> {
+    let started = Instant::now();
+    let mut cmd = command(&spec);
+    // Its own group, so a kill reaches what it spawns, e.g. cargo's rustc
+    #[cfg(unix)]
+    cmd.process_group(0);
+    let mut child = cmd
+        .stdin(Stdio::null())
+        .spawn()
+        .map_err(|e| spawn_error(&spec, e))?;
+    let _tracked = child.id().map(proctree::Tracked::new);
+
+    let (tx, mut rx) = mpsc::channel(256);
+    if let Some(stdout) = child.stdout.take() {
+        tokio::spawn(forward_lines(stdout, OutputStream::Stdout, tx.clone()));
+    }
+    if let Some(stderr) = child.stderr.take() {
+        tokio::spawn(forward_lines(stderr, OutputStream::Stderr, tx.clone()));
+    }
+    drop(tx);
+
+    let mut tail = VecDeque::with_capacity(TAIL_LINES);
+    let mut emitted = 0usize;
+    let mut truncated = false;
+    loop {
+        if cancel.is_cancelled() {
+            kill_tree(&mut child).await;
+            return Err(EngineError::Cancelled);
+        }
+        if let Some(limit) = spec.timeout {
+            if started.elapsed() >= limit {
+                kill_tree(&mut child).await;
+                return Err(EngineError::Timeout {
+                    after_ms: limit.as_millis() as u64,
+                });
+            }
+        }
+        match tokio::time::timeout(CANCEL_POLL, rx.recv()).await {
+            Ok(Some((stream, line))) => {
+                emitted = emitted.saturating_add(line.len() + 1);
+                truncated |= max_output.is_some_and(|cap| emitted > cap);
+                if let Some(sink) = sink.filter(|_| !truncated) {
+                    let _ = sink.emit(
+                        "command-output",
+                        json!({ "id": id, "stream": stream, "line": line }),
+                    );
+                }
+                if tail.len() == TAIL_LINES {
+                    tail.pop_front();
+                }
+                tail.push_back(line);
+            }
+            // Both pipes closed
+            Ok(None) => break,
+            Err(_) => continue,
+        }
+    }
+
+    let status = child
+        .wait()
+        .await
+        .map_err(|e| EngineError::internal(format!("Failed to wait for process: {}", e)))?;
+    let exit_code = status.code();
+    if let Some(sink) = sink {
+        let _ = sink.emit(
+            "command-exit",
+            json!({ "id": id, "code": exit_code, "truncated": truncated }),
+        );
+    }
+    Ok(RunOutput {
+        exit_code,
+        tail: tail.into(),
+        duration_ms: started.elapsed().as_millis() as u64,
+        truncated,
+    })
+}
+
+/// Feed `input` on stdin and collect all output, e.g. for formatters.
+/// The child is killed if it outlives the spec's timeout.
+pub async fn run_captured(spec: RunSpec, input: Vec<u8>) -> Result<CapturedOutput, EngineError> {
+    let mut child = command(&spec)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| spawn_error(&spec, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // Written concurrently so a child that fills its stdout pipe can't deadlock us
+        tokio::spawn(async move {
+            let _ = stdin.write_all(&input).await;
+        });
+    }
+    let wait = child.wait_with_output();
+    let output = match spec.timeout {
+        Some(limit) => {
+            tokio::time::timeout(limit, wait)
+                .await
+                .map_err(|_| EngineError::Timeout {
+                    after_ms: limit.as_millis() as u64,
+                })?
+        }
+        None => wait.await,
+    }
+    .map_err(|e| EngineError::internal(format!("Failed to wait for process: {}", e)))?;
+    Ok(CapturedOutput {
+        exit_code: output.status.code(),
+        stdout: output.stdout,
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    })
+}
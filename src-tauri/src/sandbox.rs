@@ -0,0 +1,264 @@
+use crate::error::EngineError;
+use crate::index::IndexRegistry;
+use crate::metrics::{self, Stage};
+use crate::paths::canonicalize;
+use crate::settings;
+use std::path::{Component, Path, PathBuf};
+use std::sync::RwLock;
+use tauri::{AppHandle, Manager};
+
+/// Managed set of workspace roots that filesystem commands may touch
+#[derive(Default)]
+pub struct Workspace {
+    roots: RwLock<Vec<PathBuf>>,
+}
+
+// Past this a chain of links is taken for a loop
+const MAX_LINK_HOPS: usize = 40;
+
+/// Canonicalize the deepest existing ancestor and re-append the rest, so paths
+/// that don't exist yet (write targets) resolve too. `..` in the missing part is rejected.
+/// A dangling symlink is followed to where it points, since that's where a write lands.
+/// Verbatim prefixes are stripped so `\\?\UNC\nas\x` and `\\nas\x` compare equal.
+fn resolve(path: &Path) -> Result<PathBuf, EngineError> {
+    if !path.is_absolute() {
+        return Err(EngineError::invalid(format!(
+            "{} is not an absolute path",
+            path.display()
+        )));
+    }
+    let mut existing = path.to_path_buf();
+    let mut rest = Vec::new();
+    let mut hops = 0;
+    loop {
+        match canonicalize(&existing) {
+            Ok(canonical) => {
+                let mut resolved = canonical;
+                for part in rest.iter().rev() {
+                    resolved.push(part);
+                }
+                return Ok(resolved);
+            }
+            Err(e) => {
+                if let Ok(target) = std::fs::read_link(&existing) {
+                    hops += 1;
+                    if hops > MAX_LINK_HOPS {
+                        return Err(EngineError::io(path, "too many levels of symbolic links"));
+                    }
+                    existing = match existing.parent() {
+                        Some(parent) => parent.join(target),
+                        None => target,
+                    };
+                    continue;
+                }
+                let name = match existing.components().next_back() {
+                    Some(Component::Normal(name)) => name.to_os_string(),
+                    _ => return Err(EngineError::io(path, e)),
+                };
+                rest.push(name);
+                existing = existing
+                    .parent()
+                    .ok_or_else(|| EngineError::io(path, e))?
+                    .to_path_buf();
+            }
+        }
+    }
+}
+
+impl Workspace {
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.roots.read().map(|r| r.clone()).unwrap_or_default()
+    }
+
+    fn contain(&self, path: &Path, resolved: PathBuf) -> Result<PathBuf, EngineError> {
+        if self.roots().iter().any(|root| resolved.starts_with(root)) {
+            Ok(resolved)
+        } else {
+            Err(EngineError::PathOutsideWorkspace {
+                path: path.display().to_string(),
+            })
+        }
+    }
+
+    /// Resolved path if it lies under a workspace root
+    pub fn check(&self, path: &Path) -> Result<PathBuf, EngineError> {
+        let _timer = metrics::timer(Stage::SandboxCheck);
+        self.contain(path, resolve(path)?)
+    }
+
+    /// [`check`](Self::check) for the entry at `path` rather than what it points
+    /// to: only the parent is resolved, so deleting or renaming a symlink acts on
+    /// the link. A root itself is never an entry inside the workspace.
+    pub fn check_entry(&self, path: &Path) -> Result<PathBuf, EngineError> {
+        let _timer = metrics::timer(Stage::SandboxCheck);
+        let (Some(parent), Some(Component::Normal(name))) =
+            (path.parent(), path.components().next_back())
+        else {
+            return Err(EngineError::invalid(format!(
+                "{} does not name a file or directory",
+                path.display()
+            )));
+        };
+        let resolved = resolve(parent)?.join(name);
+        if self.roots().contains(&resolved) {
+            return Err(EngineError::PathOutsideWorkspace {
+                path: path.display().to_string(),
+            });
+        }
+        self.contain(path, resolved)
+    }
+}
+
+fn unrestricted(app: &AppHandle) -> bool {
+    settings::load(app).filesystem.unrestricted
+}
+
+/// Where a file command may read or write `path`: under a workspace root,
+/// unless `filesystem.unrestricted` is set
+pub fn allow(app: &AppHandle, path: &str) -> Result<PathBuf, EngineError> {
+    if unrestricted(app) {
+        return Ok(PathBuf::from(path));
+    }
+    app.state::<Workspace>().check(Path::new(path))
+}
+
+/// [`allow`] for commands that act on the entry itself, like delete and rename
+pub fn allow_entry(app: &AppHandle, path: &str) -> Result<PathBuf, EngineError> {
+    if unrestricted(app) {
+        return Ok(PathBuf::from(path));
+    }
+    app.state::<Workspace>().check_entry(Path::new(path))
+}
+
+fn root_dir(path: &str) -> Result<PathBuf, EngineError> {
+    let root = canonicalize(Path::new(path)).map_err(|e| EngineError::io(path, e))?;
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!("{} is not a directory", path)));
+    }
+    Ok(root)
+}
+
+fn use_roots(app: &AppHandle, roots: Vec<PathBuf>) -> Result<(), EngineError> {
+    let index = app.state::<IndexRegistry>();
+    index.retain(&roots);
+    for root in &roots {
+        index.ensure(app, root);
+    }
+    *app.state::<Workspace>()
+        .roots
+        .write()
+        .map_err(|_| EngineError::internal("Workspace lock is poisoned"))? = roots;
+    Ok(())
+}
+
+/// Bring back the roots saved by the last `set_workspace_roots`, leaving out
+/// any that are gone or no longer a directory
+pub fn restore(app: &AppHandle) -> Result<(), EngineError> {
+    let saved = settings::load(app).filesystem.workspace_roots;
+    let roots = saved
+        .iter()
+        .filter_map(|path| root_dir(path).ok())
+        .collect();
+    use_roots(app, roots)
+}
+
+/// Replace the workspace roots, saved in settings so they hold across restarts
+#[tauri::command]
+pub async fn set_workspace_roots(
+    app: tauri::AppHandle,
+    paths: Vec<String>,
+) -> Result<Vec<String>, EngineError> {
+    let roots = paths
+        .iter()
+        .map(|path| root_dir(path))
+        .collect::<Result<Vec<_>, _>>()?;
+    let shown: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    let mut config = settings::load(&app);
+    config.filesystem.workspace_roots = shown.clone();
+    settings::save(&app, &config)?;
+    use_roots(&app, roots)?;
+    Ok(shown)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bups-sandbox-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(dir.join("inside")).unwrap();
+        canonicalize(&dir).unwrap()
+    }
+
+    #[test]
+    fn only_paths_under_a_root_pass() {
+        let dir = scratch("check");
+        let workspace = Workspace {
+            roots: RwLock::new(vec![dir.join("inside")]),
+        };
+        let file = dir.join("inside").join("new.txt");
+        assert_eq!(workspace.check(&file).unwrap(), file);
+        assert!(matches!(
+            workspace.check(&dir.join("outside.txt")),
+            Err(EngineError::PathOutsideWorkspace { .. })
+        ));
+        assert!(workspace
+            .check(
+                &dir.join("inside")
+                    .join("missing")
+                    .join("..")
+                    .join("..")
+                    .join("x")
+            )
+            .is_err());
+        assert!(matches!(
+            workspace.check_entry(&dir.join("inside")),
+            Err(EngineError::PathOutsideWorkspace { .. })
+        ));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_out_of_a_root_are_followed_and_refused() {
+        use std::os::unix::fs::symlink;
+
+        let dir = scratch("links");
+        let inside = dir.join("inside");
+        std::fs::create_dir_all(dir.join("outside")).unwrap();
+        let workspace = Workspace {
+            roots: RwLock::new(vec![inside.clone()]),
+        };
+        // Nothing there yet, so only the link says where a write would go
+        symlink(
+            dir.join("outside").join("authorized_keys"),
+            inside.join("evil"),
+        )
+        .unwrap();
+        symlink("../outside/later", inside.join("relative")).unwrap();
+        symlink(dir.join("outside"), inside.join("out")).unwrap();
+        symlink(inside.join("missing"), inside.join("fine")).unwrap();
+        symlink(inside.join("loop"), inside.join("loop")).unwrap();
+        for refused in [
+            inside.join("evil"),
+            inside.join("relative"),
+            inside.join("out"),
+            inside.join("out").join("new.txt"),
+            inside.join("out").join("deep").join("new.txt"),
+            inside.join("loop"),
+        ] {
+            assert!(workspace.check(&refused).is_err(), "{}", refused.display());
+        }
+        assert_eq!(
+            workspace.check(&inside.join("fine")).unwrap(),
+            inside.join("missing")
+        );
+        // The link itself is inside, so it can still be deleted
+        assert_eq!(
+            workspace.check_entry(&inside.join("evil")).unwrap(),
+            inside.join("evil")
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,553 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::{error_text, NullSink};
+use crate::clock::now_millis;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::hash::random_hex;
+use crate::history;
+use crate::paths::app_data_subdir;
+use crate::sandbox::Workspace;
+use crate::startup::Startup;
+use crate::templates;
+use crate::webhooks;
+use chrono::{Local, TimeZone};
+use croner::Cron;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::api::notification::Notification;
+use tauri::{AppHandle, Manager, State};
+
+const SCHEDULES_FILE: &str = "schedules.json";
+const TICK: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Trigger {
+    /// Five-field cron in the machine's local time zone, e.g. `0 9 * * 1-5`
+    Cron {
+        expression: String,
+    },
+    Interval {
+        minutes: u64,
+    },
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScheduleInput {
+    /// Name of a stored prompt template
+    pub template: String,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    /// Root the CLI runs in, with that workspace's environment profile
+    #[serde(default)]
+    pub workspace: Option<String>,
+    pub cron_or_interval: Trigger,
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// Run once on launch if a fire time passed while the app was closed
+    #[serde(default)]
+    pub catch_up: bool,
+    /// Show an OS notification when a run finishes
+    #[serde(default)]
+    pub notify: bool,
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schedule {
+    pub id: String,
+    pub template: String,
+    #[serde(default)]
+    pub vars: BTreeMap<String, String>,
+    #[serde(default)]
+    pub workspace: Option<String>,
+    pub cron_or_interval: Trigger,
+    pub enabled: bool,
+    #[serde(default)]
+    pub catch_up: bool,
+    #[serde(default)]
+    pub notify: bool,
+    pub created_at: u64,
+    /// `None` when the trigger has no future occurrence
+    pub next_run_at: Option<u64>,
+    #[serde(default)]
+    pub last_run_at: Option<u64>,
+    #[serde(default)]
+    pub last_conversation_id: Option<String>,
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+#[derive(Default)]
+pub struct Scheduler {
+    schedules: Mutex<Vec<Schedule>>,
+    running: Mutex<HashSet<String>>,
+}
+
+/// First fire time strictly after `after` (Unix millis). Cron is evaluated in
+/// `zone`'s wall-clock time (the app passes `Local`): a time skipped by a DST jump
+/// fires at the first moment after the jump, and a repeated one fires once, the
+/// first time round.
+pub fn next_fire<Tz: TimeZone>(
+    trigger: &Trigger,
+    after: u64,
+    zone: &Tz,
+) -> Result<u64, EngineError> {
+    match trigger {
+        Trigger::Interval { minutes } => Ok(after + (*minutes).max(1) * 60_000),
+        Trigger::Cron { expression } => {
+            let cron = Cron::from_str(expression).map_err(|e| {
+                EngineError::invalid(format!("Invalid cron expression '{}': {}", expression, e))
+            })?;
+            let start = zone
+                .timestamp_millis_opt(after as i64)
+                .single()
+                .ok_or_else(|| EngineError::invalid("Start time is out of range"))?;
+            let next = cron.find_next_occurrence(&start, false).map_err(|e| {
+                EngineError::invalid(format!("No next run for '{}': {}", expression, e))
+            })?;
+            Ok(next.timestamp_millis().max(0) as u64)
+        }
+    }
+}
+
+fn schedules_path(app: &AppHandle) -> Result<PathBuf, EngineError> {
+    Ok(app_data_subdir(app, "scheduler")?.join(SCHEDULES_FILE))
+}
+
+fn load_from_disk(app: &AppHandle) -> Vec<Schedule> {
+    schedules_path(app)
+        .ok()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn persist(app: &AppHandle, schedules: &[Schedule]) -> Result<(), EngineError> {
+    let path = schedules_path(app)?;
+    let json = serde_json::to_vec_pretty(schedules)
+        .map_err(|e| EngineError::internal(format!("Failed to serialize schedules: {}", e)))?;
+    atomic_write(&path, &json, false).map_err(|e| EngineError::io(&path, e))?;
+    Ok(())
+}
+
+impl Scheduler {
+    /// Apply `change` to the schedule list and persist the result
+    fn update<T>(
+        &self,
+        app: &AppHandle,
+        change: impl FnOnce(&mut Vec<Schedule>) -> Result<T, EngineError>,
+    ) -> Result<T, EngineError> {
+        let mut schedules = self
+            .schedules
+            .lock()
+            .map_err(|_| EngineError::internal("Scheduler lock is poisoned"))?;
+        let result = change(&mut schedules)?;
+        persist(app, &schedules)?;
+        Ok(result)
+    }
+
+    fn snapshot(&self) -> Vec<Schedule> {
+        self.schedules.lock().map(|s| s.clone()).unwrap_or_default()
+    }
+}
+
+fn validate(input: &ScheduleInput, workspace: &Workspace) -> Result<Option<String>, EngineError> {
+    next_fire(&input.cron_or_interval, now_millis(), &Local)?;
+    if input.template.trim().is_empty() {
+        return Err(EngineError::invalid("A schedule needs a template"));
+    }
+    input
+        .workspace
+        .as_deref()
+        .map(|root| {
+            workspace
+                .check(Path::new(root))
+                .map(|p| p.display().to_string())
+        })
+        .transpose()
+}
+
+async fn run(app: &AppHandle, schedule: &Schedule) -> Result<String, EngineError> {
+    let body = templates::load(app, &schedule.template)?;
+    let prompt = templates::render(&body, &schedule.vars)?;
+
+    let root = schedule.workspace.as_ref().map(PathBuf::from);
+    if let Some(root) = &root {
+        if !root.is_dir() {
+            return Err(EngineError::invalid(format!(
+                "Workspace {} no longer exists",
+                root.display()
+            )));
+        }
+    }
+    let mut spawn = env_profile::spawn_options(app, root)?;
+
+    // Registered like any other request so it's cancellable and blocks relaunch
+    let registry = app.state::<CancelRegistry>().inner().clone();
+    let request_id = format!("schedule-{}-{}", schedule.id, now_millis());
+    spawn.request_id = Some(request_id.clone());
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+    let started = Instant::now();
+    let result = app
+        .state::<ActiveBackend>()
+        .stream(Arc::new(NullSink), prompt.clone(), token.clone(), spawn)
+        .await
+        .map(|reply| reply.text);
+    let outcome = result.clone().map_err(error_text);
+    webhooks::notify(
+        app,
+        &request_id,
+        started,
+        &outcome,
+        token.is_cancelled(),
+        None,
+    );
+    let response = match result {
+        Ok(response) => response,
+        Err(_) if token.is_cancelled() => return Err(EngineError::Cancelled),
+        Err(e) => return Err(e),
+    };
+
+    let conversation_id = history::new_conversation_id();
+    history::record_exchange(app, &conversation_id, "scheduled", &prompt, &response)?;
+    Ok(conversation_id)
+}
+
+async fn fire(app: AppHandle, schedule: Schedule) {
+    let scheduler = app.state::<Scheduler>();
+    let result = run(&app, &schedule).await;
+    if let Ok(mut running) = scheduler.running.lock() {
+        running.remove(&schedule.id);
+    }
+
+    let (conversation_id, error) = match &result {
+        Ok(id) => (Some(id.clone()), None),
+        Err(e) => (None, Some(e.to_string())),
+    };
+    let _ = scheduler.update(&app, |schedules| {
+        if let Some(s) = schedules.iter_mut().find(|s| s.id == schedule.id) {
+            s.last_run_at = Some(now_millis());
+            s.last_error = error.clone();
+            if conversation_id.is_some() {
+                s.last_conversation_id = conversation_id.clone();
+            }
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(conversation_id) => {
+            let _ = app.emit_all(
+                "scheduled-run-complete",
+                json!({ "schedule_id": schedule.id, "conversation_id": conversation_id }),
+            );
+            if schedule.notify {
+                let _ = Notification::new(&app.config().tauri.bundle.identifier)
+                    .title("Scheduled prompt finished")
+                    .body(&schedule.template)
+                    .show();
+            }
+        }
+        Err(error) => {
+            let _ = app.emit_all(
+                "scheduled-run-failed",
+                json!({ "schedule_id": schedule.id, "error": error }),
+            );
+        }
+    }
+}
+
+/// Mark due schedules as started and advance their next fire time, so each
+/// due slot fires at most once however late we are
+fn take_due(app: &AppHandle, scheduler: &Scheduler) -> Vec<Schedule> {
+    let now = now_millis();
+    let Ok(mut running) = scheduler.running.lock() else {
+        return Vec::new();
+    };
+    scheduler
+        .update(app, |schedules| {
+            let mut due = Vec::new();
+            for s in schedules.iter_mut() {
+                let is_due = s.enabled
+                    && s.next_run_at.is_some_and(|t| t <= now)
+                    && !running.contains(&s.id);
+                if is_due {
+                    s.next_run_at = next_fire(&s.cron_or_interval, now, &Local).ok();
+                    running.insert(s.id.clone());
+                    due.push(s.clone());
+                }
+            }
+            Ok(due)
+        })
+        .unwrap_or_default()
+}
+
+/// Load persisted schedules, apply each one's catch-up policy for runs missed
+/// while the app was closed, and start the background tick
+pub fn spawn(app: AppHandle) {
+    let scheduler = app.state::<Scheduler>();
+    let now = now_millis();
+    let mut schedules = load_from_disk(&app);
+    for s in schedules.iter_mut() {
+        let missed = s.next_run_at.is_some_and(|t| t <= now);
+        if missed && !s.catch_up {
+            s.next_run_at = next_fire(&s.cron_or_interval, now, &Local).ok();
+        }
+    }
+    if let Ok(mut current) = scheduler.schedules.lock() {
+        *current = schedules;
+    }
+
+    tauri::async_runtime::spawn(async move {
+        loop {
+            let scheduler = app.state::<Scheduler>();
+            for schedule in take_due(&app, &scheduler) {
+                tauri::async_runtime::spawn(fire(app.clone(), schedule));
+            }
+            tokio::time::sleep(TICK).await;
+        }
+    });
+}
+
+fn new_schedule(input: ScheduleInput, workspace: Option<String>) -> Result<Schedule, EngineError> {
+    let now = now_millis();
+    Ok(Schedule {
+        id: format!("sched-{}", random_hex(6)),
+        next_run_at: Some(next_fire(&input.cron_or_interval, now, &Local)?),
+        template: input.template,
+        vars: input.vars,
+        workspace,
+        cron_or_interval: input.cron_or_interval,
+        enabled: input.enabled,
+        catch_up: input.catch_up,
+        notify: input.notify,
+        created_at: now,
+        last_run_at: None,
+        last_conversation_id: None,
+        last_error: None,
+    })
+}
+
+#[tauri::command]
+pub async fn create_schedule(
+    app: AppHandle,
+    schedule: ScheduleInput,
+    scheduler: State<'_, Scheduler>,
+    workspace: State<'_, Workspace>,
+) -> Result<Schedule, EngineError> {
+    app.state::<Startup>().wait_ready().await?;
+    let root = validate(&schedule, &workspace)?;
+    let created = new_schedule(schedule, root)?;
+    scheduler.update(&app, |schedules| {
+        schedules.push(created.clone());
+        Ok(())
+    })?;
+    Ok(created)
+}
+
+#[tauri::command]
+pub async fn list_schedules(
+    app: AppHandle,
+    scheduler: State<'_, Scheduler>,
+) -> Result<Vec<Schedule>, EngineError> {
+    app.state::<Startup>().wait_ready().await?;
+    Ok(scheduler.snapshot())
+}
+
+/// Replace a schedule's definition; its next run is recomputed from now
+#[tauri::command]
+pub async fn update_schedule(
+    app: AppHandle,
+    id: String,
+    schedule: ScheduleInput,
+    scheduler: State<'_, Scheduler>,
+    workspace: State<'_, Workspace>,
+) -> Result<Schedule, EngineError> {
+    app.state::<Startup>().wait_ready().await?;
+    let root = validate(&schedule, &workspace)?;
+    let next_run_at = Some(next_fire(&schedule.cron_or_interval, now_millis(), &Local)?);
+    scheduler.update(&app, |schedules| {
+        let existing = schedules
+            .iter_mut()
+            .find(|s| s.id == id)
+            .ok_or_else(|| EngineError::invalid(format!("No schedule with id {}", id)))?;
+        existing.template = schedule.template;
+        existing.vars = schedule.vars;
+        existing.workspace = root;
+        existing.cron_or_interval = schedule.cron_or_interval;
+        existing.enabled = schedule.enabled;
+        existing.catch_up = schedule.catch_up;
+        existing.notify = schedule.notify;
+        existing.next_run_at = next_run_at;
+        Ok(existing.clone())
+    })
+}
+
+/// Returns false if there was no such schedule
+#[tauri::command]
+pub async fn delete_schedule(
+    app: AppHandle,
+    id: String,
+    scheduler: State<'_, Scheduler>,
+) -> Result<bool, EngineError> {
+    app.state::<Startup>().wait_ready().await?;
+    scheduler.update(&app, |schedules| {
+        let before = schedules.len();
+        schedules.retain(|s| s.id != id);
+        Ok(schedules.len() != before)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{FixedOffset, MappedLocalTime, NaiveDate, NaiveDateTime, NaiveTime};
+
+    /// US Eastern, 2024 rules only: EDT from 2024-03-10 07:00 UTC (02:00 EST jumps to
+    /// 03:00) until 2024-11-03 06:00 UTC (02:00 EDT falls back to 01:00)
+    #[derive(Clone, Copy)]
+    struct Eastern2024;
+
+    const EDT_FROM: i64 = 1_710_054_000;
+    const EDT_UNTIL: i64 = 1_730_613_600;
+
+    fn west(hours: i32) -> FixedOffset {
+        FixedOffset::west_opt(hours * 3600).unwrap()
+    }
+
+    impl TimeZone for Eastern2024 {
+        type Offset = FixedOffset;
+
+        fn from_offset(_: &FixedOffset) -> Self {
+            Eastern2024
+        }
+
+        fn offset_from_local_date(&self, local: &NaiveDate) -> MappedLocalTime<FixedOffset> {
+            self.offset_from_local_datetime(&local.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_local_datetime(
+            &self,
+            local: &NaiveDateTime,
+        ) -> MappedLocalTime<FixedOffset> {
+            let fits = |offset: FixedOffset| {
+                (self.offset_from_utc_datetime(&(*local - offset)) == offset).then_some(offset)
+            };
+            match (fits(west(4)), fits(west(5))) {
+                (Some(earlier), Some(later)) => MappedLocalTime::Ambiguous(earlier, later),
+                (Some(only), None) | (None, Some(only)) => MappedLocalTime::Single(only),
+                (None, None) => MappedLocalTime::None,
+            }
+        }
+
+        fn offset_from_utc_date(&self, utc: &NaiveDate) -> FixedOffset {
+            self.offset_from_utc_datetime(&utc.and_time(NaiveTime::MIN))
+        }
+
+        fn offset_from_utc_datetime(&self, utc: &NaiveDateTime) -> FixedOffset {
+            if (EDT_FROM..EDT_UNTIL).contains(&utc.and_utc().timestamp()) {
+                west(4)
+            } else {
+                west(5)
+            }
+        }
+    }
+
+    fn local_millis(text: &str) -> u64 {
+        let naive = NaiveDateTime::from_str(text).unwrap();
+        let at = Eastern2024.from_local_datetime(&naive).earliest().unwrap();
+        at.timestamp_millis() as u64
+    }
+
+    fn shown(millis: u64) -> String {
+        let at = Eastern2024.timestamp_millis_opt(millis as i64).unwrap();
+        at.to_rfc3339()
+    }
+
+    #[test]
+    fn cron_follows_the_wall_clock_across_dst() {
+        type Case = (&'static str, &'static str, &'static [&'static str]);
+        let cases: &[Case] = &[
+            // 02:30 doesn't exist on Mar 10: fires as soon as the clock passes it
+            (
+                "30 2 * * *",
+                "2024-03-09T03:00:00",
+                &["2024-03-10T03:00:00-04:00", "2024-03-11T02:30:00-04:00"],
+            ),
+            // 01:30 happens twice on Nov 3: fires the first time only
+            (
+                "30 1 * * *",
+                "2024-11-02T12:00:00",
+                &["2024-11-03T01:30:00-04:00", "2024-11-04T01:30:00-05:00"],
+            ),
+            (
+                "0 * * * *",
+                "2024-11-03T00:30:00",
+                &["2024-11-03T01:00:00-04:00", "2024-11-03T02:00:00-05:00"],
+            ),
+            // Weekdays at nine, over each transition weekend
+            (
+                "0 9 * * 1-5",
+                "2024-03-08T10:00:00",
+                &["2024-03-11T09:00:00-04:00", "2024-03-12T09:00:00-04:00"],
+            ),
+            (
+                "0 9 * * 1-5",
+                "2024-11-01T10:00:00",
+                &["2024-11-04T09:00:00-05:00", "2024-11-05T09:00:00-05:00"],
+            ),
+        ];
+        for (expression, after, fires) in cases {
+            let trigger = Trigger::Cron {
+                expression: expression.to_string(),
+            };
+            let mut at = local_millis(after);
+            for expected in *fires {
+                at = next_fire(&trigger, at, &Eastern2024).unwrap();
+                assert_eq!(shown(at), *expected, "'{}' after {}", expression, after);
+            }
+        }
+    }
+
+    #[test]
+    fn weekday_fires_stay_at_nine_over_the_short_and_long_weekend() {
+        let trigger = Trigger::Cron {
+            expression: "0 9 * * 1-5".to_string(),
+        };
+        let hour = 60 * 60 * 1000;
+        for (friday, monday_gap) in [("2024-03-08T09:00:00", 71), ("2024-11-01T09:00:00", 73)] {
+            let from = local_millis(friday);
+            let next = next_fire(&trigger, from, &Eastern2024).unwrap();
+            assert_eq!(next - from, monday_gap * hour, "after {}", friday);
+        }
+    }
+
+    #[test]
+    fn intervals_ignore_the_wall_clock() {
+        let trigger = Trigger::Interval { minutes: 60 };
+        let from = local_millis("2024-03-10T01:30:00");
+        let next = next_fire(&trigger, from, &Eastern2024).unwrap();
+        assert_eq!(shown(next), "2024-03-10T03:30:00-04:00");
+    }
+
+    #[test]
+    fn bad_expressions_are_invalid_input() {
+        let trigger = Trigger::Cron {
+            expression: "every tuesday".to_string(),
+        };
+        let err = next_fire(&trigger, 0, &Eastern2024).unwrap_err();
+        assert!(matches!(err, EngineError::InvalidInput { .. }), "{:?}", err);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::hash::random_hex;
+use crate::paths::app_cache_subdir;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::time::Duration;
+use tauri::Window;
+use xcap::{Monitor, XCapError};
+
+// Long enough for the compositor to actually take the window off screen
+const HIDE_SETTLE: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureMode {
+    /// The monitor the app window is on
+    Full,
+    /// The topmost window that isn't ours, or the one matching `window_title`
+    Window,
+    Region,
+}
+
+/// Desktop coordinates, in physical pixels
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ScreenshotOptions {
+    /// Hide the app while a full-screen or region capture is taken
+    pub hide_app_window: bool,
+    pub window_title: Option<String>,
+}
+
+impl Default for ScreenshotOptions {
+    fn default() -> Self {
+        Self {
+            hide_app_window: true,
+            window_title: None,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Screenshot {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub bytes: u64,
+}
+
+fn capture_error(e: XCapError) -> EngineError {
+    match e {
+        XCapError::NotSupported => EngineError::ScreenCaptureUnsupported {
+            detail: "Screen capture isn't supported on this system".to_string(),
+        },
+        XCapError::InvalidCaptureRegion(detail) => EngineError::invalid(detail),
+        // macOS fails captures outright until screen recording is granted
+        _ if cfg!(target_os = "macos") => EngineError::ScreenCapturePermissionDenied {
+            guidance: "Allow Bups Engine under System Settings → Privacy & Security → Screen \
+                       Recording, then restart the app"
+                .to_string(),
+        },
+        // On Wayland the capture goes through the desktop portal, which the user
+        // can decline or the compositor may not provide
+        other if std::env::var_os("WAYLAND_DISPLAY").is_some() => {
+            EngineError::ScreenCaptureUnsupported {
+                detail: format!(
+                    "The screenshot portal refused or isn't available ({}). Install \
+                     xdg-desktop-portal for your desktop or use an X11 session.",
+                    other
+                ),
+            }
+        }
+        other => EngineError::internal(format!("Screen capture failed: {}", other)),
+    }
+}
+
+fn capture(
+    mode: CaptureMode,
+    region: Option<Rect>,
+    own_pid: u32,
+    window_title: Option<String>,
+    app_position: (i32, i32),
+) -> Result<xcap::image::RgbaImage, EngineError> {
+    match mode {
+        CaptureMode::Full => {
+            let monitor =
+                Monitor::from_point(app_position.0, app_position.1).map_err(capture_error)?;
+            monitor.capture_image().map_err(capture_error)
+        }
+        CaptureMode::Region => {
+            let rect =
+                region.ok_or_else(|| EngineError::invalid("Region capture needs a region"))?;
+            if rect.width == 0 || rect.height == 0 {
+                return Err(EngineError::invalid("The capture region is empty"));
+            }
+            let monitor = Monitor::from_point(rect.x, rect.y).map_err(capture_error)?;
+            let left = monitor.x().map_err(capture_error)?;
+            let top = monitor.y().map_err(capture_error)?;
+            monitor
+                .capture_region(
+                    (rect.x - left) as u32,
+                    (rect.y - top) as u32,
+                    rect.width,
+                    rect.height,
+                )
+                .map_err(capture_error)
+        }
+        CaptureMode::Window => {
+            let windows = xcap::Window::all().map_err(capture_error)?;
+            let title = window_title.map(|t| t.to_lowercase());
+            let target = windows
+                .into_iter()
+                .filter(|w| !w.is_minimized().unwrap_or(true))
+                .filter(|w| w.pid().is_ok_and(|pid| pid != own_pid))
+                .find(|w| match &title {
+                    Some(title) => w
+                        .title()
+                        .is_ok_and(|t| t.to_lowercase().contains(title.as_str())),
+                    None => w.width().unwrap_or(0) > 0,
+                })
+                .ok_or_else(|| EngineError::invalid("No matching window to capture"))?;
+            target.capture_image().map_err(capture_error)
+        }
+    }
+}
+
+/// Capture the screen, a window or a region into the attachment store as PNG
+#[tauri::command]
+pub async fn capture_screenshot(
+    window: Window,
+    app: tauri::AppHandle,
+    mode: CaptureMode,
+    region: Option<Rect>,
+    options: Option<ScreenshotOptions>,
+) -> Result<Screenshot, EngineError> {
+    let options = options.unwrap_or_default();
+    let dir = app_cache_subdir(&app, "attachments")?;
+    let path: PathBuf = dir.join(format!("screenshot-{}-{}.png", now_millis(), random_hex(4)));
+
+    let position = window
+        .outer_position()
+        .map(|p| (p.x, p.y))
+        .unwrap_or((0, 0));
+    let hide = options.hide_app_window && mode != CaptureMode::Window;
+    if hide {
+        let _ = window.hide();
+        tokio::time::sleep(HIDE_SETTLE).await;
+    }
+    let own_pid = std::process::id();
+    let result = tokio::task::spawn_blocking(move || {
+        capture(mode, region, own_pid, options.window_title, position)
+    })
+    .await;
+    if hide {
+        let _ = window.show();
+    }
+
+    // A panic inside the platform backend shouldn't take the app down with it
+    let image = result.map_err(|e| EngineError::ScreenCaptureUnsupported {
+        detail: format!("The capture backend failed: {}", e),
+    })??;
+    let (width, height) = (image.width(), image.height());
+    let save_path = path.clone();
+    tokio::task::spawn_blocking(move || {
+        image.save_with_format(&save_path, xcap::image::ImageFormat::Png)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+    .map_err(|e| EngineError::io(&path, e))?;
+    let bytes = std::fs::metadata(&path)
+        .map_err(|e| EngineError::io(&path, e))?
+        .len();
+
+    Ok(Screenshot {
+        path: path.display().to_string(),
+        width,
+        height,
+        bytes,
+    })
+}
@@ -0,0 +1,213 @@
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::context::{build_globset, relative, DEFAULT_SKIP_DIRS};
+use crate::error::EngineError;
+use crate::files::looks_binary;
+use crate::sandbox::Workspace;
+use crate::walker::{walk_files, WalkConfig};
+use regex::bytes::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use tauri::{AppHandle, Manager, State};
+
+const MAX_FILE_BYTES: u64 = 4 * 1024 * 1024;
+// Minified files put everything on one line
+const MAX_LINE_CHARS: usize = 400;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchOptions {
+    pub regex: bool,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    pub include_globs: Vec<String>,
+    pub exclude_globs: Vec<String>,
+    pub respect_gitignore: bool,
+    pub max_results: usize,
+}
+
+impl Default for SearchOptions {
+    fn default() -> Self {
+        Self {
+            regex: false,
+            case_sensitive: false,
+            whole_word: false,
+            include_globs: Vec::new(),
+            exclude_globs: Vec::new(),
+            respect_gitignore: true,
+            max_results: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchMatch {
+    /// Relative to the search root
+    pub path: String,
+    /// 1-based
+    pub line_number: usize,
+    pub line_text: String,
+    /// Of the match, from the start of the file
+    pub byte_offset: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SearchResults {
+    pub root: String,
+    pub matches: Vec<SearchMatch>,
+    /// More matches exist than `max_results`
+    pub truncated: bool,
+    pub skipped_binary: usize,
+}
+
+fn build_regex(query: &str, options: &SearchOptions) -> Result<Regex, EngineError> {
+    if query.is_empty() {
+        return Err(EngineError::invalid("Search text is empty"));
+    }
+    let mut pattern = if options.regex {
+        query.to_string()
+    } else {
+        regex::escape(query)
+    };
+    if options.whole_word {
+        pattern = format!(r"\b(?:{})\b", pattern);
+    }
+    RegexBuilder::new(&pattern)
+        .case_insensitive(!options.case_sensitive)
+        .multi_line(true)
+        .build()
+        .map_err(|e| EngineError::invalid(format!("Bad pattern: {}", e)))
+}
+
+fn line_text(line: &[u8]) -> String {
+    let text = String::from_utf8_lossy(line);
+    let text = text.trim_end_matches(['\r', '\n']);
+    match text.char_indices().nth(MAX_LINE_CHARS) {
+        Some((cut, _)) => format!("{}…", &text[..cut]),
+        None => text.to_string(),
+    }
+}
+
+/// Matches in one file as (line number, line text, byte offset)
+fn matches_in(bytes: &[u8], regex: &Regex, limit: usize) -> Vec<(usize, String, usize)> {
+    let mut found = Vec::new();
+    let mut line = 1;
+    let mut counted_to = 0;
+    for m in regex.find_iter(bytes).take(limit) {
+        line += bytes[counted_to..m.start()]
+            .iter()
+            .filter(|b| **b == b'\n')
+            .count();
+        counted_to = m.start();
+        let start = bytes[..m.start()]
+            .iter()
+            .rposition(|b| *b == b'\n')
+            .map_or(0, |i| i + 1);
+        let end = bytes[m.start()..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .map_or(bytes.len(), |i| m.start() + i);
+        found.push((line, line_text(&bytes[start..end]), m.start()));
+    }
+    found
+}
+
+enum Scanned {
+    Matches(Vec<(usize, String, usize)>),
+    Binary,
+}
+
+fn search(
+    root: &Path,
+    query: &str,
+    options: &SearchOptions,
+    cancel: Option<&CancelToken>,
+) -> Result<SearchResults, EngineError> {
+    let regex = build_regex(query, options)?;
+    let include = build_globset(&options.include_globs)?;
+    let exclude = build_globset(&options.exclude_globs)?;
+    let config = WalkConfig {
+        respect_gitignore: options.respect_gitignore,
+        max_filesize: Some(MAX_FILE_BYTES),
+        cancel,
+        ..WalkConfig::default()
+    };
+    let descend = |entry: &ignore::DirEntry| {
+        !DEFAULT_SKIP_DIRS.contains(&entry.file_name().to_string_lossy().as_ref())
+    };
+    // Enough to fill the cap and tell that it overflowed; files past it aren't read
+    let wanted = options.max_results.saturating_add(1);
+    let total = AtomicUsize::new(0);
+    let found = walk_files(root, config, descend, |entry| {
+        if total.load(Ordering::Relaxed) >= wanted {
+            return None;
+        }
+        let rel = relative(root, entry.path());
+        if include.as_ref().is_some_and(|set| !set.is_match(&rel))
+            || exclude.as_ref().is_some_and(|set| set.is_match(&rel))
+        {
+            return None;
+        }
+        let bytes = std::fs::read(entry.path()).ok()?;
+        if looks_binary(&bytes) {
+            return Some(Scanned::Binary);
+        }
+        let matches = matches_in(&bytes, &regex, wanted);
+        total.fetch_add(matches.len(), Ordering::Relaxed);
+        (!matches.is_empty()).then_some(Scanned::Matches(matches))
+    })?;
+
+    // Workers race past the cap, so it's applied again in path order
+    let mut matches = Vec::new();
+    let mut skipped_binary = 0;
+    for (path, scanned) in found {
+        let found = match scanned {
+            Scanned::Binary => {
+                skipped_binary += 1;
+                continue;
+            }
+            Scanned::Matches(found) => found,
+        };
+        let path = relative(root, &path);
+        matches.extend(
+            found
+                .into_iter()
+                .map(|(line_number, line_text, byte_offset)| SearchMatch {
+                    path: path.clone(),
+                    line_number,
+                    line_text,
+                    byte_offset,
+                }),
+        );
+    }
+    let truncated = matches.len() > options.max_results;
+    matches.truncate(options.max_results);
+    Ok(SearchResults {
+        root: root.display().to_string(),
+        matches,
+        truncated,
+        skipped_binary,
+    })
+}
+
+/// Grep every text file under `root` across worker threads, skipping binary
+/// files and the usual build and dependency folders. Cancel with `request_id`.
+#[tauri::command]
+pub async fn search_in_files(
+    app: AppHandle,
+    root: String,
+    query: String,
+    options: Option<SearchOptions>,
+    request_id: Option<String>,
+    workspace: State<'_, Workspace>,
+) -> Result<SearchResults, EngineError> {
+    let root = workspace.check(Path::new(&root))?;
+    let options = options.unwrap_or_default();
+    let guard = request_id
+        .as_deref()
+        .map(|id| app.state::<CancelRegistry>().register(id));
+    let token = guard.as_ref().map(|g| g.token());
+    tokio::task::spawn_blocking(move || search(&root, &query, &options, token.as_ref()))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
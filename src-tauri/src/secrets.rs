@@ -0,0 +1,99 @@
+use crate::error::EngineError;
+use keyring::Entry;
+
+// Every secret is filed under this service in the OS credential store
+const SERVICE: &str = "bups-engine";
+const MAX_NAME_CHARS: usize = 64;
+
+/// What the direct API backend authenticates with
+pub const ANTHROPIC_API_KEY: &str = "anthropic_api_key";
+
+fn entry(name: &str) -> Result<Entry, EngineError> {
+    let well_formed = !name.is_empty()
+        && name.chars().count() <= MAX_NAME_CHARS
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c));
+    if !well_formed {
+        return Err(EngineError::invalid(format!(
+            "'{}' is not a valid secret name",
+            name
+        )));
+    }
+    Entry::new(SERVICE, name).map_err(store_error)
+}
+
+/// Keychain failures as structured errors. Messages come from the platform
+/// and never include the secret itself.
+fn store_error(err: keyring::Error) -> EngineError {
+    match err {
+        // Locked, denied, or no keychain service running at all
+        keyring::Error::NoStorageAccess(e) | keyring::Error::PlatformFailure(e) => {
+            EngineError::SecretStoreUnavailable {
+                detail: e.to_string(),
+            }
+        }
+        keyring::Error::TooLong(attribute, limit) => EngineError::invalid(format!(
+            "{} is longer than the keychain allows ({} characters)",
+            attribute, limit
+        )),
+        keyring::Error::Invalid(attribute, reason) => {
+            EngineError::invalid(format!("{}: {}", attribute, reason))
+        }
+        other => EngineError::internal(format!("Keychain error: {}", other)),
+    }
+}
+
+/// The stored value, None when there isn't one. Blocking; the OS may ask the
+/// user to unlock the keychain first.
+pub fn read(name: &str) -> Result<Option<String>, EngineError> {
+    match entry(name)?.get_password() {
+        Ok(value) => Ok(Some(value)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+pub fn write(name: &str, value: &str) -> Result<(), EngineError> {
+    entry(name)?.set_password(value).map_err(store_error)
+}
+
+/// Deleting a secret that isn't there is not an error
+pub fn delete(name: &str) -> Result<(), EngineError> {
+    match entry(name)?.delete_credential() {
+        Ok(()) | Err(keyring::Error::NoEntry) => Ok(()),
+        Err(e) => Err(store_error(e)),
+    }
+}
+
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T, EngineError> + Send + 'static,
+) -> Result<T, EngineError> {
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| EngineError::internal(format!("Keychain task failed: {}", e)))?
+}
+
+/// Store a secret in the OS credential store; an empty value deletes it
+#[tauri::command]
+pub async fn set_secret(name: String, value: String) -> Result<(), EngineError> {
+    blocking(move || {
+        if value.is_empty() {
+            delete(&name)
+        } else {
+            write(&name, &value)
+        }
+    })
+    .await
+}
+
+/// The only way a stored secret reaches the frontend
+#[tauri::command]
+pub async fn get_secret(name: String) -> Result<Option<String>, EngineError> {
+    blocking(move || read(&name)).await
+}
+
+#[tauri::command]
+pub async fn delete_secret(name: String) -> Result<(), EngineError> {
+    blocking(move || delete(&name)).await
+}
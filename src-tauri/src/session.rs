@@ -0,0 +1,556 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelToken;
+use crate::claude::{
+    cli_command, emit_started, emit_stats, emit_text, finish, first_token, log_spawn, message_text,
+    spawn_failed, text_delta, Reply, SpawnOptions, StreamSink, UsageInfo, Watchdog,
+};
+use crate::error::EngineError;
+use crate::hash;
+use crate::metrics::{Stage, Trace};
+use crate::proctree;
+use crate::settings;
+use crate::spill::{Overflow, Spilled};
+use crate::stop::{Advance, StopReason, StopScanner};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+
+const REAP_TICK: Duration = Duration::from_secs(60);
+const POLL: Duration = Duration::from_millis(500);
+const STDERR_TAIL_BYTES: usize = 4096;
+
+/// A CLI process in stream-json input mode, holding one conversation
+struct WarmSession {
+    child: Child,
+    stdin: ChildStdin,
+    lines: UnboundedReceiver<String>,
+    stderr: Arc<Mutex<String>>,
+    /// Options it was started with; a change needs a new process
+    fingerprint: String,
+    last_used: Instant,
+}
+
+impl Drop for WarmSession {
+    fn drop(&mut self) {
+        proctree::kill_tree(&mut self.child);
+    }
+}
+
+impl WarmSession {
+    fn stderr_tail(&self) -> String {
+        self.stderr
+            .lock()
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    }
+}
+
+fn fingerprint(spawn: &SpawnOptions) -> String {
+    // The prompts can be long, so they go in hashed
+    let prompt = |p: &Option<String>| hash::sha256_hex(p.as_deref().unwrap_or("").as_bytes());
+    format!(
+        "{:?}|{:?}|{}|{:?}|{:?}|{:?}|{:?}|{}|{}",
+        spawn.session,
+        spawn.model,
+        spawn.env.digest(),
+        spawn.cwd,
+        spawn.engine,
+        spawn.tools,
+        spawn.approval,
+        prompt(&spawn.system_prompt),
+        prompt(&spawn.appended_prompt())
+    )
+}
+
+fn spawn_session(spawn: &SpawnOptions) -> Result<WarmSession, String> {
+    let mut cmd = cli_command(spawn).map_err(|e| e.to_string())?;
+    cmd.args([
+        "--print",
+        "--input-format",
+        "stream-json",
+        "--output-format",
+        "stream-json",
+        "--verbose",
+        "--include-partial-messages",
+    ]);
+    cmd.stdin(Stdio::piped());
+    cmd.stdout(Stdio::piped());
+    cmd.stderr(Stdio::piped());
+    log_spawn(&cmd, "warm");
+    let mut child = cmd.spawn().map_err(|e| spawn_failed(e).to_string())?;
+    let stdin = child.stdin.take().ok_or("Failed to capture stdin")?;
+    let stdout = child.stdout.take().ok_or("Failed to capture stdout")?;
+
+    // The channel closing is how a dead process shows up
+    let (tx, lines) = unbounded_channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stdout).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    let stderr = Arc::new(Mutex::new(String::new()));
+    if let Some(pipe) = child.stderr.take() {
+        let tail = Arc::clone(&stderr);
+        std::thread::spawn(move || {
+            for line in BufReader::new(pipe).lines().map_while(Result::ok) {
+                let Ok(mut tail) = tail.lock() else {
+                    break;
+                };
+                tail.push_str(&line);
+                tail.push('\n');
+                if tail.len() > STDERR_TAIL_BYTES {
+                    let mut cut = tail.len() - STDERR_TAIL_BYTES;
+                    while !tail.is_char_boundary(cut) {
+                        cut += 1;
+                    }
+                    tail.drain(..cut);
+                }
+            }
+        });
+    }
+
+    Ok(WarmSession {
+        child,
+        stdin,
+        lines,
+        stderr,
+        fingerprint: fingerprint(spawn),
+        last_used: Instant::now(),
+    })
+}
+
+enum TurnError {
+    /// The process went away; `emitted` says whether part of the reply was streamed
+    Died {
+        emitted: bool,
+        detail: String,
+    },
+    /// The CLI answered with an error but is still usable
+    Failed(String),
+    /// Nobody is listening any more
+    Sink(String),
+    Cancelled,
+    /// Silent past the stall limit
+    Stalled(EngineError),
+    /// A stop rule ended the reply; the process is still generating the rest
+    Stopped {
+        text: String,
+        reason: StopReason,
+    },
+}
+
+async fn run_turn(
+    session: &mut WarmSession,
+    sink: &dyn StreamSink,
+    message: &str,
+    cancel: &CancelToken,
+    started: Instant,
+    first_chunk_ms: &mut Option<u64>,
+    spawn: &SpawnOptions,
+) -> Result<(String, Option<UsageInfo>, Spilled), TurnError> {
+    let input = json!({
+        "type": "user",
+        "message": {"role": "user", "content": [{"type": "text", "text": message}]},
+    });
+    let written = writeln!(session.stdin, "{}", input).and_then(|_| session.stdin.flush());
+    if let Err(e) = written {
+        return Err(TurnError::Died {
+            emitted: false,
+            detail: format!("Failed to write to the CLI: {}", e),
+        });
+    }
+
+    let mut text = String::new();
+    // Partial messages carry the text as it's generated; without them it
+    // arrives with each complete assistant message instead
+    let mut saw_delta = false;
+    let mut scanner = spawn.stop.as_ref().map(StopScanner::new);
+    let mut watchdog = Watchdog::new(spawn.limits);
+    let mut emitted = 0;
+    let mut overflow = Overflow::new(&spawn.spill);
+    // Emits whatever the stop rules let through, and says when they've hit
+    let mut emit = |chunk: &str,
+                    text: &mut String,
+                    emitted: &mut usize|
+     -> Result<Option<StopReason>, TurnError> {
+        first_token(sink, first_chunk_ms, started);
+        let sink_error = |e: String| TurnError::Sink(format!("Failed to emit stream chunk: {}", e));
+        if overflow.active() {
+            overflow.take(sink, chunk).map_err(sink_error)?;
+            return Ok(None);
+        }
+        text.push_str(chunk);
+        let advance = match scanner.as_mut() {
+            Some(scanner) => scanner.advance(text),
+            None => Advance {
+                release: text.len(),
+                stop: None,
+            },
+        };
+        let release = advance.release.max(*emitted);
+        if release > *emitted {
+            emit_text(sink, &text[*emitted..release]).map_err(sink_error)?;
+            *emitted = release;
+        }
+        if advance.stop.is_some() {
+            text.truncate(release);
+            return Ok(advance.stop);
+        }
+        overflow.check(sink, text, emitted).map_err(sink_error)?;
+        Ok(None)
+    };
+    loop {
+        if cancel.is_cancelled() {
+            return Err(TurnError::Cancelled);
+        }
+        let line = match tokio::time::timeout(POLL, session.lines.recv()).await {
+            Err(_) => {
+                watchdog.check(sink).map_err(TurnError::Stalled)?;
+                continue;
+            }
+            Ok(None) => {
+                let detail = session.stderr_tail();
+                return Err(TurnError::Died {
+                    emitted: !text.is_empty(),
+                    detail: if detail.is_empty() {
+                        "The CLI process exited".to_string()
+                    } else {
+                        detail
+                    },
+                });
+            }
+            Ok(Some(line)) => {
+                watchdog.fed();
+                line
+            }
+        };
+        let Ok(event) = serde_json::from_str::<Value>(&line) else {
+            continue;
+        };
+        match event.get("type").and_then(Value::as_str) {
+            Some("stream_event") => {
+                if let Some(chunk) = text_delta(&event) {
+                    saw_delta = true;
+                    if let Some(reason) = emit(chunk, &mut text, &mut emitted)? {
+                        return Err(TurnError::Stopped { text, reason });
+                    }
+                }
+            }
+            Some("assistant") if !saw_delta => {
+                let chunk = message_text(&event);
+                if !chunk.is_empty() {
+                    if let Some(reason) = emit(&chunk, &mut text, &mut emitted)? {
+                        return Err(TurnError::Stopped { text, reason });
+                    }
+                }
+            }
+            Some("result") => {
+                let result = event.get("result").and_then(Value::as_str).unwrap_or("");
+                if event.get("is_error").and_then(Value::as_bool) == Some(true) {
+                    return Err(TurnError::Failed(result.to_string()));
+                }
+                // Text held back for a stop sequence that never came
+                if emitted < text.len() {
+                    emit_text(sink, &text[emitted..]).map_err(|e| {
+                        TurnError::Sink(format!("Failed to emit stream chunk: {}", e))
+                    })?;
+                }
+                let text = if text.is_empty() {
+                    result.to_string()
+                } else {
+                    text
+                };
+                return Ok((text, UsageInfo::from_result(&event), overflow.finish()));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Idle warm sessions keyed by conversation id. A session is taken out while
+/// a turn runs and put back afterwards, so dropping it kills the process.
+#[derive(Default)]
+pub struct SessionPool {
+    idle: Mutex<HashMap<String, WarmSession>>,
+    /// Fingerprints a fresh process died under without answering, i.e. the
+    /// CLI those options run can't take stream-json input; later messages
+    /// with the same options go straight to a process per message, while
+    /// a change of CLI path or model gets tried again
+    unsupported: Mutex<HashSet<String>>,
+}
+
+impl SessionPool {
+    fn is_unsupported(&self, fingerprint: &str) -> bool {
+        self.unsupported
+            .lock()
+            .is_ok_and(|unsupported| unsupported.contains(fingerprint))
+    }
+
+    fn mark_unsupported(&self, fingerprint: &str) {
+        if let Ok(mut unsupported) = self.unsupported.lock() {
+            unsupported.insert(fingerprint.to_string());
+        }
+    }
+
+    fn take(&self, conversation_id: &str) -> Option<WarmSession> {
+        self.idle.lock().ok()?.remove(conversation_id)
+    }
+
+    fn put(&self, conversation_id: &str, mut session: WarmSession, max_sessions: usize) {
+        let Ok(mut idle) = self.idle.lock() else {
+            return;
+        };
+        while idle.len() >= max_sessions.max(1) {
+            let oldest = idle
+                .iter()
+                .min_by_key(|(_, s)| s.last_used)
+                .map(|(id, _)| id.clone());
+            match oldest {
+                Some(id) => idle.remove(&id),
+                None => break,
+            };
+        }
+        session.last_used = Instant::now();
+        idle.insert(conversation_id.to_string(), session);
+    }
+
+    fn reap(&self, max_idle: Duration) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.retain(|_, s| s.last_used.elapsed() < max_idle);
+        }
+    }
+
+    /// End a conversation's warm process, e.g. once its context has changed
+    pub fn close(&self, conversation_id: &str) -> bool {
+        self.take(conversation_id).is_some()
+    }
+
+    /// Kill every warm process, on app exit
+    pub fn shutdown(&self) {
+        if let Ok(mut idle) = self.idle.lock() {
+            idle.clear();
+        }
+    }
+}
+
+/// Stream `message` through the conversation's warm CLI process, starting one
+/// when needed. Falls back to the backend's own stream, a process per message
+/// for the CLI, when warm sessions are off, the backend has none, or the CLI
+/// can't run in stream-json input mode.
+pub async fn stream_in_session(
+    app: &AppHandle,
+    conversation_id: &str,
+    sink: Arc<dyn StreamSink>,
+    message: String,
+    cancel: CancelToken,
+    spawn: SpawnOptions,
+) -> Result<Reply, EngineError> {
+    let config = settings::load(app).sessions;
+    let pool = app.state::<SessionPool>();
+    let backend = app.state::<ActiveBackend>();
+    let wanted = fingerprint(&spawn);
+    if !config.warm || !backend.warm_sessions() || pool.is_unsupported(&wanted) {
+        return backend.stream(sink, message, cancel, spawn).await;
+    }
+    // The process outlives the turn, but only a turn in flight holds a slot
+    let slot = backend.slot(&spawn, sink.as_ref(), &cancel).await?;
+    let sink = slot.meter(sink);
+
+    let started = Instant::now();
+    let mut first_chunk_ms = None;
+    let mut trace = Trace::new("warm");
+    let mut restarted = false;
+    loop {
+        // A session started with other options is dropped, which kills it
+        let existing = pool
+            .take(conversation_id)
+            .filter(|s| s.fingerprint == wanted);
+        let fresh = existing.is_none();
+        let mut session = match existing {
+            Some(session) => session,
+            None => match spawn_session(&spawn) {
+                Ok(session) => {
+                    trace.record(Stage::Spawn, started.elapsed());
+                    session
+                }
+                Err(_) => {
+                    return backend
+                        .stream_in_slot(&slot, sink, message, cancel, spawn)
+                        .await
+                }
+            },
+        };
+        emit_started(
+            sink.as_ref(),
+            if fresh { "warm-start" } else { "warm" },
+            started,
+        );
+        let outcome = run_turn(
+            &mut session,
+            sink.as_ref(),
+            &message,
+            &cancel,
+            started,
+            &mut first_chunk_ms,
+            &spawn,
+        )
+        .await;
+        let error = match outcome {
+            Ok((text, usage, spilled)) => {
+                pool.put(conversation_id, session, config.max_sessions);
+                let metrics = emit_stats(
+                    sink.as_ref(),
+                    if fresh { "warm-start" } else { "warm" },
+                    first_chunk_ms,
+                    started,
+                    &text,
+                    &mut trace,
+                );
+                let (text, mut payload) =
+                    finish(&spawn, text, None, usage.as_ref(), &metrics, &mut trace);
+                spilled.mark(&mut payload);
+                sink.emit("claude-stream-complete", payload)
+                    .map_err(|e| format!("Failed to emit completion event: {}", e))?;
+                return Ok(Reply {
+                    text,
+                    session_id: None,
+                    usage,
+                    metrics: Some(metrics),
+                    spilled,
+                });
+            }
+            Err(TurnError::Cancelled) => {
+                // The only way to stop a turn midway is to end the process
+                drop(session);
+                sink.emit("claude-stream-cancelled", Value::Null)
+                    .map_err(|e| format!("Failed to emit cancel event: {}", e))?;
+                return Err(EngineError::Cancelled);
+            }
+            Err(TurnError::Stopped { text, reason }) => {
+                // Ending the process is the only way to drop the rest of the reply
+                drop(session);
+                let metrics = emit_stats(
+                    sink.as_ref(),
+                    if fresh { "warm-start" } else { "warm" },
+                    first_chunk_ms,
+                    started,
+                    &text,
+                    &mut trace,
+                );
+                let (text, payload) =
+                    finish(&spawn, text, Some(reason), None, &metrics, &mut trace);
+                sink.emit("claude-stream-complete", payload)
+                    .map_err(|e| format!("Failed to emit completion event: {}", e))?;
+                return Ok(Reply {
+                    text,
+                    session_id: None,
+                    usage: None,
+                    metrics: Some(metrics),
+                    spilled: Spilled::default(),
+                });
+            }
+            Err(TurnError::Sink(detail)) => return Err(detail.into()),
+            Err(TurnError::Stalled(timeout)) => {
+                drop(session);
+                let _ = sink.emit("claude-stream-error", timeout.to_string().into());
+                return Err(timeout);
+            }
+            Err(TurnError::Failed(detail)) => {
+                pool.put(conversation_id, session, config.max_sessions);
+                detail
+            }
+            Err(TurnError::Died { emitted, detail }) => {
+                drop(session);
+                if fresh && !emitted {
+                    pool.mark_unsupported(&wanted);
+                    return backend
+                        .stream_in_slot(&slot, sink, message, cancel, spawn)
+                        .await;
+                }
+                if emitted || restarted {
+                    detail
+                } else {
+                    // Died between turns: start over once with a new process
+                    restarted = true;
+                    let _ = sink.emit(
+                        "session-restarted",
+                        json!({"conversation_id": conversation_id, "reason": detail}),
+                    );
+                    continue;
+                }
+            }
+        };
+        sink.emit("claude-stream-error", error.as_str().into())
+            .map_err(|e| format!("Failed to emit error event: {}", e))?;
+        return Err(EngineError::CliExited {
+            code: None,
+            stderr: error,
+        });
+    }
+}
+
+/// Shut down sessions idle longer than the configured limit
+pub fn spawn_reaper(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        loop {
+            tokio::time::sleep(REAP_TICK).await;
+            let minutes = settings::load(&app).sessions.idle_minutes;
+            app.state::<SessionPool>()
+                .reap(Duration::from_secs(minutes.saturating_mul(60)));
+        }
+    });
+}
+
+/// End a conversation's warm process, e.g. when the conversation is closed
+#[tauri::command]
+pub async fn close_session(
+    conversation_id: String,
+    pool: State<'_, SessionPool>,
+) -> Result<bool, EngineError> {
+    Ok(pool.close(&conversation_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::claude::CliSession;
+
+    #[test]
+    fn the_fingerprint_follows_session_and_environment() {
+        let base = SpawnOptions::default();
+        assert_eq!(fingerprint(&base), fingerprint(&base.clone()));
+
+        let mut resumed = base.clone();
+        resumed.session = CliSession::Resume("abc".to_string());
+        assert_ne!(fingerprint(&base), fingerprint(&resumed));
+
+        let mut other_env = base.clone();
+        other_env.env.set("ANTHROPIC_MODEL", "x");
+        assert_ne!(fingerprint(&base), fingerprint(&other_env));
+
+        let mut other_model = base.clone();
+        other_model.model = Some("opus".to_string());
+        assert_ne!(fingerprint(&base), fingerprint(&other_model));
+    }
+
+    #[test]
+    fn unsupported_is_kept_to_the_options_that_failed() {
+        let pool = SessionPool::default();
+        let failed = fingerprint(&SpawnOptions::default());
+        let mut other = SpawnOptions::default();
+        other.engine.claude_cli_path = Some("/opt/claude".into());
+        pool.mark_unsupported(&failed);
+        assert!(pool.is_unsupported(&failed));
+        assert!(!pool.is_unsupported(&fingerprint(&other)));
+    }
+}
@@ -0,0 +1,456 @@
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::spill;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tauri::{AppHandle, Manager};
+
+pub const SETTINGS_FILE: &str = "settings.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LocalApiSettings {
+    pub enabled: bool,
+    pub port: u16,
+    /// Bearer token, generated the first time the API is enabled
+    pub token: Option<String>,
+}
+
+impl Default for LocalApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: 4317,
+            token: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpdateSettings {
+    /// Check the update endpoint once a day in the background
+    pub auto_check: bool,
+    pub last_checked_at: Option<u64>,
+}
+
+impl Default for UpdateSettings {
+    fn default() -> Self {
+        Self {
+            auto_check: true,
+            last_checked_at: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportSettings {
+    /// Where exports are suggested; relative paths are under the workspace root
+    pub notes_dir: Option<String>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WebhookSettings {
+    /// Called when a request finishes; `None` disables webhooks
+    pub url: Option<String>,
+    /// Include the (truncated) response text in the payload
+    pub include_response: bool,
+    pub max_response_chars: usize,
+    /// HMAC key for the signature header, generated on first use
+    pub secret: Option<String>,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            url: None,
+            include_response: false,
+            max_response_chars: 2000,
+            secret: None,
+        }
+    }
+}
+
+// The secret stays out of debug output
+impl std::fmt::Debug for WebhookSettings {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WebhookSettings")
+            .field("url", &self.url)
+            .field("include_response", &self.include_response)
+            .field("max_response_chars", &self.max_response_chars)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IndexSettings {
+    /// Index workspaces in the background when they open
+    pub enabled: bool,
+    /// Also record definition names, so symbol search only opens likely files
+    pub symbols: bool,
+}
+
+impl Default for IndexSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            symbols: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionSettings {
+    /// Keep a CLI process per conversation instead of spawning one per message
+    pub warm: bool,
+    pub idle_minutes: u64,
+    pub max_sessions: usize,
+}
+
+impl Default for SessionSettings {
+    fn default() -> Self {
+        Self {
+            warm: false,
+            idle_minutes: 10,
+            max_sessions: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EstimateSettings {
+    /// Have send and stream estimate first and ask before crossing `confirm_*`
+    pub confirm_before_send: bool,
+    pub warn_tokens: usize,
+    pub warn_cost_usd: Option<f64>,
+    pub confirm_tokens: usize,
+    pub confirm_cost_usd: Option<f64>,
+    /// Keyed by a substring of the model id; replaces the built-in price
+    pub pricing: BTreeMap<String, ModelPrice>,
+}
+
+impl Default for EstimateSettings {
+    fn default() -> Self {
+        Self {
+            confirm_before_send: false,
+            warn_tokens: 50_000,
+            warn_cost_usd: Some(0.5),
+            confirm_tokens: 150_000,
+            confirm_cost_usd: Some(2.0),
+            pricing: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamSettings {
+    /// Larger chunks reach the chat view in parts, so one huge line can't stall it
+    pub max_chunk_bytes: usize,
+    /// Reply text is batched into at most one event per this many milliseconds
+    pub flush_interval_ms: u64,
+    /// ...or sent sooner once this much is waiting
+    pub flush_bytes: usize,
+    /// A reply past this is still streamed, but only this much is kept in
+    /// memory and returned; all of it is written to a file in the cache
+    pub max_response_bytes: usize,
+}
+
+impl Default for StreamSettings {
+    fn default() -> Self {
+        Self {
+            max_chunk_bytes: 16 * 1024,
+            // About one frame at 30fps
+            flush_interval_ms: 33,
+            flush_bytes: 8 * 1024,
+            max_response_bytes: spill::DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+}
+
+/// A find-and-replace step for replies, named so requests can ask for it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RegexRule {
+    pub name: String,
+    pub pattern: String,
+    /// May refer to groups as `$1` or `$name`
+    pub replacement: String,
+    /// A disabled rule is skipped even when a request names it
+    pub enabled: bool,
+}
+
+impl Default for RegexRule {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            pattern: String::new(),
+            replacement: String::new(),
+            enabled: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    pub rules: Vec<RegexRule>,
+}
+
+/// What answers requests
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// The CLI when it's installed, otherwise the API when there's a key
+    #[default]
+    Auto,
+    Cli,
+    /// The Messages API directly, for machines without node
+    Api,
+}
+
+/// Explicit locations for when discovery can't find the CLI (pnpm, Volta,
+/// yarn global, a portable node, ...), and the API fallback. The API key is
+/// kept in the OS keychain, not here; see [`crate::secrets`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EngineConfig {
+    /// The CLI's `cli.js`
+    pub claude_cli_path: Option<PathBuf>,
+    pub node_path: Option<PathBuf>,
+    pub backend: Backend,
+    /// Retries for an overloaded API or a dropped connection; 0 turns them off
+    pub max_retries: u32,
+    /// Longest wait between retries
+    pub max_retry_delay_secs: u64,
+    /// Set as `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY` for the CLI, which
+    /// may not inherit them when the app isn't started from a shell
+    pub http_proxy: Option<String>,
+    pub https_proxy: Option<String>,
+    pub no_proxy: Option<String>,
+    /// More variables for the CLI, over any workspace profile; an empty
+    /// value unsets one
+    pub extra_env: BTreeMap<String, String>,
+    /// Give the CLI the keychain's API key as `ANTHROPIC_API_KEY`
+    pub pass_api_key_to_cli: bool,
+    /// Ask the window before a streamed turn runs a tool its permissions
+    /// don't allow, instead of refusing it; see [`crate::approval`]
+    pub permission_prompts: bool,
+    /// Unanswered for this long, a tool call is denied
+    pub permission_timeout_secs: u64,
+    /// Approved with "always", so never asked about again
+    pub always_allowed_tools: Vec<String>,
+    /// CLI processes the chat commands run at once; more wait their turn
+    pub max_concurrent_cli: usize,
+}
+
+impl Default for EngineConfig {
+    fn default() -> Self {
+        Self {
+            claude_cli_path: None,
+            node_path: None,
+            backend: Backend::Auto,
+            max_retries: 3,
+            max_retry_delay_secs: 30,
+            http_proxy: None,
+            https_proxy: None,
+            no_proxy: None,
+            extra_env: BTreeMap::new(),
+            pass_api_key_to_cli: false,
+            permission_prompts: false,
+            permission_timeout_secs: 120,
+            always_allowed_tools: Vec::new(),
+            max_concurrent_cli: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DebugSettings {
+    /// Emit `performance-snapshot` this often, for the performance panel
+    pub performance_snapshot_minutes: Option<u64>,
+    /// Forward the CLI's stderr as `claude-stream-stderr` while it streams
+    pub stream_stderr: bool,
+    /// `engine.log` verbosity; info when unset
+    pub log_level: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FilesystemSettings {
+    /// Let the file commands reach paths outside the workspace roots
+    pub unrestricted: bool,
+    /// What `set_workspace_roots` was last given, restored at launch
+    pub workspace_roots: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CommandSettings {
+    /// Programs `run_command` starts without asking while the workspace
+    /// sandbox is on; anything else needs `confirmed: true`
+    pub allowed_programs: Vec<String>,
+    /// Output past this is no longer emitted, so a runaway process can't
+    /// flood the window
+    pub max_output_bytes: usize,
+}
+
+impl Default for CommandSettings {
+    fn default() -> Self {
+        Self {
+            allowed_programs: ["cargo", "npm", "pnpm", "yarn", "go", "make", "pytest"]
+                .map(String::from)
+                .to_vec(),
+            max_output_bytes: 4 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GitSettings {
+    /// `git_diff` output past this is cut off and marked truncated
+    pub max_diff_bytes: usize,
+}
+
+impl Default for GitSettings {
+    fn default() -> Self {
+        Self {
+            max_diff_bytes: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FetchSettings {
+    /// Let `fetch_url` reach localhost and private networks, e.g. an
+    /// intranet wiki; off so a URL planted in a prompt can't probe them
+    pub allow_private: bool,
+}
+
+/// Persisted app settings; unknown fields are ignored and missing ones defaulted
+/// so config survives upgrades and downgrades
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppSettings {
+    pub local_api: LocalApiSettings,
+    pub updates: UpdateSettings,
+    pub export: ExportSettings,
+    pub webhooks: WebhookSettings,
+    pub index: IndexSettings,
+    pub sessions: SessionSettings,
+    pub estimates: EstimateSettings,
+    pub streaming: StreamSettings,
+    pub postprocess: PostProcessSettings,
+    pub debug: DebugSettings,
+    pub filesystem: FilesystemSettings,
+    pub commands: CommandSettings,
+    pub git: GitSettings,
+    pub fetch: FetchSettings,
+    pub engine: EngineConfig,
+}
+
+fn settings_path(app: &AppHandle) -> Result<PathBuf, EngineError> {
+    let dir = app
+        .path_resolver()
+        .app_config_dir()
+        .ok_or_else(|| EngineError::internal("App config directory is unavailable"))?;
+    Ok(dir.join(SETTINGS_FILE))
+}
+
+/// Current settings; a missing or unreadable file yields the defaults
+pub fn load(app: &AppHandle) -> AppSettings {
+    settings_path(app)
+        .ok()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default()
+}
+
+fn serialize(value: &impl Serialize) -> Result<Vec<u8>, EngineError> {
+    serde_json::to_vec_pretty(value)
+        .map_err(|e| EngineError::internal(format!("Failed to serialize settings: {}", e)))
+}
+
+/// Replace the file with `json`, then tell every window with `settings-changed`
+fn write(app: &AppHandle, json: &[u8], settings: &AppSettings) -> Result<(), EngineError> {
+    let path = settings_path(app)?;
+    atomic_write(&path, json, false).map_err(|e| EngineError::io(&path, e))?;
+    let _ = app.emit_all("settings-changed", settings);
+    Ok(())
+}
+
+pub fn save(app: &AppHandle, settings: &AppSettings) -> Result<(), EngineError> {
+    write(app, &serialize(settings)?, settings)
+}
+
+/// Overlay `patch` onto `base`: objects merge key by key, anything else,
+/// null included, replaces what was there
+fn merge(base: &mut Value, patch: Value) {
+    match (base, patch) {
+        (Value::Object(base), Value::Object(patch)) => {
+            for (key, value) in patch {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, patch) => *base = patch,
+    }
+}
+
+#[tauri::command]
+pub async fn get_settings(app: AppHandle) -> Result<AppSettings, EngineError> {
+    Ok(load(&app))
+}
+
+/// Merge `partial` into the settings as they are on disk now, for changing
+/// one field without writing back a copy that may have gone stale. The file
+/// itself is patched, keeping fields this version doesn't know about for the
+/// version that wrote them.
+pub fn patch(app: &AppHandle, partial: Value) -> Result<AppSettings, EngineError> {
+    if !partial.is_object() {
+        return Err(EngineError::invalid("Settings patch must be an object"));
+    }
+    let stored = settings_path(app)
+        .ok()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|data| serde_json::from_slice::<Value>(&data).ok())
+        .filter(Value::is_object);
+    let mut merged = match stored {
+        Some(stored) => stored,
+        None => serde_json::to_value(load(app))
+            .map_err(|e| EngineError::internal(format!("Failed to serialize settings: {}", e)))?,
+    };
+    merge(&mut merged, partial);
+    let settings: AppSettings = serde_json::from_value(merged.clone())
+        .map_err(|e| EngineError::invalid(format!("Invalid settings: {}", e)))?;
+    write(app, &serialize(&merged)?, &settings)?;
+    Ok(settings)
+}
+
+/// [`patch`] for the frontend, so it can change one field without sending the rest
+#[tauri::command]
+pub async fn update_settings(app: AppHandle, partial: Value) -> Result<AppSettings, EngineError> {
+    patch(&app, partial)
+}
+
+#[tauri::command]
+pub async fn reset_settings(app: AppHandle) -> Result<AppSettings, EngineError> {
+    let settings = AppSettings::default();
+    save(&app, &settings)?;
+    Ok(settings)
+}
@@ -0,0 +1,189 @@
+use crate::claude::{emit_text, StreamSink};
+use crate::hash;
+use crate::paths::{app_cache_subdir, validate_name};
+use serde::Serialize;
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::PathBuf;
+use tauri::AppHandle;
+
+pub const SPILL_DIR: &str = "spill";
+pub const DEFAULT_MAX_RESPONSE_BYTES: usize = 10 * 1024 * 1024;
+
+/// How much of a reply is kept in memory, and where the rest goes
+#[derive(Debug, Clone)]
+pub struct SpillConfig {
+    /// None when the cache directory is unavailable; output past the cap is
+    /// then still streamed but not kept
+    pub dir: Option<PathBuf>,
+    pub max_bytes: usize,
+}
+
+impl Default for SpillConfig {
+    fn default() -> Self {
+        Self {
+            dir: None,
+            max_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+        }
+    }
+}
+
+impl SpillConfig {
+    pub fn new(app: &AppHandle, max_bytes: usize) -> Self {
+        Self {
+            dir: app_cache_subdir(app, SPILL_DIR).ok(),
+            max_bytes,
+        }
+    }
+
+    /// Spill into the conversation's own folder, so deleting it takes them along
+    pub fn for_conversation(&mut self, id: &str) {
+        if let (Some(dir), Ok(id)) = (self.dir.as_mut(), validate_name(id)) {
+            dir.push(id);
+        }
+    }
+}
+
+/// Whether a reply outgrew the cap, and where all of it was written
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Spilled {
+    pub truncated: bool,
+    pub spill_path: Option<String>,
+}
+
+impl Spilled {
+    /// Add `truncated` and `spill_path` to a `claude-stream-complete` payload
+    pub fn mark(&self, payload: &mut serde_json::Value) {
+        if self.truncated {
+            payload["truncated"] = true.into();
+            payload["spill_path"] = serde_json::json!(self.spill_path);
+        }
+    }
+}
+
+/// Caps the text a stream keeps. Past the cap the reply is cut back to the
+/// first `max_bytes`, the whole of it goes to a file in the cache, and later
+/// text is streamed and appended there without being held or stop-checked.
+/// Dropped without [`Overflow::finish`], the file is removed.
+pub struct Overflow {
+    config: SpillConfig,
+    truncated: bool,
+    file: Option<(PathBuf, BufWriter<fs::File>)>,
+}
+
+impl Overflow {
+    pub fn new(config: &SpillConfig) -> Self {
+        Self {
+            config: config.clone(),
+            truncated: false,
+            file: None,
+        }
+    }
+
+    /// Past the cap; new text goes to [`Overflow::take`] instead of the reply
+    pub fn active(&self) -> bool {
+        self.truncated
+    }
+
+    pub fn take(&mut self, sink: &dyn StreamSink, text: &str) -> Result<(), String> {
+        self.write(text);
+        emit_text(sink, text)
+    }
+
+    /// Call once text has been released. If `text` has passed the cap, what
+    /// was held back is emitted and `text` is cut back to it.
+    pub fn check(
+        &mut self,
+        sink: &dyn StreamSink,
+        text: &mut String,
+        emitted: &mut usize,
+    ) -> Result<(), String> {
+        if self.truncated || text.len() <= self.config.max_bytes {
+            return Ok(());
+        }
+        if *emitted < text.len() {
+            emit_text(sink, &text[*emitted..])?;
+        }
+        self.truncated = true;
+        self.file = self.open();
+        self.write(text);
+        let mut cut = self.config.max_bytes;
+        while !text.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        text.truncate(cut);
+        *emitted = cut;
+        tracing::info!(
+            max_bytes = self.config.max_bytes,
+            spilled = self.file.is_some(),
+            "Reply passed the size cap"
+        );
+        Ok(())
+    }
+
+    fn open(&self) -> Option<(PathBuf, BufWriter<fs::File>)> {
+        let dir = self.config.dir.as_ref()?;
+        let path = dir.join(format!("{}.txt", hash::random_hex(8)));
+        let opened = fs::create_dir_all(dir).and_then(|_| fs::File::create(&path));
+        match opened {
+            Ok(file) => Some((path, BufWriter::new(file))),
+            Err(e) => {
+                tracing::warn!(path = %path.display(), error = %e, "Failed to create spill file");
+                None
+            }
+        }
+    }
+
+    fn write(&mut self, text: &str) {
+        let Some((path, file)) = self.file.as_mut() else {
+            return;
+        };
+        if let Err(e) = file.write_all(text.as_bytes()) {
+            tracing::warn!(path = %path.display(), error = %e, "Failed to write spill file");
+            self.discard();
+        }
+    }
+
+    fn discard(&mut self) {
+        if let Some((path, file)) = self.file.take() {
+            drop(file);
+            let _ = fs::remove_file(path);
+        }
+    }
+
+    pub fn finish(mut self) -> Spilled {
+        let spill_path = match self.file.take() {
+            Some((path, mut file)) => match file.flush() {
+                Ok(()) => Some(path.display().to_string()),
+                Err(e) => {
+                    tracing::warn!(path = %path.display(), error = %e, "Failed to write spill file");
+                    let _ = fs::remove_file(path);
+                    None
+                }
+            },
+            None => None,
+        };
+        Spilled {
+            truncated: self.truncated,
+            spill_path,
+        }
+    }
+}
+
+impl Drop for Overflow {
+    fn drop(&mut self) {
+        self.discard();
+    }
+}
+
+/// Drop a deleted conversation's spilled replies
+pub fn forget(app: &AppHandle, conversation_id: &str) {
+    let mut config = SpillConfig::new(app, 0);
+    let Some(root) = config.dir.clone() else {
+        return;
+    };
+    config.for_conversation(conversation_id);
+    if let Some(dir) = config.dir.filter(|dir| *dir != root) {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,150 @@
+use crate::claude;
+use crate::cli_status::{self, CliStatus};
+use crate::error::EngineError;
+use crate::history_search::HistorySearch;
+use crate::sandbox;
+use crate::scheduler;
+use crate::settings;
+use crate::tokens;
+use serde::Serialize;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+use tokio::sync::watch;
+
+// Commands only wait this long for deferred state before giving up
+const READY_WAIT: Duration = Duration::from_secs(10);
+
+static PROCESS_START: OnceLock<Instant> = OnceLock::new();
+
+/// Call first thing in `main`, so timings include Tauri's own startup
+pub fn mark_process_start() {
+    PROCESS_START.get_or_init(Instant::now);
+}
+
+fn since_start(at: Instant) -> u64 {
+    let start = *PROCESS_START.get_or_init(Instant::now);
+    at.saturating_duration_since(start).as_millis() as u64
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PhaseTiming {
+    pub name: &'static str,
+    /// Millis after process start
+    pub started_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// Tracks deferred initialization: state other commands need is loaded off
+/// the startup path, and those commands wait on `ready` instead of racing it
+pub struct Startup {
+    phases: Mutex<Vec<PhaseTiming>>,
+    ready: watch::Sender<bool>,
+    cli: Mutex<Option<CliStatus>>,
+}
+
+impl Default for Startup {
+    fn default() -> Self {
+        Self {
+            phases: Mutex::new(Vec::new()),
+            ready: watch::channel(false).0,
+            cli: Mutex::new(None),
+        }
+    }
+}
+
+impl Startup {
+    fn record(&self, name: &'static str, started: Instant) {
+        if let Ok(mut phases) = self.phases.lock() {
+            phases.push(PhaseTiming {
+                name,
+                started_ms: since_start(started),
+                duration_ms: started.elapsed().as_millis() as u64,
+            });
+        }
+    }
+
+    /// Wait for deferred state, up to a bound, rather than see it half-loaded
+    pub async fn wait_ready(&self) -> Result<(), EngineError> {
+        let mut ready = self.ready.subscribe();
+        let waited = tokio::time::timeout(READY_WAIT, ready.wait_for(|ready| *ready))
+            .await
+            .is_ok_and(|r| r.is_ok());
+        if waited {
+            Ok(())
+        } else {
+            Err(EngineError::internal(
+                "The app is still starting up; try again",
+            ))
+        }
+    }
+}
+
+/// Everything that used to run before the window could show. State first, so
+/// waiting commands unblock early; the CLI probe last, reported as
+/// `startup-check`.
+pub fn spawn_deferred_init(app: AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let startup = app.state::<Startup>();
+
+        // Before anything can be run against a workspace
+        let started = Instant::now();
+        let handle = app.clone();
+        let _ = tokio::task::spawn_blocking(move || sandbox::restore(&handle)).await;
+        startup.record("workspace", started);
+
+        let started = Instant::now();
+        let handle = app.clone();
+        let _ = tokio::task::spawn_blocking(move || scheduler::spawn(handle)).await;
+        startup.record("schedules", started);
+        startup.ready.send_replace(true);
+
+        let started = Instant::now();
+        let _ = tokio::task::spawn_blocking(tokens::warm_up).await;
+        startup.record("tokenizer", started);
+
+        let started = Instant::now();
+        let handle = app.clone();
+        let _ = tokio::task::spawn_blocking(move || handle.state::<HistorySearch>().build(&handle))
+            .await;
+        startup.record("history_index", started);
+
+        let started = Instant::now();
+        let _ = tokio::task::spawn_blocking(claude::discover_cli).await;
+        startup.record("cli_discovery", started);
+
+        let started = Instant::now();
+        let check = cli_status::probe(settings::load(&app).engine).await;
+        startup.record("cli_version", started);
+        if let Ok(mut cli) = startup.cli.lock() {
+            *cli = Some(check.clone());
+        }
+        let _ = app.emit_all("startup-check", check);
+    });
+}
+
+/// Record the synchronous part of setup, measured by the caller
+pub fn record_setup(app: &AppHandle, started: Instant) {
+    app.state::<Startup>().record("setup", started);
+}
+
+#[derive(Debug, Serialize)]
+pub struct StartupTimings {
+    pub phases: Vec<PhaseTiming>,
+    pub ready: bool,
+    pub cli: Option<CliStatus>,
+    /// Millis since process start, as of this call
+    pub uptime_ms: u64,
+}
+
+#[tauri::command]
+pub async fn get_startup_timings(
+    startup: State<'_, Startup>,
+) -> Result<StartupTimings, EngineError> {
+    Ok(StartupTimings {
+        phases: startup.phases.lock().map(|p| p.clone()).unwrap_or_default(),
+        ready: *startup.ready.borrow(),
+        cli: startup.cli.lock().ok().and_then(|c| c.clone()),
+        uptime_ms: since_start(Instant::now()),
+    })
+}
@@ -0,0 +1,264 @@
+use serde::{Deserialize, Serialize};
+
+// A longer partial line is released even with repetition checks on, so one
+// huge line can't stall the stream
+const MAX_HELD_LINE_BYTES: usize = 4096;
+
+/// Client-side stop conditions; print mode has no stop sequences of its own
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StopRules {
+    /// Stop before the first of these; the sequence itself is dropped
+    pub sequences: Vec<String>,
+    /// Stop once a non-blank line has repeated verbatim more than this many
+    /// times in a row, keeping the repeats up to the limit
+    pub max_repeated_lines: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StopReason {
+    StopSequence,
+    Repetition,
+}
+
+/// Where the text may be released up to, and whether to stop there
+pub struct Advance {
+    /// Byte offset into the text; everything before it is final
+    pub release: usize,
+    /// Set when the text should be cut at `release` and generation stopped
+    pub stop: Option<StopReason>,
+}
+
+fn floor_boundary(text: &str, mut at: usize) -> usize {
+    at = at.min(text.len());
+    while !text.is_char_boundary(at) {
+        at -= 1;
+    }
+    at
+}
+
+/// Watches a growing response for stop conditions. Text that could still turn
+/// out to be part of a stop sequence, or a repeat, is held back so nothing
+/// past the stop point is ever released.
+pub struct StopScanner<'a> {
+    rules: &'a StopRules,
+    longest: usize,
+    scanned_to: usize,
+    line_start: usize,
+    last_line: Option<String>,
+    repeats: u32,
+}
+
+impl<'a> StopScanner<'a> {
+    pub fn new(rules: &'a StopRules) -> Self {
+        Self {
+            rules,
+            longest: rules.sequences.iter().map(String::len).max().unwrap_or(0),
+            scanned_to: 0,
+            line_start: 0,
+            last_line: None,
+            repeats: 0,
+        }
+    }
+
+    fn find_sequence(&self, text: &str) -> Option<usize> {
+        let from = floor_boundary(text, self.scanned_to.saturating_sub(self.longest));
+        self.rules
+            .sequences
+            .iter()
+            .filter(|s| !s.is_empty())
+            .filter_map(|s| text[from..].find(s.as_str()).map(|i| from + i))
+            .min()
+    }
+
+    /// Start of the line that went over the repeat limit
+    fn find_repetition(&mut self, text: &str) -> Option<usize> {
+        let max = self.rules.max_repeated_lines?;
+        while let Some(newline) = text[self.line_start..].find('\n') {
+            let start = self.line_start;
+            let line = text[start..start + newline].trim_end_matches('\r');
+            self.line_start = start + newline + 1;
+            // Blank lines between repeats don't break the run
+            if line.trim().is_empty() {
+                continue;
+            }
+            if self.last_line.as_deref() == Some(line) {
+                self.repeats += 1;
+                if self.repeats > max {
+                    return Some(start);
+                }
+            } else {
+                self.last_line = Some(line.to_string());
+                self.repeats = 0;
+            }
+        }
+        None
+    }
+
+    /// Longest tail of `text` that a stop sequence may still complete
+    fn held_prefix(&self, text: &str) -> usize {
+        self.rules
+            .sequences
+            .iter()
+            .flat_map(|s| {
+                s.char_indices()
+                    .skip(1)
+                    .map(move |(i, _)| &s[..i])
+                    .filter(|prefix| text.ends_with(prefix))
+                    .map(str::len)
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Call with the whole response after each chunk is appended
+    pub fn advance(&mut self, text: &str) -> Advance {
+        let sequence = self.find_sequence(text);
+        let repetition = self.find_repetition(text);
+        let stop = match (sequence, repetition) {
+            (Some(s), Some(r)) if r < s => Some((r, StopReason::Repetition)),
+            (Some(s), _) => Some((s, StopReason::StopSequence)),
+            (None, Some(r)) => Some((r, StopReason::Repetition)),
+            (None, None) => None,
+        };
+        if let Some((at, reason)) = stop {
+            return Advance {
+                release: at,
+                stop: Some(reason),
+            };
+        }
+        self.scanned_to = text.len();
+
+        let mut release = text.len() - self.held_prefix(text);
+        if self.rules.max_repeated_lines.is_some()
+            && text.len() - self.line_start <= MAX_HELD_LINE_BYTES
+        {
+            release = release.min(self.line_start);
+        }
+        Advance {
+            release,
+            stop: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Feed `chunks` in order, as a stream would; what got released, and why
+    /// it stopped
+    fn run(rules: &StopRules, chunks: &[&str]) -> (String, Option<StopReason>) {
+        let mut scanner = StopScanner::new(rules);
+        let mut text = String::new();
+        let mut released = 0;
+        for chunk in chunks {
+            text.push_str(chunk);
+            let advance = scanner.advance(&text);
+            assert!(text.is_char_boundary(advance.release));
+            assert!(advance.release >= released, "release went backwards");
+            released = advance.release;
+            if advance.stop.is_some() {
+                return (text[..released].to_string(), advance.stop);
+            }
+        }
+        (text[..released].to_string(), None)
+    }
+
+    fn sequences(sequences: &[&str]) -> StopRules {
+        StopRules {
+            sequences: sequences.iter().map(|s| s.to_string()).collect(),
+            max_repeated_lines: None,
+        }
+    }
+
+    /// Every way of cutting `text` in two at a character boundary
+    fn splits(text: &str) -> impl Iterator<Item = (&str, &str)> {
+        (0..=text.len())
+            .filter(|&at| text.is_char_boundary(at))
+            .map(|at| text.split_at(at))
+    }
+
+    #[test]
+    fn a_sequence_split_across_chunks_still_stops() {
+        let rules = sequences(&["STOP"]);
+        for (a, b) in splits("hello STOP and more") {
+            assert_eq!(
+                run(&rules, &[a, b]),
+                ("hello ".to_string(), Some(StopReason::StopSequence)),
+                "split {:?} / {:?}",
+                a,
+                b
+            );
+        }
+        assert_eq!(
+            run(&rules, &["hello ", "S", "T", "O", "P!"]),
+            ("hello ".to_string(), Some(StopReason::StopSequence))
+        );
+    }
+
+    #[test]
+    fn a_partial_sequence_is_held_then_let_go() {
+        let rules = sequences(&["STOP"]);
+        let mut scanner = StopScanner::new(&rules);
+        assert_eq!(scanner.advance("go ST").release, 3);
+        let advance = scanner.advance("go STAY");
+        assert_eq!(advance.release, 7);
+        assert!(advance.stop.is_none());
+    }
+
+    #[test]
+    fn multibyte_sequences_and_text_stay_on_char_boundaries() {
+        let rules = sequences(&["——", "終わり"]);
+        for text in ["日本語——残り", "é👋🏽 x 終わり y", "no stop here 世界"] {
+            let expected = match text.find("——").or(text.find("終わり")) {
+                Some(at) => (text[..at].to_string(), Some(StopReason::StopSequence)),
+                None => (text.to_string(), None),
+            };
+            for (a, b) in splits(text) {
+                assert_eq!(run(&rules, &[a, b]), expected, "split {:?} / {:?}", a, b);
+            }
+            let chars: Vec<String> = text.chars().map(String::from).collect();
+            let chars: Vec<&str> = chars.iter().map(String::as_str).collect();
+            assert_eq!(run(&rules, &chars), expected, "{:?} char by char", text);
+        }
+    }
+
+    #[test]
+    fn repeats_split_across_chunks_stop_at_the_limit() {
+        let rules = StopRules {
+            sequences: Vec::new(),
+            max_repeated_lines: Some(2),
+        };
+        let text = "intro\nagain\nagain\n\nagain\nagain\nafter\n";
+        for (a, b) in splits(text) {
+            assert_eq!(
+                run(&rules, &[a, b]),
+                (
+                    "intro\nagain\nagain\n\nagain\n".to_string(),
+                    Some(StopReason::Repetition)
+                ),
+                "split {:?} / {:?}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn the_earlier_of_a_sequence_and_a_repeat_wins() {
+        let rules = StopRules {
+            sequences: vec!["END".to_string()],
+            max_repeated_lines: Some(1),
+        };
+        assert_eq!(
+            run(&rules, &["x\nx\n", "x\nEND\n"]),
+            ("x\nx\n".to_string(), Some(StopReason::Repetition))
+        );
+        assert_eq!(
+            run(&rules, &["x END\n", "x END\n"]),
+            ("x ".to_string(), Some(StopReason::StopSequence))
+        );
+    }
+}
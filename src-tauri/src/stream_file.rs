@@ -0,0 +1,184 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::CancelRegistry;
+use crate::claude::StreamSink;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::{resolve_target, AtomicFile, IfExists};
+use crate::sandbox::Workspace;
+use crate::tokens::count_tokens;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{Manager, State, Window};
+
+// Progress is for a status line, not a live view
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct StreamToFileOptions {
+    pub if_exists: IfExists,
+    /// Also emit the text as it arrives, for a live preview
+    pub tee_to_events: bool,
+    pub model: Option<String>,
+    /// Run the CLI with this workspace root's environment and directory
+    pub workspace: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StreamToFileResult {
+    pub request_id: String,
+    pub path: String,
+    pub bytes: u64,
+    /// Estimated from the written text
+    pub tokens: usize,
+    pub elapsed_ms: u64,
+}
+
+struct Progress {
+    file: Option<AtomicFile>,
+    bytes: u64,
+    tokens: usize,
+    last_emit: Instant,
+}
+
+/// Writes chunks to the temp file instead of passing them to the webview
+struct FileSink {
+    window: Window,
+    request_id: String,
+    tee: bool,
+    progress: Mutex<Progress>,
+}
+
+impl FileSink {
+    fn emit_progress(&self, bytes: u64, tokens: usize) {
+        let _ = self.window.emit(
+            "stream-to-file-progress",
+            json!({"request_id": self.request_id, "bytes": bytes, "tokens": tokens}),
+        );
+    }
+}
+
+impl StreamSink for FileSink {
+    fn emit(&self, event: &str, payload: serde_json::Value) -> Result<(), String> {
+        // Completion, errors and cancellation are reported once the file is settled
+        if event != "claude-stream-chunk" {
+            return Ok(());
+        }
+        let chunk = payload.as_str().unwrap_or_default();
+        let mut progress = self
+            .progress
+            .lock()
+            .map_err(|_| "Progress lock is poisoned".to_string())?;
+        let file = progress
+            .file
+            .as_mut()
+            .ok_or_else(|| "The output file is closed".to_string())?;
+        file.write_all(chunk.as_bytes())
+            .map_err(|e| format!("Failed to write output file: {}", e))?;
+        progress.bytes += chunk.len() as u64;
+        progress.tokens += count_tokens(chunk);
+        if self.tee {
+            let _ = self.window.emit(
+                "stream-to-file-chunk",
+                json!({"request_id": self.request_id, "chunk": chunk}),
+            );
+        }
+        if progress.last_emit.elapsed() >= PROGRESS_INTERVAL {
+            progress.last_emit = Instant::now();
+            self.emit_progress(progress.bytes, progress.tokens);
+        }
+        Ok(())
+    }
+}
+
+/// Run a prompt and write the reply straight to `dest_path`. The file only
+/// appears once the reply is complete; a failed or cancelled run leaves
+/// nothing behind.
+#[tauri::command]
+pub async fn stream_to_file(
+    window: Window,
+    message: String,
+    options: Option<StreamToFileOptions>,
+    dest_path: String,
+    request_id: String,
+    registry: State<'_, CancelRegistry>,
+    workspace: State<'_, Workspace>,
+) -> Result<StreamToFileResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let app = window.app_handle();
+    let dest = workspace.check(Path::new(&dest_path))?;
+    let target = resolve_target(&dest, options.if_exists).map_err(|e| match e.kind() {
+        std::io::ErrorKind::AlreadyExists => EngineError::AlreadyExists {
+            path: dest.display().to_string(),
+        },
+        _ => EngineError::io(&dest, e),
+    })?;
+    let root = options
+        .workspace
+        .as_deref()
+        .map(|root| workspace.check(Path::new(root)))
+        .transpose()?;
+    let mut spawn = env_profile::spawn_options(&app, root)?;
+    spawn.model = options.model.clone();
+    spawn.request_id = Some(request_id.clone());
+
+    let file = AtomicFile::create(&target).map_err(|e| EngineError::io(&target, e))?;
+    let sink = Arc::new(FileSink {
+        window: window.clone(),
+        request_id: request_id.clone(),
+        tee: options.tee_to_events,
+        progress: Mutex::new(Progress {
+            file: Some(file),
+            bytes: 0,
+            tokens: 0,
+            last_emit: Instant::now(),
+        }),
+    });
+
+    let guard = registry.register(&request_id);
+    let token = guard.token();
+    let started = Instant::now();
+    let result = app
+        .state::<ActiveBackend>()
+        .stream(
+            Arc::clone(&sink) as Arc<dyn StreamSink>,
+            message,
+            token.clone(),
+            spawn,
+        )
+        .await;
+    drop(guard);
+
+    let (file, bytes, tokens) = {
+        let mut progress = sink
+            .progress
+            .lock()
+            .map_err(|_| EngineError::internal("Progress lock is poisoned"))?;
+        (progress.file.take(), progress.bytes, progress.tokens)
+    };
+    // Dropping the uncommitted file removes the partial output
+    match result {
+        Ok(_) => {}
+        Err(_) if token.is_cancelled() => return Err(EngineError::Cancelled),
+        Err(e) => return Err(e),
+    }
+    let file = file.ok_or_else(|| EngineError::internal("The output file is closed"))?;
+    tokio::task::spawn_blocking(move || file.commit(false))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        .map_err(|e| EngineError::io(&target, e))?;
+
+    let summary = StreamToFileResult {
+        request_id,
+        path: target.display().to_string(),
+        bytes,
+        tokens,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    };
+    sink.emit_progress(bytes, tokens);
+    let _ = window.emit("stream-to-file-complete", &summary);
+    Ok(summary)
+}
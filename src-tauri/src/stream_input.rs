@@ -0,0 +1,83 @@
+use crate::claude::{InputFormat, OpenStdin};
+use crate::error::EngineError;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tauri::State;
+
+/// Streamed turns that kept their CLI's stdin open, by request id
+#[derive(Default)]
+pub struct StreamInputs {
+    open: Mutex<HashMap<String, Arc<OpenStdin>>>,
+}
+
+/// Registration for one turn; dropping it closes the input
+pub struct InputGuard<'a> {
+    inputs: &'a StreamInputs,
+    request_id: String,
+}
+
+impl Drop for InputGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(open) = self.inputs.take(&self.request_id) {
+            open.close();
+        }
+    }
+}
+
+impl StreamInputs {
+    /// Keep `request_id`'s stdin open in `format`; the returned handle goes
+    /// in the turn's spawn options
+    pub fn register(
+        &self,
+        request_id: &str,
+        format: InputFormat,
+    ) -> (InputGuard<'_>, Arc<OpenStdin>) {
+        let open = Arc::new(OpenStdin::new(format));
+        if let Ok(mut inputs) = self.open.lock() {
+            inputs.insert(request_id.to_string(), open.clone());
+        }
+        let guard = InputGuard {
+            inputs: self,
+            request_id: request_id.to_string(),
+        };
+        (guard, open)
+    }
+
+    fn get(&self, request_id: &str) -> Option<Arc<OpenStdin>> {
+        self.open.lock().ok()?.get(request_id).cloned()
+    }
+
+    fn take(&self, request_id: &str) -> Option<Arc<OpenStdin>> {
+        self.open.lock().ok()?.remove(request_id)
+    }
+}
+
+fn closed(request_id: String) -> EngineError {
+    EngineError::StreamClosed { request_id }
+}
+
+/// Write another user message to a running turn's CLI, as a stream-json
+/// line or plain text depending on how the turn asked for input. Fails with
+/// `StreamClosed` once the CLI has exited or the input was ended.
+#[tauri::command]
+pub async fn send_stream_input(
+    request_id: String,
+    text: String,
+    inputs: State<'_, StreamInputs>,
+) -> Result<(), EngineError> {
+    match inputs.get(&request_id) {
+        Some(open) if open.send(&text) => Ok(()),
+        _ => Err(closed(request_id)),
+    }
+}
+
+/// Close a running turn's stdin so the CLI can finish answering
+#[tauri::command]
+pub async fn end_stream_input(
+    request_id: String,
+    inputs: State<'_, StreamInputs>,
+) -> Result<(), EngineError> {
+    let open = inputs.take(&request_id).ok_or_else(|| closed(request_id))?;
+    open.close();
+    Ok(())
+}
@@ -0,0 +1,507 @@
+use crate::error::EngineError;
+use crate::sandbox;
+use crate::tokens::count_tokens;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::Path;
+use tauri::AppHandle;
+
+const MAX_FILE_BYTES: u64 = 64 * 1024 * 1024;
+// Rows beyond this are counted but not used for type inference
+const INFER_ROWS: usize = 1000;
+const MAX_PARSE_ERRORS: usize = 20;
+const MAX_KEYS: usize = 50;
+const SNIFF_LINES: usize = 20;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct StructuredOptions {
+    pub max_rows: usize,
+    pub max_depth: usize,
+}
+
+impl Default for StructuredOptions {
+    fn default() -> Self {
+        Self {
+            max_rows: 20,
+            max_depth: 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StructuredFormat {
+    Csv,
+    Tsv,
+    Json,
+    Jsonl,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColumnType {
+    Empty,
+    Boolean,
+    Integer,
+    Float,
+    Date,
+    String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Column {
+    pub name: String,
+    pub inferred_type: ColumnType,
+    pub nullable: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TablePreview {
+    pub delimiter: String,
+    pub columns: Vec<Column>,
+    pub row_count: usize,
+    pub column_count: usize,
+    pub sample_rows: Vec<Vec<String>>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Field {
+    pub key: String,
+    pub shape: Shape,
+}
+
+/// Type summary of a JSON value, cut off at `max_depth`
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Shape {
+    Null,
+    Boolean,
+    Number,
+    String,
+    Array {
+        length: usize,
+        /// Shape of the first element
+        items: Option<Box<Shape>>,
+    },
+    Object {
+        key_count: usize,
+        fields: Vec<Field>,
+    },
+    /// Deeper than `max_depth`
+    Truncated,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ParseError {
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StructuredPreview {
+    pub path: String,
+    pub format: StructuredFormat,
+    pub bytes: u64,
+    pub lines: usize,
+    pub table: Option<TablePreview>,
+    pub shape: Option<Shape>,
+    /// JSONL only: number of lines that parsed
+    pub records: Option<usize>,
+    pub parse_errors: Vec<ParseError>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StructuredSummary {
+    pub text: String,
+    pub tokens: usize,
+    pub truncated: bool,
+}
+
+fn detect_format(path: &Path, text: &str) -> Option<StructuredFormat> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_ascii_lowercase());
+    match ext.as_deref() {
+        Some("csv") => return Some(StructuredFormat::Csv),
+        Some("tsv" | "tab") => return Some(StructuredFormat::Tsv),
+        Some("json" | "geojson") => return Some(StructuredFormat::Json),
+        Some("jsonl" | "ndjson") => return Some(StructuredFormat::Jsonl),
+        _ => {}
+    }
+    match text.trim_start().chars().next() {
+        Some('{' | '[') => Some(StructuredFormat::Json),
+        _ => None,
+    }
+}
+
+/// The candidate that splits the first lines into the same (>1) number of fields
+fn sniff_delimiter(text: &str) -> u8 {
+    let lines: Vec<&str> = text
+        .lines()
+        .filter(|l| !l.trim().is_empty())
+        .take(SNIFF_LINES)
+        .collect();
+    let mut best = (b',', 0usize);
+    for candidate in [b',', b'\t', b';', b'|'] {
+        let counts: Vec<usize> = lines
+            .iter()
+            .map(|l| l.bytes().filter(|&b| b == candidate).count())
+            .collect();
+        let Some(&first) = counts.first() else {
+            continue;
+        };
+        let consistent = counts.iter().filter(|&&c| c == first).count();
+        if first > 0 && consistent > best.1 {
+            best = (candidate, consistent);
+        }
+    }
+    best.0
+}
+
+fn cell_type(cell: &str) -> ColumnType {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        ColumnType::Empty
+    } else if matches!(
+        cell.to_ascii_lowercase().as_str(),
+        "true" | "false" | "yes" | "no"
+    ) {
+        ColumnType::Boolean
+    } else if cell.parse::<i64>().is_ok() {
+        ColumnType::Integer
+    } else if cell.parse::<f64>().is_ok() {
+        ColumnType::Float
+    } else if looks_like_date(cell) {
+        ColumnType::Date
+    } else {
+        ColumnType::String
+    }
+}
+
+/// `YYYY-MM-DD`, optionally followed by a time
+fn looks_like_date(cell: &str) -> bool {
+    let b = cell.as_bytes();
+    b.len() >= 10
+        && b[..4].iter().all(u8::is_ascii_digit)
+        && b[4] == b'-'
+        && b[5..7].iter().all(u8::is_ascii_digit)
+        && b[7] == b'-'
+        && b[8..10].iter().all(u8::is_ascii_digit)
+        && (b.len() == 10 || matches!(b[10], b'T' | b' '))
+}
+
+fn merge_type(a: ColumnType, b: ColumnType) -> ColumnType {
+    use ColumnType::*;
+    match (a, b) {
+        (x, Empty) | (Empty, x) => x,
+        (x, y) if x == y => x,
+        (Integer, Float) | (Float, Integer) => Float,
+        _ => String,
+    }
+}
+
+fn preview_table(
+    text: &str,
+    delimiter: u8,
+    options: &StructuredOptions,
+    errors: &mut Vec<ParseError>,
+) -> TablePreview {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .flexible(true)
+        .from_reader(text.as_bytes());
+    let headers: Vec<String> = match reader.headers() {
+        Ok(h) => h.iter().map(|s| s.trim().to_string()).collect(),
+        Err(e) => {
+            errors.push(ParseError {
+                line: Some(1),
+                message: e.to_string(),
+            });
+            Vec::new()
+        }
+    };
+
+    let mut types = vec![ColumnType::Empty; headers.len()];
+    let mut nullable = vec![false; headers.len()];
+    let mut sample_rows = Vec::new();
+    let mut row_count = 0;
+    for record in reader.records() {
+        let record = match record {
+            Ok(r) => r,
+            Err(e) => {
+                if errors.len() < MAX_PARSE_ERRORS {
+                    errors.push(ParseError {
+                        line: e.position().map(|p| p.line() as usize),
+                        message: e.to_string(),
+                    });
+                }
+                continue;
+            }
+        };
+        row_count += 1;
+        if record.len() != headers.len() && errors.len() < MAX_PARSE_ERRORS {
+            errors.push(ParseError {
+                line: record.position().map(|p| p.line() as usize),
+                message: format!("expected {} fields, found {}", headers.len(), record.len()),
+            });
+        }
+        if row_count <= INFER_ROWS {
+            for (i, cell) in record.iter().enumerate().take(headers.len()) {
+                let t = cell_type(cell);
+                nullable[i] |= t == ColumnType::Empty;
+                types[i] = merge_type(types[i], t);
+            }
+        }
+        if sample_rows.len() < options.max_rows {
+            sample_rows.push(record.iter().map(str::to_string).collect());
+        }
+    }
+
+    let columns = headers
+        .into_iter()
+        .zip(types.into_iter().zip(nullable))
+        .map(|(name, (inferred_type, nullable))| Column {
+            name,
+            inferred_type,
+            nullable,
+        })
+        .collect::<Vec<_>>();
+    TablePreview {
+        delimiter: (delimiter as char).to_string(),
+        column_count: columns.len(),
+        columns,
+        row_count,
+        sample_rows,
+    }
+}
+
+pub fn shape_of(value: &Value, depth: usize, max_depth: usize) -> Shape {
+    if depth > max_depth {
+        return Shape::Truncated;
+    }
+    match value {
+        Value::Null => Shape::Null,
+        Value::Bool(_) => Shape::Boolean,
+        Value::Number(_) => Shape::Number,
+        Value::String(_) => Shape::String,
+        Value::Array(items) => Shape::Array {
+            length: items.len(),
+            items: items
+                .first()
+                .map(|first| Box::new(shape_of(first, depth + 1, max_depth))),
+        },
+        Value::Object(map) => Shape::Object {
+            key_count: map.len(),
+            fields: map
+                .iter()
+                .take(MAX_KEYS)
+                .map(|(key, value)| Field {
+                    key: key.clone(),
+                    shape: shape_of(value, depth + 1, max_depth),
+                })
+                .collect(),
+        },
+    }
+}
+
+pub fn preview(path: &Path, options: &StructuredOptions) -> Result<StructuredPreview, EngineError> {
+    let bytes = std::fs::metadata(path)
+        .map_err(|e| EngineError::io(path, e))?
+        .len();
+    if bytes > MAX_FILE_BYTES {
+        return Err(EngineError::invalid(format!(
+            "{} is {} bytes; the preview limit is {}",
+            path.display(),
+            bytes,
+            MAX_FILE_BYTES
+        )));
+    }
+    let data = std::fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    let text = String::from_utf8_lossy(&data);
+    let format = detect_format(path, &text).ok_or_else(|| {
+        EngineError::invalid(format!("{} is not a CSV, TSV or JSON file", path.display()))
+    })?;
+
+    let mut parse_errors = Vec::new();
+    let mut table = None;
+    let mut shape = None;
+    let mut records = None;
+    match format {
+        StructuredFormat::Csv | StructuredFormat::Tsv => {
+            let delimiter = match format {
+                StructuredFormat::Tsv => b'\t',
+                _ => sniff_delimiter(&text),
+            };
+            table = Some(preview_table(&text, delimiter, options, &mut parse_errors));
+        }
+        StructuredFormat::Json => match serde_json::from_str::<Value>(&text) {
+            Ok(value) => shape = Some(shape_of(&value, 0, options.max_depth)),
+            Err(e) => parse_errors.push(ParseError {
+                line: Some(e.line()),
+                message: e.to_string(),
+            }),
+        },
+        StructuredFormat::Jsonl => {
+            let mut parsed = 0;
+            for (index, line) in text.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str::<Value>(line) {
+                    Ok(value) => {
+                        parsed += 1;
+                        if shape.is_none() {
+                            shape = Some(shape_of(&value, 0, options.max_depth));
+                        }
+                    }
+                    Err(e) if parse_errors.len() < MAX_PARSE_ERRORS => {
+                        parse_errors.push(ParseError {
+                            line: Some(index + 1),
+                            message: e.to_string(),
+                        })
+                    }
+                    Err(_) => {}
+                }
+            }
+            records = Some(parsed);
+        }
+    }
+
+    Ok(StructuredPreview {
+        path: path.display().to_string(),
+        format,
+        bytes,
+        lines: text.lines().count(),
+        table,
+        shape,
+        records,
+        parse_errors,
+    })
+}
+
+fn describe_shape(shape: &Shape, indent: usize, out: &mut String) {
+    let pad = "  ".repeat(indent);
+    match shape {
+        Shape::Object { key_count, fields } => {
+            out.push_str(&format!("object ({} keys)\n", key_count));
+            for field in fields {
+                out.push_str(&format!("{}  {}: ", pad, field.key));
+                describe_shape(&field.shape, indent + 1, out);
+            }
+        }
+        Shape::Array { length, items } => {
+            out.push_str(&format!("array ({} items)", length));
+            match items {
+                Some(items) => {
+                    out.push_str(" of ");
+                    describe_shape(items, indent, out);
+                }
+                None => out.push('\n'),
+            }
+        }
+        Shape::Null => out.push_str("null\n"),
+        Shape::Boolean => out.push_str("boolean\n"),
+        Shape::Number => out.push_str("number\n"),
+        Shape::String => out.push_str("string\n"),
+        Shape::Truncated => out.push_str("…\n"),
+    }
+}
+
+/// Schema plus as many sample rows as fit in `token_budget`
+pub fn summarize_for_prompt(preview: &StructuredPreview, token_budget: usize) -> StructuredSummary {
+    let mut text = format!(
+        "File: {} ({:?}, {} bytes, {} lines)\n",
+        preview.path, preview.format, preview.bytes, preview.lines
+    );
+    let mut samples = Vec::new();
+    if let Some(table) = &preview.table {
+        text.push_str(&format!(
+            "{} rows x {} columns\nColumns:\n",
+            table.row_count, table.column_count
+        ));
+        for column in &table.columns {
+            text.push_str(&format!(
+                "- {}: {:?}{}\n",
+                column.name,
+                column.inferred_type,
+                if column.nullable { " (nullable)" } else { "" }
+            ));
+        }
+        let delimiter = table.delimiter.clone();
+        samples = table
+            .sample_rows
+            .iter()
+            .map(|row| row.join(&delimiter))
+            .collect();
+        if !samples.is_empty() {
+            let header: Vec<&str> = table.columns.iter().map(|c| c.name.as_str()).collect();
+            samples.insert(0, header.join(&delimiter));
+        }
+    }
+    if let Some(records) = preview.records {
+        text.push_str(&format!("{} records\n", records));
+    }
+    if let Some(shape) = &preview.shape {
+        text.push_str("Shape: ");
+        describe_shape(shape, 0, &mut text);
+    }
+    if !preview.parse_errors.is_empty() {
+        text.push_str(&format!(
+            "{} parse error(s), first: {}\n",
+            preview.parse_errors.len(),
+            preview.parse_errors[0].message
+        ));
+    }
+
+    let mut tokens = count_tokens(&text);
+    let mut truncated = tokens > token_budget;
+    if !samples.is_empty() && !truncated {
+        text.push_str("Sample:\n");
+        tokens = count_tokens(&text);
+        for line in samples {
+            let cost = count_tokens(&line) + 1;
+            if tokens + cost > token_budget {
+                truncated = true;
+                break;
+            }
+            text.push_str(&line);
+            text.push('\n');
+            tokens += cost;
+        }
+    }
+    StructuredSummary {
+        text,
+        tokens,
+        truncated,
+    }
+}
+
+#[tauri::command]
+pub async fn preview_structured_file(
+    app: AppHandle,
+    path: String,
+    options: Option<StructuredOptions>,
+) -> Result<StructuredPreview, EngineError> {
+    let options = options.unwrap_or_default();
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || preview(&path, &options))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+#[tauri::command]
+pub async fn summarize_structured_file_for_prompt(
+    app: AppHandle,
+    path: String,
+    token_budget: usize,
+) -> Result<StructuredSummary, EngineError> {
+    let path = sandbox::allow(&app, &path)?;
+    tokio::task::spawn_blocking(move || {
+        let preview = preview(&path, &StructuredOptions::default())?;
+        Ok(summarize_for_prompt(&preview, token_budget))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
@@ -0,0 +1,608 @@
+use crate::backend::ActiveBackend;
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::claude::NullSink;
+use crate::context::render_section;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::looks_binary;
+use crate::hash::random_hex;
+use crate::languages;
+use crate::sandbox::Workspace;
+use crate::symbols;
+use crate::tokens::count_tokens;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Instant;
+use tauri::{AppHandle, Manager, State, Window};
+
+const MAX_FILE_BYTES: u64 = 10 * 1024 * 1024;
+// Instructions and framing around the file text
+const PROMPT_OVERHEAD_TOKENS: usize = 1_000;
+// Left free for the model's answer
+const OUTPUT_RESERVE_TOKENS: usize = 8_000;
+// Summaries that never shrink below the budget would loop forever
+const MAX_REDUCE_LEVELS: usize = 4;
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SummaryStyle {
+    #[default]
+    Overview,
+    Detailed,
+    Eli5,
+}
+
+impl SummaryStyle {
+    fn instruction(self) -> &'static str {
+        match self {
+            SummaryStyle::Overview => {
+                "Give a short overview of this file: its purpose, its main parts and how they fit together."
+            }
+            SummaryStyle::Detailed => {
+                "Explain this file in detail: its purpose, each major component, notable logic and edge cases."
+            }
+            SummaryStyle::Eli5 => {
+                "Explain what this file does in plain language, as if to someone new to programming."
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SummarizeOptions {
+    pub style: SummaryStyle,
+    pub model: Option<String>,
+    pub request_id: Option<String>,
+    /// The model's context window
+    pub context_tokens: usize,
+    /// Upper bound for one map-stage chunk
+    pub chunk_tokens: usize,
+}
+
+impl Default for SummarizeOptions {
+    fn default() -> Self {
+        Self {
+            style: SummaryStyle::default(),
+            model: None,
+            request_id: None,
+            context_tokens: 150_000,
+            chunk_tokens: 20_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryStrategy {
+    Inline,
+    MapReduce,
+}
+
+#[derive(Debug, Serialize)]
+pub struct StageInfo {
+    /// "inline", "map", "reduce" or "final"
+    pub stage: &'static str,
+    pub index: usize,
+    pub input_tokens: usize,
+    pub duration_ms: u64,
+    /// Filled in once the CLI reports usage
+    pub cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SummaryResult {
+    pub request_id: String,
+    pub summary: String,
+    pub strategy: SummaryStrategy,
+    pub file_tokens: usize,
+    pub chunks: usize,
+    pub stages: Vec<StageInfo>,
+    pub total_cost_usd: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chunk {
+    /// 0-based, end exclusive
+    pub start_line: usize,
+    pub end_line: usize,
+    pub text: String,
+    pub tokens: usize,
+}
+
+/// Lines where a chunk may start: definitions in code, headings in markdown
+fn split_points(path: &Path, text: &str) -> Vec<usize> {
+    let points = symbols::definition_lines(path, text);
+    if !points.is_empty() {
+        return points;
+    }
+    if languages::detect_path(path).is_some_and(|d| d.language == "markdown") {
+        let mut in_fence = false;
+        return text
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| {
+                if line.trim_start().starts_with("```") {
+                    in_fence = !in_fence;
+                }
+                !in_fence && line.starts_with('#')
+            })
+            .map(|(index, _)| index)
+            .collect();
+    }
+    Vec::new()
+}
+
+/// Fixed windows for a piece with no usable split points. A single line over
+/// the budget is cut by characters, at a conservative 3 chars per token.
+fn windows(lines: &[&str], first_line: usize, max_tokens: usize) -> Vec<Chunk> {
+    let mut out = Vec::new();
+    let mut current = Chunk {
+        start_line: first_line,
+        end_line: first_line,
+        text: String::new(),
+        tokens: 0,
+    };
+    for (offset, line) in lines.iter().enumerate() {
+        let line_no = first_line + offset;
+        let tokens = count_tokens(line);
+        if tokens > max_tokens {
+            if !current.text.is_empty() {
+                out.push(std::mem::replace(
+                    &mut current,
+                    Chunk {
+                        start_line: line_no,
+                        end_line: line_no,
+                        text: String::new(),
+                        tokens: 0,
+                    },
+                ));
+            }
+            let chars: Vec<char> = line.chars().collect();
+            for piece in chars.chunks(max_tokens.max(1) * 3) {
+                let text: String = piece.iter().collect();
+                out.push(Chunk {
+                    start_line: line_no,
+                    end_line: line_no + 1,
+                    tokens: count_tokens(&text),
+                    text,
+                });
+            }
+            current.start_line = line_no + 1;
+            current.end_line = line_no + 1;
+            continue;
+        }
+        if current.tokens + tokens > max_tokens && !current.text.is_empty() {
+            out.push(std::mem::replace(
+                &mut current,
+                Chunk {
+                    start_line: line_no,
+                    end_line: line_no,
+                    text: String::new(),
+                    tokens: 0,
+                },
+            ));
+        }
+        current.text.push_str(line);
+        current.tokens += tokens;
+        current.end_line = line_no + 1;
+    }
+    if !current.text.is_empty() {
+        out.push(current);
+    }
+    out
+}
+
+/// Cut `text` into chunks of at most `max_tokens`, packing whole pieces between
+/// split points together and falling back to fixed windows inside oversized ones
+pub fn chunk_text(text: &str, split_points: &[usize], max_tokens: usize) -> Vec<Chunk> {
+    let lines: Vec<&str> = text.split_inclusive('\n').collect();
+    let mut starts: Vec<usize> = split_points
+        .iter()
+        .copied()
+        .filter(|&p| p > 0 && p < lines.len())
+        .collect();
+    starts.insert(0, 0);
+    starts.dedup();
+
+    let mut out: Vec<Chunk> = Vec::new();
+    let mut current: Option<Chunk> = None;
+    for (i, &start) in starts.iter().enumerate() {
+        let end = starts.get(i + 1).copied().unwrap_or(lines.len());
+        let piece_text: String = lines[start..end].concat();
+        let piece_tokens = count_tokens(&piece_text);
+
+        if piece_tokens > max_tokens {
+            out.extend(current.take());
+            out.extend(windows(&lines[start..end], start, max_tokens));
+            continue;
+        }
+        match current.as_mut() {
+            Some(chunk) if chunk.tokens + piece_tokens <= max_tokens => {
+                chunk.text.push_str(&piece_text);
+                chunk.tokens += piece_tokens;
+                chunk.end_line = end;
+            }
+            _ => {
+                out.extend(current.take());
+                current = Some(Chunk {
+                    start_line: start,
+                    end_line: end,
+                    text: piece_text,
+                    tokens: piece_tokens,
+                });
+            }
+        }
+    }
+    out.extend(current);
+    out
+}
+
+/// Tokens the file itself may use in one prompt
+pub fn input_budget(context_tokens: usize) -> usize {
+    context_tokens.saturating_sub(PROMPT_OVERHEAD_TOKENS + OUTPUT_RESERVE_TOKENS)
+}
+
+/// Map-stage chunk size: as asked, but never more than fits in one prompt
+fn chunk_budget(options: &SummarizeOptions) -> usize {
+    options
+        .chunk_tokens
+        .clamp(1, input_budget(options.context_tokens).max(1))
+}
+
+struct Summarizer<'a> {
+    window: &'a Window,
+    app: AppHandle,
+    request_id: &'a str,
+    model: Option<String>,
+    token: CancelToken,
+    stages: Vec<StageInfo>,
+}
+
+impl Summarizer<'_> {
+    async fn ask(
+        &mut self,
+        stage: &'static str,
+        index: usize,
+        total: usize,
+        prompt: String,
+    ) -> Result<String, EngineError> {
+        if self.token.is_cancelled() {
+            return Err(EngineError::Cancelled);
+        }
+        let progress = |status: &str| {
+            json!({
+                "request_id": self.request_id,
+                "stage": stage,
+                "index": index,
+                "total": total,
+                "status": status,
+            })
+        };
+        let _ = self.window.emit("summarize-progress", progress("started"));
+
+        let mut spawn = env_profile::spawn_options(&self.app, None)?;
+        spawn.model = self.model.clone();
+        spawn.request_id = Some(self.request_id.to_string());
+        let input_tokens = count_tokens(&prompt);
+        let started = Instant::now();
+        let result = self
+            .app
+            .state::<ActiveBackend>()
+            .stream(Arc::new(NullSink), prompt, self.token.clone(), spawn)
+            .await;
+        let output = match result {
+            Ok(reply) => reply.text,
+            Err(_) if self.token.is_cancelled() => return Err(EngineError::Cancelled),
+            Err(e) => return Err(e),
+        };
+        self.stages.push(StageInfo {
+            stage,
+            index,
+            input_tokens,
+            duration_ms: started.elapsed().as_millis() as u64,
+            cost_usd: None,
+        });
+        let _ = self
+            .window
+            .emit("summarize-progress", progress("completed"));
+        Ok(output)
+    }
+}
+
+fn read_guarded(path: &Path) -> Result<String, EngineError> {
+    let size = std::fs::metadata(path)
+        .map_err(|e| EngineError::io(path, e))?
+        .len();
+    if size > MAX_FILE_BYTES {
+        return Err(EngineError::invalid(format!(
+            "{} is {} MB; files over {} MB can't be summarized",
+            path.display(),
+            size / (1024 * 1024),
+            MAX_FILE_BYTES / (1024 * 1024)
+        )));
+    }
+    let bytes = std::fs::read(path).map_err(|e| EngineError::io(path, e))?;
+    if looks_binary(&bytes) {
+        return Err(EngineError::invalid(format!(
+            "{} is a binary file",
+            path.display()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// Summarize one file: inline when it fits the context budget, otherwise
+/// summarize chunks and then the summaries
+#[tauri::command]
+pub async fn summarize_file(
+    window: Window,
+    path: String,
+    options: Option<SummarizeOptions>,
+    registry: State<'_, CancelRegistry>,
+    workspace: State<'_, Workspace>,
+) -> Result<SummaryResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let path = workspace.check(Path::new(&path))?;
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+    let request_id = options
+        .request_id
+        .clone()
+        .unwrap_or_else(|| format!("summarize-{}", random_hex(6)));
+
+    let (text, file_tokens, chunks) = {
+        let path = path.clone();
+        let budget = input_budget(options.context_tokens);
+        let chunk_tokens = chunk_budget(&options);
+        tokio::task::spawn_blocking(move || {
+            let text = read_guarded(&path)?;
+            let file_tokens = count_tokens(&text);
+            let chunks = if file_tokens <= budget {
+                Vec::new()
+            } else {
+                chunk_text(&text, &split_points(&path, &text), chunk_tokens)
+            };
+            Ok::<_, EngineError>((text, file_tokens, chunks))
+        })
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??
+    };
+
+    let guard = registry.register(&request_id);
+    let mut summarizer = Summarizer {
+        window: &window,
+        app: window.app_handle(),
+        request_id: &request_id,
+        model: options.model.clone(),
+        token: guard.token(),
+        stages: Vec::new(),
+    };
+    let instruction = options.style.instruction();
+
+    if chunks.is_empty() {
+        let prompt = format!("{}\n\n{}", instruction, render_section(&name, &text));
+        let summary = summarizer.ask("inline", 0, 1, prompt).await?;
+        return Ok(SummaryResult {
+            request_id: request_id.clone(),
+            summary,
+            strategy: SummaryStrategy::Inline,
+            file_tokens,
+            chunks: 1,
+            stages: summarizer.stages,
+            total_cost_usd: None,
+        });
+    }
+
+    let total = chunks.len();
+    let mut summaries = Vec::with_capacity(total);
+    for (index, chunk) in chunks.iter().enumerate() {
+        let prompt = format!(
+            "This is part {} of {} (lines {}-{}) of {}. Summarize this part so it can be \
+             combined with summaries of the other parts later. Keep the names of functions, \
+             types and other key details.\n\n{}",
+            index + 1,
+            total,
+            chunk.start_line + 1,
+            chunk.end_line,
+            name,
+            render_section(&name, &chunk.text)
+        );
+        summaries.push(summarizer.ask("map", index, total, prompt).await?);
+    }
+
+    // Merge groups of summaries until they all fit in one prompt
+    let budget = input_budget(options.context_tokens);
+    let mut level = 0;
+    while count_tokens(&summaries.join("\n\n")) > budget {
+        level += 1;
+        // Split points at each summary, so groups hold whole summaries where possible
+        let mut joined = String::new();
+        let mut points = Vec::with_capacity(summaries.len());
+        for summary in &summaries {
+            points.push(joined.lines().count());
+            joined.push_str(summary.trim_end());
+            joined.push_str("\n\n---\n\n");
+        }
+        let groups = chunk_text(&joined, &points, budget);
+        if level > MAX_REDUCE_LEVELS || groups.len() >= summaries.len() {
+            return Err(EngineError::internal(
+                "Chunk summaries are too long to combine within the context budget",
+            ));
+        }
+        let total = groups.len();
+        let mut merged = Vec::with_capacity(total);
+        for (index, group) in groups.into_iter().enumerate() {
+            let prompt = format!(
+                "These are summaries of consecutive parts of {}. Merge them into one \
+                 summary, keeping the important details.\n\n{}",
+                name, group.text
+            );
+            merged.push(summarizer.ask("reduce", index, total, prompt).await?);
+        }
+        summaries = merged;
+    }
+
+    let prompt = format!(
+        "{} Base it on these summaries of consecutive parts of {}:\n\n{}",
+        instruction,
+        name,
+        summaries.join("\n\n---\n\n")
+    );
+    let summary = summarizer.ask("final", 0, 1, prompt).await?;
+    Ok(SummaryResult {
+        request_id: request_id.clone(),
+        summary,
+        strategy: SummaryStrategy::MapReduce,
+        file_tokens,
+        chunks: total,
+        stages: summarizer.stages,
+        total_cost_usd: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// `count` Rust functions of `body_lines` lines each
+    fn rust_file(count: usize, body_lines: usize) -> String {
+        let mut text = String::from("use std::collections::HashMap;\n\n");
+        for f in 0..count {
+            text.push_str(&format!(
+                "pub fn handler_{}(map: &mut HashMap<u32, u32>) {{\n",
+                f
+            ));
+            for line in 0..body_lines {
+                text.push_str(&format!("    map.insert({}, {});\n", line, f * line));
+            }
+            text.push_str("}\n\n");
+        }
+        text
+    }
+
+    /// Chunks are in order, lose nothing and each fits `max_tokens`
+    fn assert_sound(text: &str, chunks: &[Chunk], max_tokens: usize) {
+        assert_eq!(
+            chunks.iter().map(|c| c.text.as_str()).collect::<String>(),
+            text
+        );
+        for pair in chunks.windows(2) {
+            assert!(pair[0].start_line <= pair[1].start_line);
+            assert!(pair[0].end_line <= pair[1].end_line);
+        }
+        for chunk in chunks {
+            assert!(chunk.start_line < chunk.end_line, "{:?}", chunk);
+            assert!(
+                chunk.tokens <= max_tokens,
+                "{} > {}",
+                chunk.tokens,
+                max_tokens
+            );
+            assert!(count_tokens(&chunk.text) <= max_tokens);
+        }
+    }
+
+    #[test]
+    fn code_splits_between_definitions() {
+        let text = rust_file(400, 30);
+        let path = Path::new("big.rs");
+        let points = split_points(path, &text);
+        assert_eq!(points.len(), 400);
+
+        let max_tokens = 2_000;
+        let chunks = chunk_text(&text, &points, max_tokens);
+        assert_sound(&text, &chunks, max_tokens);
+        assert!(chunks.len() > 1);
+        for chunk in &chunks[1..] {
+            assert!(points.contains(&chunk.start_line), "{}", chunk.start_line);
+            assert!(chunk.text.starts_with("pub fn handler_"));
+        }
+        // Packed: each chunk had no room left for the function that starts the next
+        let lines: Vec<&str> = text.split_inclusive('\n').collect();
+        for pair in chunks.windows(2) {
+            let start = pair[1].start_line;
+            let end = points.iter().find(|&&p| p > start).copied();
+            let next = lines[start..end.unwrap_or(lines.len())].concat();
+            assert!(pair[0].tokens + count_tokens(&next) > max_tokens);
+        }
+    }
+
+    #[test]
+    fn an_oversized_definition_falls_back_to_windows() {
+        let mut text = rust_file(3, 10);
+        text.push_str(&rust_file(1, 2_000));
+        text.push_str(&rust_file(3, 10));
+        let points = split_points(Path::new("mixed.rs"), &text);
+        let max_tokens = 1_000;
+        let chunks = chunk_text(&text, &points, max_tokens);
+        assert_sound(&text, &chunks, max_tokens);
+        let windowed = chunks
+            .iter()
+            .filter(|c| !points.contains(&c.start_line))
+            .count();
+        assert!(windowed > 5, "{}", windowed);
+        assert!(chunks.last().unwrap().text.starts_with("pub fn handler_0"));
+    }
+
+    #[test]
+    fn text_without_split_points_uses_fixed_windows() {
+        let text: String = (0..20_000)
+            .map(|i| format!("log line {} with a few plain words in it\n", i))
+            .collect();
+        let path = Path::new("server.log");
+        assert!(split_points(path, &text).is_empty());
+        let max_tokens = 3_000;
+        let chunks = chunk_text(&text, &[], max_tokens);
+        assert_sound(&text, &chunks, max_tokens);
+        assert!(chunks.len() > 20);
+        for chunk in &chunks[..chunks.len() - 1] {
+            assert!(chunk.tokens > max_tokens - 30, "{}", chunk.tokens);
+        }
+    }
+
+    #[test]
+    fn a_single_huge_line_is_cut_by_characters() {
+        let line = "var a=function(b){return b*2};".repeat(20_000);
+        let text = format!("{}\nconsole.log(a(1));\n", line);
+        let max_tokens = 4_000;
+        let chunks = chunk_text(&text, &[], max_tokens);
+        assert_sound(&text, &chunks, max_tokens);
+        assert!(chunks.len() > 10);
+        assert!(chunks[..chunks.len() - 1].iter().all(|c| c.start_line == 0));
+        assert_eq!(chunks.last().unwrap().start_line, 1);
+    }
+
+    #[test]
+    fn markdown_splits_at_headings_outside_fences() {
+        let text = "# Title\nintro\n```sh\n# not a heading\n```\n## Usage\nrun it\n";
+        assert_eq!(split_points(Path::new("README.md"), text), [0, 5]);
+        let first = count_tokens("# Title\nintro\n```sh\n# not a heading\n```\n");
+        let chunks = chunk_text(text, &[0, 5], first);
+        let starts: Vec<usize> = chunks.iter().map(|c| c.start_line).collect();
+        assert_eq!(starts, [0, 5]);
+    }
+
+    #[test]
+    fn budgets_leave_room_for_the_prompt_and_answer() {
+        assert_eq!(input_budget(150_000), 141_000);
+        assert_eq!(input_budget(9_000), 0);
+        assert_eq!(input_budget(100), 0);
+
+        let with = |context_tokens, chunk_tokens| {
+            chunk_budget(&SummarizeOptions {
+                context_tokens,
+                chunk_tokens,
+                ..SummarizeOptions::default()
+            })
+        };
+        assert_eq!(with(150_000, 20_000), 20_000);
+        assert_eq!(with(20_000, 50_000), 11_000);
+        assert_eq!(with(150_000, 0), 1);
+        assert_eq!(with(100, 20_000), 1);
+    }
+}
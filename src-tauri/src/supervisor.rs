@@ -0,0 +1,570 @@
+use crate::clock::now_millis;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::runner::{self, find_program, split_command, RunSpec};
+use crate::sandbox::Workspace;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::async_runtime::JoinHandle;
+use tauri::{AppHandle, Manager, State};
+use tokio::process::Child;
+use tokio::sync::Notify;
+
+// A run this long counts as healthy, so the backoff starts over
+const HEALTHY_RUN: Duration = Duration::from_secs(60);
+const SHUTDOWN_WAIT: Duration = Duration::from_secs(3);
+
+/// Starts a fresh child each time the supervisor (re)starts it
+pub type Launcher = Box<dyn Fn() -> std::io::Result<Child> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum RestartPolicy {
+    Never,
+    /// Restart after a non-zero exit, giving up after `max_restarts` in a row
+    OnFailure {
+        max_restarts: u32,
+    },
+    /// Restart after any exit, giving up after `max_restarts` in a row
+    Always {
+        max_restarts: u32,
+    },
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy::OnFailure { max_restarts: 5 }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProcessState {
+    Running,
+    Restarting,
+    Exited,
+    GaveUp,
+    Stopped,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SupervisedStatus {
+    pub name: String,
+    pub state: ProcessState,
+    pub policy: RestartPolicy,
+    pub pid: Option<u32>,
+    pub restarts: u32,
+    pub last_exit_code: Option<i32>,
+    /// Why the last spawn failed, if it did
+    pub last_error: Option<String>,
+    pub started_at: Option<u64>,
+}
+
+struct Entry {
+    status: Arc<Mutex<SupervisedStatus>>,
+    stop: Arc<Notify>,
+    task: JoinHandle<()>,
+}
+
+/// Delay before a restart: `initial`, doubled per consecutive failure up to `max`
+#[derive(Debug, Clone, Copy)]
+struct Backoff {
+    initial: Duration,
+    max: Duration,
+}
+
+impl Backoff {
+    const DEFAULT: Backoff = Backoff {
+        initial: Duration::from_millis(500),
+        max: Duration::from_secs(30),
+    };
+
+    fn delay(self, failures: u32) -> Duration {
+        self.initial
+            .saturating_mul(2u32.saturating_pow(failures))
+            .min(self.max)
+    }
+}
+
+fn emit(app: &AppHandle, name: &str, event: &str, exit_code: Option<i32>) {
+    let _ = app.emit_all(
+        "supervised-process-event",
+        json!({"name": name, "event": event, "exit_code": exit_code}),
+    );
+}
+
+fn update(status: &Mutex<SupervisedStatus>, change: impl FnOnce(&mut SupervisedStatus)) {
+    if let Ok(mut status) = status.lock() {
+        change(&mut status);
+    }
+}
+
+/// Run and restart one child until its policy gives up or `stop` fires.
+/// `emit` gets each lifecycle event and the exit code that caused it.
+async fn supervise(
+    policy: RestartPolicy,
+    backoff: Backoff,
+    launcher: Launcher,
+    status: Arc<Mutex<SupervisedStatus>>,
+    stop: Arc<Notify>,
+    emit: impl Fn(&str, Option<i32>),
+) {
+    let mut failures = 0;
+    loop {
+        let started = Instant::now();
+        // A spawn error is treated like a failed exit, so it backs off the same way
+        let (exit_code, success) = match launcher() {
+            Ok(mut child) => {
+                update(&status, |s| {
+                    s.state = ProcessState::Running;
+                    s.pid = child.id();
+                    s.last_error = None;
+                    s.started_at = Some(now_millis());
+                });
+                emit("started", None);
+                tokio::select! {
+                    exit = child.wait() => match exit {
+                        Ok(exit) => (exit.code(), exit.success()),
+                        Err(_) => (None, false),
+                    },
+                    _ = stop.notified() => {
+                        let _ = child.kill().await;
+                        update(&status, |s| {
+                            s.state = ProcessState::Stopped;
+                            s.pid = None;
+                        });
+                        emit("exited", None);
+                        return;
+                    }
+                }
+            }
+            Err(e) => {
+                update(&status, |s| s.last_error = Some(e.to_string()));
+                (None, false)
+            }
+        };
+        update(&status, |s| {
+            s.pid = None;
+            s.last_exit_code = exit_code;
+        });
+        emit("exited", exit_code);
+
+        if started.elapsed() >= HEALTHY_RUN {
+            failures = 0;
+        }
+        let max_restarts = match policy {
+            RestartPolicy::Never => None,
+            RestartPolicy::OnFailure { .. } if success => None,
+            RestartPolicy::OnFailure { max_restarts } | RestartPolicy::Always { max_restarts } => {
+                Some(max_restarts)
+            }
+        };
+        let Some(max_restarts) = max_restarts else {
+            update(&status, |s| s.state = ProcessState::Exited);
+            return;
+        };
+        if failures >= max_restarts {
+            update(&status, |s| s.state = ProcessState::GaveUp);
+            emit("gave_up", exit_code);
+            return;
+        }
+
+        let delay = backoff.delay(failures);
+        failures += 1;
+        update(&status, |s| {
+            s.state = ProcessState::Restarting;
+            s.restarts += 1;
+        });
+        emit("restarting", exit_code);
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = stop.notified() => {
+                update(&status, |s| s.state = ProcessState::Stopped);
+                return;
+            }
+        }
+    }
+}
+
+/// Long-lived helper processes, restarted per their policy when they die.
+/// Everything here is killed on app exit.
+#[derive(Default)]
+pub struct Supervisor {
+    children: Mutex<HashMap<String, Entry>>,
+}
+
+impl Supervisor {
+    /// Start supervising `name`, replacing (and stopping) any child already
+    /// registered under it
+    pub fn register(
+        &self,
+        app: &AppHandle,
+        name: &str,
+        policy: RestartPolicy,
+        launcher: Launcher,
+    ) -> Result<(), EngineError> {
+        let status = Arc::new(Mutex::new(SupervisedStatus {
+            name: name.to_string(),
+            state: ProcessState::Running,
+            policy,
+            pid: None,
+            restarts: 0,
+            last_exit_code: None,
+            last_error: None,
+            started_at: None,
+        }));
+        let stop = Arc::new(Notify::new());
+        let events = {
+            let app = app.clone();
+            let name = name.to_string();
+            move |event: &str, exit_code| emit(&app, &name, event, exit_code)
+        };
+        let task = tauri::async_runtime::spawn(supervise(
+            policy,
+            Backoff::DEFAULT,
+            launcher,
+            Arc::clone(&status),
+            Arc::clone(&stop),
+            events,
+        ));
+        let mut children = self
+            .children
+            .lock()
+            .map_err(|_| EngineError::internal("Supervisor lock is poisoned"))?;
+        if let Some(old) = children.insert(name.to_string(), Entry { status, stop, task }) {
+            old.stop.notify_one();
+        }
+        Ok(())
+    }
+
+    /// Stop and forget `name`; false if it wasn't registered
+    pub fn stop(&self, name: &str) -> bool {
+        let entry = self.children.lock().ok().and_then(|mut c| c.remove(name));
+        match entry {
+            Some(entry) => {
+                entry.stop.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> Vec<SupervisedStatus> {
+        let Ok(children) = self.children.lock() else {
+            return Vec::new();
+        };
+        let mut list: Vec<_> = children
+            .values()
+            .filter_map(|entry| entry.status.lock().ok().map(|s| s.clone()))
+            .collect();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        list
+    }
+
+    /// Kill every supervised child, on app exit
+    pub async fn shutdown(&self) {
+        let entries: Vec<Entry> = match self.children.lock() {
+            Ok(mut children) => children.drain().map(|(_, entry)| entry).collect(),
+            Err(_) => return,
+        };
+        for entry in &entries {
+            entry.stop.notify_one();
+        }
+        for entry in entries {
+            let _ = tokio::time::timeout(SHUTDOWN_WAIT, entry.task).await;
+        }
+    }
+}
+
+/// Run a helper command (e.g. an MCP server) under supervision, in a
+/// workspace root with its environment
+#[tauri::command]
+pub async fn start_supervised(
+    app: AppHandle,
+    name: String,
+    command: String,
+    workspace: Option<String>,
+    policy: Option<RestartPolicy>,
+    roots: State<'_, Workspace>,
+    supervisor: State<'_, Supervisor>,
+) -> Result<SupervisedStatus, EngineError> {
+    let root = match workspace {
+        Some(root) => roots.check(Path::new(&root))?,
+        None => roots
+            .roots()
+            .into_iter()
+            .next()
+            .ok_or_else(|| EngineError::invalid("No workspace is open"))?,
+    };
+    let mut words = split_command(&command).into_iter();
+    let program = words
+        .next()
+        .ok_or_else(|| EngineError::invalid("Command is empty"))?;
+    let env = env_profile::for_root(&app, &root)?;
+    let program_path = find_program(&program, &env.path_prepend)
+        .ok_or_else(|| EngineError::invalid(format!("{} was not found on PATH", program)))?;
+    let spec = RunSpec {
+        program: program_path,
+        args: words.collect(),
+        cwd: root,
+        env,
+        timeout: None,
+    };
+    // Nobody reads a helper's output here; null pipes can't fill up and stall it
+    let launcher: Launcher = Box::new(move || {
+        runner::command(&spec)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+    });
+    supervisor.register(&app, &name, policy.unwrap_or_default(), launcher)?;
+    supervisor
+        .list()
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or_else(|| EngineError::internal("Supervised process disappeared"))
+}
+
+#[tauri::command]
+pub async fn stop_supervised(
+    name: String,
+    supervisor: State<'_, Supervisor>,
+) -> Result<bool, EngineError> {
+    Ok(supervisor.stop(&name))
+}
+
+#[tauri::command]
+pub async fn list_supervised(
+    supervisor: State<'_, Supervisor>,
+) -> Result<Vec<SupervisedStatus>, EngineError> {
+    Ok(supervisor.list())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let backoff = Backoff::DEFAULT;
+        let delays: Vec<u64> = (0..9)
+            .map(|failures| backoff.delay(failures).as_millis() as u64)
+            .collect();
+        assert_eq!(
+            delays,
+            [500, 1_000, 2_000, 4_000, 8_000, 16_000, 30_000, 30_000, 30_000]
+        );
+        assert_eq!(backoff.delay(u32::MAX), Duration::from_secs(30));
+    }
+
+    #[cfg(unix)]
+    mod unix {
+        use super::*;
+        use std::path::PathBuf;
+
+        fn scratch(name: &str) -> PathBuf {
+            let dir = std::env::temp_dir().join(format!(
+                "bups-supervisor-{}-{}",
+                name,
+                std::process::id()
+            ));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            dir
+        }
+
+        /// A script that notes each run in `runs` and exits with `code`
+        fn exiting(dir: &Path, code: i32) -> Launcher {
+            let script = dir.join("child.sh");
+            let runs = dir.join("runs");
+            std::fs::write(
+                &script,
+                format!("echo run >> '{}'\nexit {}\n", runs.display(), code),
+            )
+            .unwrap();
+            Box::new(move || tokio::process::Command::new("sh").arg(&script).spawn())
+        }
+
+        fn runs(dir: &Path) -> usize {
+            std::fs::read_to_string(dir.join("runs"))
+                .map(|text| text.lines().count())
+                .unwrap_or(0)
+        }
+
+        fn status(policy: RestartPolicy) -> Arc<Mutex<SupervisedStatus>> {
+            Arc::new(Mutex::new(SupervisedStatus {
+                name: "test".to_string(),
+                state: ProcessState::Running,
+                policy,
+                pid: None,
+                restarts: 0,
+                last_exit_code: None,
+                last_error: None,
+                started_at: None,
+            }))
+        }
+
+        type Events = Arc<Mutex<Vec<(String, Option<i32>, Instant)>>>;
+
+        /// Supervise to the end; the status and every event emitted
+        async fn run(
+            policy: RestartPolicy,
+            backoff: Backoff,
+            launcher: Launcher,
+        ) -> (SupervisedStatus, Vec<(String, Option<i32>, Instant)>) {
+            let status = status(policy);
+            let events: Events = Arc::default();
+            let record = {
+                let events = Arc::clone(&events);
+                move |event: &str, code| {
+                    events
+                        .lock()
+                        .unwrap()
+                        .push((event.to_string(), code, Instant::now()))
+                }
+            };
+            let supervised = supervise(
+                policy,
+                backoff,
+                launcher,
+                Arc::clone(&status),
+                Arc::new(Notify::new()),
+                record,
+            );
+            tokio::time::timeout(Duration::from_secs(30), supervised)
+                .await
+                .expect("supervisor never finished");
+            let status = status.lock().unwrap().clone();
+            let events = events.lock().unwrap().clone();
+            (status, events)
+        }
+
+        fn names(events: &[(String, Option<i32>, Instant)]) -> Vec<&str> {
+            events.iter().map(|(name, _, _)| name.as_str()).collect()
+        }
+
+        #[tokio::test]
+        async fn gives_up_after_max_restarts_in_a_row() {
+            let dir = scratch("give-up");
+            let backoff = Backoff {
+                initial: Duration::from_millis(10),
+                max: Duration::from_millis(40),
+            };
+            let policy = RestartPolicy::Always { max_restarts: 3 };
+            let (status, events) = run(policy, backoff, exiting(&dir, 3)).await;
+
+            assert_eq!(status.state, ProcessState::GaveUp);
+            assert_eq!(status.restarts, 3);
+            assert_eq!(status.last_exit_code, Some(3));
+            assert_eq!(status.pid, None);
+            assert_eq!(runs(&dir), 4);
+            let mut expected = ["started", "exited", "restarting"].repeat(3);
+            expected.extend(["started", "exited", "gave_up"]);
+            assert_eq!(names(&events), expected);
+            assert!(events
+                .iter()
+                .filter(|(name, _, _)| name != "started")
+                .all(|(_, code, _)| *code == Some(3)));
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn restart_delays_stop_growing_at_the_cap() {
+            let dir = scratch("cap");
+            let backoff = Backoff {
+                initial: Duration::from_millis(50),
+                max: Duration::from_millis(100),
+            };
+            let policy = RestartPolicy::OnFailure { max_restarts: 7 };
+            let (status, events) = run(policy, backoff, exiting(&dir, 1)).await;
+
+            assert_eq!(status.state, ProcessState::GaveUp);
+            assert_eq!(runs(&dir), 8);
+            let gaps: Vec<Duration> = events
+                .windows(2)
+                .filter(|pair| pair[0].0 == "restarting" && pair[1].0 == "started")
+                .map(|pair| pair[1].2 - pair[0].2)
+                .collect();
+            assert_eq!(gaps.len(), 7);
+            for (failures, gap) in gaps.iter().enumerate() {
+                assert!(*gap >= backoff.delay(failures as u32), "{:?}", gaps);
+            }
+            // Uncapped, the last wait would be 3.2s
+            assert!(gaps[6] < Duration::from_millis(1_500), "{:?}", gaps);
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn a_clean_exit_is_not_a_failure() {
+            let dir = scratch("clean");
+            let policy = RestartPolicy::OnFailure { max_restarts: 3 };
+            let (status, events) = run(policy, Backoff::DEFAULT, exiting(&dir, 0)).await;
+            assert_eq!(status.state, ProcessState::Exited);
+            assert_eq!(status.restarts, 0);
+            assert_eq!(status.last_exit_code, Some(0));
+            assert_eq!(names(&events), ["started", "exited"]);
+            let _ = std::fs::remove_dir_all(&dir);
+        }
+
+        #[tokio::test]
+        async fn spawn_errors_back_off_like_failed_exits() {
+            let launcher: Launcher =
+                Box::new(|| tokio::process::Command::new("/nonexistent/bups-supervised").spawn());
+            let backoff = Backoff {
+                initial: Duration::from_millis(1),
+                max: Duration::from_millis(1),
+            };
+            let policy = RestartPolicy::Always { max_restarts: 2 };
+            let (status, events) = run(policy, backoff, launcher).await;
+            assert_eq!(status.state, ProcessState::GaveUp);
+            assert_eq!(status.restarts, 2);
+            assert!(status.last_error.is_some());
+            assert_eq!(
+                names(&events),
+                [
+                    "exited",
+                    "restarting",
+                    "exited",
+                    "restarting",
+                    "exited",
+                    "gave_up"
+                ]
+            );
+        }
+
+        #[tokio::test]
+        async fn stop_kills_a_running_child() {
+            let launcher: Launcher =
+                Box::new(|| tokio::process::Command::new("sleep").arg("30").spawn());
+            let policy = RestartPolicy::Always { max_restarts: 3 };
+            let status = status(policy);
+            let stop = Arc::new(Notify::new());
+            let task = tokio::spawn(supervise(
+                policy,
+                Backoff::DEFAULT,
+                launcher,
+                Arc::clone(&status),
+                Arc::clone(&stop),
+                |_: &str, _| {},
+            ));
+            while status.lock().unwrap().pid.is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+            stop.notify_one();
+            tokio::time::timeout(Duration::from_secs(5), task)
+                .await
+                .unwrap()
+                .unwrap();
+            let status = status.lock().unwrap().clone();
+            assert_eq!(status.state, ProcessState::Stopped);
+            assert_eq!(status.restarts, 0);
+            assert_eq!(status.pid, None);
+        }
+    }
+}
@@ -0,0 +1,373 @@
+use crate::cancel::{CancelRegistry, CancelToken};
+use crate::error::EngineError;
+use crate::files::looks_binary;
+use crate::index::IndexRegistry;
+use crate::languages;
+use crate::sandbox;
+use crate::walker::{visit_paths, walk_files, WalkConfig};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::{AppHandle, Manager, State};
+
+const MAX_FILE_BYTES: u64 = 1024 * 1024;
+
+struct SymbolRule {
+    kind: &'static str,
+    /// First capture group is the symbol name
+    pattern: &'static str,
+}
+
+struct LanguageRules {
+    language: &'static str,
+    /// Ids from the shared language table that these rules apply to
+    detected: &'static [&'static str],
+    rules: &'static [SymbolRule],
+}
+
+// Adding a language is a new table entry; nothing else needs to change
+const LANGUAGES: &[LanguageRules] = &[
+    LanguageRules {
+        language: "rust",
+        detected: &["rust"],
+        rules: &[
+            SymbolRule {
+                kind: "fn",
+                pattern: r#"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:(?:const|async|unsafe|extern(?:\s+"[^"]*")?)\s+)*fn\s+([A-Za-z_][A-Za-z0-9_]*)"#,
+            },
+            SymbolRule {
+                kind: "struct",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?struct\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "enum",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?enum\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "trait",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:unsafe\s+)?trait\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "impl",
+                pattern: r"^\s*(?:unsafe\s+)?impl(?:<[^>]*>)?\s+(?:[A-Za-z_][\w:<>, ]*\s+for\s+)?([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "type",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?type\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "const",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?(?:const|static(?:\s+mut)?)\s+([A-Z_][A-Z0-9_]*)\s*:",
+            },
+            SymbolRule {
+                kind: "mod",
+                pattern: r"^\s*(?:pub(?:\([^)]*\))?\s+)?mod\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "macro",
+                pattern: r"^\s*macro_rules!\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+        ],
+    },
+    LanguageRules {
+        language: "typescript",
+        detected: &["typescript", "javascript"],
+        rules: &[
+            SymbolRule {
+                kind: "function",
+                pattern: r"^\s*(?:export\s+)?(?:default\s+)?(?:async\s+)?function\s*\*?\s*([A-Za-z_$][\w$]*)",
+            },
+            SymbolRule {
+                kind: "class",
+                pattern: r"^\s*(?:export\s+)?(?:default\s+)?(?:abstract\s+)?class\s+([A-Za-z_$][\w$]*)",
+            },
+            SymbolRule {
+                kind: "interface",
+                pattern: r"^\s*(?:export\s+)?interface\s+([A-Za-z_$][\w$]*)",
+            },
+            SymbolRule {
+                kind: "type",
+                pattern: r"^\s*(?:export\s+)?type\s+([A-Za-z_$][\w$]*)\s*(?:<[^=]*>)?\s*=",
+            },
+            SymbolRule {
+                kind: "enum",
+                pattern: r"^\s*(?:export\s+)?(?:const\s+)?enum\s+([A-Za-z_$][\w$]*)",
+            },
+            SymbolRule {
+                kind: "const",
+                pattern: r"^\s*(?:export\s+)?(?:const|let|var)\s+([A-Za-z_$][\w$]*)",
+            },
+        ],
+    },
+    LanguageRules {
+        language: "python",
+        detected: &["python"],
+        rules: &[
+            SymbolRule {
+                kind: "def",
+                pattern: r"^\s*(?:async\s+)?def\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "class",
+                pattern: r"^\s*class\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+        ],
+    },
+    LanguageRules {
+        language: "go",
+        detected: &["go"],
+        rules: &[
+            SymbolRule {
+                kind: "func",
+                pattern: r"^func\s+(?:\([^)]*\)\s*)?([A-Za-z_][A-Za-z0-9_]*)",
+            },
+            SymbolRule {
+                kind: "type",
+                pattern: r"^type\s+([A-Za-z_][A-Za-z0-9_]*)",
+            },
+        ],
+    },
+];
+
+struct CompiledLanguage {
+    language: &'static str,
+    detected: &'static [&'static str],
+    rules: Vec<(&'static str, Regex)>,
+}
+
+fn compiled() -> &'static [CompiledLanguage] {
+    static COMPILED: OnceLock<Vec<CompiledLanguage>> = OnceLock::new();
+    COMPILED.get_or_init(|| {
+        LANGUAGES
+            .iter()
+            .map(|lang| CompiledLanguage {
+                language: lang.language,
+                detected: lang.detected,
+                rules: lang
+                    .rules
+                    .iter()
+                    .filter_map(|rule| Regex::new(rule.pattern).ok().map(|re| (rule.kind, re)))
+                    .collect(),
+            })
+            .collect()
+    })
+}
+
+fn language_for(path: &Path) -> Option<&'static CompiledLanguage> {
+    let detected = languages::detect_path(path)?.language;
+    compiled()
+        .iter()
+        .find(|lang| lang.detected.contains(&detected))
+}
+
+/// 0-based lines where a definition starts, for splitting a file at sensible
+/// points. Empty when the language has no rules.
+pub fn definition_lines(path: &Path, text: &str) -> Vec<usize> {
+    let Some(lang) = language_for(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| lang.rules.iter().any(|(_, re)| re.is_match(line)))
+        .map(|(index, _)| index)
+        .collect()
+}
+
+/// Names of the definitions in a file, in order, for the workspace index
+pub fn definition_names(path: &Path, text: &str) -> Vec<String> {
+    let Some(lang) = language_for(path) else {
+        return Vec::new();
+    };
+    text.lines()
+        .filter_map(|line| {
+            lang.rules
+                .iter()
+                .find_map(|(_, re)| re.captures(line)?.get(1))
+                .map(|name| name.as_str().to_string())
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SymbolSearchOptions {
+    pub languages: Vec<String>,
+    pub kinds: Vec<String>,
+    pub max_results: usize,
+}
+
+impl Default for SymbolSearchOptions {
+    fn default() -> Self {
+        Self {
+            languages: Vec::new(),
+            kinds: Vec::new(),
+            max_results: 200,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SymbolMatch {
+    pub path: String,
+    pub line: usize,
+    pub kind: &'static str,
+    pub language: &'static str,
+    pub name: String,
+    pub signature_line: String,
+    /// Lower is better: exact, case-insensitive exact, prefix, substring
+    pub score: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SymbolSearchResult {
+    pub symbols: Vec<SymbolMatch>,
+    pub truncated: bool,
+    pub files_scanned: usize,
+}
+
+fn score(name: &str, query: &str, query_lower: &str) -> Option<u8> {
+    if name == query {
+        return Some(0);
+    }
+    let lower = name.to_lowercase();
+    if lower == query_lower {
+        Some(1)
+    } else if lower.starts_with(query_lower) {
+        Some(2)
+    } else if lower.contains(query_lower) {
+        Some(3)
+    } else {
+        None
+    }
+}
+
+fn scan_file(
+    path: &Path,
+    lang: &CompiledLanguage,
+    query: &str,
+    query_lower: &str,
+    kinds: &[String],
+    out: &mut Vec<SymbolMatch>,
+) {
+    let Ok(bytes) = std::fs::read(path) else {
+        return;
+    };
+    if looks_binary(&bytes) {
+        return;
+    }
+    let text = String::from_utf8_lossy(&bytes);
+    for (index, line) in text.lines().enumerate() {
+        // Cheap prefilter: the symbol name must contain the query
+        if !line.to_lowercase().contains(query_lower) {
+            continue;
+        }
+        for (kind, re) in &lang.rules {
+            if !kinds.is_empty() && !kinds.iter().any(|k| k == kind) {
+                continue;
+            }
+            let Some(name) = re.captures(line).and_then(|c| c.get(1)) else {
+                continue;
+            };
+            if let Some(score) = score(name.as_str(), query, query_lower) {
+                out.push(SymbolMatch {
+                    path: path.display().to_string(),
+                    line: index + 1,
+                    kind,
+                    language: lang.language,
+                    name: name.as_str().to_string(),
+                    signature_line: line.trim().chars().take(300).collect(),
+                    score,
+                });
+                break;
+            }
+        }
+    }
+}
+
+/// Search definitions under `root`, or only in `files` when the index has
+/// already narrowed them down
+pub fn search_symbols(
+    root: &Path,
+    query: &str,
+    options: &SymbolSearchOptions,
+    files: Option<Vec<PathBuf>>,
+    cancel: &CancelToken,
+) -> Result<SymbolSearchResult, EngineError> {
+    let query = query.trim();
+    if query.is_empty() {
+        return Err(EngineError::invalid("Symbol query must not be empty"));
+    }
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            root.display()
+        )));
+    }
+    let query_lower = query.to_lowercase();
+
+    let files_scanned = std::sync::atomic::AtomicUsize::new(0);
+    let config = WalkConfig {
+        max_filesize: Some(MAX_FILE_BYTES),
+        cancel: Some(cancel),
+        ..WalkConfig::default()
+    };
+    let visit = |path: &Path| {
+        let lang = language_for(path)?;
+        if !options.languages.is_empty() && !options.languages.iter().any(|l| l == lang.language) {
+            return None;
+        }
+        let mut found = Vec::new();
+        scan_file(path, lang, query, &query_lower, &options.kinds, &mut found);
+        files_scanned.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        Some(found).filter(|found| !found.is_empty())
+    };
+    let found = match files {
+        Some(files) => visit_paths(files, config, visit)?,
+        None => walk_files(root, config, |_| true, |entry| visit(entry.path()))?,
+    };
+
+    let mut symbols: Vec<SymbolMatch> = found.into_iter().flat_map(|(_, found)| found).collect();
+    symbols.sort_by(|a, b| {
+        a.score
+            .cmp(&b.score)
+            .then_with(|| a.name.len().cmp(&b.name.len()))
+            .then_with(|| a.path.cmp(&b.path))
+            .then_with(|| a.line.cmp(&b.line))
+    });
+    let truncated = symbols.len() > options.max_results;
+    symbols.truncate(options.max_results);
+
+    Ok(SymbolSearchResult {
+        symbols,
+        truncated,
+        files_scanned: files_scanned.into_inner(),
+    })
+}
+
+#[tauri::command]
+pub async fn find_symbols(
+    root: String,
+    query: String,
+    options: Option<SymbolSearchOptions>,
+    request_id: Option<String>,
+    registry: State<'_, CancelRegistry>,
+    app: AppHandle,
+) -> Result<SymbolSearchResult, EngineError> {
+    let options = options.unwrap_or_default();
+    let root = sandbox::allow(&app, &root)?;
+    let guard = request_id.map(|id| registry.register(&id));
+    let cancel = guard.as_ref().map(|g| g.token()).unwrap_or_default();
+    let files = app
+        .state::<IndexRegistry>()
+        .fresh(&app, &root)
+        .map(|index| index.symbol_candidates(&root, query.trim(), MAX_FILE_BYTES));
+
+    let result = tokio::task::spawn_blocking(move || {
+        search_symbols(&root, &query, &options, files, &cancel)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?;
+    drop(guard);
+    result
+}
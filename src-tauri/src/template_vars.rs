@@ -0,0 +1,462 @@
+use crate::context::{glob_files, relative, render_section};
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::files::looks_binary;
+use crate::runner::{find_program, run_captured, split_command, RunSpec};
+use crate::sandbox::Workspace;
+use crate::templates;
+use crate::tokens::count_tokens;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+use std::time::Duration;
+use tauri::{AppHandle, ClipboardManager, Manager};
+
+const GIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+// Read-only subcommands; anything that can write, fetch or run hooks is out
+const GIT_SUBCOMMANDS: &[&str] = &[
+    "diff", "log", "show", "status", "blame", "shortlog", "ls-files", "describe",
+];
+
+// Still able to write files, run configured programs or read outside the
+// repository from a read-only subcommand
+const GIT_BLOCKED_FLAGS: &[&str] = &[
+    "--output",
+    "--ext-diff",
+    "--textconv",
+    "--exec",
+    "--no-index",
+];
+
+// Harmless names only; anything secret-looking stays out of prompts
+const ENV_ALLOWLIST: &[&str] = &[
+    "USER",
+    "USERNAME",
+    "HOME",
+    "USERPROFILE",
+    "SHELL",
+    "LANG",
+    "OS",
+    "TERM",
+    "EDITOR",
+    "COMPUTERNAME",
+    "HOSTNAME",
+];
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ExpandOptions {
+    /// Base for relative `file:`/`glob:` paths and where `git:` runs; the first
+    /// workspace root otherwise
+    pub workspace: Option<String>,
+    /// Current editor selection, supplied by the frontend
+    pub selection: Option<String>,
+    pub max_variable_bytes: usize,
+    pub max_total_bytes: usize,
+    /// Each `glob:` file is cut to this before concatenation
+    pub max_glob_file_bytes: usize,
+    pub max_glob_files: usize,
+}
+
+impl Default for ExpandOptions {
+    fn default() -> Self {
+        Self {
+            workspace: None,
+            selection: None,
+            max_variable_bytes: 64 * 1024,
+            max_total_bytes: 256 * 1024,
+            max_glob_file_bytes: 16 * 1024,
+            max_glob_files: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExpansionItem {
+    pub variable: String,
+    pub provider: &'static str,
+    /// Files or command the value came from
+    pub sources: Vec<String>,
+    pub bytes: usize,
+    pub tokens: usize,
+    pub truncated: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Expanded {
+    pub prompt: String,
+    pub report: Vec<ExpansionItem>,
+}
+
+enum Provider<'a> {
+    File(&'a str),
+    Glob(&'a str),
+    Git(&'a str),
+    Env(&'a str),
+    Clipboard,
+    Selection,
+}
+
+impl Provider<'_> {
+    fn parse(name: &str) -> Option<Provider<'_>> {
+        let (kind, arg) = match name.split_once(':') {
+            Some((kind, arg)) => (kind.trim(), arg.trim()),
+            None => (name, ""),
+        };
+        match kind {
+            "file" => Some(Provider::File(arg)),
+            "glob" => Some(Provider::Glob(arg)),
+            "git" => Some(Provider::Git(arg)),
+            "env" => Some(Provider::Env(arg)),
+            "clipboard" if arg.is_empty() => Some(Provider::Clipboard),
+            "selection" if arg.is_empty() => Some(Provider::Selection),
+            _ => None,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Provider::File(_) => "file",
+            Provider::Glob(_) => "glob",
+            Provider::Git(_) => "git",
+            Provider::Env(_) => "env",
+            Provider::Clipboard => "clipboard",
+            Provider::Selection => "selection",
+        }
+    }
+}
+
+struct Value {
+    text: String,
+    sources: Vec<String>,
+    truncated: bool,
+}
+
+fn failed(variable: &str, detail: impl ToString) -> EngineError {
+    EngineError::TemplateExpansionFailed {
+        variable: variable.to_string(),
+        detail: detail.to_string(),
+    }
+}
+
+fn truncate_to(text: &mut String, max_bytes: usize) -> bool {
+    if text.len() <= max_bytes {
+        return false;
+    }
+    let mut cut = max_bytes;
+    while !text.is_char_boundary(cut) {
+        cut -= 1;
+    }
+    text.truncate(cut);
+    true
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+    let bytes =
+        std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    if looks_binary(&bytes) {
+        return Err(format!("{} is a binary file", path.display()));
+    }
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn expand_file(
+    variable: &str,
+    arg: &str,
+    root: &Path,
+    workspace: &Workspace,
+    options: &ExpandOptions,
+) -> Result<Value, EngineError> {
+    let path = workspace.check(&root.join(arg))?;
+    let text = read_text(&path).map_err(|e| failed(variable, e))?;
+    if text.len() > options.max_variable_bytes {
+        return Err(EngineError::TemplateBudgetExceeded {
+            variable: Some(variable.to_string()),
+            bytes: text.len(),
+            limit: options.max_variable_bytes,
+        });
+    }
+    Ok(Value {
+        text,
+        sources: vec![relative(root, &path)],
+        truncated: false,
+    })
+}
+
+fn expand_glob(
+    variable: &str,
+    pattern: &str,
+    root: &Path,
+    options: &ExpandOptions,
+) -> Result<Value, EngineError> {
+    let files = glob_files(root, pattern).map_err(|e| failed(variable, e))?;
+    if files.is_empty() {
+        return Err(failed(variable, format!("No files match '{}'", pattern)));
+    }
+    if files.len() > options.max_glob_files {
+        return Err(failed(
+            variable,
+            format!(
+                "'{}' matches {} files; the limit is {}",
+                pattern,
+                files.len(),
+                options.max_glob_files
+            ),
+        ));
+    }
+
+    let mut value = Value {
+        text: String::new(),
+        sources: Vec::new(),
+        truncated: false,
+    };
+    for (rel, path) in files {
+        // Binary files are skipped rather than failing the whole glob
+        let Ok(mut content) = read_text(&path) else {
+            continue;
+        };
+        if truncate_to(&mut content, options.max_glob_file_bytes) {
+            content.push_str("\n… (truncated)");
+            value.truncated = true;
+        }
+        value.text.push_str(&render_section(&rel, &content));
+        value.sources.push(rel);
+    }
+    if value.text.len() > options.max_variable_bytes {
+        return Err(EngineError::TemplateBudgetExceeded {
+            variable: Some(variable.to_string()),
+            bytes: value.text.len(),
+            limit: options.max_variable_bytes,
+        });
+    }
+    Ok(value)
+}
+
+/// Whether a path argument reaches outside the directory git runs in.
+/// Revision ranges like `HEAD~2..HEAD` are one component, so they pass.
+fn escapes_root(arg: &str) -> bool {
+    let mut depth = 0usize;
+    for component in Path::new(arg).components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir => return true,
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+        }
+    }
+    false
+}
+
+fn check_git_args(args: &[String]) -> Result<(), String> {
+    match args.first() {
+        Some(sub) if GIT_SUBCOMMANDS.contains(&sub.as_str()) => {}
+        _ => {
+            return Err(format!(
+                "Only these git subcommands are allowed: {}",
+                GIT_SUBCOMMANDS.join(", ")
+            ))
+        }
+    }
+    if let Some(flag) = args
+        .iter()
+        .find(|a| GIT_BLOCKED_FLAGS.iter().any(|b| a.starts_with(b)))
+    {
+        return Err(format!("'{}' isn't allowed here", flag));
+    }
+    if let Some(path) = args.iter().find(|a| !a.starts_with('-') && escapes_root(a)) {
+        return Err(format!("'{}' is outside the workspace", path));
+    }
+    Ok(())
+}
+
+async fn expand_git(
+    app: &AppHandle,
+    variable: &str,
+    arg: &str,
+    root: &Path,
+    options: &ExpandOptions,
+) -> Result<Value, EngineError> {
+    let args = split_command(arg);
+    check_git_args(&args).map_err(|e| failed(variable, e))?;
+
+    let env = env_profile::for_root(app, root)?;
+    let program = find_program("git", &env.path_prepend)
+        .ok_or_else(|| failed(variable, "git was not found on PATH"))?;
+    let mut full_args = vec!["--no-pager".to_string()];
+    full_args.extend(args);
+    let spec = RunSpec {
+        program,
+        args: full_args,
+        cwd: root.to_path_buf(),
+        env,
+        timeout: Some(GIT_TIMEOUT),
+    };
+    let output = run_captured(spec, Vec::new())
+        .await
+        .map_err(|e| failed(variable, e))?;
+    if output.exit_code != Some(0) {
+        return Err(failed(variable, output.stderr.trim()));
+    }
+    let text = String::from_utf8_lossy(&output.stdout).into_owned();
+    if text.len() > options.max_variable_bytes {
+        return Err(EngineError::TemplateBudgetExceeded {
+            variable: Some(variable.to_string()),
+            bytes: text.len(),
+            limit: options.max_variable_bytes,
+        });
+    }
+    Ok(Value {
+        text,
+        sources: vec![format!("git {}", arg)],
+        truncated: false,
+    })
+}
+
+fn plain(variable: &str, text: Option<String>, what: &str) -> Result<Value, EngineError> {
+    let text = text.ok_or_else(|| failed(variable, format!("No {} available", what)))?;
+    Ok(Value {
+        text,
+        sources: Vec::new(),
+        truncated: false,
+    })
+}
+
+async fn expand_provider(
+    app: &AppHandle,
+    variable: &str,
+    provider: &Provider<'_>,
+    root: Option<&Path>,
+    options: &ExpandOptions,
+) -> Result<Value, EngineError> {
+    let needs_root = || root.ok_or_else(|| failed(variable, "No workspace is open"));
+    match provider {
+        Provider::File(arg) => {
+            let workspace = app.state::<Workspace>();
+            expand_file(variable, arg, needs_root()?, &workspace, options)
+        }
+        Provider::Glob(pattern) => {
+            let root = needs_root()?.to_path_buf();
+            let (variable_owned, pattern, options_owned) =
+                (variable.to_string(), pattern.to_string(), options.clone());
+            tokio::task::spawn_blocking(move || {
+                expand_glob(&variable_owned, &pattern, &root, &options_owned)
+            })
+            .await
+            .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+        }
+        Provider::Git(arg) => expand_git(app, variable, arg, needs_root()?, options).await,
+        Provider::Env(name) => {
+            if !ENV_ALLOWLIST.iter().any(|allowed| allowed == name) {
+                return Err(failed(
+                    variable,
+                    format!("'{}' isn't in the environment allowlist", name),
+                ));
+            }
+            plain(variable, std::env::var(name).ok(), "value")
+        }
+        Provider::Clipboard => {
+            let text = app
+                .clipboard_manager()
+                .read_text()
+                .map_err(|e| failed(variable, format!("Failed to read clipboard: {}", e)))?;
+            plain(variable, text, "clipboard text")
+        }
+        Provider::Selection => plain(variable, options.selection.clone(), "selection"),
+    }
+}
+
+/// Render `body`, resolving provider placeholders (`file:`, `glob:`, `git:`,
+/// `env:`, `clipboard`, `selection`) in addition to `vars`. Explicit vars win.
+pub async fn expand(
+    app: &AppHandle,
+    body: &str,
+    vars: &BTreeMap<String, String>,
+    options: &ExpandOptions,
+) -> Result<Expanded, EngineError> {
+    let root: Option<PathBuf> = match &options.workspace {
+        Some(root) => Some(app.state::<Workspace>().check(Path::new(root))?),
+        None => app.state::<Workspace>().roots().into_iter().next(),
+    };
+
+    let mut values = vars.clone();
+    let mut report = Vec::new();
+    let mut total = 0;
+    for name in templates::placeholders(body) {
+        if values.contains_key(&name) {
+            continue;
+        }
+        let Some(provider) = Provider::parse(&name) else {
+            continue;
+        };
+        let value = expand_provider(app, &name, &provider, root.as_deref(), options).await?;
+        total += value.text.len();
+        if total > options.max_total_bytes {
+            return Err(EngineError::TemplateBudgetExceeded {
+                variable: None,
+                bytes: total,
+                limit: options.max_total_bytes,
+            });
+        }
+        report.push(ExpansionItem {
+            variable: name.clone(),
+            provider: provider.kind(),
+            sources: value.sources,
+            bytes: value.text.len(),
+            tokens: count_tokens(&value.text),
+            truncated: value.truncated,
+        });
+        values.insert(name, value.text);
+    }
+
+    let prompt = templates::render(body, &values)?;
+    Ok(Expanded { prompt, report })
+}
+
+/// Render a saved template the way it would be sent, with what each provider added
+#[tauri::command]
+pub async fn expand_template(
+    app: AppHandle,
+    template: String,
+    vars: Option<BTreeMap<String, String>>,
+    options: Option<ExpandOptions>,
+) -> Result<Expanded, EngineError> {
+    let body = templates::load(&app, &template)?;
+    expand(
+        &app,
+        &body,
+        &vars.unwrap_or_default(),
+        &options.unwrap_or_default(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn git_args_stay_read_only_and_inside_the_root() {
+        let cases: &[(&str, bool)] = &[
+            ("diff", true),
+            ("log --oneline -5", true),
+            ("diff HEAD~2..HEAD -- src/main.rs", true),
+            ("show origin/main:docs/../README.md", true),
+            ("diff ./src", true),
+            ("diff src/../lib", true),
+            ("push", false),
+            ("diff --output=/tmp/x", false),
+            ("diff --ext-diff", false),
+            ("diff --no-index a b", false),
+            ("diff -- ../outside", false),
+            ("log -- src/../../outside", false),
+            ("diff /etc/passwd", false),
+            ("show HEAD -- /etc/hosts", false),
+        ];
+        for (arg, allowed) in cases {
+            let args = split_command(arg);
+            assert_eq!(check_git_args(&args).is_ok(), *allowed, "{}", arg);
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use crate::clock::millis;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::paths::{app_config_subdir, validate_name};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use tauri::AppHandle;
+
+pub const TEMPLATES_DIR: &str = "templates";
+
+fn template_path(app: &AppHandle, name: &str) -> Result<PathBuf, EngineError> {
+    let name = validate_name(name)?;
+    Ok(app_config_subdir(app, TEMPLATES_DIR)?.join(format!("{}.md", name)))
+}
+
+pub fn load(app: &AppHandle, name: &str) -> Result<String, EngineError> {
+    let path = template_path(app, name)?;
+    std::fs::read_to_string(&path).map_err(|e| EngineError::io(&path, e))
+}
+
+enum Segment<'a> {
+    Text(&'a str),
+    Var(&'a str),
+}
+
+/// `\{{` is a literal `{{`; an unclosed `{{` is kept as written, and braces
+/// beyond two (`{{{name}}}`) are literal text around the placeholder
+fn segments(body: &str) -> Vec<Segment<'_>> {
+    let mut out = Vec::new();
+    let mut rest = body;
+    while let Some(mut start) = rest.find("{{") {
+        if rest[..start].ends_with('\\') {
+            out.push(Segment::Text(&rest[..start - 1]));
+            out.push(Segment::Text(&rest[start..start + 2]));
+            rest = &rest[start + 2..];
+            continue;
+        }
+        while rest[start + 2..].starts_with('{') {
+            start += 1;
+        }
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            break;
+        };
+        out.push(Segment::Text(&rest[..start]));
+        out.push(Segment::Var(after[..end].trim()));
+        rest = &after[end + 2..];
+    }
+    out.push(Segment::Text(rest));
+    out
+}
+
+/// Placeholder names in order of first use, without duplicates
+pub fn placeholders(body: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for segment in segments(body) {
+        if let Segment::Var(name) = segment {
+            if !names.iter().any(|n| n == name) {
+                names.push(name.to_string());
+            }
+        }
+    }
+    names
+}
+
+/// Substitute `{{name}}` placeholders (inner whitespace allowed). `\{{` is a
+/// literal `{{`. Every missing variable is reported, not just the first.
+pub fn render(body: &str, vars: &BTreeMap<String, String>) -> Result<String, EngineError> {
+    let mut out = String::with_capacity(body.len());
+    let mut missing: Vec<String> = Vec::new();
+    for segment in segments(body) {
+        match segment {
+            Segment::Text(text) => out.push_str(text),
+            Segment::Var(name) => match vars.get(name) {
+                Some(value) => out.push_str(value),
+                None => {
+                    if !missing.iter().any(|m| m == name) {
+                        missing.push(name.to_string());
+                    }
+                }
+            },
+        }
+    }
+    if missing.is_empty() {
+        Ok(out)
+    } else {
+        Err(EngineError::TemplateVariablesMissing { names: missing })
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TemplateInfo {
+    pub name: String,
+    /// Variables it needs, in order of first use
+    pub placeholders: Vec<String>,
+    pub updated_at: u64,
+}
+
+/// Create or replace a template
+#[tauri::command]
+pub async fn save_template(app: AppHandle, name: String, body: String) -> Result<(), EngineError> {
+    let path = template_path(&app, &name)?;
+    atomic_write(&path, body.as_bytes(), false).map_err(|e| EngineError::io(&path, e))?;
+    Ok(())
+}
+
+/// Saved templates by name; a file that can't be read is left out
+#[tauri::command]
+pub async fn list_templates(app: AppHandle) -> Result<Vec<TemplateInfo>, EngineError> {
+    let dir = app_config_subdir(&app, TEMPLATES_DIR)?;
+    let entries = std::fs::read_dir(&dir).map_err(|e| EngineError::io(&dir, e))?;
+    let mut templates = Vec::new();
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension().is_none_or(|ext| ext != "md") {
+            continue;
+        }
+        let (Some(name), Ok(body)) = (path.file_stem(), std::fs::read_to_string(&path)) else {
+            continue;
+        };
+        let updated_at = std::fs::metadata(&path)
+            .and_then(|meta| meta.modified())
+            .ok()
+            .and_then(millis)
+            .unwrap_or(0);
+        templates.push(TemplateInfo {
+            name: name.to_string_lossy().into_owned(),
+            placeholders: placeholders(&body),
+            updated_at,
+        });
+    }
+    templates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(templates)
+}
+
+/// False if there was no such template
+#[tauri::command]
+pub async fn delete_template(app: AppHandle, name: String) -> Result<bool, EngineError> {
+    let path = template_path(&app, &name)?;
+    match std::fs::remove_file(&path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(EngineError::io(&path, e)),
+    }
+}
+
+/// A saved template with `vars` filled in; see [`render`]
+#[tauri::command]
+pub async fn render_template(
+    app: AppHandle,
+    name: String,
+    vars: Option<BTreeMap<String, String>>,
+) -> Result<String, EngineError> {
+    render(&load(&app, &name)?, &vars.unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn braces_and_escapes() {
+        let vars = vars(&[("name", "Ada"), ("lang", "Rust"), ("raw", "{{lang}}")]);
+        let cases = [
+            ("Hi {{name}}", "Hi Ada"),
+            ("{{ name }} and {{\tlang\n}}", "Ada and Rust"),
+            ("{{name}}{{lang}}", "AdaRust"),
+            // Escaped: printed as written, minus the backslash
+            (r"\{{name}}", "{{name}}"),
+            (r"a \{{name}} b {{name}}", "a {{name}} b Ada"),
+            (r"\{{name}} \{{", "{{name}} {{"),
+            // Only `{{` is escaped; a doubled backslash still leaves one
+            (r"\\{{name}}", r"\{{name}}"),
+            (r"\{name}", r"\{name}"),
+            // Extra braces are literal text around the placeholder
+            ("{{{name}}}", "{Ada}"),
+            ("{{{{name}}}}", "{{Ada}}"),
+            (r"\{{{name}}}", "{{{name}}}"),
+            // Unclosed or single braces stay as written
+            ("{{name", "{{name"),
+            ("{{name}", "{{name}"),
+            ("a {{ b", "a {{ b"),
+            ("{name} }} {", "{name} }} {"),
+            ("fn f() {{ x }", "fn f() {{ x }"),
+            // Values aren't expanded again
+            ("{{raw}}", "{{lang}}"),
+            ("", ""),
+        ];
+        for (body, expected) in cases {
+            assert_eq!(render(body, &vars).unwrap(), expected, "{:?}", body);
+        }
+    }
+
+    #[test]
+    fn every_missing_variable_is_reported_once() {
+        let err = render(
+            "{{a}} {{b}} {{a}} {{known}} \\{{escaped}}",
+            &vars(&[("known", "x")]),
+        )
+        .unwrap_err();
+        match err {
+            EngineError::TemplateVariablesMissing { names } => assert_eq!(names, ["a", "b"]),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn placeholders_skip_escaped_braces() {
+        assert_eq!(
+            placeholders("{{ b }} {{a}} \\{{c}} {{b}} {{{d}}} {{unclosed"),
+            ["b", "a", "d"]
+        );
+        assert!(placeholders("no braces {here}").is_empty());
+    }
+}
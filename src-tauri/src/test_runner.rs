@@ -0,0 +1,288 @@
+use crate::cancel::CancelRegistry;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::runner::{find_program, run_streamed, split_command, RunSpec};
+use crate::sandbox::Workspace;
+use crate::settings;
+use crate::workspace_config;
+use regex::Regex;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use tauri::{AppHandle, State, Window};
+
+// What `npm init` writes into scripts.test
+const NPM_PLACEHOLDER_TEST: &str = "no test specified";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TestCommand {
+    pub command: String,
+    /// Why this command was picked, for display next to the run button
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct TestCounts {
+    pub passed: u64,
+    pub failed: u64,
+    pub skipped: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TestRunResult {
+    pub command: String,
+    pub exit_code: Option<i32>,
+    pub success: bool,
+    /// `None` when the output format wasn't recognized
+    pub counts: Option<TestCounts>,
+    pub log_tail: Vec<String>,
+    pub duration_ms: u64,
+}
+
+fn proposal(command: &str, reason: &str) -> TestCommand {
+    TestCommand {
+        command: command.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+fn node_package_manager(root: &Path) -> &'static str {
+    if root.join("pnpm-lock.yaml").exists() {
+        "pnpm"
+    } else if root.join("yarn.lock").exists() {
+        "yarn"
+    } else if root.join("bun.lockb").exists() || root.join("bun.lock").exists() {
+        "bun"
+    } else {
+        "npm"
+    }
+}
+
+fn has_npm_test_script(root: &Path) -> bool {
+    std::fs::read(root.join("package.json"))
+        .ok()
+        .and_then(|data| serde_json::from_slice::<serde_json::Value>(&data).ok())
+        .and_then(|pkg| {
+            pkg.get("scripts")?
+                .get("test")?
+                .as_str()
+                .map(|s| !s.contains(NPM_PLACEHOLDER_TEST))
+        })
+        .unwrap_or(false)
+}
+
+fn file_contains(path: &Path, needle: &str) -> bool {
+    std::fs::read_to_string(path).is_ok_and(|text| text.contains(needle))
+}
+
+fn detect(root: &Path) -> Option<TestCommand> {
+    if root.join("Cargo.toml").exists() {
+        return Some(proposal("cargo test", "Cargo.toml found"));
+    }
+    if has_npm_test_script(root) {
+        let pm = node_package_manager(root);
+        let command = match pm {
+            // `bun test` is bun's own runner, not the package script
+            "bun" => "bun run test".to_string(),
+            pm => format!("{} test", pm),
+        };
+        return Some(TestCommand {
+            command,
+            reason: format!(
+                "package.json has a test script; {} inferred from lockfile",
+                pm
+            ),
+        });
+    }
+    let pytest_config = root.join("pytest.ini").exists()
+        || root.join("conftest.py").exists()
+        || root.join("pyproject.toml").exists()
+        || file_contains(&root.join("setup.cfg"), "[tool:pytest]")
+        || file_contains(&root.join("tox.ini"), "[pytest]");
+    if pytest_config {
+        return Some(proposal(
+            "pytest",
+            "Python project with pytest configuration",
+        ));
+    }
+    if root.join("go.mod").exists() {
+        return Some(proposal("go test ./...", "go.mod found"));
+    }
+    None
+}
+
+fn count(caps: &regex::Captures, name: &str) -> u64 {
+    caps.name(name)
+        .and_then(|m| m.as_str().parse().ok())
+        .unwrap_or(0)
+}
+
+struct SummaryPatterns {
+    cargo: Regex,
+    pytest: Regex,
+    jest: Regex,
+    vitest: Regex,
+    go: Regex,
+}
+
+fn patterns() -> &'static SummaryPatterns {
+    static PATTERNS: OnceLock<SummaryPatterns> = OnceLock::new();
+    PATTERNS.get_or_init(|| SummaryPatterns {
+        // test result: ok. 12 passed; 0 failed; 1 ignored; 0 measured; 0 filtered out
+        cargo: Regex::new(
+            r"test result: \w+\. (?P<passed>\d+) passed; (?P<failed>\d+) failed; (?P<skipped>\d+) ignored",
+        )
+        .unwrap(),
+        // ===== 3 passed, 1 failed, 2 skipped in 0.12s =====
+        pytest: Regex::new(r"^=+ .*\bin [\d.]+s.* =+$").unwrap(),
+        // Tests:       1 failed, 2 skipped, 5 passed, 8 total
+        jest: Regex::new(r"^Tests:\s+(.*\d+ total)").unwrap(),
+        //  Tests  5 passed | 1 failed (6)
+        vitest: Regex::new(r"^\s*Tests\s+(.*)\(\d+\)").unwrap(),
+        // ok  	example.com/pkg	0.01s / FAIL	example.com/pkg	0.02s / ?   	pkg	[no test files]
+        go: Regex::new(r"^(?P<status>ok|FAIL|\?)\s+\S+\s+(\S+|\[no test files\])").unwrap(),
+    })
+}
+
+/// `3 passed, 1 failed` / `5 passed | 1 failed` style lists
+fn tally_words(text: &str, counts: &mut TestCounts) {
+    static WORD: OnceLock<Regex> = OnceLock::new();
+    let word = WORD.get_or_init(|| Regex::new(r"(\d+) ([a-z]+)").unwrap());
+    for caps in word.captures_iter(text) {
+        let n: u64 = caps[1].parse().unwrap_or(0);
+        match &caps[2] {
+            "passed" => counts.passed += n,
+            "failed" | "error" | "errors" => counts.failed += n,
+            "skipped" | "todo" | "xfailed" | "deselected" | "pending" => counts.skipped += n,
+            _ => {}
+        }
+    }
+}
+
+/// Pass/fail/skip totals from cargo, pytest, jest, vitest or `go test` (per package) output
+pub fn parse_summary(lines: &[String]) -> Option<TestCounts> {
+    let p = patterns();
+    let mut counts = TestCounts::default();
+    let mut recognized = false;
+    for line in lines {
+        if let Some(caps) = p.cargo.captures(line) {
+            counts.passed += count(&caps, "passed");
+            counts.failed += count(&caps, "failed");
+            counts.skipped += count(&caps, "skipped");
+            recognized = true;
+        } else if p.pytest.is_match(line) {
+            tally_words(line, &mut counts);
+            recognized = true;
+        } else if let Some(caps) = p.jest.captures(line) {
+            tally_words(&caps[1], &mut counts);
+            recognized = true;
+        } else if let Some(caps) = p.vitest.captures(line) {
+            tally_words(&caps[1], &mut counts);
+            recognized = true;
+        } else if let Some(caps) = p.go.captures(line) {
+            match &caps["status"] {
+                "ok" => counts.passed += 1,
+                "FAIL" => counts.failed += 1,
+                _ => counts.skipped += 1,
+            }
+            recognized = true;
+        }
+    }
+    recognized.then_some(counts)
+}
+
+fn workspace_root(workspace: &Workspace, root: &str) -> Result<PathBuf, EngineError> {
+    let root = workspace.check(Path::new(root))?;
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            root.display()
+        )));
+    }
+    Ok(root)
+}
+
+/// The remembered manual command if there is one, otherwise what the project files suggest
+#[tauri::command]
+pub async fn detect_test_command(
+    app: AppHandle,
+    root: String,
+    workspace: State<'_, Workspace>,
+) -> Result<TestCommand, EngineError> {
+    let root = workspace_root(&workspace, &root)?;
+    if let Some(command) = workspace_config::load(&app, &root).test_command {
+        return Ok(proposal(&command, "Previously chosen for this workspace"));
+    }
+    detect(&root).ok_or_else(|| EngineError::NoTestCommand {
+        root: root.display().to_string(),
+    })
+}
+
+/// Run the workspace's tests, streaming output as `command-output` events tagged with
+/// `request_id`. An override command is remembered for the workspace.
+#[tauri::command]
+pub async fn run_tests(
+    window: Window,
+    app: AppHandle,
+    root: String,
+    request_id: String,
+    override_command: Option<String>,
+    registry: State<'_, CancelRegistry>,
+    workspace: State<'_, Workspace>,
+) -> Result<TestRunResult, EngineError> {
+    let root = workspace_root(&workspace, &root)?;
+    let command = match override_command.map(|c| c.trim().to_string()) {
+        Some(command) if !command.is_empty() => {
+            let mut config = workspace_config::load(&app, &root);
+            if config.test_command.as_deref() != Some(command.as_str()) {
+                config.test_command = Some(command.clone());
+                workspace_config::save(&app, &root, &config)?;
+            }
+            command
+        }
+        _ => match workspace_config::load(&app, &root).test_command {
+            Some(command) => command,
+            None => {
+                detect(&root)
+                    .ok_or_else(|| EngineError::NoTestCommand {
+                        root: root.display().to_string(),
+                    })?
+                    .command
+            }
+        },
+    };
+
+    let mut words = split_command(&command).into_iter();
+    let program = words
+        .next()
+        .ok_or_else(|| EngineError::invalid("Test command is empty"))?;
+    let env = env_profile::for_root(&app, &root)?;
+    let program_path = find_program(&program, &env.path_prepend)
+        .ok_or_else(|| EngineError::invalid(format!("{} was not found on PATH", program)))?;
+    let spec = RunSpec {
+        program: program_path,
+        args: words.collect(),
+        cwd: root,
+        env,
+        timeout: None,
+    };
+
+    let guard = registry.register(&request_id);
+    let max_output = settings::load(&app).commands.max_output_bytes;
+    let output = run_streamed(
+        spec,
+        &request_id,
+        Some(&window),
+        &guard.token(),
+        Some(max_output),
+    )
+    .await?;
+    Ok(TestRunResult {
+        command,
+        success: output.exit_code == Some(0),
+        exit_code: output.exit_code,
+        counts: parse_summary(&output.tail),
+        log_tail: output.tail,
+        duration_ms: output.duration_ms,
+    })
+}
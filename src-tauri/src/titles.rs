@@ -0,0 +1,161 @@
+use crate::backend::ActiveBackend;
+use crate::claude::NullSink;
+use crate::env_profile;
+use crate::error::EngineError;
+use crate::history::{self, Conversation};
+use crate::models::CHEAPEST_MODEL;
+use serde_json::json;
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager, State};
+
+const TITLE_WORDS: usize = 6;
+// Each side of the exchange is cut to this before it goes in the prompt
+const EXCERPT_CHARS: usize = 2000;
+const TITLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Conversations with a title being generated, so asking twice doesn't
+/// start a second request
+#[derive(Default)]
+pub struct TitleJobs {
+    running: Mutex<HashSet<String>>,
+}
+
+struct Job<'a> {
+    jobs: &'a TitleJobs,
+    id: String,
+}
+
+impl Drop for Job<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut running) = self.jobs.running.lock() {
+            running.remove(&self.id);
+        }
+    }
+}
+
+impl TitleJobs {
+    fn start(&self, id: &str) -> Option<Job<'_>> {
+        let fresh = self
+            .running
+            .lock()
+            .is_ok_and(|mut running| running.insert(id.to_string()));
+        fresh.then(|| Job {
+            jobs: self,
+            id: id.to_string(),
+        })
+    }
+}
+
+fn excerpt(text: &str) -> String {
+    text.trim().chars().take(EXCERPT_CHARS).collect()
+}
+
+/// The first user message and the reply after it
+fn first_exchange(conversation: &Conversation) -> Option<(&str, &str)> {
+    let at = conversation
+        .messages
+        .iter()
+        .position(|m| m.role == "user")?;
+    let prompt = conversation.messages[at].content.as_str();
+    let reply = conversation.messages[at + 1..]
+        .iter()
+        .find(|m| m.role == "assistant")?;
+    Some((prompt, reply.content.as_str()))
+}
+
+/// One line, without the quotes, label or full stop models like to add
+fn clean(raw: &str) -> Option<String> {
+    let line = raw.lines().find(|l| !l.trim().is_empty())?.trim();
+    let line = line.strip_prefix("Title:").unwrap_or(line);
+    let title = line
+        .trim()
+        .trim_matches(|c: char| "\"'`*“”‘’".contains(c))
+        .trim_end_matches(|c: char| ".!?,;:".contains(c))
+        .trim();
+    (!title.is_empty()).then(|| history::title_from(title))
+}
+
+async fn ask(app: &AppHandle, prompt: &str, reply: &str) -> Result<String, EngineError> {
+    let mut spawn = env_profile::spawn_options(app, None)?;
+    spawn.model = Some(CHEAPEST_MODEL.to_string());
+    spawn.limits.send_timeout = TITLE_TIMEOUT;
+    let request = format!(
+        "Summarize this conversation in at most {} words, for use as its title. \
+         Reply with the title only.\n\nUser:\n{}\n\nAssistant:\n{}",
+        TITLE_WORDS,
+        excerpt(prompt),
+        excerpt(reply)
+    );
+    let answer = app
+        .state::<ActiveBackend>()
+        .send(&request, Arc::new(NullSink), spawn)
+        .await?;
+    clean(&answer.text).ok_or_else(|| EngineError::internal("The model gave an empty title"))
+}
+
+/// Title a conversation from its first exchange with a short request to the
+/// cheapest model, outside the turn queue so chat isn't held up. Any failure
+/// falls back to the first line of the first message. A conversation that
+/// already has a generated or hand-written title is left alone unless
+/// `force`. `conversation-title-updated` `{ conversation_id, title,
+/// generated }` goes out when the title changes; the current title is
+/// returned either way.
+#[tauri::command]
+pub async fn generate_conversation_title(
+    app: AppHandle,
+    conversation_id: String,
+    force: Option<bool>,
+    jobs: State<'_, TitleJobs>,
+) -> Result<String, EngineError> {
+    let id = conversation_id.clone();
+    let loaded = app.clone();
+    let conversation = tokio::task::spawn_blocking(move || history::load(&loaded, &id))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+    let Some((prompt, reply)) = first_exchange(&conversation) else {
+        return Err(EngineError::invalid(
+            "The conversation has no exchange to title yet",
+        ));
+    };
+    let fallback = history::title_from(prompt);
+    // Anything but a placeholder or the first message's line was either
+    // generated already or typed by the user
+    let placeholder = ["", "Untitled", fallback.as_str()].contains(&conversation.title.as_str());
+    let titled = conversation.title_generated || !placeholder;
+    if titled && !force.unwrap_or(false) {
+        return Ok(conversation.title);
+    }
+    let Some(_job) = jobs.start(&conversation_id) else {
+        return Ok(conversation.title);
+    };
+    let (title, generated) = match ask(&app, prompt, reply).await {
+        Ok(title) => (title, true),
+        Err(e) => {
+            tracing::info!(conversation_id = %conversation_id, error = %e, "Title generation failed; using the first message");
+            (fallback, false)
+        }
+    };
+
+    // Reloaded, since turns may have been recorded while the title was made
+    let saved = app.clone();
+    let id = conversation_id.clone();
+    let new_title = title.clone();
+    let changed = tokio::task::spawn_blocking(move || {
+        let mut conversation = history::load(&saved, &id)?;
+        let changed = conversation.title != new_title;
+        conversation.title = new_title;
+        conversation.title_generated = true;
+        history::save(&saved, &conversation).map(|()| changed)
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))??;
+    if changed {
+        let _ = app.emit_all(
+            "conversation-title-updated",
+            json!({ "conversation_id": conversation_id, "title": title, "generated": generated }),
+        );
+    }
+    Ok(title)
+}
@@ -0,0 +1,30 @@
+use std::sync::OnceLock;
+use tiktoken_rs::CoreBPE;
+
+// Claude's tokenizer isn't published; cl100k_base tracks it closely enough for
+// budgeting. The BPE tables are built once and shared by every caller.
+static TOKENIZER: OnceLock<Option<CoreBPE>> = OnceLock::new();
+
+fn tokenizer() -> Option<&'static CoreBPE> {
+    TOKENIZER
+        .get_or_init(|| tiktoken_rs::cl100k_base().ok())
+        .as_ref()
+}
+
+/// Build the BPE tables now, off the path of the first count
+pub fn warm_up() {
+    let _ = tokenizer();
+}
+
+/// Whether counts come from the tokenizer rather than the chars/4 fallback
+pub fn has_tokenizer() -> bool {
+    tokenizer().is_some()
+}
+
+/// Approximate token count; falls back to chars/4 if the tokenizer can't load
+pub fn count_tokens(text: &str) -> usize {
+    match tokenizer() {
+        Some(bpe) => bpe.encode_ordinary(text).len(),
+        None => text.chars().count().div_ceil(4),
+    }
+}
@@ -0,0 +1,259 @@
+use crate::error::EngineError;
+use crate::paths::canonicalize;
+use crate::sandbox::Workspace;
+use ignore::overrides::OverrideBuilder;
+use ignore::{DirEntry, WalkBuilder};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use tauri::State;
+
+const DEFAULT_MAX_DEPTH: usize = 8;
+// Enough for a large project; pointing at a drive root stops here instead of
+// walking the whole disk
+const DEFAULT_MAX_ENTRIES: usize = 20_000;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TreeOptions {
+    pub respect_gitignore: bool,
+    pub include_hidden: bool,
+    /// Descend into symlinked directories, except ones already walked or
+    /// outside the workspace, which are listed without children
+    pub follow_links: bool,
+    /// Entries past this are left out and the tree is marked truncated
+    pub max_entries: usize,
+}
+
+impl Default for TreeOptions {
+    fn default() -> Self {
+        Self {
+            respect_gitignore: true,
+            include_hidden: false,
+            follow_links: false,
+            max_entries: DEFAULT_MAX_ENTRIES,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub path: String,
+    pub is_dir: bool,
+    pub is_symlink: bool,
+    /// Files only
+    pub size: Option<u64>,
+    /// None for files, and for directories at the depth limit that weren't read
+    pub children: Option<Vec<TreeNode>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DirectoryTree {
+    pub root: TreeNode,
+    pub entries: usize,
+    pub truncated: bool,
+}
+
+fn node(path: &Path, is_dir: bool, is_symlink: bool, size: Option<u64>) -> TreeNode {
+    TreeNode {
+        name: path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.display().to_string()),
+        path: path.display().to_string(),
+        is_dir,
+        is_symlink,
+        size,
+        children: None,
+    }
+}
+
+/// Close the open directories down to `depth`, attaching each to its parent
+fn close_to(open: &mut Vec<TreeNode>, depth: usize) {
+    while open.len() > depth.max(1) {
+        let Some(done) = open.pop() else {
+            break;
+        };
+        if let Some(parent) = open.last_mut() {
+            parent.children.get_or_insert_with(Vec::new).push(done);
+        }
+    }
+}
+
+/// The canonical directories a walk has been through, so a followed link
+/// back up the tree, or out of the workspace, is cut off instead of entered
+struct LinkGuard {
+    roots: Vec<PathBuf>,
+    visited: Mutex<HashSet<PathBuf>>,
+    cut: Mutex<Vec<PathBuf>>,
+}
+
+impl LinkGuard {
+    fn admit(&self, entry: &DirEntry) -> bool {
+        if !entry.file_type().is_some_and(|t| t.is_dir()) {
+            return true;
+        }
+        let Ok(canonical) = canonicalize(entry.path()) else {
+            return true;
+        };
+        let inside = self.roots.iter().any(|root| canonical.starts_with(root));
+        let fresh = self
+            .visited
+            .lock()
+            .is_ok_and(|mut visited| visited.insert(canonical));
+        if entry.path_is_symlink() && !(inside && fresh) {
+            if let Ok(mut cut) = self.cut.lock() {
+                cut.push(entry.path().to_path_buf());
+            }
+            return false;
+        }
+        true
+    }
+}
+
+/// Put a cut-off link back where the walk would have listed it
+fn attach(tree: &mut TreeNode, root: &Path, path: &Path) {
+    let Ok(rel) = path.strip_prefix(root) else {
+        return;
+    };
+    let mut parent = tree;
+    for part in rel.parent().into_iter().flat_map(Path::components) {
+        let name = part.as_os_str().to_string_lossy();
+        let next = parent
+            .children
+            .as_mut()
+            .and_then(|children| children.iter_mut().find(|c| c.is_dir && c.name == name));
+        let Some(next) = next else {
+            return;
+        };
+        parent = next;
+    }
+    let Some(children) = parent.children.as_mut() else {
+        return;
+    };
+    let item = node(path, true, true, None);
+    let at = children.partition_point(|c| c.name < item.name);
+    children.insert(at, item);
+}
+
+/// Walk `root` depth first in name order, building the nested tree as it
+/// goes. Symlinks are listed but only followed with `follow_links`, where
+/// a [`LinkGuard`] keeps cycles and escapes from `roots` out.
+pub fn read_tree(
+    root: &Path,
+    max_depth: usize,
+    ignore: &[String],
+    options: &TreeOptions,
+    roots: Vec<PathBuf>,
+) -> Result<DirectoryTree, EngineError> {
+    let mut overrides = OverrideBuilder::new(root);
+    for pattern in ignore {
+        overrides.add(&format!("!{}", pattern)).map_err(|e| {
+            EngineError::invalid(format!("Bad ignore pattern '{}': {}", pattern, e))
+        })?;
+    }
+    let overrides = overrides
+        .build()
+        .map_err(|e| EngineError::invalid(format!("Bad ignore patterns: {}", e)))?;
+    let guard = Arc::new(LinkGuard {
+        roots,
+        // The root itself isn't passed through filter_entry
+        visited: Mutex::new(canonicalize(root).into_iter().collect()),
+        cut: Mutex::new(Vec::new()),
+    });
+    let admit = Arc::clone(&guard);
+    let follow_links = options.follow_links;
+    let walk = WalkBuilder::new(root)
+        .hidden(!options.include_hidden)
+        .follow_links(follow_links)
+        .filter_entry(move |entry| !follow_links || admit.admit(entry))
+        .require_git(false)
+        .git_ignore(options.respect_gitignore)
+        .git_exclude(options.respect_gitignore)
+        .git_global(options.respect_gitignore)
+        .overrides(overrides)
+        .max_depth(Some(max_depth))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut root_node = node(root, true, false, None);
+    root_node.children = Some(Vec::new());
+    let mut open = vec![root_node];
+    let mut entries = 0;
+    let mut truncated = false;
+    // Unreadable entries are skipped rather than failing the whole tree
+    for entry in walk.flatten() {
+        let depth = entry.depth();
+        if depth == 0 {
+            continue;
+        }
+        if entries >= options.max_entries {
+            truncated = true;
+            break;
+        }
+        entries += 1;
+        close_to(&mut open, depth);
+        let file_type = entry.file_type();
+        let is_symlink = entry.path_is_symlink();
+        let is_dir = file_type.is_some_and(|t| t.is_dir());
+        let size = entry
+            .metadata()
+            .ok()
+            .filter(|m| !is_dir && m.is_file())
+            .map(|m| m.len());
+        let mut item = node(entry.path(), is_dir, is_symlink, size);
+        if is_dir && depth < max_depth {
+            item.children = Some(Vec::new());
+            open.push(item);
+        } else if let Some(parent) = open.last_mut() {
+            parent.children.get_or_insert_with(Vec::new).push(item);
+        }
+    }
+    close_to(&mut open, 1);
+    let mut tree = open
+        .pop()
+        .ok_or_else(|| EngineError::internal("Directory tree lost its root"))?;
+    let cut = guard.cut.lock().map(|c| c.clone()).unwrap_or_default();
+    for path in cut {
+        if entries >= options.max_entries {
+            truncated = true;
+            break;
+        }
+        entries += 1;
+        attach(&mut tree, root, &path);
+    }
+    Ok(DirectoryTree {
+        root: tree,
+        entries,
+        truncated,
+    })
+}
+
+/// Files and directories under `root` as one nested structure, for explorer
+/// views that would otherwise list each folder separately. `ignore` takes
+/// gitignore-style globs.
+#[tauri::command]
+pub async fn read_directory_tree(
+    root: String,
+    max_depth: Option<usize>,
+    ignore: Option<Vec<String>>,
+    options: Option<TreeOptions>,
+    workspace: State<'_, Workspace>,
+) -> Result<DirectoryTree, EngineError> {
+    let root = workspace.check(Path::new(&root))?;
+    if !root.is_dir() {
+        return Err(EngineError::invalid(format!(
+            "{} is not a directory",
+            root.display()
+        )));
+    }
+    let max_depth = max_depth.unwrap_or(DEFAULT_MAX_DEPTH).max(1);
+    let ignore = ignore.unwrap_or_default();
+    let options = options.unwrap_or_default();
+    let roots = workspace.roots();
+    tokio::task::spawn_blocking(move || read_tree(&root, max_depth, &ignore, &options, roots))
+        .await
+        .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
@@ -0,0 +1,237 @@
+use crate::claude::Reply;
+use crate::clock::{now_millis, utc_date};
+use crate::error::EngineError;
+use crate::paths::app_data_subdir;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use tauri::{AppHandle, Manager, State};
+
+pub const LEDGER_DIR: &str = "usage";
+const LEDGER_FILE: &str = "ledger.jsonl";
+const DAY_MS: u64 = 86_400_000;
+// Grouped under this when the CLI picked the model itself
+const DEFAULT_MODEL: &str = "default";
+
+/// One completed turn, a line of the ledger
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEntry {
+    /// Unix millis
+    pub at: u64,
+    pub model: Option<String>,
+    pub session_id: Option<String>,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    /// None when the reply didn't say what it cost
+    pub cost_usd: Option<f64>,
+}
+
+/// Managed lock over the ledger file, so appends and a clear don't interleave
+#[derive(Default)]
+pub struct UsageLedger {
+    file: Mutex<()>,
+}
+
+fn ledger_path(app: &AppHandle) -> Result<PathBuf, EngineError> {
+    Ok(app_data_subdir(app, LEDGER_DIR)?.join(LEDGER_FILE))
+}
+
+fn append(path: &Path, entry: &UsageEntry) -> std::io::Result<()> {
+    let mut line = serde_json::to_string(entry)?;
+    line.push('\n');
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    // A line cut short by a crash is ended first, so this one stays whole
+    if file.metadata()?.len() > 0 {
+        let mut last = [0u8];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+    file.write_all(line.as_bytes())
+}
+
+/// Note a completed turn in the ledger. The write happens on a blocking
+/// task so the reply isn't held up by it; a failure is only logged.
+pub fn record(app: &AppHandle, model: Option<&str>, reply: &Reply) {
+    let usage = reply.usage.clone().unwrap_or_default();
+    let entry = UsageEntry {
+        at: now_millis(),
+        model: model.map(str::to_string),
+        session_id: reply.session_id.clone(),
+        input_tokens: usage.input_tokens,
+        output_tokens: usage.output_tokens,
+        cache_read_tokens: usage.cache_read_tokens,
+        cost_usd: usage.cost_usd,
+    };
+    let app = app.clone();
+    tokio::task::spawn_blocking(move || {
+        let written = ledger_path(&app).and_then(|path| {
+            let ledger = app.state::<UsageLedger>();
+            let _file = ledger.file.lock();
+            append(&path, &entry).map_err(|e| EngineError::io(&path, e))
+        });
+        if let Err(e) = written {
+            tracing::warn!(error = %e, "Could not record usage");
+        }
+    });
+}
+
+/// Entries that parse, and how many lines didn't
+fn read(path: &Path) -> Result<(Vec<UsageEntry>, usize), EngineError> {
+    let data = match std::fs::read_to_string(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok((Vec::new(), 0)),
+        Err(e) => return Err(EngineError::io(path, e)),
+    };
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for line in data.lines().filter(|l| !l.trim().is_empty()) {
+        match serde_json::from_str(line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => skipped += 1,
+        }
+    }
+    Ok((entries, skipped))
+}
+
+/// Which turns a summary covers. `days` counts back from the start of
+/// today (UTC), 1 being today alone, and wins over `since`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct UsageRange {
+    /// Unix millis, inclusive
+    pub since: Option<u64>,
+    /// Unix millis, exclusive
+    pub until: Option<u64>,
+    pub days: Option<u32>,
+}
+
+impl UsageRange {
+    fn bounds(&self, now: u64) -> (u64, u64) {
+        let since = match self.days {
+            Some(days) => {
+                (now - now % DAY_MS).saturating_sub(days.saturating_sub(1) as u64 * DAY_MS)
+            }
+            None => self.since.unwrap_or(0),
+        };
+        (since, self.until.unwrap_or(u64::MAX))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageTotals {
+    pub turns: usize,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub cache_read_tokens: u64,
+    pub cost_usd: f64,
+    /// Turns counted whose cost wasn't reported, so `cost_usd` is short by them
+    pub unpriced_turns: usize,
+}
+
+impl UsageTotals {
+    fn add(&mut self, entry: &UsageEntry) {
+        self.turns += 1;
+        self.input_tokens += entry.input_tokens;
+        self.output_tokens += entry.output_tokens;
+        self.cache_read_tokens += entry.cache_read_tokens;
+        match entry.cost_usd {
+            Some(cost) => self.cost_usd += cost,
+            None => self.unpriced_turns += 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayUsage {
+    /// `YYYY-MM-DD`, UTC
+    pub date: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelUsage {
+    pub model: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UsageSummary {
+    pub total: UsageTotals,
+    /// Oldest first
+    pub by_day: Vec<DayUsage>,
+    /// Costliest first
+    pub by_model: Vec<ModelUsage>,
+    /// Ledger lines that couldn't be read, such as one cut short by a crash
+    pub skipped_lines: usize,
+}
+
+fn summarize(entries: &[UsageEntry], skipped: usize, range: &UsageRange) -> UsageSummary {
+    let (since, until) = range.bounds(now_millis());
+    let mut total = UsageTotals::default();
+    let mut days: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    let mut models: BTreeMap<String, UsageTotals> = BTreeMap::new();
+    for entry in entries.iter().filter(|e| e.at >= since && e.at < until) {
+        total.add(entry);
+        days.entry(utc_date(entry.at)).or_default().add(entry);
+        let model = entry.model.as_deref().unwrap_or(DEFAULT_MODEL);
+        models.entry(model.to_string()).or_default().add(entry);
+    }
+    let mut by_model: Vec<ModelUsage> = models
+        .into_iter()
+        .map(|(model, totals)| ModelUsage { model, totals })
+        .collect();
+    by_model.sort_by(|a, b| b.totals.cost_usd.total_cmp(&a.totals.cost_usd));
+    UsageSummary {
+        total,
+        by_day: days
+            .into_iter()
+            .map(|(date, totals)| DayUsage { date, totals })
+            .collect(),
+        by_model,
+        skipped_lines: skipped,
+    }
+}
+
+/// Tokens and cost of the turns in `range` (all of them by default), in
+/// total, by day and by model
+#[tauri::command]
+pub async fn get_usage_summary(
+    app: AppHandle,
+    range: Option<UsageRange>,
+) -> Result<UsageSummary, EngineError> {
+    let path = ledger_path(&app)?;
+    let range = range.unwrap_or_default();
+    tokio::task::spawn_blocking(move || {
+        let (entries, skipped) = read(&path)?;
+        Ok(summarize(&entries, skipped, &range))
+    })
+    .await
+    .map_err(|e| EngineError::internal(format!("Task error: {}", e)))?
+}
+
+/// Forget every recorded turn
+#[tauri::command]
+pub async fn clear_usage_history(
+    app: AppHandle,
+    ledger: State<'_, UsageLedger>,
+) -> Result<(), EngineError> {
+    let path = ledger_path(&app)?;
+    let _file = ledger.file.lock();
+    match std::fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(EngineError::io(&path, e)),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,150 @@
+use crate::cancel::CancelToken;
+use crate::error::EngineError;
+use ignore::{DirEntry, WalkBuilder, WalkState};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+// Past this, extra threads mostly queue on the same disk
+const MAX_THREADS: usize = 8;
+
+#[derive(Clone, Copy)]
+pub struct WalkConfig<'a> {
+    pub respect_gitignore: bool,
+    /// Files larger than this are never visited
+    pub max_filesize: Option<u64>,
+    /// Worker threads; 0 picks one per core up to a small cap
+    pub threads: usize,
+    pub cancel: Option<&'a CancelToken>,
+}
+
+impl Default for WalkConfig<'_> {
+    fn default() -> Self {
+        Self {
+            respect_gitignore: true,
+            max_filesize: None,
+            threads: 0,
+            cancel: None,
+        }
+    }
+}
+
+fn thread_count(requested: usize) -> usize {
+    if requested > 0 {
+        return requested.min(MAX_THREADS * 4);
+    }
+    std::thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(MAX_THREADS)
+}
+
+/// Visit every file under `root` across worker threads, skipping hidden
+/// entries and never following symlinks. Directories for which `descend`
+/// returns false are pruned. Results come back sorted by path whatever the
+/// order the workers finished in.
+pub fn walk_files<T, D, V>(
+    root: &Path,
+    config: WalkConfig<'_>,
+    descend: D,
+    visit: V,
+) -> Result<Vec<(PathBuf, T)>, EngineError>
+where
+    T: Send,
+    D: Fn(&DirEntry) -> bool + Send + Sync + 'static,
+    V: Fn(&DirEntry) -> Option<T> + Sync,
+{
+    let found = Mutex::new(Vec::new());
+    let cancel = config.cancel;
+    WalkBuilder::new(root)
+        .hidden(true)
+        .follow_links(false)
+        .require_git(false)
+        .git_ignore(config.respect_gitignore)
+        .git_exclude(config.respect_gitignore)
+        .git_global(config.respect_gitignore)
+        .max_filesize(config.max_filesize)
+        .threads(thread_count(config.threads))
+        .filter_entry(move |entry| {
+            entry.depth() == 0 || !entry.file_type().is_some_and(|t| t.is_dir()) || descend(entry)
+        })
+        .build_parallel()
+        .run(|| {
+            let found = &found;
+            let visit = &visit;
+            Box::new(move |entry| {
+                if cancel.is_some_and(|c| c.is_cancelled()) {
+                    return WalkState::Quit;
+                }
+                let Ok(entry) = entry else {
+                    return WalkState::Continue;
+                };
+                if !entry.file_type().is_some_and(|t| t.is_file()) {
+                    return WalkState::Continue;
+                }
+                if let Some(item) = visit(&entry) {
+                    if let Ok(mut found) = found.lock() {
+                        found.push((entry.into_path(), item));
+                    }
+                }
+                WalkState::Continue
+            })
+        });
+
+    if cancel.is_some_and(|c| c.is_cancelled()) {
+        return Err(EngineError::Cancelled);
+    }
+    let mut found = found
+        .into_inner()
+        .map_err(|_| EngineError::internal("A directory walker thread panicked"))?;
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
+
+/// [`walk_files`] over a list already known, such as one from the file index.
+/// Paths that are no longer files are skipped.
+pub fn visit_paths<T, V>(
+    paths: Vec<PathBuf>,
+    config: WalkConfig<'_>,
+    visit: V,
+) -> Result<Vec<(PathBuf, T)>, EngineError>
+where
+    T: Send,
+    V: Fn(&Path) -> Option<T> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let found = Mutex::new(Vec::new());
+    let cancel = config.cancel;
+    let threads = thread_count(config.threads).min(paths.len().max(1));
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                if cancel.is_some_and(|c| c.is_cancelled()) {
+                    return;
+                }
+                let Some(path) = paths.get(next.fetch_add(1, Ordering::Relaxed)) else {
+                    return;
+                };
+                let Ok(meta) = std::fs::symlink_metadata(path) else {
+                    continue;
+                };
+                if !meta.is_file() || config.max_filesize.is_some_and(|max| meta.len() > max) {
+                    continue;
+                }
+                if let Some(item) = visit(path) {
+                    if let Ok(mut found) = found.lock() {
+                        found.push((path.clone(), item));
+                    }
+                }
+            });
+        }
+    });
+
+    if cancel.is_some_and(|c| c.is_cancelled()) {
+        return Err(EngineError::Cancelled);
+    }
+    let mut found = found
+        .into_inner()
+        .map_err(|_| EngineError::internal("A directory walker thread panicked"))?;
+    found.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(found)
+}
@@ -0,0 +1,239 @@
+use crate::context::DEFAULT_SKIP_DIRS;
+use crate::error::EngineError;
+use crate::listing::ListingCache;
+use crate::paths;
+use crate::sandbox;
+use notify::event::{EventKind, ModifyKind};
+use notify::{Event, PollWatcher, RecursiveMode, Watcher};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State, Window};
+use tokio::sync::mpsc;
+
+// An editor's save is a burst of events; a batch closes once they go quiet
+const DEBOUNCE: Duration = Duration::from_millis(150);
+// ...or after this long, so a build writing steadily still gets reported
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+// Past this the batch is cut short and marked truncated; re-list instead
+const MAX_BATCH_PATHS: usize = 500;
+// How often a polled watch rescans; each scan is a walk of the tree
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+struct Watch {
+    // Dropping the watcher stops its events and ends the forwarding task
+    _watcher: Box<dyn Watcher + Send>,
+}
+
+/// Managed set of live watches, keyed by window label and watch id
+#[derive(Default)]
+pub struct Watchers {
+    watches: Mutex<HashMap<(String, String), Watch>>,
+}
+
+impl Watchers {
+    /// Stop every watch the window owns; called when it's destroyed
+    pub fn close_window(&self, label: &str) {
+        if let Ok(mut watches) = self.watches.lock() {
+            watches.retain(|(window, _), _| window != label);
+        }
+    }
+}
+
+fn kind_name(kind: &EventKind) -> Option<&'static str> {
+    match kind {
+        EventKind::Create(_) => Some("created"),
+        EventKind::Modify(ModifyKind::Name(_)) => Some("renamed"),
+        EventKind::Modify(_) => Some("modified"),
+        EventKind::Remove(_) => Some("removed"),
+        _ => None,
+    }
+}
+
+/// Churn inside `.git`, `node_modules`, `target` and the like would drown out
+/// the changes anyone is looking for
+fn skipped(root: &Path, path: &Path) -> bool {
+    path.strip_prefix(root).is_ok_and(|rel| {
+        rel.components().any(|c| match c {
+            Component::Normal(name) => DEFAULT_SKIP_DIRS.contains(&name.to_string_lossy().as_ref()),
+            _ => false,
+        })
+    })
+}
+
+#[derive(Default)]
+struct Batch {
+    changes: BTreeMap<&'static str, BTreeSet<PathBuf>>,
+    paths: usize,
+    truncated: bool,
+}
+
+impl Batch {
+    fn add(&mut self, root: &Path, event: Event) {
+        let Some(kind) = kind_name(&event.kind) else {
+            return;
+        };
+        for path in event.paths {
+            if skipped(root, &path) {
+                continue;
+            }
+            if self.paths >= MAX_BATCH_PATHS {
+                self.truncated = true;
+                return;
+            }
+            if self.changes.entry(kind).or_default().insert(path) {
+                self.paths += 1;
+            }
+        }
+    }
+
+    fn emit(self, window: &Window, watch_id: &str) {
+        let app = window.app_handle();
+        let cache = app.state::<ListingCache>();
+        for (kind, paths) in self.changes {
+            for path in &paths {
+                cache.invalidate(path);
+            }
+            let paths: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+            let _ = window.emit(
+                "fs-change",
+                json!({
+                    "watch_id": watch_id,
+                    "kind": kind,
+                    "paths": paths,
+                    "truncated": self.truncated,
+                }),
+            );
+        }
+    }
+}
+
+/// Coalesce raw events into one `fs-change` per kind per batch until the
+/// watcher is dropped
+async fn forward(
+    window: Window,
+    watch_id: String,
+    root: PathBuf,
+    mut events: mpsc::UnboundedReceiver<Event>,
+) {
+    while let Some(first) = events.recv().await {
+        let mut batch = Batch::default();
+        batch.add(&root, first);
+        let deadline = Instant::now() + MAX_BATCH_DELAY;
+        while let Some(wait) = deadline
+            .checked_duration_since(Instant::now())
+            .map(|left| left.min(DEBOUNCE))
+        {
+            match tokio::time::timeout(wait, events.recv()).await {
+                Ok(Some(event)) => batch.add(&root, event),
+                // Unwatched mid-batch; nobody wants these any more
+                Ok(None) => return,
+                Err(_) => break,
+            }
+        }
+        if batch.paths > 0 {
+            batch.emit(&window, &watch_id);
+        }
+    }
+}
+
+fn watch_error(path: &str, error: notify::Error) -> EngineError {
+    match error.kind {
+        notify::ErrorKind::Io(io) => EngineError::fs(path, io),
+        notify::ErrorKind::PathNotFound => EngineError::NotFound {
+            path: path.to_string(),
+        },
+        _ => EngineError::io(path, error),
+    }
+}
+
+fn forwarder(sender: mpsc::UnboundedSender<Event>) -> impl notify::EventHandler {
+    move |event: notify::Result<Event>| {
+        if let Ok(event) = event {
+            let _ = sender.send(event);
+        }
+    }
+}
+
+fn native(
+    root: &Path,
+    mode: RecursiveMode,
+    sender: mpsc::UnboundedSender<Event>,
+) -> notify::Result<Box<dyn Watcher + Send>> {
+    let mut watcher = notify::recommended_watcher(forwarder(sender))?;
+    watcher.watch(root, mode)?;
+    Ok(Box::new(watcher))
+}
+
+fn polled(
+    root: &Path,
+    mode: RecursiveMode,
+    sender: mpsc::UnboundedSender<Event>,
+) -> notify::Result<Box<dyn Watcher + Send>> {
+    let config = notify::Config::default().with_poll_interval(POLL_INTERVAL);
+    let mut watcher = PollWatcher::new(forwarder(sender), config)?;
+    watcher.watch(root, mode)?;
+    Ok(Box::new(watcher))
+}
+
+/// Emit `fs-change` `{ watch_id, kind, paths, truncated }` to this window as
+/// files under `path` are created, modified, removed or renamed. Reusing an
+/// `id` replaces that watch; all of a window's watches end when it closes.
+/// Network shares, which don't report changes, and paths the native watcher
+/// can't take (e.g. past the inotify limit) are polled every two seconds.
+#[tauri::command]
+pub async fn watch_path(
+    app: AppHandle,
+    window: Window,
+    id: String,
+    path: String,
+    recursive: Option<bool>,
+    watchers: State<'_, Watchers>,
+) -> Result<(), EngineError> {
+    let root = sandbox::allow(&app, &path)?;
+    let (sender, receiver) = mpsc::unbounded_channel();
+    let mode = if recursive.unwrap_or(false) {
+        RecursiveMode::Recursive
+    } else {
+        RecursiveMode::NonRecursive
+    };
+    let watcher = if paths::is_network_path(&root) {
+        polled(&root, mode, sender)
+    } else {
+        match native(&root, mode, sender.clone()) {
+            Ok(watcher) => Ok(watcher),
+            Err(e) if matches!(e.kind, notify::ErrorKind::PathNotFound) => Err(e),
+            Err(e) => {
+                tracing::warn!(path = %root.display(), error = %e, "Native watcher failed; polling instead");
+                polled(&root, mode, sender)
+            }
+        }
+    }
+    .map_err(|e| watch_error(&path, e))?;
+
+    let key = (window.label().to_string(), id.clone());
+    tauri::async_runtime::spawn(forward(window, id, root, receiver));
+    watchers
+        .watches
+        .lock()
+        .map_err(|_| EngineError::internal("Watcher lock is poisoned"))?
+        .insert(key, Watch { _watcher: watcher });
+    Ok(())
+}
+
+/// Stop a watch; false if there was none with this id
+#[tauri::command]
+pub async fn unwatch_path(
+    window: Window,
+    id: String,
+    watchers: State<'_, Watchers>,
+) -> Result<bool, EngineError> {
+    let removed = watchers
+        .watches
+        .lock()
+        .map_err(|_| EngineError::internal("Watcher lock is poisoned"))?
+        .remove(&(window.label().to_string(), id));
+    Ok(removed.is_some())
+}
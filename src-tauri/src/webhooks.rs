@@ -0,0 +1,267 @@
+use crate::clock::now_millis;
+use crate::error::EngineError;
+use crate::fetch::{guarded_client, url_rejection};
+use crate::hash::random_hex;
+use crate::settings;
+use hmac::{Hmac, Mac};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager, State};
+use url::Url;
+
+const ATTEMPTS: u32 = 3;
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+const MAX_LOGGED: usize = 200;
+const SIGNATURE_HEADER: &str = "X-Bups-Signature";
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RequestStatus {
+    Completed,
+    Error,
+    Cancelled,
+}
+
+#[derive(Serialize)]
+struct Payload<'a> {
+    request_id: &'a str,
+    status: RequestStatus,
+    duration_ms: u64,
+    /// Filled in once the CLI reports usage
+    usage: Option<serde_json::Value>,
+    response: Option<String>,
+    error: Option<&'a str>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Delivery {
+    pub request_id: String,
+    pub url: String,
+    pub status: RequestStatus,
+    pub attempts: u32,
+    pub http_status: Option<u16>,
+    pub delivered: bool,
+    pub error: Option<String>,
+    pub at: u64,
+}
+
+/// Most recent deliveries, newest last
+#[derive(Default)]
+pub struct WebhookLog(Mutex<VecDeque<Delivery>>);
+
+impl WebhookLog {
+    fn push(&self, delivery: Delivery) {
+        if let Ok(mut log) = self.0.lock() {
+            if log.len() == MAX_LOGGED {
+                log.pop_front();
+            }
+            log.push_back(delivery);
+        }
+    }
+}
+
+/// A literal private IP or localhost in the URL itself; the user typed it, so it's allowed
+fn explicitly_private(url: &Url) -> bool {
+    url_rejection(url, false).is_some() && url_rejection(url, true).is_none()
+}
+
+/// https only, with fetch_url's SSRF rules except for explicitly private hosts.
+/// Hostnames are still resolved through the guard, so DNS can't point one inward.
+pub fn validate_url(raw: &str) -> Result<Url, EngineError> {
+    let url = Url::parse(raw.trim()).map_err(|e| EngineError::UrlNotAllowed {
+        url: raw.to_string(),
+        reason: format!("invalid URL: {}", e),
+    })?;
+    if url.scheme() != "https" {
+        return Err(EngineError::UrlNotAllowed {
+            url: raw.to_string(),
+            reason: "webhooks must use https".to_string(),
+        });
+    }
+    if let Some(reason) = url_rejection(&url, explicitly_private(&url)) {
+        return Err(EngineError::UrlNotAllowed {
+            url: raw.to_string(),
+            reason,
+        });
+    }
+    Ok(url)
+}
+
+fn ensure_secret(app: &AppHandle) -> Result<String, EngineError> {
+    let mut current = settings::load(app);
+    if let Some(secret) = current.webhooks.secret.clone() {
+        return Ok(secret);
+    }
+    let secret = random_hex(32);
+    current.webhooks.secret = Some(secret.clone());
+    settings::save(app, &current)?;
+    Ok(secret)
+}
+
+fn signature(secret: &str, body: &[u8]) -> Result<String, EngineError> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| EngineError::internal(format!("Invalid webhook secret: {}", e)))?;
+    mac.update(body);
+    let digest = mac.finalize().into_bytes();
+    Ok(format!(
+        "sha256={}",
+        digest
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect::<String>()
+    ))
+}
+
+async fn deliver(
+    app: AppHandle,
+    raw_url: String,
+    request_id: String,
+    status: RequestStatus,
+    body: Vec<u8>,
+) {
+    let mut delivery = Delivery {
+        request_id: request_id.clone(),
+        url: raw_url.clone(),
+        status,
+        attempts: 0,
+        http_status: None,
+        delivered: false,
+        error: None,
+        at: now_millis(),
+    };
+
+    let prepared = validate_url(&raw_url).and_then(|url| {
+        let client = guarded_client(explicitly_private(&url), DELIVERY_TIMEOUT)?;
+        Ok((url, client, signature(&ensure_secret(&app)?, &body)?))
+    });
+    let (url, client, signed) = match prepared {
+        Ok(prepared) => prepared,
+        Err(e) => {
+            delivery.error = Some(e.to_string());
+            app.state::<WebhookLog>().push(delivery);
+            return;
+        }
+    };
+
+    for attempt in 1..=ATTEMPTS {
+        delivery.attempts = attempt;
+        let sent = client
+            .post(url.clone())
+            .header("Content-Type", "application/json")
+            .header(SIGNATURE_HEADER, &signed)
+            .header("X-Bups-Request-Id", &request_id)
+            .body(body.clone())
+            .send()
+            .await;
+        let retry = match sent {
+            Ok(response) => {
+                let code = response.status();
+                delivery.http_status = Some(code.as_u16());
+                delivery.delivered = code.is_success();
+                delivery.error = (!code.is_success()).then(|| format!("HTTP {}", code));
+                // Client errors won't fix themselves, except rate limiting
+                code.is_server_error() || code.as_u16() == 429
+            }
+            Err(e) => {
+                delivery.error = Some(e.to_string());
+                true
+            }
+        };
+        if delivery.delivered || !retry || attempt == ATTEMPTS {
+            break;
+        }
+        tokio::time::sleep(Duration::from_secs(2u64.pow(attempt))).await;
+    }
+    delivery.at = now_millis();
+    app.state::<WebhookLog>().push(delivery);
+}
+
+/// Fire the configured webhook (or `url_override`) for a finished request, in the
+/// background. Nothing here can change the request's own outcome.
+pub fn notify(
+    app: &AppHandle,
+    request_id: &str,
+    started: Instant,
+    result: &Result<String, String>,
+    cancelled: bool,
+    url_override: Option<String>,
+) {
+    let config = settings::load(app).webhooks;
+    let Some(url) = url_override.or(config.url) else {
+        return;
+    };
+    let status = match result {
+        Ok(_) => RequestStatus::Completed,
+        Err(_) if cancelled => RequestStatus::Cancelled,
+        Err(_) => RequestStatus::Error,
+    };
+    let payload = Payload {
+        request_id,
+        status,
+        duration_ms: started.elapsed().as_millis() as u64,
+        usage: None,
+        response: match result {
+            Ok(text) if config.include_response => {
+                Some(text.chars().take(config.max_response_chars).collect())
+            }
+            _ => None,
+        },
+        error: result.as_ref().err().map(String::as_str),
+    };
+    let Ok(body) = serde_json::to_vec(&payload) else {
+        return;
+    };
+    tauri::async_runtime::spawn(deliver(
+        app.clone(),
+        url,
+        request_id.to_string(),
+        status,
+        body,
+    ));
+}
+
+/// Set or clear the global webhook
+#[tauri::command]
+pub async fn set_webhook(
+    app: AppHandle,
+    url: Option<String>,
+    include_response: Option<bool>,
+) -> Result<(), EngineError> {
+    let url = match url.filter(|u| !u.trim().is_empty()) {
+        Some(url) => Some(validate_url(&url)?.to_string()),
+        None => None,
+    };
+    let mut current = settings::load(&app);
+    current.webhooks.url = url;
+    if let Some(include) = include_response {
+        current.webhooks.include_response = include;
+    }
+    settings::save(&app, &current)
+}
+
+/// Key for verifying the `X-Bups-Signature` header (HMAC-SHA256 of the body)
+#[tauri::command]
+pub async fn get_webhook_secret(app: AppHandle) -> Result<String, EngineError> {
+    ensure_secret(&app)
+}
+
+/// Newest first
+#[tauri::command]
+pub async fn get_webhook_deliveries(
+    limit: Option<usize>,
+    log: State<'_, WebhookLog>,
+) -> Result<Vec<Delivery>, EngineError> {
+    let log = log
+        .0
+        .lock()
+        .map_err(|_| EngineError::internal("Webhook log lock is poisoned"))?;
+    Ok(log
+        .iter()
+        .rev()
+        .take(limit.unwrap_or(50))
+        .cloned()
+        .collect())
+}
@@ -0,0 +1,44 @@
+use crate::env_profile::EnvProfile;
+use crate::error::EngineError;
+use crate::files::atomic_write;
+use crate::hash::sha256_hex;
+use crate::paths::app_data_subdir;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use tauri::AppHandle;
+
+/// Per-workspace choices the user made, kept outside the project tree
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WorkspaceConfig {
+    pub root: String,
+    /// Manually entered test command, preferred over detection
+    pub test_command: Option<String>,
+    pub env: EnvProfile,
+}
+
+// Keyed by a hash of the canonical root so any path is a valid file name
+fn config_path(app: &AppHandle, root: &Path) -> Result<PathBuf, EngineError> {
+    let key = sha256_hex(root.to_string_lossy().as_bytes());
+    Ok(app_data_subdir(app, "workspaces")?.join(format!("{}.json", &key[..16])))
+}
+
+/// Config for a workspace root; missing or unreadable files yield the defaults
+pub fn load(app: &AppHandle, root: &Path) -> WorkspaceConfig {
+    let mut config: WorkspaceConfig = config_path(app, root)
+        .ok()
+        .and_then(|path| std::fs::read(path).ok())
+        .and_then(|data| serde_json::from_slice(&data).ok())
+        .unwrap_or_default();
+    config.root = root.display().to_string();
+    config
+}
+
+pub fn save(app: &AppHandle, root: &Path, config: &WorkspaceConfig) -> Result<(), EngineError> {
+    let path = config_path(app, root)?;
+    let json = serde_json::to_vec_pretty(config).map_err(|e| {
+        EngineError::internal(format!("Failed to serialize workspace config: {}", e))
+    })?;
+    atomic_write(&path, &json, false).map_err(|e| EngineError::io(&path, e))?;
+    Ok(())
+}
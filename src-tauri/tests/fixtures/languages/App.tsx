@@ -0,0 +1,6 @@
+import { useState } from "react";
+
+export function App({ title }: { title: string }) {
+  const [count, setCount] = useState(0);
+  return <button onClick={() => setCount(count + 1)}>{title}: {count}</button>;
+}
@@ -0,0 +1,11 @@
+#ifndef LIST_H
+#define LIST_H
+
+struct list {
+    struct list *next;
+    int value;
+};
+
+int list_len(const struct list *head);
+
+#endif
@@ -0,0 +1,9 @@
+#pragma once
+#include <string>
+
+namespace ui {
+class Widget {
+public:
+    explicit Widget(std::string name);
+};
+}
@@ -6,6 +6,12 @@ import { listen, UnlistenFn } from '@tauri-apps/api/event'
 import { open } from '@tauri-apps/api/shell'
 import { contextBuilder } from '../engine/ai/ContextBuilder'
 
+// Stream events are tagged with the request they belong to
+interface StreamEvent<T> {
+  request_id: string
+  payload: T
+}
+
 // Memoized message component to prevent re-renders
 interface MessageItemProps {
   msg: ChatMessage
@@ -52,7 +58,11 @@ export default function Chat() {
   const [showContextDetails, setShowContextDetails] = useState<string | null>(null)
   const [isGenerating, setIsGenerating] = useState(false)
   const [cliMissing, setCliMissing] = useState(false)
+  const [installing, setInstalling] = useState(false)
+  const [installLine, setInstallLine] = useState<string | null>(null)
   const messagesEndRef = useRef<HTMLDivElement>(null)
+  // CLI session the chat continues; each reply reports it
+  const sessionIdRef = useRef<string | null>(null)
   const {
     messages,
     isLoading,
@@ -83,6 +93,9 @@ export default function Chat() {
   // Store refs for streaming state to avoid dependency issues
   const streamingIdRef = useRef(streamingMessageId)
   const messagesRef = useRef(messages)
+  // Id of the stream this chat is showing; events for other streams are ignored
+  const requestIdRef = useRef<string | null>(null)
+  const isCurrentRequest = (event: StreamEvent<unknown>) => event.request_id === requestIdRef.current
 
   useEffect(() => {
     streamingIdRef.current = streamingMessageId
@@ -99,21 +112,22 @@ export default function Chat() {
 
     const setupListeners = async () => {
       try {
-        const chunkUnlisten = await listen<string>('claude-stream-chunk', (event) => {
-          if (!mounted) return
+        const chunkUnlisten = await listen<StreamEvent<string>>('claude-stream-chunk', (event) => {
+          if (!mounted || !isCurrentRequest(event.payload)) return
           const id = streamingIdRef.current
           if (id) {
+            const text = event.payload.payload
             const currentMessage = messagesRef.current.find(m => m.id === id)
             const newContent = currentMessage
-              ? currentMessage.content + event.payload
-              : event.payload
+              ? currentMessage.content + text
+              : text
             updateStreamingMessage(id, newContent)
           }
         })
         if (mounted) unlistenFns.push(chunkUnlisten)
 
-        const completeUnlisten = await listen<string>('claude-stream-complete', () => {
-          if (!mounted) return
+        const completeUnlisten = await listen<StreamEvent<unknown>>('claude-stream-complete', (event) => {
+          if (!mounted || !isCurrentRequest(event.payload)) return
           const id = streamingIdRef.current
           if (id) {
             completeStreamingMessage(id)
@@ -123,14 +137,14 @@ export default function Chat() {
         })
         if (mounted) unlistenFns.push(completeUnlisten)
 
-        const errorUnlisten = await listen<string>('claude-stream-error', (event) => {
-          if (!mounted) return
+        const errorUnlisten = await listen<StreamEvent<string>>('claude-stream-error', (event) => {
+          if (!mounted || !isCurrentRequest(event.payload)) return
           const id = streamingIdRef.current
           if (id) {
             const currentMessage = messagesRef.current.find(m => m.id === id)
             const errorContent = currentMessage
-              ? currentMessage.content + `\n\nError: ${event.payload}`
-              : `Error: ${event.payload}`
+              ? currentMessage.content + `\n\nError: ${event.payload.payload}`
+              : `Error: ${event.payload.payload}`
             updateStreamingMessage(id, errorContent)
             completeStreamingMessage(id)
           }
@@ -139,8 +153,8 @@ export default function Chat() {
         })
         if (mounted) unlistenFns.push(errorUnlisten)
 
-        const cancelledUnlisten = await listen('claude-stream-cancelled', () => {
-          if (!mounted) return
+        const cancelledUnlisten = await listen<StreamEvent<null>>('claude-stream-cancelled', (event) => {
+          if (!mounted || !isCurrentRequest(event.payload)) return
           const id = streamingIdRef.current
           if (id) {
             const currentMessage = messagesRef.current.find(m => m.id === id)
@@ -185,6 +199,8 @@ export default function Chat() {
 
     // Start a streaming message
     const messageId = startStreamingMessage()
+    const requestId = `chat-${messageId}`
+    requestIdRef.current = requestId
 
     try {
       // Prepare message with context for Claude
@@ -194,20 +210,36 @@ export default function Chat() {
       }
 
       // Call Tauri backend to stream from Claude CLI
-      await invoke<string>('stream_to_claude', {
-        message: fullMessage
+      const reply = await invoke<{ text: string, session_id: string | null }>('stream_to_claude', {
+        message: fullMessage,
+        options: { session_id: sessionIdRef.current, request_id: requestId }
       })
+      sessionIdRef.current = reply.session_id ?? sessionIdRef.current
     } catch (error) {
       console.error('Failed to communicate with Claude:', error)
 
-      const errorStr = String(error)
+      // The CLI dropped the session; the next message starts a fresh one
+      if (typeof error === 'object' && error !== null && (error as { kind?: unknown }).kind === 'SessionNotFound') {
+        sessionIdRef.current = null
+        updateStreamingMessage(messageId, 'The previous Claude session has expired. Send your message again to start a new one.')
+        completeStreamingMessage(messageId)
+        setIsGenerating(false)
+        setLoading(false)
+        return
+      }
+
+      // Backend errors arrive as { kind, detail, ... }
+      const kind = typeof error === 'object' && error !== null ? (error as { kind?: unknown }).kind : undefined
+      const errorStr = kind !== undefined
+        ? String((error as { detail?: unknown, stderr?: unknown }).detail ?? (error as { stderr?: unknown }).stderr ?? kind)
+        : String(error)
 
       // Check if Claude CLI is missing
-      if (errorStr.includes('program not found') || errorStr.includes('spawn')) {
+      if (kind === 'CliNotFound' || kind === 'SpawnFailed') {
         setCliMissing(true)
         updateStreamingMessage(messageId, 'Claude CLI is not installed. The AI Chat feature requires the Claude CLI to be installed and in your PATH.')
       } else {
-        updateStreamingMessage(messageId, `Error: ${error}`)
+        updateStreamingMessage(messageId, `Error: ${errorStr}`)
       }
       completeStreamingMessage(messageId)
 
@@ -220,12 +252,39 @@ export default function Chat() {
     if (!isGenerating) return
 
     try {
-      await invoke('cancel_stream')
+      if (requestIdRef.current) {
+        await invoke('cancel_stream', { requestId: requestIdRef.current })
+      }
     } catch (error) {
       console.error('Failed to cancel stream:', error)
     }
   }, [isGenerating])
 
+  const handleInstallCli = useCallback(async () => {
+    const requestId = `install-${Date.now()}`
+    setInstalling(true)
+    const unlisten = await listen<{ id: string, line: string }>('claude-install-progress', (event) => {
+      if (event.payload.id === requestId) setInstallLine(event.payload.line)
+    })
+    try {
+      const status = await invoke<{ found: boolean }>('install_claude_cli', { requestId })
+      setInstallLine(null)
+      if (status.found) setCliMissing(false)
+    } catch (error) {
+      const kind = typeof error === 'object' && error !== null ? (error as { kind?: unknown }).kind : undefined
+      if (kind === 'NpmNotFound') {
+        // Without Node.js, the standalone installer is the way to go
+        setInstallLine(null)
+        open('https://claude.ai/download')
+      } else {
+        setInstallLine(`Install failed: ${String((error as { detail?: unknown }).detail ?? kind ?? error)}`)
+      }
+    } finally {
+      unlisten()
+      setInstalling(false)
+    }
+  }, [])
+
   const handleKeyDown = useCallback((e: React.KeyboardEvent) => {
     if (e.key === 'Enter' && !e.shiftKey) {
       e.preventDefault()
@@ -250,13 +309,16 @@ export default function Chat() {
               <line x1="12" y1="8" x2="12" y2="12"/>
               <line x1="12" y1="16" x2="12.01" y2="16"/>
             </svg>
-            <span><strong>Claude CLI not found.</strong> AI Chat requires the Claude CLI.</span>
+            <span>
+              {installLine ?? <><strong>Claude CLI not found.</strong> AI Chat requires the Claude CLI.</>}
+            </span>
           </div>
           <button
-            onClick={() => open('https://claude.ai/download')}
+            onClick={handleInstallCli}
+            disabled={installing}
             className="cli-install-btn"
           >
-            Install
+            {installing ? 'Installing...' : 'Install'}
           </button>
         </div>
       )}